@@ -0,0 +1,245 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Compares the different expression encodings.  Run with `cargo bench`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use expression_problem::allocations::*;
+use expression_problem::ch02_open_sum::*;
+use expression_problem::ch04_smart_constructors::*;
+use expression_problem::ch05a_multiplication::*;
+use expression_problem::ch08a_expressions::*;
+use expression_problem::ch09a_mendler::*;
+use expression_problem::ch09b_church_encoding::*;
+use expression_problem::ch09j_small_expressions::*;
+use expression_problem::ch10b_plugin_registry::*;
+use expression_problem::conformance::*;
+use expression_problem::corpus::*;
+use expression_problem::dag::*;
+use expression_problem::generator::*;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn bench<T, F>(name: &str, iterations: u32, mut f: F)
+where
+    F: FnMut() -> T,
+{
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<48} {:>14.1} ns/iter",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(iterations)
+    );
+}
+
+fn bench_language<L: Language>(name: &str) {
+    bench(&format!("{}: build", name), 100, || workload::<L>(12));
+    let expr = workload::<L>(12);
+    bench(&format!("{}: evaluate", name), 100, || {
+        L::evaluate(black_box(&expr))
+    });
+    let config = GeneratorConfig {
+        target_nodes: 8191,
+        ..GeneratorConfig::default()
+    };
+    bench(&format!("{}: build random", name), 100, || {
+        generate::<L>(&config)
+    });
+    let expr = generate::<L>(&config);
+    bench(&format!("{}: evaluate random", name), 100, || {
+        L::evaluate(black_box(&expr))
+    });
+
+    let mut profile = AllocProfile::new();
+    let expr = profile.pass("build", || workload::<L>(12));
+    profile.pass("evaluate", || L::evaluate(black_box(&expr)));
+    profile.pass("drop", || drop(expr));
+    for (pass, report) in &profile.passes {
+        println!("{:<48} {}", format!("{}: {}", name, pass), report);
+    }
+}
+
+/// Builds many tiny expressions, the way a program that constructs one per config value or filter
+/// predicate would: `i + 1` for a single operator, or `(i * 2) + 1` for two.
+fn many_small<L: Language>(count: i64, operators: usize) -> Vec<L::Expr> {
+    (0..count)
+        .map(|i| {
+            let lhs = match operators {
+                1 => L::integer_literal(i),
+                _ => L::multiply(L::integer_literal(i), L::integer_literal(2)),
+            };
+            L::add(lhs, L::integer_literal(1))
+        })
+        .collect()
+}
+
+fn bench_constructors<L: Language>(name: &str) {
+    for operators in [1, 2] {
+        let pass = format!("{}: build, {} operator(s)", name, operators);
+        bench(&pass, 100, || many_small::<L>(10_000, operators));
+        let (exprs, report) = measure(|| many_small::<L>(10_000, operators));
+        println!("{:<48} {}", pass, report);
+        bench(
+            &format!("{}: evaluate, {} operator(s)", name, operators),
+            100,
+            || black_box(&exprs).iter().map(L::evaluate).sum::<i64>(),
+        );
+    }
+}
+
+/// An algebra that only wants to know what the outermost term is.
+struct RootAlgebra;
+
+impl ExprAlgebra for RootAlgebra {
+    type Carrier = &'static str;
+    fn integer_literal(&self, _value: i64) -> &'static str {
+        "integer_literal"
+    }
+    fn add(&self, _lhs: &'static str, _rhs: &'static str) -> &'static str {
+        "add"
+    }
+    fn multiply(&self, _lhs: &'static str, _rhs: &'static str) -> &'static str {
+        "multiply"
+    }
+}
+
+/// Builds `x + x`, where `x` is itself built the same way, `depth` levels deep.  The tree has
+/// 2^(depth+1) - 1 nodes, but only depth + 1 of them are distinct.
+fn heavily_shared(depth: u32) -> MultExpr {
+    if depth == 0 {
+        return integer_literal(1);
+    }
+    add(heavily_shared(depth - 1), heavily_shared(depth - 1))
+}
+
+fn main() {
+    // ((1 + 2) * (3 + 4)) + ((5 * 6) + (7 * (8 + 9)))
+    let church = church_add(
+        church_multiply(
+            church_add(church_literal(1), church_literal(2)),
+            church_add(church_literal(3), church_literal(4)),
+        ),
+        church_add(
+            church_multiply(church_literal(5), church_literal(6)),
+            church_multiply(
+                church_literal(7),
+                church_add(church_literal(8), church_literal(9)),
+            ),
+        ),
+    );
+    let small: MultExpr = to_initial(&church);
+
+    println!("Small expression, shape known at compile time:");
+    bench("church: evaluate", 1_000_000, || {
+        black_box(&church).fold(&EvaluateAlgebra)
+    });
+    bench("initial: evaluate (mcata)", 1_000_000, || {
+        mcata::<_, _, i64>(&Evaluator, black_box(&small))
+    });
+    bench("initial: evaluate (from_initial)", 1_000_000, || {
+        from_initial(black_box(&small)).fold(&EvaluateAlgebra)
+    });
+
+    println!();
+    println!("Asking for the outermost term:");
+    bench("church: fold with root algebra", 1_000_000, || {
+        black_box(&church).fold(&RootAlgebra)
+    });
+    bench(
+        "initial: match on signature",
+        1_000_000,
+        || match black_box(&small).unwrap() {
+            Sum::Left(_) => "multiply",
+            Sum::Right(Sum::Left(_)) => "integer_literal",
+            Sum::Right(Sum::Right(_)) => "add",
+        },
+    );
+
+    let large = workload::<MendlerLanguage>(16);
+
+    println!();
+    println!("Large expression, shape only known at runtime:");
+    bench("initial: evaluate (mcata)", 100, || {
+        mcata::<_, _, i64>(&Evaluator, black_box(&large))
+    });
+    bench("initial: evaluate (from_initial)", 100, || {
+        from_initial(black_box(&large)).fold(&EvaluateAlgebra)
+    });
+    bench("initial -> church -> initial", 100, || {
+        to_initial::<MultExpr, _>(&from_initial(black_box(&large)))
+    });
+
+    let shared = heavily_shared(16);
+    let dag = compress(&shared);
+
+    println!();
+    println!("Large expression, heavy sharing:");
+    bench("tree: evaluate (mcata)", 100, || {
+        mcata::<_, _, i64>(&Evaluator, black_box(&shared))
+    });
+    bench("dag: evaluate", 100, || black_box(&dag).evaluate());
+    bench("dag: compress and evaluate", 100, || {
+        compress(black_box(&shared)).evaluate()
+    });
+
+    println!();
+    println!("Every encoding in the conformance suite:");
+    bench_language::<EvaluateIntLanguage>("EvaluateInt (ch03)");
+    bench_language::<OpenRecursionLanguage>("open recursion (ch08b)");
+    bench_language::<MendlerLanguage>("Mendler (ch09a)");
+    bench_language::<ChurchLanguage>("dynamic Church (ch09b)");
+    bench_language::<TaglessFinalLanguage>("tagless final (ch09c)");
+
+    bench_language::<SmallLanguage<INLINE_NODES>>("small expressions (ch09j)");
+    bench_language::<VariadicLanguage>("variadic terms (ch09k)");
+    bench_language::<DynLanguage>("trait objects (ch10a)");
+
+    println!();
+    println!("Many small expressions:");
+    bench_constructors::<MendlerLanguage>("boxed");
+    bench_constructors::<SmallLanguage<INLINE_NODES>>("small, 3 inline");
+    bench_constructors::<SmallLanguage<7>>("small, 7 inline");
+
+    // Set EXPRESSION_CORPUS to a directory of expression files to benchmark them too.
+    if let Some(dir) = std::env::var_os("EXPRESSION_CORPUS") {
+        let dir = std::path::PathBuf::from(dir);
+        let loader = CorpusLoader::new(Registry::with_plugins(&[&ArithmeticPlugin]))
+            .with_cache(std::env::temp_dir().join("expression-corpus-cache"));
+        let corpus: Corpus<MultExpr> = loader.load(&dir).expect("cannot load corpus");
+        println!();
+        println!(
+            "Corpus {} ({} expressions, {} files cached):",
+            dir.display(),
+            corpus.len(),
+            corpus.cached_files()
+        );
+        bench("corpus: evaluate (mcata)", 100, || {
+            black_box(&corpus)
+                .exprs()
+                .map(|expr| mcata::<_, _, i64>(&Evaluator, expr))
+                .sum::<i64>()
+        });
+        bench("corpus: load", 10, || {
+            loader.load::<MultExpr>(&dir).expect("cannot load corpus")
+        });
+    }
+}
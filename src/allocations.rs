@@ -0,0 +1,244 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! How much does each encoding actually cost to build and to evaluate?  Timing only tells part of
+//! the story, so this module counts the work that goes into it: heap allocations (and their bytes),
+//! boxed expression nodes, and cloned `Node`s, for each pass that you measure.
+//!
+//! Allocations are counted by `CountingAllocator`, which a program (or benchmark) has to install as
+//! its global allocator; without it, those counts stay at zero.  Nodes are counted by the `From`
+//! impls that `from_terms!` generates, which is what every smart constructor goes through, and
+//! cloned nodes by `Node`'s Clone impl.  Those two hooks sit on the hot path of every construction
+//! and every clone, so they only count anything when the `instrumented` feature is enabled;
+//! otherwise they compile down to nothing and those counts stay at zero too.  Counters are
+//! per-thread, so measurements on different threads don't interfere with each other.
+
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::alloc::System;
+use std::cell::Cell;
+use std::fmt;
+
+struct Counters {
+    allocations: Cell<usize>,
+    bytes: Cell<usize>,
+    nodes: Cell<usize>,
+    clones: Cell<usize>,
+}
+
+thread_local! {
+    // No destructor and no lazy initialization, so that the allocator can safely use it.
+    static COUNTERS: Counters = const {
+        Counters {
+            allocations: Cell::new(0),
+            bytes: Cell::new(0),
+            nodes: Cell::new(0),
+            clones: Cell::new(0),
+        }
+    };
+}
+
+fn bump(counter: impl Fn(&Counters) -> &Cell<usize>, amount: usize) {
+    // try_with fails while the thread is being torn down, and there's nothing to report by then.
+    let _ = COUNTERS.try_with(|counters| {
+        let counter = counter(counters);
+        counter.set(counter.get().wrapping_add(amount));
+    });
+}
+
+/// Records that a boxed expression node was created.
+#[cfg(feature = "instrumented")]
+pub fn record_node() {
+    bump(|c| &c.nodes, 1);
+}
+
+/// Records that a boxed expression node was created.
+#[cfg(not(feature = "instrumented"))]
+#[inline(always)]
+pub fn record_node() {}
+
+/// Records that one node of a tree was cloned.  Cloning a tree records each of its nodes.
+#[cfg(feature = "instrumented")]
+pub fn record_clone() {
+    bump(|c| &c.clones, 1);
+}
+
+/// Records that one node of a tree was cloned.  Cloning a tree records each of its nodes.
+#[cfg(not(feature = "instrumented"))]
+#[inline(always)]
+pub fn record_clone() {}
+
+/// A global allocator that counts allocations before handing them to the system allocator.
+/// Install it with
+///
+/// ```text
+/// #[global_allocator]
+/// static ALLOCATOR: CountingAllocator = CountingAllocator;
+/// ```
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        bump(|c| &c.allocations, 1);
+        bump(|c| &c.bytes, layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        bump(|c| &c.allocations, 1);
+        bump(|c| &c.bytes, layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        bump(|c| &c.allocations, 1);
+        bump(|c| &c.bytes, new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// What a pass cost.  Reallocations count as allocations of their new size.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AllocReport {
+    pub allocations: usize,
+    pub bytes: usize,
+    pub nodes: usize,
+    pub clones: usize,
+}
+
+impl AllocReport {
+    fn now() -> AllocReport {
+        COUNTERS.with(|c| AllocReport {
+            allocations: c.allocations.get(),
+            bytes: c.bytes.get(),
+            nodes: c.nodes.get(),
+            clones: c.clones.get(),
+        })
+    }
+
+    fn since(self, start: AllocReport) -> AllocReport {
+        AllocReport {
+            allocations: self.allocations.wrapping_sub(start.allocations),
+            bytes: self.bytes.wrapping_sub(start.bytes),
+            nodes: self.nodes.wrapping_sub(start.nodes),
+            clones: self.clones.wrapping_sub(start.clones),
+        }
+    }
+}
+
+impl fmt::Display for AllocReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations ({} bytes), {} nodes, {} clones",
+            self.allocations, self.bytes, self.nodes, self.clones
+        )
+    }
+}
+
+/// Runs `pass` on the current thread, and reports what it cost.
+pub fn measure<T, F>(pass: F) -> (T, AllocReport)
+where
+    F: FnOnce() -> T,
+{
+    let start = AllocReport::now();
+    let result = pass();
+    let report = AllocReport::now().since(start);
+    (result, report)
+}
+
+/// The costs of each pass of a pipeline, in the order that they ran.
+#[derive(Debug, Default)]
+pub struct AllocProfile {
+    pub passes: Vec<(String, AllocReport)>,
+}
+
+impl AllocProfile {
+    pub fn new() -> AllocProfile {
+        AllocProfile::default()
+    }
+
+    /// Runs and measures one pass.
+    pub fn pass<T, F>(&mut self, name: &str, pass: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let (result, report) = measure(pass);
+        self.passes.push((name.to_string(), report));
+        result
+    }
+}
+
+impl fmt::Display for AllocProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, report) in &self.passes {
+            writeln!(f, "{}: {}", name, report)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch02_open_sum::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::rewrite::*;
+
+    #[test]
+    fn can_count_nodes_and_boxes() {
+        let (expr, report) = measure(|| -> Expr {
+            add(
+                integer_literal(1),
+                add(integer_literal(2), integer_literal(3)),
+            )
+        });
+        #[cfg(feature = "instrumented")]
+        assert_eq!(report.nodes, 5);
+        assert_eq!(report.allocations, 5);
+        assert_eq!(report.clones, 0);
+        let ((), report) = measure(|| drop(expr));
+        assert_eq!(report.allocations, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "instrumented")]
+    fn can_count_clones() {
+        let tree = Node::term("add", vec![Node::Literal(1), Node::Literal(2)]);
+        let (_copy, report) = measure(|| tree.clone());
+        assert_eq!(report.clones, 3);
+        assert_eq!(report.nodes, 0);
+        assert!(report.allocations >= 1);
+    }
+
+    #[test]
+    fn can_profile_passes() {
+        let mut profile = AllocProfile::new();
+        let expr: Expr = profile.pass("build", || add(integer_literal(1), integer_literal(2)));
+        profile.pass("convert", || to_node(&expr));
+        #[cfg(feature = "instrumented")]
+        assert_eq!(profile.passes[0].1.nodes, 3);
+        assert_eq!(profile.passes[1].1.nodes, 0);
+        assert!(profile.to_string().starts_with("build: 3 allocations ("));
+    }
+}
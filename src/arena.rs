@@ -0,0 +1,527 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! An arena of expression nodes.  Our open-sum expressions put every node in its own Box, and
+//! their Drop impls recurse, so there's a limit to how deep (and how big) they can get.  An arena
+//! instead keeps every node in one flat table, where children are referred to by index.  That's
+//! the same representation as a DAG (and we reuse its node type), but nothing is merged: each
+//! allocation gets a new node.
+//!
+//! Arenas are also cheap to recycle.  A multi-pass pipeline creates lots of temporary nodes in
+//! each pass, which are garbage as soon as the next pass has read them.  Instead of dropping that
+//! garbage node by node and allocating fresh memory for the next pass, you can `reset` the arena
+//! (or roll it back to a `Checkpoint`), which reclaims all of those nodes at once, and keeps the
+//! memory around for the next pass to use.  Resetting invalidates the ids of the reclaimed nodes,
+//! so copy anything you want to keep into another arena first.
+//!
+//! The arena can't stop you from holding onto an id after a reset, but it can notice.  Every reset
+//! starts a new generation, each node remembers the generation it was allocated in, and each id
+//! remembers it too.  An id whose node has been reclaimed, or whose slot now holds a node from a
+//! later generation, is stale, and the arena refuses to use it, instead of silently handing you
+//! whatever was allocated into that slot afterwards.  Checkpoints are checked the same way.
+//!
+//! For throwaway trees (say, in an optimizer prototype) you can build inside a scope instead:
+//! `with_arena(|a| { let e = a.add(a.lit(1), a.lit(2)); ... })`.  The expressions that the scope
+//! hands out borrow from it, so the compiler won't let them escape the closure, and every node
+//! allocated inside the scope is reclaimed when it ends.  To get a result out, convert it into
+//! something that doesn't borrow from the scope, like a `Node` or a value.
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+use crate::dag::*;
+use crate::rewrite::*;
+
+/// A flat table of expression nodes, in which every node comes after its children.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Arena {
+    nodes: Vec<DagNode>,
+    /// The generation that each node was allocated in.
+    generations: Vec<u64>,
+    /// The current generation, which goes up every time the arena is reset.
+    generation: u64,
+}
+
+/// The id of a node in an arena.  Ids from before a reset are stale, and the arena won't accept
+/// them.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ArenaId {
+    index: usize,
+    generation: u64,
+}
+
+impl ArenaId {
+    /// The node's position in `Arena::nodes`.
+    pub fn index(self) -> usize {
+        self.index
+    }
+}
+
+/// A point in an arena's history that you can roll back to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Checkpoint {
+    len: usize,
+    /// The generation of the last node before the checkpoint.  If a reset reclaims that node, its
+    /// slot is either empty or holds a node from a later generation, so we can tell that the
+    /// checkpoint is stale even after the arena has regrown.
+    last_generation: u64,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Arena {
+        Arena {
+            nodes: Vec::with_capacity(capacity),
+            generations: Vec::with_capacity(capacity),
+            generation: 0,
+        }
+    }
+
+    /// Adds a node to the arena.  Its children are given by their index in `nodes`, and must
+    /// already be in the arena.
+    pub fn alloc(&mut self, node: DagNode) -> ArenaId {
+        let index = self.nodes.len();
+        assert!(
+            node.children().iter().all(|child| *child < index),
+            "children must be allocated before their parents"
+        );
+        self.nodes.push(node);
+        self.generations.push(self.generation);
+        ArenaId {
+            index,
+            generation: self.generation,
+        }
+    }
+
+    pub fn lit(&mut self, value: i64) -> ArenaId {
+        self.alloc(DagNode::Literal(value))
+    }
+
+    /// Adds a term whose children are already in the arena.
+    pub fn term(&mut self, kind: &'static str, children: &[ArenaId]) -> ArenaId {
+        let children = children.iter().map(|child| self.resolve(*child)).collect();
+        self.alloc(DagNode::Term { kind, children })
+    }
+
+    /// Returns whether `id` refers to a node that's still in the arena.
+    pub fn contains(&self, id: ArenaId) -> bool {
+        self.generations.get(id.index) == Some(&id.generation)
+    }
+
+    /// Returns the index of the node that `id` refers to, or panics if it's stale.
+    fn resolve(&self, id: ArenaId) -> usize {
+        assert!(self.contains(id), "node id is from before a reset");
+        id.index
+    }
+
+    pub fn get(&self, id: ArenaId) -> &DagNode {
+        &self.nodes[self.resolve(id)]
+    }
+
+    /// The ids of a node's children.  A node's children are always from the same generation as
+    /// the node, or an earlier one.
+    pub fn children(&self, id: ArenaId) -> impl Iterator<Item = ArenaId> + '_ {
+        self.get(id).children().iter().map(move |child| ArenaId {
+            index: *child,
+            generation: self.generations[*child],
+        })
+    }
+
+    pub fn nodes(&self) -> &[DagNode] {
+        &self.nodes
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The number of nodes the arena can hold without allocating more memory.
+    pub fn capacity(&self) -> usize {
+        self.nodes.capacity()
+    }
+
+    /// Reclaims every node in the arena, keeping its memory for reuse.  Every id and checkpoint
+    /// from before the reset becomes stale.
+    pub fn reset(&mut self) {
+        self.reset_to(Checkpoint {
+            len: 0,
+            last_generation: 0,
+        });
+    }
+
+    /// Marks the current state of the arena, so that you can reclaim everything allocated after
+    /// this point with `reset_to`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            len: self.nodes.len(),
+            last_generation: self.generations.last().copied().unwrap_or(0),
+        }
+    }
+
+    /// Reclaims every node allocated since `checkpoint`.  Nodes allocated before it are untouched,
+    /// and their ids stay valid.  Panics if a reset has already reclaimed anything from before
+    /// the checkpoint.
+    pub fn reset_to(&mut self, checkpoint: Checkpoint) {
+        let valid = match checkpoint.len {
+            0 => true,
+            len => self.generations.get(len - 1) == Some(&checkpoint.last_generation),
+        };
+        assert!(valid, "checkpoint is from before a reset");
+        self.nodes.truncate(checkpoint.len);
+        self.generations.truncate(checkpoint.len);
+        self.generation += 1;
+    }
+
+    /// Copies the subtree rooted at `root` in another arena into this one, returning the id of its
+    /// copy.  Only the nodes that `root` can reach are copied, and sharing is preserved.
+    pub fn copy_from(&mut self, other: &Arena, root: ArenaId) -> ArenaId {
+        let root = other.resolve(root);
+        // Children come before their parents, so one backwards sweep finds everything reachable,
+        // and one forwards sweep copies it, children first.
+        let mut reachable = vec![false; root + 1];
+        reachable[root] = true;
+        for id in (0..=root).rev() {
+            if reachable[id] {
+                for child in other.nodes[id].children() {
+                    reachable[*child] = true;
+                }
+            }
+        }
+        let mut copies = vec![0; root + 1];
+        let mut copy = None;
+        for id in (0..=root).filter(|id| reachable[*id]) {
+            let node = match &other.nodes[id] {
+                DagNode::Literal(value) => DagNode::Literal(*value),
+                DagNode::Term { kind, children } => DagNode::Term {
+                    kind,
+                    children: children.iter().map(|child| copies[*child]).collect(),
+                },
+            };
+            let id_of_copy = self.alloc(node);
+            copies[id] = id_of_copy.index;
+            copy = Some(id_of_copy);
+        }
+        // The root is always the last node that we copy.
+        copy.unwrap()
+    }
+
+    /// Copies a tree into the arena, returning the id of its root.
+    pub fn alloc_tree(&mut self, tree: &Node) -> ArenaId {
+        let node = match tree {
+            Node::Literal(value) => DagNode::Literal(*value),
+            Node::Term { kind, children } => DagNode::Term {
+                kind,
+                children: children
+                    .iter()
+                    .map(|child| self.alloc_tree(child).index)
+                    .collect(),
+            },
+        };
+        self.alloc(node)
+    }
+
+    /// Copies the subtree rooted at `id` out of the arena.
+    pub fn to_tree(&self, id: ArenaId) -> Node {
+        self.index_to_tree(self.resolve(id))
+    }
+
+    fn index_to_tree(&self, index: usize) -> Node {
+        match &self.nodes[index] {
+            DagNode::Literal(value) => Node::Literal(*value),
+            DagNode::Term { kind, children } => Node::term(
+                kind,
+                children
+                    .iter()
+                    .map(|child| self.index_to_tree(*child))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Evaluates the expression rooted at `id`, without recursing.  Other nodes in the arena
+    /// don't matter, even if they can't be evaluated.  A stale id is reported as a missing node.
+    pub fn evaluate(&self, id: ArenaId) -> Result<i64, DagError> {
+        if !self.contains(id) {
+            return Err(DagError::NoSuchNode { node: id.index });
+        }
+        evaluate_nodes(&self.nodes, id.index)
+    }
+
+    /// Runs `f` with a scope that allocates into this arena.  Every node allocated in the scope is
+    /// reclaimed when `f` returns, but the arena keeps their memory for the next scope to use.
+    pub fn scope<F, R>(&mut self, f: F) -> R
+    where
+        F: for<'s> FnOnce(&Scope<'s>) -> R,
+    {
+        let checkpoint = self.checkpoint();
+        let scope = Scope {
+            arena: RefCell::new(self),
+        };
+        let result = f(&scope);
+        scope.arena.into_inner().reset_to(checkpoint);
+        result
+    }
+}
+
+/// Runs `f` with a scope that allocates into a fresh arena, which is dropped when `f` returns.
+pub fn with_arena<F, R>(f: F) -> R
+where
+    F: for<'s> FnOnce(&Scope<'s>) -> R,
+{
+    Arena::new().scope(f)
+}
+
+/// A handle for building temporary expressions in an arena.  The `'s` lifetime brands every
+/// expression built in the scope, so they can't be used in any other scope, or outlive this one.
+pub struct Scope<'s> {
+    arena: RefCell<&'s mut Arena>,
+}
+
+/// An expression that lives in a `Scope`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ScopedExpr<'s> {
+    id: ArenaId,
+    // Invariant in 's, so that an expression from one scope can't be passed to another.
+    scope: PhantomData<fn(&'s ()) -> &'s ()>,
+}
+
+impl<'s> Scope<'s> {
+    fn wrap(id: ArenaId) -> ScopedExpr<'s> {
+        ScopedExpr {
+            id,
+            scope: PhantomData,
+        }
+    }
+
+    pub fn lit(&self, value: i64) -> ScopedExpr<'s> {
+        Self::wrap(self.arena.borrow_mut().lit(value))
+    }
+
+    pub fn term(&self, kind: &'static str, children: &[ScopedExpr<'s>]) -> ScopedExpr<'s> {
+        let children = children.iter().map(|child| child.id).collect::<Vec<_>>();
+        Self::wrap(self.arena.borrow_mut().term(kind, &children))
+    }
+
+    pub fn add(&self, lhs: ScopedExpr<'s>, rhs: ScopedExpr<'s>) -> ScopedExpr<'s> {
+        self.term("add", &[lhs, rhs])
+    }
+
+    pub fn multiply(&self, lhs: ScopedExpr<'s>, rhs: ScopedExpr<'s>) -> ScopedExpr<'s> {
+        self.term("multiply", &[lhs, rhs])
+    }
+
+    /// Copies a tree into the scope.
+    pub fn alloc_tree(&self, tree: &Node) -> ScopedExpr<'s> {
+        ScopedExpr {
+            id: self.arena.borrow_mut().alloc_tree(tree),
+            scope: PhantomData,
+        }
+    }
+
+    /// Copies an expression out of the scope, so that it can outlive it.
+    pub fn to_tree(&self, expr: ScopedExpr<'s>) -> Node {
+        self.arena.borrow().to_tree(expr.id)
+    }
+
+    pub fn evaluate(&self, expr: ScopedExpr<'s>) -> Result<i64, DagError> {
+        self.arena.borrow().evaluate(expr.id)
+    }
+
+    /// The number of nodes in the underlying arena, including any from before the scope began.
+    pub fn len(&self) -> usize {
+        self.arena.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arena.borrow().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_round_trip_trees() {
+        let tree = Node::term(
+            "add",
+            vec![
+                Node::term("multiply", vec![Node::Literal(80), Node::Literal(5)]),
+                Node::Literal(4),
+            ],
+        );
+        let mut arena = Arena::new();
+        let root = arena.alloc_tree(&tree);
+        assert_eq!(arena.len(), 5);
+        assert_eq!(arena.to_tree(root), tree);
+        assert_eq!(arena.evaluate(root), Ok(404));
+    }
+
+    // A pass that doubles every literal, writing its output into `output`.
+    fn double_literals(input: &Arena, output: &mut Arena) -> ArenaId {
+        let mut last = None;
+        for node in input.nodes() {
+            last = Some(output.alloc(match node {
+                DagNode::Literal(value) => DagNode::Literal(value * 2),
+                node => node.clone(),
+            }));
+        }
+        last.unwrap()
+    }
+
+    #[test]
+    fn passes_can_reuse_memory() {
+        let mut current = Arena::new();
+        let tree = Node::term("add", vec![Node::Literal(1), Node::Literal(2)]);
+        let mut root = current.alloc_tree(&tree);
+        let mut scratch = Arena::new();
+        let mut capacities = Vec::new();
+        for _ in 0..10 {
+            scratch.reset();
+            root = double_literals(&current, &mut scratch);
+            std::mem::swap(&mut current, &mut scratch);
+            capacities.push(scratch.capacity());
+        }
+        assert_eq!(current.evaluate(root), Ok(3 << 10));
+        // After the first couple of passes, neither arena needs any more memory.
+        assert!(capacities[2..].iter().all(|c| *c == capacities[2]));
+    }
+
+    #[test]
+    fn only_evaluates_what_the_root_refers_to() {
+        let mut arena = Arena::new();
+        let one = arena.lit(1);
+        arena.term("pair", &[one, one]);
+        let two = arena.lit(2);
+        assert_eq!(arena.evaluate(two), Ok(2));
+    }
+
+    #[test]
+    fn can_roll_back_to_a_checkpoint() {
+        let mut arena = Arena::new();
+        let keep = arena.lit(1);
+        let checkpoint = arena.checkpoint();
+        let temporary = arena.lit(2);
+        arena.term("add", &[keep, temporary]);
+        arena.reset_to(checkpoint);
+        assert_eq!(arena.len(), 1);
+        assert_eq!(arena.get(keep), &DagNode::Literal(1));
+        assert!(arena.contains(keep));
+        assert!(!arena.contains(temporary));
+    }
+
+    #[test]
+    fn stale_ids_are_rejected() {
+        let mut arena = Arena::new();
+        let old = arena.lit(1);
+        arena.reset();
+        // The new node lands in the same slot, but the old id doesn't refer to it.
+        let new = arena.lit(2);
+        assert_eq!(old.index(), new.index());
+        assert!(!arena.contains(old));
+        assert_eq!(
+            arena.evaluate(old),
+            Err(DagError::NoSuchNode { node: old.index() })
+        );
+        assert_eq!(arena.evaluate(new), Ok(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "node id is from before a reset")]
+    fn stale_ids_cant_be_used_as_children() {
+        let mut arena = Arena::new();
+        let old = arena.lit(1);
+        arena.reset();
+        let new = arena.lit(2);
+        arena.term("add", &[old, new]);
+    }
+
+    #[test]
+    #[should_panic(expected = "checkpoint is from before a reset")]
+    fn stale_checkpoints_are_rejected() {
+        let mut arena = Arena::new();
+        arena.lit(1);
+        arena.lit(2);
+        let checkpoint = arena.checkpoint();
+        arena.reset();
+        // Regrow the arena past the checkpoint.
+        for value in 0..3 {
+            arena.lit(value);
+        }
+        arena.reset_to(checkpoint);
+    }
+
+    #[test]
+    fn can_copy_results_out_of_temporaries() {
+        let mut scratch = Arena::new();
+        let garbage = scratch.lit(99);
+        let one = scratch.lit(1);
+        let sum = scratch.term("add", &[one, one]);
+        scratch.term("add", &[garbage, sum]);
+        let mut results = Arena::new();
+        let root = results.copy_from(&scratch, sum);
+        scratch.reset();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results.evaluate(root), Ok(2));
+    }
+
+    #[test]
+    fn can_build_in_a_scope() {
+        let value = with_arena(|a| {
+            let e = a.add(a.lit(1), a.lit(2));
+            a.evaluate(a.multiply(e, e))
+        });
+        assert_eq!(value, Ok(9));
+    }
+
+    #[test]
+    fn scopes_reclaim_their_temporaries() {
+        let mut arena = Arena::new();
+        let keep = arena.lit(1);
+        let tree = arena.scope(|a| {
+            let sum = a.add(a.lit(2), a.lit(3));
+            // Scratch work that we throw away.
+            a.multiply(sum, a.lit(100));
+            a.to_tree(sum)
+        });
+        assert_eq!(
+            tree,
+            Node::term("add", vec![Node::Literal(2), Node::Literal(3)])
+        );
+        assert_eq!(arena.len(), 1);
+        assert_eq!(arena.get(keep), &DagNode::Literal(1));
+        // The next scope reuses the memory instead of allocating more.
+        let capacity = arena.capacity();
+        arena.scope(|a| a.alloc_tree(&tree).id);
+        assert_eq!(arena.capacity(), capacity);
+    }
+
+    #[test]
+    #[should_panic(expected = "children must be allocated before their parents")]
+    fn children_come_first() {
+        let mut arena = Arena::new();
+        arena.alloc(DagNode::Term {
+            kind: "add",
+            children: vec![0, 1],
+        });
+    }
+}
@@ -0,0 +1,398 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A compact binary format for expressions, which can be read and written as a stream, so that
+//! neither side ever needs more than one node's worth of lookahead, and neither side recurses.
+//! That makes it safe to use for ASTs that are far too big (or too deep) to build out of Boxes.
+//!
+//! A file starts with the magic bytes `EXPR` and a version byte (currently 1).  Then come the
+//! nodes, in post-order: every node is written after all of its children.  Each node is a one-byte
+//! tag saying which kind of term it is, and literals are followed by their value, as a zigzag
+//! LEB128 varint.  Because of the post-order, a reader only needs a stack: a literal pushes itself,
+//! and a term pops its children off of the stack and pushes itself.  Once the input runs out, the
+//! stack should contain exactly one node, the root.
+
+use crate::arena::*;
+use crate::dag::*;
+use crate::rewrite::*;
+
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+
+const MAGIC: &[u8; 4] = b"EXPR";
+const VERSION: u8 = 1;
+
+const LITERAL_TAG: u8 = 0;
+
+/// Every kind of term other than literals, in tag order, along with how many children it has.
+const TERMS: [(&str, usize); 5] = [
+    ("add", 2),
+    ("multiply", 2),
+    ("pair", 2),
+    ("first", 1),
+    ("second", 1),
+];
+
+fn tag_for(kind: &str) -> Option<u8> {
+    TERMS
+        .iter()
+        .position(|(name, _)| *name == kind)
+        .map(|index| index as u8 + 1)
+}
+
+/// Writes expressions in the binary format.  Nodes must be written in post-order.  Nodes are only
+/// a few bytes each, so the output is buffered, and isn't guaranteed to reach the underlying writer
+/// until you call `finish`.
+pub struct Writer<W: Write> {
+    writer: io::BufWriter<W>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Creates a new writer, and writes the file header.
+    pub fn new(writer: W) -> io::Result<Writer<W>> {
+        let mut writer = io::BufWriter::new(writer);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Writer { writer })
+    }
+
+    pub fn literal(&mut self, value: i64) -> io::Result<()> {
+        // The tag, and then at most ten bytes of varint.
+        let mut buffer = [LITERAL_TAG; 11];
+        let mut len = 1;
+        // zigzag encoding puts small negative numbers near zero, too.
+        let mut value = ((value << 1) ^ (value >> 63)) as u64;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                buffer[len] = byte;
+                return self.writer.write_all(&buffer[..len + 1]);
+            }
+            buffer[len] = byte | 0x80;
+            len += 1;
+        }
+    }
+
+    /// Writes a term, whose children must have already been written.
+    pub fn term(&mut self, kind: &str) -> io::Result<()> {
+        let tag = tag_for(kind).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown kind of term {}", kind),
+            )
+        })?;
+        self.writer.write_all(&[tag])
+    }
+
+    /// Writes the subtree rooted at `root`.  Shared nodes are written once for each time they're
+    /// used, since the format describes a tree.
+    pub fn write_arena(&mut self, arena: &Arena, root: ArenaId) -> io::Result<()> {
+        let mut stack = vec![(root, 0)];
+        while let Some((id, next_child)) = stack.pop() {
+            match arena.get(id) {
+                DagNode::Literal(value) => self.literal(*value)?,
+                DagNode::Term { kind, .. } => match arena.children(id).nth(next_child) {
+                    Some(child) => {
+                        stack.push((id, next_child + 1));
+                        stack.push((child, 0));
+                    }
+                    None => self.term(kind)?,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_tree(&mut self, tree: &Node) -> io::Result<()> {
+        let mut stack = vec![(tree, 0)];
+        while let Some((node, next_child)) = stack.pop() {
+            match node {
+                Node::Literal(value) => self.literal(*value)?,
+                Node::Term { kind, children } => match children.get(next_child) {
+                    Some(child) => {
+                        stack.push((node, next_child + 1));
+                        stack.push((child, 0));
+                    }
+                    None => self.term(kind)?,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes everything that's been written to the underlying writer, and returns it.
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = self
+            .writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+/// Writes a single tree to a byte vector.
+pub fn to_bytes(tree: &Node) -> Vec<u8> {
+    let mut writer = Writer::new(Vec::new()).unwrap();
+    writer.write_tree(tree).unwrap();
+    writer.finish().unwrap()
+}
+
+/// The ways that reading the binary format can fail.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /// The input doesn't start with the magic bytes.
+    NotAnExpression,
+    UnsupportedVersion(u8),
+    UnknownTag(u8),
+    /// A literal's value doesn't fit in an i64.
+    LiteralTooLarge,
+    /// A term appeared without enough children before it.
+    MissingChildren(&'static str),
+    /// The input ended without any nodes.
+    Empty,
+    /// The input ended with more than one node that isn't the child of anything.
+    TooManyRoots(usize),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "{}", err),
+            ReadError::NotAnExpression => write!(f, "not an expression file"),
+            ReadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            ReadError::UnknownTag(tag) => write!(f, "unknown tag {}", tag),
+            ReadError::LiteralTooLarge => write!(f, "literal too large"),
+            ReadError::MissingChildren(kind) => write!(f, "{} is missing children", kind),
+            ReadError::Empty => write!(f, "no expression"),
+            ReadError::TooManyRoots(count) => write!(f, "{} expressions, expected 1", count),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> ReadError {
+        ReadError::Io(err)
+    }
+}
+
+fn read_byte<R: BufRead>(reader: &mut R) -> Result<Option<u8>, ReadError> {
+    let mut byte = [0];
+    match reader.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn read_literal<R: BufRead>(reader: &mut R) -> Result<i64, ReadError> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_byte(reader)?.ok_or_else(|| {
+            ReadError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unterminated literal",
+            ))
+        })?;
+        if shift > 63 || (shift == 63 && byte & 0x7e != 0) {
+            return Err(ReadError::LiteralTooLarge);
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((value >> 1) as i64) ^ -((value & 1) as i64));
+        }
+        shift += 7;
+    }
+}
+
+/// Reads an expression from a stream into an existing arena, returning the id of its root.
+pub fn read_into<R: Read>(arena: &mut Arena, reader: R) -> Result<ArenaId, ReadError> {
+    let mut reader = io::BufReader::new(reader);
+    let mut header = [0; 5];
+    reader
+        .read_exact(&mut header)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => ReadError::NotAnExpression,
+            _ => ReadError::Io(err),
+        })?;
+    if &header[..4] != MAGIC {
+        return Err(ReadError::NotAnExpression);
+    }
+    if header[4] != VERSION {
+        return Err(ReadError::UnsupportedVersion(header[4]));
+    }
+
+    let mut stack: Vec<ArenaId> = Vec::new();
+    while let Some(tag) = read_byte(&mut reader)? {
+        let id = match tag {
+            LITERAL_TAG => arena.lit(read_literal(&mut reader)?),
+            tag => {
+                let (kind, arity) = *TERMS
+                    .get(tag as usize - 1)
+                    .ok_or(ReadError::UnknownTag(tag))?;
+                if stack.len() < arity {
+                    return Err(ReadError::MissingChildren(kind));
+                }
+                let children = stack.split_off(stack.len() - arity);
+                arena.term(kind, &children)
+            }
+        };
+        stack.push(id);
+    }
+
+    match stack.len() {
+        0 => Err(ReadError::Empty),
+        1 => Ok(stack[0]),
+        count => Err(ReadError::TooManyRoots(count)),
+    }
+}
+
+/// Reads an expression from a stream into a new arena.
+pub fn read_arena<R: Read>(reader: R) -> Result<(Arena, ArenaId), ReadError> {
+    let mut arena = Arena::new();
+    let root = read_into(&mut arena, reader)?;
+    Ok((arena, root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+    use crate::ch07a_pairs::*;
+
+    #[test]
+    fn can_round_trip() {
+        let expr: PairExpr = pair(
+            add(integer_literal(-1), integer_literal(i64::MIN)),
+            first(pair(integer_literal(i64::MAX), integer_literal(300))),
+        );
+        let tree = to_node(&expr);
+        let (arena, root) = read_arena(&to_bytes(&tree)[..]).unwrap();
+        assert_eq!(arena.to_tree(root), tree);
+    }
+
+    #[test]
+    fn literals_are_compact() {
+        let bytes = to_bytes(&Node::Literal(-1));
+        assert_eq!(bytes, b"EXPR\x01\x00\x01");
+        let bytes = to_bytes(&Node::Literal(64));
+        assert_eq!(bytes, b"EXPR\x01\x00\x80\x01");
+    }
+
+    #[test]
+    fn can_stream_very_deep_expressions() {
+        // 1 + 1 + 1 + ... with a million additions, which is far too deep to build out of Boxes.
+        let depth = 1_000_000;
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.literal(1).unwrap();
+        for _ in 0..depth {
+            writer.literal(1).unwrap();
+            writer.term("add").unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        let (arena, root) = read_arena(&bytes[..]).unwrap();
+        assert_eq!(arena.len(), 2 * depth + 1);
+        assert_eq!(arena.evaluate(root), Ok(depth as i64 + 1));
+
+        // And back out again, without recursing.
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write_arena(&arena, root).unwrap();
+        assert_eq!(writer.finish().unwrap(), bytes);
+    }
+
+    #[test]
+    fn shared_nodes_are_written_once_per_use() {
+        let expr: MultExpr = multiply(
+            add(integer_literal(1), integer_literal(2)),
+            add(integer_literal(1), integer_literal(2)),
+        );
+        let tree = to_node(&expr);
+        let dag = Dag::from_tree(&tree);
+        let mut arena = Arena::new();
+        let root = arena.alloc_tree(&dag.to_tree());
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write_arena(&arena, root).unwrap();
+        assert_eq!(writer.finish().unwrap(), to_bytes(&tree));
+    }
+
+    #[test]
+    fn can_detect_malformed_input() {
+        let read = |bytes: &[u8]| read_arena(bytes).map(|_| ()).unwrap_err().to_string();
+        assert_eq!(read(b"JSON"), "not an expression file");
+        assert_eq!(read(b"EXPR\x02"), "unsupported format version 2");
+        assert_eq!(read(b"EXPR\x01"), "no expression");
+        assert_eq!(read(b"EXPR\x01\x09"), "unknown tag 9");
+        assert_eq!(read(b"EXPR\x01\x00\x02\x01"), "add is missing children");
+        assert_eq!(
+            read(b"EXPR\x01\x00\x02\x00\x02"),
+            "2 expressions, expected 1"
+        );
+        assert_eq!(read(b"EXPR\x01\x00\x80"), "unterminated literal");
+        assert_eq!(
+            read(b"EXPR\x01\x00\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f"),
+            "literal too large"
+        );
+    }
+
+    #[test]
+    fn buffers_small_writes() {
+        // Counts how many times the writer is asked to write something.
+        struct Counting(usize, Vec<u8>);
+        impl Write for Counting {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0 += 1;
+                self.1.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut writer = Writer::new(Counting(0, Vec::new())).unwrap();
+        writer.literal(i64::MIN).unwrap();
+        for _ in 0..1000 {
+            writer.literal(i64::MAX).unwrap();
+            writer.term("add").unwrap();
+        }
+        let Counting(writes, bytes) = writer.finish().unwrap();
+        assert!(writes < 10, "{} writes", writes);
+        let (arena, root) = read_arena(&bytes[..]).unwrap();
+        assert_eq!(arena.len(), 2001);
+        // The biggest literals still make it through.
+        let mut id = root;
+        while let Some(lhs) = arena.children(id).next() {
+            assert_eq!(
+                arena.get(arena.children(id).nth(1).unwrap()),
+                &DagNode::Literal(i64::MAX)
+            );
+            id = lhs;
+        }
+        assert_eq!(arena.get(id), &DagNode::Literal(i64::MIN));
+    }
+
+    #[test]
+    fn cant_write_unknown_terms() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        assert!(writer.term("measured").is_err());
+    }
+}
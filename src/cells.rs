@@ -0,0 +1,402 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A tiny spreadsheet.  A sheet holds named cells, each containing an expression, and those
+//! expressions can refer to the values of other cells with a new `CellRef` term.  That makes the
+//! cells a dependency graph: when you update a cell, the sheet recalculates that cell and
+//! everything downstream of it, in topological order, so that each cell is evaluated after all of
+//! the cells it refers to.
+//!
+//! Nothing stops you from creating a cycle (`a = b + 1`, `b = a + 1`).  Just like a real
+//! spreadsheet, the sheet accepts it, and reports an error as the value of every cell in the cycle,
+//! until you update one of them to break it.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A reference to the value of another cell.
+pub struct CellRef {
+    pub name: String,
+}
+
+pub fn cell_ref<E: From<CellRef>>(name: &str) -> E {
+    E::from(CellRef {
+        name: name.to_string(),
+    })
+}
+
+pub type CellSig<E> = Sum![CellRef, Multiply<E>, Sig<E>];
+pub struct CellExpr(pub Box<CellSig<CellExpr>>);
+
+impl Expression for CellExpr {
+    type Signature = CellSig<CellExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    CellExpr: CellRef,
+    Multiply<CellExpr>,
+    IntegerLiteral,
+    Add<CellExpr>,
+);
+
+/// The reasons that a cell might not have a value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CellError {
+    /// The cell refers to a cell that doesn't exist.
+    Undefined(String),
+    /// The cell is part of a cycle, which contains these cells.
+    Cycle(Vec<String>),
+    /// The cell refers to a cell that has an error.
+    Upstream(String),
+    /// The cell's value doesn't fit in an i64.
+    Overflow,
+}
+
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CellError::Undefined(name) => write!(f, "no cell named {}", name),
+            CellError::Cycle(cells) => write!(f, "cycle between {}", cells.join(", ")),
+            CellError::Upstream(name) => write!(f, "cell {} has an error", name),
+            CellError::Overflow => write!(f, "overflow"),
+        }
+    }
+}
+
+// To build the dependency graph, we need to know which cells an expression refers to.  That's a
+// fold that only cares about CellRefs.
+
+/// An algebra that collects the names of every cell that an expression refers to.
+pub struct References;
+
+impl<E> Algebra<CellRef, E, BTreeSet<String>> for References {
+    fn apply<F>(&self, term: &CellRef, _recurse: F) -> BTreeSet<String>
+    where
+        F: FnMut(&E) -> BTreeSet<String>,
+    {
+        std::iter::once(term.name.clone()).collect()
+    }
+}
+
+impl<E> Algebra<IntegerLiteral, E, BTreeSet<String>> for References {
+    fn apply<F>(&self, _term: &IntegerLiteral, _recurse: F) -> BTreeSet<String>
+    where
+        F: FnMut(&E) -> BTreeSet<String>,
+    {
+        BTreeSet::new()
+    }
+}
+
+macro_rules! binary_references {
+    ($($term:ident),+ $(,)?) => {
+        $(
+            impl<E> Algebra<$term<E>, E, BTreeSet<String>> for References {
+                fn apply<F>(&self, term: &$term<E>, mut recurse: F) -> BTreeSet<String>
+                where
+                    F: FnMut(&E) -> BTreeSet<String>,
+                {
+                    let mut result = recurse(&term.lhs);
+                    result.extend(recurse(&term.rhs));
+                    result
+                }
+            }
+        )+
+    };
+}
+
+binary_references!(Add, Multiply);
+
+// Evaluating a cell is a fold too, which looks up the (already calculated) values of the cells it
+// refers to.
+
+/// An algebra that evaluates a cell, given the values of the other cells in the sheet.
+pub struct CellEvaluator<'a> {
+    sheet: &'a Sheet,
+}
+
+type CellValue = Result<i64, CellError>;
+
+impl<'a, E> Algebra<CellRef, E, CellValue> for CellEvaluator<'a> {
+    fn apply<F>(&self, term: &CellRef, _recurse: F) -> CellValue
+    where
+        F: FnMut(&E) -> CellValue,
+    {
+        match self.sheet.value(&term.name) {
+            None => Err(CellError::Undefined(term.name.clone())),
+            Some(Err(_)) => Err(CellError::Upstream(term.name.clone())),
+            Some(Ok(value)) => Ok(*value),
+        }
+    }
+}
+
+impl<'a, E> Algebra<IntegerLiteral, E, CellValue> for CellEvaluator<'a> {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> CellValue
+    where
+        F: FnMut(&E) -> CellValue,
+    {
+        Ok(term.value)
+    }
+}
+
+impl<'a, E> Algebra<Add<E>, E, CellValue> for CellEvaluator<'a> {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> CellValue
+    where
+        F: FnMut(&E) -> CellValue,
+    {
+        let lhs = recurse(&term.lhs)?;
+        let rhs = recurse(&term.rhs)?;
+        lhs.checked_add(rhs).ok_or(CellError::Overflow)
+    }
+}
+
+impl<'a, E> Algebra<Multiply<E>, E, CellValue> for CellEvaluator<'a> {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> CellValue
+    where
+        F: FnMut(&E) -> CellValue,
+    {
+        let lhs = recurse(&term.lhs)?;
+        let rhs = recurse(&term.rhs)?;
+        lhs.checked_mul(rhs).ok_or(CellError::Overflow)
+    }
+}
+
+struct Cell {
+    expr: CellExpr,
+    references: BTreeSet<String>,
+    value: CellValue,
+}
+
+/// A collection of named cells.
+#[derive(Default)]
+pub struct Sheet {
+    cells: BTreeMap<String, Cell>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    InProgress,
+    Done,
+}
+
+impl Sheet {
+    pub fn new() -> Sheet {
+        Sheet::default()
+    }
+
+    /// The current value of a cell, or None if there's no cell with that name.
+    pub fn value(&self, name: &str) -> Option<&CellValue> {
+        self.cells.get(name).map(|cell| &cell.value)
+    }
+
+    pub fn expr(&self, name: &str) -> Option<&CellExpr> {
+        self.cells.get(name).map(|cell| &cell.expr)
+    }
+
+    /// The cells that refer directly to `name`.  (`name` doesn't have to exist yet.)
+    pub fn dependents(&self, name: &str) -> BTreeSet<&str> {
+        self.cells
+            .iter()
+            .filter(|(_, cell)| cell.references.contains(name))
+            .map(|(dependent, _)| dependent.as_str())
+            .collect()
+    }
+
+    /// Creates or updates a cell, and recalculates it along with every cell that depends on it.
+    /// Returns the names of the cells that were recalculated, in the order that they were
+    /// recalculated.
+    pub fn set(&mut self, name: &str, expr: CellExpr) -> Vec<String> {
+        let references = mcata(&References, &expr);
+        self.cells.insert(
+            name.to_string(),
+            Cell {
+                expr,
+                references,
+                value: Err(CellError::Undefined(name.to_string())),
+            },
+        );
+        self.recalculate(name)
+    }
+
+    fn recalculate(&mut self, changed: &str) -> Vec<String> {
+        // First find everything downstream of the changed cell...
+        let mut affected = BTreeSet::new();
+        let mut queue = vec![changed.to_string()];
+        while let Some(name) = queue.pop() {
+            if affected.insert(name.clone()) {
+                queue.extend(self.dependents(&name).into_iter().map(str::to_string));
+            }
+        }
+
+        // ...then put those cells in topological order, noting any cycles along the way...
+        let mut visits = HashMap::new();
+        let mut stack = Vec::new();
+        let mut order = Vec::new();
+        let mut cycles = HashMap::new();
+        for name in &affected {
+            self.visit(
+                name,
+                &affected,
+                &mut visits,
+                &mut stack,
+                &mut order,
+                &mut cycles,
+            );
+        }
+
+        // ...and evaluate them in that order.
+        for name in &order {
+            let value = match cycles.get(name) {
+                Some(cycle) => Err(CellError::Cycle(Vec::clone(cycle))),
+                None => mcata(&CellEvaluator { sheet: self }, &self.cells[name].expr),
+            };
+            self.cells.get_mut(name).unwrap().value = value;
+        }
+        order
+    }
+
+    // A depth-first search over the affected cells, following references.  Each cell is added to
+    // `order` after everything it refers to, unless that's impossible because of a cycle.  If we
+    // reach a cell that's still in progress, then everything on the stack from that cell up is a
+    // cycle.
+    fn visit(
+        &self,
+        name: &str,
+        affected: &BTreeSet<String>,
+        visits: &mut HashMap<String, Visit>,
+        stack: &mut Vec<String>,
+        order: &mut Vec<String>,
+        cycles: &mut HashMap<String, Vec<String>>,
+    ) {
+        match visits.get(name) {
+            Some(Visit::Done) => return,
+            Some(Visit::InProgress) => {
+                let start = stack.iter().position(|n| n == name).unwrap();
+                let cycle = stack[start..].to_vec();
+                for member in &cycle {
+                    cycles.insert(member.clone(), cycle.clone());
+                }
+                return;
+            }
+            None => {}
+        }
+        let cell = match self.cells.get(name) {
+            Some(cell) => cell,
+            None => return,
+        };
+        visits.insert(name.to_string(), Visit::InProgress);
+        stack.push(name.to_string());
+        for reference in &cell.references {
+            if affected.contains(reference) {
+                self.visit(reference, affected, visits, stack, order, cycles);
+            }
+        }
+        stack.pop();
+        visits.insert(name.to_string(), Visit::Done);
+        order.push(name.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_refer_to_other_cells() {
+        let mut sheet = Sheet::new();
+        sheet.set("a", integer_literal(80));
+        sheet.set("b", multiply(cell_ref("a"), integer_literal(5)));
+        sheet.set("c", add(cell_ref("b"), integer_literal(4)));
+        assert_eq!(sheet.value("c"), Some(&Ok(404)));
+        assert_eq!(sheet.value("d"), None);
+    }
+
+    #[test]
+    fn recalculates_dependents_in_order() {
+        let mut sheet = Sheet::new();
+        sheet.set("a", integer_literal(1));
+        sheet.set("b", add(cell_ref("a"), integer_literal(1)));
+        sheet.set("c", add(cell_ref("a"), cell_ref("b")));
+        sheet.set("unrelated", integer_literal(7));
+        assert_eq!(sheet.set("a", integer_literal(10)), vec!["a", "b", "c"]);
+        assert_eq!(sheet.value("c"), Some(&Ok(21)));
+    }
+
+    #[test]
+    fn can_refer_to_cells_before_they_exist() {
+        let mut sheet = Sheet::new();
+        sheet.set("b", add(cell_ref("a"), integer_literal(1)));
+        assert_eq!(
+            sheet.value("b"),
+            Some(&Err(CellError::Undefined("a".to_string())))
+        );
+        assert_eq!(sheet.set("a", integer_literal(1)), vec!["a", "b"]);
+        assert_eq!(sheet.value("b"), Some(&Ok(2)));
+    }
+
+    #[test]
+    fn can_detect_cycles() {
+        let mut sheet = Sheet::new();
+        sheet.set("a", add(cell_ref("b"), integer_literal(1)));
+        sheet.set("c", add(cell_ref("a"), integer_literal(1)));
+        sheet.set("b", add(cell_ref("a"), integer_literal(1)));
+        let cycle = CellError::Cycle(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(sheet.value("a"), Some(&Err(cycle.clone())));
+        assert_eq!(sheet.value("b"), Some(&Err(cycle)));
+        assert_eq!(
+            sheet.value("c"),
+            Some(&Err(CellError::Upstream("a".to_string())))
+        );
+
+        // Breaking the cycle fixes everything.
+        sheet.set("b", integer_literal(1));
+        assert_eq!(sheet.value("a"), Some(&Ok(2)));
+        assert_eq!(sheet.value("c"), Some(&Ok(3)));
+    }
+
+    #[test]
+    fn cells_can_refer_to_themselves() {
+        let mut sheet = Sheet::new();
+        sheet.set("a", add(cell_ref("a"), integer_literal(1)));
+        assert_eq!(
+            sheet.value("a"),
+            Some(&Err(CellError::Cycle(vec!["a".to_string()])))
+        );
+    }
+
+    #[test]
+    fn can_detect_overflow() {
+        let mut sheet = Sheet::new();
+        sheet.set("a", integer_literal(i64::MAX));
+        sheet.set("b", add(cell_ref("a"), integer_literal(1)));
+        assert_eq!(sheet.value("b"), Some(&Err(CellError::Overflow)));
+    }
+}
@@ -40,6 +40,17 @@ pub enum Sum<L, R> {
     Right(R),
 }
 
+// Like in the paper, we expect the Sum type to be used in a "list-like", right-associative fashion.
+// That gets cumbersome once a signature has more than a couple of terms in it, so here's a macro
+// that does the nesting for you: `Sum![A, B, C]` expands to `Sum<A, Sum<B, C>>`.
+
+/// Creates a right-nested `Sum` type containing each of the given types.
+#[macro_export]
+macro_rules! Sum {
+    { $A:ty $(,)? } => { $A };
+    { $A:ty, $($B:ty),+ $(,)? } => { $crate::ch02_open_sum::Sum<$A, Sum![$($B),+]> };
+}
+
 // To create the analogue of `Expr (Val :+: Add)` in Rust, we'd ideally want to do:
 //
 // pub type Expr = Sum<IntegerLiteral, Add<Box<Expr>>>;
@@ -63,4 +74,10 @@ mod tests {
             rhs: Expr(Box::new(Sum::Left(IntegerLiteral { value: 1219 }))),
         })));
     }
+
+    #[test]
+    fn can_nest_sums_with_macro() {
+        type Wide = Sum![IntegerLiteral, Add<Expr>, IntegerLiteral, Add<Expr>];
+        let _: Wide = Sum::Right(Sum::Right(Sum::Left(IntegerLiteral { value: 7 })));
+    }
 }
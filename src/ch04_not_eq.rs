@@ -0,0 +1,47 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! This is how ch04 originally implemented injection: directly in terms of From, with some extra
+//! constraints that keep the two Sum impls from overlapping.  Those constraints rely on a nightly
+//! feature (auto traits with negative impls, which used to be called `optin_builtin_traits`) to
+//! define NotEq, which lets us assert that some of the type variables in the second impl represent
+//! distinct types.  That's why this module is only available with the `nightly` feature.
+//!
+//! Note that more recent nightly compilers don't derive an auto trait impl for a type once *any*
+//! negative impl applies to it, so `(X, L): NotEq` no longer holds even when `X` and `L` are
+//! different.  These impls still compile, but you can't actually use them to inject anything.
+//! That's why the rest of the crate uses the stable `Inject` trait from ch04 instead.
+
+use crate::ch02_open_sum::*;
+
+pub auto trait NotEq {}
+impl<X> !NotEq for (X, X) {}
+
+impl<L, R> From<L> for Sum<L, R> {
+    fn from(left: L) -> Sum<L, R> {
+        Sum::Left(left)
+    }
+}
+
+impl<X, L, R> From<X> for Sum<L, R>
+where
+    R: From<X>,
+    (X, L): NotEq,
+    (X, Self): NotEq,
+{
+    fn from(x: X) -> Sum<L, R> {
+        Sum::Right(R::from(x))
+    }
+}
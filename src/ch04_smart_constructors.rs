@@ -17,51 +17,88 @@
 
 use crate::ch02_open_sum::*;
 
+use std::marker::PhantomData;
+
 // In Rust, we already have the equivalent of the :<: typeclass.  It's called std::convert::From!
-// So we just need to define an impl for our Sum type.
+// Ideally we'd just define a couple of From impls for our Sum type: one that injects into the left
+// variant, and one that recurses into the right variant.
 //
-// Complicating things is that these two impls overlap.  In the paper, Swierstra runs into the same
+// Complicating things is that those two impls overlap.  In the paper, Swierstra runs into the same
 // difficulty, and relies on a Haskell extension that allows overlapping instances of typeclasses.
 // Rust has something similar in #![feature(specialization)], but it unfortunately has more
-// restrictions and doesn't work for this example.  Instead, we need to add some extra constraints
-// to the second impl to make them no longer conflict.  These extra constraints rely on
-// #![feature(optin_builtin_traits)] to define NotEq, which lets us assert that some of the type
-// variables in the second impl represent distinct types.
+// restrictions and doesn't work for this example.  The ch04_not_eq module (only available with the
+// `nightly` feature) shows how we can use a nightly-only auto trait to add constraints that make
+// the impls no longer conflict.
+//
+// On stable Rust, we use a different trick.  We define our own injection trait, with an extra type
+// parameter that describes *where* in the sum the injected type lives.  Each impl uses a different
+// "index" type, so none of them overlap.  You never have to write the index yourself; the compiler
+// infers it, since (as long as each type appears in a sum only once) there's only ever one index
+// that works.
 //
 // Also note that, like in the paper, we expect the Sum type to be used in a "list-like",
 // right-associative fashion.  That is, if you want the sum of A, B, or C, you need to use `Sum<A,
 // Sum<B, C>>`, and not `Sum<Sum<A, B>, C>`.
 
-pub auto trait NotEq {}
-impl<X> !NotEq for (X, X) {}
+/// Injects a value of type `X` into `Self`.  `I` describes where `X` lives inside of `Self`.
+pub trait Inject<X, I> {
+    fn inject(x: X) -> Self;
+}
+
+/// The index of the left variant of a sum.
+pub struct Here;
 
-impl<L, R> From<L> for Sum<L, R> {
-    fn from(left: L) -> Sum<L, R> {
+/// The index of the right variant of a sum.
+pub struct Tail;
+
+/// The index of a type that lives somewhere inside the right variant of a sum, at index `I`.
+pub struct There<I>(PhantomData<I>);
+
+impl<L, R> Inject<L, Here> for Sum<L, R> {
+    fn inject(left: L) -> Sum<L, R> {
         Sum::Left(left)
     }
 }
 
-impl<X, L, R> From<X> for Sum<L, R>
-where
-    R: From<X>,
-    (X, L): NotEq,
-    (X, Self): NotEq,
-{
-    fn from(x: X) -> Sum<L, R> {
-        Sum::Right(R::from(x))
+impl<L, R> Inject<R, Tail> for Sum<L, R> {
+    fn inject(right: R) -> Sum<L, R> {
+        Sum::Right(right)
     }
 }
 
-// And like EvaluateInt, we have to explicitly write an impl for our Expr type.
-impl<X> From<X> for Expr
+impl<X, I, L, R> Inject<X, There<I>> for Sum<L, R>
 where
-    Sig<Expr>: From<X>,
+    R: Inject<X, I>,
 {
-    fn from(x: X) -> Expr {
-        Expr(Box::new(Sig::<Expr>::from(x)))
+    fn inject(x: X) -> Sum<L, R> {
+        Sum::Right(R::inject(x))
     }
 }
 
+// Our smart constructors will still use From, though, since that's the trait that everyone already
+// knows.  Like EvaluateInt, we have to explicitly write impls for our Expr type — one for each kind
+// of term that can appear in an Expr.  Each impl uses Inject to find the right place in the
+// signature for its term.  The from_terms! macro writes these impls for us; we'll use it again for
+// every other expression type that we define.
+
+/// Implements `From` for an expression type, for each of the given terms.  The expression type
+/// must be a newtype wrapping a boxed signature, and each term must appear in the signature.
+#[macro_export]
+macro_rules! from_terms {
+    ($expr:ident: $($term:ty),+ $(,)?) => {
+        $(
+            impl From<$term> for $expr {
+                fn from(term: $term) -> $expr {
+                    $crate::allocations::record_node();
+                    $expr(Box::new($crate::ch04_smart_constructors::Inject::inject(term)))
+                }
+            }
+        )+
+    };
+}
+
+from_terms!(Expr: IntegerLiteral, Add<Expr>);
+
 // With those impls in place, we can define smart constructors like we did in ch01.
 
 pub fn integer_literal<E: From<IntegerLiteral>>(value: i64) -> E {
@@ -72,6 +109,70 @@ pub fn add<E: From<Add<E>>>(lhs: E, rhs: E) -> E {
     E::from(Add { lhs, rhs })
 }
 
+// It's still a bit noisy to wrap every constant in a call to integer_literal.  IntoExpr lets you
+// pass a bare integer anywhere that you'd pass an expression.
+//
+// You might wonder why the smart constructors don't just take `impl IntoExpr<E>` for each of their
+// subexpressions.  The problem is that the compiler can only work out the type of a subexpression
+// from the constructor that it's passed to if that constructor asks for exactly `E`.  When it asks
+// for "anything that implements IntoExpr<E>", the inner call's type is left undetermined — even if
+// E itself were the only type that implemented the trait.  (There's an example in IntoExpr's
+// documentation.)  Every nested constructor call would need a type annotation, including the
+// `multiply(2, 3)` in `add(1, multiply(2, 3))`, so we keep the smart constructors as they are, and
+// call into_expr on the bare integers instead.
+
+/// Something that can be turned into an expression of type `E`: either an integer, or an
+/// expression that already has that type.
+///
+/// A smart constructor that took `impl IntoExpr<E>` would make every nested constructor call
+/// ambiguous:
+///
+/// ```compile_fail,E0283
+/// use expression_problem::ch02_open_sum::*;
+/// use expression_problem::ch04_smart_constructors::*;
+///
+/// fn relaxed_add<E: From<Add<E>>>(lhs: impl IntoExpr<E>, rhs: impl IntoExpr<E>) -> E {
+///     E::from(Add {
+///         lhs: lhs.into_expr(),
+///         rhs: rhs.into_expr(),
+///     })
+/// }
+///
+/// let _: Expr = relaxed_add(1, relaxed_add(integer_literal(2), 3));
+/// ```
+///
+/// The bare integers are fine; it's the `relaxed_add` and `integer_literal` calls inside of the
+/// outer one whose types can't be inferred.  Each of them needs a type annotation:
+///
+/// ```
+/// # use expression_problem::ch02_open_sum::*;
+/// # use expression_problem::ch04_smart_constructors::*;
+/// #
+/// # fn relaxed_add<E: From<Add<E>>>(lhs: impl IntoExpr<E>, rhs: impl IntoExpr<E>) -> E {
+/// #     E::from(Add {
+/// #         lhs: lhs.into_expr(),
+/// #         rhs: rhs.into_expr(),
+/// #     })
+/// # }
+/// #
+/// let _: Expr = relaxed_add(1, relaxed_add::<Expr>(integer_literal::<Expr>(2), 3));
+/// ```
+pub trait IntoExpr<E> {
+    fn into_expr(self) -> E;
+}
+
+impl<E: From<IntegerLiteral>> IntoExpr<E> for i64 {
+    fn into_expr(self) -> E {
+        integer_literal(self)
+    }
+}
+
+impl<E> IntoExpr<E> for E {
+    fn into_expr(self) -> E {
+        self
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,4 +195,26 @@ mod tests {
         );
         assert_eq!(add.evaluate(), 31337);
     }
+
+    #[test]
+    fn can_evaluate_bare_integers() {
+        // 30000 + 1330 + 7
+        let add: Expr = add(30000.into_expr(), add(1330.into_expr(), 7.into_expr()));
+        assert_eq!(add.evaluate(), 31337);
+    }
+
+    #[test]
+    fn can_accept_integers_or_expressions() {
+        fn increment<E>(value: impl IntoExpr<E>) -> E
+        where
+            E: From<IntegerLiteral> + From<Add<E>>,
+        {
+            add(value.into_expr(), 1.into_expr())
+        }
+        let from_int: Expr = increment(41);
+        assert_eq!(from_int.evaluate(), 42);
+        let forty_one: Expr = add(integer_literal(40), integer_literal(1));
+        let from_expr: Expr = increment(forty_one);
+        assert_eq!(from_expr.evaluate(), 42);
+    }
 }
@@ -50,14 +50,7 @@ impl EvaluateInt for MultExpr {
     }
 }
 
-impl<X> From<X> for MultExpr
-where
-    MultSig<MultExpr>: From<X>,
-{
-    fn from(x: X) -> MultExpr {
-        MultExpr(Box::new(MultSig::<MultExpr>::from(x)))
-    }
-}
+from_terms!(MultExpr: Multiply<MultExpr>, IntegerLiteral, Add<MultExpr>);
 
 // And to show off, we can create an expression that isn't allowed to contain addition!
 pub type NoAddSig<E> = Sum<IntegerLiteral, Multiply<E>>;
@@ -69,14 +62,7 @@ impl EvaluateInt for NoAddExpr {
     }
 }
 
-impl<X> From<X> for NoAddExpr
-where
-    NoAddSig<NoAddExpr>: From<X>,
-{
-    fn from(x: X) -> NoAddExpr {
-        NoAddExpr(Box::new(NoAddSig::<NoAddExpr>::from(x)))
-    }
-}
+from_terms!(NoAddExpr: IntegerLiteral, Multiply<NoAddExpr>);
 
 #[cfg(test)]
 mod tests {
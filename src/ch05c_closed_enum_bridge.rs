@@ -0,0 +1,196 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! We've now seen the same toy language written two ways: as the closed enum from ch01a, and as
+//! the open sums from ch02 onwards.  If you've got ASTs from the "before" world, here's how to move
+//! them into the "after" world (and back again), so that you can compare what the evaluators say
+//! about them.
+//!
+//! The two worlds don't have quite the same terms.  The closed enum has subtraction, which none of
+//! our open-sum expression types have; and MultExpr has multiplication, which the closed enum
+//! doesn't.  So some of the conversions can fail.  Subtraction can be written in terms of
+//! multiplication, though, so converting into a MultExpr always works.
+
+use crate::ch01a_before::Expression;
+use crate::ch02_open_sum::*;
+use crate::ch04_smart_constructors::*;
+use crate::ch05a_multiplication::*;
+
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A term that only exists on one side of the bridge.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnsupportedTerm {
+    pub term: &'static str,
+}
+
+impl fmt::Display for UnsupportedTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the target language has no `{}` term", self.term)
+    }
+}
+
+impl std::error::Error for UnsupportedTerm {}
+
+// From the closed enum into the open sums.
+
+impl TryFrom<&Expression> for Expr {
+    type Error = UnsupportedTerm;
+    fn try_from(expr: &Expression) -> Result<Expr, UnsupportedTerm> {
+        match expr {
+            Expression::IntegerLiteral(value) => Ok(integer_literal(*value)),
+            Expression::Add(lhs, rhs) => Ok(add(
+                Expr::try_from(lhs.as_ref())?,
+                Expr::try_from(rhs.as_ref())?,
+            )),
+            Expression::Subtract(_, _) => Err(UnsupportedTerm { term: "subtract" }),
+        }
+    }
+}
+
+/// `a - b` becomes `a + (-1 × b)`.
+impl From<&Expression> for MultExpr {
+    fn from(expr: &Expression) -> MultExpr {
+        match expr {
+            Expression::IntegerLiteral(value) => integer_literal(*value),
+            Expression::Add(lhs, rhs) => add(lhs.as_ref().into(), rhs.as_ref().into()),
+            Expression::Subtract(lhs, rhs) => add(
+                lhs.as_ref().into(),
+                multiply(integer_literal(-1), rhs.as_ref().into()),
+            ),
+        }
+    }
+}
+
+// And back again.
+
+impl From<&Expr> for Expression {
+    fn from(expr: &Expr) -> Expression {
+        match expr.0.as_ref() {
+            Sum::Left(literal) => Expression::IntegerLiteral(literal.value),
+            Sum::Right(add) => {
+                Expression::Add(Box::new((&add.lhs).into()), Box::new((&add.rhs).into()))
+            }
+        }
+    }
+}
+
+/// Undoes the desugaring of subtraction; any other multiplication can't be converted.
+impl TryFrom<&MultExpr> for Expression {
+    type Error = UnsupportedTerm;
+    fn try_from(expr: &MultExpr) -> Result<Expression, UnsupportedTerm> {
+        match expr.0.as_ref() {
+            Sum::Left(_) => Err(UnsupportedTerm { term: "multiply" }),
+            Sum::Right(Sum::Left(literal)) => Ok(Expression::IntegerLiteral(literal.value)),
+            Sum::Right(Sum::Right(add)) => {
+                let lhs = Box::new(Expression::try_from(&add.lhs)?);
+                if let Sum::Left(multiply) = add.rhs.0.as_ref() {
+                    if let Sum::Right(Sum::Left(IntegerLiteral { value: -1 })) =
+                        multiply.lhs.0.as_ref()
+                    {
+                        let rhs = Box::new(Expression::try_from(&multiply.rhs)?);
+                        return Ok(Expression::Subtract(lhs, rhs));
+                    }
+                }
+                let rhs = Box::new(Expression::try_from(&add.rhs)?);
+                Ok(Expression::Add(lhs, rhs))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch01a_before;
+    use crate::ch03_evaluation::*;
+
+    fn before_add(lhs: Expression, rhs: Expression) -> Expression {
+        ch01a_before::add(lhs, rhs)
+    }
+
+    fn before_subtract(lhs: Expression, rhs: Expression) -> Expression {
+        ch01a_before::subtract(lhs, rhs)
+    }
+
+    fn before_literal(value: i64) -> Expression {
+        ch01a_before::integer_literal(value)
+    }
+
+    #[test]
+    fn can_convert_into_expr() {
+        let before = before_add(
+            before_literal(1),
+            before_add(before_literal(2), before_literal(3)),
+        );
+        let after = Expr::try_from(&before).unwrap();
+        assert_eq!(after.evaluate(), before.evaluate());
+        assert_eq!(after.evaluate(), 6);
+    }
+
+    #[test]
+    fn cannot_convert_subtraction_into_expr() {
+        let before = before_add(
+            before_literal(1),
+            before_subtract(before_literal(2), before_literal(3)),
+        );
+        assert_eq!(
+            Expr::try_from(&before).err(),
+            Some(UnsupportedTerm { term: "subtract" })
+        );
+    }
+
+    #[test]
+    fn can_convert_subtraction_into_mult_expr() {
+        let before = before_subtract(
+            before_literal(10),
+            before_add(before_literal(2), before_literal(3)),
+        );
+        let after = MultExpr::from(&before);
+        assert_eq!(after.evaluate(), before.evaluate());
+        assert_eq!(after.evaluate(), 5);
+    }
+
+    #[test]
+    fn can_convert_expr_back() {
+        let after: Expr = add(
+            integer_literal(30000),
+            add(integer_literal(1330), integer_literal(7)),
+        );
+        let before = Expression::from(&after);
+        assert_eq!(before.evaluate(), 31337);
+        assert_eq!(before.to_string(), "(30000 + (1330 + 7))");
+    }
+
+    #[test]
+    fn can_round_trip_through_mult_expr() {
+        let before = before_subtract(
+            before_literal(1),
+            before_subtract(before_literal(2), before_literal(3)),
+        );
+        let round_tripped = Expression::try_from(&MultExpr::from(&before)).unwrap();
+        assert_eq!(round_tripped.to_string(), "(1 - (2 - 3))");
+    }
+
+    #[test]
+    fn cannot_convert_multiplication_back() {
+        let after: MultExpr = multiply(integer_literal(6), integer_literal(7));
+        assert_eq!(
+            Expression::try_from(&after).err(),
+            Some(UnsupportedTerm { term: "multiply" })
+        );
+    }
+}
@@ -0,0 +1,97 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! ch01a had subtraction, but we left it behind when we moved to open sums.  Let's bring it back,
+//! the same way that ch05a added multiplication: a type for the term, an evaluation rule, a smart
+//! constructor, and a language that contains it.  We'll also render it, like ch05b did for the
+//! other terms.  The later chapters each add the impls that their technique needs, right next to
+//! the ones for Add and Multiply, so you can see one more binary operator threaded through all of
+//! them.
+
+use crate::ch02_open_sum::*;
+use crate::ch03_evaluation::*;
+
+use std::fmt;
+
+/// Subtracts one expression from another.
+pub struct Subtract<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+impl<E> EvaluateInt for Subtract<E>
+where
+    E: EvaluateInt,
+{
+    fn evaluate(&self) -> i64 {
+        self.lhs.evaluate() - self.rhs.evaluate()
+    }
+}
+
+pub fn subtract<E: From<Subtract<E>>>(lhs: E, rhs: E) -> E {
+    E::from(Subtract { lhs, rhs })
+}
+
+impl<E> fmt::Display for Subtract<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} - {})", self.lhs, self.rhs)
+    }
+}
+
+// A language with addition and subtraction, but no multiplication.
+pub type SubSig<E> = Sum<Subtract<E>, Sig<E>>;
+pub struct SubExpr(pub Box<SubSig<SubExpr>>);
+
+impl EvaluateInt for SubExpr {
+    fn evaluate(&self) -> i64 {
+        self.0.evaluate()
+    }
+}
+
+impl fmt::Display for SubExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+from_terms!(SubExpr: Subtract<SubExpr>, IntegerLiteral, Add<SubExpr>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_evaluate_subtraction() {
+        // (1337 - 1000) - (30 + 7)
+        let sub: SubExpr = subtract(
+            subtract(integer_literal(1337), integer_literal(1000)),
+            add(integer_literal(30), integer_literal(7)),
+        );
+        assert_eq!(sub.evaluate(), 300);
+    }
+
+    #[test]
+    fn can_render_subtraction() {
+        let sub: SubExpr = subtract(
+            integer_literal(10),
+            subtract(integer_literal(4), integer_literal(3)),
+        );
+        assert_eq!(sub.to_string(), "(10 - (4 - 3))");
+    }
+}
@@ -0,0 +1,159 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A transformation, rather than an evaluation: distribute multiplication over addition, so that
+//! `(a + b) * c` becomes `a * c + b * c`.  Unlike ch03 and ch05b, which only ever look at a term,
+//! this one takes an expression apart and builds a new one out of its pieces.  And since one side
+//! of the multiplication ends up in both halves of the result, we have to be able to copy it.
+
+use crate::ch02_open_sum::*;
+use crate::ch04_smart_constructors::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::Expression;
+
+// None of our terms knew how to copy themselves, but that's just one more function that operates
+// on all of them, just like in ch05b.
+
+impl Clone for IntegerLiteral {
+    fn clone(&self) -> Self {
+        IntegerLiteral { value: self.value }
+    }
+}
+
+impl<E: Clone> Clone for Add<E> {
+    fn clone(&self) -> Self {
+        Add {
+            lhs: self.lhs.clone(),
+            rhs: self.rhs.clone(),
+        }
+    }
+}
+
+impl<E: Clone> Clone for Multiply<E> {
+    fn clone(&self) -> Self {
+        Multiply {
+            lhs: self.lhs.clone(),
+            rhs: self.rhs.clone(),
+        }
+    }
+}
+
+impl<L: Clone, R: Clone> Clone for Sum<L, R> {
+    fn clone(&self) -> Self {
+        match self {
+            Sum::Left(lhs) => Sum::Left(lhs.clone()),
+            Sum::Right(rhs) => Sum::Right(rhs.clone()),
+        }
+    }
+}
+
+impl Clone for MultExpr {
+    fn clone(&self) -> Self {
+        MultExpr(self.0.clone())
+    }
+}
+
+// MultExpr has a Drop impl (so that dropping a deep expression doesn't recurse; see the trampoline
+// module), so we can't move its signature out of the box with `*expr.0`.  ch08a's `into_signature`
+// takes it out for us.
+
+/// Rewrites an expression so that no addition appears underneath a multiplication.
+pub fn distribute(expr: MultExpr) -> MultExpr {
+    match expr.into_signature() {
+        Sum::Left(Multiply { lhs, rhs }) => distribute_product(distribute(lhs), distribute(rhs)),
+        Sum::Right(Sum::Left(IntegerLiteral { value })) => integer_literal(value),
+        Sum::Right(Sum::Right(Add { lhs, rhs })) => add(distribute(lhs), distribute(rhs)),
+    }
+}
+
+// Multiplies two expressions that have already been distributed.  We move each operand into the
+// match, so we have to rebuild it (instead of reusing the original) if it turns out not to be an
+// addition.
+fn distribute_product(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+    match lhs.into_signature() {
+        // (a + b) * c → a * c + b * c
+        Sum::Right(Sum::Right(Add { lhs: a, rhs: b })) => add(
+            distribute_product(a, rhs.clone()),
+            distribute_product(b, rhs),
+        ),
+        lhs => {
+            let lhs = MultExpr(Box::new(lhs));
+            match rhs.into_signature() {
+                // a * (b + c) → a * b + a * c
+                Sum::Right(Sum::Right(Add { lhs: b, rhs: c })) => add(
+                    distribute_product(lhs.clone(), b),
+                    distribute_product(lhs, c),
+                ),
+                rhs => multiply(lhs, MultExpr(Box::new(rhs))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch03_evaluation::*;
+
+    #[test]
+    fn can_distribute_on_the_left() {
+        // (1 + 2) * 3
+        let expr: MultExpr = multiply(
+            add(integer_literal(1), integer_literal(2)),
+            integer_literal(3),
+        );
+        let distributed = distribute(expr);
+        assert_eq!(distributed.to_string(), "((1 * 3) + (2 * 3))");
+        assert_eq!(distributed.evaluate(), 9);
+    }
+
+    #[test]
+    fn can_distribute_on_the_right() {
+        // 3 * (1 + 2)
+        let expr: MultExpr = multiply(
+            integer_literal(3),
+            add(integer_literal(1), integer_literal(2)),
+        );
+        assert_eq!(distribute(expr).to_string(), "((3 * 1) + (3 * 2))");
+    }
+
+    #[test]
+    fn can_distribute_both_sides_and_nested_products() {
+        // (1 + 2) * (3 + 4), and then the whole thing times 5
+        let expr: MultExpr = multiply(
+            multiply(
+                add(integer_literal(1), integer_literal(2)),
+                add(integer_literal(3), integer_literal(4)),
+            ),
+            integer_literal(5),
+        );
+        let before = expr.evaluate();
+        let distributed = distribute(expr);
+        assert_eq!(
+            distributed.to_string(),
+            "((((1 * 3) * 5) + ((1 * 4) * 5)) + (((2 * 3) * 5) + ((2 * 4) * 5)))"
+        );
+        assert_eq!(distributed.evaluate(), before);
+    }
+
+    #[test]
+    fn leaves_sums_of_products_alone() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(2), integer_literal(3)),
+            integer_literal(4),
+        );
+        assert_eq!(distribute(expr).to_string(), "((2 * 3) + 4)");
+    }
+}
@@ -19,7 +19,7 @@
 
 /// A memory store can be incremented by a delta value, but this requires mutable access to it.
 pub trait Increment {
-    fn increment(&mut self, delta: i64) -> ();
+    fn increment(&mut self, delta: i64);
 }
 
 /// If you only want to read the contents of the memory, you can get away with non-mutable access
@@ -34,7 +34,7 @@ pub struct Mem {
 }
 
 impl Increment for Mem {
-    fn increment(&mut self, delta: i64) -> () {
+    fn increment(&mut self, delta: i64) {
         self.value += delta;
     }
 }
@@ -48,26 +48,19 @@ pub fn second<E: From<Second<E>>>(pair: E) -> E {
     E::from(Second { pair })
 }
 
-// All of these nested Sums are getting cumbersome.  Let's add a macro.
-
-macro_rules! Sum {
-    { $A:ty, $B:ty } => { Sum<$A, $B> };
-    { $A:ty, $($B:ty),+ } => { Sum<$A, Sum![$($B),+]> };
-}
-
-// Now we create an expression type that can include pairs.
+// Now we create an expression type that can include pairs.  All of these nested Sums are getting
+// cumbersome, so we use the Sum! macro from ch02.
 
 pub type PairSig<E> = Sum![Pair<E>, First<E>, Second<E>, Sig<E>];
 pub struct PairExpr(pub Box<PairSig<PairExpr>>);
 
-impl<X> From<X> for PairExpr
-where
-    PairSig<PairExpr>: From<X>,
-{
-    fn from(x: X) -> PairExpr {
-        PairExpr(Box::new(PairSig::<PairExpr>::from(x)))
-    }
-}
+from_terms!(
+    PairExpr: Pair<PairExpr>,
+    First<PairExpr>,
+    Second<PairExpr>,
+    IntegerLiteral,
+    Add<PairExpr>,
+);
 
 #[cfg(test)]
 mod tests {
@@ -19,6 +19,7 @@
 //! we can extend in a different module to work with PairExpr and pairs?  (Yes.)
 
 use crate::ch02_open_sum::*;
+use crate::ch05d_subtraction::*;
 
 /// Well that was easy.  (Not really!  Don't worry, we'll run into wrinkles.)
 pub trait EvaluateAny<V> {
@@ -59,6 +60,16 @@ where
     }
 }
 
+impl<V, E> EvaluateAny<V> for Subtract<E>
+where
+    E: EvaluateAny<V>,
+    V: std::ops::Sub<Output = V>,
+{
+    fn evaluate(&self) -> V {
+        self.lhs.evaluate() - self.rhs.evaluate()
+    }
+}
+
 /// We can evaluate a sum if we know how to evaluate both of its variants; we just delegate to the
 /// underlying type's impl.
 impl<V, L, R> EvaluateAny<V> for Sum<L, R>
@@ -86,6 +97,15 @@ where
     }
 }
 
+impl<V> EvaluateAny<V> for SubExpr
+where
+    V: From<i64> + std::ops::Add<Output = V> + std::ops::Sub<Output = V>,
+{
+    fn evaluate(&self) -> V {
+        self.0.evaluate()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,7 +116,7 @@ mod tests {
         // 118 + 1219
         let add: Expr = add(integer_literal(118), integer_literal(1219));
         // Kind of gross
-        assert_eq!((&add as &EvaluateAny<i64>).evaluate(), 1337);
+        assert_eq!((&add as &dyn EvaluateAny<i64>).evaluate(), 1337);
         // A little bit nicer
         assert_eq!(evaluate_any::<i64, _>(&add), 1337);
     }
@@ -108,7 +128,17 @@ mod tests {
             integer_literal(30000),
             add(integer_literal(1330), integer_literal(7)),
         );
-        assert_eq!((&add as &EvaluateAny<i64>).evaluate(), 31337);
+        assert_eq!((&add as &dyn EvaluateAny<i64>).evaluate(), 31337);
         assert_eq!(evaluate_any::<i64, _>(&add), 31337);
     }
+
+    #[test]
+    fn can_evaluate_subtraction() {
+        // 1337 - (30000 + 7)
+        let sub: SubExpr = subtract(
+            integer_literal(1337),
+            add(integer_literal(30000), integer_literal(7)),
+        );
+        assert_eq!(evaluate_any::<i64, _>(&sub), -28670);
+    }
 }
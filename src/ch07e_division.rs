@@ -0,0 +1,241 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Division is the first term whose evaluation rule can fail even when every operand is an integer:
+//! you can't divide by zero.  (Or divide `i64::MIN` by -1, which overflows.)  SafeIntOrPair from
+//! ch07d already turns failures into values instead of panics, but it can only say that
+//! *something* went wrong.  Let's make a result type that says what.
+//!
+//! Just like in ch07d, the evaluation rules don't need to know about any of this; the rule for
+//! Divide only needs a value type that implements `std::ops::Div`.
+
+use crate::ch02_open_sum::*;
+use crate::ch07a_pairs::*;
+use crate::ch07b_generic_evaluation::*;
+use crate::ch07c_pair_evaluation::*;
+
+use std::fmt;
+
+/// Divides one expression by another, rounding towards zero.
+pub struct Divide<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+pub fn divide<E: From<Divide<E>>>(lhs: E, rhs: E) -> E {
+    E::from(Divide { lhs, rhs })
+}
+
+impl<E> fmt::Display for Divide<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} / {})", self.lhs, self.rhs)
+    }
+}
+
+impl<V, E> EvaluateAny<V> for Divide<E>
+where
+    E: EvaluateAny<V>,
+    V: std::ops::Div<Output = V>,
+{
+    fn evaluate(&self) -> V {
+        self.lhs.evaluate() / self.rhs.evaluate()
+    }
+}
+
+// A language with pairs and division.
+pub type DivSig<E> = Sum<Divide<E>, PairSig<E>>;
+pub struct DivExpr(pub Box<DivSig<DivExpr>>);
+
+from_terms!(
+    DivExpr: Divide<DivExpr>,
+    Pair<DivExpr>,
+    First<DivExpr>,
+    Second<DivExpr>,
+    IntegerLiteral,
+    Add<DivExpr>,
+);
+
+impl<V> EvaluateAny<V> for DivExpr
+where
+    V: From<i64>
+        + From<(V, V)>
+        + std::ops::Add<Output = V>
+        + std::ops::Div<Output = V>
+        + ProjectPair,
+{
+    fn evaluate(&self) -> V {
+        self.0.evaluate()
+    }
+}
+
+/// Everything that can go wrong while evaluating a DivExpr.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EvalError {
+    DivideByZero,
+    Overflow,
+    /// Arithmetic on a pair.
+    NotAnInt,
+    /// A projection of an integer.
+    NotAPair,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::DivideByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "arithmetic overflow"),
+            EvalError::NotAnInt => write!(f, "expected an integer, found a pair"),
+            EvalError::NotAPair => write!(f, "expected a pair, found an integer"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Like SafeIntOrPair, but remembers why an evaluation failed.  The first error wins, and flows
+/// up through the rest of the evaluation.
+#[derive(Debug, PartialEq)]
+pub struct Checked(pub Result<IntOrPair, EvalError>);
+
+impl From<i64> for Checked {
+    fn from(value: i64) -> Checked {
+        Checked(Ok(IntOrPair::Int(value)))
+    }
+}
+
+impl Checked {
+    pub(crate) fn ints(self, other: Checked) -> Result<(i64, i64), EvalError> {
+        match (self.0?, other.0?) {
+            (IntOrPair::Int(lhs), IntOrPair::Int(rhs)) => Ok((lhs, rhs)),
+            _ => Err(EvalError::NotAnInt),
+        }
+    }
+}
+
+impl std::ops::Add for Checked {
+    type Output = Checked;
+    fn add(self, other: Checked) -> Checked {
+        let sum = self
+            .ints(other)
+            .and_then(|(lhs, rhs)| lhs.checked_add(rhs).ok_or(EvalError::Overflow));
+        Checked(sum.map(IntOrPair::Int))
+    }
+}
+
+impl std::ops::Div for Checked {
+    type Output = Checked;
+    fn div(self, other: Checked) -> Checked {
+        let quotient = self.ints(other).and_then(|(lhs, rhs)| match rhs {
+            0 => Err(EvalError::DivideByZero),
+            _ => lhs.checked_div(rhs).ok_or(EvalError::Overflow),
+        });
+        Checked(quotient.map(IntOrPair::Int))
+    }
+}
+
+impl From<(Checked, Checked)> for Checked {
+    fn from(value: (Checked, Checked)) -> Checked {
+        let (Checked(first), Checked(second)) = value;
+        let pair = |first, second| IntOrPair::Pair(Box::new(first), Box::new(second));
+        Checked(first.and_then(|first| Ok(pair(first, second?))))
+    }
+}
+
+impl ProjectPair for Checked {
+    fn first(self) -> Checked {
+        Checked(self.0.and_then(|value| match value {
+            IntOrPair::Pair(first, _) => Ok(*first),
+            IntOrPair::Int(_) => Err(EvalError::NotAPair),
+        }))
+    }
+
+    fn second(self) -> Checked {
+        Checked(self.0.and_then(|value| match value {
+            IntOrPair::Pair(_, second) => Ok(*second),
+            IntOrPair::Int(_) => Err(EvalError::NotAPair),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_divide() {
+        // first(pair(1337 / (3 + 4), 0))
+        let expr: DivExpr = first(pair(
+            divide(
+                integer_literal(1337),
+                add(integer_literal(3), integer_literal(4)),
+            ),
+            integer_literal(0),
+        ));
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Ok(IntOrPair::Int(191)))
+        );
+
+        // Division rounds towards zero.
+        let quotient = Divide {
+            lhs: IntegerLiteral { value: -7 },
+            rhs: IntegerLiteral { value: 2 },
+        };
+        assert_eq!(evaluate_any::<i64, _>(&quotient), -3);
+        assert_eq!(Divide { lhs: -7, rhs: 2 }.to_string(), "(-7 / 2)");
+    }
+
+    #[test]
+    fn division_by_zero_is_a_value() {
+        // 1 + (5 / (2 + -2))
+        let expr: DivExpr = add(
+            integer_literal(1),
+            divide(
+                integer_literal(5),
+                add(integer_literal(2), integer_literal(-2)),
+            ),
+        );
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Err(EvalError::DivideByZero))
+        );
+        let expr: DivExpr = divide(integer_literal(i64::MIN), integer_literal(-1));
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Err(EvalError::Overflow))
+        );
+    }
+
+    #[test]
+    fn other_errors_are_values_too() {
+        let expr: DivExpr = divide(
+            pair(integer_literal(1), integer_literal(2)),
+            integer_literal(0),
+        );
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Err(EvalError::NotAnInt))
+        );
+        let expr: DivExpr = second(integer_literal(1));
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Err(EvalError::NotAPair))
+        );
+    }
+}
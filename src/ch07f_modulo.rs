@@ -0,0 +1,213 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Two more division-like terms.  `Modulo` gives the remainder of a division, and its evaluation
+//! rule only needs a value type that implements `std::ops::Rem`, the same way that ch07e's rule for
+//! `Divide` only needs `std::ops::Div`.
+//!
+//! `IntDiv` is division that always produces a whole number.  For ints, that's exactly what
+//! `Divide` already does, but for a value type like f64, `/` doesn't round at all.  There's no
+//! std::ops trait for "divide and round", so (like ProjectPair in ch07c) we define one.  It rounds
+//! towards zero, to agree with `Rem`: for any `a` and nonzero `b`, `a` is always
+//! `b * (a div b) + (a % b)`.
+
+use crate::ch02_open_sum::*;
+use crate::ch07a_pairs::*;
+use crate::ch07b_generic_evaluation::*;
+use crate::ch07c_pair_evaluation::*;
+use crate::ch07e_division::*;
+
+use std::fmt;
+
+/// The remainder of dividing one expression by another.  It has the same sign as the dividend.
+pub struct Modulo<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+/// Divides one expression by another, rounding the quotient towards zero, whatever kind of number
+/// the expressions evaluate to.
+pub struct IntDiv<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+pub fn modulo<E: From<Modulo<E>>>(lhs: E, rhs: E) -> E {
+    E::from(Modulo { lhs, rhs })
+}
+
+pub fn int_div<E: From<IntDiv<E>>>(lhs: E, rhs: E) -> E {
+    E::from(IntDiv { lhs, rhs })
+}
+
+impl<E> fmt::Display for Modulo<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} % {})", self.lhs, self.rhs)
+    }
+}
+
+impl<E> fmt::Display for IntDiv<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} div {})", self.lhs, self.rhs)
+    }
+}
+
+/// A value type that can divide and round the result towards zero.
+pub trait IntegerDivide {
+    fn int_div(self, other: Self) -> Self;
+}
+
+impl IntegerDivide for i64 {
+    fn int_div(self, other: i64) -> i64 {
+        self / other
+    }
+}
+
+impl IntegerDivide for f64 {
+    fn int_div(self, other: f64) -> f64 {
+        (self / other).trunc()
+    }
+}
+
+impl<V, E> EvaluateAny<V> for Modulo<E>
+where
+    E: EvaluateAny<V>,
+    V: std::ops::Rem<Output = V>,
+{
+    fn evaluate(&self) -> V {
+        self.lhs.evaluate() % self.rhs.evaluate()
+    }
+}
+
+impl<V, E> EvaluateAny<V> for IntDiv<E>
+where
+    E: EvaluateAny<V>,
+    V: IntegerDivide,
+{
+    fn evaluate(&self) -> V {
+        self.lhs.evaluate().int_div(self.rhs.evaluate())
+    }
+}
+
+// A language with both kinds of division, along with ch07e's language of pairs and division.
+pub type ModSig<E> = Sum<Modulo<E>, Sum<IntDiv<E>, DivSig<E>>>;
+pub struct ModExpr(pub Box<ModSig<ModExpr>>);
+
+from_terms!(
+    ModExpr: Modulo<ModExpr>,
+    IntDiv<ModExpr>,
+    Divide<ModExpr>,
+    Pair<ModExpr>,
+    First<ModExpr>,
+    Second<ModExpr>,
+    IntegerLiteral,
+    Add<ModExpr>,
+);
+
+impl<V> EvaluateAny<V> for ModExpr
+where
+    V: From<i64>
+        + From<(V, V)>
+        + std::ops::Add<Output = V>
+        + std::ops::Div<Output = V>
+        + std::ops::Rem<Output = V>
+        + IntegerDivide
+        + ProjectPair,
+{
+    fn evaluate(&self) -> V {
+        self.0.evaluate()
+    }
+}
+
+// ch07e's Checked result type can report a zero divisor for these terms too.
+
+impl std::ops::Rem for Checked {
+    type Output = Checked;
+    fn rem(self, other: Checked) -> Checked {
+        let remainder = self.ints(other).and_then(|(lhs, rhs)| match rhs {
+            0 => Err(EvalError::DivideByZero),
+            _ => lhs.checked_rem(rhs).ok_or(EvalError::Overflow),
+        });
+        Checked(remainder.map(IntOrPair::Int))
+    }
+}
+
+impl IntegerDivide for Checked {
+    fn int_div(self, other: Checked) -> Checked {
+        self / other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_take_remainders() {
+        // (17 % 5) + (17 div 5)
+        let expr: ModExpr = add(
+            modulo(integer_literal(17), integer_literal(5)),
+            int_div(integer_literal(17), integer_literal(5)),
+        );
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Ok(IntOrPair::Int(5)))
+        );
+        let remainder = Modulo {
+            lhs: IntegerLiteral { value: -17 },
+            rhs: IntegerLiteral { value: 5 },
+        };
+        assert_eq!(evaluate_any::<i64, _>(&remainder), -2);
+        assert_eq!(Modulo { lhs: 17, rhs: 5 }.to_string(), "(17 % 5)");
+        assert_eq!(IntDiv { lhs: 17, rhs: 5 }.to_string(), "(17 div 5)");
+    }
+
+    #[test]
+    fn quotients_and_remainders_agree() {
+        for (a, b) in [(7, 2), (-7, 2), (7, -2), (-7, -2)] {
+            let quotient = a.int_div(b);
+            assert_eq!(b * quotient + a % b, a);
+        }
+        assert_eq!(7.5.int_div(2.0), 3.0);
+        assert_eq!((-7.5).int_div(2.0), -3.0);
+        assert_eq!(7.5 % 2.0, 1.5);
+    }
+
+    #[test]
+    fn zero_divisors_are_values() {
+        let expr: ModExpr = modulo(integer_literal(1), integer_literal(0));
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Err(EvalError::DivideByZero))
+        );
+        let expr: ModExpr = int_div(integer_literal(1), integer_literal(0));
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Err(EvalError::DivideByZero))
+        );
+        let expr: ModExpr = modulo(integer_literal(i64::MIN), integer_literal(-1));
+        assert_eq!(
+            evaluate_any::<Checked, _>(&expr),
+            Checked(Err(EvalError::Overflow))
+        );
+    }
+}
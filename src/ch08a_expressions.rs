@@ -17,7 +17,11 @@
 
 use crate::ch02_open_sum::*;
 use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
 use crate::ch07a_pairs::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::trampoline::take_signature;
 
 /// An Expression represents the AST of one of our mini-languages.  It has a `Signature` associated
 /// type, which is a `Sum` of all of the possible terms in the language, along with methods for
@@ -28,11 +32,16 @@ pub trait Expression {
     type Signature;
     fn wrap(sig: Self::Signature) -> Self;
     fn unwrap(&self) -> &Self::Signature;
+    fn into_signature(self) -> Self::Signature;
 }
 
 // And then we define an Expression impl for each of our actual expression AST types.  They're all
 // *very* boilerplate.  But!  If we've done this right, it will eliminate *all* of the other
 // per-AST-type boilerplate!
+//
+// (The trampoline module gives these types Drop impls that don't recurse, and you can't move
+// anything out of a type that has a Drop impl.  So instead of moving the signature out of its box,
+// `into_signature` swaps it for a literal, which is all that the Drop impl will then see.)
 
 impl Expression for Expr {
     type Signature = Sig<Expr>;
@@ -42,6 +51,9 @@ impl Expression for Expr {
     fn unwrap(&self) -> &Self::Signature {
         &self.0
     }
+    fn into_signature(mut self) -> Self::Signature {
+        take_signature(&mut *self.0)
+    }
 }
 
 impl Expression for MultExpr {
@@ -52,6 +64,9 @@ impl Expression for MultExpr {
     fn unwrap(&self) -> &Self::Signature {
         &self.0
     }
+    fn into_signature(mut self) -> Self::Signature {
+        take_signature(&mut *self.0)
+    }
 }
 
 impl Expression for NoAddExpr {
@@ -62,6 +77,48 @@ impl Expression for NoAddExpr {
     fn unwrap(&self) -> &Self::Signature {
         &self.0
     }
+    fn into_signature(mut self) -> Self::Signature {
+        take_signature(&mut *self.0)
+    }
+}
+
+impl Expression for SubExpr {
+    type Signature = SubSig<SubExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(mut self) -> Self::Signature {
+        take_signature(&mut *self.0)
+    }
+}
+
+impl Expression for DivExpr {
+    type Signature = DivSig<DivExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(mut self) -> Self::Signature {
+        take_signature(&mut *self.0)
+    }
+}
+
+impl Expression for ModExpr {
+    type Signature = ModSig<ModExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(mut self) -> Self::Signature {
+        take_signature(&mut *self.0)
+    }
 }
 
 impl Expression for PairExpr {
@@ -72,4 +129,7 @@ impl Expression for PairExpr {
     fn unwrap(&self) -> &Self::Signature {
         &self.0
     }
+    fn into_signature(mut self) -> Self::Signature {
+        take_signature(&mut *self.0)
+    }
 }
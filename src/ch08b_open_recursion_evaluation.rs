@@ -19,8 +19,11 @@
 
 use crate::ch02_open_sum::*;
 use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
 use crate::ch07a_pairs::*;
 use crate::ch07c_pair_evaluation::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
 use crate::ch08a_expressions::*;
 
 // Ideally we would be able to reuse EvaluateAny.  It's quite nice!  But as much as we want to, we
@@ -100,6 +103,54 @@ where
     }
 }
 
+impl<V, E> Eval<V, E> for Subtract<E>
+where
+    V: std::ops::Sub<Output = V>,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs) - eval_subexpr(&self.rhs)
+    }
+}
+
+impl<V, E> Eval<V, E> for Divide<E>
+where
+    V: std::ops::Div<Output = V>,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs) / eval_subexpr(&self.rhs)
+    }
+}
+
+impl<V, E> Eval<V, E> for Modulo<E>
+where
+    V: std::ops::Rem<Output = V>,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs) % eval_subexpr(&self.rhs)
+    }
+}
+
+impl<V, E> Eval<V, E> for IntDiv<E>
+where
+    V: IntegerDivide,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs).int_div(eval_subexpr(&self.rhs))
+    }
+}
+
 impl<V, E> Eval<V, E> for Pair<E>
 where
     V: From<(V, V)>,
@@ -187,7 +238,7 @@ where
 //     let expr: Expr = /* whatever */;
 //     expr.evaluate::<i64>();
 
-trait Evaluate: Sized {
+pub trait Evaluate: Sized {
     fn evaluate<V>(&self) -> V
     where
         Self: Eval<V, Self>;
@@ -244,6 +295,36 @@ mod tests {
         assert_eq!(mult.evaluate::<i64>(), 42);
     }
 
+    #[test]
+    fn can_evaluate_subtraction() {
+        let sub: SubExpr = subtract(
+            add(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(sub.evaluate::<i64>(), 81);
+    }
+
+    #[test]
+    fn can_evaluate_division() {
+        let expr: DivExpr = first(pair(
+            divide(integer_literal(7), integer_literal(0)),
+            integer_literal(1),
+        ));
+        assert_eq!(
+            expr.evaluate::<Checked>(),
+            Checked(Err(EvalError::DivideByZero))
+        );
+    }
+
+    #[test]
+    fn can_evaluate_modulo() {
+        let expr: ModExpr = add(
+            modulo(integer_literal(-7), integer_literal(2)),
+            int_div(integer_literal(-7), integer_literal(2)),
+        );
+        assert_eq!(expr.evaluate::<Checked>(), Checked(Ok(IntOrPair::Int(-4))));
+    }
+
     #[test]
     fn can_evaluate_pair() {
         let expr: PairExpr = pair(integer_literal(7), integer_literal(6));
@@ -0,0 +1,328 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Now that our evaluation rules work for any value type that supports the right operators, let's
+//! use that to check something more interesting than "is this an integer or a pair".  Here's a
+//! value type that carries a physical unit along with each number.  Adding two values only works
+//! if their units match, and multiplying them multiplies their units — so evaluating an expression
+//! also checks that it's dimensionally correct.  None of the evaluation rules need to change!
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+
+use std::fmt;
+
+/// A unit is a product of powers of base units.  We only track a few of them, but adding more
+/// would just mean adding fields.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Unit {
+    pub metres: i8,
+    pub seconds: i8,
+    pub kilograms: i8,
+}
+
+impl Unit {
+    pub const DIMENSIONLESS: Unit = Unit {
+        metres: 0,
+        seconds: 0,
+        kilograms: 0,
+    };
+    pub const METRE: Unit = Unit {
+        metres: 1,
+        ..Unit::DIMENSIONLESS
+    };
+    pub const SECOND: Unit = Unit {
+        seconds: 1,
+        ..Unit::DIMENSIONLESS
+    };
+    pub const KILOGRAM: Unit = Unit {
+        kilograms: 1,
+        ..Unit::DIMENSIONLESS
+    };
+
+    /// Returns the reciprocal of this unit, which lets you write units like m·s⁻¹.
+    pub fn inverse(self) -> Unit {
+        Unit {
+            metres: -self.metres,
+            seconds: -self.seconds,
+            kilograms: -self.kilograms,
+        }
+    }
+}
+
+impl std::ops::Mul for Unit {
+    type Output = Unit;
+    fn mul(self, other: Unit) -> Unit {
+        Unit {
+            metres: self.metres + other.metres,
+            seconds: self.seconds + other.seconds,
+            kilograms: self.kilograms + other.kilograms,
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let powers = [
+            ("kg", self.kilograms),
+            ("m", self.metres),
+            ("s", self.seconds),
+        ];
+        let mut first = true;
+        for (symbol, power) in powers.iter().filter(|(_, power)| *power != 0) {
+            if !first {
+                write!(f, "*")?;
+            }
+            first = false;
+            match power {
+                1 => write!(f, "{}", symbol)?,
+                _ => write!(f, "{}^{}", symbol, power)?,
+            }
+        }
+        if first {
+            write!(f, "1")?;
+        }
+        Ok(())
+    }
+}
+
+/// A number along with its unit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantity {
+    pub value: i64,
+    pub unit: Unit,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.unit == Unit::DIMENSIONLESS {
+            write!(f, "{}", self.value)
+        } else {
+            write!(f, "{} {}", self.value, self.unit)
+        }
+    }
+}
+
+/// What goes wrong when an expression isn't dimensionally correct.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnitError {
+    Mismatch { lhs: Unit, rhs: Unit },
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnitError::Mismatch { lhs, rhs } => write!(f, "cannot add {} to {}", rhs, lhs),
+        }
+    }
+}
+
+impl std::error::Error for UnitError {}
+
+// Like SafeIntOrPair in ch07d, our value type wraps up the possibility of an error, so that the
+// operators can return it instead of panicking.  Once an error happens, it flows up through the
+// rest of the evaluation.
+
+#[derive(Debug, PartialEq)]
+pub struct Measure(pub Result<Quantity, UnitError>);
+
+impl From<Quantity> for Measure {
+    fn from(quantity: Quantity) -> Measure {
+        Measure(Ok(quantity))
+    }
+}
+
+/// Plain integers don't have a unit.  That means you can use them to scale other quantities, but
+/// you can't add them to anything that does have a unit.
+impl From<i64> for Measure {
+    fn from(value: i64) -> Measure {
+        Quantity {
+            value,
+            unit: Unit::DIMENSIONLESS,
+        }
+        .into()
+    }
+}
+
+impl std::ops::Add for Measure {
+    type Output = Measure;
+    fn add(self, other: Measure) -> Measure {
+        let (lhs, rhs) = match (self.0, other.0) {
+            (Ok(lhs), Ok(rhs)) => (lhs, rhs),
+            (Err(error), _) | (_, Err(error)) => return Measure(Err(error)),
+        };
+        if lhs.unit != rhs.unit {
+            return Measure(Err(UnitError::Mismatch {
+                lhs: lhs.unit,
+                rhs: rhs.unit,
+            }));
+        }
+        Quantity {
+            value: lhs.value + rhs.value,
+            unit: lhs.unit,
+        }
+        .into()
+    }
+}
+
+impl std::ops::Mul for Measure {
+    type Output = Measure;
+    fn mul(self, other: Measure) -> Measure {
+        match (self.0, other.0) {
+            (Ok(lhs), Ok(rhs)) => Quantity {
+                value: lhs.value * rhs.value,
+                unit: lhs.unit * rhs.unit,
+            }
+            .into(),
+            (Err(error), _) | (_, Err(error)) => Measure(Err(error)),
+        }
+    }
+}
+
+// We do need one new term, so that we can write down a number that has a unit in the first place.
+// Its evaluation rule works with any value type that can be created from a Quantity.
+
+/// A number with a unit.
+pub struct Measured {
+    pub value: i64,
+    pub unit: Unit,
+}
+
+impl<V, E> Eval<V, E> for Measured
+where
+    V: From<Quantity>,
+{
+    fn eval<F>(&self, _eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        V::from(Quantity {
+            value: self.value,
+            unit: self.unit,
+        })
+    }
+}
+
+pub fn measured<E: From<Measured>>(value: i64, unit: Unit) -> E {
+    E::from(Measured { value, unit })
+}
+
+pub type UnitSig<E> = Sum![Measured, Multiply<E>, Sig<E>];
+pub struct UnitExpr(pub Box<UnitSig<UnitExpr>>);
+
+impl Expression for UnitExpr {
+    type Signature = UnitSig<UnitExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    UnitExpr: Measured,
+    Multiply<UnitExpr>,
+    IntegerLiteral,
+    Add<UnitExpr>,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn metres(value: i64) -> UnitExpr {
+        measured(value, Unit::METRE)
+    }
+
+    fn seconds(value: i64) -> UnitExpr {
+        measured(value, Unit::SECOND)
+    }
+
+    #[test]
+    fn can_add_matching_units() {
+        let expr: UnitExpr = add(metres(3), metres(4));
+        assert_eq!(
+            expr.evaluate::<Measure>(),
+            Quantity {
+                value: 7,
+                unit: Unit::METRE
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn cannot_add_mismatched_units() {
+        let expr: UnitExpr = add(metres(3), seconds(4));
+        let error = expr.evaluate::<Measure>().0.unwrap_err();
+        assert_eq!(
+            error,
+            UnitError::Mismatch {
+                lhs: Unit::METRE,
+                rhs: Unit::SECOND
+            }
+        );
+        assert_eq!(error.to_string(), "cannot add s to m");
+    }
+
+    #[test]
+    fn can_multiply_units() {
+        // (3 m * 4 m) * 2 kg
+        let expr: UnitExpr = multiply(multiply(metres(3), metres(4)), measured(2, Unit::KILOGRAM));
+        let quantity = expr.evaluate::<Measure>().0.unwrap();
+        assert_eq!(quantity.value, 24);
+        assert_eq!(quantity.to_string(), "24 kg*m^2");
+    }
+
+    #[test]
+    fn can_scale_by_plain_integers() {
+        // 3 * 5 m/s + 2 m/s
+        let speed = Unit::METRE * Unit::SECOND.inverse();
+        let expr: UnitExpr = add(
+            multiply(integer_literal(3), measured(5, speed)),
+            measured(2, speed),
+        );
+        assert_eq!(
+            expr.evaluate::<Measure>().0.unwrap().to_string(),
+            "17 m*s^-1"
+        );
+    }
+
+    #[test]
+    fn cannot_add_plain_integers_to_units() {
+        let expr: UnitExpr = add(integer_literal(1), metres(2));
+        assert!(expr.evaluate::<Measure>().0.is_err());
+    }
+
+    #[test]
+    fn errors_propagate() {
+        // (1 m + 1 s) * 2 m — the multiplication can't hide the error in its subexpression
+        let expr: UnitExpr = multiply(add(metres(1), seconds(1)), metres(2));
+        assert!(expr.evaluate::<Measure>().0.is_err());
+    }
+
+    #[test]
+    fn dimensionless_expressions_still_evaluate() {
+        let expr: UnitExpr = add(integer_literal(2), integer_literal(3));
+        assert_eq!(expr.evaluate::<Measure>(), 5.into());
+    }
+}
@@ -0,0 +1,386 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! We have a whole family of expression types now, and plenty of them overlap.  Every Expr is also
+//! a valid PairExpr, and plenty of PairExprs (the ones that don't use any pairs) are valid Exprs
+//! too.  Can we convert between them generically, without writing a conversion for each pair of
+//! types?
+//!
+//! The tricky part is going in the "narrowing" direction.  Given some term, we need to ask the
+//! target signature whether it has a variant that can hold it — and get a "no" back at runtime,
+//! instead of a compile error, when it doesn't.  ch04's Inject can't do that, since it only has
+//! impls for the cases that succeed.  So we define a new trait that tries each variant of a
+//! signature in turn.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
+use crate::ch07a_pairs::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::ch08a_expressions::*;
+
+use std::any::Any;
+use std::fmt;
+
+/// A type that a value of type `X` might be narrowed into.  Returns the original value if it
+/// doesn't fit.
+pub trait Narrow<X>: Sized {
+    fn narrow(x: X) -> Result<Self, X>;
+}
+
+impl<X, L, R> Narrow<X> for Sum<L, R>
+where
+    L: Narrow<X>,
+    R: Narrow<X>,
+{
+    fn narrow(x: X) -> Result<Sum<L, R>, X> {
+        L::narrow(x)
+            .map(Sum::Left)
+            .or_else(|x| R::narrow(x).map(Sum::Right))
+    }
+}
+
+// At the leaves of a signature, we have a single term type, and we need to check whether it's the
+// same type as X.  We can't express "is the same type" with trait impls on stable Rust (see ch04),
+// but since all of our terms are 'static, we can check at runtime using Any.
+
+/// Moves `x` into a `T`, if it actually is one.
+pub fn cast<X: 'static, T: 'static>(x: X) -> Result<T, X> {
+    let mut slot = Some(x);
+    if let Some(t) = (&mut slot as &mut dyn Any).downcast_mut::<Option<T>>() {
+        return Ok(t.take().unwrap());
+    }
+    Err(slot.unwrap())
+}
+
+/// Implements `Narrow` for term types, so that they can appear at the leaves of a signature.
+#[macro_export]
+macro_rules! narrow_terms {
+    ($($term:ident $(<$E:ident>)?),+ $(,)?) => {
+        $(
+            impl<X: 'static $(, $E: 'static)?> $crate::ch08d_cross_family_conversion::Narrow<X>
+                for $term $(<$E>)?
+            {
+                fn narrow(x: X) -> Result<Self, X> {
+                    $crate::ch08d_cross_family_conversion::cast(x)
+                }
+            }
+        )+
+    };
+}
+
+narrow_terms!(
+    IntegerLiteral,
+    Add<E>,
+    Multiply<E>,
+    Subtract<E>,
+    Divide<E>,
+    Modulo<E>,
+    IntDiv<E>,
+    Pair<E>,
+    First<E>,
+    Second<E>
+);
+
+// Now we can convert a term.  We use open recursion again (like ch08b), so that each term only has
+// to say how to rebuild itself once its subexpressions have been converted — and which step of the
+// path each subexpression lives at, so that we can say where any failures happened.
+
+/// Somewhere that a conversion found a term that the target doesn't have.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingTerm {
+    pub term: &'static str,
+    pub path: Vec<&'static str>,
+}
+
+impl fmt::Display for MissingTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "no `{}` term at the root", self.term)
+        } else {
+            write!(f, "no `{}` term at {}", self.term, self.path.join("."))
+        }
+    }
+}
+
+/// Every place where a conversion found a term that the target doesn't have.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConvertError {
+    pub missing: Vec<MissingTerm>,
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let missing: Vec<String> = self.missing.iter().map(|m| m.to_string()).collect();
+        write!(f, "{}", missing.join("; "))
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+/// Each term type should implement this trait to define how it's converted into an expression of
+/// type `T`.  `E` is the type of the term's subexpressions.
+pub trait ConvertTerm<T, E> {
+    fn convert_term<F>(&self, path: &[&'static str], convert_subexpr: F) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>;
+}
+
+/// Wraps up a rebuilt term in the target expression type, if its signature has room for it.
+fn rebuild<T, X>(term: X, name: &'static str, path: &[&'static str]) -> Result<T, ConvertError>
+where
+    T: Expression,
+    T::Signature: Narrow<X>,
+{
+    T::Signature::narrow(term)
+        .map(T::wrap)
+        .map_err(|_| ConvertError {
+            missing: vec![MissingTerm {
+                term: name,
+                path: path.to_vec(),
+            }],
+        })
+}
+
+/// Converts two subexpressions, reporting the failures from both of them.
+fn convert_both<T, E, F>(
+    lhs: (&E, &'static str),
+    rhs: (&E, &'static str),
+    mut convert_subexpr: F,
+) -> Result<(T, T), ConvertError>
+where
+    F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+{
+    let lhs = convert_subexpr(lhs.0, lhs.1);
+    let rhs = convert_subexpr(rhs.0, rhs.1);
+    match (lhs, rhs) {
+        (Ok(lhs), Ok(rhs)) => Ok((lhs, rhs)),
+        (lhs, rhs) => Err(ConvertError {
+            missing: (lhs.err().into_iter())
+                .chain(rhs.err())
+                .flat_map(|error| error.missing)
+                .collect(),
+        }),
+    }
+}
+
+impl<T, E> ConvertTerm<T, E> for IntegerLiteral
+where
+    T: Expression,
+    T::Signature: Narrow<IntegerLiteral>,
+{
+    fn convert_term<F>(&self, path: &[&'static str], _convert_subexpr: F) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+    {
+        rebuild(
+            IntegerLiteral { value: self.value },
+            "integer_literal",
+            path,
+        )
+    }
+}
+
+impl<T, E> ConvertTerm<T, E> for Add<E>
+where
+    T: Expression,
+    T::Signature: Narrow<Add<T>>,
+{
+    fn convert_term<F>(&self, path: &[&'static str], convert_subexpr: F) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+    {
+        let (lhs, rhs) = convert_both((&self.lhs, "lhs"), (&self.rhs, "rhs"), convert_subexpr)?;
+        rebuild(Add { lhs, rhs }, "add", path)
+    }
+}
+
+impl<T, E> ConvertTerm<T, E> for Multiply<E>
+where
+    T: Expression,
+    T::Signature: Narrow<Multiply<T>>,
+{
+    fn convert_term<F>(&self, path: &[&'static str], convert_subexpr: F) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+    {
+        let (lhs, rhs) = convert_both((&self.lhs, "lhs"), (&self.rhs, "rhs"), convert_subexpr)?;
+        rebuild(Multiply { lhs, rhs }, "multiply", path)
+    }
+}
+
+impl<T, E> ConvertTerm<T, E> for Pair<E>
+where
+    T: Expression,
+    T::Signature: Narrow<Pair<T>>,
+{
+    fn convert_term<F>(&self, path: &[&'static str], convert_subexpr: F) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+    {
+        let (first, second) = convert_both(
+            (&self.first, "first"),
+            (&self.second, "second"),
+            convert_subexpr,
+        )?;
+        rebuild(Pair { first, second }, "pair", path)
+    }
+}
+
+impl<T, E> ConvertTerm<T, E> for First<E>
+where
+    T: Expression,
+    T::Signature: Narrow<First<T>>,
+{
+    fn convert_term<F>(
+        &self,
+        path: &[&'static str],
+        mut convert_subexpr: F,
+    ) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+    {
+        let pair = convert_subexpr(&self.pair, "pair")?;
+        rebuild(First { pair }, "first", path)
+    }
+}
+
+impl<T, E> ConvertTerm<T, E> for Second<E>
+where
+    T: Expression,
+    T::Signature: Narrow<Second<T>>,
+{
+    fn convert_term<F>(
+        &self,
+        path: &[&'static str],
+        mut convert_subexpr: F,
+    ) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+    {
+        let pair = convert_subexpr(&self.pair, "pair")?;
+        rebuild(Second { pair }, "second", path)
+    }
+}
+
+impl<T, E, L, R> ConvertTerm<T, E> for Sum<L, R>
+where
+    L: ConvertTerm<T, E>,
+    R: ConvertTerm<T, E>,
+{
+    fn convert_term<F>(&self, path: &[&'static str], convert_subexpr: F) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+    {
+        match self {
+            Sum::Left(lhs) => lhs.convert_term(path, convert_subexpr),
+            Sum::Right(rhs) => rhs.convert_term(path, convert_subexpr),
+        }
+    }
+}
+
+// And like ch08b's evaluate, one method to tie the knot, for any Expression type.
+
+pub trait TryConvert: Expression + Sized {
+    fn try_convert<T>(&self) -> Result<T, ConvertError>
+    where
+        Self::Signature: ConvertTerm<T, Self>;
+}
+
+impl<E> TryConvert for E
+where
+    E: Expression,
+{
+    fn try_convert<T>(&self) -> Result<T, ConvertError>
+    where
+        Self::Signature: ConvertTerm<T, Self>,
+    {
+        fn convert_at<T, E>(expr: &E, path: &mut Vec<&'static str>) -> Result<T, ConvertError>
+        where
+            E: Expression,
+            E::Signature: ConvertTerm<T, E>,
+        {
+            let here = path.clone();
+            expr.unwrap().convert_term(&here, |subexpr, step| {
+                path.push(step);
+                let result = convert_at(subexpr, path);
+                path.pop();
+                result
+            })
+        }
+        convert_at(self, &mut Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch03_evaluation::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_widen_expressions() {
+        let expr: Expr = add(
+            integer_literal(30000),
+            add(integer_literal(1330), integer_literal(7)),
+        );
+        let widened: MultExpr = expr.try_convert().unwrap();
+        assert_eq!(widened.evaluate(), 31337);
+    }
+
+    #[test]
+    fn can_narrow_expressions_that_only_use_shared_terms() {
+        let expr: PairExpr = add(integer_literal(118), integer_literal(1219));
+        let narrowed = expr.try_convert::<Expr>().unwrap();
+        assert_eq!(narrowed.evaluate(), 1337);
+    }
+
+    #[test]
+    fn cannot_narrow_expressions_with_missing_terms() {
+        let expr: PairExpr = add(
+            first(pair(integer_literal(1), integer_literal(2))),
+            add(integer_literal(3), second(integer_literal(4))),
+        );
+        let error = expr.try_convert::<Expr>().err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "no `pair` term at lhs.pair; no `second` term at rhs.rhs"
+        );
+    }
+
+    #[test]
+    fn reports_missing_terms_at_the_root() {
+        let expr: MultExpr = multiply(integer_literal(6), integer_literal(7));
+        let error = expr.try_convert::<Expr>().err().unwrap();
+        assert_eq!(
+            error.missing,
+            vec![MissingTerm {
+                term: "multiply",
+                path: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn can_convert_between_partially_overlapping_families() {
+        // NoAddExpr and Expr only share integer literals.
+        let expr: NoAddExpr = integer_literal(5);
+        assert_eq!(expr.try_convert::<Expr>().unwrap().evaluate(), 5);
+        let expr: NoAddExpr = multiply(integer_literal(6), integer_literal(7));
+        assert!(expr.try_convert::<Expr>().is_err());
+        assert_eq!(expr.try_convert::<MultExpr>().unwrap().evaluate(), 42);
+    }
+}
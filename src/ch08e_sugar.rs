@@ -0,0 +1,409 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Some terms don't need to be built into the language at all, because they can be written in
+//! terms of the others.  Negation is just multiplication by -1.  We call these terms "syntactic
+//! sugar": they make expressions nicer to write, but before we evaluate an expression, we
+//! "desugar" it, replacing each sugar term with its definition.  The nice part about doing this
+//! with open sums is that the desugared expression has a different type than the sugared one — a
+//! type that doesn't contain the sugar terms — so the compiler checks that we didn't forget any.
+//!
+//! Desugaring is a transformation that rebuilds the whole tree, so (like ch09d's owned fold) each
+//! term consumes itself, and is handed a function that desugars its subexpressions by value.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
+use crate::ch07a_pairs::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::ch08a_expressions::*;
+use crate::narrow_terms;
+use crate::trampoline::take_signature;
+
+use std::fmt;
+
+/// A term that can be desugared into an expression of type `F`, given a way to desugar its
+/// subexpressions (of type `E`).
+pub trait Desugar<E, F> {
+    fn desugar<D>(self, desugar: D) -> F
+    where
+        D: FnMut(E) -> F;
+}
+
+impl<E, F, L, R> Desugar<E, F> for Sum<L, R>
+where
+    L: Desugar<E, F>,
+    R: Desugar<E, F>,
+{
+    fn desugar<D>(self, desugar: D) -> F
+    where
+        D: FnMut(E) -> F,
+    {
+        match self {
+            Sum::Left(lhs) => lhs.desugar(desugar),
+            Sum::Right(rhs) => rhs.desugar(desugar),
+        }
+    }
+}
+
+/// Desugars an entire expression.
+pub fn desugar<E, F>(expr: E) -> F
+where
+    E: Expression,
+    E::Signature: Desugar<E, F>,
+{
+    expr.into_signature().desugar(desugar::<E, F>)
+}
+
+// And, like ch08b's Evaluate, a blanket impl lets you call it as a method on any expression.  A
+// language never needs its own Desugar impl: it only needs each of its terms to have one.
+
+/// Desugars an expression into an expression of type `F`.
+pub trait DesugarExpression: Expression + Sized {
+    fn desugar<F>(self) -> F
+    where
+        Self::Signature: Desugar<Self, F>;
+}
+
+impl<E> DesugarExpression for E
+where
+    E: Expression,
+{
+    fn desugar<F>(self) -> F
+    where
+        Self::Signature: Desugar<Self, F>,
+    {
+        desugar(self)
+    }
+}
+
+// Terms that aren't sugar desugar into themselves.  (`SugarFree` is defined below.)
+
+impl<E, F> Desugar<E, F> for IntegerLiteral
+where
+    F: From<IntegerLiteral>,
+{
+    fn desugar<D>(self, _desugar: D) -> F
+    where
+        D: FnMut(E) -> F,
+    {
+        F::from(self)
+    }
+}
+
+macro_rules! not_sugar {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<E> SugarFree for $term<E> {}
+
+            impl<E, F> Desugar<E, F> for $term<E>
+            where
+                F: From<$term<F>>,
+            {
+                fn desugar<D>(self, mut desugar: D) -> F
+                where
+                    D: FnMut(E) -> F,
+                {
+                    F::from($term { $($field: desugar(self.$field)),+ })
+                }
+            }
+        )+
+    };
+}
+
+not_sugar!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Subtract { lhs, rhs },
+    Divide { lhs, rhs },
+    Modulo { lhs, rhs },
+    IntDiv { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+);
+
+// The output type of `desugar` is what proves that the sugar is gone, but only to someone who can
+// see what that type is.  Generic code downstream might want to require it: "give me any
+// expression, as long as it doesn't contain sugar."  We can't ask the compiler whether a signature
+// *lacks* a term (that's the NotEq problem from ch04 again), but we can turn it around: every term
+// that isn't sugar says so, and a signature is sugar-free if all of its terms are.  Sugar terms
+// simply never implement the trait, so a signature that contains one doesn't either.
+
+/// Evidence that a term, or every term in a signature, isn't syntactic sugar.  If you define a new
+/// term that isn't sugar, implement this for it.
+pub trait SugarFree {}
+
+impl<L, R> SugarFree for Sum<L, R>
+where
+    L: SugarFree,
+    R: SugarFree,
+{
+}
+
+impl SugarFree for IntegerLiteral {}
+
+/// An expression that's guaranteed not to contain any sugar.
+pub trait SugarFreeExpression: Expression {}
+
+impl<E> SugarFreeExpression for E
+where
+    E: Expression,
+    E::Signature: SugarFree,
+{
+}
+
+/// Desugars an expression, and proves that the result is sugar-free, even in generic code that
+/// doesn't know what `F` is.  (ch09l's `desugar_within` does the same, but under `RewriteLimits`.)
+pub fn desugar_fully<E, F>(expr: E) -> F
+where
+    E: Expression,
+    E::Signature: Desugar<E, F>,
+    F: SugarFreeExpression,
+{
+    desugar(expr)
+}
+
+/// The terms that a sugar term's definition can use.
+pub trait DesugarTarget: From<IntegerLiteral> + From<Add<Self>> + From<Multiply<Self>> {}
+
+impl<F> DesugarTarget for F where F: From<IntegerLiteral> + From<Add<F>> + From<Multiply<F>> {}
+
+// Every sugar term needs the same four things: a struct, a smart constructor, a Desugar impl, and
+// a Display impl.  Only two parts of that actually vary — the definition, and how to print it — so
+// a macro can write the rest.
+//
+// In the definition, each field is bound to its already-desugared subexpression, which you can
+// combine with the ch04 smart constructors.  Since a desugared subexpression isn't Clone, each
+// field can only appear once in the definition.  The display string can refer to fields by name.
+
+/// Defines a sugar term:
+///
+/// ```text
+/// define_sugar!(
+///     /// Negation
+///     Negate { value }, negate, "-{value}" => multiply(integer_literal(-1), value)
+/// );
+/// ```
+#[macro_export]
+macro_rules! define_sugar {
+    (
+        $(#[$meta:meta])*
+        $name:ident { $($field:ident),+ $(,)? }, $constructor:ident, $display:literal
+            => $definition:expr $(,)?
+    ) => {
+        $(#[$meta])*
+        pub struct $name<E> {
+            $(pub $field: E),+
+        }
+
+        pub fn $constructor<E: From<$name<E>>>($($field: E),+) -> E {
+            E::from($name { $($field),+ })
+        }
+
+        impl<E, F> $crate::ch08e_sugar::Desugar<E, F> for $name<E>
+        where
+            F: $crate::ch08e_sugar::DesugarTarget,
+        {
+            fn desugar<D>(self, mut desugar: D) -> F
+            where
+                D: FnMut(E) -> F,
+            {
+                #[allow(unused_imports)]
+                use $crate::ch04_smart_constructors::*;
+                #[allow(unused_imports)]
+                use $crate::ch05a_multiplication::*;
+                $(let $field = desugar(self.$field);)+
+                $definition
+            }
+        }
+
+        impl<E: ::std::fmt::Display> ::std::fmt::Display for $name<E> {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                write!(f, $display, $($field = self.$field),+)
+            }
+        }
+    };
+}
+
+// With the macro, negation is one line.
+
+define_sugar!(
+    /// Negation, which is just multiplication by -1.
+    Negate { value }, negate, "-{value}" => multiply(integer_literal(-1), value)
+);
+
+// The infix parser can read negations back in, so it needs to be able to narrow them into a
+// signature, just like ch08d's built-in terms.
+narrow_terms!(Negate<E>);
+
+pub type NegateSig<E> = Sum![Negate<E>, MultSig<E>];
+pub struct NegateExpr(pub Box<NegateSig<NegateExpr>>);
+
+impl Expression for NegateExpr {
+    type Signature = NegateSig<NegateExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(mut self) -> Self::Signature {
+        take_signature(&mut *self.0)
+    }
+}
+
+from_terms!(
+    NegateExpr: Negate<NegateExpr>,
+    Multiply<NegateExpr>,
+    IntegerLiteral,
+    Add<NegateExpr>,
+);
+
+impl fmt::Display for NegateExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch08b_open_recursion_evaluation::{Eval, Evaluate};
+
+    define_sugar!(
+        /// The sum of three things.
+        Sum3 { a, b, c }, sum3, "sum({a}, {b}, {c})" => add(add(a, b), c)
+    );
+
+    type Sum3Sig<E> = Sum![Sum3<E>, NegateSig<E>];
+    struct Sum3Expr(Box<Sum3Sig<Sum3Expr>>);
+
+    impl Expression for Sum3Expr {
+        type Signature = Sum3Sig<Sum3Expr>;
+        fn wrap(sig: Self::Signature) -> Self {
+            Self(Box::new(sig))
+        }
+        fn unwrap(&self) -> &Self::Signature {
+            &self.0
+        }
+        fn into_signature(self) -> Self::Signature {
+            *self.0
+        }
+    }
+
+    from_terms!(
+        Sum3Expr: Sum3<Sum3Expr>,
+        Negate<Sum3Expr>,
+        Multiply<Sum3Expr>,
+        IntegerLiteral,
+        Add<Sum3Expr>,
+    );
+
+    #[test]
+    fn can_desugar_negation() {
+        let expr: NegateExpr = add(integer_literal(410), negate(integer_literal(6)));
+        assert_eq!(expr.to_string(), "(410 + -6)");
+        let desugared: MultExpr = desugar(expr);
+        assert_eq!(desugared.evaluate::<i64>(), 404);
+        assert_eq!(desugared.to_string(), "(410 + (-1 * 6))");
+    }
+
+    #[test]
+    fn can_desugar_nested_sugar() {
+        let expr: Sum3Expr = sum3(
+            integer_literal(400),
+            negate(negate(integer_literal(3))),
+            integer_literal(1),
+        );
+        let desugared: MultExpr = desugar(expr);
+        assert_eq!(desugared.evaluate::<i64>(), 404);
+    }
+
+    // Sugar can be added to any language, as long as each of its terms can be desugared.
+    type SubMultSig<E> = Sum![Subtract<E>, MultSig<E>];
+    struct SubMultExpr(Box<SubMultSig<SubMultExpr>>);
+    type NegSubSig<E> = Sum![Negate<E>, SubMultSig<E>];
+    struct NegSubExpr(Box<NegSubSig<NegSubExpr>>);
+
+    impl Expression for SubMultExpr {
+        type Signature = SubMultSig<SubMultExpr>;
+        fn wrap(sig: Self::Signature) -> Self {
+            Self(Box::new(sig))
+        }
+        fn unwrap(&self) -> &Self::Signature {
+            &self.0
+        }
+        fn into_signature(self) -> Self::Signature {
+            *self.0
+        }
+    }
+
+    impl Expression for NegSubExpr {
+        type Signature = NegSubSig<NegSubExpr>;
+        fn wrap(sig: Self::Signature) -> Self {
+            Self(Box::new(sig))
+        }
+        fn unwrap(&self) -> &Self::Signature {
+            &self.0
+        }
+        fn into_signature(self) -> Self::Signature {
+            *self.0
+        }
+    }
+
+    from_terms!(
+        SubMultExpr: Subtract<SubMultExpr>,
+        Multiply<SubMultExpr>,
+        IntegerLiteral,
+        Add<SubMultExpr>,
+    );
+
+    from_terms!(
+        NegSubExpr: Negate<NegSubExpr>,
+        Subtract<NegSubExpr>,
+        Multiply<NegSubExpr>,
+        IntegerLiteral,
+        Add<NegSubExpr>,
+    );
+
+    #[test]
+    fn can_desugar_with_a_method() {
+        let expr: NegSubExpr = subtract(integer_literal(400), negate(integer_literal(4)));
+        let desugared: SubMultExpr = expr.desugar();
+        assert_eq!(desugared.evaluate::<i64>(), 404);
+    }
+
+    // This only compiles because MultExpr's signature is sugar-free; try it with NegateExpr.
+    fn evaluate_without_sugar<E>(expr: &E) -> i64
+    where
+        E: SugarFreeExpression + Eval<i64, E>,
+    {
+        expr.evaluate()
+    }
+
+    #[test]
+    fn can_require_sugar_free_expressions() {
+        let expr: Sum3Expr = sum3(
+            integer_literal(400),
+            negate(integer_literal(-3)),
+            integer_literal(1),
+        );
+        let desugared: MultExpr = desugar_fully(expr);
+        assert_eq!(evaluate_without_sugar(&desugared), 404);
+    }
+}
@@ -0,0 +1,107 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! `IntegerLiteral` hard-codes `i64`, so every language with a different kind of number has had to
+//! define a literal term of its own, along with its own constructor, rendering, and evaluation
+//! rule.  None of those care what kind of value the literal holds, though.  `Lit<T>` is a literal
+//! of any type: it evaluates into any value type that can be built from a `T`, so one term serves
+//! float, bigint, and custom-numeric languages alike.
+//!
+//! We keep `IntegerLiteral` as it is, since so much is built on it, but ch12c's `FloatLiteral` is
+//! now just another name for `Lit<f64>`.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+
+use std::fmt;
+use std::num::Wrapping;
+
+/// A constant of any type.
+pub struct Lit<T> {
+    pub value: T,
+}
+
+pub fn lit<E: From<Lit<T>>, T>(value: T) -> E {
+    E::from(Lit { value })
+}
+
+// We render literals with Debug instead of Display, since that's what tells a float apart from an
+// int: 2.0 instead of 2.
+impl<T: fmt::Debug> fmt::Display for Lit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.value)
+    }
+}
+
+impl<V, E, T> Eval<V, E> for Lit<T>
+where
+    T: Clone,
+    V: From<T>,
+{
+    fn eval<F>(&self, _eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        V::from(self.value.clone())
+    }
+}
+
+// As an example, here's a language of bytes that wrap around on overflow, using the standard
+// library's `Wrapping`.  The existing rules for addition and multiplication already work for it.
+pub type ByteSig<E> = Sum![Lit<Wrapping<u8>>, Add<E>, Multiply<E>];
+pub struct ByteExpr(pub Box<ByteSig<ByteExpr>>);
+
+impl Expression for ByteExpr {
+    type Signature = ByteSig<ByteExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    ByteExpr: Lit<Wrapping<u8>>,
+    Add<ByteExpr>,
+    Multiply<ByteExpr>,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_evaluate_custom_literals() {
+        // 200 + 100 * 3 = 500, which wraps around to 244
+        let expr: ByteExpr = add(
+            lit(Wrapping(200)),
+            multiply(lit(Wrapping(100)), lit(Wrapping(3))),
+        );
+        assert_eq!(expr.evaluate::<Wrapping<u8>>(), Wrapping(244));
+    }
+
+    #[test]
+    fn can_render_literals() {
+        assert_eq!(Lit { value: 2.0 }.to_string(), "2.0");
+        assert_eq!(Lit { value: 7 }.to_string(), "7");
+    }
+}
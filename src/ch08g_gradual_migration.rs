@@ -0,0 +1,168 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! By now we've seen three evaluation traits: ch03's `EvaluateInt`, ch07b's `EvaluateAny<V>`, and
+//! ch08b's open-recursion `Eval<V, E>`.  A real codebase that started with the first one would have
+//! a lot of terms written against it, and wouldn't want to rewrite all of them before it could use
+//! the later ones.  This chapter makes it possible to migrate one term at a time.
+//!
+//! Wrapping a term in `Legacy` lets it join a signature that's evaluated with either of the newer
+//! traits, as long as the value type is i64.  The wrapped term still evaluates its subexpressions
+//! the old way, by calling `EvaluateInt::evaluate` on them, so the expression type has to implement
+//! `EvaluateInt` too.  The `evaluate_int_via_eval!` macro writes that impl, by running the open
+//! recursion evaluator.  Once a term has been ported to `Eval`, you drop the `Legacy` wrapper from
+//! the signature, and nothing else has to change.
+
+use crate::ch02_open_sum::*;
+use crate::ch03_evaluation::*;
+use crate::ch07b_generic_evaluation::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+
+use std::fmt;
+
+/// A term that only knows how to evaluate itself with `EvaluateInt`.
+pub struct Legacy<T>(pub T);
+
+pub fn legacy<E: From<Legacy<T>>, T>(term: T) -> E {
+    E::from(Legacy(term))
+}
+
+impl<T: fmt::Display> fmt::Display for Legacy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T> EvaluateAny<i64> for Legacy<T>
+where
+    T: EvaluateInt,
+{
+    fn evaluate(&self) -> i64 {
+        self.0.evaluate()
+    }
+}
+
+// The wrapped term doesn't use `eval_subexpr`, since it evaluates its subexpressions on its own.
+impl<T, E> Eval<i64, E> for Legacy<T>
+where
+    T: EvaluateInt,
+{
+    fn eval<F>(&self, _eval_subexpr: F) -> i64
+    where
+        F: FnMut(&E) -> i64,
+    {
+        self.0.evaluate()
+    }
+}
+
+/// Implements `EvaluateInt` for an expression type by evaluating it with ch08b's `Eval`, so that
+/// `Legacy` terms in its signature can evaluate their subexpressions.
+#[macro_export]
+macro_rules! evaluate_int_via_eval {
+    ($($expr:ty),+ $(,)?) => {
+        $(
+            impl $crate::ch03_evaluation::EvaluateInt for $expr {
+                fn evaluate(&self) -> i64 {
+                    <$expr as $crate::ch08b_open_recursion_evaluation::Evaluate>::evaluate::<i64>(
+                        self,
+                    )
+                }
+            }
+        )+
+    };
+}
+
+// Here's a term that was written for ch03, and hasn't been ported yet.
+
+/// The absolute value of a subexpression.
+pub struct Abs<E> {
+    pub expr: E,
+}
+
+impl<E> EvaluateInt for Abs<E>
+where
+    E: EvaluateInt,
+{
+    fn evaluate(&self) -> i64 {
+        self.expr.evaluate().abs()
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Abs<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "|{}|", self.expr)
+    }
+}
+
+pub fn abs<E: From<Legacy<Abs<E>>>>(expr: E) -> E {
+    legacy(Abs { expr })
+}
+
+// A language that mixes it with terms that have been ported.
+pub type MigrationSig<E> = Sum![Legacy<Abs<E>>, Sig<E>];
+pub struct MigrationExpr(pub Box<MigrationSig<MigrationExpr>>);
+
+impl Expression for MigrationExpr {
+    type Signature = MigrationSig<MigrationExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    MigrationExpr: Legacy<Abs<MigrationExpr>>,
+    IntegerLiteral,
+    Add<MigrationExpr>,
+);
+
+evaluate_int_via_eval!(MigrationExpr);
+
+impl EvaluateAny<i64> for MigrationExpr {
+    fn evaluate(&self) -> i64 {
+        EvaluateAny::evaluate(&*self.0)
+    }
+}
+
+impl fmt::Display for MigrationExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn legacy_terms_work_in_every_world() {
+        // |-3 + |-4|| + 1
+        let expr: MigrationExpr = add(
+            abs(add(integer_literal(-3), abs(integer_literal(-4)))),
+            integer_literal(1),
+        );
+        assert_eq!(expr.to_string(), "(|(-3 + |-4|)| + 1)");
+        assert_eq!(Evaluate::evaluate::<i64>(&expr), 2);
+        assert_eq!(evaluate_any::<i64, _>(&expr), 2);
+        assert_eq!(EvaluateInt::evaluate(&expr), 2);
+    }
+}
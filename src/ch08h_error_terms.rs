@@ -0,0 +1,269 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Tools that work on broken input — an editor showing a half-typed expression, a linter running
+//! over code that doesn't parse — still want an AST, so that they can print and analyze everything
+//! around the broken parts.  ch10b's parser handles this with a `SyntaxError` placeholder, but only
+//! for dynamic expressions.  Here's the same idea as an ordinary open-sum term: an `ErrorTerm`
+//! stands in for a part of the expression that we couldn't build, and says why.
+//!
+//! An error term has no value, so evaluating one produces an error value instead, which flows up
+//! through the rest of the evaluation like ch07e's `Checked`.  And since it's just another term, it
+//! only appears in languages that ask for it: an expression type without `ErrorTerm` in its
+//! signature is still guaranteed to be complete.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::Eval;
+use crate::ch08d_cross_family_conversion::*;
+use crate::narrow_terms;
+use crate::span::*;
+
+use std::fmt;
+
+/// A placeholder for part of an expression that couldn't be built.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorTerm {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+pub fn error_term<E: From<ErrorTerm>>(message: &str) -> E {
+    E::from(ErrorTerm {
+        message: message.to_string(),
+        span: None,
+    })
+}
+
+pub fn error_term_at<E: From<ErrorTerm>>(message: &str, span: Span) -> E {
+    E::from(ErrorTerm {
+        message: message.to_string(),
+        span: Some(span),
+    })
+}
+
+impl fmt::Display for ErrorTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<error: {}>", self.message)
+    }
+}
+
+// Like Pow in ch09i, the evaluation rule only needs a value type that knows how to represent an
+// error term.
+
+/// A value type that can represent the result of evaluating an error term.
+pub trait FromErrorTerm {
+    fn from_error_term(term: &ErrorTerm) -> Self;
+}
+
+impl<V, E> Eval<V, E> for ErrorTerm
+where
+    V: FromErrorTerm,
+{
+    fn eval<F>(&self, _eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        V::from_error_term(self)
+    }
+}
+
+/// The value of an expression that might contain error terms.  The first error wins, and flows up
+/// through the rest of the evaluation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partial(pub Result<i64, ErrorTerm>);
+
+impl From<i64> for Partial {
+    fn from(value: i64) -> Partial {
+        Partial(Ok(value))
+    }
+}
+
+impl FromErrorTerm for Partial {
+    fn from_error_term(term: &ErrorTerm) -> Partial {
+        Partial(Err(term.clone()))
+    }
+}
+
+impl std::ops::Add for Partial {
+    type Output = Partial;
+    fn add(self, other: Partial) -> Partial {
+        Partial(self.0.and_then(|lhs| Ok(lhs + other.0?)))
+    }
+}
+
+impl std::ops::Mul for Partial {
+    type Output = Partial;
+    fn mul(self, other: Partial) -> Partial {
+        Partial(self.0.and_then(|lhs| Ok(lhs * other.0?)))
+    }
+}
+
+// A language whose expressions might be incomplete.
+
+pub type ResilientSig<E> = Sum![ErrorTerm, MultSig<E>];
+pub struct ResilientExpr(pub Box<ResilientSig<ResilientExpr>>);
+
+impl Expression for ResilientExpr {
+    type Signature = ResilientSig<ResilientExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    ResilientExpr: ErrorTerm,
+    Multiply<ResilientExpr>,
+    IntegerLiteral,
+    Add<ResilientExpr>,
+);
+
+impl fmt::Display for ResilientExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// ch08d's conversions fail if the target language is missing any of the source's terms.  If the
+// target has error terms, we can do better: convert everything we can, and put a placeholder
+// wherever we can't.  Error terms themselves convert like any other term.
+
+narrow_terms!(ErrorTerm);
+
+impl<T, E> ConvertTerm<T, E> for ErrorTerm
+where
+    T: Expression,
+    T::Signature: Narrow<ErrorTerm>,
+{
+    fn convert_term<F>(&self, path: &[&'static str], _convert_subexpr: F) -> Result<T, ConvertError>
+    where
+        F: FnMut(&E, &'static str) -> Result<T, ConvertError>,
+    {
+        T::Signature::narrow(self.clone())
+            .map(T::wrap)
+            .map_err(|_| ConvertError {
+                missing: vec![MissingTerm {
+                    term: "error",
+                    path: path.to_vec(),
+                }],
+            })
+    }
+}
+
+pub trait ConvertResiliently: Expression + Sized {
+    /// Converts an expression into a language that has error terms, replacing each term that the
+    /// target language doesn't have with an error term.
+    fn convert_resiliently<T>(&self) -> T
+    where
+        T: Expression + From<ErrorTerm>,
+        Self::Signature: ConvertTerm<T, Self>;
+}
+
+impl<E> ConvertResiliently for E
+where
+    E: Expression,
+{
+    fn convert_resiliently<T>(&self) -> T
+    where
+        T: Expression + From<ErrorTerm>,
+        Self::Signature: ConvertTerm<T, Self>,
+    {
+        fn convert_at<T, E>(expr: &E, path: &mut Vec<&'static str>) -> T
+        where
+            T: Expression + From<ErrorTerm>,
+            E: Expression,
+            E::Signature: ConvertTerm<T, E>,
+        {
+            let here = path.clone();
+            // Subexpressions always convert, so an error can only be about this term.
+            let converted = expr.unwrap().convert_term(&here, |subexpr, step| {
+                path.push(step);
+                let result = convert_at(subexpr, path);
+                path.pop();
+                Ok(result)
+            });
+            converted.unwrap_or_else(|error| error_term(&error.to_string()))
+        }
+        convert_at(self, &mut Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch07a_pairs::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+
+    #[test]
+    fn error_terms_print_as_placeholders() {
+        let expr: ResilientExpr = add(
+            integer_literal(400),
+            multiply(integer_literal(2), error_term("expected an operand")),
+        );
+        assert_eq!(
+            expr.to_string(),
+            "(400 + (2 * <error: expected an operand>))"
+        );
+    }
+
+    #[test]
+    fn error_terms_evaluate_to_errors() {
+        let expr: ResilientExpr = add(
+            error_term_at("first", Span::new(0, 1)),
+            error_term("second"),
+        );
+        assert_eq!(
+            expr.evaluate::<Partial>(),
+            Partial(Err(ErrorTerm {
+                message: "first".to_string(),
+                span: Some(Span::new(0, 1)),
+            }))
+        );
+        let expr: ResilientExpr = add(integer_literal(400), integer_literal(4));
+        assert_eq!(expr.evaluate::<Partial>(), Partial(Ok(404)));
+    }
+
+    #[test]
+    fn failed_narrowing_inserts_placeholders() {
+        let expr: PairExpr = add(
+            first(pair(integer_literal(1), integer_literal(2))),
+            add(integer_literal(3), integer_literal(4)),
+        );
+        let converted: ResilientExpr = expr.convert_resiliently();
+        assert_eq!(
+            converted.to_string(),
+            "(<error: no `first` term at lhs> + (3 + 4))"
+        );
+        assert!(converted.evaluate::<Partial>().0.is_err());
+    }
+
+    #[test]
+    fn complete_expressions_convert_without_placeholders() {
+        let expr: MultExpr = multiply(integer_literal(101), integer_literal(4));
+        let converted: ResilientExpr = expr.convert_resiliently();
+        assert_eq!(converted.evaluate::<Partial>(), Partial(Ok(404)));
+        // And back again, since there aren't any error terms left.
+        let back: MultExpr = converted.try_convert().unwrap();
+        assert_eq!(back.to_string(), "(101 * 4)");
+    }
+}
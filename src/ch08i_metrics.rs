@@ -0,0 +1,250 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Evaluation isn't the only thing we can do with ch08b's open recursion.  Any operation that
+//! combines the results for a term's subexpressions into a result for the term fits the same
+//! pattern.  Here's one that measures the shape of an expression instead of computing its value:
+//! how many nodes it has, how deep it is, and how many of each kind of term it contains.
+//!
+//! Metrics can't be a value type for `Eval`, since a value type only sees the operators, and
+//! `Add` and `Subtract` would both just give it two numbers.  So this gets its own trait, with the
+//! same shape as `Eval`: each term says how to combine its subexpressions' metrics, and is handed
+//! the function that computes them.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
+use crate::ch07a_pairs::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::ch08a_expressions::*;
+use crate::ch08e_sugar::Negate;
+use crate::ch09e_functor::*;
+use crate::telemetry::TermKind;
+use crate::trampoline::{cata, Slot};
+
+use std::collections::BTreeMap;
+
+/// The shape of an expression.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    /// The number of terms in the expression.
+    pub nodes: usize,
+    /// The length of the longest path from the root to a leaf, counting both.  A lone literal has
+    /// depth 1.
+    pub depth: usize,
+    /// How many terms of each kind the expression contains, by telemetry name.
+    pub counts: BTreeMap<&'static str, usize>,
+}
+
+impl Metrics {
+    /// The metrics of a term of kind `name`, whose subexpressions have the given metrics.
+    pub fn term<I>(name: &'static str, children: I) -> Metrics
+    where
+        I: IntoIterator<Item = Metrics>,
+    {
+        let mut result = Metrics::default();
+        for child in children {
+            result.nodes += child.nodes;
+            result.depth = result.depth.max(child.depth);
+            for (name, count) in child.counts {
+                *result.counts.entry(name).or_insert(0) += count;
+            }
+        }
+        result.nodes += 1;
+        result.depth += 1;
+        *result.counts.entry(name).or_insert(0) += 1;
+        result
+    }
+
+    /// How many terms of kind `name` the expression contains.
+    pub fn count(&self, name: &str) -> usize {
+        self.counts.get(name).copied().unwrap_or(0)
+    }
+}
+
+/// Each term type implements this to say how its metrics are computed.  If the term has any
+/// subexpressions, it should use `measure_subexpr` to measure them.
+pub trait Measure<E> {
+    fn measure<F>(&self, measure_subexpr: F) -> Metrics
+    where
+        F: FnMut(&E) -> Metrics;
+}
+
+impl<E> Measure<E> for IntegerLiteral {
+    fn measure<F>(&self, _measure_subexpr: F) -> Metrics
+    where
+        F: FnMut(&E) -> Metrics,
+    {
+        Metrics::term(Self::NAME, None)
+    }
+}
+
+// Every other term looks the same: measure each subexpression, and combine them.
+
+macro_rules! measure_terms {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<E> Measure<E> for $term<E> {
+                fn measure<F>(&self, mut measure_subexpr: F) -> Metrics
+                where
+                    F: FnMut(&E) -> Metrics,
+                {
+                    Metrics::term(Self::NAME, vec![$(measure_subexpr(&self.$field)),+])
+                }
+            }
+        )+
+    };
+}
+
+measure_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Subtract { lhs, rhs },
+    Divide { lhs, rhs },
+    Modulo { lhs, rhs },
+    IntDiv { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+    Negate { value },
+);
+
+// The Sum impl and the blanket impl for expressions are exactly like the ones for Eval.
+
+impl<E, L, R> Measure<E> for Sum<L, R>
+where
+    L: Measure<E>,
+    R: Measure<E>,
+{
+    fn measure<F>(&self, measure_subexpr: F) -> Metrics
+    where
+        F: FnMut(&E) -> Metrics,
+    {
+        match self {
+            Sum::Left(lhs) => lhs.measure(measure_subexpr),
+            Sum::Right(rhs) => rhs.measure(measure_subexpr),
+        }
+    }
+}
+
+impl<E> Measure<E> for E
+where
+    E: Expression,
+    E::Signature: Measure<E>,
+{
+    fn measure<F>(&self, measure_subexpr: F) -> Metrics
+    where
+        F: FnMut(&E) -> Metrics,
+    {
+        self.unwrap().measure(measure_subexpr)
+    }
+}
+
+/// Measures any expression.
+pub fn metrics<E: Measure<E>>(expr: &E) -> Metrics {
+    expr.measure(metrics)
+}
+
+// That recurses once per level of the expression, like every other fold in ch08, so it can
+// overflow the stack on the deep trees that stress tests like to build.  But a term whose children
+// have already been replaced by their metrics can measure itself by just cloning them, and the
+// trampoline module's `cata` hands us exactly those terms — so the same impls give us a version
+// that doesn't recurse at all.
+
+/// Measures an expression without recursing, no matter how deep it is.  Consumes the expression.
+pub fn stack_safe_metrics<E, M, S>(expr: E) -> Metrics
+where
+    E: Expression,
+    E::Signature: Functor<E, Slot, Output = M>,
+    M: Functor<Slot, Metrics, Output = S>,
+    S: Measure<Metrics>,
+{
+    cata(expr, &mut |term: S| term.measure(Metrics::clone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn sample() -> MultExpr {
+        // (80 * 5) + 4
+        add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        )
+    }
+
+    #[test]
+    fn can_measure_expressions() {
+        let measured = metrics(&sample());
+        assert_eq!(measured.nodes, 5);
+        assert_eq!(measured.depth, 3);
+        assert_eq!(measured.count(IntegerLiteral::NAME), 3);
+        assert_eq!(measured.count(Add::<()>::NAME), 1);
+        assert_eq!(measured.count(Multiply::<()>::NAME), 1);
+        assert_eq!(measured.count(Pair::<()>::NAME), 0);
+    }
+
+    #[test]
+    fn can_measure_every_kind_of_term() {
+        let expr: PairExpr = first(pair(
+            integer_literal(1),
+            add(integer_literal(2), integer_literal(3)),
+        ));
+        let measured = metrics(&expr);
+        assert_eq!((measured.nodes, measured.depth), (6, 4));
+        assert_eq!(measured.counts.len(), 4);
+
+        let expr: SubExpr = subtract(integer_literal(7), integer_literal(2));
+        let measured = metrics(&expr);
+        assert_eq!((measured.nodes, measured.depth), (3, 2));
+        assert_eq!(measured.count(Subtract::<()>::NAME), 1);
+
+        let expr: ModExpr = modulo(
+            integer_literal(7),
+            int_div(
+                integer_literal(9),
+                divide(integer_literal(4), integer_literal(2)),
+            ),
+        );
+        let measured = metrics(&expr);
+        assert_eq!((measured.nodes, measured.depth), (7, 4));
+        for name in &["modulo", "int_div", "divide"] {
+            assert_eq!(measured.count(name), 1);
+        }
+    }
+
+    #[test]
+    fn stack_safe_metrics_match() {
+        let expected = metrics(&sample());
+        assert_eq!(stack_safe_metrics(sample()), expected);
+    }
+
+    #[test]
+    fn can_measure_deep_expressions() {
+        const DEEP: usize = 200_000;
+        // 1 + (1 + (1 + ...))
+        let mut expr: Expr = integer_literal(1);
+        for _ in 1..DEEP {
+            expr = add(integer_literal(1), expr);
+        }
+        let measured = stack_safe_metrics(expr);
+        assert_eq!(measured.depth, DEEP);
+        assert_eq!(measured.nodes, 2 * DEEP - 1);
+        assert_eq!(measured.count(Add::<()>::NAME), DEEP - 1);
+    }
+}
@@ -0,0 +1,296 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! If you squint at the `Eval` trait from ch08b, you'll notice that it's already most of the way
+//! to a *Mendler-style* fold: each evaluation rule never recurses into its subexpressions on its
+//! own; it's handed an abstract recursive call (`eval_subexpr`) and can only look at its children
+//! by calling it.  The only thing that's still hard-coded is the *algebra* — `Eval` can only ever
+//! evaluate.  If we wanted to compute something else by folding over an expression, we'd need a
+//! new trait, a new `Sum` impl, and a new blanket impl for every `Expression`.
+//!
+//! Let's pull the algebra out into a value of its own, so that one generic fold works for all of
+//! them.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch07c_pair_evaluation::*;
+use crate::ch08a_expressions::*;
+
+// In Haskell, a Mendler algebra has the type `forall r. (r -> a) -> f r -> a`.  The `forall r` is
+// what makes it interesting: the algebra has no idea what the subexpressions look like, and so the
+// *only* thing it can do with them is hand them to the recursive call.  We don't have rank-2 types
+// in Rust, but we get the same guarantee for free by making every impl generic in the
+// subexpression type `E`.
+//
+// Each algebra is its own type, and implements this trait once for each kind of term it knows how
+// to handle.  `V` is the carrier — the type of value that the fold produces.
+
+/// A Mendler-style algebra that knows how to fold a term of type `T`, whose subexpressions have
+/// type `E`, into a value of type `V`.  The algebra must use `recurse` to get at the value of any
+/// subexpression.
+pub trait Algebra<T, E, V> {
+    fn apply<F>(&self, term: &T, recurse: F) -> V
+    where
+        F: FnMut(&E) -> V;
+}
+
+// Just like in every other chapter, an algebra can handle a sum if it can handle both of its
+// variants.  The difference is that this is a single impl that works for *every* algebra.
+
+impl<A, E, V, L, R> Algebra<Sum<L, R>, E, V> for A
+where
+    A: Algebra<L, E, V> + Algebra<R, E, V>,
+{
+    fn apply<F>(&self, term: &Sum<L, R>, recurse: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        match term {
+            Sum::Left(lhs) => self.apply(lhs, recurse),
+            Sum::Right(rhs) => self.apply(rhs, recurse),
+        }
+    }
+}
+
+/// And this is the fold itself.  Compare this with the `Evaluate` helper at the end of ch08b: the
+/// bounds are just as simple (we only need the algebra to handle the expression's signature), but
+/// it works for any algebra, and not just for evaluation.
+pub fn mcata<A, E, V>(algebra: &A, expr: &E) -> V
+where
+    E: Expression,
+    A: Algebra<E::Signature, E, V>,
+{
+    algebra.apply(expr.unwrap(), |subexpr| mcata(algebra, subexpr))
+}
+
+// Our first algebra evaluates expressions.  The rules are exactly the same as in ch08b, and have
+// the same bounds on the value type.
+
+/// Evaluates an expression into any value type that supports the operations its terms need.
+pub struct Evaluator;
+
+impl<V, E> Algebra<IntegerLiteral, E, V> for Evaluator
+where
+    V: From<i64>,
+{
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        V::from(term.value)
+    }
+}
+
+impl<V, E> Algebra<Add<E>, E, V> for Evaluator
+where
+    V: std::ops::Add<Output = V>,
+{
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        recurse(&term.lhs) + recurse(&term.rhs)
+    }
+}
+
+impl<V, E> Algebra<Multiply<E>, E, V> for Evaluator
+where
+    V: std::ops::Mul<Output = V>,
+{
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        recurse(&term.lhs) * recurse(&term.rhs)
+    }
+}
+
+impl<V, E> Algebra<Pair<E>, E, V> for Evaluator
+where
+    V: From<(V, V)>,
+{
+    fn apply<F>(&self, term: &Pair<E>, mut recurse: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        V::from((recurse(&term.first), recurse(&term.second)))
+    }
+}
+
+impl<V, E> Algebra<First<E>, E, V> for Evaluator
+where
+    V: ProjectPair,
+{
+    fn apply<F>(&self, term: &First<E>, mut recurse: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        recurse(&term.pair).first()
+    }
+}
+
+impl<V, E> Algebra<Second<E>, E, V> for Evaluator
+where
+    V: ProjectPair,
+{
+    fn apply<F>(&self, term: &Second<E>, mut recurse: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        recurse(&term.pair).second()
+    }
+}
+
+// And here's the payoff: a completely different fold, which calculates how deep an expression is.
+// We don't need a new trait, or a new Sum impl, or a new blanket impl for Expression.  We just
+// need a new algebra, with one impl per term.
+
+/// Calculates the depth of an expression.  A leaf term has depth 1.
+pub struct Depth;
+
+impl<E> Algebra<IntegerLiteral, E, usize> for Depth {
+    fn apply<F>(&self, _term: &IntegerLiteral, _recurse: F) -> usize
+    where
+        F: FnMut(&E) -> usize,
+    {
+        1
+    }
+}
+
+impl<E> Algebra<Add<E>, E, usize> for Depth {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> usize
+    where
+        F: FnMut(&E) -> usize,
+    {
+        1 + recurse(&term.lhs).max(recurse(&term.rhs))
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, usize> for Depth {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> usize
+    where
+        F: FnMut(&E) -> usize,
+    {
+        1 + recurse(&term.lhs).max(recurse(&term.rhs))
+    }
+}
+
+impl<E> Algebra<Pair<E>, E, usize> for Depth {
+    fn apply<F>(&self, term: &Pair<E>, mut recurse: F) -> usize
+    where
+        F: FnMut(&E) -> usize,
+    {
+        1 + recurse(&term.first).max(recurse(&term.second))
+    }
+}
+
+impl<E> Algebra<First<E>, E, usize> for Depth {
+    fn apply<F>(&self, term: &First<E>, mut recurse: F) -> usize
+    where
+        F: FnMut(&E) -> usize,
+    {
+        1 + recurse(&term.pair)
+    }
+}
+
+impl<E> Algebra<Second<E>, E, usize> for Depth {
+    fn apply<F>(&self, term: &Second<E>, mut recurse: F) -> usize
+    where
+        F: FnMut(&E) -> usize,
+    {
+        1 + recurse(&term.pair)
+    }
+}
+
+// So how does this compare with ch08b?  The per-term impls are about the same size — the algebra
+// moves from being the trait to being the Self type, and the term moves from being the Self type
+// to being a trait parameter.  The trait bounds are also about the same: `mcata` needs
+// `A: Algebra<E::Signature, E, V>`, where ch08b needed `E::Signature: Eval<V, E>`.  What we win is
+// that all of the plumbing (the Sum impl and the recursion) is written exactly once, no matter how
+// many different folds we define.  What we lose is that the algebra is now an extra thing that you
+// have to name at every call site.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    // These are the same test cases that we used in ch08b.
+
+    #[test]
+    fn can_evaluate_ugly_expression() {
+        let add: Expr = add(integer_literal(118), integer_literal(1219));
+        assert_eq!(mcata::<_, _, i64>(&Evaluator, &add), 1337);
+    }
+
+    #[test]
+    fn can_evaluate_nested_expression() {
+        // 30000 + 1330 + 7
+        let add: Expr = add(
+            integer_literal(30000),
+            add(integer_literal(1330), integer_literal(7)),
+        );
+        assert_eq!(mcata::<_, _, i64>(&Evaluator, &add), 31337);
+    }
+
+    #[test]
+    fn can_evaluate_multiplication() {
+        let mult: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(mcata::<_, _, i64>(&Evaluator, &mult), 404);
+    }
+
+    #[test]
+    fn can_evaluate_no_add_multiplication() {
+        let mult: NoAddExpr = multiply(integer_literal(6), integer_literal(7));
+        assert_eq!(mcata::<_, _, i64>(&Evaluator, &mult), 42);
+    }
+
+    #[test]
+    fn can_evaluate_pair() {
+        let expr: PairExpr = pair(integer_literal(7), integer_literal(6));
+        assert_eq!(
+            mcata::<_, _, IntOrPair>(&Evaluator, &expr),
+            IntOrPair::Pair(Box::new(IntOrPair::Int(7)), Box::new(IntOrPair::Int(6)))
+        );
+    }
+
+    #[test]
+    fn can_evaluate_pair_projection() {
+        let expr: PairExpr = first(pair(integer_literal(7), integer_literal(6)));
+        assert_eq!(
+            mcata::<_, _, IntOrPair>(&Evaluator, &expr),
+            IntOrPair::Int(7)
+        );
+    }
+
+    // And the same fold works with our new algebra, too.
+
+    #[test]
+    fn can_calculate_depth() {
+        let one: Expr = integer_literal(1);
+        assert_eq!(mcata(&Depth, &one), 1);
+        let mult: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(mcata(&Depth, &mult), 3);
+        let expr: PairExpr = first(pair(integer_literal(7), integer_literal(6)));
+        assert_eq!(mcata(&Depth, &expr), 3);
+    }
+}
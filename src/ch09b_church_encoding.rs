@@ -0,0 +1,402 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Every encoding we've looked at so far is an *initial* encoding: an expression is a tree of
+//! values, and we fold over that tree to do anything with it.  The Church (or Boehm–Berarducci)
+//! encoding turns that inside out — an expression *is* its fold.  It's a function that takes in an
+//! algebra and gives you back whatever that algebra computes.
+//!
+//! In Haskell that's `forall a. (Int -> a) -> (a -> a -> a) -> (a -> a -> a) -> a`, which needs
+//! a rank-2 type.  We can't write that as a Rust closure type, but we can get it from a trait with
+//! a generic method.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch09a_mendler::*;
+
+use std::any::Any;
+use std::rc::Rc;
+
+/// An algebra for the arithmetic terms that we've defined so far.  The carrier is the type of value
+/// that the algebra computes.  Note that the algebra never sees a subexpression — by the time it's
+/// called, each subexpression has already been turned into a carrier value.
+pub trait ExprAlgebra {
+    type Carrier;
+    fn integer_literal(&self, value: i64) -> Self::Carrier;
+    fn add(&self, lhs: Self::Carrier, rhs: Self::Carrier) -> Self::Carrier;
+    fn multiply(&self, lhs: Self::Carrier, rhs: Self::Carrier) -> Self::Carrier;
+}
+
+/// A Church-encoded expression can be folded with *any* algebra.  The fact that `A` is a parameter
+/// of the method, and not of the trait, is what makes this "rank-2": the expression has to work
+/// for every algebra, and the caller gets to choose which one.
+pub trait Church {
+    fn fold<A: ExprAlgebra>(&self, algebra: &A) -> A::Carrier;
+}
+
+// To create Church-encoded expressions, we need a type for each kind of term.  Unlike in the
+// initial encoding, these types don't have to agree on what their subexpressions look like — all
+// they need is for each subexpression to be foldable.  That means the *shape* of the expression is
+// encoded into its type.
+
+pub struct ChurchLiteral(pub i64);
+
+pub struct ChurchAdd<L, R>(pub L, pub R);
+
+pub struct ChurchMultiply<L, R>(pub L, pub R);
+
+impl Church for ChurchLiteral {
+    fn fold<A: ExprAlgebra>(&self, algebra: &A) -> A::Carrier {
+        algebra.integer_literal(self.0)
+    }
+}
+
+impl<L, R> Church for ChurchAdd<L, R>
+where
+    L: Church,
+    R: Church,
+{
+    fn fold<A: ExprAlgebra>(&self, algebra: &A) -> A::Carrier {
+        algebra.add(self.0.fold(algebra), self.1.fold(algebra))
+    }
+}
+
+impl<L, R> Church for ChurchMultiply<L, R>
+where
+    L: Church,
+    R: Church,
+{
+    fn fold<A: ExprAlgebra>(&self, algebra: &A) -> A::Carrier {
+        algebra.multiply(self.0.fold(algebra), self.1.fold(algebra))
+    }
+}
+
+// And some smart constructors, so that building a Church-encoded expression looks just like
+// building an initial one.
+
+pub fn church_literal(value: i64) -> ChurchLiteral {
+    ChurchLiteral(value)
+}
+
+pub fn church_add<L: Church, R: Church>(lhs: L, rhs: R) -> ChurchAdd<L, R> {
+    ChurchAdd(lhs, rhs)
+}
+
+pub fn church_multiply<L: Church, R: Church>(lhs: L, rhs: R) -> ChurchMultiply<L, R> {
+    ChurchMultiply(lhs, rhs)
+}
+
+// A couple of algebras to fold with.
+
+/// Evaluates an expression into an integer.
+pub struct EvaluateAlgebra;
+
+impl ExprAlgebra for EvaluateAlgebra {
+    type Carrier = i64;
+    fn integer_literal(&self, value: i64) -> i64 {
+        value
+    }
+    fn add(&self, lhs: i64, rhs: i64) -> i64 {
+        lhs + rhs
+    }
+    fn multiply(&self, lhs: i64, rhs: i64) -> i64 {
+        lhs * rhs
+    }
+}
+
+/// Renders an expression the same way that the `Display` impls from ch05b do.
+pub struct RenderAlgebra;
+
+impl ExprAlgebra for RenderAlgebra {
+    type Carrier = String;
+    fn integer_literal(&self, value: i64) -> String {
+        value.to_string()
+    }
+    fn add(&self, lhs: String, rhs: String) -> String {
+        format!("({} + {})", lhs, rhs)
+    }
+    fn multiply(&self, lhs: String, rhs: String) -> String {
+        format!("({} * {})", lhs, rhs)
+    }
+}
+
+// Converting from the Church encoding back to the initial encoding is just another fold: the
+// algebra's carrier is the initial expression type, and each algebra method calls the
+// corresponding smart constructor.
+
+/// An algebra that builds an initial-encoded expression of type `E`.
+pub struct InitialAlgebra<E>(std::marker::PhantomData<E>);
+
+impl<E> InitialAlgebra<E> {
+    pub fn new() -> InitialAlgebra<E> {
+        InitialAlgebra(std::marker::PhantomData)
+    }
+}
+
+impl<E> Default for InitialAlgebra<E> {
+    fn default() -> InitialAlgebra<E> {
+        InitialAlgebra::new()
+    }
+}
+
+impl<E> ExprAlgebra for InitialAlgebra<E>
+where
+    E: From<IntegerLiteral> + From<Add<E>> + From<Multiply<E>>,
+{
+    type Carrier = E;
+    fn integer_literal(&self, value: i64) -> E {
+        E::from(IntegerLiteral { value })
+    }
+    fn add(&self, lhs: E, rhs: E) -> E {
+        E::from(Add { lhs, rhs })
+    }
+    fn multiply(&self, lhs: E, rhs: E) -> E {
+        E::from(Multiply { lhs, rhs })
+    }
+}
+
+/// Converts a Church-encoded expression into an initial-encoded one.
+pub fn to_initial<E, C>(church: &C) -> E
+where
+    C: Church,
+    E: From<IntegerLiteral> + From<Add<E>> + From<Multiply<E>>,
+{
+    church.fold(&InitialAlgebra::new())
+}
+
+// Going the other direction, we can't build one of our Church term types from an initial
+// expression, since their types encode the shape of the tree, and we don't know that shape until
+// runtime.  But we don't need to!  An initial expression already knows how to fold itself; that's
+// what the Mendler-style `mcata` from ch09a does.  All we need is an adapter that lets a Church
+// algebra pretend to be a Mendler algebra.
+
+struct MendlerAdapter<'a, A>(&'a A);
+
+impl<A, E> Algebra<IntegerLiteral, E, A::Carrier> for MendlerAdapter<'_, A>
+where
+    A: ExprAlgebra,
+{
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> A::Carrier
+    where
+        F: FnMut(&E) -> A::Carrier,
+    {
+        self.0.integer_literal(term.value)
+    }
+}
+
+impl<A, E> Algebra<Add<E>, E, A::Carrier> for MendlerAdapter<'_, A>
+where
+    A: ExprAlgebra,
+{
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> A::Carrier
+    where
+        F: FnMut(&E) -> A::Carrier,
+    {
+        let lhs = recurse(&term.lhs);
+        let rhs = recurse(&term.rhs);
+        self.0.add(lhs, rhs)
+    }
+}
+
+impl<A, E> Algebra<Multiply<E>, E, A::Carrier> for MendlerAdapter<'_, A>
+where
+    A: ExprAlgebra,
+{
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> A::Carrier
+    where
+        F: FnMut(&E) -> A::Carrier,
+    {
+        let lhs = recurse(&term.lhs);
+        let rhs = recurse(&term.rhs);
+        self.0.multiply(lhs, rhs)
+    }
+}
+
+/// Views an initial-encoded expression as a Church-encoded one.
+pub struct FromInitial<'a, E>(pub &'a E);
+
+pub fn from_initial<E>(expr: &E) -> FromInitial<'_, E> {
+    FromInitial(expr)
+}
+
+// We can't write a single generic impl here, since the bound we'd need
+// (`MendlerAdapter<A>: Algebra<E::Signature, E, A::Carrier>`) mentions the method's `A` parameter.
+// So we need one impl per expression type, just like in ch03.
+
+impl Church for FromInitial<'_, Expr> {
+    fn fold<A: ExprAlgebra>(&self, algebra: &A) -> A::Carrier {
+        mcata(&MendlerAdapter(algebra), self.0)
+    }
+}
+
+impl Church for FromInitial<'_, MultExpr> {
+    fn fold<A: ExprAlgebra>(&self, algebra: &A) -> A::Carrier {
+        mcata(&MendlerAdapter(algebra), self.0)
+    }
+}
+
+impl Church for FromInitial<'_, NoAddExpr> {
+    fn fold<A: ExprAlgebra>(&self, algebra: &A) -> A::Carrier {
+        mcata(&MendlerAdapter(algebra), self.0)
+    }
+}
+
+// There's one more way to get a Church-encoded expression whose shape we only know at runtime: we
+// can take the "an expression is a function" idea literally, and store a closure.  Rust closures
+// can't be generic, so the closure can't be polymorphic in the algebra's carrier type the way the
+// `Church` trait is.  Instead, we erase the carrier into a `Box<dyn Any>`, and downcast it back on
+// the way out.  (That's also why `DynChurch` has its own `fold` method instead of implementing
+// `Church` — we need the carrier to be `'static` to be able to erase it.)
+
+/// An object-safe version of `ExprAlgebra`, whose carrier has been erased.
+pub trait ErasedAlgebra {
+    fn integer_literal(&self, value: i64) -> Box<dyn Any>;
+    fn add(&self, lhs: Box<dyn Any>, rhs: Box<dyn Any>) -> Box<dyn Any>;
+    fn multiply(&self, lhs: Box<dyn Any>, rhs: Box<dyn Any>) -> Box<dyn Any>;
+}
+
+fn unerase<C: 'static>(value: Box<dyn Any>) -> C {
+    *value
+        .downcast()
+        .expect("Church expression folded with the wrong carrier type")
+}
+
+impl<A> ErasedAlgebra for A
+where
+    A: ExprAlgebra,
+    A::Carrier: 'static,
+{
+    fn integer_literal(&self, value: i64) -> Box<dyn Any> {
+        Box::new(ExprAlgebra::integer_literal(self, value))
+    }
+    fn add(&self, lhs: Box<dyn Any>, rhs: Box<dyn Any>) -> Box<dyn Any> {
+        Box::new(ExprAlgebra::add(self, unerase(lhs), unerase(rhs)))
+    }
+    fn multiply(&self, lhs: Box<dyn Any>, rhs: Box<dyn Any>) -> Box<dyn Any> {
+        Box::new(ExprAlgebra::multiply(self, unerase(lhs), unerase(rhs)))
+    }
+}
+
+type ErasedFold = dyn Fn(&dyn ErasedAlgebra) -> Box<dyn Any>;
+
+/// A Church-encoded expression that can be built at runtime.
+#[derive(Clone)]
+pub struct DynChurch(Rc<ErasedFold>);
+
+impl DynChurch {
+    pub fn fold<A>(&self, algebra: &A) -> A::Carrier
+    where
+        A: ExprAlgebra,
+        A::Carrier: 'static,
+    {
+        unerase((self.0)(algebra))
+    }
+}
+
+pub fn dyn_church_literal(value: i64) -> DynChurch {
+    DynChurch(Rc::new(move |algebra| algebra.integer_literal(value)))
+}
+
+pub fn dyn_church_add(lhs: DynChurch, rhs: DynChurch) -> DynChurch {
+    DynChurch(Rc::new(move |algebra| {
+        algebra.add((lhs.0)(algebra), (rhs.0)(algebra))
+    }))
+}
+
+pub fn dyn_church_multiply(lhs: DynChurch, rhs: DynChurch) -> DynChurch {
+    DynChurch(Rc::new(move |algebra| {
+        algebra.multiply((lhs.0)(algebra), (rhs.0)(algebra))
+    }))
+}
+
+// So which encoding is better?  (See benches/encodings.rs for the numbers.)
+//
+// When the shape of an expression is known at compile time, the Church encoding wins by a mile.
+// Each term type's `fold` is a tiny generic function, and after monomorphization the compiler can
+// inline the entire fold into straight-line code, without a single allocation or pointer chase.
+//
+// The initial encoding wins as soon as you need to *inspect* an expression instead of consuming it.
+// Asking "is the outermost term an addition?" is a single `match` for an initial expression, but a
+// Church expression can only answer questions by folding all the way through itself.  And of
+// course, an initial expression can be built at runtime (by a parser, say), whose shape we don't
+// know until we see the input.  The `from_initial` bridge lets us fold such an expression with a
+// Church algebra, but it pays the same per-node cost as any other initial fold.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_evaluate_church_expression() {
+        // (80 * 5) + 4
+        let expr = church_add(
+            church_multiply(church_literal(80), church_literal(5)),
+            church_literal(4),
+        );
+        assert_eq!(expr.fold(&EvaluateAlgebra), 404);
+        assert_eq!(expr.fold(&RenderAlgebra), "((80 * 5) + 4)");
+    }
+
+    #[test]
+    fn can_convert_church_to_initial() {
+        let expr = church_add(
+            church_multiply(church_literal(80), church_literal(5)),
+            church_literal(4),
+        );
+        let mult: MultExpr = to_initial(&expr);
+        assert_eq!(mcata::<_, _, i64>(&Evaluator, &mult), 404);
+    }
+
+    #[test]
+    fn can_convert_initial_to_church() {
+        let add: Expr = add(
+            integer_literal(30000),
+            add(integer_literal(1330), integer_literal(7)),
+        );
+        assert_eq!(from_initial(&add).fold(&EvaluateAlgebra), 31337);
+        assert_eq!(
+            from_initial(&add).fold(&RenderAlgebra),
+            "(30000 + (1330 + 7))"
+        );
+        let mult: NoAddExpr = multiply(integer_literal(6), integer_literal(7));
+        assert_eq!(from_initial(&mult).fold(&EvaluateAlgebra), 42);
+    }
+
+    #[test]
+    fn can_round_trip() {
+        let mult: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let round_tripped: MultExpr = to_initial(&from_initial(&mult));
+        assert_eq!(
+            from_initial(&round_tripped).fold(&RenderAlgebra),
+            "((80 * 5) + 4)"
+        );
+    }
+
+    #[test]
+    fn can_fold_dyn_church_expression() {
+        let expr = dyn_church_add(
+            dyn_church_multiply(dyn_church_literal(80), dyn_church_literal(5)),
+            dyn_church_literal(4),
+        );
+        assert_eq!(expr.fold(&EvaluateAlgebra), 404);
+        assert_eq!(expr.fold(&RenderAlgebra), "((80 * 5) + 4)");
+        let mult: MultExpr = expr.fold(&InitialAlgebra::new());
+        assert_eq!(mcata::<_, _, i64>(&Evaluator, &mult), 404);
+    }
+}
@@ -0,0 +1,412 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! There's a completely different way to solve the expression problem, which doesn't need sums at
+//! all: the *finally tagless* encoding.  Instead of a data type for each kind of term, we define a
+//! trait for each kind of term, whose methods construct that term in some representation.  An
+//! expression is then a generic function that builds itself using whichever of those traits it
+//! needs, and an interpreter is a type that implements them.
+//!
+//! Adding a new kind of term means adding a new trait; adding a new operation means adding a new
+//! interpreter type.  Neither requires touching any existing code.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch07c_pair_evaluation::*;
+use crate::ch08a_expressions::*;
+
+use std::marker::PhantomData;
+
+/// Every interpreter has a representation type, which is what it produces for each expression.
+/// All of the per-term traits below extend this one, so that they agree on what that type is.
+pub trait Symantics {
+    type Repr;
+}
+
+pub trait IntegerLiteralSym: Symantics {
+    fn integer_literal(&self, value: i64) -> Self::Repr;
+}
+
+pub trait AddSym: Symantics {
+    fn add(&self, lhs: Self::Repr, rhs: Self::Repr) -> Self::Repr;
+}
+
+pub trait MultiplySym: Symantics {
+    fn multiply(&self, lhs: Self::Repr, rhs: Self::Repr) -> Self::Repr;
+}
+
+pub trait PairSym: Symantics {
+    fn pair(&self, first: Self::Repr, second: Self::Repr) -> Self::Repr;
+    fn first(&self, pair: Self::Repr) -> Self::Repr;
+    fn second(&self, pair: Self::Repr) -> Self::Repr;
+}
+
+// An expression is just a generic function.  Its trait bounds play the same role as the signature
+// of an open-sum expression type: they spell out which kinds of term the expression might contain.
+// For instance, here's (80 * 5) + 4:
+//
+//     fn example<S>(s: &S) -> S::Repr
+//     where
+//         S: IntegerLiteralSym + AddSym + MultiplySym,
+//     {
+//         s.add(s.multiply(s.integer_literal(80), s.integer_literal(5)), s.integer_literal(4))
+//     }
+
+/// Our first interpreter evaluates expressions.  It has exactly the same bounds on the value type
+/// as the evaluation rules from ch07b and ch08b.
+pub struct Evaluate<V>(PhantomData<V>);
+
+impl<V> Evaluate<V> {
+    pub fn new() -> Evaluate<V> {
+        Evaluate(PhantomData)
+    }
+}
+
+impl<V> Default for Evaluate<V> {
+    fn default() -> Evaluate<V> {
+        Evaluate::new()
+    }
+}
+
+impl<V> Symantics for Evaluate<V> {
+    type Repr = V;
+}
+
+impl<V> IntegerLiteralSym for Evaluate<V>
+where
+    V: From<i64>,
+{
+    fn integer_literal(&self, value: i64) -> V {
+        V::from(value)
+    }
+}
+
+impl<V> AddSym for Evaluate<V>
+where
+    V: std::ops::Add<Output = V>,
+{
+    fn add(&self, lhs: V, rhs: V) -> V {
+        lhs + rhs
+    }
+}
+
+impl<V> MultiplySym for Evaluate<V>
+where
+    V: std::ops::Mul<Output = V>,
+{
+    fn multiply(&self, lhs: V, rhs: V) -> V {
+        lhs * rhs
+    }
+}
+
+impl<V> PairSym for Evaluate<V>
+where
+    V: From<(V, V)> + ProjectPair,
+{
+    fn pair(&self, first: V, second: V) -> V {
+        V::from((first, second))
+    }
+    fn first(&self, pair: V) -> V {
+        pair.first()
+    }
+    fn second(&self, pair: V) -> V {
+        pair.second()
+    }
+}
+
+/// A second interpreter renders expressions the same way as the `Display` impls from ch05b.  Note
+/// that it doesn't implement `PairSym`, and so you'll get a compile error if you try to render an
+/// expression that might contain pairs.
+pub struct Render;
+
+impl Symantics for Render {
+    type Repr = String;
+}
+
+impl IntegerLiteralSym for Render {
+    fn integer_literal(&self, value: i64) -> String {
+        value.to_string()
+    }
+}
+
+impl AddSym for Render {
+    fn add(&self, lhs: String, rhs: String) -> String {
+        format!("({} + {})", lhs, rhs)
+    }
+}
+
+impl MultiplySym for Render {
+    fn multiply(&self, lhs: String, rhs: String) -> String {
+        format!("({} * {})", lhs, rhs)
+    }
+}
+
+// Now let's build some bridges to the open-sum world.  Going from tagless-final to open-sum is
+// easy: it's just another interpreter, whose representation is an open-sum expression type, and
+// whose methods call the corresponding smart constructors.
+
+/// An interpreter that builds an open-sum expression of type `E`.
+pub struct Build<E>(PhantomData<E>);
+
+impl<E> Build<E> {
+    pub fn new() -> Build<E> {
+        Build(PhantomData)
+    }
+}
+
+impl<E> Default for Build<E> {
+    fn default() -> Build<E> {
+        Build::new()
+    }
+}
+
+impl<E> Symantics for Build<E> {
+    type Repr = E;
+}
+
+impl<E> IntegerLiteralSym for Build<E>
+where
+    E: From<IntegerLiteral>,
+{
+    fn integer_literal(&self, value: i64) -> E {
+        E::from(IntegerLiteral { value })
+    }
+}
+
+impl<E> AddSym for Build<E>
+where
+    E: From<Add<E>>,
+{
+    fn add(&self, lhs: E, rhs: E) -> E {
+        E::from(Add { lhs, rhs })
+    }
+}
+
+impl<E> MultiplySym for Build<E>
+where
+    E: From<Multiply<E>>,
+{
+    fn multiply(&self, lhs: E, rhs: E) -> E {
+        E::from(Multiply { lhs, rhs })
+    }
+}
+
+impl<E> PairSym for Build<E>
+where
+    E: From<Pair<E>> + From<First<E>> + From<Second<E>>,
+{
+    fn pair(&self, first: E, second: E) -> E {
+        E::from(Pair { first, second })
+    }
+    fn first(&self, pair: E) -> E {
+        E::from(First { pair })
+    }
+    fn second(&self, pair: E) -> E {
+        E::from(Second { pair })
+    }
+}
+
+// Going the other direction, we need to replay an open-sum expression into any tagless-final
+// interpreter.  That's a fold, and so we use the same open-recursion trick as in ch08b: each term
+// gets a `Reflect` impl, which is handed the function to use to reflect its subexpressions.
+
+/// Replays a term into the tagless-final interpreter `S`.
+pub trait Reflect<S: Symantics, E> {
+    fn reflect<F>(&self, sym: &S, reflect_subexpr: F) -> S::Repr
+    where
+        F: FnMut(&E) -> S::Repr;
+}
+
+impl<S, E> Reflect<S, E> for IntegerLiteral
+where
+    S: IntegerLiteralSym,
+{
+    fn reflect<F>(&self, sym: &S, _reflect_subexpr: F) -> S::Repr
+    where
+        F: FnMut(&E) -> S::Repr,
+    {
+        sym.integer_literal(self.value)
+    }
+}
+
+impl<S, E> Reflect<S, E> for Add<E>
+where
+    S: AddSym,
+{
+    fn reflect<F>(&self, sym: &S, mut reflect_subexpr: F) -> S::Repr
+    where
+        F: FnMut(&E) -> S::Repr,
+    {
+        let lhs = reflect_subexpr(&self.lhs);
+        let rhs = reflect_subexpr(&self.rhs);
+        sym.add(lhs, rhs)
+    }
+}
+
+impl<S, E> Reflect<S, E> for Multiply<E>
+where
+    S: MultiplySym,
+{
+    fn reflect<F>(&self, sym: &S, mut reflect_subexpr: F) -> S::Repr
+    where
+        F: FnMut(&E) -> S::Repr,
+    {
+        let lhs = reflect_subexpr(&self.lhs);
+        let rhs = reflect_subexpr(&self.rhs);
+        sym.multiply(lhs, rhs)
+    }
+}
+
+impl<S, E> Reflect<S, E> for Pair<E>
+where
+    S: PairSym,
+{
+    fn reflect<F>(&self, sym: &S, mut reflect_subexpr: F) -> S::Repr
+    where
+        F: FnMut(&E) -> S::Repr,
+    {
+        let first = reflect_subexpr(&self.first);
+        let second = reflect_subexpr(&self.second);
+        sym.pair(first, second)
+    }
+}
+
+impl<S, E> Reflect<S, E> for First<E>
+where
+    S: PairSym,
+{
+    fn reflect<F>(&self, sym: &S, mut reflect_subexpr: F) -> S::Repr
+    where
+        F: FnMut(&E) -> S::Repr,
+    {
+        sym.first(reflect_subexpr(&self.pair))
+    }
+}
+
+impl<S, E> Reflect<S, E> for Second<E>
+where
+    S: PairSym,
+{
+    fn reflect<F>(&self, sym: &S, mut reflect_subexpr: F) -> S::Repr
+    where
+        F: FnMut(&E) -> S::Repr,
+    {
+        sym.second(reflect_subexpr(&self.pair))
+    }
+}
+
+impl<S, E, L, R> Reflect<S, E> for Sum<L, R>
+where
+    S: Symantics,
+    L: Reflect<S, E>,
+    R: Reflect<S, E>,
+{
+    fn reflect<F>(&self, sym: &S, reflect_subexpr: F) -> S::Repr
+    where
+        F: FnMut(&E) -> S::Repr,
+    {
+        match self {
+            Sum::Left(lhs) => lhs.reflect(sym, reflect_subexpr),
+            Sum::Right(rhs) => rhs.reflect(sym, reflect_subexpr),
+        }
+    }
+}
+
+/// Replays any open-sum expression into a tagless-final interpreter.
+pub fn reflect<S, E>(sym: &S, expr: &E) -> S::Repr
+where
+    S: Symantics,
+    E: Expression,
+    E::Signature: Reflect<S, E>,
+{
+    expr.unwrap().reflect(sym, |subexpr| reflect(sym, subexpr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn ugly<S>(s: &S) -> S::Repr
+    where
+        S: IntegerLiteralSym + AddSym,
+    {
+        // 118 + 1219
+        s.add(s.integer_literal(118), s.integer_literal(1219))
+    }
+
+    fn multiplication<S>(s: &S) -> S::Repr
+    where
+        S: IntegerLiteralSym + AddSym + MultiplySym,
+    {
+        // (80 * 5) + 4
+        s.add(
+            s.multiply(s.integer_literal(80), s.integer_literal(5)),
+            s.integer_literal(4),
+        )
+    }
+
+    fn pair_projection<S>(s: &S) -> S::Repr
+    where
+        S: IntegerLiteralSym + PairSym,
+    {
+        s.first(s.pair(s.integer_literal(7), s.integer_literal(6)))
+    }
+
+    #[test]
+    fn can_evaluate_tagless_expressions() {
+        assert_eq!(ugly(&Evaluate::<i64>::new()), 1337);
+        assert_eq!(multiplication(&Evaluate::<i64>::new()), 404);
+        assert_eq!(
+            pair_projection(&Evaluate::<IntOrPair>::new()),
+            IntOrPair::Int(7)
+        );
+    }
+
+    #[test]
+    fn can_render_tagless_expressions() {
+        assert_eq!(ugly(&Render), "(118 + 1219)");
+        assert_eq!(multiplication(&Render), "((80 * 5) + 4)");
+    }
+
+    #[test]
+    fn can_build_open_sum_expressions() {
+        let add: Expr = ugly(&Build::new());
+        assert_eq!(reflect(&Evaluate::<i64>::new(), &add), 1337);
+        let mult: MultExpr = multiplication(&Build::new());
+        assert_eq!(reflect(&Render, &mult), "((80 * 5) + 4)");
+        let pair: PairExpr = pair_projection(&Build::new());
+        assert_eq!(
+            reflect(&Evaluate::<IntOrPair>::new(), &pair),
+            IntOrPair::Int(7)
+        );
+    }
+
+    #[test]
+    fn can_reflect_open_sum_expressions() {
+        let mult: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(reflect(&Evaluate::<i64>::new(), &mult), 404);
+        assert_eq!(reflect(&Render, &mult), "((80 * 5) + 4)");
+        // And we can round-trip through the tagless-final world back into a different open-sum
+        // type, as long as its signature has all of the terms we need.
+        let no_add: NoAddExpr = multiply(integer_literal(6), integer_literal(7));
+        let round_tripped: MultExpr = reflect(&Build::new(), &no_add);
+        assert_eq!(reflect(&Render, &round_tripped), "(6 * 7)");
+    }
+}
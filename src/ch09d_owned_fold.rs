@@ -0,0 +1,374 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! ch09a's fold borrows the expression, and hands each algebra references to its subexpressions.
+//! That's exactly right for evaluation.  But for a transformation that rebuilds the tree, it means
+//! that every piece of the old tree that survives into the new one has to be copied (and our terms
+//! aren't even Clone).  If you don't need the original tree afterwards, you can give it to the
+//! fold instead, and each algebra gets its term — and its subexpressions — by value.
+//!
+//! The only new thing that we need from an expression is a way to take its signature by value,
+//! which is what `Expression::into_signature` is for.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::passes::Outcome;
+
+use std::cell::Cell;
+
+/// Like ch09a's Algebra, but the algebra owns the term, and `recurse` takes ownership of each
+/// subexpression.
+pub trait OwnedAlgebra<T, E, V> {
+    fn apply_owned<F>(&self, term: T, recurse: F) -> V
+    where
+        F: FnMut(E) -> V;
+}
+
+impl<A, E, V, L, R> OwnedAlgebra<Sum<L, R>, E, V> for A
+where
+    A: OwnedAlgebra<L, E, V> + OwnedAlgebra<R, E, V>,
+{
+    fn apply_owned<F>(&self, term: Sum<L, R>, recurse: F) -> V
+    where
+        F: FnMut(E) -> V,
+    {
+        match term {
+            Sum::Left(lhs) => self.apply_owned(lhs, recurse),
+            Sum::Right(rhs) => self.apply_owned(rhs, recurse),
+        }
+    }
+}
+
+/// Folds an expression, consuming it.
+pub fn into_fold<A, E, V>(algebra: &A, expr: E) -> V
+where
+    E: Expression,
+    A: OwnedAlgebra<E::Signature, E, V>,
+{
+    algebra.apply_owned(expr.into_signature(), |subexpr| into_fold(algebra, subexpr))
+}
+
+// Our first example moves an expression into a bigger language.  Literals are moved as-is, and
+// every other term is rebuilt around its (already converted) children.  Nothing gets copied.
+
+/// Moves an expression into a language whose signature has all of the same terms.
+pub struct Widen;
+
+impl<E, T> OwnedAlgebra<IntegerLiteral, E, T> for Widen
+where
+    T: From<IntegerLiteral>,
+{
+    fn apply_owned<F>(&self, term: IntegerLiteral, _recurse: F) -> T
+    where
+        F: FnMut(E) -> T,
+    {
+        T::from(term)
+    }
+}
+
+impl<E, T> OwnedAlgebra<Add<E>, E, T> for Widen
+where
+    T: From<Add<T>>,
+{
+    fn apply_owned<F>(&self, term: Add<E>, mut recurse: F) -> T
+    where
+        F: FnMut(E) -> T,
+    {
+        T::from(Add {
+            lhs: recurse(term.lhs),
+            rhs: recurse(term.rhs),
+        })
+    }
+}
+
+impl<E, T> OwnedAlgebra<Multiply<E>, E, T> for Widen
+where
+    T: From<Multiply<T>>,
+{
+    fn apply_owned<F>(&self, term: Multiply<E>, mut recurse: F) -> T
+    where
+        F: FnMut(E) -> T,
+    {
+        T::from(Multiply {
+            lhs: recurse(term.lhs),
+            rhs: recurse(term.rhs),
+        })
+    }
+}
+
+impl<E, T> OwnedAlgebra<Pair<E>, E, T> for Widen
+where
+    T: From<Pair<T>>,
+{
+    fn apply_owned<F>(&self, term: Pair<E>, mut recurse: F) -> T
+    where
+        F: FnMut(E) -> T,
+    {
+        T::from(Pair {
+            first: recurse(term.first),
+            second: recurse(term.second),
+        })
+    }
+}
+
+/// Moves an expression into a language whose signature has all of the same terms.
+pub fn widen<E, T>(expr: E) -> T
+where
+    E: Expression,
+    Widen: OwnedAlgebra<E::Signature, E, T>,
+{
+    into_fold(&Widen, expr)
+}
+
+// Our second example folds constants.  Any subtree that's made only of literals collapses into a
+// single literal; everything else is rebuilt, reusing the subtrees that didn't change.  The algebra
+// counts how many terms it folds away, so that we can tell the caller whether anything changed.
+
+/// The result of constant folding a subexpression: either a constant, or an expression that
+/// couldn't be folded away.
+pub enum Folded<E> {
+    Constant(i64),
+    Expr(E),
+}
+
+impl<E: From<IntegerLiteral>> Folded<E> {
+    pub fn into_expr(self) -> E {
+        match self {
+            Folded::Constant(value) => E::from(IntegerLiteral { value }),
+            Folded::Expr(expr) => expr,
+        }
+    }
+}
+
+/// Folds every subtree that only contains literals, producing a new expression of type `T`.
+#[derive(Default)]
+pub struct ConstantFold {
+    folds: Cell<usize>,
+}
+
+impl ConstantFold {
+    pub fn new() -> ConstantFold {
+        ConstantFold::default()
+    }
+
+    /// How many additions and multiplications have been folded away so far.
+    pub fn folds(&self) -> usize {
+        self.folds.get()
+    }
+
+    fn fold<T>(&self, value: i64) -> Folded<T> {
+        self.folds.set(self.folds.get() + 1);
+        Folded::Constant(value)
+    }
+}
+
+impl<E, T> OwnedAlgebra<IntegerLiteral, E, Folded<T>> for ConstantFold {
+    fn apply_owned<F>(&self, term: IntegerLiteral, _recurse: F) -> Folded<T>
+    where
+        F: FnMut(E) -> Folded<T>,
+    {
+        Folded::Constant(term.value)
+    }
+}
+
+impl<E, T> OwnedAlgebra<Add<E>, E, Folded<T>> for ConstantFold
+where
+    T: From<IntegerLiteral> + From<Add<T>>,
+{
+    fn apply_owned<F>(&self, term: Add<E>, mut recurse: F) -> Folded<T>
+    where
+        F: FnMut(E) -> Folded<T>,
+    {
+        let (lhs, rhs) = (recurse(term.lhs), recurse(term.rhs));
+        // Overflow is an evaluation error, so we leave an overflowing sum for the evaluator to
+        // report.
+        if let (Folded::Constant(lhs), Folded::Constant(rhs)) = (&lhs, &rhs) {
+            if let Some(sum) = lhs.checked_add(*rhs) {
+                return self.fold(sum);
+            }
+        }
+        Folded::Expr(T::from(Add {
+            lhs: lhs.into_expr(),
+            rhs: rhs.into_expr(),
+        }))
+    }
+}
+
+impl<E, T> OwnedAlgebra<Multiply<E>, E, Folded<T>> for ConstantFold
+where
+    T: From<IntegerLiteral> + From<Multiply<T>>,
+{
+    fn apply_owned<F>(&self, term: Multiply<E>, mut recurse: F) -> Folded<T>
+    where
+        F: FnMut(E) -> Folded<T>,
+    {
+        let (lhs, rhs) = (recurse(term.lhs), recurse(term.rhs));
+        if let (Folded::Constant(lhs), Folded::Constant(rhs)) = (&lhs, &rhs) {
+            if let Some(product) = lhs.checked_mul(*rhs) {
+                return self.fold(product);
+            }
+        }
+        Folded::Expr(T::from(Multiply {
+            lhs: lhs.into_expr(),
+            rhs: rhs.into_expr(),
+        }))
+    }
+}
+
+// Pairs can't be folded into a constant, but their contents can be.
+
+impl<E, T> OwnedAlgebra<Pair<E>, E, Folded<T>> for ConstantFold
+where
+    T: From<IntegerLiteral> + From<Pair<T>>,
+{
+    fn apply_owned<F>(&self, term: Pair<E>, mut recurse: F) -> Folded<T>
+    where
+        F: FnMut(E) -> Folded<T>,
+    {
+        Folded::Expr(T::from(Pair {
+            first: recurse(term.first).into_expr(),
+            second: recurse(term.second).into_expr(),
+        }))
+    }
+}
+
+impl<E, T> OwnedAlgebra<First<E>, E, Folded<T>> for ConstantFold
+where
+    T: From<IntegerLiteral> + From<First<T>>,
+{
+    fn apply_owned<F>(&self, term: First<E>, mut recurse: F) -> Folded<T>
+    where
+        F: FnMut(E) -> Folded<T>,
+    {
+        Folded::Expr(T::from(First {
+            pair: recurse(term.pair).into_expr(),
+        }))
+    }
+}
+
+impl<E, T> OwnedAlgebra<Second<E>, E, Folded<T>> for ConstantFold
+where
+    T: From<IntegerLiteral> + From<Second<T>>,
+{
+    fn apply_owned<F>(&self, term: Second<E>, mut recurse: F) -> Folded<T>
+    where
+        F: FnMut(E) -> Folded<T>,
+    {
+        Folded::Expr(T::from(Second {
+            pair: recurse(term.pair).into_expr(),
+        }))
+    }
+}
+
+/// Folds the constants in an expression, consuming it.  (If there was nothing to fold, the
+/// expression is still rebuilt, but you get told that it's `Unchanged`.)
+pub fn fold_constants<E>(expr: E) -> Outcome<E>
+where
+    E: Expression + From<IntegerLiteral>,
+    ConstantFold: OwnedAlgebra<E::Signature, E, Folded<E>>,
+{
+    let algebra = ConstantFold::new();
+    let folded = into_fold(&algebra, expr).into_expr();
+    if algebra.folds() > 0 {
+        Outcome::Changed(folded)
+    } else {
+        Outcome::Unchanged(folded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch07c_pair_evaluation::*;
+    use crate::ch08b_open_recursion_evaluation::*;
+
+    #[test]
+    fn can_widen_expressions() {
+        let expr: Expr = add(
+            integer_literal(30000),
+            add(integer_literal(1330), integer_literal(7)),
+        );
+        let widened: MultExpr = widen(expr);
+        assert_eq!(widened.evaluate::<i64>(), 31337);
+        let expr: Expr = add(integer_literal(1), integer_literal(2));
+        let widened: PairExpr = widen(expr);
+        assert_eq!(widened.evaluate::<IntOrPair>(), IntOrPair::Int(3));
+    }
+
+    #[test]
+    fn can_fold_constants() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let folded = fold_constants(expr);
+        assert!(folded.is_changed());
+        assert!(matches!(
+            folded.into_inner().unwrap(),
+            Sum::Right(Sum::Left(IntegerLiteral { value: 404 }))
+        ));
+    }
+
+    #[test]
+    fn keeps_subtrees_that_cannot_be_folded() {
+        // first(pair(1 + 2, 3 + 4)) + 5 -> first(pair(3, 7)) + 5
+        let expr: PairExpr = add(
+            first(pair(
+                add(integer_literal(1), integer_literal(2)),
+                add(integer_literal(3), integer_literal(4)),
+            )),
+            integer_literal(5),
+        );
+        let folded = fold_constants(expr).into_inner();
+        assert_eq!(folded.evaluate::<IntOrPair>(), IntOrPair::Int(8));
+        assert_eq!(
+            crate::dump::dump(&folded),
+            "expression-dump 1\n\
+             add\n\
+             \x20 first\n\
+             \x20   pair\n\
+             \x20     integer_literal 3\n\
+             \x20     integer_literal 7\n\
+             \x20 integer_literal 5\n"
+        );
+    }
+
+    #[test]
+    fn leaves_overflowing_subtrees_unfolded() {
+        // (i64::MAX + 1) * (2 * 3) -> (i64::MAX + 1) * 6
+        let expr: MultExpr = multiply(
+            add(integer_literal(i64::MAX), integer_literal(1)),
+            multiply(integer_literal(2), integer_literal(3)),
+        );
+        let folded = fold_constants(expr).into_inner();
+        assert_eq!(folded.to_string(), format!("(({} + 1) * 6)", i64::MAX));
+        let expr: MultExpr = multiply(integer_literal(i64::MIN), integer_literal(-1));
+        assert!(!fold_constants(expr).is_changed());
+    }
+
+    #[test]
+    fn reports_when_nothing_was_folded() {
+        let expr: PairExpr = first(pair(integer_literal(1), integer_literal(2)));
+        let folded = fold_constants(expr);
+        assert!(!folded.is_changed());
+        assert_eq!(
+            folded.into_inner().evaluate::<IntOrPair>(),
+            IntOrPair::Int(1)
+        );
+    }
+}
@@ -0,0 +1,391 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Way back in ch03, we noted that we don't have functors in Rust.  We don't have higher-kinded
+//! types, so we can't write Haskell's `Functor f` typeclass directly.  But we can get surprisingly
+//! close by naming both the "before" and "after" child types in the trait: `Add<A>` is a functor
+//! that can turn into an `Add<B>`, given a function from A to B.
+//!
+//! Once we have ownership of a term (thanks to ch09d's `into_signature`), `fmap` is easy.  The
+//! interesting part is `try_fmap`, whose function can fail: it stops at the first child that fails,
+//! without calling the function on the rest.  That's what you want for transformations like
+//! signature narrowing or type-directed lowering, where one bad child dooms the whole term.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
+use crate::ch07a_pairs::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::ch08a_expressions::*;
+use crate::ch08e_sugar::*;
+
+/// A term whose children have type `A`, which can be turned into the same kind of term whose
+/// children have type `B`.
+pub trait Functor<A, B> {
+    type Output;
+
+    fn fmap<F>(self, f: F) -> Self::Output
+    where
+        F: FnMut(A) -> B;
+
+    /// Like `fmap`, but stops at the first child for which `f` fails.  Children are visited in
+    /// order, so `f` is never called for any child after the one that failed.
+    fn try_fmap<F, Err>(self, f: F) -> Result<Self::Output, Err>
+    where
+        F: FnMut(A) -> Result<B, Err>;
+}
+
+/// Literals don't have any children, so there's nothing to map.
+impl<A, B> Functor<A, B> for IntegerLiteral {
+    type Output = IntegerLiteral;
+
+    fn fmap<F>(self, _f: F) -> IntegerLiteral
+    where
+        F: FnMut(A) -> B,
+    {
+        self
+    }
+
+    fn try_fmap<F, Err>(self, _f: F) -> Result<IntegerLiteral, Err>
+    where
+        F: FnMut(A) -> Result<B, Err>,
+    {
+        Ok(self)
+    }
+}
+
+// Every other term looks the same: call f on each child, in order.  The `?` in try_fmap is what
+// short-circuits.
+
+macro_rules! functor_terms {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<A, B> Functor<A, B> for $term<A> {
+                type Output = $term<B>;
+
+                fn fmap<F>(self, mut f: F) -> $term<B>
+                where
+                    F: FnMut(A) -> B,
+                {
+                    $term { $($field: f(self.$field)),+ }
+                }
+
+                fn try_fmap<F, Err>(self, mut f: F) -> Result<$term<B>, Err>
+                where
+                    F: FnMut(A) -> Result<B, Err>,
+                {
+                    Ok($term { $($field: f(self.$field)?),+ })
+                }
+            }
+        )+
+    };
+}
+
+functor_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Subtract { lhs, rhs },
+    Divide { lhs, rhs },
+    Modulo { lhs, rhs },
+    IntDiv { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+    Negate { value },
+);
+
+impl<A, B, L, R> Functor<A, B> for Sum<L, R>
+where
+    L: Functor<A, B>,
+    R: Functor<A, B>,
+{
+    type Output = Sum<L::Output, R::Output>;
+
+    fn fmap<F>(self, f: F) -> Self::Output
+    where
+        F: FnMut(A) -> B,
+    {
+        match self {
+            Sum::Left(lhs) => Sum::Left(lhs.fmap(f)),
+            Sum::Right(rhs) => Sum::Right(rhs.fmap(f)),
+        }
+    }
+
+    fn try_fmap<F, Err>(self, f: F) -> Result<Self::Output, Err>
+    where
+        F: FnMut(A) -> Result<B, Err>,
+    {
+        match self {
+            Sum::Left(lhs) => Ok(Sum::Left(lhs.try_fmap(f)?)),
+            Sum::Right(rhs) => Ok(Sum::Right(rhs.try_fmap(f)?)),
+        }
+    }
+}
+
+// With a functor, we can write the fold from the papers directly: map the fold over the children,
+// then hand the result to the algebra.  Unlike ch09a's Mendler algebras, this algebra gets to see
+// the values of its children, already computed, in place of the children themselves.  With
+// try_fmap, the algebra can fail too, and the first failure stops the whole fold.
+
+/// A fallible catamorphism.  `algebra` receives each term with its children replaced by their
+/// values.
+pub fn try_cata<E, V, Err, S>(
+    expr: E,
+    algebra: &mut dyn FnMut(S) -> Result<V, Err>,
+) -> Result<V, Err>
+where
+    E: Expression,
+    E::Signature: Functor<E, V, Output = S>,
+{
+    let term = (expr.into_signature()).try_fmap(|child| try_cata(child, &mut *algebra))?;
+    algebra(term)
+}
+
+/// An infallible catamorphism.
+pub fn cata<E, V, S>(expr: E, algebra: &mut dyn FnMut(S) -> V) -> V
+where
+    E: Expression,
+    E::Signature: Functor<E, V, Output = S>,
+{
+    let term = expr
+        .into_signature()
+        .fmap(|child| cata(child, &mut *algebra));
+    algebra(term)
+}
+
+// try_fmap hard-codes one particular effect: failure via Result.  Haskell generalizes this to
+// `traverse`, which works for any applicative.  We can't abstract over every applicative, but the
+// two we actually care about, Option and Result, can both be described in terms of Result, which
+// means we can build traverse for them out of try_fmap.
+
+/// An effect that a child transformation can have.  It's either a value of type `T`, or a reason
+/// to stop.
+pub trait Effect<T>: Sized {
+    type Error;
+    /// The same effect, wrapped around some other type.
+    type Rewrapped<U>;
+
+    fn into_result(self) -> Result<T, Self::Error>;
+    fn from_result<U>(result: Result<U, Self::Error>) -> Self::Rewrapped<U>;
+}
+
+impl<T> Effect<T> for Option<T> {
+    type Error = ();
+    type Rewrapped<U> = Option<U>;
+
+    fn into_result(self) -> Result<T, ()> {
+        self.ok_or(())
+    }
+
+    fn from_result<U>(result: Result<U, ()>) -> Option<U> {
+        result.ok()
+    }
+}
+
+impl<T, E> Effect<T> for Result<T, E> {
+    type Error = E;
+    type Rewrapped<U> = Result<U, E>;
+
+    fn into_result(self) -> Result<T, E> {
+        self
+    }
+
+    fn from_result<U>(result: Result<U, E>) -> Result<U, E> {
+        result
+    }
+}
+
+/// Runs an effectful function over each child of a term, collecting the results into the same
+/// effect wrapped around the whole term.  Every functor is traversable.
+pub trait Traverse<A, B>: Functor<A, B> + Sized {
+    fn traverse<M, F>(self, mut f: F) -> M::Rewrapped<Self::Output>
+    where
+        M: Effect<B>,
+        F: FnMut(A) -> M,
+    {
+        M::from_result(self.try_fmap(|child| f(child).into_result()))
+    }
+}
+
+impl<A, B, T> Traverse<A, B> for T where T: Functor<A, B> {}
+
+/// Turns a term whose children are effects into an effect wrapped around a term, e.g.
+/// `Add<Option<E>>` into `Option<Add<E>>`.
+pub fn sequence<T, B, M>(term: T) -> M::Rewrapped<T::Output>
+where
+    T: Functor<M, B>,
+    M: Effect<B>,
+{
+    term.traverse(|child| child)
+}
+
+// None of our terms have a variable number of children yet, but function calls, tuples, and
+// argument lists will.  Those terms will hold their children in a Vec, so a Vec is a functor too,
+// which is all they'll need to be traversable.
+
+impl<A, B> Functor<A, B> for Vec<A> {
+    type Output = Vec<B>;
+
+    fn fmap<F>(self, f: F) -> Vec<B>
+    where
+        F: FnMut(A) -> B,
+    {
+        self.into_iter().map(f).collect()
+    }
+
+    fn try_fmap<F, Err>(self, f: F) -> Result<Vec<B>, Err>
+    where
+        F: FnMut(A) -> Result<B, Err>,
+    {
+        self.into_iter().map(f).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+    use crate::ch08d_cross_family_conversion::*;
+
+    #[test]
+    fn can_map_children() {
+        let term = Add { lhs: 1, rhs: 2 }.fmap(|child| child * 10);
+        assert_eq!((term.lhs, term.rhs), (10, 20));
+    }
+
+    #[test]
+    fn try_fmap_short_circuits() {
+        let mut visited = Vec::new();
+        let result = Multiply { lhs: -1, rhs: 2 }.try_fmap(|child: i64| {
+            visited.push(child);
+            if child < 0 {
+                Err("negative")
+            } else {
+                Ok(child as u64)
+            }
+        });
+        assert_eq!(result.err(), Some("negative"));
+        assert_eq!(visited, vec![-1]);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Overflow;
+
+    fn checked_evaluate(expr: MultExpr) -> Result<i64, Overflow> {
+        try_cata(expr, &mut |term: MultSig<i64>| match term {
+            Sum::Left(Multiply { lhs, rhs }) => lhs.checked_mul(rhs).ok_or(Overflow),
+            Sum::Right(Sum::Left(IntegerLiteral { value })) => Ok(value),
+            Sum::Right(Sum::Right(Add { lhs, rhs })) => lhs.checked_add(rhs).ok_or(Overflow),
+        })
+    }
+
+    #[test]
+    fn can_fold_with_a_fallible_algebra() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(checked_evaluate(expr), Ok(404));
+        let expr: MultExpr = add(
+            multiply(integer_literal(i64::MAX), integer_literal(2)),
+            integer_literal(4),
+        );
+        assert_eq!(checked_evaluate(expr), Err(Overflow));
+    }
+
+    #[test]
+    fn can_fold_with_an_infallible_algebra() {
+        let expr: Expr = add(
+            integer_literal(1),
+            add(integer_literal(2), integer_literal(3)),
+        );
+        let size = cata(expr, &mut |term: Sig<usize>| match term {
+            Sum::Left(_) => 1,
+            Sum::Right(Add { lhs, rhs }) => 1 + lhs + rhs,
+        });
+        assert_eq!(size, 5);
+    }
+
+    #[test]
+    fn can_fold_sugar_without_desugaring_it() {
+        // 410 + -(2 * 3)
+        let expr = || -> NegateExpr {
+            add(
+                integer_literal(410),
+                negate(multiply(integer_literal(2), integer_literal(3))),
+            )
+        };
+        let value = cata(expr(), &mut |term: NegateSig<i64>| match term {
+            Sum::Left(Negate { value }) => -value,
+            Sum::Right(Sum::Left(Multiply { lhs, rhs })) => lhs * rhs,
+            Sum::Right(Sum::Right(Sum::Left(IntegerLiteral { value }))) => value,
+            Sum::Right(Sum::Right(Sum::Right(Add { lhs, rhs }))) => lhs + rhs,
+        });
+        let desugared: MultExpr = desugar(expr());
+        assert_eq!(value, 404);
+        assert_eq!(desugared.to_string(), "(410 + (-1 * (2 * 3)))");
+    }
+
+    #[test]
+    fn can_traverse_with_options() {
+        let term = Pair {
+            first: Some(1),
+            second: Some(2),
+        };
+        let term: Option<Pair<i64>> = sequence(term);
+        assert_eq!(term.map(|p| (p.first, p.second)), Some((1, 2)));
+        let term = Pair {
+            first: Some(1),
+            second: None,
+        };
+        let term: Option<Pair<i64>> = sequence(term);
+        assert!(term.is_none());
+    }
+
+    #[test]
+    fn can_traverse_any_number_of_children() {
+        let arguments = vec![1, 2, 3];
+        assert_eq!(arguments.clone().traverse(Some), Some(vec![1, 2, 3]));
+        let mut visited = 0;
+        let result = arguments.traverse(|child| {
+            visited += 1;
+            if child == 2 {
+                Err(child)
+            } else {
+                Ok(child)
+            }
+        });
+        assert_eq!(result, Err(2));
+        assert_eq!(visited, 2);
+    }
+
+    #[test]
+    fn can_narrow_children_with_traverse() {
+        let lhs: PairExpr = add(integer_literal(1), integer_literal(2));
+        let rhs: PairExpr = integer_literal(3);
+        let term: Result<Add<Expr>, ConvertError> =
+            Add { lhs, rhs }.traverse(|child: PairExpr| child.try_convert());
+        let expr = Expr::wrap(Sum::Right(term.unwrap()));
+        assert_eq!(expr.evaluate::<i64>(), 6);
+
+        let lhs: PairExpr = first(pair(integer_literal(1), integer_literal(2)));
+        let rhs: PairExpr = integer_literal(3);
+        let term: Result<Add<Expr>, ConvertError> =
+            Add { lhs, rhs }.traverse(|child: PairExpr| child.try_convert());
+        assert!(term.is_err());
+    }
+}
@@ -0,0 +1,144 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! ch09d's `fold_constants` shrinks an expression after the fact.  But if you're generating
+//! expressions programmatically, you might never want the unfolded version in the first place.
+//! This chapter has alternative smart constructors that fold as they go: `add_s` and `multiply_s`
+//! look at their children, and if both are literals, build a single literal instead.
+//!
+//! To look at a child, we need to know whether its outermost term is a literal.  A Mendler algebra
+//! can answer that without looking any further down the tree, because it's never forced to call
+//! `recurse`.  So a "fold" with this algebra only ever visits the root.
+
+use crate::ch02_open_sum::*;
+use crate::ch04_smart_constructors::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+
+/// An algebra that returns the value of an expression if it's a literal.  It never recurses.
+pub struct LiteralValue;
+
+impl<E> Algebra<IntegerLiteral, E, Option<i64>> for LiteralValue {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Option<i64>
+    where
+        F: FnMut(&E) -> Option<i64>,
+    {
+        Some(term.value)
+    }
+}
+
+macro_rules! not_literals {
+    ($($term:ident),+ $(,)?) => {
+        $(
+            impl<E> Algebra<$term<E>, E, Option<i64>> for LiteralValue {
+                fn apply<F>(&self, _term: &$term<E>, _recurse: F) -> Option<i64>
+                where
+                    F: FnMut(&E) -> Option<i64>,
+                {
+                    None
+                }
+            }
+        )+
+    };
+}
+
+not_literals!(Add, Multiply, Pair, First, Second);
+
+/// Returns the value of an expression if it's a literal.
+pub fn literal_value<E>(expr: &E) -> Option<i64>
+where
+    E: Expression,
+    LiteralValue: Algebra<E::Signature, E, Option<i64>>,
+{
+    mcata(&LiteralValue, expr)
+}
+
+// The simplifying constructors only fold when the result fits in an i64; otherwise they build the
+// term as usual, so that evaluating it reports the overflow just like it would have without them.
+
+/// Like `add`, but builds a single literal if both children are literals.
+pub fn add_s<E>(lhs: E, rhs: E) -> E
+where
+    E: Expression + From<IntegerLiteral> + From<Add<E>>,
+    LiteralValue: Algebra<E::Signature, E, Option<i64>>,
+{
+    match (literal_value(&lhs), literal_value(&rhs)) {
+        (Some(l), Some(r)) => match l.checked_add(r) {
+            Some(value) => integer_literal(value),
+            None => add(lhs, rhs),
+        },
+        _ => add(lhs, rhs),
+    }
+}
+
+/// Like `multiply`, but builds a single literal if both children are literals.
+pub fn multiply_s<E>(lhs: E, rhs: E) -> E
+where
+    E: Expression + From<IntegerLiteral> + From<Multiply<E>>,
+    LiteralValue: Algebra<E::Signature, E, Option<i64>>,
+{
+    match (literal_value(&lhs), literal_value(&rhs)) {
+        (Some(l), Some(r)) => match l.checked_mul(r) {
+            Some(value) => integer_literal(value),
+            None => multiply(lhs, rhs),
+        },
+        _ => multiply(lhs, rhs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch07c_pair_evaluation::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+
+    #[test]
+    fn folds_literals_eagerly() {
+        let expr: MultExpr = add_s(
+            multiply_s(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(literal_value(&expr), Some(404));
+    }
+
+    #[test]
+    fn leaves_everything_else_alone() {
+        let expr: PairExpr = add_s(
+            first(pair(integer_literal(1), integer_literal(2))),
+            add_s(integer_literal(3), integer_literal(4)),
+        );
+        assert_eq!(literal_value(&expr), None);
+        assert_eq!(mcata(&Depth, &expr), 4);
+        assert_eq!(expr.evaluate::<IntOrPair>(), IntOrPair::Int(8));
+    }
+
+    #[test]
+    fn doesnt_fold_overflow() {
+        let expr: MultExpr = multiply_s(integer_literal(i64::MAX), integer_literal(2));
+        assert_eq!(literal_value(&expr), None);
+        assert_eq!(mcata(&Depth, &expr), 2);
+    }
+
+    #[test]
+    fn generated_expressions_stay_small() {
+        // sum of i * i for i in 1..=100, built left to right
+        let expr: MultExpr = (1..=100)
+            .map(|i| multiply_s(integer_literal(i), integer_literal(i)))
+            .fold(integer_literal(0), add_s);
+        assert_eq!(literal_value(&expr), Some(338_350));
+    }
+}
@@ -0,0 +1,383 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A signature is a type-level list of terms, so it's natural to want to ask set-like questions
+//! about it.  Does this signature contain that term?  Is every term of this signature also in
+//! that one?  What's left of this signature after we take a term out of it?  We can answer all of
+//! these with the same trick that ch04 used for Inject: an extra "index" type parameter, which
+//! the compiler infers, that says where each term lives.
+//!
+//! While we're at it, we can make the compiler's complaints easier to read.  Forget to include a
+//! term in a signature, and the error you get from the smart constructors is about a missing
+//! `From` impl, or worse, about some `Inject` impl deep inside the signature.  Code that requires
+//! `InSignature` instead gets an error that says what actually went wrong.  That's only code that
+//! asks for it, like `build` and `outermost` below: the ch04 constructors still go through `From`,
+//! and still report their errors that way.
+
+use crate::ch02_open_sum::*;
+use crate::ch04_smart_constructors::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::ch08e_sugar::Negate;
+use crate::ch09e_functor::*;
+
+use std::marker::PhantomData;
+
+/// Membership: `Self` contains the term `T`, at index `I`.  Besides injecting a term into the
+/// signature (which Inject already did), you can also try to project it back out.
+pub trait Contains<T, I>: Inject<T, I> + Sized {
+    /// Returns the term, if this is one.
+    fn project(&self) -> Option<&T>;
+    /// Returns the term if this is one, and gives you back the original otherwise.
+    fn try_project(self) -> Result<T, Self>;
+}
+
+impl<L, R> Contains<L, Here> for Sum<L, R> {
+    fn project(&self) -> Option<&L> {
+        match self {
+            Sum::Left(left) => Some(left),
+            Sum::Right(_) => None,
+        }
+    }
+
+    fn try_project(self) -> Result<L, Self> {
+        match self {
+            Sum::Left(left) => Ok(left),
+            other => Err(other),
+        }
+    }
+}
+
+impl<L, R> Contains<R, Tail> for Sum<L, R> {
+    fn project(&self) -> Option<&R> {
+        match self {
+            Sum::Left(_) => None,
+            Sum::Right(right) => Some(right),
+        }
+    }
+
+    fn try_project(self) -> Result<R, Self> {
+        match self {
+            Sum::Right(right) => Ok(right),
+            other => Err(other),
+        }
+    }
+}
+
+impl<X, I, L, R> Contains<X, There<I>> for Sum<L, R>
+where
+    R: Contains<X, I>,
+{
+    fn project(&self) -> Option<&X> {
+        match self {
+            Sum::Left(_) => None,
+            Sum::Right(right) => right.project(),
+        }
+    }
+
+    fn try_project(self) -> Result<X, Self> {
+        match self {
+            Sum::Left(left) => Err(Sum::Left(left)),
+            Sum::Right(right) => right.try_project().map_err(Sum::Right),
+        }
+    }
+}
+
+// A subset check has to look at each term of Self, and a term might itself be a Sum.  We can't
+// write a blanket impl for "any single term", since Sums are types too, and a signature like
+// MultSig has Sig as its tail: Sig could be embedded in it as one term, or term by term, and the
+// compiler would refuse to pick.  So, like ch08d's Narrow, each kind of term gets its own impl,
+// which the subset_terms! macro writes.
+
+/// The index of a single term that lives in a signature at index `I`.
+pub struct Leaf<I>(PhantomData<I>);
+
+/// The indexes of both sides of a sum.
+pub struct Both<L, R>(PhantomData<(L, R)>);
+
+/// Inclusion: every term of `Self` is also a term of `S`.  `I` describes where each one lives.
+pub trait Subset<S, I> {
+    /// Moves a value into the larger signature.
+    fn embed(self) -> S;
+}
+
+/// Implements `Subset` for term types, so that they can appear at the leaves of a signature.
+#[macro_export]
+macro_rules! subset_terms {
+    ($($term:ident $(<$E:ident>)?),+ $(,)?) => {
+        $(
+            impl<S, I $(, $E)?> $crate::ch09g_signature_sets::Subset<
+                S,
+                $crate::ch09g_signature_sets::Leaf<I>,
+            > for $term $(<$E>)?
+            where
+                S: $crate::ch09g_signature_sets::Contains<Self, I>,
+            {
+                fn embed(self) -> S {
+                    S::inject(self)
+                }
+            }
+        )+
+    };
+}
+
+subset_terms!(
+    IntegerLiteral,
+    Add<E>,
+    Multiply<E>,
+    Pair<E>,
+    First<E>,
+    Second<E>,
+    Negate<E>,
+);
+
+impl<L, R, S, IL, IR> Subset<S, Both<IL, IR>> for Sum<L, R>
+where
+    L: Subset<S, IL>,
+    R: Subset<S, IR>,
+{
+    fn embed(self) -> S {
+        match self {
+            Sum::Left(left) => left.embed(),
+            Sum::Right(right) => right.embed(),
+        }
+    }
+}
+
+/// Difference: `Self` with the term `T` (at index `I`) taken out.
+pub trait Remove<T, I> {
+    type Rest;
+    /// Splits a value into either the removed term, or one of the remaining ones.
+    fn remove(self) -> Result<T, Self::Rest>;
+}
+
+impl<L, R> Remove<L, Here> for Sum<L, R> {
+    type Rest = R;
+    fn remove(self) -> Result<L, R> {
+        match self {
+            Sum::Left(left) => Ok(left),
+            Sum::Right(right) => Err(right),
+        }
+    }
+}
+
+impl<L, R> Remove<R, Tail> for Sum<L, R> {
+    type Rest = L;
+    fn remove(self) -> Result<R, L> {
+        match self {
+            Sum::Left(left) => Err(left),
+            Sum::Right(right) => Ok(right),
+        }
+    }
+}
+
+impl<X, I, L, R> Remove<X, There<I>> for Sum<L, R>
+where
+    R: Remove<X, I>,
+{
+    type Rest = Sum<L, R::Rest>;
+    fn remove(self) -> Result<X, Sum<L, R::Rest>> {
+        match self {
+            Sum::Left(left) => Err(Sum::Left(left)),
+            Sum::Right(right) => right.remove().map_err(Sum::Right),
+        }
+    }
+}
+
+// When the compiler can't find a term, it reports the innermost bound that failed — which, for
+// Inject and Contains, is some suffix of the signature, not the whole thing.  So Locate carries
+// the whole signature along in an extra type parameter, purely so that the error message can
+// mention it.
+
+/// Finds where `T` lives in `Self`, which is (a suffix of) the signature `S`.
+#[diagnostic::on_unimplemented(
+    message = "the signature `{S}` does not contain `{T}`",
+    label = "`{T}` is not one of this signature's terms",
+    note = "add `{T}` to the signature, or use an expression type whose signature already has it"
+)]
+pub trait Locate<T, I, S> {
+    fn locate(term: T) -> Self;
+}
+
+impl<L, R, S> Locate<L, Here, S> for Sum<L, R> {
+    fn locate(term: L) -> Sum<L, R> {
+        Sum::Left(term)
+    }
+}
+
+impl<L, R, S> Locate<R, Tail, S> for Sum<L, R> {
+    fn locate(term: R) -> Sum<L, R> {
+        Sum::Right(term)
+    }
+}
+
+impl<X, I, L, R, S> Locate<X, There<I>, S> for Sum<L, R>
+where
+    R: Locate<X, I, S>,
+{
+    fn locate(term: X) -> Sum<L, R> {
+        Sum::Right(R::locate(term))
+    }
+}
+
+/// Membership, for use in bounds: the signature `Self` contains the term `T`.
+pub trait InSignature<T, I>: Sized {
+    fn inject_term(term: T) -> Self;
+}
+
+impl<S, T, I> InSignature<T, I> for S
+where
+    S: Locate<T, I, S>,
+{
+    fn inject_term(term: T) -> S {
+        S::locate(term)
+    }
+}
+
+/// A smart constructor for any term.  Unlike the ch04 constructors, which need a `From` impl, this
+/// works for every term of every expression type's signature, and if the term isn't there, the
+/// compiler tells you so.  `MultExpr` doesn't have pairs, so this doesn't compile:
+///
+/// ```compile_fail,E0277
+/// use expression_problem::ch02_open_sum::*;
+/// use expression_problem::ch05a_multiplication::*;
+/// use expression_problem::ch07a_pairs::*;
+/// use expression_problem::ch09g_signature_sets::*;
+///
+/// let first: MultExpr = build(IntegerLiteral { value: 1 });
+/// let second: MultExpr = build(IntegerLiteral { value: 2 });
+/// let _: MultExpr = build(Pair { first, second });
+/// ```
+///
+/// and the error is
+///
+/// ```text
+/// error[E0277]: the signature `Sum<Multiply<MultExpr>, Sum<IntegerLiteral, Add<MultExpr>>>`
+///               does not contain `Pair<MultExpr>`
+/// ```
+pub fn build<E, T, I>(term: T) -> E
+where
+    E: Expression,
+    E::Signature: InSignature<T, I>,
+{
+    E::wrap(E::Signature::inject_term(term))
+}
+
+/// Returns the outermost term of an expression if it's a `T`.  Evaluators that need to look for
+/// a particular term can use this, and get the same error as `build` if the term can't be there.
+pub fn outermost<T, E, I>(expr: &E) -> Option<&T>
+where
+    E: Expression,
+    E::Signature: InSignature<T, I> + Contains<T, I>,
+{
+    expr.unwrap().project()
+}
+
+// Rust can't compute the union of two signatures as a type, since that would mean checking
+// whether two types are equal.  But we don't need the type, just a way to say "U contains both A
+// and B", which is two Subset bounds.
+
+/// Moves a value of either signature into a signature that contains both of them.
+pub fn union<A, B, U, IA, IB>(value: Sum<A, B>) -> U
+where
+    A: Subset<U, IA>,
+    B: Subset<U, IB>,
+{
+    match value {
+        Sum::Left(a) => a.embed(),
+        Sum::Right(b) => b.embed(),
+    }
+}
+
+// With Subset (and ch09e's fmap to convert the children), widening an expression into any
+// expression type whose signature is a superset only needs a single function, with no per-term
+// impls at all.
+
+/// Converts an expression into any expression type whose signature contains all of its terms.
+pub fn widen_into<E, F, S, I>(expr: E) -> F
+where
+    E: Expression,
+    F: Expression,
+    E::Signature: Functor<E, F, Output = S>,
+    S: Subset<F::Signature, I>,
+{
+    F::wrap(expr.into_signature().fmap(widen_into::<E, F, S, I>).embed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch07c_pair_evaluation::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+
+    #[test]
+    fn can_project_terms() {
+        let sig: MultSig<()> = Sum::Right(Sum::Left(IntegerLiteral { value: 404 }));
+        let literal: Option<&IntegerLiteral> = sig.project();
+        assert_eq!(literal.map(|l| l.value), Some(404));
+        let product: Option<&Multiply<()>> = sig.project();
+        assert!(product.is_none());
+        let add: Result<Add<()>, _> = sig.try_project();
+        assert!(add.is_err());
+    }
+
+    #[test]
+    fn can_remove_terms() {
+        let sig: MultSig<i64> = Sum::Left(Multiply { lhs: 80, rhs: 5 });
+        let rest: Result<Multiply<i64>, Sig<i64>> = sig.remove();
+        assert_eq!(rest.ok().map(|m| m.lhs * m.rhs), Some(400));
+        let sig: MultSig<i64> = Sum::Right(Sum::Right(Add { lhs: 400, rhs: 4 }));
+        let rest: Result<Add<i64>, Sum<Multiply<i64>, IntegerLiteral>> = sig.remove();
+        assert!(rest.is_ok());
+    }
+
+    #[test]
+    fn can_embed_subsets() {
+        let sig: Sig<i64> = Sum::Right(Add { lhs: 400, rhs: 4 });
+        let embedded: MultSig<i64> = sig.embed();
+        assert!(Contains::<Add<i64>, _>::project(&embedded).is_some());
+    }
+
+    #[test]
+    fn can_take_unions() {
+        let value: Sum<Multiply<i64>, Pair<i64>> = Sum::Right(Pair {
+            first: 1,
+            second: 2,
+        });
+        let sig: Sum![Pair<i64>, Add<i64>, Multiply<i64>] = union(value);
+        assert!(Contains::<Pair<i64>, _>::project(&sig).is_some());
+    }
+
+    #[test]
+    fn can_widen_expressions() {
+        let expr: Expr = add(integer_literal(400), integer_literal(4));
+        let widened: MultExpr = widen_into(expr);
+        assert_eq!(widened.evaluate::<i64>(), 404);
+        let expr: Expr = add(integer_literal(400), integer_literal(4));
+        let widened: PairExpr = widen_into(expr);
+        assert_eq!(widened.evaluate::<IntOrPair>(), IntOrPair::Int(404));
+    }
+
+    #[test]
+    fn can_build_any_term() {
+        let lhs: MultExpr = build(IntegerLiteral { value: 80 });
+        let rhs: MultExpr = build(IntegerLiteral { value: 5 });
+        let expr: MultExpr = build(Multiply { lhs, rhs });
+        assert_eq!(expr.evaluate::<i64>(), 400);
+        assert!(outermost::<Multiply<MultExpr>, _, _>(&expr).is_some());
+        assert!(outermost::<Add<MultExpr>, _, _>(&expr).is_none());
+    }
+}
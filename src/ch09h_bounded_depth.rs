@@ -0,0 +1,284 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! This chapter is an experiment.  Everything else in the crate puts each node of an expression in
+//! its own Box, which is what lets expressions be arbitrarily deep — and which is why deep ones
+//! are trouble (see the limits and trampoline modules).  What if we went the other way, and had
+//! the type system rule out deep expressions entirely?  Then we wouldn't need any boxes: a
+//! `BoundedExpr<D>`, which is at most `D` levels deep, can hold its children inline, and can be
+//! built and evaluated without touching the heap.
+//!
+//! The catch is that the children of a `BoundedExpr<D>` need to be `BoundedExpr<{D - 1}>`, and
+//! stable Rust doesn't let us do arithmetic on const generics in types.  So we spell out the
+//! relationship one depth at a time, with a `Bound` trait that maps each depth to the type of its
+//! children.  Depth 1 maps to an uninhabited type, so that the only depth-1 expressions are
+//! literals.  Trying to put a `BoundedExpr<3>` inside of another `BoundedExpr<3>` is a type error,
+//! so there's no way to build an expression that's deeper than its type says.
+//!
+//! Holding children inline doubles the size of the type at each level, so we only go up to
+//! `MAX_DEPTH`.  That's plenty for the small expressions (config values, filter predicates) that
+//! this is meant for.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+
+use std::fmt;
+
+/// The deepest `BoundedExpr` that we define.
+pub const MAX_DEPTH: usize = 8;
+
+/// The type of child that a depth-1 expression has, which is to say, none.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoChildren {}
+
+/// Marks a depth, so that we can implement `Bound` for it.
+pub struct AtMost<const D: usize>;
+
+/// Maps a depth to the type of the children of an expression with that depth.
+pub trait Bound {
+    type Child: BoundedTerm + Copy + fmt::Debug + fmt::Display + PartialEq;
+}
+
+impl Bound for AtMost<1> {
+    type Child = NoChildren;
+}
+
+macro_rules! bounds {
+    ($($depth:literal => $child:literal),*) => {
+        $(
+            impl Bound for AtMost<$depth> {
+                type Child = BoundedExpr<$child>;
+            }
+        )*
+    };
+}
+
+bounds!(2 => 1, 3 => 2, 4 => 3, 5 => 4, 6 => 5, 7 => 6, 8 => 7);
+
+/// An expression that is at most `D` levels deep, whose children are stored inline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundedExpr<const D: usize>
+where
+    AtMost<D>: Bound,
+{
+    Literal(i64),
+    Add(<AtMost<D> as Bound>::Child, <AtMost<D> as Bound>::Child),
+    Multiply(<AtMost<D> as Bound>::Child, <AtMost<D> as Bound>::Child),
+}
+
+impl<const D: usize> BoundedExpr<D>
+where
+    AtMost<D>: Bound,
+{
+    /// A literal fits at any depth.
+    pub fn literal(value: i64) -> BoundedExpr<D> {
+        BoundedExpr::Literal(value)
+    }
+
+    pub fn add(
+        lhs: <AtMost<D> as Bound>::Child,
+        rhs: <AtMost<D> as Bound>::Child,
+    ) -> BoundedExpr<D> {
+        BoundedExpr::Add(lhs, rhs)
+    }
+
+    pub fn multiply(
+        lhs: <AtMost<D> as Bound>::Child,
+        rhs: <AtMost<D> as Bound>::Child,
+    ) -> BoundedExpr<D> {
+        BoundedExpr::Multiply(lhs, rhs)
+    }
+}
+
+/// Evaluating a bounded expression overflowed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Overflow;
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "arithmetic overflow")
+    }
+}
+
+impl std::error::Error for Overflow {}
+
+/// The operations that we support on bounded expressions.  These recurse, but never more than
+/// `MAX_DEPTH` levels.
+pub trait BoundedTerm: Sized {
+    /// Evaluates this expression.  Bounded expressions are meant for small inputs from outside
+    /// (config values, filter predicates), so this reports overflow instead of panicking.
+    fn evaluate(&self) -> Result<i64, Overflow>;
+
+    /// How deep this expression actually is, which might be less than its bound.
+    fn depth(&self) -> usize;
+
+    /// Converts this expression into a boxed, open-sum expression.
+    fn to_expr<E>(&self) -> E
+    where
+        E: From<IntegerLiteral> + From<Add<E>> + From<Multiply<E>>;
+
+    /// Converts a boxed expression into a bounded one, if it isn't too deep to fit.
+    fn from_expr(expr: &MultExpr) -> Option<Self>;
+}
+
+impl BoundedTerm for NoChildren {
+    fn evaluate(&self) -> Result<i64, Overflow> {
+        match *self {}
+    }
+
+    fn depth(&self) -> usize {
+        match *self {}
+    }
+
+    fn to_expr<E>(&self) -> E
+    where
+        E: From<IntegerLiteral> + From<Add<E>> + From<Multiply<E>>,
+    {
+        match *self {}
+    }
+
+    fn from_expr(_expr: &MultExpr) -> Option<NoChildren> {
+        None
+    }
+}
+
+impl fmt::Display for NoChildren {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl<const D: usize> BoundedTerm for BoundedExpr<D>
+where
+    AtMost<D>: Bound,
+{
+    fn evaluate(&self) -> Result<i64, Overflow> {
+        match self {
+            BoundedExpr::Literal(value) => Ok(*value),
+            BoundedExpr::Add(lhs, rhs) => (lhs.evaluate()?)
+                .checked_add(rhs.evaluate()?)
+                .ok_or(Overflow),
+            BoundedExpr::Multiply(lhs, rhs) => (lhs.evaluate()?)
+                .checked_mul(rhs.evaluate()?)
+                .ok_or(Overflow),
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            BoundedExpr::Literal(_) => 1,
+            BoundedExpr::Add(lhs, rhs) | BoundedExpr::Multiply(lhs, rhs) => {
+                1 + lhs.depth().max(rhs.depth())
+            }
+        }
+    }
+
+    fn to_expr<E>(&self) -> E
+    where
+        E: From<IntegerLiteral> + From<Add<E>> + From<Multiply<E>>,
+    {
+        match self {
+            BoundedExpr::Literal(value) => E::from(IntegerLiteral { value: *value }),
+            BoundedExpr::Add(lhs, rhs) => E::from(Add {
+                lhs: lhs.to_expr(),
+                rhs: rhs.to_expr(),
+            }),
+            BoundedExpr::Multiply(lhs, rhs) => E::from(Multiply {
+                lhs: lhs.to_expr(),
+                rhs: rhs.to_expr(),
+            }),
+        }
+    }
+
+    fn from_expr(expr: &MultExpr) -> Option<BoundedExpr<D>> {
+        let child = <AtMost<D> as Bound>::Child::from_expr;
+        Some(match &*expr.0 {
+            Sum::Left(Multiply { lhs, rhs }) => BoundedExpr::Multiply(child(lhs)?, child(rhs)?),
+            Sum::Right(Sum::Left(IntegerLiteral { value })) => BoundedExpr::Literal(*value),
+            Sum::Right(Sum::Right(Add { lhs, rhs })) => BoundedExpr::Add(child(lhs)?, child(rhs)?),
+        })
+    }
+}
+
+impl<const D: usize> fmt::Display for BoundedExpr<D>
+where
+    AtMost<D>: Bound,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BoundedExpr::Literal(value) => write!(f, "{}", value),
+            BoundedExpr::Add(lhs, rhs) => write!(f, "({} + {})", lhs, rhs),
+            BoundedExpr::Multiply(lhs, rhs) => write!(f, "({} * {})", lhs, rhs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    // (80 * 5) + 4
+    fn example() -> BoundedExpr<3> {
+        BoundedExpr::add(
+            BoundedExpr::multiply(BoundedExpr::literal(80), BoundedExpr::literal(5)),
+            BoundedExpr::literal(4),
+        )
+    }
+
+    #[test]
+    fn can_evaluate_bounded_expressions() {
+        let expr = example();
+        assert_eq!(expr.evaluate(), Ok(404));
+        assert_eq!(expr.depth(), 3);
+        let shallow: BoundedExpr<3> = BoundedExpr::literal(7);
+        assert_eq!(shallow.depth(), 1);
+    }
+
+    #[test]
+    fn reports_overflow() {
+        let expr: BoundedExpr<3> = BoundedExpr::add(
+            BoundedExpr::literal(1),
+            BoundedExpr::multiply(BoundedExpr::literal(i64::MAX), BoundedExpr::literal(2)),
+        );
+        assert_eq!(expr.evaluate(), Err(Overflow));
+        let expr: BoundedExpr<2> =
+            BoundedExpr::add(BoundedExpr::literal(i64::MAX), BoundedExpr::literal(1));
+        assert_eq!(expr.evaluate(), Err(Overflow));
+    }
+
+    #[test]
+    fn can_convert_to_open_sums() {
+        let expr: MultExpr = example().to_expr();
+        assert_eq!(expr.to_string(), "((80 * 5) + 4)");
+    }
+
+    #[test]
+    fn can_convert_from_open_sums() {
+        let expr: MultExpr = example().to_expr();
+        assert_eq!(BoundedExpr::<3>::from_expr(&expr), Some(example()));
+        assert_eq!(BoundedExpr::<2>::from_expr(&expr), None);
+        assert_eq!(example().to_string(), "((80 * 5) + 4)");
+    }
+
+    #[test]
+    fn bounded_expressions_live_on_the_stack() {
+        // A depth-1 expression can only be a literal, so it doesn't even need a tag.  Each level
+        // above that holds two copies of the level below it, plus a tag.
+        assert_eq!(size_of::<BoundedExpr<1>>(), size_of::<i64>());
+        assert!(size_of::<BoundedExpr<3>>() <= 128);
+        assert!(size_of::<BoundedExpr<MAX_DEPTH>>() <= 8192);
+    }
+}
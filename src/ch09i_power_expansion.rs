@@ -0,0 +1,298 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A code generator usually doesn't have an exponent instruction, so before we hand it an
+//! expression, we want to lower every `Power` into multiplications.  The obvious lowering, `x * x *
+//! x * x`, takes n - 1 multiplications.  We can do better by squaring: `x⁴` is `(x * x) * (x *
+//! x)`, and the two copies of `x * x` are the same value, so we only need to compute it once.
+//! Repeated squaring gets any exponent down to about 2 log₂ n multiplications.
+//!
+//! The catch is that "compute it once" needs sharing, which our boxed expression trees can't
+//! express: each copy of `x * x` would be a separate subtree, and the tree would be just as big as
+//! the naive one.  So the lowering writes its output into an `Arena`, where a node can be the child
+//! of several parents.  That's also the form that a code generator wants anyway, since each arena
+//! node is one instruction.
+
+use crate::arena::*;
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::Eval;
+use crate::ch09a_mendler::*;
+
+use std::cell::RefCell;
+use std::fmt;
+
+/// Raises a subexpression to a constant power.
+pub struct Power<E> {
+    pub base: E,
+    pub exponent: u32,
+}
+
+pub fn power<E: From<Power<E>>>(base: E, exponent: u32) -> E {
+    E::from(Power { base, exponent })
+}
+
+impl<E: fmt::Display> fmt::Display for Power<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}^{})", self.base, self.exponent)
+    }
+}
+
+// There's no std::ops trait for exponentiation, so just like ProjectPair in ch07c, we invent one.
+// The evaluation rule for Power only needs a value type that implements it; each value type gets
+// to decide what raising to a power means, and how to do it efficiently.
+
+/// A value type that can be raised to a constant power.
+pub trait Pow {
+    fn pow(self, exponent: u32) -> Self;
+}
+
+impl Pow for i64 {
+    fn pow(self, exponent: u32) -> i64 {
+        i64::pow(self, exponent)
+    }
+}
+
+impl Pow for f64 {
+    fn pow(self, exponent: u32) -> f64 {
+        self.powf(f64::from(exponent))
+    }
+}
+
+impl<V, E> Eval<V, E> for Power<E>
+where
+    V: Pow,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.base).pow(self.exponent)
+    }
+}
+
+pub type PowerSig<E> = Sum![Power<E>, MultSig<E>];
+pub struct PowerExpr(pub Box<PowerSig<PowerExpr>>);
+
+impl Expression for PowerExpr {
+    type Signature = PowerSig<PowerExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    PowerExpr: Power<PowerExpr>,
+    Multiply<PowerExpr>,
+    IntegerLiteral,
+    Add<PowerExpr>,
+);
+
+impl fmt::Display for PowerExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Allocates a multiplication of two arena nodes.
+fn alloc_multiply(arena: &mut Arena, lhs: ArenaId, rhs: ArenaId) -> ArenaId {
+    arena.term("multiply", &[lhs, rhs])
+}
+
+/// Allocates `base` raised to `exponent`, by repeated squaring.  Every square refers to the
+/// same node twice, instead of to two copies of it.
+pub fn alloc_power(arena: &mut Arena, base: ArenaId, exponent: u32) -> ArenaId {
+    match exponent {
+        0 => arena.lit(1),
+        1 => base,
+        _ => {
+            let half = alloc_power(arena, base, exponent / 2);
+            let square = alloc_multiply(arena, half, half);
+            if exponent.is_multiple_of(2) {
+                square
+            } else {
+                alloc_multiply(arena, square, base)
+            }
+        }
+    }
+}
+
+/// An algebra that lowers an expression into an arena, expanding every power into
+/// multiplications.  It returns the id of each subexpression's node.
+pub struct ExpandPowers<'a> {
+    pub arena: RefCell<&'a mut Arena>,
+}
+
+impl<E> Algebra<IntegerLiteral, E, ArenaId> for ExpandPowers<'_> {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> ArenaId
+    where
+        F: FnMut(&E) -> ArenaId,
+    {
+        self.arena.borrow_mut().lit(term.value)
+    }
+}
+
+impl<E> Algebra<Add<E>, E, ArenaId> for ExpandPowers<'_> {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> ArenaId
+    where
+        F: FnMut(&E) -> ArenaId,
+    {
+        let lhs = recurse(&term.lhs);
+        let rhs = recurse(&term.rhs);
+        self.arena.borrow_mut().term("add", &[lhs, rhs])
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, ArenaId> for ExpandPowers<'_> {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> ArenaId
+    where
+        F: FnMut(&E) -> ArenaId,
+    {
+        let lhs = recurse(&term.lhs);
+        let rhs = recurse(&term.rhs);
+        alloc_multiply(&mut self.arena.borrow_mut(), lhs, rhs)
+    }
+}
+
+impl<E> Algebra<Power<E>, E, ArenaId> for ExpandPowers<'_> {
+    fn apply<F>(&self, term: &Power<E>, mut recurse: F) -> ArenaId
+    where
+        F: FnMut(&E) -> ArenaId,
+    {
+        let base = recurse(&term.base);
+        alloc_power(&mut self.arena.borrow_mut(), base, term.exponent)
+    }
+}
+
+/// Lowers an expression into an arena, expanding every power into multiplications.  Returns the
+/// id of the root node.
+pub fn expand_powers<E>(expr: &E, arena: &mut Arena) -> ArenaId
+where
+    E: Expression,
+    for<'a> ExpandPowers<'a>: Algebra<E::Signature, E, ArenaId>,
+{
+    let algebra = ExpandPowers {
+        arena: RefCell::new(arena),
+    };
+    mcata(&algebra, expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+    use crate::dag::DagNode;
+    use crate::rewrite::Node;
+
+    #[test]
+    fn can_evaluate_powers() {
+        let expr: PowerExpr = add(power(integer_literal(2), 10), integer_literal(1));
+        assert_eq!(expr.to_string(), "((2^10) + 1)");
+        assert_eq!(expr.evaluate::<i64>(), 1025);
+    }
+
+    #[test]
+    fn value_types_choose_how_to_raise_powers() {
+        // A value type that only counts how many multiplications it would take.
+        #[derive(Debug, PartialEq)]
+        struct Multiplications(u32);
+
+        impl From<i64> for Multiplications {
+            fn from(_value: i64) -> Multiplications {
+                Multiplications(0)
+            }
+        }
+
+        impl std::ops::Add for Multiplications {
+            type Output = Multiplications;
+            fn add(self, other: Multiplications) -> Multiplications {
+                Multiplications(self.0 + other.0)
+            }
+        }
+
+        impl std::ops::Mul for Multiplications {
+            type Output = Multiplications;
+            fn mul(self, other: Multiplications) -> Multiplications {
+                Multiplications(self.0 + other.0 + 1)
+            }
+        }
+
+        impl Pow for Multiplications {
+            fn pow(self, exponent: u32) -> Multiplications {
+                Multiplications(self.0 + exponent.saturating_sub(1))
+            }
+        }
+
+        let expr: PowerExpr = multiply(power(integer_literal(3), 4), integer_literal(2));
+        assert_eq!(expr.evaluate::<Multiplications>(), Multiplications(4));
+        assert_eq!(Pow::pow(1.5, 2), 2.25);
+    }
+
+    #[test]
+    fn squares_share_their_halves() {
+        let expr: PowerExpr = power(integer_literal(3), 4);
+        let mut arena = Arena::new();
+        let root = expand_powers(&expr, &mut arena);
+        // 3, 3 * 3, and (3 * 3) * (3 * 3)
+        assert_eq!(arena.len(), 3);
+        assert_eq!(
+            arena.get(root),
+            &DagNode::Term {
+                kind: "multiply",
+                children: vec![1, 1],
+            }
+        );
+        assert_eq!(
+            arena.to_tree(root),
+            Node::term(
+                "multiply",
+                vec![
+                    Node::term("multiply", vec![Node::Literal(3), Node::Literal(3)]),
+                    Node::term("multiply", vec![Node::Literal(3), Node::Literal(3)]),
+                ]
+            )
+        );
+        assert_eq!(arena.evaluate(root), Ok(81));
+    }
+
+    #[test]
+    fn expansion_matches_the_reference_semantics() {
+        for exponent in 0..=20 {
+            let expr: PowerExpr = power(add(integer_literal(1), integer_literal(1)), exponent);
+            let mut arena = Arena::new();
+            let root = expand_powers(&expr, &mut arena);
+            assert_eq!(arena.evaluate(root), Ok(expr.evaluate::<i64>()));
+        }
+    }
+
+    #[test]
+    fn large_exponents_stay_small() {
+        let expr: PowerExpr = power(integer_literal(1), 1_000_000);
+        let mut arena = Arena::new();
+        let root = expand_powers(&expr, &mut arena);
+        // 19 squarings and 6 extra multiplications, since 1,000,000 has 20 bits, and 6 of the bits
+        // after the leading one are set.
+        assert_eq!(arena.len(), 1 + 19 + 6);
+        assert_eq!(arena.evaluate(root), Ok(1));
+    }
+}
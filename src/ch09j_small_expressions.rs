@@ -0,0 +1,220 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! ch09h avoided the heap by ruling out deep expressions in the type system.  Here's a more
+//! forgiving version of the same idea, borrowed from "small string" and "small vector"
+//! optimizations: most expressions that a program builds are tiny (a literal, or one operator
+//! applied to two literals), so store those inline, and only fall back on boxes for the ones that
+//! outgrow the inline space.
+//!
+//! An inline expression is a fixed-size array of instructions in postfix order, which is both
+//! compact and easy to evaluate with a small stack machine.  Combining two inline expressions just
+//! concatenates their instructions, as long as the result fits.  Once it doesn't, the expression
+//! *spills*: it's converted into the ordinary boxed `MultExpr`, and stays boxed from then on.  So
+//! you pay for the heap only when an expression is big enough that the allocations are a small
+//! part of the cost of building it.
+
+use crate::ch04_smart_constructors::*;
+use crate::ch05a_multiplication::*;
+use crate::ch09a_mendler::*;
+
+use std::fmt;
+
+/// The default number of nodes that we store inline: enough for one operator and its two
+/// literal operands.
+pub const INLINE_NODES: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instr {
+    Literal(i64),
+    Add,
+    Multiply,
+}
+
+/// An expression with at most `N` nodes stored inline, or a boxed expression of any size.
+pub struct SmallExpr<const N: usize = INLINE_NODES>(Repr<N>);
+
+enum Repr<const N: usize> {
+    Inline { instrs: [Instr; N], len: usize },
+    Spilled(MultExpr),
+}
+
+impl<const N: usize> SmallExpr<N> {
+    pub fn literal(value: i64) -> SmallExpr<N> {
+        assert!(N > 0, "SmallExpr needs room for at least one node");
+        let mut instrs = [Instr::Literal(0); N];
+        instrs[0] = Instr::Literal(value);
+        SmallExpr(Repr::Inline { instrs, len: 1 })
+    }
+
+    fn combine(op: Instr, lhs: SmallExpr<N>, rhs: SmallExpr<N>) -> SmallExpr<N> {
+        match (lhs.0, rhs.0) {
+            (
+                Repr::Inline {
+                    instrs: mut result,
+                    len: lhs_len,
+                },
+                Repr::Inline {
+                    instrs: rhs,
+                    len: rhs_len,
+                },
+            ) if lhs_len + rhs_len < N => {
+                result[lhs_len..lhs_len + rhs_len].copy_from_slice(&rhs[..rhs_len]);
+                result[lhs_len + rhs_len] = op;
+                SmallExpr(Repr::Inline {
+                    instrs: result,
+                    len: lhs_len + rhs_len + 1,
+                })
+            }
+            (lhs, rhs) => {
+                let (lhs, rhs) = (SmallExpr(lhs).into_boxed(), SmallExpr(rhs).into_boxed());
+                SmallExpr(Repr::Spilled(match op {
+                    Instr::Add => add(lhs, rhs),
+                    _ => multiply(lhs, rhs),
+                }))
+            }
+        }
+    }
+
+    /// Whether this expression is stored without any heap allocations.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Inline { .. })
+    }
+
+    /// Converts this expression into an ordinary boxed expression.
+    pub fn into_boxed(self) -> MultExpr {
+        let (instrs, len) = match self.0 {
+            Repr::Inline { instrs, len } => (instrs, len),
+            Repr::Spilled(expr) => return expr,
+        };
+        let mut stack: [Option<MultExpr>; N] = std::array::from_fn(|_| None);
+        let mut depth = 0;
+        for instr in &instrs[..len] {
+            let expr = match instr {
+                Instr::Literal(value) => integer_literal(*value),
+                Instr::Add | Instr::Multiply => {
+                    depth -= 2;
+                    let lhs = stack[depth].take().unwrap();
+                    let rhs = stack[depth + 1].take().unwrap();
+                    match instr {
+                        Instr::Add => add(lhs, rhs),
+                        _ => multiply(lhs, rhs),
+                    }
+                }
+            };
+            stack[depth] = Some(expr);
+            depth += 1;
+        }
+        stack[0].take().unwrap()
+    }
+
+    pub fn evaluate(&self) -> i64 {
+        let (instrs, len) = match &self.0 {
+            Repr::Inline { instrs, len } => (instrs, *len),
+            Repr::Spilled(expr) => return mcata(&Evaluator, expr),
+        };
+        // The stack never holds more values than there are instructions, so it fits inline too.
+        let mut stack = [0; N];
+        let mut depth = 0;
+        for instr in &instrs[..len] {
+            match instr {
+                Instr::Literal(value) => {
+                    stack[depth] = *value;
+                    depth += 1;
+                }
+                Instr::Add => {
+                    depth -= 1;
+                    stack[depth - 1] += stack[depth];
+                }
+                Instr::Multiply => {
+                    depth -= 1;
+                    stack[depth - 1] *= stack[depth];
+                }
+            }
+        }
+        stack[0]
+    }
+}
+
+impl<const N: usize> std::ops::Add for SmallExpr<N> {
+    type Output = SmallExpr<N>;
+    fn add(self, other: SmallExpr<N>) -> SmallExpr<N> {
+        SmallExpr::combine(Instr::Add, self, other)
+    }
+}
+
+impl<const N: usize> std::ops::Mul for SmallExpr<N> {
+    type Output = SmallExpr<N>;
+    fn mul(self, other: SmallExpr<N>) -> SmallExpr<N> {
+        SmallExpr::combine(Instr::Multiply, self, other)
+    }
+}
+
+impl<const N: usize> fmt::Display for SmallExpr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (instrs, len) = match &self.0 {
+            Repr::Inline { instrs, len } => (instrs, *len),
+            Repr::Spilled(expr) => return expr.fmt(f),
+        };
+        let mut stack: Vec<String> = Vec::with_capacity(len);
+        for instr in &instrs[..len] {
+            let rendered = match instr {
+                Instr::Literal(value) => value.to_string(),
+                Instr::Add | Instr::Multiply => {
+                    let rhs = stack.pop().unwrap();
+                    let lhs = stack.pop().unwrap();
+                    let op = if *instr == Instr::Add { "+" } else { "*" };
+                    format!("({} {} {})", lhs, op, rhs)
+                }
+            };
+            stack.push(rendered);
+        }
+        f.write_str(&stack.pop().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocations::*;
+
+    type Small = SmallExpr;
+
+    #[test]
+    fn small_expressions_stay_inline() {
+        let (expr, report) = measure(|| Small::literal(1) + Small::literal(2));
+        assert!(expr.is_inline());
+        assert_eq!(report.nodes, 0);
+        assert_eq!(expr.evaluate(), 3);
+        assert_eq!(expr.to_string(), "(1 + 2)");
+    }
+
+    #[test]
+    fn large_expressions_spill() {
+        // (1 + 2) * 3 has five nodes, which is too many for three inline slots.
+        let expr = (Small::literal(1) + Small::literal(2)) * Small::literal(3);
+        assert!(!expr.is_inline());
+        assert_eq!(expr.evaluate(), 9);
+        assert_eq!(expr.to_string(), "((1 + 2) * 3)");
+
+        // But it fits in seven.
+        let literal = SmallExpr::<7>::literal;
+        let expr = (literal(1) + literal(2)) * literal(3);
+        assert!(expr.is_inline());
+        assert_eq!(expr.evaluate(), 9);
+        assert_eq!(expr.to_string(), "((1 + 2) * 3)");
+        assert_eq!(mcata::<_, _, i64>(&Evaluator, &expr.into_boxed()), 9);
+    }
+}
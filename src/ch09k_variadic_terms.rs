@@ -0,0 +1,391 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Addition and multiplication are associative, so a chain like `((1 + 2) + 3) + 4` is really
+//! just one sum with four operands.  Binary terms hide that: code that wants to reorder the
+//! operands (to put an expression in a canonical form, or to check whether two expressions match
+//! up to commutativity) has to walk down a lopsided tree to find them, and the tree can be lopsided
+//! in either direction.  Here we add variadic `AddN` and `MulN` terms that hold their operands in a
+//! flat list, and a pass that flattens binary chains into them.
+//!
+//! The flattened language doesn't contain the binary terms at all, so (like desugaring in ch08e)
+//! its type proves that there aren't any chains left to flatten.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::Eval;
+use crate::ch08i_metrics::*;
+use crate::ch09d_owned_fold::*;
+use crate::telemetry::TermKind;
+
+use std::fmt;
+
+/// The sum of any number of operands.  An empty sum is 0.
+pub struct AddN<E> {
+    pub operands: Vec<E>,
+}
+
+/// The product of any number of operands.  An empty product is 1.
+pub struct MulN<E> {
+    pub operands: Vec<E>,
+}
+
+pub fn add_n<E: From<AddN<E>>>(operands: Vec<E>) -> E {
+    E::from(AddN { operands })
+}
+
+pub fn mul_n<E: From<MulN<E>>>(operands: Vec<E>) -> E {
+    E::from(MulN { operands })
+}
+
+impl<V, E> Eval<V, E> for AddN<E>
+where
+    V: From<i64> + std::ops::Add<Output = V>,
+{
+    fn eval<F>(&self, eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        self.operands
+            .iter()
+            .map(eval_subexpr)
+            .fold(V::from(0), |sum, operand| sum + operand)
+    }
+}
+
+impl<V, E> Eval<V, E> for MulN<E>
+where
+    V: From<i64> + std::ops::Mul<Output = V>,
+{
+    fn eval<F>(&self, eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        self.operands
+            .iter()
+            .map(eval_subexpr)
+            .fold(V::from(1), |product, operand| product * operand)
+    }
+}
+
+impl<E> Measure<E> for AddN<E> {
+    fn measure<F>(&self, measure_subexpr: F) -> Metrics
+    where
+        F: FnMut(&E) -> Metrics,
+    {
+        Metrics::term(Self::NAME, self.operands.iter().map(measure_subexpr))
+    }
+}
+
+impl<E> Measure<E> for MulN<E> {
+    fn measure<F>(&self, measure_subexpr: F) -> Metrics
+    where
+        F: FnMut(&E) -> Metrics,
+    {
+        Metrics::term(Self::NAME, self.operands.iter().map(measure_subexpr))
+    }
+}
+
+fn fmt_operands<E: fmt::Display>(
+    f: &mut fmt::Formatter,
+    operands: &[E],
+    operator: &str,
+    identity: i64,
+) -> fmt::Result {
+    if operands.is_empty() {
+        return write!(f, "{}", identity);
+    }
+    write!(f, "(")?;
+    for (index, operand) in operands.iter().enumerate() {
+        if index > 0 {
+            write!(f, " {} ", operator)?;
+        }
+        write!(f, "{}", operand)?;
+    }
+    write!(f, ")")
+}
+
+impl<E: fmt::Display> fmt::Display for AddN<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_operands(f, &self.operands, "+", 0)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for MulN<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_operands(f, &self.operands, "*", 1)
+    }
+}
+
+// A language with only the variadic terms.
+
+pub type FlatSig<E> = Sum![AddN<E>, MulN<E>, IntegerLiteral];
+pub struct FlatExpr(pub Box<FlatSig<FlatExpr>>);
+
+impl Expression for FlatExpr {
+    type Signature = FlatSig<FlatExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(FlatExpr: AddN<FlatExpr>, MulN<FlatExpr>, IntegerLiteral);
+
+impl fmt::Display for FlatExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Flattening is an owned fold into FlatExpr.  Each operator converts its children first, and then
+// splices in the operands of any child that is the same operator.
+
+/// An algebra that flattens chains of additions and multiplications into variadic terms.
+pub struct Flatten;
+
+fn sum_operands(expr: FlatExpr) -> Vec<FlatExpr> {
+    match *expr.0 {
+        Sum::Left(AddN { operands }) => operands,
+        sig => vec![FlatExpr::wrap(sig)],
+    }
+}
+
+fn product_operands(expr: FlatExpr) -> Vec<FlatExpr> {
+    match *expr.0 {
+        Sum::Right(Sum::Left(MulN { operands })) => operands,
+        sig => vec![FlatExpr::wrap(sig)],
+    }
+}
+
+impl<E> OwnedAlgebra<IntegerLiteral, E, FlatExpr> for Flatten {
+    fn apply_owned<F>(&self, term: IntegerLiteral, _recurse: F) -> FlatExpr
+    where
+        F: FnMut(E) -> FlatExpr,
+    {
+        FlatExpr::from(term)
+    }
+}
+
+impl<E> OwnedAlgebra<Add<E>, E, FlatExpr> for Flatten {
+    fn apply_owned<F>(&self, term: Add<E>, mut recurse: F) -> FlatExpr
+    where
+        F: FnMut(E) -> FlatExpr,
+    {
+        let mut operands = sum_operands(recurse(term.lhs));
+        operands.extend(sum_operands(recurse(term.rhs)));
+        add_n(operands)
+    }
+}
+
+impl<E> OwnedAlgebra<Multiply<E>, E, FlatExpr> for Flatten {
+    fn apply_owned<F>(&self, term: Multiply<E>, mut recurse: F) -> FlatExpr
+    where
+        F: FnMut(E) -> FlatExpr,
+    {
+        let mut operands = product_operands(recurse(term.lhs));
+        operands.extend(product_operands(recurse(term.rhs)));
+        mul_n(operands)
+    }
+}
+
+// Variadic terms can be nested too, so they get flattened the same way.
+
+impl<E> OwnedAlgebra<AddN<E>, E, FlatExpr> for Flatten {
+    fn apply_owned<F>(&self, term: AddN<E>, recurse: F) -> FlatExpr
+    where
+        F: FnMut(E) -> FlatExpr,
+    {
+        add_n(
+            term.operands
+                .into_iter()
+                .map(recurse)
+                .flat_map(sum_operands)
+                .collect(),
+        )
+    }
+}
+
+impl<E> OwnedAlgebra<MulN<E>, E, FlatExpr> for Flatten {
+    fn apply_owned<F>(&self, term: MulN<E>, recurse: F) -> FlatExpr
+    where
+        F: FnMut(E) -> FlatExpr,
+    {
+        mul_n(
+            term.operands
+                .into_iter()
+                .map(recurse)
+                .flat_map(product_operands)
+                .collect(),
+        )
+    }
+}
+
+/// Flattens every chain of additions and multiplications in an expression.
+pub fn flatten<E>(expr: E) -> FlatExpr
+where
+    E: Expression,
+    Flatten: OwnedAlgebra<E::Signature, E, FlatExpr>,
+{
+    into_fold(&Flatten, expr)
+}
+
+// With flat operand lists, putting an expression into a canonical form is just sorting each list.
+// Like the canonical dumps in dump.rs, we sort operands by how they render, which means that two
+// expressions that only differ in the order of their operands end up rendering identically.
+
+/// An algebra that sorts the operands of every variadic term.
+pub struct Canonicalize;
+
+impl<E> OwnedAlgebra<IntegerLiteral, E, FlatExpr> for Canonicalize {
+    fn apply_owned<F>(&self, term: IntegerLiteral, _recurse: F) -> FlatExpr
+    where
+        F: FnMut(E) -> FlatExpr,
+    {
+        FlatExpr::from(term)
+    }
+}
+
+impl<E> OwnedAlgebra<AddN<E>, E, FlatExpr> for Canonicalize {
+    fn apply_owned<F>(&self, term: AddN<E>, recurse: F) -> FlatExpr
+    where
+        F: FnMut(E) -> FlatExpr,
+    {
+        let mut operands: Vec<FlatExpr> = term.operands.into_iter().map(recurse).collect();
+        operands.sort_by_cached_key(FlatExpr::to_string);
+        add_n(operands)
+    }
+}
+
+impl<E> OwnedAlgebra<MulN<E>, E, FlatExpr> for Canonicalize {
+    fn apply_owned<F>(&self, term: MulN<E>, recurse: F) -> FlatExpr
+    where
+        F: FnMut(E) -> FlatExpr,
+    {
+        let mut operands: Vec<FlatExpr> = term.operands.into_iter().map(recurse).collect();
+        operands.sort_by_cached_key(FlatExpr::to_string);
+        mul_n(operands)
+    }
+}
+
+/// Sorts the operands of every variadic term in a flattened expression.
+pub fn canonicalize(expr: FlatExpr) -> FlatExpr {
+    into_fold(&Canonicalize, expr)
+}
+
+/// Returns whether two expressions are the same, up to associativity and commutativity.
+pub fn matches_commutatively<E, F>(lhs: E, rhs: F) -> bool
+where
+    E: Expression,
+    F: Expression,
+    Flatten: OwnedAlgebra<E::Signature, E, FlatExpr> + OwnedAlgebra<F::Signature, F, FlatExpr>,
+{
+    let lhs = canonicalize(flatten(lhs)).to_string();
+    let rhs = canonicalize(flatten(rhs)).to_string();
+    lhs == rhs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+
+    #[test]
+    fn can_evaluate_variadic_terms() {
+        let expr: FlatExpr = add_n(vec![
+            integer_literal(4),
+            mul_n(vec![
+                integer_literal(2),
+                integer_literal(5),
+                integer_literal(40),
+            ]),
+        ]);
+        assert_eq!(expr.to_string(), "(4 + (2 * 5 * 40))");
+        assert_eq!(expr.evaluate::<i64>(), 404);
+    }
+
+    #[test]
+    fn empty_terms_are_identities() {
+        let sum: FlatExpr = add_n(vec![]);
+        let product: FlatExpr = mul_n(vec![]);
+        assert_eq!(sum.to_string(), "0");
+        assert_eq!(sum.evaluate::<i64>(), 0);
+        assert_eq!(product.to_string(), "1");
+        assert_eq!(product.evaluate::<i64>(), 1);
+    }
+
+    #[test]
+    fn can_flatten_chains() {
+        // ((1 + 2) + (3 + 4)) * (5 * 6)
+        let expr: MultExpr = multiply(
+            add(
+                add(integer_literal(1), integer_literal(2)),
+                add(integer_literal(3), integer_literal(4)),
+            ),
+            multiply(integer_literal(5), integer_literal(6)),
+        );
+        let expected = expr.evaluate::<i64>();
+        let flat = flatten(expr);
+        assert_eq!(flat.to_string(), "((1 + 2 + 3 + 4) * 5 * 6)");
+        assert_eq!(flat.evaluate::<i64>(), expected);
+    }
+
+    #[test]
+    fn flattening_stops_at_other_operators() {
+        // (1 * 2) + (3 * (4 + 5))
+        let expr: MultExpr = add(
+            multiply(integer_literal(1), integer_literal(2)),
+            multiply(
+                integer_literal(3),
+                add(integer_literal(4), integer_literal(5)),
+            ),
+        );
+        assert_eq!(flatten(expr).to_string(), "((1 * 2) + (3 * (4 + 5)))");
+    }
+
+    #[test]
+    fn can_flatten_nested_variadic_terms() {
+        let expr: FlatExpr = add_n(vec![
+            add_n(vec![integer_literal(1), integer_literal(2)]),
+            add_n(vec![]),
+            integer_literal(3),
+        ]);
+        assert_eq!(flatten(expr).to_string(), "(1 + 2 + 3)");
+    }
+
+    #[test]
+    fn can_match_up_to_commutativity() {
+        // (1 + 2) + 3 * 7, and 7 * 3 + (2 + 1)
+        let lhs: MultExpr = add(
+            add(integer_literal(1), integer_literal(2)),
+            multiply(integer_literal(3), integer_literal(7)),
+        );
+        let rhs: MultExpr = add(
+            multiply(integer_literal(7), integer_literal(3)),
+            add(integer_literal(2), integer_literal(1)),
+        );
+        assert!(matches_commutatively(lhs, rhs));
+        let different: MultExpr = add(integer_literal(1), integer_literal(2));
+        let other: MultExpr = multiply(integer_literal(1), integer_literal(2));
+        assert!(!matches_commutatively(different, other));
+    }
+}
@@ -0,0 +1,352 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! ch08e showed that desugaring changes an expression's type, so that the compiler can check that
+//! the sugar is gone.  A real compiler lowers its input in several stages, each of which removes
+//! something: sugar, then binary chains, then whatever the code generator can't handle.  Here we
+//! give each stage a `Pass` impl with an input and an output language, and chain them into a
+//! pipeline.  The pipeline only type-checks if each stage's output language is the next stage's
+//! input language, so the "this term no longer exists" guarantee holds for the whole pipeline, and
+//! not just for one stage.
+//!
+//! This is different from the pipelines in passes.rs, whose passes all map a language to itself,
+//! and which can run those passes in any order, as many times as they want.  A lowering runs each
+//! stage exactly once, in order.
+
+use crate::ch08a_expressions::*;
+use crate::ch08e_sugar::*;
+use crate::ch08i_metrics::*;
+use crate::rewrite::{Limit, RewriteLimits};
+
+use std::fmt;
+use std::marker::PhantomData;
+
+/// One stage of a lowering, from one language to another.
+pub trait Pass {
+    type Input;
+    type Output;
+    fn run(&self, input: Self::Input) -> Self::Output;
+
+    /// Like `run`, but also adds how many steps of work it took to `steps`.  A pass that doesn't
+    /// count its work any more finely than that takes one step.
+    fn run_counting(&self, input: Self::Input, steps: &mut usize) -> Self::Output {
+        *steps += 1;
+        self.run(input)
+    }
+}
+
+/// A pass that's implemented by a function.
+pub struct FnPass<F, I, O> {
+    run: F,
+    types: PhantomData<fn(I) -> O>,
+}
+
+pub fn from_fn<F, I, O>(run: F) -> FnPass<F, I, O>
+where
+    F: Fn(I) -> O,
+{
+    FnPass {
+        run,
+        types: PhantomData,
+    }
+}
+
+impl<F, I, O> Pass for FnPass<F, I, O>
+where
+    F: Fn(I) -> O,
+{
+    type Input = I;
+    type Output = O;
+    fn run(&self, input: I) -> O {
+        (self.run)(input)
+    }
+}
+
+/// A pass that desugars language `E` into language `F`.
+pub struct Desugaring<E, F>(PhantomData<fn(E) -> F>);
+
+pub fn desugaring<E, F>() -> Desugaring<E, F> {
+    Desugaring(PhantomData)
+}
+
+impl<E, F> Pass for Desugaring<E, F>
+where
+    E: Expression,
+    E::Signature: Desugar<E, F>,
+{
+    type Input = E;
+    type Output = F;
+    fn run(&self, input: E) -> F {
+        desugar(input)
+    }
+
+    fn run_counting(&self, input: E, steps: &mut usize) -> F {
+        desugar_counting(input, steps)
+    }
+}
+
+/// Desugars an expression, taking one step for each of its nodes.
+fn desugar_counting<E, F>(expr: E, steps: &mut usize) -> F
+where
+    E: Expression,
+    E::Signature: Desugar<E, F>,
+{
+    *steps += 1;
+    expr.into_signature()
+        .desugar(|subexpr| desugar_counting(subexpr, steps))
+}
+
+/// Runs one pass, and then another on its result.
+pub struct Then<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Pass for Then<A, B>
+where
+    A: Pass,
+    B: Pass<Input = A::Output>,
+{
+    type Input = A::Input;
+    type Output = B::Output;
+    fn run(&self, input: A::Input) -> B::Output {
+        self.second.run(self.first.run(input))
+    }
+
+    fn run_counting(&self, input: A::Input, steps: &mut usize) -> B::Output {
+        let input = self.first.run_counting(input, steps);
+        self.second.run_counting(input, steps)
+    }
+}
+
+/// A sequence of passes, each of which lowers the output of the one before it.
+pub struct LoweringPipeline<P> {
+    passes: P,
+}
+
+impl<P: Pass> LoweringPipeline<P> {
+    pub fn new(pass: P) -> LoweringPipeline<P> {
+        LoweringPipeline { passes: pass }
+    }
+
+    /// Adds a pass to the end of the pipeline.  Its input language has to be the pipeline's
+    /// current output language.
+    pub fn then<Q>(self, pass: Q) -> LoweringPipeline<Then<P, Q>>
+    where
+        Q: Pass<Input = P::Output>,
+    {
+        LoweringPipeline {
+            passes: Then {
+                first: self.passes,
+                second: pass,
+            },
+        }
+    }
+
+    pub fn run(&self, input: P::Input) -> P::Output {
+        self.passes.run(input)
+    }
+
+    /// Runs the pipeline, and checks that it stayed within `limits`.
+    pub fn run_within(
+        &self,
+        input: P::Input,
+        limits: &RewriteLimits,
+    ) -> Result<P::Output, LoweringLimitExceeded<P::Output>>
+    where
+        P::Input: Measure<P::Input>,
+        P::Output: Measure<P::Output>,
+    {
+        let input_nodes = metrics(&input).nodes;
+        let mut steps = 0;
+        let output = self.passes.run_counting(input, &mut steps);
+        check_limits(output, input_nodes, steps, limits)
+    }
+}
+
+// A pipeline is itself a pass, so it can be a stage of a bigger pipeline.
+
+impl<P: Pass> Pass for LoweringPipeline<P> {
+    type Input = P::Input;
+    type Output = P::Output;
+    fn run(&self, input: P::Input) -> P::Output {
+        self.passes.run(input)
+    }
+
+    fn run_counting(&self, input: P::Input, steps: &mut usize) -> P::Output {
+        self.passes.run_counting(input, steps)
+    }
+}
+
+// Lowering can't loop forever like a set of rewrite rules can, since each stage runs once, but it
+// can still make an expression much bigger than it started out (think of sugar whose definition
+// is much bigger than the sugar), so a pipeline can be held to the rewriter's limits, too.  There's
+// one difference: a lowering can't stop halfway, because a half-lowered expression doesn't have a
+// type.  So the stages always run to completion, and then we check whether they went over.
+
+/// A lowering that went over one of its limits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoweringLimitExceeded<T> {
+    pub limit: Limit,
+    /// How many steps the lowering took.
+    pub steps: usize,
+    /// The lowered expression, which went over the limit.
+    pub result: T,
+}
+
+impl<T> fmt::Display for LoweringLimitExceeded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.limit {
+            Limit::Steps(max) => write!(
+                f,
+                "lowering took {} steps, more than the limit of {}",
+                self.steps, max
+            ),
+            Limit::Growth(max) => write!(f, "lowering grew the tree by more than {}x", max),
+        }
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for LoweringLimitExceeded<T> {}
+
+fn check_limits<T>(
+    result: T,
+    input_nodes: usize,
+    steps: usize,
+    limits: &RewriteLimits,
+) -> Result<T, LoweringLimitExceeded<T>>
+where
+    T: Measure<T>,
+{
+    let limit = match (limits.max_steps, limits.max_growth) {
+        (Some(max_steps), _) if steps > max_steps => Some(Limit::Steps(max_steps)),
+        (_, Some(max_growth))
+            if metrics(&result).nodes as f64 > input_nodes as f64 * max_growth =>
+        {
+            Some(Limit::Growth(max_growth))
+        }
+        _ => None,
+    };
+    match limit {
+        Some(limit) => Err(LoweringLimitExceeded {
+            limit,
+            steps,
+            result,
+        }),
+        None => Ok(result),
+    }
+}
+
+/// Like ch08e's `desugar_fully`, but checks that desugaring stayed within `limits`.  Desugaring
+/// takes one step for each node of `expr`.
+pub fn desugar_within<E, F>(expr: E, limits: &RewriteLimits) -> Result<F, LoweringLimitExceeded<F>>
+where
+    E: Expression,
+    E::Signature: Desugar<E, F>,
+    F: SugarFreeExpression + Measure<F>,
+{
+    let mut steps = 0;
+    let result = desugar_counting(expr, &mut steps);
+    // Desugaring visits every node exactly once, so that's also the size of the input.
+    check_limits(result, steps, steps, limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+    use crate::ch09k_variadic_terms::*;
+
+    // NegateExpr → MultExpr → FlatExpr → FlatExpr.  Swapping the first two stages won't compile,
+    // since flatten doesn't know what to do with Negate, and desugaring can't read a FlatExpr.
+    fn pipeline() -> LoweringPipeline<impl Pass<Input = NegateExpr, Output = FlatExpr>> {
+        LoweringPipeline::new(desugaring::<NegateExpr, MultExpr>())
+            .then(from_fn(flatten::<MultExpr>))
+            .then(from_fn(canonicalize))
+    }
+
+    #[test]
+    fn can_lower_in_stages() {
+        // (3 + -(2 + 1)) * 101
+        let expr: NegateExpr = multiply(
+            add(
+                integer_literal(3),
+                negate(add(integer_literal(2), integer_literal(1))),
+            ),
+            integer_literal(101),
+        );
+        let lowered = pipeline().run(expr);
+        assert_eq!(lowered.to_string(), "((((1 + 2) * -1) + 3) * 101)");
+        assert_eq!(lowered.evaluate::<i64>(), 0);
+    }
+
+    #[test]
+    fn pipelines_are_passes() {
+        let evaluate = from_fn(|expr: FlatExpr| expr.evaluate::<i64>());
+        let outer = LoweringPipeline::new(pipeline()).then(evaluate);
+        let expr: NegateExpr = add(integer_literal(410), negate(integer_literal(6)));
+        assert_eq!(outer.run(expr), 404);
+    }
+
+    #[test]
+    fn can_desugar_within_limits() {
+        // ---3 has four nodes, and desugars into seven.
+        let expr = || -> NegateExpr { negate(negate(negate(integer_literal(3)))) };
+        let desugared: MultExpr = desugar_within(expr(), &RewriteLimits::default())
+            .ok()
+            .unwrap();
+        assert_eq!(desugared.evaluate::<i64>(), -3);
+
+        let limits = RewriteLimits {
+            max_steps: Some(3),
+            max_growth: None,
+        };
+        let err = desugar_within::<_, MultExpr>(expr(), &limits)
+            .err()
+            .unwrap();
+        assert_eq!((err.limit, err.steps), (Limit::Steps(3), 4));
+
+        let limits = RewriteLimits {
+            max_steps: None,
+            max_growth: Some(1.5),
+        };
+        let err = desugar_within::<_, MultExpr>(expr(), &limits)
+            .err()
+            .unwrap();
+        assert_eq!(err.limit, Limit::Growth(1.5));
+        assert_eq!(err.result.evaluate::<i64>(), -3);
+    }
+
+    #[test]
+    fn can_lower_within_limits() {
+        // Desugaring takes a step for each of the four nodes, and the other stages take one each.
+        let expr = || -> NegateExpr { add(integer_literal(410), negate(integer_literal(6))) };
+        let lowered = pipeline().run_within(expr(), &RewriteLimits::default());
+        assert_eq!(lowered.ok().unwrap().evaluate::<i64>(), 404);
+
+        let limits = RewriteLimits {
+            max_steps: Some(5),
+            max_growth: None,
+        };
+        let err = pipeline().run_within(expr(), &limits).err().unwrap();
+        assert_eq!((err.limit, err.steps), (Limit::Steps(5), 6));
+        assert_eq!(
+            err.to_string(),
+            "lowering took 6 steps, more than the limit of 5"
+        );
+    }
+}
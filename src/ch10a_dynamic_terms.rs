@@ -0,0 +1,291 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Everything we've done so far has been resolved statically: the set of terms in a language is
+//! baked into the type of its expressions.  That's great if you know that set at compile time.  But
+//! what if you want to add new kinds of term at runtime — say, from a plugin?
+//!
+//! Rust's answer to "I don't know the type until runtime" is trait objects.  Let's see how far we
+//! get with an expression type whose nodes are `Box<dyn Term>`.
+
+use crate::ch02_open_sum::*;
+use crate::ch03_evaluation::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+
+use std::any::Any;
+use std::fmt;
+
+/// A term that can appear in a dynamic expression.  All of the interesting methods come from the
+/// supertraits, and so they're all available through the vtable.  The only thing that we need to
+/// add is a way to get back at the concrete type of the term.
+pub trait Term: Any + EvaluateInt + fmt::Display {
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Any type that we know how to evaluate and display can be used as a term.  That includes all of
+/// the existing term types from the open-sum chapters — and any new ones that you define later,
+/// without touching this module!
+impl<T> Term for T
+where
+    T: Any + EvaluateInt + fmt::Display,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A dynamic expression.  Each node is a type-erased term, and its subexpressions (if any) are
+/// themselves `DynExpr`s.
+pub struct DynExpr(pub Box<dyn Term>);
+
+impl DynExpr {
+    pub fn new<T: Term>(term: T) -> DynExpr {
+        DynExpr(Box::new(term))
+    }
+
+    /// Returns the outermost term of this expression, if it has type `T`.
+    pub fn downcast_ref<T: Term>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref::<T>()
+    }
+}
+
+// Evaluating or rendering a dynamic expression dispatches through the vtable of its outermost
+// term.  That term's own impl then evaluates or renders its subexpressions, which dispatch through
+// *their* vtables, and so on down the tree.
+
+impl EvaluateInt for DynExpr {
+    fn evaluate(&self) -> i64 {
+        self.0.evaluate()
+    }
+}
+
+impl fmt::Display for DynExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Some smart constructors.  We don't need to use From here, since every kind of term ends up in the
+// same DynExpr type.
+
+pub fn dyn_integer_literal(value: i64) -> DynExpr {
+    DynExpr::new(IntegerLiteral { value })
+}
+
+pub fn dyn_add(lhs: DynExpr, rhs: DynExpr) -> DynExpr {
+    DynExpr::new(Add { lhs, rhs })
+}
+
+pub fn dyn_multiply(lhs: DynExpr, rhs: DynExpr) -> DynExpr {
+    DynExpr::new(Multiply { lhs, rhs })
+}
+
+// Converting a static expression into a dynamic one is a fold, so we can use the Mendler-style
+// fold from ch09a.  The algebra's carrier is DynExpr.
+
+/// An algebra that converts a static expression into a dynamic one.
+pub struct ToDyn;
+
+impl<E> Algebra<IntegerLiteral, E, DynExpr> for ToDyn {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> DynExpr
+    where
+        F: FnMut(&E) -> DynExpr,
+    {
+        dyn_integer_literal(term.value)
+    }
+}
+
+impl<E> Algebra<Add<E>, E, DynExpr> for ToDyn {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> DynExpr
+    where
+        F: FnMut(&E) -> DynExpr,
+    {
+        dyn_add(recurse(&term.lhs), recurse(&term.rhs))
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, DynExpr> for ToDyn {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> DynExpr
+    where
+        F: FnMut(&E) -> DynExpr,
+    {
+        dyn_multiply(recurse(&term.lhs), recurse(&term.rhs))
+    }
+}
+
+/// Converts any static expression into a dynamic one.
+pub fn to_dyn<E>(expr: &E) -> DynExpr
+where
+    E: Expression,
+    ToDyn: Algebra<E::Signature, E, DynExpr>,
+{
+    mcata(&ToDyn, expr)
+}
+
+// Going the other way can fail, since a dynamic expression might contain a kind of term that the
+// static expression type doesn't know about.  Each kind of term knows how to recognize itself (by
+// downcasting), and uses open recursion to convert its subexpressions.
+
+/// Tries to create a term of this type from the outermost term of a dynamic expression.
+pub trait FromDyn<E>: Sized {
+    fn from_dyn<F>(expr: &DynExpr, from_subexpr: F) -> Option<Self>
+    where
+        F: FnMut(&DynExpr) -> Option<E>;
+}
+
+impl<E> FromDyn<E> for IntegerLiteral {
+    fn from_dyn<F>(expr: &DynExpr, _from_subexpr: F) -> Option<Self>
+    where
+        F: FnMut(&DynExpr) -> Option<E>,
+    {
+        let term = expr.downcast_ref::<IntegerLiteral>()?;
+        Some(IntegerLiteral { value: term.value })
+    }
+}
+
+impl<E> FromDyn<E> for Add<E> {
+    fn from_dyn<F>(expr: &DynExpr, mut from_subexpr: F) -> Option<Self>
+    where
+        F: FnMut(&DynExpr) -> Option<E>,
+    {
+        let term = expr.downcast_ref::<Add<DynExpr>>()?;
+        Some(Add {
+            lhs: from_subexpr(&term.lhs)?,
+            rhs: from_subexpr(&term.rhs)?,
+        })
+    }
+}
+
+impl<E> FromDyn<E> for Multiply<E> {
+    fn from_dyn<F>(expr: &DynExpr, mut from_subexpr: F) -> Option<Self>
+    where
+        F: FnMut(&DynExpr) -> Option<E>,
+    {
+        let term = expr.downcast_ref::<Multiply<DynExpr>>()?;
+        Some(Multiply {
+            lhs: from_subexpr(&term.lhs)?,
+            rhs: from_subexpr(&term.rhs)?,
+        })
+    }
+}
+
+/// A sum tries each of its variants in turn.
+impl<E, L, R> FromDyn<E> for Sum<L, R>
+where
+    L: FromDyn<E>,
+    R: FromDyn<E>,
+{
+    fn from_dyn<F>(expr: &DynExpr, mut from_subexpr: F) -> Option<Self>
+    where
+        F: FnMut(&DynExpr) -> Option<E>,
+    {
+        if let Some(lhs) = L::from_dyn(expr, &mut from_subexpr) {
+            return Some(Sum::Left(lhs));
+        }
+        R::from_dyn(expr, from_subexpr).map(Sum::Right)
+    }
+}
+
+/// Converts a dynamic expression into a static one, if the static expression's signature contains
+/// every kind of term that appears in the dynamic expression.
+pub fn from_dyn<E>(expr: &DynExpr) -> Option<E>
+where
+    E: Expression,
+    E::Signature: FromDyn<E>,
+{
+    E::Signature::from_dyn(expr, from_dyn).map(E::wrap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_evaluate_dynamic_expression() {
+        let expr = dyn_add(
+            dyn_multiply(dyn_integer_literal(80), dyn_integer_literal(5)),
+            dyn_integer_literal(4),
+        );
+        assert_eq!(expr.evaluate(), 404);
+        assert_eq!(expr.to_string(), "((80 * 5) + 4)");
+    }
+
+    // Here's a brand new kind of term.  We don't have to register it anywhere; implementing
+    // EvaluateInt and Display is enough to let it appear in a dynamic expression.
+
+    struct Negate<E> {
+        nested: E,
+    }
+
+    impl<E: EvaluateInt> EvaluateInt for Negate<E> {
+        fn evaluate(&self) -> i64 {
+            -self.nested.evaluate()
+        }
+    }
+
+    impl<E: fmt::Display> fmt::Display for Negate<E> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "-{}", self.nested)
+        }
+    }
+
+    #[test]
+    fn can_add_new_terms_at_runtime() {
+        let expr = dyn_add(
+            DynExpr::new(Negate {
+                nested: dyn_integer_literal(1),
+            }),
+            dyn_integer_literal(3),
+        );
+        assert_eq!(expr.evaluate(), 2);
+        assert_eq!(expr.to_string(), "(-1 + 3)");
+    }
+
+    #[test]
+    fn can_convert_static_to_dynamic() {
+        let mult: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let expr = to_dyn(&mult);
+        assert_eq!(expr.evaluate(), 404);
+        assert_eq!(expr.to_string(), "((80 * 5) + 4)");
+        assert!(expr.downcast_ref::<Add<DynExpr>>().is_some());
+        assert!(expr.downcast_ref::<Multiply<DynExpr>>().is_none());
+    }
+
+    #[test]
+    fn can_convert_dynamic_to_static() {
+        let expr = dyn_add(
+            dyn_multiply(dyn_integer_literal(80), dyn_integer_literal(5)),
+            dyn_integer_literal(4),
+        );
+        let mult: MultExpr = from_dyn(&expr).unwrap();
+        assert_eq!(mult.evaluate(), 404);
+        // Expr doesn't know about multiplication, so the conversion fails.
+        assert!(from_dyn::<Expr>(&expr).is_none());
+    }
+
+    #[test]
+    fn cannot_convert_unknown_terms_to_static() {
+        let expr = DynExpr::new(Negate {
+            nested: dyn_integer_literal(1),
+        });
+        assert!(from_dyn::<MultExpr>(&expr).is_none());
+    }
+}
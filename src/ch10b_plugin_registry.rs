@@ -0,0 +1,867 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! In ch10a, a new kind of term only had to implement a couple of traits to be usable in a dynamic
+//! expression.  But *something* still has to know about that term to create instances of it.  In
+//! this chapter we build a registry that plugins can add to at startup: each plugin registers the
+//! kinds of term that it provides, along with a rule for parsing each one, and a rule for
+//! evaluating it.  The host program only needs to know about the registry.
+
+use crate::ch02_open_sum::*;
+use crate::ch03_evaluation::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08h_error_terms::*;
+use crate::ch10a_dynamic_terms::*;
+use crate::limits::{Budget, OverBudget};
+use crate::span::*;
+
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An error that occurs while parsing an expression.
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// The input ended in the middle of an expression.
+    UnexpectedEnd,
+    /// We found a token that can't appear where it did.
+    UnexpectedToken(String),
+    /// No plugin has registered a term with this name.
+    UnknownTerm(String),
+    /// A term was given the wrong number of subexpressions.
+    WrongArity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// The expression is nested more deeply than the registry allows.  `position` is the byte
+    /// offset of the subexpression that went over the limit.
+    TooDeep { position: usize, limit: usize },
+    /// The expression would have used more memory than its budget allows.  `position` is the
+    /// byte offset of the subexpression that went over.
+    OverBudget { position: usize, error: OverBudget },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ParseError::UnexpectedToken(token) => write!(f, "unexpected token `{}`", token),
+            ParseError::UnknownTerm(name) => write!(f, "unknown term `{}`", name),
+            ParseError::WrongArity {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` expects {} subexpressions, but got {}",
+                name, expected, found
+            ),
+            ParseError::TooDeep { position, limit } => write!(
+                f,
+                "expression at offset {} is nested more than {} levels deep",
+                position, limit
+            ),
+            ParseError::OverBudget { position, error } => {
+                write!(f, "expression at offset {}: {}", position, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A syntax error, along with the part of the input that it refers to.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub error: ParseError,
+}
+
+/// A placeholder for part of the input that we couldn't parse.  This lets us keep going after a
+/// syntax error, and still produce an expression for everything around it.
+pub struct SyntaxError {
+    pub span: Span,
+}
+
+impl EvaluateInt for SyntaxError {
+    /// There's no sensible value for a syntax error, so this panics.  Use `Registry::evaluate` if
+    /// you need to evaluate an expression that might contain one.
+    fn evaluate(&self) -> i64 {
+        panic!(
+            "Cannot evaluate a syntax error at offset {}",
+            self.span.start
+        );
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<error>")
+    }
+}
+
+// A syntax error placeholder becomes ch08h's ErrorTerm when we convert to a static expression, so
+// that a language with error terms can hold whatever the parser recovered.
+
+impl<E> FromDyn<E> for ErrorTerm {
+    fn from_dyn<F>(expr: &DynExpr, _from_subexpr: F) -> Option<Self>
+    where
+        F: FnMut(&DynExpr) -> Option<E>,
+    {
+        let term = expr.downcast_ref::<SyntaxError>()?;
+        Some(ErrorTerm {
+            message: "syntax error".to_string(),
+            span: Some(term.span),
+        })
+    }
+}
+
+/// Wraps a subexpression with the part of the input that it was parsed from, so that evaluation
+/// errors can point back at it.  It's otherwise invisible: it evaluates and prints just like the
+/// subexpression that it wraps.
+pub struct Located {
+    pub span: Span,
+    pub expr: DynExpr,
+}
+
+impl EvaluateInt for Located {
+    fn evaluate(&self) -> i64 {
+        self.expr.evaluate()
+    }
+}
+
+impl fmt::Display for Located {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.expr.fmt(f)
+    }
+}
+
+/// An error that occurs while evaluating an expression.
+#[derive(Debug, PartialEq)]
+pub enum EvalError {
+    /// The expression contains a term that no plugin has registered an evaluation rule for.
+    UnregisteredTerm,
+    /// A plugin's evaluation rule failed.
+    Failed(String),
+    /// Evaluation failed inside of the subexpression at `span`.  You only get these for
+    /// expressions that were parsed with `Registry::parse_located`.
+    At { span: Span, error: Box<EvalError> },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UnregisteredTerm => write!(f, "expression contains an unregistered term"),
+            EvalError::Failed(message) => write!(f, "{}", message),
+            EvalError::At { span, error } => write!(f, "{} (at offset {})", error, span.start),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+type ParseRule = Box<dyn Fn(Vec<DynExpr>) -> DynExpr>;
+type EvalRule = Box<
+    dyn Fn(&DynExpr, &mut dyn FnMut(&DynExpr) -> Result<i64, EvalError>) -> Result<i64, EvalError>,
+>;
+
+type LiteralRule = Box<dyn Fn(&str) -> Option<i64>>;
+
+struct ParseEntry {
+    arity: usize,
+    rule: ParseRule,
+}
+
+/// A plugin adds some new kinds of term to a registry.
+pub trait Plugin {
+    fn register(&self, registry: &mut Registry);
+}
+
+/// The default limit on how deeply a parsed expression can be nested.
+pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+/// Knows how to parse and evaluate every kind of term that has been registered with it.  Integer
+/// literals are built in; everything else has to come from a plugin.
+pub struct Registry {
+    parse_rules: HashMap<String, ParseEntry>,
+    eval_rules: HashMap<TypeId, EvalRule>,
+    max_depth: Option<usize>,
+    literal_rule: LiteralRule,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+impl Registry {
+    /// Creates a new registry that only knows about integer literals.
+    pub fn new() -> Registry {
+        let mut registry = Registry {
+            parse_rules: HashMap::new(),
+            eval_rules: HashMap::new(),
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            literal_rule: Box::new(|text| text.parse().ok()),
+        };
+        registry.register_eval::<IntegerLiteral, _>(|term, _eval_subexpr| Ok(term.value));
+        registry.register_eval::<SyntaxError, _>(|term, _eval_subexpr| {
+            Err(EvalError::Failed(format!(
+                "syntax error at offset {}",
+                term.span.start
+            )))
+        });
+        registry.register_eval::<Located, _>(|term, eval_subexpr| {
+            // Only the innermost location is interesting, since that's where the error happened.
+            eval_subexpr(&term.expr).map_err(|error| match error {
+                EvalError::At { .. } => error,
+                error => EvalError::At {
+                    span: term.span,
+                    error: Box::new(error),
+                },
+            })
+        });
+        registry
+    }
+
+    /// Creates a new registry and registers each of the given plugins with it.
+    pub fn with_plugins(plugins: &[&dyn Plugin]) -> Registry {
+        let mut registry = Registry::new();
+        for plugin in plugins {
+            plugin.register(&mut registry);
+        }
+        registry
+    }
+
+    /// Registers a parse rule for a kind of term.  The term is written in the input as
+    /// `(name subexpr...)`, and must have exactly `arity` subexpressions, which are passed to
+    /// `parse` in order.
+    pub fn register_parse<F>(&mut self, name: &str, arity: usize, parse: F)
+    where
+        F: Fn(Vec<DynExpr>) -> DynExpr + 'static,
+    {
+        let rule = Box::new(parse);
+        self.parse_rules
+            .insert(name.to_string(), ParseEntry { arity, rule });
+    }
+
+    /// Registers an evaluation rule for a kind of term.  Like the `Eval` trait from ch08b, the rule
+    /// is given a function that it should use to evaluate any subexpressions.
+    pub fn register_eval<T, F>(&mut self, eval: F)
+    where
+        T: Term,
+        F: Fn(&T, &mut dyn FnMut(&DynExpr) -> Result<i64, EvalError>) -> Result<i64, EvalError>
+            + 'static,
+    {
+        let rule: EvalRule = Box::new(move |expr, eval_subexpr| {
+            // The registry only calls this rule for expressions whose outermost term has type T,
+            // so this downcast can't fail.
+            let term = expr.downcast_ref::<T>().expect("Term has the wrong type");
+            eval(term, eval_subexpr)
+        });
+        self.eval_rules.insert(TypeId::of::<T>(), rule);
+    }
+
+    /// Limits how deeply a parsed expression can be nested.  (A literal has depth 1.)  Parsing,
+    /// evaluating, and dropping an expression all recurse once per level, so without a limit, a
+    /// malicious input can overflow the stack.  `None` removes the limit.
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+
+    /// Replaces the rule for parsing integer literals, which by default only accepts plain decimal
+    /// numbers.  Any token that isn't a parenthesis is passed to this rule, and if it returns
+    /// `None`, the token is reported as unexpected.
+    pub fn set_literal_rule<F>(&mut self, rule: F)
+    where
+        F: Fn(&str) -> Option<i64> + 'static,
+    {
+        self.literal_rule = Box::new(rule);
+    }
+
+    /// Returns whether a plugin has registered a term with the given name.
+    pub fn knows(&self, name: &str) -> bool {
+        self.parse_rules.contains_key(name)
+    }
+
+    /// Parses an s-expression, such as `(add 1 (multiply 2 3))`.  If there are any syntax errors,
+    /// returns the first one.
+    pub fn parse(&self, input: &str) -> Result<DynExpr, ParseError> {
+        let (expr, diagnostics) = self.parse_recovering(input);
+        match diagnostics.into_iter().next() {
+            Some(diagnostic) => Err(diagnostic.error),
+            None => Ok(expr),
+        }
+    }
+
+    /// Parses an s-expression, reporting every syntax error instead of stopping at the first one.
+    /// Each subexpression that has an error is replaced with a `SyntaxError` placeholder, so you
+    /// always get an expression back, which you can print to see what the parser understood.
+    pub fn parse_recovering(&self, input: &str) -> (DynExpr, Vec<Diagnostic>) {
+        self.parse_with(input, false, None)
+    }
+
+    /// Like `parse_recovering`, but also wraps every subexpression in a `Located` term, so that
+    /// `evaluate` can tell you where an evaluation error happened.
+    pub fn parse_located(&self, input: &str) -> (DynExpr, Vec<Diagnostic>) {
+        self.parse_with(input, true, None)
+    }
+
+    /// Like `parse`, but charges every node that it builds against a budget, and fails if the
+    /// expression doesn't fit in it.
+    pub fn parse_within(&self, input: &str, budget: &Budget) -> Result<DynExpr, ParseError> {
+        let (expr, diagnostics) = self.parse_with(input, false, Some(budget));
+        match diagnostics.into_iter().next() {
+            Some(diagnostic) => Err(diagnostic.error),
+            None => Ok(expr),
+        }
+    }
+
+    fn parse_with(
+        &self,
+        input: &str,
+        locate: bool,
+        budget: Option<&Budget>,
+    ) -> (DynExpr, Vec<Diagnostic>) {
+        let mut parser = Parser {
+            registry: self,
+            locate,
+            budget,
+            over_budget: false,
+            tokens: tokenize(input),
+            next: 0,
+            end: input.len(),
+            diagnostics: Vec::new(),
+        };
+        let (expr, _) = parser.parse_expr(1);
+        if let Some(extra) = parser.tokens.get(parser.next) {
+            let last = parser.tokens.last().unwrap_or(extra);
+            let span = extra.span().to(last.span());
+            let error = ParseError::UnexpectedToken(extra.text.clone());
+            parser.report(span, error);
+        }
+        (expr, parser.diagnostics)
+    }
+
+    /// Evaluates an expression using the registered evaluation rules.
+    pub fn evaluate(&self, expr: &DynExpr) -> Result<i64, EvalError> {
+        let rule = self
+            .eval_rules
+            .get(&expr.0.as_any().type_id())
+            .ok_or(EvalError::UnregisteredTerm)?;
+        rule(expr, &mut |subexpr| self.evaluate(subexpr))
+    }
+}
+
+struct Token {
+    text: String,
+    /// The byte offset of the start of the token in the input.
+    position: usize,
+}
+
+impl Token {
+    fn span(&self) -> Span {
+        Span::new(self.position, self.position + self.text.len())
+    }
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+    for (position, ch) in input.char_indices() {
+        if ch == '(' || ch == ')' || ch.is_whitespace() {
+            tokens.extend(current.take());
+            if !ch.is_whitespace() {
+                tokens.push(Token {
+                    text: ch.to_string(),
+                    position,
+                });
+            }
+        } else {
+            current
+                .get_or_insert_with(|| Token {
+                    text: String::new(),
+                    position,
+                })
+                .text
+                .push(ch);
+        }
+    }
+    tokens.extend(current);
+    tokens
+}
+
+// The parser recovers from errors one subexpression at a time.  When something goes wrong inside
+// of a parenthesized form, we report it, keep parsing up to the matching close paren (so that we
+// find any other errors inside the form), and then replace the whole form with a placeholder.
+
+struct Parser<'a> {
+    registry: &'a Registry,
+    /// Whether to wrap each subexpression in a `Located` term.
+    locate: bool,
+    budget: Option<&'a Budget>,
+    /// Whether we've already gone over the budget.  We skip everything after that, instead of
+    /// reporting the same error for every remaining subexpression.
+    over_budget: bool,
+    tokens: Vec<Token>,
+    next: usize,
+    /// The length of the input, which is where we report errors about it ending too soon.
+    end: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+fn placeholder(span: Span) -> DynExpr {
+    DynExpr::new(SyntaxError { span })
+}
+
+impl Parser<'_> {
+    fn report(&mut self, span: Span, error: ParseError) {
+        self.diagnostics.push(Diagnostic { span, error });
+    }
+
+    /// Skips over the next subexpression without parsing it, returning its span.
+    fn skip_subexpr(&mut self) -> Span {
+        let mut span = self.tokens[self.next].span();
+        let mut open = 0;
+        while let Some(token) = self.tokens.get(self.next) {
+            span = span.to(token.span());
+            self.next += 1;
+            match token.text.as_str() {
+                "(" => open += 1,
+                ")" => open -= 1,
+                _ => (),
+            }
+            if open <= 0 {
+                break;
+            }
+        }
+        span
+    }
+
+    /// Charges a newly built subexpression against the budget, replacing it with a placeholder if
+    /// it doesn't fit.
+    fn charge(&mut self, expr: DynExpr, span: Span) -> DynExpr {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return expr,
+        };
+        match budget.charge(1, std::mem::size_of_val(&*expr.0)) {
+            Ok(()) => expr,
+            Err(error) => {
+                let position = span.start;
+                self.report(span, ParseError::OverBudget { position, error });
+                self.over_budget = true;
+                placeholder(span)
+            }
+        }
+    }
+
+    fn parse_expr(&mut self, depth: usize) -> (DynExpr, Span) {
+        if self.over_budget && self.next < self.tokens.len() {
+            let span = self.skip_subexpr();
+            return (placeholder(span), span);
+        }
+        let (expr, span) = self.parse_unlocated(depth);
+        if expr.downcast_ref::<SyntaxError>().is_some() {
+            return (expr, span);
+        }
+        let expr = self.charge(expr, span);
+        if self.locate && expr.downcast_ref::<SyntaxError>().is_none() {
+            return (DynExpr::new(Located { span, expr }), span);
+        }
+        (expr, span)
+    }
+
+    fn parse_unlocated(&mut self, depth: usize) -> (DynExpr, Span) {
+        let token = match self.tokens.get(self.next) {
+            Some(token) => token,
+            None => {
+                let span = Span::at(self.end);
+                self.report(span, ParseError::UnexpectedEnd);
+                return (placeholder(span), span);
+            }
+        };
+        let (text, span) = (token.text.clone(), token.span());
+        if let Some(limit) = self.registry.max_depth {
+            if depth > limit {
+                let position = span.start;
+                let span = self.skip_subexpr();
+                self.report(span, ParseError::TooDeep { position, limit });
+                return (placeholder(span), span);
+            }
+        }
+        self.next += 1;
+        if text != "(" {
+            return match (self.registry.literal_rule)(&text) {
+                Some(value) => (dyn_integer_literal(value), span),
+                None => {
+                    self.report(span, ParseError::UnexpectedToken(text));
+                    (placeholder(span), span)
+                }
+            };
+        }
+        self.parse_form(span, depth)
+    }
+
+    fn parse_form(&mut self, open: Span, depth: usize) -> (DynExpr, Span) {
+        let name = match self.tokens.get(self.next) {
+            Some(token) if token.text == "(" || token.text == ")" => {
+                let (text, span) = (token.text.clone(), token.span());
+                self.report(span, ParseError::UnexpectedToken(text));
+                None
+            }
+            Some(token) => {
+                self.next += 1;
+                Some((token.text.clone(), token.span()))
+            }
+            None => None,
+        };
+        let mut subexprs = Vec::new();
+        let close = loop {
+            match self.tokens.get(self.next) {
+                None => {
+                    let span = Span::at(self.end);
+                    self.report(span, ParseError::UnexpectedEnd);
+                    let span = open.to(span);
+                    return (placeholder(span), span);
+                }
+                Some(token) if token.text == ")" => {
+                    self.next += 1;
+                    break token.span();
+                }
+                Some(_) => subexprs.push(self.parse_expr(depth + 1).0),
+            }
+        };
+        let span = open.to(close);
+        let (name, name_span) = match name {
+            Some(name) => name,
+            None => return (placeholder(span), span),
+        };
+        let entry = match self.registry.parse_rules.get(&name) {
+            Some(entry) => entry,
+            None => {
+                self.report(name_span, ParseError::UnknownTerm(name));
+                return (placeholder(span), span);
+            }
+        };
+        if subexprs.len() != entry.arity {
+            let expected = entry.arity;
+            let found = subexprs.len();
+            let error = ParseError::WrongArity {
+                name,
+                expected,
+                found,
+            };
+            self.report(span, error);
+            return (placeholder(span), span);
+        }
+        ((entry.rule)(subexprs), span)
+    }
+}
+
+/// A plugin that provides the arithmetic terms from the open-sum chapters.
+pub struct ArithmeticPlugin;
+
+impl Plugin for ArithmeticPlugin {
+    fn register(&self, registry: &mut Registry) {
+        registry.register_parse("add", 2, |mut subexprs| {
+            let rhs = subexprs.pop().unwrap();
+            let lhs = subexprs.pop().unwrap();
+            dyn_add(lhs, rhs)
+        });
+        registry.register_eval::<Add<DynExpr>, _>(|term, eval_subexpr| {
+            let (lhs, rhs) = (eval_subexpr(&term.lhs)?, eval_subexpr(&term.rhs)?);
+            lhs.checked_add(rhs)
+                .ok_or_else(|| EvalError::Failed("addition overflowed".to_string()))
+        });
+        registry.register_parse("multiply", 2, |mut subexprs| {
+            let rhs = subexprs.pop().unwrap();
+            let lhs = subexprs.pop().unwrap();
+            dyn_multiply(lhs, rhs)
+        });
+        registry.register_eval::<Multiply<DynExpr>, _>(|term, eval_subexpr| {
+            let (lhs, rhs) = (eval_subexpr(&term.lhs)?, eval_subexpr(&term.rhs)?);
+            lhs.checked_mul(rhs)
+                .ok_or_else(|| EvalError::Failed("multiplication overflowed".to_string()))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch11a_format_options::{parse_literal, DigitGrouping};
+    use crate::limits::Resource;
+
+    #[test]
+    fn can_parse_and_evaluate() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let expr = registry.parse("(add (multiply 80 5) 4)").unwrap();
+        assert_eq!(expr.to_string(), "((80 * 5) + 4)");
+        assert_eq!(registry.evaluate(&expr), Ok(404));
+    }
+
+    #[test]
+    fn reports_overflow() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let expr = registry
+            .parse("(add 1 (multiply 9223372036854775807 2))")
+            .unwrap();
+        assert_eq!(
+            registry.evaluate(&expr),
+            Err(EvalError::Failed("multiplication overflowed".to_string()))
+        );
+        let expr = registry.parse("(add 9223372036854775807 1)").unwrap();
+        assert_eq!(
+            registry.evaluate(&expr),
+            Err(EvalError::Failed("addition overflowed".to_string()))
+        );
+    }
+
+    #[test]
+    fn can_parse_custom_literals() {
+        let mut registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        assert!(registry.parse("(add 0x10 1_000)").is_err());
+        // ch11a's literal parser reads back anything that its formatter prints.
+        let grouping = Some(DigitGrouping::nibbles());
+        registry.set_literal_rule(move |text| parse_literal(text, grouping));
+        let expr = registry.parse("(add 0x10 1_000)").unwrap();
+        assert_eq!(registry.evaluate(&expr), Ok(1016));
+    }
+
+    #[test]
+    fn can_parse_within_a_budget() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let budget = Budget::new();
+        let expr = registry.parse_within("(add 1 (multiply 2 3))", &budget);
+        assert_eq!(registry.evaluate(&expr.unwrap()), Ok(7));
+        assert_eq!(budget.nodes_used(), 5);
+        assert!(budget.bytes_used() > 0);
+
+        let budget = Budget::new().with_max_nodes(3);
+        let error = registry
+            .parse_within("(add 1 (multiply 2 3))", &budget)
+            .err();
+        assert_eq!(
+            error,
+            Some(ParseError::OverBudget {
+                position: 7,
+                error: OverBudget {
+                    resource: Resource::Nodes,
+                    limit: 3,
+                    requested: 4,
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_deeply_nested_input() {
+        let mut registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        registry.set_max_depth(Some(3));
+        assert!(registry.parse("(add (add 1 2) 3)").is_ok());
+        assert_eq!(
+            registry.parse("(add (add (add 1 2) 3) 4)").err(),
+            Some(ParseError::TooDeep {
+                position: 15,
+                limit: 3
+            })
+        );
+        // The default limit keeps a pathological input from overflowing the stack.
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let input = format!("{}1{}", "(add ".repeat(100_000), " 2)".repeat(100_000));
+        assert_eq!(
+            registry.parse(&input).err(),
+            Some(ParseError::TooDeep {
+                position: 5 * DEFAULT_MAX_DEPTH,
+                limit: DEFAULT_MAX_DEPTH
+            })
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        assert_eq!(
+            registry.parse("(add 1").err(),
+            Some(ParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            registry.parse("(subtract 1 2)").err(),
+            Some(ParseError::UnknownTerm("subtract".to_string()))
+        );
+        assert_eq!(
+            registry.parse("(add 1 2 3)").err(),
+            Some(ParseError::WrongArity {
+                name: "add".to_string(),
+                expected: 2,
+                found: 3,
+            })
+        );
+        assert_eq!(
+            registry.parse("1 2").err(),
+            Some(ParseError::UnexpectedToken("2".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_every_syntax_error() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let input = "(add (subtract 1 2) (multiply (add 3 x) 4 5))";
+        let (expr, diagnostics) = registry.parse_recovering(input);
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    span: Span::new(6, 14),
+                    error: ParseError::UnknownTerm("subtract".to_string()),
+                },
+                Diagnostic {
+                    span: Span::new(37, 38),
+                    error: ParseError::UnexpectedToken("x".to_string()),
+                },
+                Diagnostic {
+                    span: Span::new(20, 44),
+                    error: ParseError::WrongArity {
+                        name: "multiply".to_string(),
+                        expected: 2,
+                        found: 3,
+                    },
+                },
+            ]
+        );
+        assert_eq!(expr.to_string(), "(<error> + <error>)");
+        assert_eq!(
+            registry.evaluate(&expr),
+            Err(EvalError::Failed("syntax error at offset 5".to_string()))
+        );
+    }
+
+    #[test]
+    fn keeps_parsing_around_errors() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let (expr, diagnostics) = registry.parse_recovering("(add (multiply 2 y) 3) 4");
+        assert_eq!(expr.to_string(), "((2 * <error>) + 3)");
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    span: Span::new(17, 18),
+                    error: ParseError::UnexpectedToken("y".to_string()),
+                },
+                Diagnostic {
+                    span: Span::new(23, 24),
+                    error: ParseError::UnexpectedToken("4".to_string()),
+                },
+            ]
+        );
+        let (expr, _) = registry.parse_recovering("(add (multiply 2 y) 3) 4");
+        let resilient: ResilientExpr = from_dyn(&expr).unwrap();
+        assert_eq!(resilient.to_string(), "((2 * <error: syntax error>) + 3)");
+        let (expr, diagnostics) = registry.parse_recovering("(add 1");
+        assert_eq!(expr.to_string(), "<error>");
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                span: Span::at(6),
+                error: ParseError::UnexpectedEnd,
+            }]
+        );
+    }
+
+    #[test]
+    fn can_locate_evaluation_errors() {
+        let mut registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        registry.register_parse("fail", 0, |_| DynExpr::new(Fail));
+        registry.register_eval::<Fail, _>(|_, _| Err(EvalError::Failed("oops".to_string())));
+        let (expr, diagnostics) = registry.parse_located("(add 1 (multiply (fail) 2))");
+        assert!(diagnostics.is_empty());
+        assert_eq!(expr.to_string(), "(1 + (<fail> * 2))");
+        assert_eq!(
+            registry.evaluate(&expr),
+            Err(EvalError::At {
+                span: Span::new(17, 23),
+                error: Box::new(EvalError::Failed("oops".to_string())),
+            })
+        );
+    }
+
+    struct Fail;
+
+    impl EvaluateInt for Fail {
+        fn evaluate(&self) -> i64 {
+            panic!("oops");
+        }
+    }
+
+    impl fmt::Display for Fail {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "<fail>")
+        }
+    }
+
+    // Here's a plugin that the registry has never heard of.
+
+    struct Negate {
+        nested: DynExpr,
+    }
+
+    impl EvaluateInt for Negate {
+        fn evaluate(&self) -> i64 {
+            -self.nested.evaluate()
+        }
+    }
+
+    impl fmt::Display for Negate {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "-{}", self.nested)
+        }
+    }
+
+    struct NegatePlugin;
+
+    impl Plugin for NegatePlugin {
+        fn register(&self, registry: &mut Registry) {
+            registry.register_parse("negate", 1, |mut subexprs| {
+                DynExpr::new(Negate {
+                    nested: subexprs.pop().unwrap(),
+                })
+            });
+            registry
+                .register_eval::<Negate, _>(|term, eval_subexpr| Ok(-eval_subexpr(&term.nested)?));
+        }
+    }
+
+    #[test]
+    fn can_register_new_plugins() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        assert!(!registry.knows("negate"));
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin, &NegatePlugin]);
+        assert!(registry.knows("negate"));
+        let expr = registry.parse("(add (negate 1) 3)").unwrap();
+        assert_eq!(expr.to_string(), "(-1 + 3)");
+        assert_eq!(registry.evaluate(&expr), Ok(2));
+    }
+
+    #[test]
+    fn cannot_evaluate_unregistered_terms() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let expr = DynExpr::new(Negate {
+            nested: dyn_integer_literal(1),
+        });
+        assert_eq!(registry.evaluate(&expr), Err(EvalError::UnregisteredTerm));
+    }
+}
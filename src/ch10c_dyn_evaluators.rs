@@ -0,0 +1,209 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! By now we've got a lot of different ways to evaluate an expression: ch03's EvaluateInt, ch07's
+//! EvaluateAny with its choice of value types, ch08b's open recursion, and ch10b's registry.
+//! They're all generic, which means that you have to pick one at compile time.  What if an
+//! application wants to keep several of them in a table, and choose one at runtime?
+//!
+//! For that we need an object-safe evaluator trait.  Its method can't be generic, so it takes the
+//! expression as a trait object too, and each evaluator checks whether it's been handed an
+//! expression type that it understands.
+
+use crate::ch03_evaluation::*;
+use crate::ch07b_generic_evaluation::*;
+use crate::ch07c_pair_evaluation::*;
+use crate::ch07d_safer_pair_evaluation::*;
+use crate::ch08b_open_recursion_evaluation::{Eval, Evaluate};
+use crate::ch10a_dynamic_terms::*;
+use crate::ch10b_plugin_registry::*;
+
+use std::any::Any;
+use std::marker::PhantomData;
+
+/// Any expression, of any type, with its type erased.
+pub trait AnyExpr: Any {
+    fn as_any(&self) -> &dyn Any;
+    fn type_name(&self) -> &'static str;
+}
+
+impl<T: Any> AnyExpr for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+}
+
+/// An evaluator that can be stored as a trait object.
+pub trait DynEvaluator {
+    fn eval_i64(&self, expr: &dyn AnyExpr) -> Result<i64, EvalError>;
+}
+
+/// Recovers the concrete type of an expression, or explains why we couldn't.
+fn downcast<E: Any>(expr: &dyn AnyExpr) -> Result<&E, EvalError> {
+    expr.as_any().downcast_ref::<E>().ok_or_else(|| {
+        EvalError::Failed(format!(
+            "expected a {}, but got a {}",
+            std::any::type_name::<E>(),
+            expr.type_name()
+        ))
+    })
+}
+
+// Then an adapter for each of our generic evaluators.  Each one is a zero-sized type that
+// remembers which expression type it works with.
+
+/// Evaluates `E`s using ch03's EvaluateInt.
+pub struct IntEvaluator<E>(PhantomData<fn(&E)>);
+
+impl<E> Default for IntEvaluator<E> {
+    fn default() -> IntEvaluator<E> {
+        IntEvaluator(PhantomData)
+    }
+}
+
+impl<E> DynEvaluator for IntEvaluator<E>
+where
+    E: Any + EvaluateInt,
+{
+    fn eval_i64(&self, expr: &dyn AnyExpr) -> Result<i64, EvalError> {
+        Ok(downcast::<E>(expr)?.evaluate())
+    }
+}
+
+/// Evaluates `E`s using ch08b's open-recursion evaluation rules.
+pub struct OpenEvaluator<E>(PhantomData<fn(&E)>);
+
+impl<E> Default for OpenEvaluator<E> {
+    fn default() -> OpenEvaluator<E> {
+        OpenEvaluator(PhantomData)
+    }
+}
+
+impl<E> DynEvaluator for OpenEvaluator<E>
+where
+    E: Any + Eval<i64, E>,
+{
+    fn eval_i64(&self, expr: &dyn AnyExpr) -> Result<i64, EvalError> {
+        Ok(Evaluate::evaluate::<i64>(downcast::<E>(expr)?))
+    }
+}
+
+/// Evaluates `E`s into ch07d's SafeIntOrPair, so that type errors (like adding a pair) turn into
+/// errors instead of panics.  Expressions that evaluate to a pair are errors too, since we promised
+/// an i64.
+pub struct SafeEvaluator<E>(PhantomData<fn(&E)>);
+
+impl<E> Default for SafeEvaluator<E> {
+    fn default() -> SafeEvaluator<E> {
+        SafeEvaluator(PhantomData)
+    }
+}
+
+impl<E> DynEvaluator for SafeEvaluator<E>
+where
+    E: Any + EvaluateAny<SafeIntOrPair>,
+{
+    fn eval_i64(&self, expr: &dyn AnyExpr) -> Result<i64, EvalError> {
+        match evaluate_any::<SafeIntOrPair, E>(downcast::<E>(expr)?) {
+            SafeIntOrPair(Some(IntOrPair::Int(value))) => Ok(value),
+            SafeIntOrPair(Some(IntOrPair::Pair(_, _))) => Err(EvalError::Failed(
+                "expression evaluated to a pair".to_string(),
+            )),
+            SafeIntOrPair(None) => Err(EvalError::Failed("type error".to_string())),
+        }
+    }
+}
+
+/// Evaluates DynExprs using a plugin registry's evaluation rules.
+pub struct RegistryEvaluator<'a>(pub &'a Registry);
+
+impl DynEvaluator for RegistryEvaluator<'_> {
+    fn eval_i64(&self, expr: &dyn AnyExpr) -> Result<i64, EvalError> {
+        self.0.evaluate(downcast::<DynExpr>(expr)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch02_open_sum::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+    use crate::ch07a_pairs::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn can_select_evaluators_at_runtime() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let mut evaluators: HashMap<&str, Box<dyn DynEvaluator>> = HashMap::new();
+        evaluators.insert("int", Box::new(IntEvaluator::<Expr>::default()));
+        evaluators.insert("open", Box::new(OpenEvaluator::<Expr>::default()));
+        evaluators.insert("registry", Box::new(RegistryEvaluator(&registry)));
+
+        let expr: Expr = add(integer_literal(118), integer_literal(1219));
+        let dyn_expr = registry.parse("(add 118 1219)").unwrap();
+        for (name, input) in [
+            ("int", &expr as &dyn AnyExpr),
+            ("open", &expr),
+            ("registry", &dyn_expr),
+        ] {
+            assert_eq!(evaluators[name].eval_i64(input), Ok(1337));
+        }
+    }
+
+    #[test]
+    fn rejects_the_wrong_expression_type() {
+        let evaluator: Box<dyn DynEvaluator> = Box::new(IntEvaluator::<Expr>::default());
+        let expr: MultExpr = multiply(integer_literal(6), integer_literal(7));
+        match evaluator.eval_i64(&expr) {
+            Err(EvalError::Failed(message)) => assert!(message.contains("MultExpr")),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn can_evaluate_pairs_safely() {
+        let evaluator: Box<dyn DynEvaluator> = Box::new(SafeEvaluator::<PairExpr>::default());
+        let expr: PairExpr = first(pair(integer_literal(7), integer_literal(6)));
+        assert_eq!(evaluator.eval_i64(&expr), Ok(7));
+        let expr: PairExpr = add(
+            pair(integer_literal(1), integer_literal(2)),
+            integer_literal(3),
+        );
+        assert!(evaluator.eval_i64(&expr).is_err());
+        let expr: PairExpr = pair(integer_literal(1), integer_literal(2));
+        assert!(evaluator.eval_i64(&expr).is_err());
+    }
+
+    #[test]
+    fn can_mix_evaluators_for_different_expression_types() {
+        let evaluators: Vec<Box<dyn DynEvaluator>> = vec![
+            Box::new(IntEvaluator::<Expr>::default()),
+            Box::new(IntEvaluator::<MultExpr>::default()),
+            Box::new(IntEvaluator::<DynExpr>::default()),
+        ];
+        let expr: MultExpr = multiply(integer_literal(6), integer_literal(7));
+        let results: Vec<bool> = evaluators
+            .iter()
+            .map(|evaluator| evaluator.eval_i64(&expr).is_ok())
+            .collect();
+        assert_eq!(results, vec![false, true, false]);
+    }
+}
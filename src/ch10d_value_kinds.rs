@@ -0,0 +1,522 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! ch10c let us pick an evaluator at runtime.  The other thing that we've been picking at compile
+//! time is the value type: the same expression can be evaluated into an i64, or into ch07d's
+//! SafeIntOrPair, or into anything else that has the right operators.  A REPL might want to let the
+//! user choose, say with a `:set values rational` command.
+//!
+//! We can't return "some value type" from a function without knowing which one at compile time,
+//! so we wrap each of the statically-typed evaluations in one entry point that returns an enum.
+//! Along the way, here are a few more value types to choose from — including two semirings,
+//! which reinterpret what `+` and `*` mean without changing the expressions at all.
+
+use crate::ch07c_pair_evaluation::*;
+use crate::ch07d_safer_pair_evaluation::*;
+use crate::ch08b_open_recursion_evaluation::{Eval, Evaluate};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// A range of possible values.  Arithmetic on intervals gives you bounds on the result.  If a
+/// bound would overflow, the result widens to `UNBOUNDED`, which is always a safe answer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+    pub lo: i64,
+    pub hi: i64,
+}
+
+impl Interval {
+    /// Every value that an i64 can have.
+    pub const UNBOUNDED: Interval = Interval {
+        lo: i64::MIN,
+        hi: i64::MAX,
+    };
+}
+
+impl From<i64> for Interval {
+    fn from(value: i64) -> Interval {
+        Interval {
+            lo: value,
+            hi: value,
+        }
+    }
+}
+
+impl std::ops::Add for Interval {
+    type Output = Interval;
+    fn add(self, other: Interval) -> Interval {
+        match (self.lo.checked_add(other.lo), self.hi.checked_add(other.hi)) {
+            (Some(lo), Some(hi)) => Interval { lo, hi },
+            _ => Interval::UNBOUNDED,
+        }
+    }
+}
+
+impl std::ops::Mul for Interval {
+    type Output = Interval;
+    fn mul(self, other: Interval) -> Interval {
+        let products = [
+            self.lo.checked_mul(other.lo),
+            self.lo.checked_mul(other.hi),
+            self.hi.checked_mul(other.lo),
+            self.hi.checked_mul(other.hi),
+        ];
+        let products: Option<Vec<i64>> = products.iter().copied().collect();
+        match products {
+            Some(products) => Interval {
+                lo: *products.iter().min().unwrap(),
+                hi: *products.iter().max().unwrap(),
+            },
+            None => Interval::UNBOUNDED,
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+/// A fraction, always stored in lowest terms with a positive denominator.  Like ch07d's
+/// SafeIntOrPair, arithmetic that overflows gives you an error value instead of panicking, and
+/// arithmetic on the error value gives you the error value again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rational {
+    numerator: i64,
+    denominator: i64,
+}
+
+impl Rational {
+    /// The result of arithmetic that overflowed.  (No fraction has a zero denominator, so this
+    /// can't be mistaken for one.)
+    pub const OVERFLOW: Rational = Rational {
+        numerator: 0,
+        denominator: 0,
+    };
+
+    pub fn new(numerator: i64, denominator: i64) -> Rational {
+        assert!(denominator != 0, "denominator cannot be zero");
+        Rational::reduce(i128::from(numerator), i128::from(denominator))
+    }
+
+    // The numerator and denominator of a sum or product of two fractions always fit in an i128,
+    // so we only have to check whether they fit in an i64 once they're in lowest terms.
+    fn reduce(numerator: i128, denominator: i128) -> Rational {
+        let sign = denominator.signum();
+        let divisor = gcd(numerator, denominator).max(1);
+        let numerator = i64::try_from(sign * numerator / divisor);
+        let denominator = i64::try_from(sign * denominator / divisor);
+        match (numerator, denominator) {
+            (Ok(numerator), Ok(denominator)) => Rational {
+                numerator,
+                denominator,
+            },
+            _ => Rational::OVERFLOW,
+        }
+    }
+
+    pub fn is_overflow(&self) -> bool {
+        self.denominator == 0
+    }
+
+    fn wide(self) -> (i128, i128) {
+        (i128::from(self.numerator), i128::from(self.denominator))
+    }
+
+    pub fn numerator(&self) -> i64 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> i64 {
+        self.denominator
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(value: i64) -> Rational {
+        Rational::new(value, 1)
+    }
+}
+
+impl std::ops::Add for Rational {
+    type Output = Rational;
+    fn add(self, other: Rational) -> Rational {
+        if self.is_overflow() || other.is_overflow() {
+            return Rational::OVERFLOW;
+        }
+        let (lhs, rhs) = (self.wide(), other.wide());
+        Rational::reduce(lhs.0 * rhs.1 + rhs.0 * lhs.1, lhs.1 * rhs.1)
+    }
+}
+
+impl std::ops::Mul for Rational {
+    type Output = Rational;
+    fn mul(self, other: Rational) -> Rational {
+        if self.is_overflow() || other.is_overflow() {
+            return Rational::OVERFLOW;
+        }
+        let (lhs, rhs) = (self.wide(), other.wide());
+        Rational::reduce(lhs.0 * rhs.0, lhs.1 * rhs.1)
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_overflow() {
+            write!(f, "overflow")
+        } else if self.denominator == 1 {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+/// The max-plus, or tropical, semiring: `+` takes the larger of its operands, and `*` adds them.
+/// Evaluating an expression this way finds the heaviest path through it, which is how you'd
+/// compute, say, the critical path through a schedule.  Negative infinity is the identity for max,
+/// so we include it too.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct MaxPlus(Option<i64>);
+
+impl MaxPlus {
+    /// The additive identity, which is smaller than every finite value.
+    pub const NEG_INFINITY: MaxPlus = MaxPlus(None);
+
+    /// Returns the value, or None if it's negative infinity.
+    pub fn value(&self) -> Option<i64> {
+        self.0
+    }
+}
+
+impl From<i64> for MaxPlus {
+    fn from(value: i64) -> MaxPlus {
+        MaxPlus(Some(value))
+    }
+}
+
+impl std::ops::Add for MaxPlus {
+    type Output = MaxPlus;
+    fn add(self, other: MaxPlus) -> MaxPlus {
+        self.max(other)
+    }
+}
+
+impl std::ops::Mul for MaxPlus {
+    type Output = MaxPlus;
+    // In this semiring, multiplication really is addition.
+    #[allow(clippy::suspicious_arithmetic_impl)]
+    fn mul(self, other: MaxPlus) -> MaxPlus {
+        match (self.0, other.0) {
+            (Some(lhs), Some(rhs)) => MaxPlus(Some(lhs + rhs)),
+            _ => MaxPlus::NEG_INFINITY,
+        }
+    }
+}
+
+impl fmt::Display for MaxPlus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "{}", value),
+            None => write!(f, "-∞"),
+        }
+    }
+}
+
+/// The boolean semiring: `+` is or, and `*` is and.  A literal is true if it's nonzero.  Evaluating
+/// an expression this way tells you whether any path through it avoids every zero.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BoolSemiring(pub bool);
+
+impl From<i64> for BoolSemiring {
+    fn from(value: i64) -> BoolSemiring {
+        BoolSemiring(value != 0)
+    }
+}
+
+impl std::ops::Add for BoolSemiring {
+    type Output = BoolSemiring;
+    fn add(self, other: BoolSemiring) -> BoolSemiring {
+        BoolSemiring(self.0 || other.0)
+    }
+}
+
+impl std::ops::Mul for BoolSemiring {
+    type Output = BoolSemiring;
+    fn mul(self, other: BoolSemiring) -> BoolSemiring {
+        BoolSemiring(self.0 && other.0)
+    }
+}
+
+impl fmt::Display for BoolSemiring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Which value type to evaluate into.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ValueKind {
+    Int,
+    Interval,
+    Rational,
+    Safe,
+    MaxPlus,
+    Boolean,
+}
+
+impl ValueKind {
+    pub const ALL: [ValueKind; 6] = [
+        ValueKind::Int,
+        ValueKind::Interval,
+        ValueKind::Rational,
+        ValueKind::Safe,
+        ValueKind::MaxPlus,
+        ValueKind::Boolean,
+    ];
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueKind::Int => write!(f, "i64"),
+            ValueKind::Interval => write!(f, "interval"),
+            ValueKind::Rational => write!(f, "rational"),
+            ValueKind::Safe => write!(f, "safe"),
+            ValueKind::MaxPlus => write!(f, "max-plus"),
+            ValueKind::Boolean => write!(f, "boolean"),
+        }
+    }
+}
+
+/// The name of a value kind that we don't know about.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnknownValueKind(pub String);
+
+impl fmt::Display for UnknownValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown value kind `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownValueKind {}
+
+/// Parses the same names that Display produces, so that a CLI can take them as arguments.
+impl FromStr for ValueKind {
+    type Err = UnknownValueKind;
+    fn from_str(name: &str) -> Result<ValueKind, UnknownValueKind> {
+        ValueKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.to_string() == name)
+            .ok_or_else(|| UnknownValueKind(name.to_string()))
+    }
+}
+
+/// The result of evaluating into a value kind chosen at runtime.
+#[derive(Debug, PartialEq)]
+pub enum DynValue {
+    Int(i64),
+    Interval(Interval),
+    Rational(Rational),
+    Safe(SafeIntOrPair),
+    MaxPlus(MaxPlus),
+    Boolean(BoolSemiring),
+}
+
+impl DynValue {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            DynValue::Int(_) => ValueKind::Int,
+            DynValue::Interval(_) => ValueKind::Interval,
+            DynValue::Rational(_) => ValueKind::Rational,
+            DynValue::Safe(_) => ValueKind::Safe,
+            DynValue::MaxPlus(_) => ValueKind::MaxPlus,
+            DynValue::Boolean(_) => ValueKind::Boolean,
+        }
+    }
+}
+
+impl fmt::Display for DynValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DynValue::Int(value) => write!(f, "{}", value),
+            DynValue::Interval(value) => write!(f, "{}", value),
+            DynValue::Rational(value) => write!(f, "{}", value),
+            DynValue::Safe(SafeIntOrPair(Some(value))) => write!(f, "{}", IntOrPairDisplay(value)),
+            DynValue::Safe(SafeIntOrPair(None)) => write!(f, "error"),
+            DynValue::MaxPlus(value) => write!(f, "{}", value),
+            DynValue::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+struct IntOrPairDisplay<'a>(&'a IntOrPair);
+
+impl fmt::Display for IntOrPairDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            IntOrPair::Int(value) => write!(f, "{}", value),
+            IntOrPair::Pair(first, second) => write!(
+                f,
+                "({}, {})",
+                IntOrPairDisplay(first),
+                IntOrPairDisplay(second)
+            ),
+        }
+    }
+}
+
+/// Evaluates an expression into the value type selected by `kind`.  The expression has to support
+/// every kind, since we don't know until runtime which one we'll need.
+pub fn eval_as<E>(expr: &E, kind: ValueKind) -> DynValue
+where
+    E: Eval<i64, E> + Eval<Interval, E> + Eval<Rational, E> + Eval<SafeIntOrPair, E>,
+    E: Eval<MaxPlus, E> + Eval<BoolSemiring, E>,
+{
+    match kind {
+        ValueKind::Int => DynValue::Int(expr.evaluate()),
+        ValueKind::Interval => DynValue::Interval(expr.evaluate()),
+        ValueKind::Rational => DynValue::Rational(expr.evaluate()),
+        ValueKind::Safe => DynValue::Safe(expr.evaluate()),
+        ValueKind::MaxPlus => DynValue::MaxPlus(expr.evaluate()),
+        ValueKind::Boolean => DynValue::Boolean(expr.evaluate()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch02_open_sum::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+
+    #[test]
+    fn can_select_value_kinds_at_runtime() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let results: Vec<String> = ValueKind::ALL
+            .iter()
+            .map(|kind| eval_as(&expr, *kind))
+            .map(|value| format!("{}: {}", value.kind(), value))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                "i64: 404",
+                "interval: [404, 404]",
+                "rational: 404",
+                "safe: 404",
+                "max-plus: 85",
+                "boolean: true"
+            ]
+        );
+    }
+
+    #[test]
+    fn can_parse_value_kinds() {
+        assert_eq!("rational".parse(), Ok(ValueKind::Rational));
+        assert_eq!(
+            "float".parse::<ValueKind>(),
+            Err(UnknownValueKind("float".to_string()))
+        );
+        for kind in ValueKind::ALL.iter() {
+            assert_eq!(kind.to_string().parse(), Ok(*kind));
+        }
+    }
+
+    #[test]
+    fn can_evaluate_plain_expressions() {
+        let expr: Expr = add(integer_literal(118), integer_literal(1219));
+        assert_eq!(eval_as(&expr, ValueKind::Int), DynValue::Int(1337));
+    }
+
+    #[test]
+    fn can_do_interval_arithmetic() {
+        let x = Interval { lo: -2, hi: 3 };
+        assert_eq!(x + Interval::from(1), Interval { lo: -1, hi: 4 });
+        assert_eq!(x * x, Interval { lo: -6, hi: 9 });
+    }
+
+    #[test]
+    fn intervals_widen_instead_of_overflowing() {
+        let x = Interval { lo: -2, hi: 3 };
+        assert_eq!(Interval::from(i64::MAX) + x, Interval::UNBOUNDED);
+        assert_eq!(x * Interval::from(i64::MIN), Interval::UNBOUNDED);
+        assert_eq!(Interval::UNBOUNDED * Interval::from(0), Interval::from(0));
+    }
+
+    #[test]
+    fn can_do_rational_arithmetic() {
+        let half = Rational::new(2, 4);
+        assert_eq!(half.to_string(), "1/2");
+        assert_eq!((half + Rational::new(1, -3)).to_string(), "1/6");
+        assert_eq!((half * Rational::from(6)).to_string(), "3");
+    }
+
+    #[test]
+    fn rational_overflow_is_an_error_value() {
+        let max = Rational::from(i64::MAX);
+        assert!((max + Rational::from(1)).is_overflow());
+        assert!((max * Rational::from(2)).is_overflow());
+        assert!(Rational::new(i64::MIN, -1).is_overflow());
+        assert!((Rational::OVERFLOW * Rational::from(0)).is_overflow());
+        assert_eq!(Rational::OVERFLOW.to_string(), "overflow");
+        // The cross-multiplication overflows an i64, but the result doesn't.
+        let tiny = Rational::new(1, i64::MAX);
+        assert_eq!(tiny + tiny, Rational::new(2, i64::MAX));
+        assert_eq!((max * tiny).to_string(), "1");
+    }
+
+    #[test]
+    fn can_find_heaviest_paths() {
+        // Two tasks that run in parallel, taking 3 and 5 steps, followed by one that takes 2.
+        let schedule: MultExpr = multiply(
+            add(integer_literal(3), integer_literal(5)),
+            integer_literal(2),
+        );
+        assert_eq!(schedule.evaluate::<MaxPlus>(), MaxPlus::from(7));
+        let never = MaxPlus::NEG_INFINITY;
+        assert_eq!(never + MaxPlus::from(-100), MaxPlus::from(-100));
+        assert_eq!((never * MaxPlus::from(100)).to_string(), "-∞");
+    }
+
+    #[test]
+    fn can_evaluate_in_the_boolean_semiring() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(1), integer_literal(0)),
+            integer_literal(0),
+        );
+        assert_eq!(expr.evaluate::<BoolSemiring>(), BoolSemiring(false));
+        let expr: MultExpr = add(
+            multiply(integer_literal(1), integer_literal(0)),
+            integer_literal(7),
+        );
+        assert_eq!(expr.evaluate::<BoolSemiring>(), BoolSemiring(true));
+    }
+}
@@ -0,0 +1,210 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Back in ch07b, evaluation became generic over the value type: give `+` and `*` a different
+//! meaning by defining a new type with different `Add` and `Mul` impls.  That's the right approach
+//! when the meaning is fixed at compile time.  But if you're experimenting — is this expression
+//! more interesting over the max-plus semiring, or min-plus, or with saturating arithmetic? — you
+//! don't want to define a new type for each one.
+//!
+//! This chapter's evaluator looks up what each operator means in a table, at runtime.  The table
+//! maps each kind of term (by the same names that telemetry uses) to a function, and you can
+//! replace any entry whenever you like.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::telemetry::TermKind;
+
+use std::collections::HashMap;
+use std::fmt;
+
+type Operator<V> = Box<dyn Fn(V, V) -> V>;
+
+/// What each kind of term means, for values of type `V`.
+pub struct Semantics<V> {
+    literal: Box<dyn Fn(i64) -> V>,
+    operators: HashMap<&'static str, Operator<V>>,
+}
+
+impl<V> Semantics<V> {
+    /// Creates a table that knows how to interpret literals, but doesn't have any operators yet.
+    pub fn new<F>(literal: F) -> Semantics<V>
+    where
+        F: Fn(i64) -> V + 'static,
+    {
+        Semantics {
+            literal: Box::new(literal),
+            operators: HashMap::new(),
+        }
+    }
+
+    /// Sets (or replaces) the meaning of a binary operator, such as `"add"` or `"multiply"`.
+    pub fn set_operator<F>(&mut self, kind: &'static str, operator: F)
+    where
+        F: Fn(V, V) -> V + 'static,
+    {
+        self.operators.insert(kind, Box::new(operator));
+    }
+
+    pub fn with_operator<F>(mut self, kind: &'static str, operator: F) -> Semantics<V>
+    where
+        F: Fn(V, V) -> V + 'static,
+    {
+        self.set_operator(kind, operator);
+        self
+    }
+
+    fn apply(&self, kind: &'static str, lhs: V, rhs: V) -> Result<V, MissingOperator> {
+        let operator = self
+            .operators
+            .get(kind)
+            .ok_or(MissingOperator { term: kind })?;
+        Ok(operator(lhs, rhs))
+    }
+}
+
+impl Semantics<i64> {
+    /// The usual meaning of each operator.
+    pub fn standard() -> Semantics<i64> {
+        Semantics::new(|value| value)
+            .with_operator(Add::<()>::NAME, |lhs, rhs| lhs + rhs)
+            .with_operator(Multiply::<()>::NAME, |lhs, rhs| lhs * rhs)
+    }
+
+    /// The max-plus ("tropical") semiring, where `+` means max, and `*` means +.
+    pub fn max_plus() -> Semantics<i64> {
+        Semantics::new(|value| value)
+            .with_operator(Add::<()>::NAME, i64::max)
+            .with_operator(Multiply::<()>::NAME, |lhs, rhs| lhs + rhs)
+    }
+}
+
+/// The table doesn't say what a term means.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MissingOperator {
+    pub term: &'static str,
+}
+
+impl fmt::Display for MissingOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no semantics for `{}`", self.term)
+    }
+}
+
+impl std::error::Error for MissingOperator {}
+
+/// An algebra that evaluates an expression using a table of semantics.
+pub struct TableEvaluator<'a, V> {
+    pub semantics: &'a Semantics<V>,
+}
+
+impl<'a, V, E> Algebra<IntegerLiteral, E, Result<V, MissingOperator>> for TableEvaluator<'a, V> {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Result<V, MissingOperator>
+    where
+        F: FnMut(&E) -> Result<V, MissingOperator>,
+    {
+        Ok((self.semantics.literal)(term.value))
+    }
+}
+
+macro_rules! binary_operators {
+    ($($term:ident),+ $(,)?) => {
+        $(
+            impl<'a, V, E> Algebra<$term<E>, E, Result<V, MissingOperator>>
+                for TableEvaluator<'a, V>
+            {
+                fn apply<F>(&self, term: &$term<E>, mut recurse: F) -> Result<V, MissingOperator>
+                where
+                    F: FnMut(&E) -> Result<V, MissingOperator>,
+                {
+                    let lhs = recurse(&term.lhs)?;
+                    let rhs = recurse(&term.rhs)?;
+                    self.semantics.apply($term::<E>::NAME, lhs, rhs)
+                }
+            }
+        )+
+    };
+}
+
+binary_operators!(Add, Multiply);
+
+/// Evaluates an expression using a table of semantics.
+pub fn evaluate_with<V, E>(semantics: &Semantics<V>, expr: &E) -> Result<V, MissingOperator>
+where
+    E: Expression,
+    for<'a> TableEvaluator<'a, V>: Algebra<E::Signature, E, Result<V, MissingOperator>>,
+{
+    mcata(&TableEvaluator { semantics }, expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_use_standard_semantics() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(evaluate_with(&Semantics::standard(), &expr), Ok(404));
+    }
+
+    #[test]
+    fn can_use_tropical_semantics() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        // max(80 + 5, 4)
+        assert_eq!(evaluate_with(&Semantics::max_plus(), &expr), Ok(85));
+    }
+
+    #[test]
+    fn can_override_operators_at_runtime() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let mut semantics = Semantics::standard();
+        semantics.set_operator("add", i64::min);
+        assert_eq!(evaluate_with(&semantics, &expr), Ok(4));
+
+        let strings = Semantics::new(|value| value.to_string())
+            .with_operator("add", |lhs, rhs| format!("{}{}", lhs, rhs))
+            .with_operator("multiply", |lhs, rhs| format!("[{}|{}]", lhs, rhs));
+        assert_eq!(evaluate_with(&strings, &expr), Ok("[80|5]4".to_string()));
+    }
+
+    #[test]
+    fn reports_missing_operators() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let semantics = Semantics::new(|value| value).with_operator("add", |l, r| l + r);
+        assert_eq!(
+            evaluate_with(&semantics, &expr),
+            Err(MissingOperator { term: "multiply" })
+        );
+    }
+}
@@ -0,0 +1,541 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! The Display impls from ch05b are good enough for debugging, but they only know one way to print
+//! an expression: fully parenthesized, with spaces around every operator, on a single line.
+//! Tooling usually wants some control over that.  In this chapter we add a `format_with` function
+//! that takes a `FormatOptions` describing the output style.
+//!
+//! Rather than adding yet another trait with an impl per term, we use a Mendler-style algebra from
+//! ch09a to turn an expression into a `Layout` — a small tree that records just enough about each
+//! term to print it.  All of the option handling then happens in one place, when we print the
+//! layout.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use std::convert::TryFrom;
+
+/// When to wrap a subexpression in parentheses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParenPolicy {
+    /// Wrap every operator in parentheses, just like the Display impls do.
+    Always,
+    /// Only add parentheses when precedence requires them.  Operators are treated as
+    /// left-associative, so `1 + (2 + 3)` keeps its parentheses but `(1 + 2) + 3` doesn't.
+    Minimal,
+}
+
+/// Which base to print integer literals in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Radix {
+    Binary,
+    Octal,
+    Decimal,
+    Hexadecimal,
+}
+
+/// How to split the digits of a literal into groups, such as `1,000,000` or `0xdead_beef`.
+/// Groups are counted from the right, and the radix prefix and sign are never grouped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DigitGrouping {
+    pub separator: char,
+    /// How many digits are in each group.
+    pub size: usize,
+}
+
+impl DigitGrouping {
+    /// Groups of three digits, separated by `separator`: `,` for English, `.` for German, and so
+    /// on.
+    pub fn thousands(separator: char) -> DigitGrouping {
+        DigitGrouping { separator, size: 3 }
+    }
+
+    /// Groups of four digits separated by underscores, which reads well in binary and hex, and
+    /// which is also Rust's own syntax.
+    pub fn nibbles() -> DigitGrouping {
+        DigitGrouping {
+            separator: '_',
+            size: 4,
+        }
+    }
+}
+
+/// Controls how `format_with` prints an expression.  The default options produce the same output
+/// as the Display impls.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatOptions {
+    /// Whether to put spaces around binary operators.
+    pub spacing: bool,
+    pub parens: ParenPolicy,
+    /// If set, any subexpression that doesn't fit in this many columns is broken across lines,
+    /// with its operator at the start of a new line.
+    pub max_width: Option<usize>,
+    pub radix: Radix,
+    pub grouping: Option<DigitGrouping>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            spacing: true,
+            parens: ParenPolicy::Always,
+            max_width: None,
+            radix: Radix::Decimal,
+            grouping: None,
+        }
+    }
+}
+
+/// The precedence of addition.  Operators with higher precedence bind more tightly.
+pub const ADD_PRECEDENCE: u8 = 1;
+/// The precedence of multiplication.
+pub const MULTIPLY_PRECEDENCE: u8 = 2;
+/// The precedence of a term that never needs parentheses, like a literal.
+pub const ATOM_PRECEDENCE: u8 = u8::MAX;
+
+/// Everything that we need to know about an expression to print it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Layout {
+    Literal(i64),
+    Binary {
+        operator: &'static str,
+        precedence: u8,
+        lhs: Box<Layout>,
+        rhs: Box<Layout>,
+    },
+}
+
+/// An algebra that turns an expression into a layout.
+pub struct ToLayout;
+
+impl<E> Algebra<IntegerLiteral, E, Layout> for ToLayout {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Layout
+    where
+        F: FnMut(&E) -> Layout,
+    {
+        Layout::Literal(term.value)
+    }
+}
+
+impl<E> Algebra<Add<E>, E, Layout> for ToLayout {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> Layout
+    where
+        F: FnMut(&E) -> Layout,
+    {
+        Layout::Binary {
+            operator: "+",
+            precedence: ADD_PRECEDENCE,
+            lhs: Box::new(recurse(&term.lhs)),
+            rhs: Box::new(recurse(&term.rhs)),
+        }
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, Layout> for ToLayout {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> Layout
+    where
+        F: FnMut(&E) -> Layout,
+    {
+        Layout::Binary {
+            operator: "*",
+            precedence: MULTIPLY_PRECEDENCE,
+            lhs: Box::new(recurse(&term.lhs)),
+            rhs: Box::new(recurse(&term.rhs)),
+        }
+    }
+}
+
+/// Which side of its parent operator a subexpression appears on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Lhs,
+    Rhs,
+}
+
+/// Returns whether an operator with the given precedence needs parentheses, when it appears on
+/// the given side of its parent operator.  Operators are treated as left-associative.
+pub fn needs_parens(precedence: u8, parent: u8, side: Side) -> bool {
+    match side {
+        Side::Lhs => precedence < parent,
+        Side::Rhs => precedence <= parent,
+    }
+}
+
+/// Prints an integer in the given radix.  Negative numbers get a leading minus sign, and not a
+/// two's complement representation.
+pub fn format_literal(value: i64, radix: Radix) -> String {
+    format_grouped_literal(value, radix, None)
+}
+
+/// Prints an integer in the given radix, with its digits split into groups.
+pub fn format_grouped_literal(value: i64, radix: Radix, grouping: Option<DigitGrouping>) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let magnitude = value.unsigned_abs();
+    let (prefix, digits) = match radix {
+        Radix::Binary => ("0b", format!("{:b}", magnitude)),
+        Radix::Octal => ("0o", format!("{:o}", magnitude)),
+        Radix::Decimal => ("", magnitude.to_string()),
+        Radix::Hexadecimal => ("0x", format!("{:x}", magnitude)),
+    };
+    let digits = match grouping {
+        Some(grouping) if grouping.size > 0 => {
+            let mut grouped = String::new();
+            for (index, digit) in digits.chars().enumerate() {
+                let remaining = digits.len() - index;
+                if index > 0 && remaining % grouping.size == 0 {
+                    grouped.push(grouping.separator);
+                }
+                grouped.push(digit);
+            }
+            grouped
+        }
+        _ => digits,
+    };
+    format!("{}{}{}", sign, prefix, digits)
+}
+
+/// Parses a literal printed by `format_grouped_literal`, in any radix.  The infix parser uses this
+/// for every literal, so `parse_formatted` can read back whatever `format_with` prints.  Separators are only
+/// allowed between digits, and only if `grouping` says what they are.  (We don't check that the
+/// groups are the right size, though, since people writing input by hand don't always bother.)
+pub fn parse_literal(text: &str, grouping: Option<DigitGrouping>) -> Option<i64> {
+    let (negative, rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (radix, digits) = match rest.get(..2) {
+        Some("0b") => (2, &rest[2..]),
+        Some("0o") => (8, &rest[2..]),
+        Some("0x") => (16, &rest[2..]),
+        _ => (10, rest),
+    };
+    let separator = grouping.map(|g| g.separator);
+    if digits.starts_with(|c| Some(c) == separator) || digits.ends_with(|c| Some(c) == separator) {
+        return None;
+    }
+    let mut magnitude: u64 = 0;
+    let mut previous_was_separator = false;
+    for ch in digits.chars() {
+        if Some(ch) == separator {
+            if previous_was_separator {
+                return None;
+            }
+            previous_was_separator = true;
+            continue;
+        }
+        previous_was_separator = false;
+        let digit = ch.to_digit(radix)?;
+        magnitude = magnitude
+            .checked_mul(u64::from(radix))?
+            .checked_add(u64::from(digit))?;
+    }
+    if digits.is_empty() {
+        return None;
+    }
+    if negative {
+        0i64.checked_sub_unsigned(magnitude)
+    } else {
+        i64::try_from(magnitude).ok()
+    }
+}
+
+impl Layout {
+    /// Returns whether this layout needs parentheses, when it appears on the given side of an
+    /// operator with the given precedence.
+    fn needs_parens(&self, options: &FormatOptions, parent: Option<(u8, Side)>) -> bool {
+        let precedence = match self {
+            Layout::Literal(_) => return false,
+            Layout::Binary { precedence, .. } => *precedence,
+        };
+        match (options.parens, parent) {
+            (ParenPolicy::Always, _) => true,
+            (ParenPolicy::Minimal, None) => false,
+            (ParenPolicy::Minimal, Some((parent, side))) => needs_parens(precedence, parent, side),
+        }
+    }
+
+    fn flat(&self, options: &FormatOptions, parent: Option<(u8, Side)>) -> String {
+        match self {
+            Layout::Literal(value) => {
+                format_grouped_literal(*value, options.radix, options.grouping)
+            }
+            Layout::Binary {
+                operator,
+                precedence,
+                lhs,
+                rhs,
+            } => {
+                let lhs = lhs.flat(options, Some((*precedence, Side::Lhs)));
+                let rhs = rhs.flat(options, Some((*precedence, Side::Rhs)));
+                let space = if options.spacing { " " } else { "" };
+                let result = format!("{}{}{}{}{}", lhs, space, operator, space, rhs);
+                if self.needs_parens(options, parent) {
+                    format!("({})", result)
+                } else {
+                    result
+                }
+            }
+        }
+    }
+
+    /// Prints this layout, assuming that its first line starts at the given column.
+    fn pretty(&self, options: &FormatOptions, parent: Option<(u8, Side)>, column: usize) -> String {
+        let flat = self.flat(options, parent);
+        let fits = match options.max_width {
+            Some(max_width) => column + flat.chars().count() <= max_width,
+            None => true,
+        };
+        match self {
+            Layout::Binary {
+                operator,
+                precedence,
+                lhs,
+                rhs,
+            } if !fits => {
+                // Put the operator at the start of a new line, lined up with the start of the
+                // left-hand side.
+                let parens = self.needs_parens(options, parent);
+                let column = if parens { column + 1 } else { column };
+                let space = if options.spacing { " " } else { "" };
+                let rhs_column = column + operator.len() + space.len();
+                let lhs = lhs.pretty(options, Some((*precedence, Side::Lhs)), column);
+                let rhs = rhs.pretty(options, Some((*precedence, Side::Rhs)), rhs_column);
+                let indent = " ".repeat(column);
+                let result = format!("{}\n{}{}{}{}", lhs, indent, operator, space, rhs);
+                if parens {
+                    format!("({})", result)
+                } else {
+                    result
+                }
+            }
+            _ => flat,
+        }
+    }
+
+    /// Prints this layout using the given options.
+    pub fn format(&self, options: &FormatOptions) -> String {
+        self.pretty(options, None, 0)
+    }
+}
+
+/// Converts an expression into a layout.
+pub fn to_layout<E>(expr: &E) -> Layout
+where
+    E: Expression,
+    ToLayout: Algebra<E::Signature, E, Layout>,
+{
+    mcata(&ToLayout, expr)
+}
+
+/// Prints an expression using the given options.
+pub fn format_with<E>(expr: &E, options: &FormatOptions) -> String
+where
+    E: Expression,
+    ToLayout: Algebra<E::Signature, E, Layout>,
+{
+    to_layout(expr).format(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn minimal() -> FormatOptions {
+        FormatOptions {
+            parens: ParenPolicy::Minimal,
+            ..FormatOptions::default()
+        }
+    }
+
+    #[test]
+    fn default_options_match_display() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(
+            format_with(&expr, &FormatOptions::default()),
+            expr.to_string()
+        );
+        let expr: MultExpr = multiply(
+            integer_literal(118),
+            add(integer_literal(5), integer_literal(4)),
+        );
+        assert_eq!(
+            format_with(&expr, &FormatOptions::default()),
+            expr.to_string()
+        );
+    }
+
+    #[test]
+    fn can_omit_unneeded_parens() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(format_with(&expr, &minimal()), "80 * 5 + 4");
+        let expr: MultExpr = multiply(
+            integer_literal(118),
+            add(integer_literal(5), integer_literal(4)),
+        );
+        assert_eq!(format_with(&expr, &minimal()), "118 * (5 + 4)");
+        let expr: Expr = add(
+            integer_literal(1),
+            add(integer_literal(2), integer_literal(3)),
+        );
+        assert_eq!(format_with(&expr, &minimal()), "1 + (2 + 3)");
+        let expr: Expr = add(
+            add(integer_literal(1), integer_literal(2)),
+            integer_literal(3),
+        );
+        assert_eq!(format_with(&expr, &minimal()), "1 + 2 + 3");
+    }
+
+    #[test]
+    fn can_omit_spacing() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let options = FormatOptions {
+            spacing: false,
+            ..minimal()
+        };
+        assert_eq!(format_with(&expr, &options), "80*5+4");
+    }
+
+    #[test]
+    fn can_print_literals_in_other_radixes() {
+        assert_eq!(format_literal(255, Radix::Binary), "0b11111111");
+        assert_eq!(format_literal(8, Radix::Octal), "0o10");
+        assert_eq!(format_literal(-255, Radix::Hexadecimal), "-0xff");
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let options = FormatOptions {
+            radix: Radix::Hexadecimal,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_with(&expr, &options), "((0x50 * 0x5) + 0x4)");
+    }
+
+    #[test]
+    fn can_break_long_lines() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let options = FormatOptions {
+            max_width: Some(8),
+            ..minimal()
+        };
+        assert_eq!(format_with(&expr, &options), "80 * 5\n+ 4");
+        let options = FormatOptions {
+            max_width: Some(8),
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_with(&expr, &options), "((80\n  * 5)\n + 4)");
+        // Everything fits, so nothing changes.
+        let options = FormatOptions {
+            max_width: Some(80),
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_with(&expr, &options), "((80 * 5) + 4)");
+        // Widths count characters, not bytes, even if a separator takes more than one byte.
+        let expr: MultExpr = multiply(integer_literal(1_000_000), integer_literal(2_000_000));
+        let options = FormatOptions {
+            max_width: Some(21),
+            grouping: Some(DigitGrouping::thousands('’')),
+            ..minimal()
+        };
+        assert_eq!(format_with(&expr, &options), "1’000’000 * 2’000’000");
+    }
+
+    #[test]
+    fn can_group_digits() {
+        let thousands = Some(DigitGrouping::thousands(','));
+        assert_eq!(
+            format_grouped_literal(-1234567, Radix::Decimal, thousands),
+            "-1,234,567"
+        );
+        assert_eq!(
+            format_grouped_literal(123, Radix::Decimal, thousands),
+            "123"
+        );
+        let nibbles = Some(DigitGrouping::nibbles());
+        assert_eq!(
+            format_grouped_literal(0xdeadbeef, Radix::Hexadecimal, nibbles),
+            "0xdead_beef"
+        );
+        assert_eq!(
+            format_grouped_literal(0b100101, Radix::Binary, nibbles),
+            "0b10_0101"
+        );
+        let options = FormatOptions {
+            grouping: Some(DigitGrouping::thousands('.')),
+            ..minimal()
+        };
+        let expr: MultExpr = multiply(integer_literal(1000), integer_literal(2500));
+        assert_eq!(format_with(&expr, &options), "1.000 * 2.500");
+    }
+
+    #[test]
+    fn can_parse_what_we_print() {
+        let groupings = [
+            None,
+            Some(DigitGrouping::thousands(',')),
+            Some(DigitGrouping::nibbles()),
+        ];
+        let radixes = [
+            Radix::Binary,
+            Radix::Octal,
+            Radix::Decimal,
+            Radix::Hexadecimal,
+        ];
+        for value in &[0, 1, -1, 404, -123_456_789, i64::MAX, i64::MIN] {
+            for radix in &radixes {
+                for grouping in &groupings {
+                    let printed = format_grouped_literal(*value, *radix, *grouping);
+                    assert_eq!(
+                        parse_literal(&printed, *grouping),
+                        Some(*value),
+                        "{}",
+                        printed
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_misplaced_separators() {
+        let thousands = Some(DigitGrouping::thousands(','));
+        assert_eq!(parse_literal("1,000", None), None);
+        assert_eq!(parse_literal(",100", thousands), None);
+        assert_eq!(parse_literal("100,", thousands), None);
+        assert_eq!(parse_literal("1,,000", thousands), None);
+        assert_eq!(parse_literal("0x", None), None);
+        assert_eq!(parse_literal("9223372036854775808", None), None);
+    }
+}
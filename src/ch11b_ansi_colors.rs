@@ -0,0 +1,167 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! When you're staring at an expression in a terminal, it helps if the different parts of it stand
+//! out.  This chapter adds a printer that uses ANSI escape codes to color literals, operators, and
+//! parentheses differently.  It's just another Mendler-style algebra over the same terms, whose
+//! carrier is a `String`.
+//!
+//! Not every terminal understands ANSI escape codes, so this module is only available with the
+//! `ansi` feature.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+
+/// The escape code that resets the terminal back to its default style.
+pub const RESET: &str = "\x1b[0m";
+
+/// The escape codes to use for each part of an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub literal: &'static str,
+    pub operator: &'static str,
+    pub paren: &'static str,
+}
+
+impl Default for Palette {
+    /// Cyan literals, bold yellow operators, and dim parentheses.
+    fn default() -> Palette {
+        Palette {
+            literal: "\x1b[36m",
+            operator: "\x1b[1;33m",
+            paren: "\x1b[2m",
+        }
+    }
+}
+
+impl Palette {
+    fn paint(&self, color: &str, text: &str) -> String {
+        format!("{}{}{}", color, text, RESET)
+    }
+
+    fn binary(&self, lhs: String, operator: &str, rhs: String) -> String {
+        format!(
+            "{}{} {} {}{}",
+            self.paint(self.paren, "("),
+            lhs,
+            self.paint(self.operator, operator),
+            rhs,
+            self.paint(self.paren, ")")
+        )
+    }
+}
+
+/// An algebra that prints an expression with ANSI colors.  Apart from the escape codes, the output
+/// is the same as the Display impls from ch05b.
+#[derive(Default)]
+pub struct AnsiPrinter {
+    pub palette: Palette,
+}
+
+impl<E> Algebra<IntegerLiteral, E, String> for AnsiPrinter {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        self.palette
+            .paint(self.palette.literal, &term.value.to_string())
+    }
+}
+
+impl<E> Algebra<Add<E>, E, String> for AnsiPrinter {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        self.palette
+            .binary(recurse(&term.lhs), "+", recurse(&term.rhs))
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, String> for AnsiPrinter {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        self.palette
+            .binary(recurse(&term.lhs), "*", recurse(&term.rhs))
+    }
+}
+
+/// Prints an expression with the default palette.
+pub fn colorize<E>(expr: &E) -> String
+where
+    E: Expression,
+    AnsiPrinter: Algebra<E::Signature, E, String>,
+{
+    mcata(&AnsiPrinter::default(), expr)
+}
+
+/// Removes all of the ANSI escape codes from a string.
+pub fn strip_ansi(colored: &str) -> String {
+    let mut result = String::with_capacity(colored.len());
+    let mut chars = colored.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            // Skip everything up to and including the final byte of the escape sequence.
+            for ch in &mut chars {
+                if ch.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_colorize_literal() {
+        let expr: Expr = integer_literal(7);
+        assert_eq!(colorize(&expr), "\x1b[36m7\x1b[0m");
+    }
+
+    #[test]
+    fn can_colorize_operators_and_parens() {
+        let palette = Palette {
+            literal: "<L>",
+            operator: "<O>",
+            paren: "<P>",
+        };
+        let expr: MultExpr = multiply(integer_literal(6), integer_literal(7));
+        let colored = mcata(&AnsiPrinter { palette }, &expr);
+        assert_eq!(
+            colored,
+            "<P>(\x1b[0m<L>6\x1b[0m <O>*\x1b[0m <L>7\x1b[0m<P>)\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn matches_display_without_colors() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(strip_ansi(&colorize(&expr)), expr.to_string());
+    }
+}
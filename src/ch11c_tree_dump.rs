@@ -0,0 +1,200 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Infix notation gets hard to read once an expression is more than a few levels deep — you end up
+//! counting parentheses.  For debugging, it's often easier to look at the tree directly.  This
+//! chapter adds a `dump_tree` function that prints one node per line, with the same box-drawing
+//! branches that the `tree` command uses:
+//!
+//! ```text
+//! add
+//! ├── multiply
+//! │   ├── 80
+//! │   └── 5
+//! └── 4
+//! ```
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+
+/// A generic tree of labeled nodes.  Each term only has to say what its label is and what its
+/// children are; the rendering is the same for all of them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeNode {
+    pub label: String,
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    pub fn leaf(label: impl Into<String>) -> TreeNode {
+        TreeNode {
+            label: label.into(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn branch(label: impl Into<String>, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode {
+            label: label.into(),
+            children,
+        }
+    }
+
+    /// Renders this tree one node per line, using box-drawing characters to show the branches.
+    /// Every line (including the last) ends with a newline.
+    pub fn render(&self) -> String {
+        let mut result = String::new();
+        result.push_str(&self.label);
+        result.push('\n');
+        self.render_children("", &mut result);
+        result
+    }
+
+    fn render_children(&self, prefix: &str, result: &mut String) {
+        let count = self.children.len();
+        for (index, child) in self.children.iter().enumerate() {
+            let last = index + 1 == count;
+            result.push_str(prefix);
+            result.push_str(if last { "└── " } else { "├── " });
+            result.push_str(&child.label);
+            result.push('\n');
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            child.render_children(&prefix, result);
+        }
+    }
+}
+
+/// An algebra that converts an expression into a tree.
+pub struct ToTree;
+
+impl<E> Algebra<IntegerLiteral, E, TreeNode> for ToTree {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> TreeNode
+    where
+        F: FnMut(&E) -> TreeNode,
+    {
+        TreeNode::leaf(term.value.to_string())
+    }
+}
+
+impl<E> Algebra<Add<E>, E, TreeNode> for ToTree {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> TreeNode
+    where
+        F: FnMut(&E) -> TreeNode,
+    {
+        TreeNode::branch("add", vec![recurse(&term.lhs), recurse(&term.rhs)])
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, TreeNode> for ToTree {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> TreeNode
+    where
+        F: FnMut(&E) -> TreeNode,
+    {
+        TreeNode::branch("multiply", vec![recurse(&term.lhs), recurse(&term.rhs)])
+    }
+}
+
+impl<E> Algebra<Pair<E>, E, TreeNode> for ToTree {
+    fn apply<F>(&self, term: &Pair<E>, mut recurse: F) -> TreeNode
+    where
+        F: FnMut(&E) -> TreeNode,
+    {
+        TreeNode::branch("pair", vec![recurse(&term.first), recurse(&term.second)])
+    }
+}
+
+impl<E> Algebra<First<E>, E, TreeNode> for ToTree {
+    fn apply<F>(&self, term: &First<E>, mut recurse: F) -> TreeNode
+    where
+        F: FnMut(&E) -> TreeNode,
+    {
+        TreeNode::branch("first", vec![recurse(&term.pair)])
+    }
+}
+
+impl<E> Algebra<Second<E>, E, TreeNode> for ToTree {
+    fn apply<F>(&self, term: &Second<E>, mut recurse: F) -> TreeNode
+    where
+        F: FnMut(&E) -> TreeNode,
+    {
+        TreeNode::branch("second", vec![recurse(&term.pair)])
+    }
+}
+
+/// Converts an expression into a tree.
+pub fn to_tree<E>(expr: &E) -> TreeNode
+where
+    E: Expression,
+    ToTree: Algebra<E::Signature, E, TreeNode>,
+{
+    mcata(&ToTree, expr)
+}
+
+/// Renders an expression as a tree, one node per line.
+pub fn dump_tree<E>(expr: &E) -> String
+where
+    E: Expression,
+    ToTree: Algebra<E::Signature, E, TreeNode>,
+{
+    to_tree(expr).render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_dump_literal() {
+        let expr: Expr = integer_literal(7);
+        assert_eq!(dump_tree(&expr), "7\n");
+    }
+
+    #[test]
+    fn can_dump_nested_expression() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(
+            dump_tree(&expr),
+            "add\n\
+             ├── multiply\n\
+             │   ├── 80\n\
+             │   └── 5\n\
+             └── 4\n"
+        );
+    }
+
+    #[test]
+    fn can_dump_deep_right_branch() {
+        let expr: PairExpr = first(pair(
+            integer_literal(7),
+            add(integer_literal(1), integer_literal(2)),
+        ));
+        assert_eq!(
+            dump_tree(&expr),
+            "first\n\
+             └── pair\n    \
+                 ├── 7\n    \
+                 └── add\n        \
+                     ├── 1\n        \
+                     └── 2\n"
+        );
+    }
+}
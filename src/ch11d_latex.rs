@@ -0,0 +1,257 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Let's render expressions as LaTeX math, so that they can be embedded in documents.  This time
+//! we define the new operation the same way that ch05b defined Display: with a trait, and an impl
+//! for each term.
+//!
+//! LaTeX doesn't need parentheses that precedence already implies, so each term also has to tell
+//! its parent how tightly it binds.  That's a separate `Precedence` trait, since it's useful for
+//! other renderers, too.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07e_division::*;
+use crate::ch08a_expressions::*;
+use crate::ch11a_format_options::*;
+
+/// How tightly the outermost operator of a term binds.  (See the constants in ch11a.)
+pub trait Precedence {
+    fn precedence(&self) -> u8;
+}
+
+impl Precedence for IntegerLiteral {
+    fn precedence(&self) -> u8 {
+        ATOM_PRECEDENCE
+    }
+}
+
+impl<E> Precedence for Add<E> {
+    fn precedence(&self) -> u8 {
+        ADD_PRECEDENCE
+    }
+}
+
+impl<E> Precedence for Multiply<E> {
+    fn precedence(&self) -> u8 {
+        MULTIPLY_PRECEDENCE
+    }
+}
+
+// A fraction bar groups both of its operands, and nothing outside of it can split it apart, so a
+// quotient binds as tightly as a literal.
+impl<E> Precedence for Divide<E> {
+    fn precedence(&self) -> u8 {
+        ATOM_PRECEDENCE
+    }
+}
+
+impl<L, R> Precedence for Sum<L, R>
+where
+    L: Precedence,
+    R: Precedence,
+{
+    fn precedence(&self) -> u8 {
+        match self {
+            Sum::Left(lhs) => lhs.precedence(),
+            Sum::Right(rhs) => rhs.precedence(),
+        }
+    }
+}
+
+impl<E> Precedence for E
+where
+    E: Expression,
+    E::Signature: Precedence,
+{
+    fn precedence(&self) -> u8 {
+        self.unwrap().precedence()
+    }
+}
+
+/// Renders a term as LaTeX math.
+pub trait Latex {
+    fn latex(&self) -> String;
+}
+
+/// Renders an operand of a binary operator, adding parentheses if it needs them.
+fn operand<E>(expr: &E, parent: u8, side: Side) -> String
+where
+    E: Latex + Precedence,
+{
+    if needs_parens(expr.precedence(), parent, side) {
+        format!("({})", expr.latex())
+    } else {
+        expr.latex()
+    }
+}
+
+impl Latex for IntegerLiteral {
+    fn latex(&self) -> String {
+        self.value.to_string()
+    }
+}
+
+impl<E> Latex for Add<E>
+where
+    E: Latex + Precedence,
+{
+    fn latex(&self) -> String {
+        format!(
+            "{} + {}",
+            operand(&self.lhs, ADD_PRECEDENCE, Side::Lhs),
+            operand(&self.rhs, ADD_PRECEDENCE, Side::Rhs)
+        )
+    }
+}
+
+impl<E> Latex for Multiply<E>
+where
+    E: Latex + Precedence,
+{
+    fn latex(&self) -> String {
+        format!(
+            "{} \\times {}",
+            operand(&self.lhs, MULTIPLY_PRECEDENCE, Side::Lhs),
+            operand(&self.rhs, MULTIPLY_PRECEDENCE, Side::Rhs)
+        )
+    }
+}
+
+impl<E> Latex for Divide<E>
+where
+    E: Latex,
+{
+    fn latex(&self) -> String {
+        format!("\\frac{{{}}}{{{}}}", self.lhs.latex(), self.rhs.latex())
+    }
+}
+
+impl<L, R> Latex for Sum<L, R>
+where
+    L: Latex,
+    R: Latex,
+{
+    fn latex(&self) -> String {
+        match self {
+            Sum::Left(lhs) => lhs.latex(),
+            Sum::Right(rhs) => rhs.latex(),
+        }
+    }
+}
+
+// And then the boilerplate impl for each expression type.  We can't use a single blanket impl for
+// every Expression here, like we could for Precedence: rendering an Expr needs its Add terms to be
+// renderable, which needs their Expr subexpressions to be renderable, and the compiler can't prove
+// that cycle of bounds.  (That's exactly the problem that the open recursion in ch08b avoids.)
+
+impl Latex for Expr {
+    fn latex(&self) -> String {
+        self.0.latex()
+    }
+}
+
+impl Latex for MultExpr {
+    fn latex(&self) -> String {
+        self.0.latex()
+    }
+}
+
+impl Latex for NoAddExpr {
+    fn latex(&self) -> String {
+        self.0.latex()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    type FracSig<E> = Sum<Divide<E>, MultSig<E>>;
+    struct FracExpr(Box<FracSig<FracExpr>>);
+
+    impl Expression for FracExpr {
+        type Signature = FracSig<FracExpr>;
+        fn wrap(sig: Self::Signature) -> Self {
+            Self(Box::new(sig))
+        }
+        fn unwrap(&self) -> &Self::Signature {
+            &self.0
+        }
+        fn into_signature(self) -> Self::Signature {
+            *self.0
+        }
+    }
+
+    from_terms!(
+        FracExpr: Divide<FracExpr>,
+        Multiply<FracExpr>,
+        IntegerLiteral,
+        Add<FracExpr>,
+    );
+
+    impl Latex for FracExpr {
+        fn latex(&self) -> String {
+            self.0.latex()
+        }
+    }
+
+    #[test]
+    fn can_render_literal() {
+        let expr: Expr = integer_literal(7);
+        assert_eq!(expr.latex(), "7");
+    }
+
+    #[test]
+    fn can_render_without_extra_parens() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(expr.latex(), "80 \\times 5 + 4");
+    }
+
+    #[test]
+    fn can_render_with_needed_parens() {
+        let expr: MultExpr = multiply(
+            integer_literal(118),
+            add(integer_literal(5), integer_literal(4)),
+        );
+        assert_eq!(expr.latex(), "118 \\times (5 + 4)");
+        let expr: Expr = add(
+            integer_literal(1),
+            add(integer_literal(2), integer_literal(3)),
+        );
+        assert_eq!(expr.latex(), "1 + (2 + 3)");
+    }
+
+    #[test]
+    fn can_render_fractions() {
+        let expr: FracExpr = multiply(
+            divide(
+                add(integer_literal(1), integer_literal(2)),
+                integer_literal(3),
+            ),
+            integer_literal(4),
+        );
+        assert_eq!(expr.latex(), "\\frac{1 + 2}{3} \\times 4");
+        let expr: FracExpr = divide(
+            integer_literal(1),
+            divide(integer_literal(2), integer_literal(3)),
+        );
+        assert_eq!(expr.latex(), "\\frac{1}{\\frac{2}{3}}");
+    }
+}
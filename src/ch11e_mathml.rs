@@ -0,0 +1,197 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Browsers can render math written in MathML, so let's add that as another output format.  This
+//! one is a Mendler-style algebra from ch09a.  The algebra can't look inside the values that it
+//! gets back from the recursive calls, but it *can* put bounds on the subexpressions themselves —
+//! so it reuses the `Precedence` trait from the LaTeX chapter to decide where the parentheses go.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07e_division::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::ch11a_format_options::*;
+use crate::ch11d_latex::*;
+
+/// An algebra that renders an expression as presentation MathML.
+pub struct MathML;
+
+fn operand<E: Precedence>(subexpr: &E, rendered: String, parent: u8, side: Side) -> String {
+    if needs_parens(subexpr.precedence(), parent, side) {
+        format!("<mrow><mo>(</mo>{}<mo>)</mo></mrow>", rendered)
+    } else {
+        rendered
+    }
+}
+
+fn binary<E, F>(lhs: &E, operator: &str, rhs: &E, precedence: u8, mut recurse: F) -> String
+where
+    E: Precedence,
+    F: FnMut(&E) -> String,
+{
+    format!(
+        "<mrow>{}<mo>{}</mo>{}</mrow>",
+        operand(lhs, recurse(lhs), precedence, Side::Lhs),
+        operator,
+        operand(rhs, recurse(rhs), precedence, Side::Rhs)
+    )
+}
+
+impl<E> Algebra<IntegerLiteral, E, String> for MathML {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        if term.value < 0 {
+            format!(
+                "<mrow><mo>-</mo><mn>{}</mn></mrow>",
+                term.value.unsigned_abs()
+            )
+        } else {
+            format!("<mn>{}</mn>", term.value)
+        }
+    }
+}
+
+impl<E> Algebra<Add<E>, E, String> for MathML
+where
+    E: Precedence,
+{
+    fn apply<F>(&self, term: &Add<E>, recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        binary(&term.lhs, "+", &term.rhs, ADD_PRECEDENCE, recurse)
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, String> for MathML
+where
+    E: Precedence,
+{
+    fn apply<F>(&self, term: &Multiply<E>, recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        binary(&term.lhs, "&#xD7;", &term.rhs, MULTIPLY_PRECEDENCE, recurse)
+    }
+}
+
+// A fraction doesn't need parentheses around either operand, since the bar already groups them.
+impl<E> Algebra<Divide<E>, E, String> for MathML {
+    fn apply<F>(&self, term: &Divide<E>, mut recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        format!(
+            "<mfrac>{}{}</mfrac>",
+            recurse(&term.lhs),
+            recurse(&term.rhs)
+        )
+    }
+}
+
+/// Renders an expression as a complete `<math>` element.
+pub fn to_mathml<E>(expr: &E) -> String
+where
+    E: Expression,
+    MathML: Algebra<E::Signature, E, String>,
+{
+    format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>",
+        mcata(&MathML, expr)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    type FracSig<E> = Sum<Divide<E>, MultSig<E>>;
+    struct FracExpr(Box<FracSig<FracExpr>>);
+
+    impl Expression for FracExpr {
+        type Signature = FracSig<FracExpr>;
+        fn wrap(sig: Self::Signature) -> Self {
+            Self(Box::new(sig))
+        }
+        fn unwrap(&self) -> &Self::Signature {
+            &self.0
+        }
+        fn into_signature(self) -> Self::Signature {
+            *self.0
+        }
+    }
+
+    from_terms!(
+        FracExpr: Divide<FracExpr>,
+        Multiply<FracExpr>,
+        IntegerLiteral,
+        Add<FracExpr>,
+    );
+
+    #[test]
+    fn can_render_literals() {
+        let expr: Expr = integer_literal(7);
+        assert_eq!(mcata(&MathML, &expr), "<mn>7</mn>");
+        let expr: Expr = integer_literal(-7);
+        assert_eq!(mcata(&MathML, &expr), "<mrow><mo>-</mo><mn>7</mn></mrow>");
+    }
+
+    #[test]
+    fn can_render_without_extra_parens() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(
+            mcata(&MathML, &expr),
+            "<mrow><mrow><mn>80</mn><mo>&#xD7;</mo><mn>5</mn></mrow><mo>+</mo><mn>4</mn></mrow>"
+        );
+    }
+
+    #[test]
+    fn can_render_with_needed_parens() {
+        let expr: MultExpr = multiply(
+            integer_literal(118),
+            add(integer_literal(5), integer_literal(4)),
+        );
+        assert_eq!(
+            to_mathml(&expr),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">\
+             <mrow><mn>118</mn><mo>&#xD7;</mo>\
+             <mrow><mo>(</mo><mrow><mn>5</mn><mo>+</mo><mn>4</mn></mrow><mo>)</mo></mrow>\
+             </mrow></math>"
+        );
+    }
+
+    #[test]
+    fn can_render_fractions() {
+        let expr: FracExpr = multiply(
+            divide(
+                add(integer_literal(1), integer_literal(2)),
+                integer_literal(3),
+            ),
+            integer_literal(4),
+        );
+        assert_eq!(
+            mcata(&MathML, &expr),
+            "<mrow><mfrac><mrow><mn>1</mn><mo>+</mo><mn>2</mn></mrow><mn>3</mn></mfrac>\
+             <mo>&#xD7;</mo><mn>4</mn></mrow>"
+        );
+    }
+}
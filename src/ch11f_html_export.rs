@@ -0,0 +1,215 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! For really big expressions, even the tree dump from ch11c is hard to navigate.  This chapter
+//! exports an expression as a self-contained HTML page, with a tree view whose branches you can
+//! collapse and expand.  Each node also shows the subexpression it represents, and the value that
+//! it evaluates to, so that you can see where a surprising result comes from.
+//!
+//! To get all of that in a single pass, we use a *tracing* evaluator: a Mendler-style algebra that
+//! evaluates each term like the one in ch09a, but also records the value and rendering of every
+//! node along the way.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+
+/// One node of an evaluation trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TracedNode {
+    /// The kind of term.
+    pub label: String,
+    /// The subexpression rooted at this node, rendered just like the Display impls from ch05b.
+    pub source: String,
+    /// The value of the subexpression rooted at this node.
+    pub value: i64,
+    pub children: Vec<TracedNode>,
+}
+
+/// An algebra that evaluates an expression, and records the value of every subexpression.
+pub struct Trace;
+
+fn binary(label: &str, operator: &str, lhs: TracedNode, rhs: TracedNode, value: i64) -> TracedNode {
+    TracedNode {
+        label: label.to_string(),
+        source: format!("({} {} {})", lhs.source, operator, rhs.source),
+        value,
+        children: vec![lhs, rhs],
+    }
+}
+
+impl<E> Algebra<IntegerLiteral, E, TracedNode> for Trace {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> TracedNode
+    where
+        F: FnMut(&E) -> TracedNode,
+    {
+        TracedNode {
+            label: "integer literal".to_string(),
+            source: term.value.to_string(),
+            value: term.value,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl<E> Algebra<Add<E>, E, TracedNode> for Trace {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> TracedNode
+    where
+        F: FnMut(&E) -> TracedNode,
+    {
+        let lhs = recurse(&term.lhs);
+        let rhs = recurse(&term.rhs);
+        let value = lhs.value + rhs.value;
+        binary("add", "+", lhs, rhs, value)
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, TracedNode> for Trace {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> TracedNode
+    where
+        F: FnMut(&E) -> TracedNode,
+    {
+        let lhs = recurse(&term.lhs);
+        let rhs = recurse(&term.rhs);
+        let value = lhs.value * rhs.value;
+        binary("multiply", "*", lhs, rhs, value)
+    }
+}
+
+/// Evaluates an expression, recording the value of every subexpression.
+pub fn trace<E>(expr: &E) -> TracedNode
+where
+    E: Expression,
+    Trace: Algebra<E::Signature, E, TracedNode>,
+{
+    mcata(&Trace, expr)
+}
+
+/// Escapes the characters that have special meanings in HTML.
+pub fn escape_html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; }
+details { margin-left: 1.5em; }
+summary, .leaf { margin-left: 1.5em; padding: 0.1em 0; }
+summary { margin-left: 0; cursor: pointer; }
+.label { font-weight: bold; }
+.value { color: #0a6; }
+code { color: #555; }
+";
+
+impl TracedNode {
+    fn write_html(&self, result: &mut String) {
+        let line = format!(
+            "<span class=\"label\">{}</span> = <span class=\"value\">{}</span> <code>{}</code>",
+            escape_html(&self.label),
+            self.value,
+            escape_html(&self.source)
+        );
+        if self.children.is_empty() {
+            result.push_str(&format!("<div class=\"leaf\">{}</div>\n", line));
+            return;
+        }
+        // <details> elements can be collapsed and expanded without any JavaScript.
+        result.push_str(&format!("<details open>\n<summary>{}</summary>\n", line));
+        for child in &self.children {
+            child.write_html(result);
+        }
+        result.push_str("</details>\n");
+    }
+
+    /// Renders this trace as a complete HTML page.
+    pub fn to_html(&self, title: &str) -> String {
+        let mut result = String::new();
+        result.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        result.push_str(&format!("<title>{}</title>\n", escape_html(title)));
+        result.push_str(&format!("<style>\n{}</style>\n", STYLE));
+        result.push_str("</head>\n<body>\n");
+        result.push_str(&format!("<h1>{}</h1>\n", escape_html(title)));
+        self.write_html(&mut result);
+        result.push_str("</body>\n</html>\n");
+        result
+    }
+}
+
+/// Exports an expression as a self-contained HTML page with a collapsible tree view.
+pub fn export_html<E>(expr: &E, title: &str) -> String
+where
+    E: Expression,
+    Trace: Algebra<E::Signature, E, TracedNode>,
+{
+    trace(expr).to_html(title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_trace_every_subexpression() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let traced = trace(&expr);
+        assert_eq!(traced.value, 404);
+        assert_eq!(traced.source, expr.to_string());
+        assert_eq!(traced.children[0].label, "multiply");
+        assert_eq!(traced.children[0].value, 400);
+        assert_eq!(traced.children[0].source, "(80 * 5)");
+        assert_eq!(traced.children[1].value, 4);
+        assert!(traced.children[1].children.is_empty());
+    }
+
+    #[test]
+    fn can_escape_html() {
+        assert_eq!(escape_html("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+    }
+
+    #[test]
+    fn can_export_html() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let html = export_html(&expr, "Example <1>");
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Example &lt;1&gt;</title>"));
+        assert_eq!(html.matches("<details open>").count(), 2);
+        assert_eq!(html.matches("<div class=\"leaf\">").count(), 3);
+        assert!(html.contains(
+            "<summary><span class=\"label\">add</span> = <span class=\"value\">404</span> \
+             <code>((80 * 5) + 4)</code></summary>"
+        ));
+        assert!(html.trim_end().ends_with("</html>"));
+    }
+}
@@ -0,0 +1,103 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Remember showing your work in math class?  Here's an operation that does it for us: it
+//! evaluates an expression one operation at a time, and prints the whole expression after each
+//! step, until all that's left is the answer.
+//!
+//! ```text
+//! 80 * 5 + 2 * 2
+//! = 400 + 2 * 2
+//! = 400 + 4
+//! = 404
+//! ```
+//!
+//! The steps come from the small-step evaluator in the rewrite module, and each intermediate
+//! expression is printed with ch11a's `format_with`, so you can pick the output style.
+
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::ch11a_format_options::*;
+use crate::rewrite::*;
+
+/// Prints each step of evaluating an expression, one per line.  Every line after the first starts
+/// with `= `.
+pub fn show_your_work<E>(expr: &E, options: &FormatOptions) -> String
+where
+    E: Expression,
+    E::Signature: FromNode<E>,
+    ToNode: Algebra<E::Signature, E, Node>,
+    ToLayout: Algebra<E::Signature, E, Layout>,
+{
+    let mut lines = vec![format_with(expr, options)];
+    for step in small_step().steps(&to_node(expr)) {
+        // Evaluating only ever replaces subtrees with literals, which every expression type has.
+        let expr: E = from_node(&step.result).expect("rewrite produced an unknown term");
+        lines.push(format!("= {}", format_with(&expr, options)));
+    }
+    let mut result = lines.join("\n");
+    result.push('\n');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch02_open_sum::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+
+    fn minimal() -> FormatOptions {
+        FormatOptions {
+            parens: ParenPolicy::Minimal,
+            ..FormatOptions::default()
+        }
+    }
+
+    #[test]
+    fn can_show_work() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            multiply(integer_literal(2), integer_literal(2)),
+        );
+        assert_eq!(
+            show_your_work(&expr, &minimal()),
+            "80 * 5 + 2 * 2\n\
+             = 400 + 2 * 2\n\
+             = 400 + 4\n\
+             = 404\n"
+        );
+    }
+
+    #[test]
+    fn respects_format_options() {
+        let expr: Expr = add(
+            integer_literal(30000),
+            add(integer_literal(1330), integer_literal(7)),
+        );
+        assert_eq!(
+            show_your_work(&expr, &FormatOptions::default()),
+            "(30000 + (1330 + 7))\n\
+             = (30000 + 1337)\n\
+             = 31337\n"
+        );
+    }
+
+    #[test]
+    fn literals_need_no_work() {
+        let expr: Expr = integer_literal(42);
+        assert_eq!(show_your_work(&expr, &minimal()), "42\n");
+    }
+}
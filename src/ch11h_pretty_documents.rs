@@ -0,0 +1,366 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! ch11a can break a long expression across lines, but it decides where to break by printing each
+//! subexpression flat and measuring it, over and over again, which is quadratic in the size of the
+//! expression.  It also only knows about binary operators.  In this chapter we borrow the
+//! standard solution from Wadler's "A prettier printer": each term describes its output as a
+//! `Doc`, which says where line breaks are *allowed* and which of them belong together, and a
+//! single renderer then picks the breaks that make the whole thing fit in the page width.
+//!
+//! A `Doc` is built from just a few pieces:
+//!
+//!   - `text`, which is always printed as-is;
+//!   - `line` and `softline`, which are printed as a space (or nothing) if their group fits on
+//!     the current line, and as a newline otherwise;
+//!   - `nest`, which indents every line break inside of it; and
+//!   - `group`, which makes all of the line breaks inside of it (but outside of any nested groups)
+//!     break or not break together.
+//!
+//! The renderer tries to keep each group flat, outermost first, and only needs to look ahead as
+//! far as the end of the current line to decide, so it runs in linear time.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::ch11a_format_options::*;
+use std::convert::TryFrom;
+
+/// A pretty-printing document.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Doc {
+    Nil,
+    Text(String),
+    /// A line break, which is printed as the given string if its group is flat.
+    Break(&'static str),
+    Concat(Vec<Doc>),
+    Nest(usize, Box<Doc>),
+    Group(Box<Doc>),
+}
+
+impl Doc {
+    pub fn text<S: Into<String>>(text: S) -> Doc {
+        Doc::Text(text.into())
+    }
+
+    /// A line break that turns into a space when its group is flat.
+    pub fn line() -> Doc {
+        Doc::Break(" ")
+    }
+
+    /// A line break that disappears when its group is flat.
+    pub fn softline() -> Doc {
+        Doc::Break("")
+    }
+
+    pub fn concat(docs: Vec<Doc>) -> Doc {
+        Doc::Concat(docs)
+    }
+
+    /// Indents every line break in `doc` by `indent` more columns.
+    pub fn nest(indent: usize, doc: Doc) -> Doc {
+        Doc::Nest(indent, Box::new(doc))
+    }
+
+    pub fn group(doc: Doc) -> Doc {
+        Doc::Group(Box::new(doc))
+    }
+
+    /// Renders this document, breaking lines so that they fit in `width` columns wherever
+    /// possible.  (A single piece of text that's wider than the page will still overflow it.)
+    pub fn pretty(&self, width: usize) -> String {
+        let mut output = String::new();
+        let mut column = 0;
+        // The parts of the document that we haven't printed yet, with the innermost last.
+        let mut stack = vec![(0, Mode::Break, self)];
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Nil => {}
+                Doc::Text(text) => {
+                    output.push_str(text);
+                    column += text.chars().count();
+                }
+                Doc::Break(flat) if mode == Mode::Flat => {
+                    output.push_str(flat);
+                    column += flat.chars().count();
+                }
+                Doc::Break(_) => {
+                    output.push('\n');
+                    output.extend(std::iter::repeat_n(' ', indent));
+                    column = indent;
+                }
+                Doc::Concat(docs) => {
+                    stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc)));
+                }
+                Doc::Nest(extra, doc) => stack.push((indent + extra, mode, doc)),
+                Doc::Group(doc) if mode == Mode::Flat => stack.push((indent, Mode::Flat, doc)),
+                Doc::Group(doc) => {
+                    let remaining = width.saturating_sub(column);
+                    let mode = if fits(remaining, doc, &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((indent, mode, doc));
+                }
+            }
+        }
+        output
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Returns whether `doc`, printed flat, fits in `remaining` columns along with whatever follows it
+/// on the same line.
+fn fits(remaining: usize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut remaining = isize::try_from(remaining).unwrap_or(isize::MAX);
+    let mut pending = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+    loop {
+        let (mode, doc) = match pending.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some((_, mode, doc)) => (*mode, *doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Nil => {}
+            Doc::Text(text) => remaining -= text.chars().count() as isize,
+            Doc::Break(flat) if mode == Mode::Flat => remaining -= flat.chars().count() as isize,
+            // The rest of the document starts on a new line, so it doesn't matter how long it is.
+            Doc::Break(_) => return true,
+            Doc::Concat(docs) => pending.extend(docs.iter().rev().map(|doc| (mode, doc))),
+            Doc::Nest(_, doc) | Doc::Group(doc) => pending.push((mode, doc)),
+        }
+        if remaining < 0 {
+            return false;
+        }
+    }
+}
+
+// Each term renders itself into a document, along with its precedence, so that its parent can
+// decide whether to wrap it in parentheses.
+
+/// A subexpression that has been rendered into a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fragment {
+    pub doc: Doc,
+    pub precedence: u8,
+}
+
+/// An algebra that turns an expression into a document, using the parenthesization, spacing,
+/// and literal style from a `FormatOptions`.
+pub struct ToDoc<'a> {
+    pub options: &'a FormatOptions,
+}
+
+impl<'a> ToDoc<'a> {
+    fn parenthesize(&self, fragment: Fragment, parent: u8, side: Side) -> Doc {
+        let parens = match self.options.parens {
+            _ if fragment.precedence == ATOM_PRECEDENCE => false,
+            ParenPolicy::Always => true,
+            ParenPolicy::Minimal => needs_parens(fragment.precedence, parent, side),
+        };
+        if parens {
+            Doc::concat(vec![
+                Doc::text("("),
+                Doc::nest(1, fragment.doc),
+                Doc::text(")"),
+            ])
+        } else {
+            fragment.doc
+        }
+    }
+
+    /// Lays out a binary operator so that, if it doesn't fit on one line, the operator starts a
+    /// new line lined up with the left-hand side.
+    pub fn binary(&self, operator: &str, precedence: u8, lhs: Fragment, rhs: Fragment) -> Fragment {
+        let space = if self.options.spacing { " " } else { "" };
+        let lhs = self.parenthesize(lhs, precedence, Side::Lhs);
+        let rhs = self.parenthesize(rhs, precedence, Side::Rhs);
+        let doc = Doc::group(Doc::concat(vec![
+            lhs,
+            Doc::Break(space),
+            Doc::text(format!("{}{}", operator, space)),
+            Doc::nest(operator.len() + space.len(), rhs),
+        ]));
+        Fragment { doc, precedence }
+    }
+}
+
+impl<'a, E> Algebra<IntegerLiteral, E, Fragment> for ToDoc<'a> {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Fragment
+    where
+        F: FnMut(&E) -> Fragment,
+    {
+        let options = self.options;
+        Fragment {
+            doc: Doc::text(format_grouped_literal(
+                term.value,
+                options.radix,
+                options.grouping,
+            )),
+            precedence: ATOM_PRECEDENCE,
+        }
+    }
+}
+
+impl<'a, E> Algebra<Add<E>, E, Fragment> for ToDoc<'a> {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> Fragment
+    where
+        F: FnMut(&E) -> Fragment,
+    {
+        let lhs = recurse(&term.lhs);
+        let rhs = recurse(&term.rhs);
+        self.binary("+", ADD_PRECEDENCE, lhs, rhs)
+    }
+}
+
+impl<'a, E> Algebra<Multiply<E>, E, Fragment> for ToDoc<'a> {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> Fragment
+    where
+        F: FnMut(&E) -> Fragment,
+    {
+        let lhs = recurse(&term.lhs);
+        let rhs = recurse(&term.rhs);
+        self.binary("*", MULTIPLY_PRECEDENCE, lhs, rhs)
+    }
+}
+
+/// Converts an expression into a document.  The outermost term never gets parentheses, unless
+/// the options ask for them everywhere.
+pub fn to_doc<'a, E>(expr: &E, options: &'a FormatOptions) -> Doc
+where
+    E: Expression,
+    ToDoc<'a>: Algebra<E::Signature, E, Fragment>,
+{
+    let fragment = mcata(&ToDoc { options }, expr);
+    match options.parens {
+        ParenPolicy::Always => ToDoc { options }.parenthesize(fragment, 0, Side::Lhs),
+        ParenPolicy::Minimal => fragment.doc,
+    }
+}
+
+/// Prints an expression, wrapping it to fit in `options.max_width` columns.
+pub fn format_pretty<E>(expr: &E, options: &FormatOptions) -> String
+where
+    E: Expression,
+    for<'a> ToDoc<'a>: Algebra<E::Signature, E, Fragment>,
+{
+    to_doc(expr, options).pretty(options.max_width.unwrap_or(usize::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn minimal(max_width: usize) -> FormatOptions {
+        FormatOptions {
+            parens: ParenPolicy::Minimal,
+            max_width: Some(max_width),
+            ..FormatOptions::default()
+        }
+    }
+
+    /// A sum of `count` products, which is far too long to print on one line.
+    fn long_sum(count: i64) -> MultExpr {
+        let mut expr = multiply(integer_literal(1), integer_literal(1));
+        for i in 2..=count {
+            expr = add(expr, multiply(integer_literal(i), integer_literal(i * 100)));
+        }
+        expr
+    }
+
+    #[test]
+    fn can_render_documents() {
+        let doc = Doc::group(Doc::concat(vec![
+            Doc::text("let x ="),
+            Doc::nest(2, Doc::concat(vec![Doc::line(), Doc::text("42")])),
+        ]));
+        assert_eq!(doc.pretty(80), "let x = 42");
+        assert_eq!(doc.pretty(8), "let x =\n  42");
+        let doc = Doc::group(Doc::concat(vec![
+            Doc::text("["),
+            Doc::softline(),
+            Doc::text("]"),
+        ]));
+        assert_eq!(doc.pretty(80), "[]");
+        assert_eq!(doc.pretty(1), "[\n]");
+    }
+
+    #[test]
+    fn matches_format_with_when_flat() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let options = FormatOptions::default();
+        assert_eq!(format_pretty(&expr, &options), expr.to_string());
+        let options = FormatOptions {
+            spacing: false,
+            radix: Radix::Hexadecimal,
+            ..minimal(80)
+        };
+        assert_eq!(format_pretty(&expr, &options), "0x50*0x5+0x4");
+    }
+
+    #[test]
+    fn can_wrap_expressions() {
+        let expr: MultExpr = multiply(
+            add(integer_literal(1000), integer_literal(2000)),
+            add(integer_literal(3000), integer_literal(4000)),
+        );
+        assert_eq!(
+            format_pretty(&expr, &minimal(20)),
+            "(1000 + 2000)\n* (3000 + 4000)"
+        );
+        assert_eq!(
+            format_pretty(&expr, &minimal(12)),
+            "(1000\n + 2000)\n* (3000\n   + 4000)"
+        );
+    }
+
+    #[test]
+    fn large_expressions_fit_in_the_page() {
+        let expr = long_sum(200);
+        let output = format_pretty(&expr, &minimal(40));
+        assert!(output.lines().count() > 1);
+        for line in output.lines() {
+            assert!(line.len() <= 40, "{:?} is too long", line);
+        }
+        // Each product stays on one line, since each one fits.
+        assert!(output.lines().any(|line| line == "+ 200 * 20000"));
+        // And taking out the line breaks gives the flat output back.
+        assert_eq!(
+            output.replace('\n', " "),
+            format_pretty(
+                &expr,
+                &FormatOptions {
+                    max_width: None,
+                    ..minimal(0)
+                }
+            )
+        );
+    }
+}
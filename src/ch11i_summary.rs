@@ -0,0 +1,220 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Sometimes we want to mention an expression somewhere that can't hold all of it: a log line, an
+//! error message, the title of a window.  The Display impls will happily produce megabytes of
+//! output for a big enough expression, and chopping that off after the first few dozen characters
+//! usually leaves you looking at a wall of open parentheses.
+//!
+//! In this chapter we add a `summary` function that fits an expression into a fixed number of
+//! characters by printing as much of it as will fit, and replacing whole subexpressions that
+//! don't with a placeholder that says how big they are:
+//!
+//! ```text
+//! (118 + (… 413 nodes …))
+//! ```
+//!
+//! We reuse the `Layout` from ch11a, so this works for any expression that `format_with` can
+//! print.
+
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::ch11a_format_options::*;
+
+/// A layout, along with the size of each subexpression, so that we only have to compute them
+/// once.
+struct Measured<'a> {
+    layout: &'a Layout,
+    /// The number of terms in this subexpression.
+    nodes: usize,
+    /// The number of characters that Display would print for this subexpression.
+    width: usize,
+    operands: Option<Box<(Measured<'a>, Measured<'a>)>>,
+}
+
+impl<'a> Measured<'a> {
+    fn new(layout: &'a Layout) -> Measured<'a> {
+        match layout {
+            Layout::Literal(value) => Measured {
+                layout,
+                nodes: 1,
+                width: format_literal(*value, Radix::Decimal).len(),
+                operands: None,
+            },
+            Layout::Binary {
+                operator, lhs, rhs, ..
+            } => {
+                let lhs = Measured::new(lhs);
+                let rhs = Measured::new(rhs);
+                Measured {
+                    layout,
+                    nodes: 1 + lhs.nodes + rhs.nodes,
+                    // "(" lhs " " operator " " rhs ")"
+                    width: lhs.width + rhs.width + operator.chars().count() + 4,
+                    operands: Some(Box::new((lhs, rhs))),
+                }
+            }
+        }
+    }
+
+    /// The placeholder that stands in for this subexpression when it doesn't fit.  Literals don't
+    /// get a node count, since it's always one.
+    fn placeholder(&self) -> String {
+        match self.operands {
+            Some(_) => format!("(… {} nodes …)", self.nodes),
+            None => String::from("…"),
+        }
+    }
+
+    /// The fewest characters that we can usefully print this subexpression in: either all of it,
+    /// or its placeholder, whichever is shorter.  A bare ellipsis doesn't tell the reader
+    /// anything, so we never elide a literal unless it's the whole expression.
+    fn minimum(&self) -> usize {
+        match self.operands {
+            Some(_) => self.width.min(self.placeholder().chars().count()),
+            None => self.width,
+        }
+    }
+
+    /// Prints this subexpression in at most `budget` characters.
+    fn summarize(&self, budget: usize, result: &mut String) {
+        if self.width <= budget {
+            result.push_str(&self.layout.format(&FormatOptions::default()));
+            return;
+        }
+
+        // If the operands can each get at least their minimum, print the operator and split the
+        // rest of the budget between them.  Whichever operand fits completely gets all the room it
+        // needs, and the other one gets what's left; otherwise they split it evenly.
+        if let (Some(operands), Layout::Binary { operator, .. }) = (&self.operands, self.layout) {
+            let (lhs, rhs) = &**operands;
+            let overhead = operator.chars().count() + 4;
+            let remaining = budget.saturating_sub(overhead);
+            if budget >= overhead && remaining >= lhs.minimum() + rhs.minimum() {
+                let lhs_budget = if lhs.width + rhs.minimum() <= remaining {
+                    lhs.width
+                } else if rhs.width + lhs.minimum() <= remaining {
+                    remaining - rhs.width
+                } else {
+                    (remaining / 2)
+                        .max(lhs.minimum())
+                        .min(remaining - rhs.minimum())
+                };
+                result.push('(');
+                lhs.summarize(lhs_budget, result);
+                result.push(' ');
+                result.push_str(operator);
+                result.push(' ');
+                rhs.summarize(remaining - lhs_budget, result);
+                result.push(')');
+                return;
+            }
+        }
+
+        // Otherwise elide the whole thing.  If even the placeholder is too long, fall back on a
+        // bare ellipsis, or on nothing at all.
+        let placeholder = self.placeholder();
+        if placeholder.chars().count() <= budget {
+            result.push_str(&placeholder);
+        } else if budget > 0 {
+            result.push('…');
+        }
+    }
+}
+
+impl Layout {
+    /// Prints this layout in at most `max_len` characters, eliding any subexpressions that don't
+    /// fit.
+    pub fn summary(&self, max_len: usize) -> String {
+        let mut result = String::new();
+        Measured::new(self).summarize(max_len, &mut result);
+        result
+    }
+}
+
+/// Prints an expression in at most `max_len` characters (not bytes), eliding any subexpressions
+/// that don't fit.  If the whole expression fits, this is the same as its Display output.
+pub fn summary<E>(expr: &E, max_len: usize) -> String
+where
+    E: Expression,
+    ToLayout: Algebra<E::Signature, E, Layout>,
+{
+    to_layout(expr).summary(max_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+
+    /// 1 + (1 + (1 + ...)), with `count` literals.
+    fn chain(count: usize) -> MultExpr {
+        let mut expr = integer_literal(1);
+        for _ in 1..count {
+            expr = add(integer_literal(1), expr);
+        }
+        expr
+    }
+
+    #[test]
+    fn small_expressions_are_printed_in_full() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(summary(&expr, 80), expr.to_string());
+        assert_eq!(summary(&expr, expr.to_string().len()), expr.to_string());
+    }
+
+    #[test]
+    fn large_subexpressions_are_elided() {
+        let expr = add(integer_literal(118), chain(207));
+        assert_eq!(summary(&expr, 23), "(118 + (… 413 nodes …))");
+        assert_eq!(summary(&expr, 22), "(… 415 nodes …)");
+        assert_eq!(summary(&expr, 30), "(118 + (1 + (… 411 nodes …)))");
+        assert_eq!(summary(&expr, 5), "…");
+        assert_eq!(summary(&expr, 0), "");
+    }
+
+    #[test]
+    fn both_sides_can_be_elided() {
+        let expr: MultExpr = multiply(chain(50), chain(50));
+        assert_eq!(summary(&expr, 40), "((… 99 nodes …) * (… 99 nodes …))");
+    }
+
+    #[test]
+    fn summaries_fit_in_their_budget() {
+        let expr: MultExpr = multiply(
+            add(
+                chain(30),
+                add(
+                    multiply(integer_literal(80), integer_literal(5)),
+                    integer_literal(4),
+                ),
+            ),
+            chain(12),
+        );
+        let full = expr.to_string();
+        for max_len in 0..full.len() + 2 {
+            let summary = summary(&expr, max_len);
+            assert!(summary.chars().count() <= max_len, "{}", summary);
+            if max_len >= full.len() {
+                assert_eq!(summary, full);
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A language doesn't have to be about integers!  Here's a tiny language of boolean formulas, built
+//! out of the same kinds of pieces as everything else: one type per term, a Sum for the signature,
+//! and open-recursion evaluation rules from ch08b.
+
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+
+use std::fmt;
+
+/// A boolean constant.
+pub struct BoolLiteral {
+    pub value: bool,
+}
+
+/// A named variable, whose value we don't know yet.
+pub struct Variable {
+    pub name: String,
+}
+
+/// True if both subexpressions are true.
+pub struct And<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+/// True if either subexpression is true.
+pub struct Or<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+/// True if the subexpression is false.
+pub struct Not<E> {
+    pub expr: E,
+}
+
+pub fn bool_literal<E: From<BoolLiteral>>(value: bool) -> E {
+    E::from(BoolLiteral { value })
+}
+
+pub fn variable<E: From<Variable>>(name: &str) -> E {
+    E::from(Variable {
+        name: name.to_string(),
+    })
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+pub fn and<E: From<And<E>>>(lhs: E, rhs: E) -> E {
+    E::from(And { lhs, rhs })
+}
+
+pub fn or<E: From<Or<E>>>(lhs: E, rhs: E) -> E {
+    E::from(Or { lhs, rhs })
+}
+
+pub fn not<E: From<Not<E>>>(expr: E) -> E {
+    E::from(Not { expr })
+}
+
+impl fmt::Display for BoolLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for And<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} ∧ {})", self.lhs, self.rhs)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Or<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} ∨ {})", self.lhs, self.rhs)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Not<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "¬{}", self.expr)
+    }
+}
+
+pub type BoolSig<E> = Sum![BoolLiteral, Variable, And<E>, Or<E>, Not<E>];
+pub struct BoolExpr(pub Box<BoolSig<BoolExpr>>);
+
+impl Expression for BoolExpr {
+    type Signature = BoolSig<BoolExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    BoolExpr: BoolLiteral,
+    Variable,
+    And<BoolExpr>,
+    Or<BoolExpr>,
+    Not<BoolExpr>,
+);
+
+// And one without variables, whose formulas always have a definite value.
+pub type ClosedBoolSig<E> = Sum![BoolLiteral, And<E>, Or<E>, Not<E>];
+pub struct ClosedBoolExpr(pub Box<ClosedBoolSig<ClosedBoolExpr>>);
+
+impl Expression for ClosedBoolExpr {
+    type Signature = ClosedBoolSig<ClosedBoolExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    ClosedBoolExpr: BoolLiteral,
+    And<ClosedBoolExpr>,
+    Or<ClosedBoolExpr>,
+    Not<ClosedBoolExpr>,
+);
+
+// The evaluation rules use the standard bitwise operators, so that they'll work for bool, and for
+// any other value type that wants to act like one.  Variables need a value type that knows what to
+// do with them; there's no sensible way to turn a variable into a plain bool.
+
+/// A value type that can represent a variable whose value isn't known.
+pub trait FromVariable {
+    fn from_variable(name: &str) -> Self;
+}
+
+impl<V, E> Eval<V, E> for BoolLiteral
+where
+    V: From<bool>,
+{
+    fn eval<F>(&self, _eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        V::from(self.value)
+    }
+}
+
+impl<V, E> Eval<V, E> for Variable
+where
+    V: FromVariable,
+{
+    fn eval<F>(&self, _eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        V::from_variable(&self.name)
+    }
+}
+
+impl<V, E> Eval<V, E> for And<E>
+where
+    V: std::ops::BitAnd<Output = V>,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs) & eval_subexpr(&self.rhs)
+    }
+}
+
+impl<V, E> Eval<V, E> for Or<E>
+where
+    V: std::ops::BitOr<Output = V>,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs) | eval_subexpr(&self.rhs)
+    }
+}
+
+impl<V, E> Eval<V, E> for Not<E>
+where
+    V: std::ops::Not<Output = V>,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        !eval_subexpr(&self.expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_evaluate_formulas() {
+        // (true ∧ ¬false) ∨ false
+        let expr: ClosedBoolExpr = or(
+            and(bool_literal(true), not(bool_literal(false))),
+            bool_literal(false),
+        );
+        assert!(expr.evaluate::<bool>());
+    }
+
+    #[test]
+    fn can_evaluate_negation() {
+        let expr: ClosedBoolExpr = not(and(bool_literal(true), bool_literal(false)));
+        assert!(expr.evaluate::<bool>());
+    }
+}
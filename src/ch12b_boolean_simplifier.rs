@@ -0,0 +1,399 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Let's simplify boolean formulas, by applying De Morgan's laws, absorption, and constant
+//! elimination until none of them apply.  That's exactly what the rewrite.rs engine is for, so each
+//! law is a `Rule`, and the `Rewriter` decides where to apply them.
+//!
+//! The engine works on `Node`s, whose leaves are integers, so we need a way to get a boolean
+//! expression in and out of that form.  We evaluate the expression into a `Formula` (ch08b's
+//! evaluator will fold an expression into any value type that has the right operators), and then
+//! write each constant as a 0 or 1 and each variable as its index into a table of names.
+
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch12a_booleans::*;
+use crate::rewrite::*;
+
+use std::fmt;
+
+/// A boolean formula, as a plain tree that we can convert to and from a `Node`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Formula {
+    Constant(bool),
+    Variable(String),
+    Not(Box<Formula>),
+    And(Box<Formula>, Box<Formula>),
+    Or(Box<Formula>, Box<Formula>),
+}
+
+impl From<bool> for Formula {
+    fn from(value: bool) -> Formula {
+        Formula::Constant(value)
+    }
+}
+
+impl FromVariable for Formula {
+    fn from_variable(name: &str) -> Formula {
+        Formula::Variable(name.to_string())
+    }
+}
+
+impl std::ops::Not for Formula {
+    type Output = Formula;
+    fn not(self) -> Formula {
+        Formula::Not(Box::new(self))
+    }
+}
+
+impl std::ops::BitAnd for Formula {
+    type Output = Formula;
+    fn bitand(self, other: Formula) -> Formula {
+        Formula::And(Box::new(self), Box::new(other))
+    }
+}
+
+impl std::ops::BitOr for Formula {
+    type Output = Formula;
+    fn bitor(self, other: Formula) -> Formula {
+        Formula::Or(Box::new(self), Box::new(other))
+    }
+}
+
+impl Formula {
+    /// Converts this formula into a Node, adding the name of each variable to `names` the first
+    /// time we see it.
+    pub fn to_node(&self, names: &mut Vec<String>) -> Node {
+        match self {
+            Formula::Constant(value) => constant(*value),
+            Formula::Variable(name) => {
+                let index = match names.iter().position(|existing| existing == name) {
+                    Some(index) => index,
+                    None => {
+                        names.push(name.clone());
+                        names.len() - 1
+                    }
+                };
+                Node::term("variable", vec![Node::Literal(index as i64)])
+            }
+            Formula::Not(expr) => Node::term("not", vec![expr.to_node(names)]),
+            Formula::And(lhs, rhs) => {
+                Node::term("and", vec![lhs.to_node(names), rhs.to_node(names)])
+            }
+            Formula::Or(lhs, rhs) => Node::term("or", vec![lhs.to_node(names), rhs.to_node(names)]),
+        }
+    }
+
+    /// Converts a Node back into a formula, using the same table of names that `to_node` filled
+    /// in.  Returns None if the Node isn't a boolean formula.
+    pub fn from_node(node: &Node, names: &[String]) -> Option<Formula> {
+        let children = node.children();
+        match (node.kind(), children) {
+            ("bool_literal", [Node::Literal(value)]) => Some(Formula::Constant(*value != 0)),
+            ("variable", [Node::Literal(index)]) => {
+                Some(Formula::Variable(names.get(*index as usize)?.clone()))
+            }
+            ("not", [expr]) => Some(!Formula::from_node(expr, names)?),
+            ("and", [lhs, rhs]) => {
+                Some(Formula::from_node(lhs, names)? & Formula::from_node(rhs, names)?)
+            }
+            ("or", [lhs, rhs]) => {
+                Some(Formula::from_node(lhs, names)? | Formula::from_node(rhs, names)?)
+            }
+            _ => None,
+        }
+    }
+
+    /// Turns this formula back into an expression of any type that has all of the boolean terms.
+    pub fn to_expr<E>(&self) -> E
+    where
+        E: From<BoolLiteral> + From<Variable> + From<And<E>> + From<Or<E>> + From<Not<E>>,
+    {
+        match self {
+            Formula::Constant(value) => bool_literal(*value),
+            Formula::Variable(name) => variable(name),
+            Formula::Not(expr) => not(expr.to_expr()),
+            Formula::And(lhs, rhs) => and(lhs.to_expr(), rhs.to_expr()),
+            Formula::Or(lhs, rhs) => or(lhs.to_expr(), rhs.to_expr()),
+        }
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Formula::Constant(value) => write!(f, "{}", value),
+            Formula::Variable(name) => write!(f, "{}", name),
+            Formula::Not(expr) => write!(f, "¬{}", expr),
+            Formula::And(lhs, rhs) => write!(f, "({} ∧ {})", lhs, rhs),
+            Formula::Or(lhs, rhs) => write!(f, "({} ∨ {})", lhs, rhs),
+        }
+    }
+}
+
+fn constant(value: bool) -> Node {
+    Node::term("bool_literal", vec![Node::Literal(value as i64)])
+}
+
+fn as_constant(node: &Node) -> Option<bool> {
+    match (node.kind(), node.children()) {
+        ("bool_literal", [Node::Literal(value)]) => Some(*value != 0),
+        _ => None,
+    }
+}
+
+fn operands<'a>(node: &'a Node, kind: &str) -> Option<(&'a Node, &'a Node)> {
+    match node.children() {
+        [lhs, rhs] if node.kind() == kind => Some((lhs, rhs)),
+        _ => None,
+    }
+}
+
+fn negated<'a>(node: &'a Node, kind: &str) -> Option<&'a Node> {
+    match node.children() {
+        [expr] if node.kind() == "not" && expr.kind() == kind => Some(expr),
+        _ => None,
+    }
+}
+
+// Negation is where De Morgan's laws come in.  They push every negation down through the
+// conjunctions and disjunctions beneath it, until it reaches a constant (which it flips) or a
+// variable (where it stays).  Double negations cancel out along the way.
+
+/// ¬true is false, and ¬false is true.
+pub const NOT_CONSTANT: Rule = Rule {
+    name: "not constant",
+    rewrite: |node| Some(constant(!as_constant(negated(node, "bool_literal")?)?)),
+};
+
+/// ¬¬a is a.
+pub const DOUBLE_NEGATION: Rule = Rule {
+    name: "double negation",
+    rewrite: |node| Some(negated(node, "not")?.children()[0].clone()),
+};
+
+/// ¬(a ∧ b) is ¬a ∨ ¬b.
+pub const DE_MORGAN_AND: Rule = Rule {
+    name: "de morgan and",
+    rewrite: |node| {
+        let (lhs, rhs) = operands(negated(node, "and")?, "and")?;
+        Some(Node::term(
+            "or",
+            vec![
+                Node::term("not", vec![lhs.clone()]),
+                Node::term("not", vec![rhs.clone()]),
+            ],
+        ))
+    },
+};
+
+/// ¬(a ∨ b) is ¬a ∧ ¬b.
+pub const DE_MORGAN_OR: Rule = Rule {
+    name: "de morgan or",
+    rewrite: |node| {
+        let (lhs, rhs) = operands(negated(node, "or")?, "or")?;
+        Some(Node::term(
+            "and",
+            vec![
+                Node::term("not", vec![lhs.clone()]),
+                Node::term("not", vec![rhs.clone()]),
+            ],
+        ))
+    },
+};
+
+// Conjunction and disjunction are mirror images of each other.  Each one eliminates constants,
+// removes duplicate operands, and applies the absorption law: a ∧ (a ∨ b) is just a, and
+// a ∨ (a ∧ b) is too.  The dominating constant is the one that decides the result by itself: false
+// for a conjunction, and true for a disjunction.
+
+fn eliminate_constant(node: &Node, kind: &str, dominant: bool) -> Option<Node> {
+    let (lhs, rhs) = operands(node, kind)?;
+    match (as_constant(lhs), as_constant(rhs)) {
+        (Some(value), _) | (_, Some(value)) if value == dominant => Some(constant(dominant)),
+        (Some(_), _) => Some(rhs.clone()),
+        (_, Some(_)) => Some(lhs.clone()),
+        (None, None) => None,
+    }
+}
+
+fn remove_duplicate(node: &Node, kind: &str) -> Option<Node> {
+    let (lhs, rhs) = operands(node, kind)?;
+    if lhs == rhs {
+        Some(lhs.clone())
+    } else {
+        None
+    }
+}
+
+/// Whether `other` is one of the operands of `node`, if it's an `inner` term.
+fn absorbs(node: &Node, other: &Node, inner: &str) -> bool {
+    match operands(node, inner) {
+        Some((lhs, rhs)) => lhs == other || rhs == other,
+        None => false,
+    }
+}
+
+fn absorb(node: &Node, kind: &str, inner: &str) -> Option<Node> {
+    let (lhs, rhs) = operands(node, kind)?;
+    if absorbs(rhs, lhs, inner) {
+        Some(lhs.clone())
+    } else if absorbs(lhs, rhs, inner) {
+        Some(rhs.clone())
+    } else {
+        None
+    }
+}
+
+/// false ∧ a is false, and true ∧ a is a.
+pub const AND_CONSTANT: Rule = Rule {
+    name: "and constant",
+    rewrite: |node| eliminate_constant(node, "and", false),
+};
+
+/// true ∨ a is true, and false ∨ a is a.
+pub const OR_CONSTANT: Rule = Rule {
+    name: "or constant",
+    rewrite: |node| eliminate_constant(node, "or", true),
+};
+
+/// a ∧ a is a.
+pub const AND_IDEMPOTENCE: Rule = Rule {
+    name: "and idempotence",
+    rewrite: |node| remove_duplicate(node, "and"),
+};
+
+/// a ∨ a is a.
+pub const OR_IDEMPOTENCE: Rule = Rule {
+    name: "or idempotence",
+    rewrite: |node| remove_duplicate(node, "or"),
+};
+
+/// a ∧ (a ∨ b) is a.
+pub const AND_ABSORPTION: Rule = Rule {
+    name: "and absorption",
+    rewrite: |node| absorb(node, "and", "or"),
+};
+
+/// a ∨ (a ∧ b) is a.
+pub const OR_ABSORPTION: Rule = Rule {
+    name: "or absorption",
+    rewrite: |node| absorb(node, "or", "and"),
+};
+
+/// A rewriter that applies all of the simplification rules.
+pub fn boolean_simplifier() -> Rewriter {
+    Rewriter::new(vec![
+        NOT_CONSTANT,
+        DOUBLE_NEGATION,
+        DE_MORGAN_AND,
+        DE_MORGAN_OR,
+        AND_CONSTANT,
+        OR_CONSTANT,
+        AND_IDEMPOTENCE,
+        OR_IDEMPOTENCE,
+        AND_ABSORPTION,
+        OR_ABSORPTION,
+    ])
+}
+
+/// Simplifies a boolean expression, returning a new expression of the same type.
+pub fn simplify<E>(expr: &E) -> E
+where
+    E: Expression + Eval<Formula, E>,
+    E: From<BoolLiteral> + From<Variable> + From<And<E>> + From<Or<E>> + From<Not<E>>,
+{
+    let mut names = Vec::new();
+    let node = expr.evaluate::<Formula>().to_node(&mut names);
+    let simplified = boolean_simplifier().normalize(&node);
+    // None of the rules introduce new kinds of terms or new variables.
+    Formula::from_node(&simplified, &names)
+        .expect("simplifier produced a non-boolean node")
+        .to_expr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simplified(expr: BoolExpr) -> String {
+        simplify(&expr).evaluate::<Formula>().to_string()
+    }
+
+    fn a() -> BoolExpr {
+        variable("a")
+    }
+
+    fn b() -> BoolExpr {
+        variable("b")
+    }
+
+    #[test]
+    fn can_eliminate_constants() {
+        assert_eq!(simplified(and(a(), bool_literal(true))), "a");
+        assert_eq!(simplified(and(bool_literal(false), a())), "false");
+        assert_eq!(simplified(or(a(), bool_literal(false))), "a");
+        assert_eq!(simplified(or(bool_literal(true), a())), "true");
+        assert_eq!(simplified(not(bool_literal(true))), "false");
+    }
+
+    #[test]
+    fn can_apply_de_morgans_laws() {
+        assert_eq!(simplified(not(and(a(), b()))), "(¬a ∨ ¬b)");
+        assert_eq!(simplified(not(or(a(), b()))), "(¬a ∧ ¬b)");
+        assert_eq!(simplified(not(not(a()))), "a");
+        assert_eq!(simplified(not(or(not(a()), b()))), "(a ∧ ¬b)");
+    }
+
+    #[test]
+    fn can_apply_absorption() {
+        assert_eq!(simplified(and(a(), or(a(), b()))), "a");
+        assert_eq!(simplified(and(or(b(), a()), a())), "a");
+        assert_eq!(simplified(or(a(), and(b(), a()))), "a");
+        assert_eq!(simplified(or(and(a(), b()), a())), "a");
+    }
+
+    #[test]
+    fn can_simplify_after_rewriting_subexpressions() {
+        // ¬(¬a ∧ true) ∧ (a ∨ (b ∧ false)) — both sides simplify to a, which then collapse together
+        let expr: BoolExpr = and(
+            not(and(not(a()), bool_literal(true))),
+            or(a(), and(b(), bool_literal(false))),
+        );
+        assert_eq!(simplified(expr), "a");
+    }
+
+    #[test]
+    fn simplifying_a_closed_formula_evaluates_it() {
+        let expr: BoolExpr = or(
+            not(and(bool_literal(true), not(bool_literal(false)))),
+            and(bool_literal(true), bool_literal(true)),
+        );
+        assert_eq!(simplified(expr), "true");
+    }
+
+    #[test]
+    fn can_explain_simplifications() {
+        // ¬(a ∧ ¬b)
+        let expr: BoolExpr = not(and(a(), not(b())));
+        let mut names = Vec::new();
+        let node = expr.evaluate::<Formula>().to_node(&mut names);
+        let (_, proof) = boolean_simplifier().normalize_with_proof(&node);
+        assert_eq!(
+            proof.to_string(),
+            "1. de morgan and at root\n2. double negation at 1\n"
+        );
+    }
+}
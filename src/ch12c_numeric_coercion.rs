@@ -0,0 +1,492 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Another language that isn't only about integers: this one has floating-point numbers too.  The
+//! interesting question is what `1 + 2.5` means.  We could teach the evaluator to promote an int to
+//! a float whenever it sees mixed operands, but then every evaluator (and every value type) has to
+//! agree on the promotion rules, and has to check for them at runtime.
+//!
+//! Instead, the evaluator refuses to mix types, and we make conversions explicit with a new
+//! `IntToFloat` term.  Nobody wants to write those by hand, so a type-directed pass inserts them:
+//! wherever the type checker would find an int and a float meeting at an operator, the pass wraps
+//! the int in a conversion.  Its output always type-checks.
+
+use crate::ch02_open_sum::*;
+use crate::ch04_smart_constructors::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch08f_generic_literals::*;
+use crate::ch09a_mendler::*;
+
+use std::fmt;
+
+/// A floating-point constant.
+pub type FloatLiteral = Lit<f64>;
+
+/// Converts an int subexpression into a float.
+pub struct IntToFloat<E> {
+    pub expr: E,
+}
+
+pub fn float_literal<E: From<FloatLiteral>>(value: f64) -> E {
+    E::from(FloatLiteral { value })
+}
+
+pub fn int_to_float<E: From<IntToFloat<E>>>(expr: E) -> E {
+    E::from(IntToFloat { expr })
+}
+
+impl<E: fmt::Display> fmt::Display for IntToFloat<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "float({})", self.expr)
+    }
+}
+
+pub type NumSig<E> = Sum![FloatLiteral, IntToFloat<E>, MultSig<E>];
+pub struct NumExpr(pub Box<NumSig<NumExpr>>);
+
+impl Expression for NumExpr {
+    type Signature = NumSig<NumExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    NumExpr: FloatLiteral,
+    IntToFloat<NumExpr>,
+    IntegerLiteral,
+    Add<NumExpr>,
+    Multiply<NumExpr>,
+);
+
+impl fmt::Display for NumExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// The type checker is a fold from ch09a.  Every subexpression is either an int or a float, and
+// operators need both of their operands to have the same type.
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TypeError {
+    /// An operator's operands have different types.
+    MixedOperands {
+        term: &'static str,
+        lhs: Type,
+        rhs: Type,
+    },
+    /// A conversion's operand is already a float.
+    NotAnInt,
+    /// An operator's int result doesn't fit in an i64.  Not a type error as such, but evaluating
+    /// a well-typed expression can still fail this way.
+    Overflow { term: &'static str },
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeError::MixedOperands { term, lhs, rhs } => {
+                write!(f, "can't {} {:?} and {:?}", term, lhs, rhs)
+            }
+            TypeError::NotAnInt => write!(f, "can only convert an int to a float"),
+            TypeError::Overflow { term } => write!(f, "can't {}: int overflow", term),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// Finds the type of an expression.
+pub struct TypeOf;
+
+impl<E> Algebra<IntegerLiteral, E, Result<Type, TypeError>> for TypeOf {
+    fn apply<F>(&self, _term: &IntegerLiteral, _recurse: F) -> Result<Type, TypeError>
+    where
+        F: FnMut(&E) -> Result<Type, TypeError>,
+    {
+        Ok(Type::Int)
+    }
+}
+
+impl<E> Algebra<FloatLiteral, E, Result<Type, TypeError>> for TypeOf {
+    fn apply<F>(&self, _term: &FloatLiteral, _recurse: F) -> Result<Type, TypeError>
+    where
+        F: FnMut(&E) -> Result<Type, TypeError>,
+    {
+        Ok(Type::Float)
+    }
+}
+
+impl<E> Algebra<IntToFloat<E>, E, Result<Type, TypeError>> for TypeOf {
+    fn apply<F>(&self, term: &IntToFloat<E>, mut recurse: F) -> Result<Type, TypeError>
+    where
+        F: FnMut(&E) -> Result<Type, TypeError>,
+    {
+        match recurse(&term.expr)? {
+            Type::Int => Ok(Type::Float),
+            Type::Float => Err(TypeError::NotAnInt),
+        }
+    }
+}
+
+fn same_type(term: &'static str, lhs: Type, rhs: Type) -> Result<Type, TypeError> {
+    if lhs == rhs {
+        Ok(lhs)
+    } else {
+        Err(TypeError::MixedOperands { term, lhs, rhs })
+    }
+}
+
+impl<E> Algebra<Add<E>, E, Result<Type, TypeError>> for TypeOf {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> Result<Type, TypeError>
+    where
+        F: FnMut(&E) -> Result<Type, TypeError>,
+    {
+        same_type("add", recurse(&term.lhs)?, recurse(&term.rhs)?)
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, Result<Type, TypeError>> for TypeOf {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> Result<Type, TypeError>
+    where
+        F: FnMut(&E) -> Result<Type, TypeError>,
+    {
+        same_type("multiply", recurse(&term.lhs)?, recurse(&term.rhs)?)
+    }
+}
+
+pub fn type_of<E>(expr: &E) -> Result<Type, TypeError>
+where
+    E: Expression,
+    TypeOf: Algebra<E::Signature, E, Result<Type, TypeError>>,
+{
+    mcata(&TypeOf, expr)
+}
+
+// The coercion pass is another fold, which follows the same typing rules but rebuilds the
+// expression as it goes.  When an operator's operands don't match, it converts whichever one is
+// the int, instead of reporting an error.  The only error left is converting a float, which can
+// only happen if the input already had a conversion in it.
+
+/// An expression, along with its type.
+pub struct Typed<E> {
+    pub expr: E,
+    pub ty: Type,
+}
+
+/// Inserts an `IntToFloat` wherever an int meets a float.
+pub struct InsertCoercions;
+
+type Coerced<E> = Result<Typed<E>, TypeError>;
+
+impl<E> Algebra<IntegerLiteral, E, Coerced<E>> for InsertCoercions
+where
+    E: From<IntegerLiteral>,
+{
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Coerced<E>
+    where
+        F: FnMut(&E) -> Coerced<E>,
+    {
+        let expr = integer_literal(term.value);
+        Ok(Typed {
+            expr,
+            ty: Type::Int,
+        })
+    }
+}
+
+impl<E> Algebra<FloatLiteral, E, Coerced<E>> for InsertCoercions
+where
+    E: From<FloatLiteral>,
+{
+    fn apply<F>(&self, term: &FloatLiteral, _recurse: F) -> Coerced<E>
+    where
+        F: FnMut(&E) -> Coerced<E>,
+    {
+        let expr = float_literal(term.value);
+        Ok(Typed {
+            expr,
+            ty: Type::Float,
+        })
+    }
+}
+
+impl<E> Algebra<IntToFloat<E>, E, Coerced<E>> for InsertCoercions
+where
+    E: From<IntToFloat<E>>,
+{
+    fn apply<F>(&self, term: &IntToFloat<E>, mut recurse: F) -> Coerced<E>
+    where
+        F: FnMut(&E) -> Coerced<E>,
+    {
+        let operand = recurse(&term.expr)?;
+        if operand.ty != Type::Int {
+            return Err(TypeError::NotAnInt);
+        }
+        Ok(Typed {
+            expr: int_to_float(operand.expr),
+            ty: Type::Float,
+        })
+    }
+}
+
+fn promote<E: From<IntToFloat<E>>>(operand: Typed<E>) -> E {
+    match operand.ty {
+        Type::Int => int_to_float(operand.expr),
+        Type::Float => operand.expr,
+    }
+}
+
+/// Gives two operands the same type, converting one of them if needed.
+fn unify<E: From<IntToFloat<E>>>(lhs: Typed<E>, rhs: Typed<E>) -> (E, E, Type) {
+    if lhs.ty == rhs.ty {
+        (lhs.expr, rhs.expr, lhs.ty)
+    } else {
+        (promote(lhs), promote(rhs), Type::Float)
+    }
+}
+
+impl<E> Algebra<Add<E>, E, Coerced<E>> for InsertCoercions
+where
+    E: From<Add<E>> + From<IntToFloat<E>>,
+{
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> Coerced<E>
+    where
+        F: FnMut(&E) -> Coerced<E>,
+    {
+        let (lhs, rhs, ty) = unify(recurse(&term.lhs)?, recurse(&term.rhs)?);
+        Ok(Typed {
+            expr: add(lhs, rhs),
+            ty,
+        })
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, Coerced<E>> for InsertCoercions
+where
+    E: From<Multiply<E>> + From<IntToFloat<E>>,
+{
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> Coerced<E>
+    where
+        F: FnMut(&E) -> Coerced<E>,
+    {
+        let (lhs, rhs, ty) = unify(recurse(&term.lhs)?, recurse(&term.rhs)?);
+        Ok(Typed {
+            expr: multiply(lhs, rhs),
+            ty,
+        })
+    }
+}
+
+/// Returns a copy of `expr` with explicit conversions wherever an int meets a float.
+pub fn insert_coercions<E>(expr: &E) -> Coerced<E>
+where
+    E: Expression,
+    InsertCoercions: Algebra<E::Signature, E, Coerced<E>>,
+{
+    mcata(&InsertCoercions, expr)
+}
+
+// Now the evaluator.  Its value type holds either kind of number, and its operators never convert
+// anything: mixing an int and a float is an error, just like in the type checker.  The only way to
+// turn an int into a float is the `IntToFloat` term, whose evaluation rule needs a value type that
+// knows how to do the conversion.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+/// A value type that can convert an int into a float.
+pub trait Promote {
+    fn promote(self) -> Self;
+}
+
+/// The result of evaluating a `NumExpr`, which fails if any operator sees mixed operands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Strict(pub Result<Number, TypeError>);
+
+impl From<i64> for Strict {
+    fn from(value: i64) -> Strict {
+        Strict(Ok(Number::Int(value)))
+    }
+}
+
+impl From<f64> for Strict {
+    fn from(value: f64) -> Strict {
+        Strict(Ok(Number::Float(value)))
+    }
+}
+
+impl Promote for Strict {
+    fn promote(self) -> Strict {
+        Strict(match self.0 {
+            Ok(Number::Int(value)) => Ok(Number::Float(value as f64)),
+            Ok(Number::Float(_)) => Err(TypeError::NotAnInt),
+            Err(error) => Err(error),
+        })
+    }
+}
+
+impl Strict {
+    fn combine(
+        self,
+        other: Strict,
+        term: &'static str,
+        int: fn(i64, i64) -> Option<i64>,
+        float: fn(f64, f64) -> f64,
+    ) -> Strict {
+        Strict(match (self.0, other.0) {
+            (Err(error), _) | (_, Err(error)) => Err(error),
+            (Ok(Number::Int(lhs)), Ok(Number::Int(rhs))) => int(lhs, rhs)
+                .map(Number::Int)
+                .ok_or(TypeError::Overflow { term }),
+            (Ok(Number::Float(lhs)), Ok(Number::Float(rhs))) => Ok(Number::Float(float(lhs, rhs))),
+            (Ok(Number::Int(_)), Ok(Number::Float(_))) => Err(TypeError::MixedOperands {
+                term,
+                lhs: Type::Int,
+                rhs: Type::Float,
+            }),
+            (Ok(Number::Float(_)), Ok(Number::Int(_))) => Err(TypeError::MixedOperands {
+                term,
+                lhs: Type::Float,
+                rhs: Type::Int,
+            }),
+        })
+    }
+}
+
+impl std::ops::Add for Strict {
+    type Output = Strict;
+    fn add(self, other: Strict) -> Strict {
+        self.combine(other, "add", i64::checked_add, |lhs, rhs| lhs + rhs)
+    }
+}
+
+impl std::ops::Mul for Strict {
+    type Output = Strict;
+    fn mul(self, other: Strict) -> Strict {
+        self.combine(other, "multiply", i64::checked_mul, |lhs, rhs| lhs * rhs)
+    }
+}
+
+impl<V, E> Eval<V, E> for IntToFloat<E>
+where
+    V: Promote,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.expr).promote()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2 * (1.5 + 3)
+    fn mixed() -> NumExpr {
+        multiply(
+            integer_literal(2),
+            add(float_literal(1.5), integer_literal(3)),
+        )
+    }
+
+    #[test]
+    fn can_type_check() {
+        let expr: NumExpr = add(integer_literal(1), integer_literal(2));
+        assert_eq!(type_of(&expr), Ok(Type::Int));
+        let expr: NumExpr = add(int_to_float(integer_literal(1)), float_literal(0.5));
+        assert_eq!(type_of(&expr), Ok(Type::Float));
+        assert_eq!(
+            type_of(&mixed()),
+            Err(TypeError::MixedOperands {
+                term: "add",
+                lhs: Type::Float,
+                rhs: Type::Int,
+            })
+        );
+        let expr: NumExpr = int_to_float(float_literal(0.5));
+        assert_eq!(type_of(&expr), Err(TypeError::NotAnInt));
+    }
+
+    #[test]
+    fn evaluator_does_not_promote() {
+        assert_eq!(
+            mixed().evaluate::<Strict>(),
+            Strict(Err(TypeError::MixedOperands {
+                term: "add",
+                lhs: Type::Float,
+                rhs: Type::Int,
+            }))
+        );
+    }
+
+    #[test]
+    fn evaluator_checks_for_overflow() {
+        let expr: NumExpr = add(integer_literal(i64::MAX), integer_literal(1));
+        assert_eq!(
+            expr.evaluate::<Strict>(),
+            Strict(Err(TypeError::Overflow { term: "add" }))
+        );
+        let expr: NumExpr = multiply(integer_literal(i64::MIN), integer_literal(-1));
+        assert_eq!(
+            expr.evaluate::<Strict>(),
+            Strict(Err(TypeError::Overflow { term: "multiply" }))
+        );
+    }
+
+    #[test]
+    fn can_insert_coercions() {
+        let coerced = insert_coercions(&mixed()).unwrap();
+        assert_eq!(coerced.ty, Type::Float);
+        assert_eq!(coerced.expr.to_string(), "(float(2) * (1.5 + float(3)))");
+        assert_eq!(type_of(&coerced.expr), Ok(Type::Float));
+        assert_eq!(
+            coerced.expr.evaluate::<Strict>(),
+            Strict(Ok(Number::Float(9.0)))
+        );
+    }
+
+    #[test]
+    fn leaves_well_typed_expressions_alone() {
+        let expr: NumExpr = multiply(
+            add(integer_literal(1), integer_literal(2)),
+            int_to_float(integer_literal(3)),
+        );
+        let coerced = insert_coercions(&expr).unwrap();
+        assert_eq!(coerced.expr.to_string(), "(float((1 + 2)) * float(3))");
+        let expr: NumExpr = int_to_float(float_literal(0.5));
+        assert_eq!(insert_coercions(&expr).err(), Some(TypeError::NotAnInt));
+    }
+}
@@ -0,0 +1,552 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! An embedder usually wants to expose some of its own functionality to the expressions that it
+//! evaluates: a square root, a lookup in its database, the current exchange rate.  This chapter
+//! adds an `Extern` term that calls a host function by name.  Host functions are registered ahead
+//! of time, each with a type signature, so calls can be type-checked before anything runs.  The
+//! evaluator checks the arguments again before dispatching, so a host function never sees values
+//! of the wrong type, even if you skip the type checker.
+//!
+//! The type checker and evaluator both need the table of host functions, which `TypeOf` and the
+//! `Eval` rules from earlier chapters have no way to get at.  So they're algebras that carry the
+//! table with them, and handle the numeric terms from ch12c using the same rules as before.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::ch12c_numeric_coercion::*;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Calls a host function.
+pub struct Extern<E> {
+    pub name: String,
+    pub args: Vec<E>,
+}
+
+pub fn call_extern<E: From<Extern<E>>>(name: &str, args: Vec<E>) -> E {
+    E::from(Extern {
+        name: name.to_string(),
+        args,
+    })
+}
+
+impl<E: fmt::Display> fmt::Display for Extern<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (index, arg) in self.args.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ")")
+    }
+}
+
+pub type ExternSig<E> = Sum<Extern<E>, NumSig<E>>;
+pub struct ExternExpr(pub Box<ExternSig<ExternExpr>>);
+
+impl Expression for ExternExpr {
+    type Signature = ExternSig<ExternExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    ExternExpr: Extern<ExternExpr>,
+    FloatLiteral,
+    IntToFloat<ExternExpr>,
+    IntegerLiteral,
+    Add<ExternExpr>,
+    Multiply<ExternExpr>,
+);
+
+impl fmt::Display for ExternExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The type of a host function.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FunctionType {
+    pub params: Vec<Type>,
+    pub result: Type,
+}
+
+impl FunctionType {
+    pub fn new(params: &[Type], result: Type) -> FunctionType {
+        FunctionType {
+            params: params.to_vec(),
+            result,
+        }
+    }
+}
+
+/// Everything that can go wrong when checking or calling a host function.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExternError {
+    /// A type error in the numeric terms, just like in ch12c.
+    Type(TypeError),
+    UnknownFunction(String),
+    WrongArity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    WrongArgument {
+        name: String,
+        index: usize,
+        expected: Type,
+        found: Type,
+    },
+    /// The host function ran, and failed.
+    Host {
+        name: String,
+        message: String,
+    },
+}
+
+impl From<TypeError> for ExternError {
+    fn from(error: TypeError) -> ExternError {
+        ExternError::Type(error)
+    }
+}
+
+impl fmt::Display for ExternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExternError::Type(error) => error.fmt(f),
+            ExternError::UnknownFunction(name) => write!(f, "no host function named `{}`", name),
+            ExternError::WrongArity {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} arguments, but was given {}",
+                name, expected, found
+            ),
+            ExternError::WrongArgument {
+                name,
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "argument {} of `{}` should be {:?}, not {:?}",
+                index, name, expected, found
+            ),
+            ExternError::Host { name, message } => write!(f, "`{}` failed: {}", name, message),
+        }
+    }
+}
+
+impl std::error::Error for ExternError {}
+
+type Implementation = Box<dyn Fn(&[Number]) -> Result<Number, String>>;
+
+struct HostFunction {
+    ty: FunctionType,
+    implementation: Implementation,
+}
+
+/// The host functions that expressions can call.
+#[derive(Default)]
+pub struct Externs {
+    functions: HashMap<String, HostFunction>,
+}
+
+fn type_of_number(number: &Number) -> Type {
+    match number {
+        Number::Int(_) => Type::Int,
+        Number::Float(_) => Type::Float,
+    }
+}
+
+impl Externs {
+    pub fn new() -> Externs {
+        Externs::default()
+    }
+
+    /// Registers a host function, replacing any existing function with the same name.  The
+    /// function is only ever called with arguments that match `ty`, and must return a value of
+    /// `ty`'s result type.
+    pub fn register<F>(&mut self, name: &str, ty: FunctionType, implementation: F)
+    where
+        F: Fn(&[Number]) -> Result<Number, String> + 'static,
+    {
+        let implementation = Box::new(implementation);
+        self.functions
+            .insert(name.to_string(), HostFunction { ty, implementation });
+    }
+
+    pub fn type_of(&self, name: &str) -> Option<&FunctionType> {
+        self.functions.get(name).map(|function| &function.ty)
+    }
+
+    /// Checks a call against a function's signature, and returns its result type.
+    pub fn check_call(&self, name: &str, args: &[Type]) -> Result<Type, ExternError> {
+        let ty = self
+            .type_of(name)
+            .ok_or_else(|| ExternError::UnknownFunction(name.to_string()))?;
+        if ty.params.len() != args.len() {
+            return Err(ExternError::WrongArity {
+                name: name.to_string(),
+                expected: ty.params.len(),
+                found: args.len(),
+            });
+        }
+        let mismatch = ty
+            .params
+            .iter()
+            .zip(args)
+            .enumerate()
+            .find(|(_, (p, a))| p != a);
+        if let Some((index, (expected, found))) = mismatch {
+            return Err(ExternError::WrongArgument {
+                name: name.to_string(),
+                index,
+                expected: *expected,
+                found: *found,
+            });
+        }
+        Ok(ty.result)
+    }
+
+    /// Checks the arguments against the function's signature, and then calls it.
+    pub fn call(&self, name: &str, args: &[Number]) -> Result<Number, ExternError> {
+        let types: Vec<Type> = args.iter().map(type_of_number).collect();
+        let result = self.check_call(name, &types)?;
+        let function = &self.functions[name];
+        let host_error = |message| ExternError::Host {
+            name: name.to_string(),
+            message,
+        };
+        let value = (function.implementation)(args).map_err(host_error)?;
+        if type_of_number(&value) != result {
+            return Err(host_error(format!("returned {:?}", value)));
+        }
+        Ok(value)
+    }
+}
+
+// The type checker.  Every term except Extern follows the same rules as TypeOf.
+
+/// Finds the type of an expression, looking up host functions in a table.
+pub struct TypeOfExterns<'a> {
+    pub externs: &'a Externs,
+}
+
+type Checked = Result<Type, ExternError>;
+
+impl<'a, E> Algebra<IntegerLiteral, E, Checked> for TypeOfExterns<'a> {
+    fn apply<F>(&self, _term: &IntegerLiteral, _recurse: F) -> Checked
+    where
+        F: FnMut(&E) -> Checked,
+    {
+        Ok(Type::Int)
+    }
+}
+
+impl<'a, E> Algebra<FloatLiteral, E, Checked> for TypeOfExterns<'a> {
+    fn apply<F>(&self, _term: &FloatLiteral, _recurse: F) -> Checked
+    where
+        F: FnMut(&E) -> Checked,
+    {
+        Ok(Type::Float)
+    }
+}
+
+impl<'a, E> Algebra<IntToFloat<E>, E, Checked> for TypeOfExterns<'a> {
+    fn apply<F>(&self, term: &IntToFloat<E>, mut recurse: F) -> Checked
+    where
+        F: FnMut(&E) -> Checked,
+    {
+        match recurse(&term.expr)? {
+            Type::Int => Ok(Type::Float),
+            Type::Float => Err(TypeError::NotAnInt.into()),
+        }
+    }
+}
+
+fn same_type(term: &'static str, lhs: Type, rhs: Type) -> Checked {
+    if lhs != rhs {
+        return Err(TypeError::MixedOperands { term, lhs, rhs }.into());
+    }
+    Ok(lhs)
+}
+
+impl<'a, E> Algebra<Add<E>, E, Checked> for TypeOfExterns<'a> {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> Checked
+    where
+        F: FnMut(&E) -> Checked,
+    {
+        same_type("add", recurse(&term.lhs)?, recurse(&term.rhs)?)
+    }
+}
+
+impl<'a, E> Algebra<Multiply<E>, E, Checked> for TypeOfExterns<'a> {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> Checked
+    where
+        F: FnMut(&E) -> Checked,
+    {
+        same_type("multiply", recurse(&term.lhs)?, recurse(&term.rhs)?)
+    }
+}
+
+impl<'a, E> Algebra<Extern<E>, E, Checked> for TypeOfExterns<'a> {
+    fn apply<F>(&self, term: &Extern<E>, recurse: F) -> Checked
+    where
+        F: FnMut(&E) -> Checked,
+    {
+        let args = term
+            .args
+            .iter()
+            .map(recurse)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.externs.check_call(&term.name, &args)
+    }
+}
+
+pub fn type_of_with_externs<E>(expr: &E, externs: &Externs) -> Checked
+where
+    E: Expression,
+    for<'a> TypeOfExterns<'a>: Algebra<E::Signature, E, Checked>,
+{
+    mcata(&TypeOfExterns { externs }, expr)
+}
+
+// And the evaluator.  Like Strict in ch12c, it never promotes anything on its own.
+
+/// Evaluates an expression, dispatching calls to a table of host functions.
+pub struct ExternEvaluator<'a> {
+    pub externs: &'a Externs,
+}
+
+type Evaluated = Result<Number, ExternError>;
+
+fn strict(value: Evaluated) -> Result<Strict, ExternError> {
+    value.map(|number| Strict(Ok(number)))
+}
+
+fn unstrict(value: Strict) -> Evaluated {
+    Ok(value.0?)
+}
+
+impl<'a, E> Algebra<IntegerLiteral, E, Evaluated> for ExternEvaluator<'a> {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Evaluated
+    where
+        F: FnMut(&E) -> Evaluated,
+    {
+        Ok(Number::Int(term.value))
+    }
+}
+
+impl<'a, E> Algebra<FloatLiteral, E, Evaluated> for ExternEvaluator<'a> {
+    fn apply<F>(&self, term: &FloatLiteral, _recurse: F) -> Evaluated
+    where
+        F: FnMut(&E) -> Evaluated,
+    {
+        Ok(Number::Float(term.value))
+    }
+}
+
+impl<'a, E> Algebra<IntToFloat<E>, E, Evaluated> for ExternEvaluator<'a> {
+    fn apply<F>(&self, term: &IntToFloat<E>, mut recurse: F) -> Evaluated
+    where
+        F: FnMut(&E) -> Evaluated,
+    {
+        unstrict(strict(recurse(&term.expr))?.promote())
+    }
+}
+
+impl<'a, E> Algebra<Add<E>, E, Evaluated> for ExternEvaluator<'a> {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> Evaluated
+    where
+        F: FnMut(&E) -> Evaluated,
+    {
+        unstrict(strict(recurse(&term.lhs))? + strict(recurse(&term.rhs))?)
+    }
+}
+
+impl<'a, E> Algebra<Multiply<E>, E, Evaluated> for ExternEvaluator<'a> {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> Evaluated
+    where
+        F: FnMut(&E) -> Evaluated,
+    {
+        unstrict(strict(recurse(&term.lhs))? * strict(recurse(&term.rhs))?)
+    }
+}
+
+impl<'a, E> Algebra<Extern<E>, E, Evaluated> for ExternEvaluator<'a> {
+    fn apply<F>(&self, term: &Extern<E>, recurse: F) -> Evaluated
+    where
+        F: FnMut(&E) -> Evaluated,
+    {
+        let args = term
+            .args
+            .iter()
+            .map(recurse)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.externs.call(&term.name, &args)
+    }
+}
+
+pub fn evaluate_with_externs<E>(expr: &E, externs: &Externs) -> Evaluated
+where
+    E: Expression,
+    for<'a> ExternEvaluator<'a>: Algebra<E::Signature, E, Evaluated>,
+{
+    mcata(&ExternEvaluator { externs }, expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn externs() -> Externs {
+        let mut externs = Externs::new();
+        externs.register(
+            "sqrt",
+            FunctionType::new(&[Type::Float], Type::Float),
+            |args| match args {
+                [Number::Float(x)] if *x >= 0.0 => Ok(Number::Float(x.sqrt())),
+                _ => Err("negative argument".to_string()),
+            },
+        );
+        externs.register(
+            "max",
+            FunctionType::new(&[Type::Int, Type::Int], Type::Int),
+            |args| match args {
+                [Number::Int(a), Number::Int(b)] => Ok(Number::Int(*a.max(b))),
+                _ => unreachable!(),
+            },
+        );
+        externs
+    }
+
+    #[test]
+    fn can_call_host_functions() {
+        let externs = externs();
+        // sqrt(float(max(3, 4 + 5))) * 2.0
+        let expr: ExternExpr = multiply(
+            call_extern(
+                "sqrt",
+                vec![int_to_float(call_extern(
+                    "max",
+                    vec![
+                        integer_literal(3),
+                        add(integer_literal(4), integer_literal(5)),
+                    ],
+                ))],
+            ),
+            float_literal(2.0),
+        );
+        assert_eq!(expr.to_string(), "(sqrt(float(max(3, (4 + 5)))) * 2.0)");
+        assert_eq!(type_of_with_externs(&expr, &externs), Ok(Type::Float));
+        assert_eq!(
+            evaluate_with_externs(&expr, &externs),
+            Ok(Number::Float(6.0))
+        );
+    }
+
+    #[test]
+    fn type_checker_checks_calls() {
+        let externs = externs();
+        let expr: ExternExpr = call_extern("sqrt", vec![integer_literal(2)]);
+        let expected = ExternError::WrongArgument {
+            name: "sqrt".to_string(),
+            index: 0,
+            expected: Type::Float,
+            found: Type::Int,
+        };
+        assert_eq!(type_of_with_externs(&expr, &externs), Err(expected.clone()));
+        // The evaluator won't pass a bad argument along either.
+        assert_eq!(evaluate_with_externs(&expr, &externs), Err(expected));
+
+        let expr: ExternExpr = add(
+            integer_literal(1),
+            call_extern("max", vec![integer_literal(2)]),
+        );
+        assert_eq!(
+            type_of_with_externs(&expr, &externs)
+                .unwrap_err()
+                .to_string(),
+            "`max` takes 2 arguments, but was given 1"
+        );
+        let expr: ExternExpr = add(call_extern("cbrt", vec![]), float_literal(1.0));
+        assert_eq!(
+            type_of_with_externs(&expr, &externs),
+            Err(ExternError::UnknownFunction("cbrt".to_string()))
+        );
+        let expr: ExternExpr = add(
+            call_extern("max", vec![integer_literal(1), integer_literal(2)]),
+            float_literal(1.0),
+        );
+        assert!(matches!(
+            type_of_with_externs(&expr, &externs),
+            Err(ExternError::Type(TypeError::MixedOperands { .. }))
+        ));
+    }
+
+    #[test]
+    fn int_overflow_is_an_error() {
+        let externs = externs();
+        let expr: ExternExpr = add(
+            call_extern("max", vec![integer_literal(i64::MAX), integer_literal(0)]),
+            integer_literal(1),
+        );
+        assert_eq!(type_of_with_externs(&expr, &externs), Ok(Type::Int));
+        assert_eq!(
+            evaluate_with_externs(&expr, &externs),
+            Err(ExternError::Type(TypeError::Overflow { term: "add" }))
+        );
+    }
+
+    #[test]
+    fn host_failures_are_errors() {
+        let mut externs = externs();
+        let expr: ExternExpr = call_extern("sqrt", vec![float_literal(-1.0)]);
+        assert_eq!(
+            evaluate_with_externs(&expr, &externs)
+                .unwrap_err()
+                .to_string(),
+            "`sqrt` failed: negative argument"
+        );
+        // A host function that breaks its own signature is caught too.
+        externs.register(
+            "sqrt",
+            FunctionType::new(&[Type::Float], Type::Float),
+            |_| Ok(Number::Int(0)),
+        );
+        assert!(matches!(
+            evaluate_with_externs(&expr, &externs),
+            Err(ExternError::Host { .. })
+        ));
+    }
+}
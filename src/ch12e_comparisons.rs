@@ -0,0 +1,359 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! So far, every operator has produced the same kind of value that it consumes: adding two ints
+//! gives you an int, and and-ing two booleans gives you a boolean.  Comparisons are different:
+//! they take two ints, and produce a boolean.  That turns out to need nothing new from the open-sum
+//! machinery.  Each comparison is a term of its own, its evaluation rule asks for a value type that
+//! knows how to compare, and a language that has both arithmetic and ch12a's boolean connectives
+//! can use them together.
+//!
+//! The value type does have to hold both kinds of value, though, and has to do something sensible
+//! when it's asked to add two booleans.  Like `SafeIntOrPair` in ch07d, it turns those mistakes
+//! into an error value instead of panicking.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch12a_booleans::*;
+
+use std::fmt;
+
+/// True if both subexpressions have the same value.
+pub struct Equals<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+/// True if the left subexpression is smaller than the right.
+pub struct LessThan<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+/// True if the left subexpression is smaller than or equal to the right.
+pub struct LessOrEqual<E> {
+    pub lhs: E,
+    pub rhs: E,
+}
+
+pub fn equals<E: From<Equals<E>>>(lhs: E, rhs: E) -> E {
+    E::from(Equals { lhs, rhs })
+}
+
+pub fn less_than<E: From<LessThan<E>>>(lhs: E, rhs: E) -> E {
+    E::from(LessThan { lhs, rhs })
+}
+
+pub fn less_or_equal<E: From<LessOrEqual<E>>>(lhs: E, rhs: E) -> E {
+    E::from(LessOrEqual { lhs, rhs })
+}
+
+impl<E: fmt::Display> fmt::Display for Equals<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} = {})", self.lhs, self.rhs)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for LessThan<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} < {})", self.lhs, self.rhs)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for LessOrEqual<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} ≤ {})", self.lhs, self.rhs)
+    }
+}
+
+// Greater-than comparisons don't need terms of their own; they're less-than comparisons with the
+// operands swapped.
+
+pub fn greater_than<E: From<LessThan<E>>>(lhs: E, rhs: E) -> E {
+    less_than(rhs, lhs)
+}
+
+pub fn greater_or_equal<E: From<LessOrEqual<E>>>(lhs: E, rhs: E) -> E {
+    less_or_equal(rhs, lhs)
+}
+
+/// There's no std::ops trait for comparisons that produce something other than a Rust bool, so
+/// here's one, like ProjectPair in ch07c.
+pub trait Compare {
+    fn equals(self, other: Self) -> Self;
+    fn less_than(self, other: Self) -> Self;
+    fn less_or_equal(self, other: Self) -> Self;
+}
+
+impl<V, E> Eval<V, E> for Equals<E>
+where
+    V: Compare,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs).equals(eval_subexpr(&self.rhs))
+    }
+}
+
+impl<V, E> Eval<V, E> for LessThan<E>
+where
+    V: Compare,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs).less_than(eval_subexpr(&self.rhs))
+    }
+}
+
+impl<V, E> Eval<V, E> for LessOrEqual<E>
+where
+    V: Compare,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.lhs).less_or_equal(eval_subexpr(&self.rhs))
+    }
+}
+
+// A language with arithmetic, comparisons, and the closed boolean connectives.
+pub type CmpSig<E> = Sum![
+    Equals<E>,
+    LessThan<E>,
+    LessOrEqual<E>,
+    BoolLiteral,
+    And<E>,
+    Or<E>,
+    Not<E>,
+    MultSig<E>,
+];
+pub struct CmpExpr(pub Box<CmpSig<CmpExpr>>);
+
+impl Expression for CmpExpr {
+    type Signature = CmpSig<CmpExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    CmpExpr: Equals<CmpExpr>,
+    LessThan<CmpExpr>,
+    LessOrEqual<CmpExpr>,
+    BoolLiteral,
+    And<CmpExpr>,
+    Or<CmpExpr>,
+    Not<CmpExpr>,
+    Multiply<CmpExpr>,
+    IntegerLiteral,
+    Add<CmpExpr>,
+);
+
+impl fmt::Display for CmpExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A value that's either an int or a boolean.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scalar {
+    Int(i64),
+    Bool(bool),
+}
+
+/// An operator was given the wrong kind of value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    pub operator: &'static str,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "wrong kind of operands for `{}`", self.operator)
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// The result of evaluating a CmpExpr.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IntOrBool(pub Result<Scalar, Mismatch>);
+
+impl From<i64> for IntOrBool {
+    fn from(value: i64) -> IntOrBool {
+        IntOrBool(Ok(Scalar::Int(value)))
+    }
+}
+
+impl From<bool> for IntOrBool {
+    fn from(value: bool) -> IntOrBool {
+        IntOrBool(Ok(Scalar::Bool(value)))
+    }
+}
+
+impl IntOrBool {
+    fn ints(self, other: IntOrBool, operator: &'static str) -> Result<(i64, i64), Mismatch> {
+        match (self.0?, other.0?) {
+            (Scalar::Int(lhs), Scalar::Int(rhs)) => Ok((lhs, rhs)),
+            _ => Err(Mismatch { operator }),
+        }
+    }
+
+    fn bools(self, other: IntOrBool, operator: &'static str) -> Result<(bool, bool), Mismatch> {
+        match (self.0?, other.0?) {
+            (Scalar::Bool(lhs), Scalar::Bool(rhs)) => Ok((lhs, rhs)),
+            _ => Err(Mismatch { operator }),
+        }
+    }
+}
+
+impl std::ops::Add for IntOrBool {
+    type Output = IntOrBool;
+    fn add(self, other: IntOrBool) -> IntOrBool {
+        IntOrBool(self.ints(other, "add").map(|(l, r)| Scalar::Int(l + r)))
+    }
+}
+
+impl std::ops::Mul for IntOrBool {
+    type Output = IntOrBool;
+    fn mul(self, other: IntOrBool) -> IntOrBool {
+        IntOrBool(
+            self.ints(other, "multiply")
+                .map(|(l, r)| Scalar::Int(l * r)),
+        )
+    }
+}
+
+impl std::ops::BitAnd for IntOrBool {
+    type Output = IntOrBool;
+    fn bitand(self, other: IntOrBool) -> IntOrBool {
+        IntOrBool(self.bools(other, "and").map(|(l, r)| Scalar::Bool(l & r)))
+    }
+}
+
+impl std::ops::BitOr for IntOrBool {
+    type Output = IntOrBool;
+    fn bitor(self, other: IntOrBool) -> IntOrBool {
+        IntOrBool(self.bools(other, "or").map(|(l, r)| Scalar::Bool(l | r)))
+    }
+}
+
+impl std::ops::Not for IntOrBool {
+    type Output = IntOrBool;
+    fn not(self) -> IntOrBool {
+        IntOrBool(match self.0 {
+            Ok(Scalar::Bool(value)) => Ok(Scalar::Bool(!value)),
+            Ok(Scalar::Int(_)) => Err(Mismatch { operator: "not" }),
+            Err(error) => Err(error),
+        })
+    }
+}
+
+// Equality works on either kind of value, as long as both sides are the same kind; the orderings
+// only work on ints.
+
+impl Compare for IntOrBool {
+    fn equals(self, other: IntOrBool) -> IntOrBool {
+        IntOrBool(match (self.0, other.0) {
+            (Err(error), _) | (_, Err(error)) => Err(error),
+            (Ok(Scalar::Int(l)), Ok(Scalar::Int(r))) => Ok(Scalar::Bool(l == r)),
+            (Ok(Scalar::Bool(l)), Ok(Scalar::Bool(r))) => Ok(Scalar::Bool(l == r)),
+            _ => Err(Mismatch { operator: "equals" }),
+        })
+    }
+
+    fn less_than(self, other: IntOrBool) -> IntOrBool {
+        IntOrBool(
+            self.ints(other, "less_than")
+                .map(|(l, r)| Scalar::Bool(l < r)),
+        )
+    }
+
+    fn less_or_equal(self, other: IntOrBool) -> IntOrBool {
+        let result = self.ints(other, "less_or_equal");
+        IntOrBool(result.map(|(l, r)| Scalar::Bool(l <= r)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn comparisons_produce_booleans() {
+        // (2 * 3 < 7) ∧ ¬(1 + 1 = 3)
+        let expr: CmpExpr = and(
+            less_than(
+                multiply(integer_literal(2), integer_literal(3)),
+                integer_literal(7),
+            ),
+            not(equals(
+                add(integer_literal(1), integer_literal(1)),
+                integer_literal(3),
+            )),
+        );
+        assert_eq!(expr.evaluate::<IntOrBool>(), true.into());
+
+        let expr: CmpExpr = greater_or_equal(integer_literal(4), integer_literal(4));
+        assert_eq!(expr.evaluate::<IntOrBool>(), true.into());
+        let expr: CmpExpr = greater_than(integer_literal(4), integer_literal(4));
+        assert_eq!(expr.evaluate::<IntOrBool>(), false.into());
+        let expr: CmpExpr = equals(
+            bool_literal(true),
+            less_than(integer_literal(1), integer_literal(2)),
+        );
+        assert_eq!(expr.evaluate::<IntOrBool>(), true.into());
+    }
+
+    #[test]
+    fn mixing_kinds_of_values_is_an_error() {
+        // (1 < 2) + 3
+        let expr: CmpExpr = add(
+            less_than(integer_literal(1), integer_literal(2)),
+            integer_literal(3),
+        );
+        let error = Mismatch { operator: "add" };
+        assert_eq!(expr.evaluate::<IntOrBool>(), IntOrBool(Err(error)));
+        assert_eq!(error.to_string(), "wrong kind of operands for `add`");
+
+        let expr: CmpExpr = less_than(bool_literal(false), bool_literal(true));
+        assert_eq!(
+            expr.evaluate::<IntOrBool>(),
+            IntOrBool(Err(Mismatch {
+                operator: "less_than"
+            }))
+        );
+        let expr: CmpExpr = equals(integer_literal(0), bool_literal(false));
+        assert_eq!(
+            expr.evaluate::<IntOrBool>(),
+            IntOrBool(Err(Mismatch { operator: "equals" }))
+        );
+    }
+}
@@ -0,0 +1,394 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Every language so far has been first-order: values are numbers, pairs, or booleans, and the
+//! only things that take arguments are the operators themselves.  This chapter adds functions as
+//! values, with a `Lambda` term that builds a function and an `Apply` term that calls one.
+//!
+//! The terms themselves are no harder to add than any other.  The interesting part is evaluation,
+//! since a function body refers to a variable whose value isn't known until the function is
+//! called, and a function has to remember the values of any variables it closes over from where
+//! it was defined.  ch08b's evaluation rules don't pass an environment around, and we'd rather not
+//! change them.  So instead of evaluating an expression directly to a value, we evaluate it to its
+//! *meaning*: a function from an environment to a value.  The meaning of `x + 1` is "look up `x`,
+//! and add one to it".  Building meanings is an ordinary bottom-up evaluation, using the ordinary
+//! evaluation rules — the existing rules for literals, addition, and multiplication work unchanged,
+//! since meanings can be added and multiplied.  Each body is only visited once, when its meaning
+//! is built, no matter how many times the function is called.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch12a_booleans::*;
+
+use std::fmt;
+use std::rc::Rc;
+
+/// A function of one parameter.
+pub struct Lambda<E> {
+    pub param: String,
+    pub body: E,
+}
+
+/// Calls a function with an argument.
+pub struct Apply<E> {
+    pub function: E,
+    pub argument: E,
+}
+
+pub fn lambda<E: From<Lambda<E>>>(param: &str, body: E) -> E {
+    E::from(Lambda {
+        param: param.to_string(),
+        body,
+    })
+}
+
+pub fn apply<E: From<Apply<E>>>(function: E, argument: E) -> E {
+    E::from(Apply { function, argument })
+}
+
+impl<E: fmt::Display> fmt::Display for Lambda<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(λ{}. {})", self.param, self.body)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Apply<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} {})", self.function, self.argument)
+    }
+}
+
+// Variables are ch12a's; here they refer to a lambda's parameter instead of to an unknown boolean.
+pub type LambdaSig<E> = Sum![Lambda<E>, Apply<E>, Variable, MultSig<E>];
+pub struct LambdaExpr(pub Box<LambdaSig<LambdaExpr>>);
+
+impl Expression for LambdaExpr {
+    type Signature = LambdaSig<LambdaExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    LambdaExpr: Lambda<LambdaExpr>,
+    Apply<LambdaExpr>,
+    Variable,
+    Multiply<LambdaExpr>,
+    IntegerLiteral,
+    Add<LambdaExpr>,
+);
+
+impl fmt::Display for LambdaExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A value type that can build and call functions, like ProjectPair in ch07c.
+pub trait Closures {
+    fn lambda(param: &str, body: Self) -> Self;
+    fn apply(self, argument: Self) -> Self;
+}
+
+impl<V, E> Eval<V, E> for Lambda<E>
+where
+    V: Closures,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        V::lambda(&self.param, eval_subexpr(&self.body))
+    }
+}
+
+impl<V, E> Eval<V, E> for Apply<E>
+where
+    V: Closures,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        eval_subexpr(&self.function).apply(eval_subexpr(&self.argument))
+    }
+}
+
+/// The result of running an expression: an int, or a function along with the environment that it
+/// was defined in.
+#[derive(Clone)]
+pub enum Value {
+    Int(i64),
+    Closure(Closure),
+}
+
+#[derive(Clone)]
+pub struct Closure {
+    pub param: Rc<str>,
+    pub body: Meaning,
+    pub env: Env,
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            Value::Closure(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Closure(closure) => write!(f, "<closure λ{}>", closure.param),
+        }
+    }
+}
+
+/// The values of the variables in scope.  Calling a function adds a binding to the environment
+/// that the function captured, and it's shared with that environment, so capturing one is cheap.
+#[derive(Clone, Default)]
+pub struct Env(Option<Rc<Binding>>);
+
+struct Binding {
+    name: Rc<str>,
+    value: Value,
+    next: Env,
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env(None)
+    }
+
+    pub fn bind(&self, name: &str, value: Value) -> Env {
+        Env(Some(Rc::new(Binding {
+            name: Rc::from(name),
+            value,
+            next: self.clone(),
+        })))
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Value> {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if &*binding.name == name {
+                return Some(&binding.value);
+            }
+            env = &binding.next;
+        }
+        None
+    }
+}
+
+/// The ways that running an expression can go wrong.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RuntimeError {
+    /// A variable isn't bound by any enclosing lambda.
+    Unbound(String),
+    /// Tried to call something that isn't a function.
+    NotAFunction,
+    /// An arithmetic operator was given a function.
+    NotAnInt { operator: &'static str },
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::Unbound(name) => write!(f, "unbound variable `{}`", name),
+            RuntimeError::NotAFunction => f.write_str("called something that isn't a function"),
+            RuntimeError::NotAnInt { operator } => {
+                write!(f, "`{}` needs ints, not functions", operator)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// What an expression means: a function that runs it in a particular environment.
+#[derive(Clone)]
+pub struct Meaning(Rc<RunFn>);
+
+type RunFn = dyn Fn(&Env) -> Result<Value, RuntimeError>;
+
+impl Meaning {
+    fn new<F>(run: F) -> Meaning
+    where
+        F: Fn(&Env) -> Result<Value, RuntimeError> + 'static,
+    {
+        Meaning(Rc::new(run))
+    }
+
+    pub fn run(&self, env: &Env) -> Result<Value, RuntimeError> {
+        (self.0)(env)
+    }
+
+    fn ints(self, other: Meaning, operator: &'static str, op: fn(i64, i64) -> i64) -> Meaning {
+        Meaning::new(move |env| match (self.run(env)?, other.run(env)?) {
+            (Value::Int(lhs), Value::Int(rhs)) => Ok(Value::Int(op(lhs, rhs))),
+            _ => Err(RuntimeError::NotAnInt { operator }),
+        })
+    }
+}
+
+impl From<i64> for Meaning {
+    fn from(value: i64) -> Meaning {
+        Meaning::new(move |_env| Ok(Value::Int(value)))
+    }
+}
+
+impl std::ops::Add for Meaning {
+    type Output = Meaning;
+    fn add(self, other: Meaning) -> Meaning {
+        self.ints(other, "add", |lhs, rhs| lhs + rhs)
+    }
+}
+
+impl std::ops::Mul for Meaning {
+    type Output = Meaning;
+    fn mul(self, other: Meaning) -> Meaning {
+        self.ints(other, "multiply", |lhs, rhs| lhs * rhs)
+    }
+}
+
+impl FromVariable for Meaning {
+    fn from_variable(name: &str) -> Meaning {
+        let name = name.to_string();
+        Meaning::new(move |env| {
+            env.lookup(&name)
+                .cloned()
+                .ok_or_else(|| RuntimeError::Unbound(name.clone()))
+        })
+    }
+}
+
+impl Closures for Meaning {
+    fn lambda(param: &str, body: Meaning) -> Meaning {
+        let param: Rc<str> = Rc::from(param);
+        Meaning::new(move |env| {
+            Ok(Value::Closure(Closure {
+                param: param.clone(),
+                body: body.clone(),
+                env: env.clone(),
+            }))
+        })
+    }
+
+    fn apply(self, argument: Meaning) -> Meaning {
+        Meaning::new(move |env| match self.run(env)? {
+            Value::Closure(closure) => {
+                let argument = argument.run(env)?;
+                closure
+                    .body
+                    .run(&closure.env.bind(&closure.param, argument))
+            }
+            Value::Int(_) => Err(RuntimeError::NotAFunction),
+        })
+    }
+}
+
+/// Runs an expression that has no free variables.
+pub fn run<E>(expr: &E) -> Result<Value, RuntimeError>
+where
+    E: Eval<Meaning, E>,
+{
+    expr.evaluate::<Meaning>().run(&Env::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_apply_functions() {
+        // (λx. x * x) (3 + 4)
+        let expr: LambdaExpr = apply(
+            lambda("x", multiply(variable("x"), variable("x"))),
+            add(integer_literal(3), integer_literal(4)),
+        );
+        assert_eq!(expr.to_string(), "((λx. (x * x)) (3 + 4))");
+        assert_eq!(run(&expr).unwrap().as_int(), Some(49));
+    }
+
+    #[test]
+    fn closures_capture_their_environment() {
+        // ((λx. λy. x + y) 10) 5, where the inner function outlives the call that bound x
+        let adder: LambdaExpr = lambda("x", lambda("y", add(variable("x"), variable("y"))));
+        let expr: LambdaExpr = apply(apply(adder, integer_literal(10)), integer_literal(5));
+        assert_eq!(run(&expr).unwrap().as_int(), Some(15));
+
+        let partial: LambdaExpr = apply(
+            lambda("x", lambda("y", add(variable("x"), variable("y")))),
+            integer_literal(10),
+        );
+        assert_eq!(run(&partial).unwrap().to_string(), "<closure λy>");
+    }
+
+    #[test]
+    fn can_pass_functions_as_arguments() {
+        // (λf. f (f 1)) (λn. n * 3), and an inner x that shadows an outer one
+        let twice: LambdaExpr = lambda(
+            "f",
+            apply(variable("f"), apply(variable("f"), integer_literal(1))),
+        );
+        let triple: LambdaExpr = lambda("n", multiply(variable("n"), integer_literal(3)));
+        assert_eq!(run(&apply(twice, triple)).unwrap().as_int(), Some(9));
+
+        let shadowed: LambdaExpr = apply(
+            lambda("x", apply(lambda("x", variable("x")), integer_literal(2))),
+            integer_literal(1),
+        );
+        assert_eq!(run(&shadowed).unwrap().as_int(), Some(2));
+    }
+
+    #[test]
+    fn reports_runtime_errors() {
+        let unbound: LambdaExpr = add(variable("y"), integer_literal(1));
+        assert_eq!(
+            run(&unbound).unwrap_err(),
+            RuntimeError::Unbound("y".to_string())
+        );
+
+        let not_a_function: LambdaExpr = apply(integer_literal(1), integer_literal(2));
+        assert_eq!(
+            run(&not_a_function).unwrap_err(),
+            RuntimeError::NotAFunction
+        );
+
+        let not_an_int: LambdaExpr = add(lambda("x", variable("x")), integer_literal(1));
+        assert_eq!(
+            run(&not_an_int).unwrap_err(),
+            RuntimeError::NotAnInt { operator: "add" }
+        );
+    }
+}
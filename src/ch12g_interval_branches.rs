@@ -0,0 +1,622 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Conditionals, and a first taste of an analysis feeding a transformation.  An `IfThenElse` term
+//! evaluates its condition, and then only the branch that the condition selects.  Often, though, we
+//! can tell which branch will be taken without running anything.  If we know that an input is
+//! between 0 and 100, then `x < 1000` is always true, and the else branch is dead code.
+//!
+//! To find out, we evaluate the expression *abstractly*: instead of a value, each subexpression
+//! produces `Bounds` that describe every value it could have, like ch10d's `Interval`s.  That's a
+//! value type like any other, so the ch08b evaluation rules for arithmetic, comparisons, and the
+//! boolean connectives already know how to compute it.  Only the inputs need something new, since
+//! their bounds come from outside of the expression.  Then a pass uses the analysis to replace
+//! each conditional whose condition has a known value with the branch that it selects.
+//!
+//! The inputs are the program module's `Global`s.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch09a_mendler::*;
+use crate::ch09d_owned_fold::*;
+use crate::ch10d_value_kinds::Interval;
+use crate::ch12a_booleans::*;
+use crate::ch12e_comparisons::*;
+use crate::passes::*;
+use crate::program::*;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Evaluates `then_branch` if the condition is true, and `else_branch` if it's false.
+pub struct IfThenElse<E> {
+    pub condition: E,
+    pub then_branch: E,
+    pub else_branch: E,
+}
+
+pub fn if_then_else<E: From<IfThenElse<E>>>(condition: E, then_branch: E, else_branch: E) -> E {
+    E::from(IfThenElse {
+        condition,
+        then_branch,
+        else_branch,
+    })
+}
+
+impl<E: fmt::Display> fmt::Display for IfThenElse<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "(if {} then {} else {})",
+            self.condition, self.then_branch, self.else_branch
+        )
+    }
+}
+
+/// A value type that can decide which branch of a conditional to take.
+pub trait Choose: Sized {
+    /// Returns which branch this condition selects, or None if it might select either one.  If it
+    /// can't select a branch at all (because it isn't a boolean), returns the value that the whole
+    /// conditional should have instead.
+    fn choose(&self) -> Result<Option<bool>, Self>;
+    /// Combines the values of two branches, when we don't know which one will be taken.
+    fn join(self, other: Self) -> Self;
+}
+
+impl<V, E> Eval<V, E> for IfThenElse<E>
+where
+    V: Choose,
+{
+    fn eval<F>(&self, mut eval_subexpr: F) -> V
+    where
+        F: FnMut(&E) -> V,
+    {
+        match eval_subexpr(&self.condition).choose() {
+            Ok(Some(true)) => eval_subexpr(&self.then_branch),
+            Ok(Some(false)) => eval_subexpr(&self.else_branch),
+            Ok(None) => eval_subexpr(&self.then_branch).join(eval_subexpr(&self.else_branch)),
+            Err(value) => value,
+        }
+    }
+}
+
+// A concrete boolean always selects exactly one branch.
+
+impl Choose for IntOrBool {
+    fn choose(&self) -> Result<Option<bool>, IntOrBool> {
+        match self.0 {
+            Ok(Scalar::Bool(value)) => Ok(Some(value)),
+            Ok(Scalar::Int(_)) => Err(IntOrBool(Err(Mismatch {
+                operator: "if_then_else",
+            }))),
+            Err(error) => Err(IntOrBool(Err(error))),
+        }
+    }
+
+    fn join(self, _other: IntOrBool) -> IntOrBool {
+        unreachable!("a concrete condition always selects a branch")
+    }
+}
+
+/// Everything that we know about the value of a subexpression.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bounds {
+    /// An int, somewhere in this interval.
+    Int(Interval),
+    /// A boolean with this value.
+    Bool(bool),
+    /// A boolean, but it could be either one.
+    AnyBool,
+    /// We don't know anything.  The value might have overflowed, come from an input that we don't
+    /// have bounds for, or be a type error.
+    Unknown,
+}
+
+impl Bounds {
+    fn is_bool(self) -> bool {
+        matches!(self, Bounds::Bool(_) | Bounds::AnyBool)
+    }
+
+    fn ints(self, other: Bounds) -> Option<(Interval, Interval)> {
+        match (self, other) {
+            (Bounds::Int(lhs), Bounds::Int(rhs)) => Some((lhs, rhs)),
+            _ => None,
+        }
+    }
+
+    // The result of a comparison that's true if `always` holds, false if `never` holds, and could
+    // be either otherwise.
+    fn compare(
+        self,
+        other: Bounds,
+        always: fn(Interval, Interval) -> bool,
+        never: fn(Interval, Interval) -> bool,
+    ) -> Bounds {
+        match self.ints(other) {
+            Some((lhs, rhs)) if always(lhs, rhs) => Bounds::Bool(true),
+            Some((lhs, rhs)) if never(lhs, rhs) => Bounds::Bool(false),
+            Some(_) => Bounds::AnyBool,
+            None => Bounds::Unknown,
+        }
+    }
+}
+
+impl From<i64> for Bounds {
+    fn from(value: i64) -> Bounds {
+        Bounds::Int(Interval::from(value))
+    }
+}
+
+impl From<bool> for Bounds {
+    fn from(value: bool) -> Bounds {
+        Bounds::Bool(value)
+    }
+}
+
+// ch10d's interval arithmetic widens to an unbounded interval on overflow; here, we can say more
+// directly that we don't know anything about the result.
+
+impl std::ops::Add for Bounds {
+    type Output = Bounds;
+    fn add(self, other: Bounds) -> Bounds {
+        self.ints(other)
+            .and_then(|(lhs, rhs)| {
+                Some(Interval {
+                    lo: lhs.lo.checked_add(rhs.lo)?,
+                    hi: lhs.hi.checked_add(rhs.hi)?,
+                })
+            })
+            .map_or(Bounds::Unknown, Bounds::Int)
+    }
+}
+
+impl std::ops::Mul for Bounds {
+    type Output = Bounds;
+    fn mul(self, other: Bounds) -> Bounds {
+        self.ints(other)
+            .and_then(|(lhs, rhs)| {
+                let products = [
+                    lhs.lo.checked_mul(rhs.lo)?,
+                    lhs.lo.checked_mul(rhs.hi)?,
+                    lhs.hi.checked_mul(rhs.lo)?,
+                    lhs.hi.checked_mul(rhs.hi)?,
+                ];
+                Some(Interval {
+                    lo: *products.iter().min().unwrap(),
+                    hi: *products.iter().max().unwrap(),
+                })
+            })
+            .map_or(Bounds::Unknown, Bounds::Int)
+    }
+}
+
+// A concrete `&` evaluates both of its operands, and fails if either isn't a boolean, so a false
+// operand only decides the result if the other one is known to be a boolean, too.
+
+impl std::ops::BitAnd for Bounds {
+    type Output = Bounds;
+    fn bitand(self, other: Bounds) -> Bounds {
+        match (self, other) {
+            (Bounds::Bool(lhs), Bounds::Bool(rhs)) => Bounds::Bool(lhs & rhs),
+            (Bounds::Bool(false), rhs) if rhs.is_bool() => Bounds::Bool(false),
+            (lhs, Bounds::Bool(false)) if lhs.is_bool() => Bounds::Bool(false),
+            (lhs, rhs) if lhs.is_bool() && rhs.is_bool() => Bounds::AnyBool,
+            _ => Bounds::Unknown,
+        }
+    }
+}
+
+impl std::ops::BitOr for Bounds {
+    type Output = Bounds;
+    fn bitor(self, other: Bounds) -> Bounds {
+        match (self, other) {
+            (Bounds::Bool(lhs), Bounds::Bool(rhs)) => Bounds::Bool(lhs | rhs),
+            (Bounds::Bool(true), rhs) if rhs.is_bool() => Bounds::Bool(true),
+            (lhs, Bounds::Bool(true)) if lhs.is_bool() => Bounds::Bool(true),
+            (lhs, rhs) if lhs.is_bool() && rhs.is_bool() => Bounds::AnyBool,
+            _ => Bounds::Unknown,
+        }
+    }
+}
+
+impl std::ops::Not for Bounds {
+    type Output = Bounds;
+    fn not(self) -> Bounds {
+        match self {
+            Bounds::Bool(value) => Bounds::Bool(!value),
+            Bounds::AnyBool => Bounds::AnyBool,
+            _ => Bounds::Unknown,
+        }
+    }
+}
+
+impl Compare for Bounds {
+    fn equals(self, other: Bounds) -> Bounds {
+        match (self, other) {
+            (Bounds::Bool(lhs), Bounds::Bool(rhs)) => Bounds::Bool(lhs == rhs),
+            (lhs, rhs) if lhs.is_bool() && rhs.is_bool() => Bounds::AnyBool,
+            _ => self.compare(
+                other,
+                |lhs, rhs| lhs.lo == lhs.hi && lhs == rhs,
+                |lhs, rhs| lhs.hi < rhs.lo || rhs.hi < lhs.lo,
+            ),
+        }
+    }
+
+    fn less_than(self, other: Bounds) -> Bounds {
+        self.compare(
+            other,
+            |lhs, rhs| lhs.hi < rhs.lo,
+            |lhs, rhs| lhs.lo >= rhs.hi,
+        )
+    }
+
+    fn less_or_equal(self, other: Bounds) -> Bounds {
+        self.compare(
+            other,
+            |lhs, rhs| lhs.hi <= rhs.lo,
+            |lhs, rhs| lhs.lo > rhs.hi,
+        )
+    }
+}
+
+impl Choose for Bounds {
+    fn choose(&self) -> Result<Option<bool>, Bounds> {
+        match self {
+            Bounds::Bool(value) => Ok(Some(*value)),
+            Bounds::AnyBool => Ok(None),
+            _ => Err(Bounds::Unknown),
+        }
+    }
+
+    fn join(self, other: Bounds) -> Bounds {
+        match (self, other) {
+            (Bounds::Int(lhs), Bounds::Int(rhs)) => Bounds::Int(Interval {
+                lo: lhs.lo.min(rhs.lo),
+                hi: lhs.hi.max(rhs.hi),
+            }),
+            (Bounds::Bool(lhs), Bounds::Bool(rhs)) if lhs == rhs => Bounds::Bool(lhs),
+            (lhs, rhs) if lhs.is_bool() && rhs.is_bool() => Bounds::AnyBool,
+            _ => Bounds::Unknown,
+        }
+    }
+}
+
+// The analysis is a Mendler algebra, so that it can carry the bounds of the inputs with it.  For
+// every term except the inputs, it just uses the term's evaluation rule.
+
+/// An algebra that computes the bounds of an expression, given the bounds of its inputs.
+pub struct IntervalAnalysis<'a> {
+    pub inputs: &'a HashMap<String, Interval>,
+}
+
+impl<'a, E> Algebra<Global, E, Bounds> for IntervalAnalysis<'a> {
+    fn apply<F>(&self, term: &Global, _recurse: F) -> Bounds
+    where
+        F: FnMut(&E) -> Bounds,
+    {
+        self.inputs
+            .get(&term.name)
+            .map_or(Bounds::Unknown, |interval| Bounds::Int(*interval))
+    }
+}
+
+macro_rules! analyze_with_eval {
+    ($($term:ident $(<$E:ident>)?),+ $(,)?) => {
+        $(
+            impl<'a, E> Algebra<$term $(<$E>)?, E, Bounds> for IntervalAnalysis<'a> {
+                fn apply<F>(&self, term: &$term $(<$E>)?, recurse: F) -> Bounds
+                where
+                    F: FnMut(&E) -> Bounds,
+                {
+                    term.eval(recurse)
+                }
+            }
+        )+
+    };
+}
+
+analyze_with_eval!(
+    IntegerLiteral,
+    BoolLiteral,
+    Add<E>,
+    Multiply<E>,
+    And<E>,
+    Or<E>,
+    Not<E>,
+    Equals<E>,
+    LessThan<E>,
+    LessOrEqual<E>,
+    IfThenElse<E>,
+);
+
+/// Computes the bounds of an expression, given the bounds of its inputs.
+pub fn analyze<E>(expr: &E, inputs: &HashMap<String, Interval>) -> Bounds
+where
+    E: Expression,
+    for<'a> IntervalAnalysis<'a>: Algebra<E::Signature, E, Bounds>,
+{
+    mcata(&IntervalAnalysis { inputs }, expr)
+}
+
+// The pass rebuilds the expression with ch09d's owned fold.  When it reaches a conditional, it
+// analyzes the condition before recursing into it, and if that tells us which branch will be
+// taken, that branch is all we keep.  (Dropping the condition is safe, because evaluating it can't
+// have any side effects.)  Every other term is rebuilt as it was.
+
+/// An algebra that removes the branches of conditionals that can never be taken.
+pub struct DeadBranches<'a> {
+    analysis: IntervalAnalysis<'a>,
+    removed: Cell<usize>,
+}
+
+impl DeadBranches<'_> {
+    /// How many branches have been removed so far.
+    pub fn removed(&self) -> usize {
+        self.removed.get()
+    }
+}
+
+impl<'a, E, T> OwnedAlgebra<IfThenElse<E>, E, T> for DeadBranches<'a>
+where
+    E: Expression,
+    IntervalAnalysis<'a>: Algebra<E::Signature, E, Bounds>,
+    T: From<IfThenElse<T>>,
+{
+    fn apply_owned<F>(&self, term: IfThenElse<E>, mut recurse: F) -> T
+    where
+        F: FnMut(E) -> T,
+    {
+        match mcata(&self.analysis, &term.condition).choose() {
+            Ok(Some(taken)) => {
+                self.removed.set(self.removed.get() + 1);
+                recurse(if taken {
+                    term.then_branch
+                } else {
+                    term.else_branch
+                })
+            }
+            _ => T::from(IfThenElse {
+                condition: recurse(term.condition),
+                then_branch: recurse(term.then_branch),
+                else_branch: recurse(term.else_branch),
+            }),
+        }
+    }
+}
+
+macro_rules! rebuild_leaves {
+    ($($term:ident),+ $(,)?) => {
+        $(
+            impl<'a, E, T: From<$term>> OwnedAlgebra<$term, E, T> for DeadBranches<'a> {
+                fn apply_owned<F>(&self, term: $term, _recurse: F) -> T
+                where
+                    F: FnMut(E) -> T,
+                {
+                    T::from(term)
+                }
+            }
+        )+
+    };
+}
+
+rebuild_leaves!(IntegerLiteral, BoolLiteral, Global);
+
+macro_rules! rebuild_terms {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<'a, E, T: From<$term<T>>> OwnedAlgebra<$term<E>, E, T> for DeadBranches<'a> {
+                fn apply_owned<F>(&self, term: $term<E>, mut recurse: F) -> T
+                where
+                    F: FnMut(E) -> T,
+                {
+                    T::from($term { $($field: recurse(term.$field)),+ })
+                }
+            }
+        )+
+    };
+}
+
+rebuild_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    And { lhs, rhs },
+    Or { lhs, rhs },
+    Not { expr },
+    Equals { lhs, rhs },
+    LessThan { lhs, rhs },
+    LessOrEqual { lhs, rhs },
+);
+
+/// Removes every branch that the interval analysis proves can never be taken.
+pub fn eliminate_dead_branches<E>(expr: E, inputs: &HashMap<String, Interval>) -> Outcome<E>
+where
+    E: Expression,
+    for<'a> DeadBranches<'a>: OwnedAlgebra<E::Signature, E, E>,
+{
+    let algebra = DeadBranches {
+        analysis: IntervalAnalysis { inputs },
+        removed: Cell::new(0),
+    };
+    let result = into_fold(&algebra, expr);
+    if algebra.removed() > 0 {
+        Outcome::Changed(result)
+    } else {
+        Outcome::Unchanged(result)
+    }
+}
+
+// A language with conditionals, inputs, and everything from ch12e.
+pub type BranchSig<E> = Sum![IfThenElse<E>, Global, CmpSig<E>];
+pub struct BranchExpr(pub Box<BranchSig<BranchExpr>>);
+
+impl Expression for BranchExpr {
+    type Signature = BranchSig<BranchExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    BranchExpr: IfThenElse<BranchExpr>,
+    Global,
+    Equals<BranchExpr>,
+    LessThan<BranchExpr>,
+    LessOrEqual<BranchExpr>,
+    BoolLiteral,
+    And<BranchExpr>,
+    Or<BranchExpr>,
+    Not<BranchExpr>,
+    Multiply<BranchExpr>,
+    IntegerLiteral,
+    Add<BranchExpr>,
+);
+
+impl fmt::Display for BranchExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn x() -> BranchExpr {
+        global("x")
+    }
+
+    fn inputs(lo: i64, hi: i64) -> HashMap<String, Interval> {
+        std::iter::once(("x".to_string(), Interval { lo, hi })).collect()
+    }
+
+    // if x * 2 < 1000 then x + 1 else 0
+    fn clamp() -> BranchExpr {
+        if_then_else(
+            less_than(multiply(x(), integer_literal(2)), integer_literal(1000)),
+            add(x(), integer_literal(1)),
+            integer_literal(0),
+        )
+    }
+
+    // Conditionals without inputs, which we can evaluate.
+    type CondSig<E> = Sum![IfThenElse<E>, CmpSig<E>];
+    struct CondExpr(Box<CondSig<CondExpr>>);
+
+    impl Expression for CondExpr {
+        type Signature = CondSig<CondExpr>;
+        fn wrap(sig: Self::Signature) -> Self {
+            Self(Box::new(sig))
+        }
+        fn unwrap(&self) -> &Self::Signature {
+            &self.0
+        }
+        fn into_signature(self) -> Self::Signature {
+            *self.0
+        }
+    }
+
+    from_terms!(
+        CondExpr: IfThenElse<CondExpr>,
+        LessThan<CondExpr>,
+        BoolLiteral,
+        IntegerLiteral,
+        Add<CondExpr>,
+    );
+
+    #[test]
+    fn conditionals_only_evaluate_one_branch() {
+        // The untaken branch is a type error, which we'd notice if we evaluated it.
+        let expr: CondExpr = if_then_else(
+            less_than(integer_literal(1), integer_literal(2)),
+            integer_literal(7),
+            add(bool_literal(true), integer_literal(1)),
+        );
+        assert_eq!(expr.evaluate::<IntOrBool>(), 7.into());
+        let expr: CondExpr =
+            if_then_else(integer_literal(1), integer_literal(7), integer_literal(8));
+        let error = Mismatch {
+            operator: "if_then_else",
+        };
+        assert_eq!(expr.evaluate::<IntOrBool>(), IntOrBool(Err(error)));
+    }
+
+    #[test]
+    fn can_analyze_bounds() {
+        assert_eq!(
+            analyze(&clamp(), &inputs(0, 100)),
+            Bounds::Int(Interval { lo: 1, hi: 101 })
+        );
+        assert_eq!(
+            analyze(&clamp(), &inputs(0, 1000)),
+            Bounds::Int(Interval { lo: 0, hi: 1001 })
+        );
+        assert_eq!(analyze(&clamp(), &HashMap::new()), Bounds::Unknown);
+        let overflow: BranchExpr = multiply(x(), x());
+        assert_eq!(analyze(&overflow, &inputs(0, i64::MAX)), Bounds::Unknown);
+    }
+
+    #[test]
+    fn removes_branches_that_are_never_taken() {
+        let result = eliminate_dead_branches(clamp(), &inputs(0, 100));
+        assert!(result.is_changed());
+        assert_eq!(result.into_inner().to_string(), "(x + 1)");
+        let result = eliminate_dead_branches(clamp(), &inputs(500, 600));
+        assert_eq!(result.into_inner().to_string(), "0");
+    }
+
+    #[test]
+    fn keeps_branches_that_might_be_taken() {
+        let result = eliminate_dead_branches(clamp(), &inputs(0, 1000));
+        assert!(!result.is_changed());
+        assert_eq!(
+            result.into_inner().to_string(),
+            "(if ((x * 2) < 1000) then (x + 1) else 0)"
+        );
+        // Without bounds for x, we can't remove anything.
+        let result = eliminate_dead_branches(clamp(), &HashMap::new());
+        assert!(!result.is_changed());
+    }
+
+    #[test]
+    fn removes_nested_branches() {
+        // if x ≤ 10 then (if x = 20 then 1 else 2) else (if ¬(x < 0) ∧ true then 3 else 4)
+        let expr: BranchExpr = if_then_else(
+            less_or_equal(x(), integer_literal(10)),
+            if_then_else(
+                equals(x(), integer_literal(20)),
+                integer_literal(1),
+                integer_literal(2),
+            ),
+            if_then_else(
+                and(not(less_than(x(), integer_literal(0))), bool_literal(true)),
+                integer_literal(3),
+                integer_literal(4),
+            ),
+        );
+        let result = eliminate_dead_branches(expr, &inputs(0, 5)).into_inner();
+        assert_eq!(result.to_string(), "2");
+    }
+}
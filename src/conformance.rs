@@ -0,0 +1,495 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! By now we have quite a few different ways of encoding the same little arithmetic language.
+//! This module lets us check that they all agree with each other.  Each encoding implements the
+//! `Language` trait, and then we run the same corpus of expressions through every one of them.
+//! When you add a new encoding, add a `Language` impl for it here, and a one-line test at the
+//! bottom of the file.
+
+use crate::ch03_evaluation::*;
+use crate::ch04_smart_constructors::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch09a_mendler::*;
+use crate::ch09b_church_encoding::*;
+use crate::ch09c_tagless_final::*;
+use crate::ch09h_bounded_depth::*;
+use crate::ch09j_small_expressions::*;
+use crate::ch09k_variadic_terms::*;
+use crate::ch10a_dynamic_terms::*;
+// Both ch08b and ch09c define something called `Evaluate`; we want the tagless-final interpreter.
+use crate::ch09c_tagless_final::Evaluate;
+
+/// An implementation of our arithmetic language: some way of constructing literals, additions, and
+/// multiplications, and then evaluating and printing the result.
+pub trait Language {
+    type Expr;
+    fn integer_literal(value: i64) -> Self::Expr;
+    fn add(lhs: Self::Expr, rhs: Self::Expr) -> Self::Expr;
+    fn multiply(lhs: Self::Expr, rhs: Self::Expr) -> Self::Expr;
+    fn evaluate(expr: &Self::Expr) -> i64;
+    fn print(expr: &Self::Expr) -> String;
+}
+
+/// One entry in the conformance corpus.
+pub struct Case<E> {
+    pub name: &'static str,
+    pub expr: E,
+    pub value: i64,
+    pub printed: &'static str,
+}
+
+/// The corpus of expressions that every language has to agree on.
+pub fn corpus<L: Language>() -> Vec<Case<L::Expr>> {
+    let lit = L::integer_literal;
+    vec![
+        Case {
+            name: "literal",
+            expr: lit(1),
+            value: 1,
+            printed: "1",
+        },
+        Case {
+            name: "negative literal",
+            expr: lit(-7),
+            value: -7,
+            printed: "-7",
+        },
+        Case {
+            name: "add",
+            expr: L::add(lit(118), lit(1219)),
+            value: 1337,
+            printed: "(118 + 1219)",
+        },
+        Case {
+            name: "nested add",
+            expr: L::add(lit(30000), L::add(lit(1330), lit(7))),
+            value: 31337,
+            printed: "(30000 + (1330 + 7))",
+        },
+        Case {
+            name: "multiply",
+            expr: L::multiply(lit(6), lit(7)),
+            value: 42,
+            printed: "(6 * 7)",
+        },
+        Case {
+            name: "multiply inside add",
+            expr: L::add(L::multiply(lit(80), lit(5)), lit(4)),
+            value: 404,
+            printed: "((80 * 5) + 4)",
+        },
+        Case {
+            name: "add inside multiply",
+            expr: L::multiply(lit(118), L::add(lit(5), lit(4))),
+            value: 1062,
+            printed: "(118 * (5 + 4))",
+        },
+        Case {
+            name: "multiply by zero",
+            expr: L::multiply(L::add(lit(1), lit(2)), lit(0)),
+            value: 0,
+            printed: "((1 + 2) * 0)",
+        },
+    ]
+}
+
+/// Checks that a language evaluates and prints every expression in the corpus correctly.  Panics
+/// with the name of the first case that doesn't.
+pub fn check_conformance<L: Language>() {
+    for case in corpus::<L>() {
+        assert_eq!(
+            L::evaluate(&case.expr),
+            case.value,
+            "evaluating {}",
+            case.name
+        );
+        assert_eq!(L::print(&case.expr), case.printed, "printing {}", case.name);
+    }
+}
+
+/// Builds a balanced expression with 2^depth leaves, for use as a benchmark workload.  The
+/// multiplications are all at the bottom of the tree, so that evaluating it doesn't overflow.
+pub fn workload<L: Language>(depth: u32) -> L::Expr {
+    fn build<L: Language>(depth: u32, next: &mut i64) -> L::Expr {
+        if depth == 0 {
+            *next += 1;
+            return L::integer_literal(*next % 10);
+        }
+        let lhs = build::<L>(depth - 1, next);
+        let rhs = build::<L>(depth - 1, next);
+        if depth == 1 {
+            L::multiply(lhs, rhs)
+        } else {
+            L::add(lhs, rhs)
+        }
+    }
+    build::<L>(depth, &mut 0)
+}
+
+// ------------------------------------------------------------------------------------------------
+// The encodings
+
+/// The open sum from ch02–ch05, evaluated with `EvaluateInt` from ch03.
+pub struct EvaluateIntLanguage;
+
+impl Language for EvaluateIntLanguage {
+    type Expr = MultExpr;
+    fn integer_literal(value: i64) -> MultExpr {
+        integer_literal(value)
+    }
+    fn add(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+        add(lhs, rhs)
+    }
+    fn multiply(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+        multiply(lhs, rhs)
+    }
+    fn evaluate(expr: &MultExpr) -> i64 {
+        EvaluateInt::evaluate(expr)
+    }
+    fn print(expr: &MultExpr) -> String {
+        expr.to_string()
+    }
+}
+
+/// The open sum, evaluated with the open-recursion `Eval` trait from ch08b.
+pub struct OpenRecursionLanguage;
+
+fn eval_open_recursion(expr: &MultExpr) -> i64 {
+    expr.eval(eval_open_recursion)
+}
+
+impl Language for OpenRecursionLanguage {
+    type Expr = MultExpr;
+    fn integer_literal(value: i64) -> MultExpr {
+        integer_literal(value)
+    }
+    fn add(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+        add(lhs, rhs)
+    }
+    fn multiply(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+        multiply(lhs, rhs)
+    }
+    fn evaluate(expr: &MultExpr) -> i64 {
+        eval_open_recursion(expr)
+    }
+    fn print(expr: &MultExpr) -> String {
+        expr.to_string()
+    }
+}
+
+/// The open sum, evaluated with the Mendler-style fold from ch09a.
+pub struct MendlerLanguage;
+
+impl Language for MendlerLanguage {
+    type Expr = MultExpr;
+    fn integer_literal(value: i64) -> MultExpr {
+        integer_literal(value)
+    }
+    fn add(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+        add(lhs, rhs)
+    }
+    fn multiply(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+        multiply(lhs, rhs)
+    }
+    fn evaluate(expr: &MultExpr) -> i64 {
+        mcata(&Evaluator, expr)
+    }
+    fn print(expr: &MultExpr) -> String {
+        expr.to_string()
+    }
+}
+
+/// The runtime-constructible Church encoding from ch09b.
+pub struct ChurchLanguage;
+
+impl Language for ChurchLanguage {
+    type Expr = DynChurch;
+    fn integer_literal(value: i64) -> DynChurch {
+        dyn_church_literal(value)
+    }
+    fn add(lhs: DynChurch, rhs: DynChurch) -> DynChurch {
+        dyn_church_add(lhs, rhs)
+    }
+    fn multiply(lhs: DynChurch, rhs: DynChurch) -> DynChurch {
+        dyn_church_multiply(lhs, rhs)
+    }
+    fn evaluate(expr: &DynChurch) -> i64 {
+        expr.fold(&EvaluateAlgebra)
+    }
+    fn print(expr: &DynChurch) -> String {
+        expr.fold(&RenderAlgebra)
+    }
+}
+
+/// A tagless-final interpreter that runs two other interpreters side by side.  We need this to
+/// build a tagless-final expression at runtime that we can both evaluate and print.
+pub struct Duplicate<A, B>(pub A, pub B);
+
+impl<A, B> Symantics for Duplicate<A, B>
+where
+    A: Symantics,
+    B: Symantics,
+{
+    type Repr = (A::Repr, B::Repr);
+}
+
+impl<A, B> IntegerLiteralSym for Duplicate<A, B>
+where
+    A: IntegerLiteralSym,
+    B: IntegerLiteralSym,
+{
+    fn integer_literal(&self, value: i64) -> Self::Repr {
+        (self.0.integer_literal(value), self.1.integer_literal(value))
+    }
+}
+
+impl<A, B> AddSym for Duplicate<A, B>
+where
+    A: AddSym,
+    B: AddSym,
+{
+    fn add(&self, lhs: Self::Repr, rhs: Self::Repr) -> Self::Repr {
+        (self.0.add(lhs.0, rhs.0), self.1.add(lhs.1, rhs.1))
+    }
+}
+
+impl<A, B> MultiplySym for Duplicate<A, B>
+where
+    A: MultiplySym,
+    B: MultiplySym,
+{
+    fn multiply(&self, lhs: Self::Repr, rhs: Self::Repr) -> Self::Repr {
+        (self.0.multiply(lhs.0, rhs.0), self.1.multiply(lhs.1, rhs.1))
+    }
+}
+
+/// The tagless-final encoding from ch09c.
+pub struct TaglessFinalLanguage;
+
+type Interpreter = Duplicate<Evaluate<i64>, Render>;
+
+fn interpreter() -> Interpreter {
+    Duplicate(Evaluate::new(), Render)
+}
+
+impl Language for TaglessFinalLanguage {
+    type Expr = <Interpreter as Symantics>::Repr;
+    fn integer_literal(value: i64) -> Self::Expr {
+        interpreter().integer_literal(value)
+    }
+    fn add(lhs: Self::Expr, rhs: Self::Expr) -> Self::Expr {
+        interpreter().add(lhs, rhs)
+    }
+    fn multiply(lhs: Self::Expr, rhs: Self::Expr) -> Self::Expr {
+        interpreter().multiply(lhs, rhs)
+    }
+    fn evaluate(expr: &Self::Expr) -> i64 {
+        expr.0
+    }
+    fn print(expr: &Self::Expr) -> String {
+        expr.1.clone()
+    }
+}
+
+/// Depth-bounded expressions from ch09h.  `Language`'s constructors take the same type at every
+/// level, which is exactly what a `BoundedExpr` rules out, so we build an open sum and bound it
+/// before evaluating or printing it.
+pub struct BoundedLanguage;
+
+fn bounded(expr: &MultExpr) -> BoundedExpr<MAX_DEPTH> {
+    BoundedExpr::from_expr(expr).expect("expression is too deep for a BoundedExpr")
+}
+
+impl Language for BoundedLanguage {
+    type Expr = MultExpr;
+    fn integer_literal(value: i64) -> MultExpr {
+        integer_literal(value)
+    }
+    fn add(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+        add(lhs, rhs)
+    }
+    fn multiply(lhs: MultExpr, rhs: MultExpr) -> MultExpr {
+        multiply(lhs, rhs)
+    }
+    fn evaluate(expr: &MultExpr) -> i64 {
+        bounded(expr)
+            .evaluate()
+            .expect("evaluating a BoundedExpr overflowed")
+    }
+    fn print(expr: &MultExpr) -> String {
+        bounded(expr).to_string()
+    }
+}
+
+/// Small expressions from ch09j, which are stored inline until they have more than `N` nodes.
+pub struct SmallLanguage<const N: usize>;
+
+impl<const N: usize> Language for SmallLanguage<N> {
+    type Expr = SmallExpr<N>;
+    fn integer_literal(value: i64) -> SmallExpr<N> {
+        SmallExpr::literal(value)
+    }
+    fn add(lhs: SmallExpr<N>, rhs: SmallExpr<N>) -> SmallExpr<N> {
+        lhs + rhs
+    }
+    fn multiply(lhs: SmallExpr<N>, rhs: SmallExpr<N>) -> SmallExpr<N> {
+        lhs * rhs
+    }
+    fn evaluate(expr: &SmallExpr<N>) -> i64 {
+        expr.evaluate()
+    }
+    fn print(expr: &SmallExpr<N>) -> String {
+        expr.to_string()
+    }
+}
+
+/// The variadic terms from ch09k, with each binary operator built as a term with two operands.
+pub struct VariadicLanguage;
+
+fn eval_variadic(expr: &FlatExpr) -> i64 {
+    expr.eval(eval_variadic)
+}
+
+impl Language for VariadicLanguage {
+    type Expr = FlatExpr;
+    fn integer_literal(value: i64) -> FlatExpr {
+        integer_literal(value)
+    }
+    fn add(lhs: FlatExpr, rhs: FlatExpr) -> FlatExpr {
+        add_n(vec![lhs, rhs])
+    }
+    fn multiply(lhs: FlatExpr, rhs: FlatExpr) -> FlatExpr {
+        mul_n(vec![lhs, rhs])
+    }
+    fn evaluate(expr: &FlatExpr) -> i64 {
+        eval_variadic(expr)
+    }
+    fn print(expr: &FlatExpr) -> String {
+        expr.to_string()
+    }
+}
+
+/// Trait objects from ch10a, evaluated and printed through their vtables.
+pub struct DynLanguage;
+
+impl Language for DynLanguage {
+    type Expr = DynExpr;
+    fn integer_literal(value: i64) -> DynExpr {
+        dyn_integer_literal(value)
+    }
+    fn add(lhs: DynExpr, rhs: DynExpr) -> DynExpr {
+        dyn_add(lhs, rhs)
+    }
+    fn multiply(lhs: DynExpr, rhs: DynExpr) -> DynExpr {
+        dyn_multiply(lhs, rhs)
+    }
+    fn evaluate(expr: &DynExpr) -> i64 {
+        EvaluateInt::evaluate(expr)
+    }
+    fn print(expr: &DynExpr) -> String {
+        expr.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluate_int_conforms() {
+        check_conformance::<EvaluateIntLanguage>();
+    }
+
+    #[test]
+    fn open_recursion_conforms() {
+        check_conformance::<OpenRecursionLanguage>();
+    }
+
+    #[test]
+    fn mendler_conforms() {
+        check_conformance::<MendlerLanguage>();
+    }
+
+    #[test]
+    fn church_conforms() {
+        check_conformance::<ChurchLanguage>();
+    }
+
+    #[test]
+    fn tagless_final_conforms() {
+        check_conformance::<TaglessFinalLanguage>();
+    }
+
+    #[test]
+    fn bounded_expressions_conform() {
+        check_conformance::<BoundedLanguage>();
+    }
+
+    #[test]
+    fn small_expressions_conform() {
+        check_conformance::<SmallLanguage<INLINE_NODES>>();
+        check_conformance::<SmallLanguage<7>>();
+    }
+
+    #[test]
+    fn variadic_terms_conform() {
+        check_conformance::<VariadicLanguage>();
+    }
+
+    #[test]
+    fn dynamic_terms_conform() {
+        check_conformance::<DynLanguage>();
+    }
+
+    #[test]
+    fn workloads_agree() {
+        let expected = EvaluateIntLanguage::evaluate(&workload::<EvaluateIntLanguage>(10));
+        assert_eq!(
+            OpenRecursionLanguage::evaluate(&workload::<OpenRecursionLanguage>(10)),
+            expected
+        );
+        assert_eq!(
+            MendlerLanguage::evaluate(&workload::<MendlerLanguage>(10)),
+            expected
+        );
+        assert_eq!(
+            ChurchLanguage::evaluate(&workload::<ChurchLanguage>(10)),
+            expected
+        );
+        assert_eq!(
+            TaglessFinalLanguage::evaluate(&workload::<TaglessFinalLanguage>(10)),
+            expected
+        );
+        assert_eq!(
+            SmallLanguage::<7>::evaluate(&workload::<SmallLanguage<7>>(10)),
+            expected
+        );
+        assert_eq!(
+            VariadicLanguage::evaluate(&workload::<VariadicLanguage>(10)),
+            expected
+        );
+        assert_eq!(
+            DynLanguage::evaluate(&workload::<DynLanguage>(10)),
+            expected
+        );
+        // A bounded expression can't hold the whole workload, but it can hold a smaller one.
+        assert_eq!(
+            BoundedLanguage::evaluate(&workload::<BoundedLanguage>(MAX_DEPTH as u32 - 1)),
+            EvaluateIntLanguage::evaluate(&workload::<EvaluateIntLanguage>(MAX_DEPTH as u32 - 1))
+        );
+    }
+}
@@ -0,0 +1,441 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A corpus of sample expressions, loaded from a directory of files, for benchmarks and
+//! differential tests that want realistic inputs instead of generated ones.
+//!
+//! Each file can hold any number of expressions.  Its extension says what syntax they're written
+//! in: `.sexp` files use the s-expression syntax of ch10b's registry parser, and `.infix` files are
+//! reserved for infix syntax (which we can't parse yet).  Files with any other extension are
+//! ignored, so a corpus can have a README next to its samples.  Every expression is converted into
+//! the expression type that you ask for, so that a corpus can't contain a term that your signature
+//! doesn't know about.
+//!
+//! Parsing a big corpus can take longer than the benchmark that uses it, so the loader can cache
+//! each file's expressions in the binary format from the binary module.  A cached file is used
+//! instead of its source as long as it's newer than the source.
+
+use crate::binary::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::ch10a_dynamic_terms::*;
+use crate::ch10b_plugin_registry::*;
+use crate::parallel::split_top_level;
+use crate::rewrite::*;
+use crate::span::*;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The syntax that a corpus file is written in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    SExpr,
+    Infix,
+}
+
+impl Format {
+    /// Picks a format based on a file's extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "sexp" => Some(Format::SExpr),
+            "infix" => Some(Format::Infix),
+            _ => None,
+        }
+    }
+}
+
+/// The ways that loading a corpus can fail.  Each error says which file it came from.
+#[derive(Debug)]
+pub enum CorpusError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    UnsupportedFormat {
+        path: PathBuf,
+        format: Format,
+    },
+    Parse {
+        path: PathBuf,
+        /// Where the expression that failed to parse is in the file.
+        span: Span,
+        error: ParseError,
+    },
+    /// An expression contains a kind of term that the requested signature doesn't.
+    NotInSignature {
+        path: PathBuf,
+        span: Span,
+    },
+}
+
+impl fmt::Display for CorpusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CorpusError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            CorpusError::UnsupportedFormat { path, format } => {
+                write!(f, "{}: can't parse {:?} files yet", path.display(), format)
+            }
+            CorpusError::Parse { path, span, error } => {
+                write!(f, "{} at offset {}: {}", path.display(), span.start, error)
+            }
+            CorpusError::NotInSignature { path, span } => write!(
+                f,
+                "{} at offset {}: expression has terms that aren't in the signature",
+                path.display(),
+                span.start
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CorpusError {}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> CorpusError + '_ {
+    move |error| CorpusError::Io {
+        path: path.to_path_buf(),
+        error,
+    }
+}
+
+/// One expression from a corpus.
+pub struct Sample<E> {
+    /// The file that the expression came from.
+    pub path: PathBuf,
+    /// Which expression it is in that file, starting from 0.
+    pub index: usize,
+    pub expr: E,
+}
+
+/// Every expression in a corpus, ordered by file name and then by position in the file.
+pub struct Corpus<E> {
+    samples: Vec<Sample<E>>,
+    cached_files: usize,
+}
+
+impl<E> Corpus<E> {
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// How many files were loaded from the cache instead of being parsed.
+    pub fn cached_files(&self) -> usize {
+        self.cached_files
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Sample<E>> {
+        self.samples.iter()
+    }
+
+    /// Iterates over just the expressions.
+    pub fn exprs(&self) -> impl Iterator<Item = &E> {
+        self.samples.iter().map(|sample| &sample.expr)
+    }
+}
+
+impl<'a, E> IntoIterator for &'a Corpus<E> {
+    type Item = &'a Sample<E>;
+    type IntoIter = std::slice::Iter<'a, Sample<E>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.samples.iter()
+    }
+}
+
+impl<E> IntoIterator for Corpus<E> {
+    type Item = Sample<E>;
+    type IntoIter = std::vec::IntoIter<Sample<E>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.samples.into_iter()
+    }
+}
+
+// A cache file holds every expression from one source file: a header, and then each expression's
+// binary encoding, preceded by its length as a little-endian u32.
+
+const CACHE_MAGIC: &[u8] = b"EXPRCORPUS1";
+
+/// Loads corpora, using a registry to parse them.
+pub struct CorpusLoader {
+    registry: Registry,
+    cache_dir: Option<PathBuf>,
+}
+
+impl CorpusLoader {
+    pub fn new(registry: Registry) -> CorpusLoader {
+        CorpusLoader {
+            registry,
+            cache_dir: None,
+        }
+    }
+
+    /// Caches parsed files in the given directory, which is created if it doesn't exist.  Cache
+    /// files are named after their source files, so each corpus needs its own cache directory.
+    pub fn with_cache<P: Into<PathBuf>>(mut self, cache_dir: P) -> CorpusLoader {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Loads every expression from the corpus files in `dir`.
+    pub fn load<E>(&self, dir: &Path) -> Result<Corpus<E>, CorpusError>
+    where
+        E: Expression,
+        E::Signature: FromDyn<E> + FromNode<E>,
+        ToNode: Algebra<E::Signature, E, Node>,
+    {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir).map_err(io_error(dir))? {
+            let path = entry.map_err(io_error(dir))?.path();
+            if let (true, Some(format)) = (path.is_file(), Format::from_path(&path)) {
+                files.push((path, format));
+            }
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut samples = Vec::new();
+        let mut cached_files = 0;
+        for (path, format) in files {
+            let exprs = match self.read_cache(&path) {
+                Some(exprs) => {
+                    cached_files += 1;
+                    exprs
+                }
+                None => {
+                    let exprs = self.parse_file(&path, format)?;
+                    self.write_cache(&path, &exprs)?;
+                    exprs
+                }
+            };
+            samples.extend(exprs.into_iter().enumerate().map(|(index, expr)| Sample {
+                path: path.clone(),
+                index,
+                expr,
+            }));
+        }
+        Ok(Corpus {
+            samples,
+            cached_files,
+        })
+    }
+
+    fn parse_file<E>(&self, path: &Path, format: Format) -> Result<Vec<E>, CorpusError>
+    where
+        E: Expression,
+        E::Signature: FromDyn<E>,
+    {
+        if format != Format::SExpr {
+            let path = path.to_path_buf();
+            return Err(CorpusError::UnsupportedFormat { path, format });
+        }
+        let text = fs::read_to_string(path).map_err(io_error(path))?;
+        split_top_level(&text)
+            .into_iter()
+            .map(|span| {
+                let parsed = self.registry.parse(span.slice(&text));
+                let parsed = parsed.map_err(|error| CorpusError::Parse {
+                    path: path.to_path_buf(),
+                    span,
+                    error,
+                })?;
+                from_dyn(&parsed).ok_or_else(|| CorpusError::NotInSignature {
+                    path: path.to_path_buf(),
+                    span,
+                })
+            })
+            .collect()
+    }
+
+    fn cache_path(&self, path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?.to_str()?;
+        Some(self.cache_dir.as_ref()?.join(format!("{}.bin", name)))
+    }
+
+    /// Returns the cached expressions for a file, if there's an up-to-date cache that can be read
+    /// into the requested signature.  Any problem with the cache just means that we reparse.
+    fn read_cache<E>(&self, path: &Path) -> Option<Vec<E>>
+    where
+        E: Expression,
+        E::Signature: FromNode<E>,
+    {
+        let cache_path = self.cache_path(path)?;
+        let cache_modified = fs::metadata(&cache_path).ok()?.modified().ok()?;
+        let source_modified = fs::metadata(path).ok()?.modified().ok()?;
+        if cache_modified < source_modified {
+            return None;
+        }
+        let bytes = fs::read(&cache_path).ok()?;
+        let mut rest = bytes.strip_prefix(CACHE_MAGIC)?;
+        let mut exprs = Vec::new();
+        while !rest.is_empty() {
+            let (length, after) = rest.split_first_chunk::<4>()?;
+            let length = usize::try_from(u32::from_le_bytes(*length)).ok()?;
+            let encoded = after.get(..length)?;
+            let (arena, root) = read_arena(encoded).ok()?;
+            exprs.push(from_node(&arena.to_tree(root))?);
+            rest = &after[length..];
+        }
+        Some(exprs)
+    }
+
+    fn write_cache<E>(&self, path: &Path, exprs: &[E]) -> Result<(), CorpusError>
+    where
+        E: Expression,
+        ToNode: Algebra<E::Signature, E, Node>,
+    {
+        let cache_path = match self.cache_path(path) {
+            Some(cache_path) => cache_path,
+            None => return Ok(()),
+        };
+        let mut bytes = CACHE_MAGIC.to_vec();
+        for expr in exprs {
+            let encoded = to_bytes(&to_node(expr));
+            let length = u32::try_from(encoded.len()).expect("expression is too big to cache");
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(&encoded);
+        }
+        if let Some(dir) = cache_path.parent() {
+            fs::create_dir_all(dir).map_err(io_error(dir))?;
+        }
+        fs::write(&cache_path, bytes).map_err(io_error(&cache_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch02_open_sum::*;
+    use crate::ch03_evaluation::*;
+    use crate::ch05a_multiplication::*;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    /// Creates an empty directory that no other test is using.
+    fn scratch_dir() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "expression-corpus-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn example_corpus() -> PathBuf {
+        let dir = scratch_dir();
+        fs::write(dir.join("a.sexp"), "(add 1 2)\n(multiply (add 80 0) 5)\n").unwrap();
+        fs::write(dir.join("b.sexp"), "7").unwrap();
+        fs::write(dir.join("README"), "not an expression").unwrap();
+        dir
+    }
+
+    fn loader() -> CorpusLoader {
+        CorpusLoader::new(Registry::with_plugins(&[&ArithmeticPlugin]))
+    }
+
+    #[test]
+    fn can_load_a_corpus() {
+        let dir = example_corpus();
+        let corpus: Corpus<MultExpr> = loader().load(&dir).unwrap();
+        let summary: Vec<(String, usize, i64)> = corpus
+            .iter()
+            .map(|sample| {
+                let file = sample.path.file_name().unwrap().to_string_lossy();
+                (file.to_string(), sample.index, sample.expr.evaluate())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a.sexp".to_string(), 0, 3),
+                ("a.sexp".to_string(), 1, 400),
+                ("b.sexp".to_string(), 0, 7),
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_terms_outside_the_signature() {
+        let dir = example_corpus();
+        let error = loader().load::<Expr>(&dir).err().unwrap();
+        assert!(matches!(
+            error,
+            CorpusError::NotInSignature { span, .. } if span == Span::new(10, 33)
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let dir = scratch_dir();
+        fs::write(dir.join("bad.sexp"), "(add 1 2) (subtract 1 2)").unwrap();
+        let error = loader().load::<MultExpr>(&dir).err().unwrap();
+        assert!(error
+            .to_string()
+            .ends_with("at offset 10: unknown term `subtract`"));
+        fs::write(dir.join("bad.sexp"), "1").unwrap();
+        fs::write(dir.join("later.infix"), "1 + 2").unwrap();
+        let error = loader().load::<MultExpr>(&dir).err().unwrap();
+        assert!(matches!(
+            error,
+            CorpusError::UnsupportedFormat {
+                format: Format::Infix,
+                ..
+            }
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn can_cache_parsed_files() {
+        let dir = example_corpus();
+        let cache = dir.join("cache");
+        let loader = loader().with_cache(&cache);
+        let corpus: Corpus<MultExpr> = loader.load(&dir).unwrap();
+        assert_eq!(corpus.cached_files(), 0);
+        assert!(cache.join("a.sexp.bin").is_file());
+
+        // Break the source file, but make it older than its cache, so that the cache is used.
+        let source = dir.join("a.sexp");
+        fs::write(&source, "(this does not parse").unwrap();
+        let long_ago = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(long_ago)
+            .unwrap();
+        let corpus: Corpus<MultExpr> = loader.load(&dir).unwrap();
+        assert_eq!(corpus.cached_files(), 2);
+        let values: Vec<i64> = corpus.exprs().map(|expr| expr.evaluate()).collect();
+        assert_eq!(values, vec![3, 400, 7]);
+
+        // A cache in the wrong signature is ignored, and the source is parsed instead.
+        assert!(loader.load::<Expr>(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,509 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Expressions are trees, so a subexpression that appears twice is stored (and evaluated) twice.
+//! This module compresses a tree into an explicit DAG — a table of nodes, where each node refers to
+//! its children by index — by merging subtrees that are structurally equal.  The node table is in
+//! topological order: every node comes after all of its children.
+//!
+//! That order is also exactly what we need to evaluate the DAG: walk the table from front to back,
+//! and every node's children already have values by the time we reach it.  A shared subtree is
+//! evaluated once, no matter how many times it appeared in the original tree.
+//!
+//! To see what got shared, you can export a DAG as GraphML or as a simple JSON graph, and load it
+//! into your graph tool of choice.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::ch11f_html_export::escape_html;
+use crate::rewrite::*;
+use crate::telemetry::TermKind;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+
+/// The index of a node in a DAG's node table.
+pub type NodeId = usize;
+
+/// One node of a DAG.  Just like `Node`, except that children are indices into the node table.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum DagNode {
+    Literal(i64),
+    Term {
+        kind: &'static str,
+        children: Vec<NodeId>,
+    },
+}
+
+impl DagNode {
+    pub fn children(&self) -> &[NodeId] {
+        match self {
+            DagNode::Literal(_) => &[],
+            DagNode::Term { children, .. } => children,
+        }
+    }
+
+    /// What to call this node in a graph: the literal's value, or the kind of term.
+    pub fn label(&self) -> String {
+        match self {
+            DagNode::Literal(value) => value.to_string(),
+            DagNode::Term { kind, .. } => kind.to_string(),
+        }
+    }
+}
+
+/// An expression with all of its structurally equal subtrees merged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dag {
+    nodes: Vec<DagNode>,
+    root: NodeId,
+    tree_size: usize,
+}
+
+impl Dag {
+    /// Compresses a tree, merging every pair of structurally equal subtrees.
+    pub fn from_tree(tree: &Node) -> Dag {
+        let mut builder = Builder::default();
+        let root = builder.insert(tree);
+        Dag {
+            nodes: builder.nodes,
+            root,
+            tree_size: tree.size(),
+        }
+    }
+
+    /// All of the distinct nodes, children before parents.
+    pub fn nodes(&self) -> &[DagNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, id: NodeId) -> &DagNode {
+        &self.nodes[id]
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// The number of distinct nodes.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The number of nodes in the tree that this DAG was compressed from.
+    pub fn tree_size(&self) -> usize {
+        self.tree_size
+    }
+
+    /// How many tree nodes each DAG node stands for, on average.  1.0 means that there was no
+    /// sharing at all.
+    pub fn compression_ratio(&self) -> f64 {
+        self.tree_size as f64 / self.nodes.len() as f64
+    }
+
+    /// Expands the DAG back out into a tree.
+    pub fn to_tree(&self) -> Node {
+        self.expand(self.root)
+    }
+
+    /// Evaluates the DAG, computing each node exactly once.  Fails if the DAG contains a term that
+    /// doesn't evaluate to a number, or if the result overflows.
+    pub fn evaluate(&self) -> Result<i64, DagError> {
+        evaluate_nodes(&self.nodes, self.root)
+    }
+
+    fn expand(&self, id: NodeId) -> Node {
+        match &self.nodes[id] {
+            DagNode::Literal(value) => Node::Literal(*value),
+            DagNode::Term { kind, children } => {
+                Node::term(kind, children.iter().map(|id| self.expand(*id)).collect())
+            }
+        }
+    }
+}
+
+/// Why a node table couldn't be evaluated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DagError {
+    /// The expression contains a term that doesn't evaluate to a number.
+    UnsupportedTerm { term: &'static str },
+    /// A node has the wrong number of children for its kind, or refers to a child that doesn't
+    /// come before it in the table.
+    Malformed { node: NodeId },
+    /// The root isn't in the table.
+    NoSuchNode { node: NodeId },
+    /// The value of a node doesn't fit in an i64.
+    Overflow { node: NodeId },
+}
+
+impl fmt::Display for DagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DagError::UnsupportedTerm { term } => write!(f, "can't evaluate a {} term", term),
+            DagError::Malformed { node } => write!(f, "node {} is malformed", node),
+            DagError::NoSuchNode { node } => write!(f, "there is no node {}", node),
+            DagError::Overflow { node } => write!(f, "node {} overflows", node),
+        }
+    }
+}
+
+impl std::error::Error for DagError {}
+
+/// Evaluates the node at `root`, given a node table in topological order.  Only the nodes that
+/// `root` refers to are evaluated, each exactly once, so the table can contain other expressions
+/// that we don't know how to evaluate.
+pub fn evaluate_nodes(nodes: &[DagNode], root: NodeId) -> Result<i64, DagError> {
+    if root >= nodes.len() {
+        return Err(DagError::NoSuchNode { node: root });
+    }
+
+    // Walk backwards from the root to find the nodes that it needs.  Since children come before
+    // their parents, one pass is enough.
+    let mut reachable = vec![false; root + 1];
+    reachable[root] = true;
+    for id in (0..=root).rev() {
+        if !reachable[id] {
+            continue;
+        }
+        for child in nodes[id].children() {
+            if *child >= id {
+                return Err(DagError::Malformed { node: id });
+            }
+            reachable[*child] = true;
+        }
+    }
+
+    let mut values: Vec<Option<i64>> = vec![None; root + 1];
+    for id in (0..=root).filter(|id| reachable[*id]) {
+        let value = match &nodes[id] {
+            DagNode::Literal(value) => *value,
+            DagNode::Term { kind, children } => {
+                let operation: fn(i64, i64) -> Option<i64> = match *kind {
+                    k if k == Add::<NodeId>::NAME => i64::checked_add,
+                    k if k == Multiply::<NodeId>::NAME => i64::checked_mul,
+                    _ => return Err(DagError::UnsupportedTerm { term: kind }),
+                };
+                let (lhs, rhs) = match children[..] {
+                    [lhs, rhs] => (values[lhs].unwrap(), values[rhs].unwrap()),
+                    _ => return Err(DagError::Malformed { node: id }),
+                };
+                operation(lhs, rhs).ok_or(DagError::Overflow { node: id })?
+            }
+        };
+        values[id] = Some(value);
+    }
+    Ok(values[root].unwrap())
+}
+
+// A node can use the same child more than once (x + x), so each edge records which of its parent's
+// children it is.  Otherwise the two edges of x + x would be indistinguishable.
+
+impl Dag {
+    /// Exports the DAG as a GraphML document.  Nodes have a `label` attribute, edges have an
+    /// `index` attribute saying which of the source's children the target is, and the graph has a
+    /// `root` attribute.
+    pub fn to_graphml(&self) -> String {
+        let mut result = String::new();
+        result.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        result.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        result.push_str(
+            "  <key id=\"root\" for=\"graph\" attr.name=\"root\" attr.type=\"string\"/>\n",
+        );
+        result.push_str(
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+        );
+        result
+            .push_str("  <key id=\"index\" for=\"edge\" attr.name=\"index\" attr.type=\"int\"/>\n");
+        result.push_str("  <graph id=\"expression\" edgedefault=\"directed\">\n");
+        writeln!(result, "    <data key=\"root\">n{}</data>", self.root).unwrap();
+        for (id, node) in self.nodes.iter().enumerate() {
+            writeln!(
+                result,
+                "    <node id=\"n{}\"><data key=\"label\">{}</data></node>",
+                id,
+                escape_html(&node.label())
+            )
+            .unwrap();
+        }
+        for (id, node) in self.nodes.iter().enumerate() {
+            for (index, child) in node.children().iter().enumerate() {
+                writeln!(
+                    result,
+                    "    <edge source=\"n{}\" target=\"n{}\"><data key=\"index\">{}</data></edge>",
+                    id, child, index
+                )
+                .unwrap();
+            }
+        }
+        result.push_str("  </graph>\n");
+        result.push_str("</graphml>\n");
+        result
+    }
+
+    /// Exports the DAG as JSON, in the form
+    ///
+    /// ```text
+    /// {"root":2,"nodes":[{"id":0,"label":"1"},...],"edges":[{"source":2,"target":0,"index":0},...]}
+    /// ```
+    pub fn to_json(&self) -> String {
+        let nodes = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(id, node)| format!("{{\"id\":{},\"label\":{}}}", id, json_string(&node.label())))
+            .collect::<Vec<_>>();
+        let edges = self
+            .nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(id, node)| {
+                node.children()
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, child)| {
+                        format!(
+                            "{{\"source\":{},\"target\":{},\"index\":{}}}",
+                            id, child, index
+                        )
+                    })
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"root\":{},\"nodes\":[{}],\"edges\":[{}]}}",
+            self.root,
+            nodes.join(","),
+            edges.join(",")
+        )
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for ch in value.chars() {
+        match ch {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            ch if ch.is_control() => write!(result, "\\u{:04x}", ch as u32).unwrap(),
+            ch => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[derive(Default)]
+struct Builder {
+    nodes: Vec<DagNode>,
+    ids: HashMap<DagNode, NodeId>,
+}
+
+impl Builder {
+    // Children are inserted before their parent, which is what keeps the table in topological
+    // order.  And since children are replaced by their ids first, checking the parent for an
+    // existing copy is a single hash lookup, no matter how big the subtree is.
+    fn insert(&mut self, tree: &Node) -> NodeId {
+        let node = match tree {
+            Node::Literal(value) => DagNode::Literal(*value),
+            Node::Term { kind, children } => DagNode::Term {
+                kind,
+                children: children.iter().map(|child| self.insert(child)).collect(),
+            },
+        };
+        if let Some(id) = self.ids.get(&node) {
+            return *id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(node.clone());
+        self.ids.insert(node, id);
+        id
+    }
+}
+
+/// Compresses an expression into a DAG.
+pub fn compress<E>(expr: &E) -> Dag
+where
+    E: Expression,
+    ToNode: Algebra<E::Signature, E, Node>,
+{
+    Dag::from_tree(&to_node(expr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch07a_pairs::*;
+
+    fn shared() -> MultExpr {
+        // (1 + 2) * (1 + 2)
+        multiply(
+            add(integer_literal(1), integer_literal(2)),
+            add(integer_literal(1), integer_literal(2)),
+        )
+    }
+
+    #[test]
+    fn can_merge_equal_subtrees() {
+        let dag = compress(&shared());
+        assert_eq!(dag.tree_size(), 7);
+        assert_eq!(dag.node_count(), 4);
+        assert_eq!(dag.compression_ratio(), 7.0 / 4.0);
+        assert_eq!(
+            dag.node(dag.root()),
+            &DagNode::Term {
+                kind: "multiply",
+                children: vec![2, 2],
+            }
+        );
+    }
+
+    #[test]
+    fn nodes_are_in_topological_order() {
+        let dag = compress(&shared());
+        for (id, node) in dag.nodes().iter().enumerate() {
+            assert!(node.children().iter().all(|child| *child < id));
+        }
+        assert_eq!(dag.root(), dag.node_count() - 1);
+    }
+
+    #[test]
+    fn can_expand_back_into_a_tree() {
+        let tree = to_node(&shared());
+        assert_eq!(Dag::from_tree(&tree).to_tree(), tree);
+    }
+
+    #[test]
+    fn can_evaluate() {
+        let dag = compress(&shared());
+        assert_eq!(dag.evaluate(), Ok(9));
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(compress(&expr).evaluate(), Ok(404));
+    }
+
+    #[test]
+    fn reports_overflow() {
+        let expr: MultExpr = add(
+            integer_literal(1),
+            multiply(integer_literal(i64::MAX), integer_literal(2)),
+        );
+        assert_eq!(
+            compress(&expr).evaluate(),
+            Err(DagError::Overflow { node: 3 })
+        );
+    }
+
+    #[test]
+    fn only_evaluates_reachable_nodes() {
+        let nodes = vec![
+            DagNode::Literal(1),
+            DagNode::Term {
+                kind: "pair",
+                children: vec![0, 0],
+            },
+            DagNode::Term {
+                kind: "add",
+                children: vec![0, 0],
+            },
+        ];
+        assert_eq!(evaluate_nodes(&nodes, 2), Ok(2));
+        assert_eq!(evaluate_nodes(&nodes, 0), Ok(1));
+        assert_eq!(
+            evaluate_nodes(&nodes, 1),
+            Err(DagError::UnsupportedTerm { term: "pair" })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_tables() {
+        let nodes = vec![
+            DagNode::Literal(1),
+            DagNode::Term {
+                kind: "add",
+                children: vec![0],
+            },
+            DagNode::Term {
+                kind: "multiply",
+                children: vec![0, 2],
+            },
+        ];
+        assert_eq!(
+            evaluate_nodes(&nodes, 1),
+            Err(DagError::Malformed { node: 1 })
+        );
+        assert_eq!(
+            evaluate_nodes(&nodes, 2),
+            Err(DagError::Malformed { node: 2 })
+        );
+        assert_eq!(
+            evaluate_nodes(&nodes, 3),
+            Err(DagError::NoSuchNode { node: 3 })
+        );
+    }
+
+    #[test]
+    fn cant_evaluate_pairs() {
+        let expr: PairExpr = first(pair(integer_literal(1), integer_literal(2)));
+        assert_eq!(
+            compress(&expr).evaluate(),
+            Err(DagError::UnsupportedTerm { term: "pair" })
+        );
+    }
+
+    #[test]
+    fn can_export_json() {
+        let expr: MultExpr = add(integer_literal(-1), integer_literal(-1));
+        assert_eq!(
+            compress(&expr).to_json(),
+            concat!(
+                r#"{"root":1,"nodes":[{"id":0,"label":"-1"},{"id":1,"label":"add"}],"#,
+                r#""edges":[{"source":1,"target":0,"index":0},{"source":1,"target":0,"index":1}]}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn can_export_graphml() {
+        let expr: MultExpr = add(integer_literal(1), integer_literal(1));
+        let graphml = compress(&expr).to_graphml();
+        assert!(graphml.contains(r#"<data key="root">n1</data>"#));
+        assert!(graphml.contains(r#"<node id="n0"><data key="label">1</data></node>"#));
+        assert!(graphml.contains(r#"<node id="n1"><data key="label">add</data></node>"#));
+        assert!(
+            graphml.contains(r#"<edge source="n1" target="n0"><data key="index">0</data></edge>"#)
+        );
+        assert!(
+            graphml.contains(r#"<edge source="n1" target="n0"><data key="index">1</data></edge>"#)
+        );
+        assert!(graphml.ends_with("</graphml>\n"));
+    }
+
+    #[test]
+    fn can_quote_json_strings() {
+        assert_eq!(json_string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+    }
+
+    #[test]
+    fn trees_without_sharing_dont_compress() {
+        let expr: MultExpr = add(integer_literal(1), integer_literal(2));
+        assert_eq!(compress(&expr).compression_ratio(), 1.0);
+    }
+}
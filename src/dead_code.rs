@@ -0,0 +1,134 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Dead-definition elimination, the simplest whole-program optimization there is: starting from
+//! the expression that a program is run for, follow name references to find every definition
+//! that could possibly be evaluated, and drop the rest.
+//!
+//! The reachability analysis only needs to know which names each definition refers to, so it
+//! works the same for a `Program`'s `Global`s and for a `Sheet`'s `CellRef`s; each just supplies
+//! its own references algebra.
+
+use crate::ch09a_mendler::*;
+use crate::program::*;
+
+use std::collections::BTreeSet;
+
+/// Every name reachable from `roots`, where `references` gives the names that a definition refers
+/// to, or None if there's no definition with that name.  Undefined names are left out of the
+/// result, since there's nothing there to keep.
+pub fn reachable<F>(roots: BTreeSet<String>, mut references: F) -> BTreeSet<String>
+where
+    F: FnMut(&str) -> Option<BTreeSet<String>>,
+{
+    let mut seen = BTreeSet::new();
+    let mut pending: Vec<String> = roots.into_iter().collect();
+    while let Some(name) = pending.pop() {
+        if seen.contains(&name) {
+            continue;
+        }
+        if let Some(names) = references(&name) {
+            pending.extend(names.into_iter().filter(|name| !seen.contains(name)));
+            seen.insert(name);
+        }
+    }
+    seen
+}
+
+/// What dead-definition elimination removed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EliminationReport {
+    /// The definitions that were dropped, in the order they were written.
+    pub removed: Vec<String>,
+}
+
+/// Drops every definition that `entry` can't reach.  The definitions that are left stay in the
+/// order they were written.
+pub fn eliminate_dead_definitions(
+    program: Program,
+    entry: &ProgramExpr,
+) -> (Program, EliminationReport) {
+    let live = reachable(mcata(&Globals, entry), |name| {
+        program.get(name).map(|expr| mcata(&Globals, expr))
+    });
+    let mut result = Program::new();
+    let mut report = EliminationReport::default();
+    for definition in program.into_definitions() {
+        if live.contains(&definition.name) {
+            // The names were already unique in the original program.
+            result.define(&definition.name, definition.expr).unwrap();
+        } else {
+            report.removed.push(definition.name);
+        }
+    }
+    (result, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+
+    fn example() -> Program {
+        let mut program = Program::new();
+        program.define("unused", global("helper")).unwrap();
+        program.define("helper", integer_literal(2)).unwrap();
+        program
+            .define("area", multiply(global("width"), global("height")))
+            .unwrap();
+        program.define("width", integer_literal(3)).unwrap();
+        program.define("height", global("width")).unwrap();
+        program.define("island", global("island")).unwrap();
+        program
+    }
+
+    #[test]
+    fn can_drop_unreachable_definitions() {
+        let entry: ProgramExpr = add(global("area"), integer_literal(1));
+        let (program, report) = eliminate_dead_definitions(example(), &entry);
+        assert_eq!(report.removed, vec!["unused", "helper", "island"]);
+        assert_eq!(
+            program.to_string(),
+            "area = (width * height)\nwidth = 3\nheight = width\n"
+        );
+        assert_eq!(program.evaluate().unwrap()["area"], 9);
+    }
+
+    #[test]
+    fn keeps_everything_reachable() {
+        let entry: ProgramExpr = add(global("unused"), global("area"));
+        let (_, report) = eliminate_dead_definitions(example(), &entry);
+        assert_eq!(report.removed, vec!["island"]);
+
+        let entry: ProgramExpr = integer_literal(1);
+        let (program, report) = eliminate_dead_definitions(example(), &entry);
+        assert_eq!(report.removed.len(), 6);
+        assert!(program.definitions().is_empty());
+    }
+
+    #[test]
+    fn can_follow_cell_references() {
+        let mut sheet = Sheet::new();
+        sheet.set("a", add(cell_ref("b"), cell_ref("missing")));
+        sheet.set("b", integer_literal(1));
+        sheet.set("c", cell_ref("a"));
+        let live = reachable(std::iter::once("a".to_string()).collect(), |name| {
+            sheet.expr(name).map(|expr| mcata(&References, expr))
+        });
+        assert_eq!(live.into_iter().collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,345 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Renders errors the way that rustc does: with an excerpt of the source, carets under the part
+//! that's wrong, and any notes that might help explain it.
+//!
+//! ```text
+//! error: unknown term `subtract`
+//!  --> 1:7
+//!   |
+//! 1 | (add (subtract 1 2) 3)
+//!   |       ^^^^^^^^ no plugin provides this term
+//! ```
+//!
+//! A `Report` doesn't care where its errors came from; it just needs spans into the source text.
+//! At the bottom of the module are conversions from the parse and evaluation errors in ch10b.
+
+use crate::ch10b_plugin_registry::*;
+use crate::span::*;
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Points at a part of the source, with an optional explanation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: Option<String>,
+}
+
+/// Some extra information about an error.  A note that has a span is rendered with its own
+/// excerpt, underlined with dashes instead of carets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+/// An error (or warning) that can be rendered against the source text that it refers to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub severity: Severity,
+    pub message: String,
+    pub primary: Option<Label>,
+    pub notes: Vec<Note>,
+}
+
+impl Report {
+    pub fn error(message: impl Into<String>) -> Report {
+        Report {
+            severity: Severity::Error,
+            message: message.into(),
+            primary: None,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Points this report at the part of the source where the problem is.
+    pub fn with_label(mut self, span: Span, message: Option<String>) -> Report {
+        self.primary = Some(Label { span, message });
+        self
+    }
+
+    /// Adds a note that points at another part of the source.
+    pub fn with_span_note(mut self, span: Span, message: impl Into<String>) -> Report {
+        self.notes.push(Note {
+            span: Some(span),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Adds a note that doesn't point at anything in particular.
+    pub fn with_note(mut self, message: impl Into<String>) -> Report {
+        self.notes.push(Note {
+            span: None,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Renders this report against the source text that its spans refer to.
+    pub fn render(&self, source: &str) -> String {
+        let mut excerpts: Vec<(Span, char, Option<&str>)> = Vec::new();
+        if let Some(label) = &self.primary {
+            excerpts.push((label.span, '^', label.message.as_deref()));
+        }
+        for note in &self.notes {
+            if let Some(span) = note.span {
+                excerpts.push((span, '-', Some(note.message.as_str())));
+            }
+        }
+        let width = excerpts
+            .iter()
+            .map(|(span, _, _)| locate(source, span.start).0.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let gutter = " ".repeat(width);
+
+        let mut result = format!("{}: {}\n", self.severity, self.message);
+        if let Some(label) = &self.primary {
+            let (line, column) = locate(source, label.span.start);
+            result.push_str(&format!("{}--> {}:{}\n", gutter, line, column));
+        }
+        if !excerpts.is_empty() {
+            result.push_str(&format!("{} |\n", gutter));
+        }
+        for (span, mark, message) in excerpts {
+            let (line, column) = locate(source, span.start);
+            let text = source.lines().nth(line - 1).unwrap_or("");
+            // Spans that cross lines are only underlined up to the end of their first line.
+            let available = text.chars().count().saturating_sub(column - 1);
+            let start = clamp(source, span.start);
+            let end = clamp(source, span.end).max(start);
+            let length = source[start..end].chars().count().min(available).max(1);
+            result.push_str(&format!("{:>width$} | {}\n", line, text, width = width));
+            let mut underline = format!(
+                "{} | {}{}",
+                gutter,
+                " ".repeat(column - 1),
+                mark.to_string().repeat(length)
+            );
+            if let Some(message) = message {
+                underline.push(' ');
+                underline.push_str(message);
+            }
+            result.push_str(&underline);
+            result.push('\n');
+        }
+        for note in &self.notes {
+            if note.span.is_none() {
+                result.push_str(&format!("{} = note: {}\n", gutter, note.message));
+            }
+        }
+        result
+    }
+}
+
+/// Returns the 1-based line and column (in characters) of a byte offset.
+// Spans come from wherever the diagnostic was made, and might not match the source that we're
+// given.  Rather than panicking, we clamp them to the source, and back them up to the start of
+// the character that they land in.
+fn clamp(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+fn locate(source: &str, offset: usize) -> (usize, usize) {
+    let offset = clamp(source, offset);
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+    let column = source[line_start..offset].chars().count() + 1;
+    (line, column)
+}
+
+impl From<&Diagnostic> for Report {
+    fn from(diagnostic: &Diagnostic) -> Report {
+        let report = Report::error(diagnostic.error.to_string());
+        let label = |message: &str| Some(message.to_string());
+        match &diagnostic.error {
+            ParseError::UnexpectedEnd => {
+                report.with_label(diagnostic.span, label("expected more input here"))
+            }
+            ParseError::UnexpectedToken(_) => {
+                report.with_label(diagnostic.span, label("not expected here"))
+            }
+            ParseError::UnknownTerm(_) => {
+                report.with_label(diagnostic.span, label("no plugin provides this term"))
+            }
+            ParseError::WrongArity { name, expected, .. } => {
+                report.with_label(diagnostic.span, None).with_note(format!(
+                    "`{}` is written as `({}{})`",
+                    name,
+                    name,
+                    " <expr>".repeat(*expected)
+                ))
+            }
+            ParseError::TooDeep { limit, .. } => report
+                .with_label(diagnostic.span, label("nested too deeply"))
+                .with_note(format!(
+                    "expressions can be nested at most {} levels deep",
+                    limit
+                )),
+            ParseError::OverBudget { error, .. } => report
+                .with_label(diagnostic.span, label("this went over the budget"))
+                .with_note(format!(
+                    "expressions can use at most {} {}",
+                    error.limit, error.resource
+                )),
+        }
+    }
+}
+
+impl From<&EvalError> for Report {
+    fn from(error: &EvalError) -> Report {
+        match error {
+            EvalError::At { span, error } => {
+                Report::error(error.to_string()).with_label(*span, None)
+            }
+            EvalError::UnregisteredTerm => Report::error(error.to_string())
+                .with_note("every kind of term needs an evaluation rule from a plugin"),
+            EvalError::Failed(_) => Report::error(error.to_string()),
+        }
+    }
+}
+
+/// Renders every report, separated by blank lines.
+pub fn render_all<'a, I>(source: &str, reports: I) -> String
+where
+    I: IntoIterator<Item = &'a Report>,
+{
+    reports
+        .into_iter()
+        .map(|report| report.render(source))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_render_parse_errors() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let source = "(add (subtract 1 2) 3)";
+        let (_, diagnostics) = registry.parse_recovering(source);
+        let reports: Vec<Report> = diagnostics.iter().map(Report::from).collect();
+        assert_eq!(
+            render_all(source, &reports),
+            "error: unknown term `subtract`\n\
+             \x20--> 1:7\n\
+             \x20 |\n\
+             1 | (add (subtract 1 2) 3)\n\
+             \x20 |       ^^^^^^^^ no plugin provides this term\n"
+        );
+    }
+
+    #[test]
+    fn can_render_errors_on_later_lines() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let source = "(add\n  (multiply 1)\n  2)";
+        let (_, diagnostics) = registry.parse_recovering(source);
+        assert_eq!(
+            Report::from(&diagnostics[0]).render(source),
+            "error: `multiply` expects 2 subexpressions, but got 1\n\
+             \x20--> 2:3\n\
+             \x20 |\n\
+             2 |   (multiply 1)\n\
+             \x20 |   ^^^^^^^^^^^^\n\
+             \x20 = note: `multiply` is written as `(multiply <expr> <expr>)`\n"
+        );
+    }
+
+    #[test]
+    fn can_point_at_the_end_of_the_input() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let source = "(add 1";
+        let (_, diagnostics) = registry.parse_recovering(source);
+        assert_eq!(
+            Report::from(&diagnostics[0]).render(source),
+            "error: unexpected end of input\n\
+             \x20--> 1:7\n\
+             \x20 |\n\
+             1 | (add 1\n\
+             \x20 |       ^ expected more input here\n"
+        );
+    }
+
+    #[test]
+    fn tolerates_spans_that_dont_fit_the_source() {
+        let source = "(add é 1)";
+        let render = |span| Report::error("oops").with_label(span, None).render(source);
+        // Past the end, backwards, and in the middle of the é.
+        assert!(render(Span::new(20, 25)).ends_with("1 | (add é 1)\n\x20 |          ^\n"));
+        assert!(render(Span::new(3, 1)).ends_with("\x20 |    ^\n"));
+        assert!(render(Span::new(6, 7)).ends_with("\x20 |      ^\n"));
+    }
+
+    #[test]
+    fn can_render_notes_with_spans() {
+        let source = "(add (pair 1 2) 3)";
+        let report = Report::error("cannot add a pair")
+            .with_label(Span::new(0, 18), None)
+            .with_span_note(Span::new(5, 15), "this subexpression evaluated to (1, 2)")
+            .with_note("only integers can be added");
+        assert_eq!(
+            report.render(source),
+            "error: cannot add a pair\n\
+             \x20--> 1:1\n\
+             \x20 |\n\
+             1 | (add (pair 1 2) 3)\n\
+             \x20 | ^^^^^^^^^^^^^^^^^^\n\
+             1 | (add (pair 1 2) 3)\n\
+             \x20 |      ---------- this subexpression evaluated to (1, 2)\n\
+             \x20 = note: only integers can be added\n"
+        );
+    }
+
+    #[test]
+    fn can_render_evaluation_errors() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let source = "(add 1 (multiply x 2))";
+        let (expr, _) = registry.parse_located(source);
+        let error = registry.evaluate(&expr).unwrap_err();
+        assert_eq!(
+            Report::from(&error).render(source),
+            "error: syntax error at offset 17\n\
+             \x20--> 1:8\n\
+             \x20 |\n\
+             1 | (add 1 (multiply x 2))\n\
+             \x20 |        ^^^^^^^^^^^^^^\n"
+        );
+    }
+}
@@ -0,0 +1,410 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A textual dump format for snapshot tests.  Unlike the Display impls, which we're free to make
+//! prettier whenever we like, this format is versioned: a given expression will always dump to the
+//! same text under the same version, no matter how the crate is refactored.  If the format ever has
+//! to change, it'll get a new version number, and `dump_version` will still produce the old one.
+//!
+//! Version 1 looks like this:
+//!
+//! ```text
+//! expression-dump 1
+//! add
+//!   multiply
+//!     integer_literal 80
+//!     integer_literal 5 @unit="m"
+//!   integer_literal 4
+//! ```
+//!
+//! The first line names the format and its version.  Then there's one line per node, in preorder,
+//! indented by two spaces per level of nesting.  Each line has the term's name, then the term's own
+//! fields, if it has any, then any annotations, sorted by key.  Annotation values are always
+//! quoted, with `\`, `"`, and control characters escaped.  The names are part of the format, so
+//! they have their own table below; they happen to match the names that telemetry uses, but
+//! telemetry is free to rename things, and the dump format isn't.
+//!
+//! Since the format is stable, it also makes a good basis for a content address: a hash of an
+//! expression that you can use as a cache key across processes.  `content_hash` dumps an
+//! expression in version 1 of the format, after stripping its annotations and putting it in a
+//! canonical form, where the operands of commutative terms are in a standard order.  So `1 + 2`
+//! and `2 + 1` have the same content hash.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+// The name of each kind of term in the format.  Like the rest of the format, these can never
+// change.
+
+const INTEGER_LITERAL: &str = "integer_literal";
+const ADD: &str = "add";
+const MULTIPLY: &str = "multiply";
+const PAIR: &str = "pair";
+const FIRST: &str = "first";
+const SECOND: &str = "second";
+
+/// The versions of the dump format that we know how to produce.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DumpVersion {
+    V1,
+}
+
+impl DumpVersion {
+    pub const LATEST: DumpVersion = DumpVersion::V1;
+}
+
+/// One node of a dump.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DumpNode {
+    pub name: &'static str,
+    pub fields: Vec<String>,
+    pub annotations: BTreeMap<String, String>,
+    pub children: Vec<DumpNode>,
+}
+
+impl DumpNode {
+    fn new(name: &'static str, fields: Vec<String>, children: Vec<DumpNode>) -> DumpNode {
+        DumpNode {
+            name,
+            fields,
+            annotations: BTreeMap::new(),
+            children,
+        }
+    }
+
+    /// Returns the node at `path`, where each element is the index of a child.
+    pub fn at_path_mut(&mut self, path: &[usize]) -> Option<&mut DumpNode> {
+        match path.split_first() {
+            None => Some(self),
+            Some((index, rest)) => self.children.get_mut(*index)?.at_path_mut(rest),
+        }
+    }
+
+    /// Renders this node and its children in the given version of the format.
+    pub fn render(&self, version: DumpVersion) -> String {
+        let mut result = String::new();
+        match version {
+            DumpVersion::V1 => {
+                result.push_str("expression-dump 1\n");
+                self.render_v1(0, &mut result);
+            }
+        }
+        result
+    }
+
+    fn render_v1(&self, depth: usize, result: &mut String) {
+        result.push_str(&"  ".repeat(depth));
+        self.render_line_v1(result);
+        for child in &self.children {
+            child.render_v1(depth + 1, result);
+        }
+    }
+
+    /// Renders just this node's own line, without any indentation.
+    fn render_line_v1(&self, result: &mut String) {
+        result.push_str(self.name);
+        for field in &self.fields {
+            result.push(' ');
+            result.push_str(field);
+        }
+        for (key, value) in &self.annotations {
+            write!(result, " @{}={}", key, quote(value)).unwrap();
+        }
+        result.push('\n');
+    }
+}
+
+// The operands of these terms can be swapped without changing the expression's value.
+const COMMUTATIVE: &[&str] = &[ADD, MULTIPLY];
+
+impl DumpNode {
+    /// Removes every annotation, and sorts the operands of every commutative term, so that
+    /// expressions that only differ in those ways end up identical.
+    pub fn canonicalize(&mut self) {
+        self.canonicalize_with_key();
+    }
+
+    // Returns the key that we sort operands by: a hash of the node's own line and its children's
+    // keys.  Since each key is built from the keys below it, we only look at each node once.  Two
+    // different subtrees could have the same key, but that only means that swapping them might
+    // change the content hash, which is as unlikely as any other hash collision.
+    fn canonicalize_with_key(&mut self) -> ContentHash {
+        self.annotations.clear();
+        let mut children: Vec<(ContentHash, DumpNode)> = std::mem::take(&mut self.children)
+            .into_iter()
+            .map(|mut child| (child.canonicalize_with_key(), child))
+            .collect();
+        if COMMUTATIVE.contains(&self.name) {
+            children.sort_by_key(|(key, _)| *key);
+        }
+        let mut line = String::new();
+        self.render_line_v1(&mut line);
+        let mut bytes = line.into_bytes();
+        for (key, _) in &children {
+            bytes.extend_from_slice(&key.0.to_le_bytes());
+        }
+        self.children = children.into_iter().map(|(_, child)| child).collect();
+        ContentHash::of(&bytes)
+    }
+
+    /// The content hash of this tree, ignoring annotations and the order of commutative operands.
+    pub fn content_hash(&self) -> ContentHash {
+        let mut canonical = self.clone();
+        canonical.canonicalize();
+        ContentHash::of(canonical.render(DumpVersion::V1).as_bytes())
+    }
+}
+
+/// A stable 64-bit digest of an expression.  It's the FNV-1a hash of the expression's canonical
+/// version 1 dump, which (unlike `std::hash`) is guaranteed to be the same in every process and
+/// every version of Rust.  It's not a cryptographic hash, so don't use it to detect tampering.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ContentHash(pub u64);
+
+impl ContentHash {
+    fn of(bytes: &[u8]) -> ContentHash {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+        let hash = bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+        });
+        ContentHash(hash)
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Quotes a string without relying on Debug, whose output isn't guaranteed to stay the same.
+fn quote(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for ch in value.chars() {
+        match ch {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            ch if ch.is_control() => write!(result, "\\u{{{:x}}}", ch as u32).unwrap(),
+            ch => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// An algebra that builds the dump of an expression.
+pub struct Dump;
+
+impl<E> Algebra<IntegerLiteral, E, DumpNode> for Dump {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> DumpNode
+    where
+        F: FnMut(&E) -> DumpNode,
+    {
+        DumpNode::new(INTEGER_LITERAL, vec![term.value.to_string()], vec![])
+    }
+}
+
+impl<E> Algebra<Add<E>, E, DumpNode> for Dump {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> DumpNode
+    where
+        F: FnMut(&E) -> DumpNode,
+    {
+        let children = vec![recurse(&term.lhs), recurse(&term.rhs)];
+        DumpNode::new(ADD, vec![], children)
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, DumpNode> for Dump {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> DumpNode
+    where
+        F: FnMut(&E) -> DumpNode,
+    {
+        let children = vec![recurse(&term.lhs), recurse(&term.rhs)];
+        DumpNode::new(MULTIPLY, vec![], children)
+    }
+}
+
+impl<E> Algebra<Pair<E>, E, DumpNode> for Dump {
+    fn apply<F>(&self, term: &Pair<E>, mut recurse: F) -> DumpNode
+    where
+        F: FnMut(&E) -> DumpNode,
+    {
+        let children = vec![recurse(&term.first), recurse(&term.second)];
+        DumpNode::new(PAIR, vec![], children)
+    }
+}
+
+impl<E> Algebra<First<E>, E, DumpNode> for Dump {
+    fn apply<F>(&self, term: &First<E>, mut recurse: F) -> DumpNode
+    where
+        F: FnMut(&E) -> DumpNode,
+    {
+        DumpNode::new(FIRST, vec![], vec![recurse(&term.pair)])
+    }
+}
+
+impl<E> Algebra<Second<E>, E, DumpNode> for Dump {
+    fn apply<F>(&self, term: &Second<E>, mut recurse: F) -> DumpNode
+    where
+        F: FnMut(&E) -> DumpNode,
+    {
+        DumpNode::new(SECOND, vec![], vec![recurse(&term.pair)])
+    }
+}
+
+/// Builds the dump of an expression, so that you can annotate it before rendering it.
+pub fn dump_tree<E>(expr: &E) -> DumpNode
+where
+    E: Expression,
+    Dump: Algebra<E::Signature, E, DumpNode>,
+{
+    mcata(&Dump, expr)
+}
+
+/// Dumps an expression in a specific version of the format.
+pub fn dump_version<E>(expr: &E, version: DumpVersion) -> String
+where
+    E: Expression,
+    Dump: Algebra<E::Signature, E, DumpNode>,
+{
+    dump_tree(expr).render(version)
+}
+
+/// Dumps an expression in the latest version of the format.
+pub fn dump<E>(expr: &E) -> String
+where
+    E: Expression,
+    Dump: Algebra<E::Signature, E, DumpNode>,
+{
+    dump_version(expr, DumpVersion::LATEST)
+}
+
+/// Computes the content hash of an expression.
+pub fn content_hash<E>(expr: &E) -> ContentHash
+where
+    E: Expression,
+    Dump: Algebra<E::Signature, E, DumpNode>,
+{
+    dump_tree(expr).content_hash()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    // These tests are the format's guarantee.  If one of them has to change, the format needs a
+    // new version instead.
+
+    #[test]
+    fn can_dump_expressions() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(-4),
+        );
+        assert_eq!(
+            dump(&expr),
+            "expression-dump 1\n\
+             add\n\
+             \x20 multiply\n\
+             \x20   integer_literal 80\n\
+             \x20   integer_literal 5\n\
+             \x20 integer_literal -4\n"
+        );
+    }
+
+    #[test]
+    fn can_dump_pairs() {
+        let expr: PairExpr = first(pair(integer_literal(7), integer_literal(6)));
+        assert_eq!(
+            dump(&expr),
+            "expression-dump 1\n\
+             first\n\
+             \x20 pair\n\
+             \x20   integer_literal 7\n\
+             \x20   integer_literal 6\n"
+        );
+    }
+
+    #[test]
+    fn can_dump_annotations() {
+        let expr: Expr = add(integer_literal(1), integer_literal(2));
+        let mut tree = dump_tree(&expr);
+        let rhs = tree.at_path_mut(&[1]).unwrap();
+        rhs.annotations.insert("unit".to_string(), "m".to_string());
+        rhs.annotations
+            .insert("note".to_string(), "say \"hi\"\n".to_string());
+        assert_eq!(
+            tree.render(DumpVersion::V1),
+            "expression-dump 1\n\
+             add\n\
+             \x20 integer_literal 1\n\
+             \x20 integer_literal 2 @note=\"say \\\"hi\\\"\\n\" @unit=\"m\"\n"
+        );
+    }
+
+    #[test]
+    fn content_hashes_are_stable() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(-4),
+        );
+        // The FNV-1a hash of:
+        //   expression-dump 1
+        //   add
+        //     integer_literal -4
+        //     multiply
+        //       integer_literal 5
+        //       integer_literal 80
+        assert_eq!(content_hash(&expr).to_string(), "bb34272b8d63a9ca");
+        assert_eq!(ContentHash::of(b"").0, 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn content_hashes_ignore_annotations_and_operand_order() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(-4),
+        );
+        let swapped: MultExpr = add(
+            integer_literal(-4),
+            multiply(integer_literal(5), integer_literal(80)),
+        );
+        assert_eq!(content_hash(&expr), content_hash(&swapped));
+
+        let mut annotated = dump_tree(&expr);
+        let lhs = annotated.at_path_mut(&[0]).unwrap();
+        lhs.annotations.insert("unit".to_string(), "m".to_string());
+        assert_eq!(annotated.content_hash(), content_hash(&expr));
+
+        // Pairs aren't commutative, and different values hash differently.
+        let pair_12: PairExpr = pair(integer_literal(1), integer_literal(2));
+        let pair_21: PairExpr = pair(integer_literal(2), integer_literal(1));
+        assert_ne!(content_hash(&pair_12), content_hash(&pair_21));
+        let other: MultExpr = add(integer_literal(1), integer_literal(2));
+        assert_ne!(content_hash(&expr), content_hash(&other));
+    }
+}
@@ -0,0 +1,333 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Effect inference: which side effects evaluating a subtree might perform.  Each kind of term
+//! declares the effects that it performs itself — reading a global or a cell reads memory, and
+//! calling a host function (or any function we can't see into) might do anything, so it counts as
+//! IO — and a subtree's effects are those of every term in it.  No term in this crate writes to
+//! memory yet, but the analysis has room for one.
+//!
+//! The result is an annotation pass: an `EffectTree` with the same shape as the expression, which
+//! an optimizer can consult by path to decide whether it's safe to reorder, merge, or drop
+//! subtrees.
+
+use crate::cells::*;
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
+use crate::ch07a_pairs::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::ch12a_booleans::Variable;
+use crate::ch12c_numeric_coercion::*;
+use crate::ch12d_extern_functions::*;
+use crate::ch12f_higher_order_functions::*;
+use crate::program::*;
+
+use std::fmt;
+
+/// A set of effects.  The empty set means that a subtree is pure.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Effects(u8);
+
+impl Effects {
+    pub const PURE: Effects = Effects(0);
+    pub const READS_MEMORY: Effects = Effects(1);
+    pub const WRITES_MEMORY: Effects = Effects(2);
+    pub const IO: Effects = Effects(4);
+
+    pub fn is_pure(self) -> bool {
+        self == Effects::PURE
+    }
+
+    pub fn contains(self, other: Effects) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether two computations with these effects can run in either order and give the same
+    /// result.  Reads can be reordered with reads, but not with writes, and IO can't be reordered
+    /// with other IO.
+    pub fn commutes_with(self, other: Effects) -> bool {
+        let conflicts = |a: Effects, b: Effects| {
+            a.contains(Effects::WRITES_MEMORY)
+                && (b.contains(Effects::READS_MEMORY) || b.contains(Effects::WRITES_MEMORY))
+        };
+        let both_io = self.contains(Effects::IO) && other.contains(Effects::IO);
+        !conflicts(self, other) && !conflicts(other, self) && !both_io
+    }
+}
+
+impl std::ops::BitOr for Effects {
+    type Output = Effects;
+    fn bitor(self, other: Effects) -> Effects {
+        Effects(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Effects {
+    fn bitor_assign(&mut self, other: Effects) {
+        self.0 |= other.0;
+    }
+}
+
+impl fmt::Display for Effects {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_pure() {
+            return f.write_str("pure");
+        }
+        let names = [
+            (Effects::READS_MEMORY, "reads"),
+            (Effects::WRITES_MEMORY, "writes"),
+            (Effects::IO, "io"),
+        ];
+        let present: Vec<&str> = names
+            .iter()
+            .filter(|(effect, _)| self.contains(*effect))
+            .map(|(_, name)| *name)
+            .collect();
+        f.write_str(&present.join("+"))
+    }
+}
+
+/// The effects of every subtree of an expression, in the same shape as the expression.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EffectTree {
+    /// The effects of this subtree as a whole, including all of its children.
+    pub effects: Effects,
+    pub children: Vec<EffectTree>,
+}
+
+impl EffectTree {
+    fn new(own: Effects, children: Vec<EffectTree>) -> EffectTree {
+        let effects = children
+            .iter()
+            .fold(own, |effects, child| effects | child.effects);
+        EffectTree { effects, children }
+    }
+
+    /// Returns the subtree at `path`, where each element is the index of a child.
+    pub fn at(&self, path: &[usize]) -> Option<&EffectTree> {
+        match path.split_first() {
+            None => Some(self),
+            Some((index, rest)) => self.children.get(*index)?.at(rest),
+        }
+    }
+
+    /// Whether the subtrees at two paths can be evaluated in either order.
+    pub fn can_reorder(&self, a: &[usize], b: &[usize]) -> Option<bool> {
+        Some(self.at(a)?.effects.commutes_with(self.at(b)?.effects))
+    }
+}
+
+/// The effects that a kind of term performs itself, not counting its subexpressions.
+pub trait TermEffects {
+    const EFFECTS: Effects;
+}
+
+/// An algebra that infers the effects of each subtree.
+pub struct InferEffects;
+
+macro_rules! leaf_effects {
+    ($($term:ty => $effects:expr),+ $(,)?) => {
+        $(
+            impl TermEffects for $term {
+                const EFFECTS: Effects = $effects;
+            }
+
+            impl<E> Algebra<$term, E, EffectTree> for InferEffects {
+                fn apply<F>(&self, _term: &$term, _recurse: F) -> EffectTree
+                where
+                    F: FnMut(&E) -> EffectTree,
+                {
+                    EffectTree::new(<$term>::EFFECTS, vec![])
+                }
+            }
+        )+
+    };
+}
+
+leaf_effects!(
+    IntegerLiteral => Effects::PURE,
+    FloatLiteral => Effects::PURE,
+    Global => Effects::READS_MEMORY,
+    CellRef => Effects::READS_MEMORY,
+    // A variable names a function's parameter, which nothing can write to.
+    Variable => Effects::PURE,
+);
+
+macro_rules! pure_effects {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<E> TermEffects for $term<E> {
+                const EFFECTS: Effects = Effects::PURE;
+            }
+
+            impl<E> Algebra<$term<E>, E, EffectTree> for InferEffects {
+                fn apply<F>(&self, term: &$term<E>, mut recurse: F) -> EffectTree
+                where
+                    F: FnMut(&E) -> EffectTree,
+                {
+                    EffectTree::new($term::<E>::EFFECTS, vec![$(recurse(&term.$field)),+])
+                }
+            }
+        )+
+    };
+}
+
+pure_effects!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Subtract { lhs, rhs },
+    Divide { lhs, rhs },
+    Modulo { lhs, rhs },
+    IntDiv { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+    IntToFloat { expr },
+    // Building a closure doesn't run its body.  But the body's effects are still part of the
+    // lambda's subtree, so an optimizer that moves a lambda around has to respect them.
+    Lambda { body },
+);
+
+// Like a host function, the function that an Apply calls might come from anywhere — a variable
+// doesn't tell us which lambda it's bound to — so we have to assume the worst about it, too.
+impl<E> TermEffects for Apply<E> {
+    const EFFECTS: Effects = Effects::IO;
+}
+
+impl<E> Algebra<Apply<E>, E, EffectTree> for InferEffects {
+    fn apply<F>(&self, term: &Apply<E>, mut recurse: F) -> EffectTree
+    where
+        F: FnMut(&E) -> EffectTree,
+    {
+        EffectTree::new(
+            Apply::<E>::EFFECTS,
+            vec![recurse(&term.function), recurse(&term.argument)],
+        )
+    }
+}
+
+// We can't see inside a host function, so we have to assume the worst about it.
+impl<E> TermEffects for Extern<E> {
+    const EFFECTS: Effects = Effects::IO;
+}
+
+impl<E> Algebra<Extern<E>, E, EffectTree> for InferEffects {
+    fn apply<F>(&self, term: &Extern<E>, recurse: F) -> EffectTree
+    where
+        F: FnMut(&E) -> EffectTree,
+    {
+        EffectTree::new(
+            Extern::<E>::EFFECTS,
+            term.args.iter().map(recurse).collect(),
+        )
+    }
+}
+
+/// Annotates every subtree of an expression with the effects it might perform.
+pub fn infer_effects<E>(expr: &E) -> EffectTree
+where
+    E: Expression,
+    InferEffects: Algebra<E::Signature, E, EffectTree>,
+{
+    mcata(&InferEffects, expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch12a_booleans::variable;
+
+    #[test]
+    fn arithmetic_is_pure() {
+        let expr: PairExpr = first(pair(
+            add(integer_literal(1), integer_literal(2)),
+            integer_literal(3),
+        ));
+        let tree = infer_effects(&expr);
+        assert!(tree.effects.is_pure());
+        assert_eq!(tree.can_reorder(&[0, 0], &[0, 1]), Some(true));
+    }
+
+    #[test]
+    fn effects_propagate_upwards() {
+        // (rate * 2) + 1
+        let expr: ProgramExpr = add(
+            multiply(global("rate"), integer_literal(2)),
+            integer_literal(1),
+        );
+        let tree = infer_effects(&expr);
+        assert_eq!(tree.effects, Effects::READS_MEMORY);
+        assert_eq!(tree.at(&[0]).unwrap().effects, Effects::READS_MEMORY);
+        assert!(tree.at(&[0, 1]).unwrap().effects.is_pure());
+        assert!(tree.at(&[1]).unwrap().effects.is_pure());
+        assert_eq!(tree.at(&[2]), None);
+        // Two reads can happen in either order.
+        assert_eq!(tree.can_reorder(&[0], &[1]), Some(true));
+    }
+
+    #[test]
+    fn host_calls_are_io() {
+        // now() + now() can't be swapped, but 1 + now() can
+        let expr: ExternExpr = add(
+            add(call_extern("now", vec![]), call_extern("now", vec![])),
+            add(integer_literal(1), call_extern("now", vec![])),
+        );
+        let tree = infer_effects(&expr);
+        assert_eq!(tree.effects.to_string(), "io");
+        assert_eq!(tree.can_reorder(&[0, 0], &[0, 1]), Some(false));
+        assert_eq!(tree.can_reorder(&[1, 0], &[1, 1]), Some(true));
+    }
+
+    #[test]
+    fn division_is_pure() {
+        let expr: ModExpr = modulo(
+            int_div(integer_literal(7), integer_literal(2)),
+            integer_literal(2),
+        );
+        assert!(infer_effects(&expr).effects.is_pure());
+    }
+
+    #[test]
+    fn calls_are_io() {
+        // (λx. x + 1)(2)
+        let expr: LambdaExpr = apply(
+            lambda("x", add(variable("x"), integer_literal(1))),
+            integer_literal(2),
+        );
+        let tree = infer_effects(&expr);
+        assert_eq!(tree.effects, Effects::IO);
+        assert!(tree.at(&[0]).unwrap().effects.is_pure());
+        assert!(tree.at(&[1]).unwrap().effects.is_pure());
+    }
+
+    #[test]
+    fn writes_conflict_with_reads() {
+        let reads = Effects::READS_MEMORY;
+        let writes = Effects::WRITES_MEMORY;
+        assert!(reads.commutes_with(reads));
+        assert!(!reads.commutes_with(writes));
+        assert!(!writes.commutes_with(writes));
+        assert!(writes.commutes_with(Effects::PURE));
+        assert_eq!(
+            (reads | writes | Effects::IO).to_string(),
+            "reads+writes+io"
+        );
+    }
+}
@@ -0,0 +1,613 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A semantic equivalence check.  Comparing rewrite `Node`s (perhaps after normalizing them with
+//! the rewrite engine) tells you when two expressions are *syntactically* the same, but misses
+//! plenty of expressions that mean the same thing: `x * (y + 1)` and `(x * y) + x` are equivalent,
+//! and no rule set we have would prove it.
+//!
+//! Instead, we can evaluate both expressions with random values for their variables (the program
+//! module's `Global`s), and see whether they ever disagree.  Agreeing on every trial doesn't prove
+//! that they're equivalent, but one disagreement proves that they aren't, and we report it.
+//!
+//! The evaluation wraps around on overflow.  Arithmetic that wraps is still a ring, so two
+//! equivalent expressions agree on every input even if their intermediate results overflow, and we
+//! can draw variable values from the whole i64 range.
+//!
+//! When there are few enough inputs, we can do better than "probably", and try every one of them.
+//! Expressions without variables only have one input, and small domains don't have many more.
+//! Boolean formulas have a better option still: we can build a binary decision diagram for each
+//! one, which is a canonical form, so two formulas are equivalent exactly when their diagrams are
+//! equal.  A proof comes with a `Certificate` saying how it was done.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch09a_mendler::*;
+use crate::ch12a_booleans::*;
+use crate::generator::Rng;
+use crate::program::*;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// An algebra that evaluates an expression, looking up its variables in an environment.  Variables
+/// that aren't in the environment are zero.
+pub struct InEnvironment<'a> {
+    pub env: &'a BTreeMap<String, i64>,
+}
+
+impl<'a, E> Algebra<Global, E, i64> for InEnvironment<'a> {
+    fn apply<F>(&self, term: &Global, _recurse: F) -> i64
+    where
+        F: FnMut(&E) -> i64,
+    {
+        self.env.get(&term.name).copied().unwrap_or(0)
+    }
+}
+
+impl<'a, E> Algebra<IntegerLiteral, E, i64> for InEnvironment<'a> {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> i64
+    where
+        F: FnMut(&E) -> i64,
+    {
+        term.value
+    }
+}
+
+impl<'a, E> Algebra<Add<E>, E, i64> for InEnvironment<'a> {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> i64
+    where
+        F: FnMut(&E) -> i64,
+    {
+        recurse(&term.lhs).wrapping_add(recurse(&term.rhs))
+    }
+}
+
+impl<'a, E> Algebra<Multiply<E>, E, i64> for InEnvironment<'a> {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> i64
+    where
+        F: FnMut(&E) -> i64,
+    {
+        recurse(&term.lhs).wrapping_mul(recurse(&term.rhs))
+    }
+}
+
+/// Builds a random environment that assigns a value to each of `names`.  Each trial of
+/// `probably_equivalent` uses its trial number as the seed, so you can rebuild the environment of
+/// any trial.
+pub fn random_environment(names: &BTreeSet<String>, seed: u64) -> BTreeMap<String, i64> {
+    let mut rng = Rng::new(seed);
+    (names.iter())
+        .map(|name| (name.clone(), rng.next_u64() as i64))
+        .collect()
+}
+
+/// An input on which two expressions disagree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Counterexample {
+    /// The trial that found the disagreement.  For `probably_equivalent`, that's also the seed of
+    /// its environment; for `prove_equivalent`, it's how many assignments came before it.
+    pub trial: u64,
+    pub env: BTreeMap<String, i64>,
+    pub lhs: i64,
+    pub rhs: i64,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "with ")?;
+        if self.env.is_empty() {
+            write!(f, "no variables")?;
+        }
+        for (index, (name, value)) in self.env.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} = {}", name, value)?;
+        }
+        write!(f, ", {} != {}", self.lhs, self.rhs)
+    }
+}
+
+/// Evaluates both expressions in `trials` random environments, and returns the first one where
+/// they disagree.  If they never do, they're probably equivalent.
+pub fn probably_equivalent<E>(a: &E, b: &E, trials: u64) -> Result<(), Counterexample>
+where
+    E: Expression,
+    Globals: Algebra<E::Signature, E, BTreeSet<String>>,
+    for<'a> InEnvironment<'a>: Algebra<E::Signature, E, i64>,
+{
+    let mut names = mcata(&Globals, a);
+    names.extend(mcata(&Globals, b));
+    for trial in 0..trials {
+        let env = random_environment(&names, trial);
+        let lhs = mcata(&InEnvironment { env: &env }, a);
+        let rhs = mcata(&InEnvironment { env: &env }, b);
+        if lhs != rhs {
+            return Err(Counterexample {
+                trial,
+                env,
+                lhs,
+                rhs,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// How an equivalence was proven.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Method {
+    /// Neither expression has any variables, so evaluating each of them once was enough.
+    Evaluation,
+    /// Every assignment of values from `domain` to the variables was tried.
+    Exhaustive {
+        domain: RangeInclusive<i64>,
+        assignments: u64,
+    },
+    /// Both formulas have the same binary decision diagram.
+    Bdd,
+}
+
+/// Evidence that two expressions are equal for every input in some domain.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Certificate {
+    /// The variables that the proof covers.
+    pub variables: BTreeSet<String>,
+    pub method: Method,
+}
+
+/// Why two expressions couldn't be proven equivalent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProofError {
+    /// They disagree on this input.
+    Counterexample(Counterexample),
+    /// There are more than `MAX_ASSIGNMENTS` ways to assign values to the variables.
+    TooManyAssignments {
+        variables: usize,
+        domain: RangeInclusive<i64>,
+    },
+    /// The domain is empty, so there is nothing to try the variables with.  Trying nothing would
+    /// "prove" any two expressions equivalent.
+    EmptyDomain { domain: RangeInclusive<i64> },
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProofError::Counterexample(counterexample) => write!(f, "{}", counterexample),
+            ProofError::TooManyAssignments { variables, domain } => write!(
+                f,
+                "too many ways to assign {} variables values in {}..={}",
+                variables,
+                domain.start(),
+                domain.end()
+            ),
+            ProofError::EmptyDomain { domain } => write!(
+                f,
+                "no values to assign variables in {}..={}",
+                domain.start(),
+                domain.end()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+/// The most assignments that `prove_equivalent` will try.
+pub const MAX_ASSIGNMENTS: u64 = 1 << 20;
+
+/// Proves that two expressions are equal for every assignment of values from `domain` to their
+/// variables, by trying all of them.
+pub fn prove_equivalent<E>(
+    a: &E,
+    b: &E,
+    domain: RangeInclusive<i64>,
+) -> Result<Certificate, ProofError>
+where
+    E: Expression,
+    Globals: Algebra<E::Signature, E, BTreeSet<String>>,
+    for<'a> InEnvironment<'a>: Algebra<E::Signature, E, i64>,
+{
+    let mut variables = mcata(&Globals, a);
+    variables.extend(mcata(&Globals, b));
+    if !variables.is_empty() && domain.is_empty() {
+        return Err(ProofError::EmptyDomain { domain });
+    }
+    let width = (*domain.end() as i128 - *domain.start() as i128 + 1).max(0);
+    let assignments = (u64::try_from(width).ok())
+        .zip(u32::try_from(variables.len()).ok())
+        .and_then(|(width, count)| width.checked_pow(count))
+        .filter(|assignments| *assignments <= MAX_ASSIGNMENTS)
+        .ok_or_else(|| ProofError::TooManyAssignments {
+            variables: variables.len(),
+            domain: domain.clone(),
+        })?;
+    // Count through the assignments like an odometer, with the first variable changing fastest.
+    let mut env: BTreeMap<String, i64> = (variables.iter())
+        .map(|name| (name.clone(), *domain.start()))
+        .collect();
+    for trial in 0..assignments {
+        let lhs = mcata(&InEnvironment { env: &env }, a);
+        let rhs = mcata(&InEnvironment { env: &env }, b);
+        if lhs != rhs {
+            return Err(ProofError::Counterexample(Counterexample {
+                trial,
+                env,
+                lhs,
+                rhs,
+            }));
+        }
+        for value in env.values_mut() {
+            if *value < *domain.end() {
+                *value += 1;
+                break;
+            }
+            *value = *domain.start();
+        }
+    }
+    let method = if variables.is_empty() {
+        Method::Evaluation
+    } else {
+        Method::Exhaustive {
+            domain,
+            assignments,
+        }
+    };
+    Ok(Certificate { variables, method })
+}
+
+// A binary decision diagram is a value type for ch12a's formulas, just like ch12b's Formula is:
+// its operators build a new diagram out of their operands, so we get the diagram for a formula by
+// evaluating it.  Variables are always tested in order of their names, and a test whose branches
+// are the same is left out, which is what makes the diagrams canonical.  We don't bother sharing
+// equal subdiagrams, which real BDD libraries do, since our formulas are small.
+
+/// A reduced, ordered binary decision diagram.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Bdd {
+    Constant(bool),
+    /// Tests a variable, and continues with `low` if it's false, and `high` if it's true.
+    Test {
+        variable: String,
+        low: Rc<Bdd>,
+        high: Rc<Bdd>,
+    },
+}
+
+impl Bdd {
+    fn test(variable: String, low: Bdd, high: Bdd) -> Bdd {
+        if low == high {
+            return low;
+        }
+        Bdd::Test {
+            variable,
+            low: Rc::new(low),
+            high: Rc::new(high),
+        }
+    }
+
+    fn variable(&self) -> Option<&str> {
+        match self {
+            Bdd::Constant(_) => None,
+            Bdd::Test { variable, .. } => Some(variable),
+        }
+    }
+
+    /// The diagrams for when `variable` is false, and when it's true.  Since variables are tested
+    /// in order, `variable` can only be tested at the root.
+    fn cofactors(&self, variable: &str) -> (Bdd, Bdd) {
+        match self {
+            Bdd::Test {
+                variable: v,
+                low,
+                high,
+            } if v == variable => (low.as_ref().clone(), high.as_ref().clone()),
+            _ => (self.clone(), self.clone()),
+        }
+    }
+
+    fn apply(&self, other: &Bdd, op: fn(bool, bool) -> bool) -> Bdd {
+        let variable = match (self.variable(), other.variable()) {
+            (None, None) => match (self, other) {
+                (Bdd::Constant(lhs), Bdd::Constant(rhs)) => return Bdd::Constant(op(*lhs, *rhs)),
+                _ => unreachable!(),
+            },
+            (Some(variable), None) | (None, Some(variable)) => variable,
+            (Some(lhs), Some(rhs)) => lhs.min(rhs),
+        }
+        .to_string();
+        let (self_low, self_high) = self.cofactors(&variable);
+        let (other_low, other_high) = other.cofactors(&variable);
+        let low = self_low.apply(&other_low, op);
+        let high = self_high.apply(&other_high, op);
+        Bdd::test(variable, low, high)
+    }
+
+    /// The variables that the diagram tests.
+    pub fn variables(&self) -> BTreeSet<String> {
+        match self {
+            Bdd::Constant(_) => BTreeSet::new(),
+            Bdd::Test {
+                variable,
+                low,
+                high,
+            } => {
+                let mut result = low.variables();
+                result.extend(high.variables());
+                result.insert(variable.clone());
+                result
+            }
+        }
+    }
+
+    /// An assignment that makes the formula true, if there is one.  Variables that aren't in the
+    /// assignment don't matter.
+    pub fn satisfying_assignment(&self) -> Option<BTreeMap<String, bool>> {
+        match self {
+            Bdd::Constant(value) => value.then(BTreeMap::new),
+            Bdd::Test {
+                variable,
+                low,
+                high,
+            } => {
+                let (value, mut assignment) = match high.satisfying_assignment() {
+                    Some(assignment) => (true, assignment),
+                    None => (false, low.satisfying_assignment()?),
+                };
+                assignment.insert(variable.clone(), value);
+                Some(assignment)
+            }
+        }
+    }
+}
+
+impl From<bool> for Bdd {
+    fn from(value: bool) -> Bdd {
+        Bdd::Constant(value)
+    }
+}
+
+impl FromVariable for Bdd {
+    fn from_variable(name: &str) -> Bdd {
+        Bdd::test(name.to_string(), Bdd::Constant(false), Bdd::Constant(true))
+    }
+}
+
+impl std::ops::BitAnd for Bdd {
+    type Output = Bdd;
+    fn bitand(self, other: Bdd) -> Bdd {
+        self.apply(&other, |lhs, rhs| lhs & rhs)
+    }
+}
+
+impl std::ops::BitOr for Bdd {
+    type Output = Bdd;
+    fn bitor(self, other: Bdd) -> Bdd {
+        self.apply(&other, |lhs, rhs| lhs | rhs)
+    }
+}
+
+impl std::ops::Not for Bdd {
+    type Output = Bdd;
+    fn not(self) -> Bdd {
+        self.apply(&Bdd::Constant(true), |lhs, _| !lhs)
+    }
+}
+
+/// Proves that two boolean formulas are equal for every assignment to their variables, by
+/// comparing their decision diagrams.  If they aren't, returns an assignment that they disagree
+/// on.
+pub fn prove_formulas_equivalent<E>(a: &E, b: &E) -> Result<Certificate, BTreeMap<String, bool>>
+where
+    E: Eval<Bdd, E>,
+{
+    let lhs: Bdd = a.evaluate();
+    let rhs: Bdd = b.evaluate();
+    if lhs != rhs {
+        let difference = lhs.apply(&rhs, |lhs, rhs| lhs != rhs);
+        return Err(difference
+            .satisfying_assignment()
+            .expect("different diagrams disagree somewhere"));
+    }
+    Ok(Certificate {
+        variables: lhs.variables(),
+        method: Method::Bdd,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::obfuscate::*;
+
+    fn a() -> BoolExpr {
+        variable("a")
+    }
+
+    fn b() -> BoolExpr {
+        variable("b")
+    }
+
+    fn x() -> ProgramExpr {
+        global("x")
+    }
+
+    fn y() -> ProgramExpr {
+        global("y")
+    }
+
+    #[test]
+    fn can_recognize_equivalent_expressions() {
+        let factored: ProgramExpr = multiply(x(), add(y(), integer_literal(1)));
+        let expanded: ProgramExpr = add(multiply(x(), y()), x());
+        assert_eq!(probably_equivalent(&factored, &expanded, 100), Ok(()));
+    }
+
+    #[test]
+    fn reports_the_first_distinguishing_input() {
+        let square: ProgramExpr = multiply(add(x(), y()), add(x(), y()));
+        let wrong: ProgramExpr = add(multiply(x(), x()), multiply(y(), y()));
+        let counterexample = probably_equivalent(&square, &wrong, 100).unwrap_err();
+        assert_eq!(counterexample.trial, 0);
+        let names = ["x", "y"].iter().map(|name| name.to_string()).collect();
+        assert_eq!(counterexample.env, random_environment(&names, 0));
+        let env = &counterexample.env;
+        assert_eq!(counterexample.lhs, mcata(&InEnvironment { env }, &square));
+        assert_eq!(counterexample.rhs, mcata(&InEnvironment { env }, &wrong));
+    }
+
+    #[test]
+    fn expressions_with_different_variables_can_differ() {
+        let lhs: ProgramExpr = add(x(), integer_literal(0));
+        let rhs: ProgramExpr = add(y(), integer_literal(0));
+        assert!(probably_equivalent(&lhs, &rhs, 10).is_err());
+        assert_eq!(probably_equivalent(&lhs, &x(), 10), Ok(()));
+    }
+
+    #[test]
+    fn can_describe_counterexamples() {
+        let counterexample =
+            probably_equivalent::<ProgramExpr>(&integer_literal(1), &integer_literal(2), 1)
+                .unwrap_err();
+        assert_eq!(counterexample.to_string(), "with no variables, 1 != 2");
+    }
+
+    #[test]
+    fn obfuscated_expressions_are_equivalent() {
+        let original: MultExpr = add(
+            multiply(integer_literal(6), integer_literal(7)),
+            integer_literal(-5),
+        );
+        for seed in 0..10 {
+            let config = ObfuscatorConfig {
+                seed,
+                ..ObfuscatorConfig::default()
+            };
+            let obfuscated = obfuscate(&original, &config);
+            assert_eq!(probably_equivalent(&original, &obfuscated, 1), Ok(()));
+        }
+    }
+
+    #[test]
+    fn can_prove_variable_free_expressions_equivalent() {
+        let original: MultExpr = multiply(integer_literal(6), integer_literal(7));
+        let obfuscated = obfuscate(&original, &ObfuscatorConfig::default());
+        let certificate = prove_equivalent(&original, &obfuscated, 0..=0).unwrap();
+        assert_eq!(certificate.method, Method::Evaluation);
+        assert!(certificate.variables.is_empty());
+    }
+
+    #[test]
+    fn can_prove_equivalence_over_a_small_domain() {
+        let factored: ProgramExpr = multiply(x(), add(y(), integer_literal(1)));
+        let expanded: ProgramExpr = add(multiply(x(), y()), x());
+        let certificate = prove_equivalent(&factored, &expanded, -3..=3).unwrap();
+        assert_eq!(
+            certificate.method,
+            Method::Exhaustive {
+                domain: -3..=3,
+                assignments: 49,
+            }
+        );
+        assert_eq!(certificate.variables.len(), 2);
+    }
+
+    #[test]
+    fn exhaustive_proofs_find_counterexamples() {
+        let square: ProgramExpr = multiply(add(x(), y()), add(x(), y()));
+        let wrong: ProgramExpr = add(multiply(x(), x()), multiply(y(), y()));
+        // These agree whenever x or y is 0.
+        let error = prove_equivalent(&square, &wrong, 0..=1).unwrap_err();
+        let expected = Counterexample {
+            trial: 3,
+            env: vec![("x".to_string(), 1), ("y".to_string(), 1)]
+                .into_iter()
+                .collect(),
+            lhs: 4,
+            rhs: 2,
+        };
+        assert_eq!(error, ProofError::Counterexample(expected));
+        assert_eq!(error.to_string(), "with x = 1, y = 1, 4 != 2");
+    }
+
+    #[test]
+    fn rejects_empty_domains() {
+        let lhs: ProgramExpr = x();
+        let rhs: ProgramExpr = add(x(), integer_literal(1));
+        let empty = RangeInclusive::new(5, 4);
+        assert_eq!(
+            prove_equivalent(&lhs, &rhs, empty.clone()),
+            Err(ProofError::EmptyDomain {
+                domain: empty.clone()
+            })
+        );
+        // Without any variables, the domain doesn't matter.
+        let one: ProgramExpr = integer_literal(1);
+        assert!(prove_equivalent(&one, &one, empty).is_ok());
+    }
+
+    #[test]
+    fn refuses_to_try_too_many_assignments() {
+        let lhs: ProgramExpr = add(x(), y());
+        let rhs: ProgramExpr = add(y(), x());
+        assert_eq!(
+            prove_equivalent(&lhs, &rhs, i64::MIN..=i64::MAX),
+            Err(ProofError::TooManyAssignments {
+                variables: 2,
+                domain: i64::MIN..=i64::MAX,
+            })
+        );
+        assert!(prove_equivalent(&lhs, &rhs, 0..=1023).is_ok());
+        assert!(prove_equivalent(&lhs, &rhs, 0..=1024).is_err());
+    }
+
+    #[test]
+    fn can_prove_formulas_equivalent() {
+        // De Morgan: ¬(a ∧ b) = ¬a ∨ ¬b
+        let lhs: BoolExpr = not(and(a(), b()));
+        let rhs: BoolExpr = or(not(a()), not(b()));
+        let certificate = prove_formulas_equivalent(&lhs, &rhs).unwrap();
+        assert_eq!(certificate.method, Method::Bdd);
+        assert_eq!(certificate.variables.len(), 2);
+        // Absorption: a ∨ (a ∧ b) = a, which doesn't depend on b at all.
+        let lhs: BoolExpr = or(a(), and(a(), b()));
+        let certificate = prove_formulas_equivalent(&lhs, &a()).unwrap();
+        assert_eq!(
+            certificate.variables,
+            std::iter::once("a".to_string()).collect()
+        );
+    }
+
+    #[test]
+    fn bdds_find_distinguishing_assignments() {
+        let lhs: BoolExpr = and(a(), b());
+        let rhs: BoolExpr = or(a(), b());
+        let assignment = prove_formulas_equivalent(&lhs, &rhs).unwrap_err();
+        let expected = vec![("a".to_string(), true), ("b".to_string(), false)];
+        assert_eq!(assignment, expected.into_iter().collect());
+        let lhs: BoolExpr = bool_literal(true);
+        let rhs: BoolExpr = or(a(), not(a()));
+        assert!(prove_formulas_equivalent(&lhs, &rhs).is_ok());
+    }
+}
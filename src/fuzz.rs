@@ -0,0 +1,406 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Entry points for fuzzing.  A fuzzer hands us a slice of arbitrary bytes; we turn those bytes
+//! into an expression, and then check properties that should hold for *every* expression.  A
+//! `cargo fuzz` target just has to forward its input to one of the `check_*` functions here.
+//!
+//! The `Arbitrary` trait follows the shape of the one in the `arbitrary` crate, but without the
+//! dependency: each term and signature knows how to build itself out of an `Unstructured` stream
+//! of bytes.  Running out of bytes is never an error.  Every type can always build *something*,
+//! because a sum will pick a non-recursive variant once the input (or the depth budget) runs out.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::ch08e_sugar::*;
+use crate::ch09a_mendler::*;
+use crate::ch10a_dynamic_terms::*;
+use crate::ch10b_plugin_registry::*;
+use crate::parse::*;
+
+use std::fmt;
+
+/// A stream of fuzzer-provided bytes.
+pub struct Unstructured<'a> {
+    data: &'a [u8],
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Unstructured<'a> {
+    pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+    pub fn new(data: &'a [u8]) -> Unstructured<'a> {
+        Unstructured {
+            data,
+            depth: 0,
+            max_depth: Unstructured::DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// Returns the next byte, or 0 once the input has run out.
+    pub fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((byte, rest)) => {
+                self.data = rest;
+                *byte
+            }
+            None => 0,
+        }
+    }
+
+    /// Returns whether we should stop building recursive terms, either because the input has run
+    /// out, or because we're nested as deeply as we're allowed to be.
+    pub fn should_stop(&self) -> bool {
+        self.data.is_empty() || self.depth >= self.max_depth
+    }
+
+    /// Builds a subexpression, one level deeper than the current one.
+    pub fn nested<T: Arbitrary>(&mut self) -> T {
+        self.depth += 1;
+        let result = T::arbitrary(self);
+        self.depth -= 1;
+        result
+    }
+}
+
+/// A type that can be built out of fuzzer-provided bytes.
+pub trait Arbitrary: Sized {
+    /// Whether this type can be built without building any subexpressions.
+    const IS_LEAF: bool;
+    fn arbitrary(u: &mut Unstructured) -> Self;
+}
+
+/// Literals are small, so that arithmetic on them is interesting without overflowing straight
+/// away.
+impl Arbitrary for IntegerLiteral {
+    const IS_LEAF: bool = true;
+    fn arbitrary(u: &mut Unstructured) -> IntegerLiteral {
+        IntegerLiteral {
+            value: i64::from(u.byte() as i8),
+        }
+    }
+}
+
+macro_rules! arbitrary_binary_terms {
+    ($($term:ident { $lhs:ident, $rhs:ident }),+ $(,)?) => {
+        $(
+            impl<E: Arbitrary> Arbitrary for $term<E> {
+                const IS_LEAF: bool = false;
+                fn arbitrary(u: &mut Unstructured) -> $term<E> {
+                    $term {
+                        $lhs: u.nested(),
+                        $rhs: u.nested(),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+arbitrary_binary_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Pair { first, second }
+);
+
+impl<E: Arbitrary> Arbitrary for First<E> {
+    const IS_LEAF: bool = false;
+    fn arbitrary(u: &mut Unstructured) -> First<E> {
+        First { pair: u.nested() }
+    }
+}
+
+impl<E: Arbitrary> Arbitrary for Second<E> {
+    const IS_LEAF: bool = false;
+    fn arbitrary(u: &mut Unstructured) -> Second<E> {
+        Second { pair: u.nested() }
+    }
+}
+
+impl<E: Arbitrary> Arbitrary for Negate<E> {
+    const IS_LEAF: bool = false;
+    fn arbitrary(u: &mut Unstructured) -> Negate<E> {
+        Negate { value: u.nested() }
+    }
+}
+
+/// A sum uses the next byte to choose a variant, until it's time to stop, when it chooses a variant
+/// that can be a leaf.
+impl<L: Arbitrary, R: Arbitrary> Arbitrary for Sum<L, R> {
+    const IS_LEAF: bool = L::IS_LEAF || R::IS_LEAF;
+    fn arbitrary(u: &mut Unstructured) -> Sum<L, R> {
+        let left = if u.should_stop() {
+            L::IS_LEAF
+        } else {
+            u.byte() & 1 == 0
+        };
+        if left {
+            Sum::Left(L::arbitrary(u))
+        } else {
+            Sum::Right(R::arbitrary(u))
+        }
+    }
+}
+
+// Like EvaluateInt, each expression type needs its own impl; a blanket impl for every Expression
+// would have recursive bounds that the compiler can never prove.
+
+macro_rules! arbitrary_expressions {
+    ($($expr:ident),+ $(,)?) => {
+        $(
+            impl Arbitrary for $expr {
+                const IS_LEAF: bool = <$expr as Expression>::Signature::IS_LEAF;
+                fn arbitrary(u: &mut Unstructured) -> $expr {
+                    $expr::wrap(Arbitrary::arbitrary(u))
+                }
+            }
+        )+
+    };
+}
+
+arbitrary_expressions!(Expr, MultExpr, NoAddExpr, NegateExpr, PairExpr);
+
+// Arithmetic on fuzzer-chosen values overflows all the time, and an overflow panic isn't the kind
+// of bug that we're looking for.  So the harnesses evaluate into a value type that wraps around.
+
+/// An integer whose arithmetic wraps around on overflow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WrappingInt(pub i64);
+
+impl From<i64> for WrappingInt {
+    fn from(value: i64) -> WrappingInt {
+        WrappingInt(value)
+    }
+}
+
+impl std::ops::Add for WrappingInt {
+    type Output = WrappingInt;
+    fn add(self, other: WrappingInt) -> WrappingInt {
+        WrappingInt(self.0.wrapping_add(other.0))
+    }
+}
+
+impl std::ops::Mul for WrappingInt {
+    type Output = WrappingInt;
+    fn mul(self, other: WrappingInt) -> WrappingInt {
+        WrappingInt(self.0.wrapping_mul(other.0))
+    }
+}
+
+/// An algebra that prints an expression in the s-expression syntax that ch10b's registry parses.
+pub struct SExpr;
+
+impl<E> Algebra<IntegerLiteral, E, String> for SExpr {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        term.value.to_string()
+    }
+}
+
+impl<E> Algebra<Add<E>, E, String> for SExpr {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        format!("(add {} {})", recurse(&term.lhs), recurse(&term.rhs))
+    }
+}
+
+impl<E> Algebra<Multiply<E>, E, String> for SExpr {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> String
+    where
+        F: FnMut(&E) -> String,
+    {
+        format!("(multiply {} {})", recurse(&term.lhs), recurse(&term.rhs))
+    }
+}
+
+/// Builds an arbitrary expression out of fuzzer input.
+pub fn arbitrary_expr<E: Arbitrary>(data: &[u8]) -> E {
+    E::arbitrary(&mut Unstructured::new(data))
+}
+
+/// Parses the input, prints whatever it parsed, and checks that parsing the printed form gives back
+/// the same expression.  Input that isn't valid UTF-8, or that doesn't parse, is skipped.
+pub fn check_parse_print_parse(data: &[u8]) {
+    let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+    let input = match std::str::from_utf8(data) {
+        Ok(input) => input,
+        Err(_) => return,
+    };
+    let parsed = match registry.parse(input) {
+        Ok(parsed) => parsed,
+        Err(_) => return,
+    };
+    let expr: MultExpr = from_dyn(&parsed).expect("parser produced an unknown term");
+    let printed = mcata(&SExpr, &expr);
+    let reparsed = registry
+        .parse(&printed)
+        .unwrap_or_else(|error| panic!("cannot reparse {:?}: {}", printed, error));
+    let reexpr: MultExpr = from_dyn(&reparsed).expect("parser produced an unknown term");
+    assert_eq!(printed, mcata(&SExpr, &reexpr));
+}
+
+/// Builds an expression, prints it, and checks that the printed form parses back into the same
+/// expression.
+pub fn check_print_parse(data: &[u8]) {
+    let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+    let expr: MultExpr = arbitrary_expr(data);
+    let printed = mcata(&SExpr, &expr);
+    let parsed = registry
+        .parse(&printed)
+        .unwrap_or_else(|error| panic!("cannot parse {:?}: {}", printed, error));
+    let reexpr: MultExpr = from_dyn(&parsed).expect("parser produced an unknown term");
+    assert_eq!(printed, mcata(&SExpr, &reexpr));
+}
+
+/// Prints an expression in the infix syntax, parses it back in, and checks that the result prints
+/// the same way.  The open-sum expression types can't be compared directly, so the printed form is
+/// what we compare.  (It's also the only thing that can be preserved: a negated literal and a
+/// negative one print the same way, so they can't both survive a round trip.)
+pub fn infix_round_trip<E>(expr: &E) -> E
+where
+    E: Expression + fmt::Display,
+    E::Signature: ParseableSignature<E>,
+{
+    let printed = expr.to_string();
+    let parsed: E =
+        parse(&printed).unwrap_or_else(|error| panic!("cannot parse {:?}: {}", printed, error));
+    assert_eq!(printed, parsed.to_string());
+    parsed
+}
+
+/// Builds an expression, and checks that its infix form parses back into the same expression.
+pub fn check_infix_round_trip<E>(data: &[u8])
+where
+    E: Arbitrary + Expression + fmt::Display,
+    E::Signature: ParseableSignature<E>,
+{
+    let expr: E = arbitrary_expr(data);
+    infix_round_trip(&expr);
+}
+
+/// Builds an expression, and checks that evaluating it twice gives the same answer.
+pub fn check_eval_twice(data: &[u8]) {
+    let expr: MultExpr = arbitrary_expr(data);
+    let first: WrappingInt = mcata(&Evaluator, &expr);
+    let second: WrappingInt = mcata(&Evaluator, &expr);
+    assert_eq!(first, second);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch03_evaluation::EvaluateInt;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+    use crate::ch09d_owned_fold::*;
+    use crate::generator::Rng;
+    use crate::vm::*;
+
+    fn inputs() -> Vec<Vec<u8>> {
+        let mut rng = Rng::new(0x5eed);
+        let mut inputs = vec![vec![], vec![0; 16], vec![255; 16]];
+        for length in 1..64 {
+            inputs.push((0..length).map(|_| rng.below(256) as u8).collect());
+        }
+        inputs
+    }
+
+    #[test]
+    fn always_builds_an_expression() {
+        for input in inputs() {
+            let _: Expr = arbitrary_expr(&input);
+            let _: MultExpr = arbitrary_expr(&input);
+            let _: NoAddExpr = arbitrary_expr(&input);
+            let _: NegateExpr = arbitrary_expr(&input);
+            let _: PairExpr = arbitrary_expr(&input);
+        }
+    }
+
+    #[test]
+    fn respects_the_depth_limit() {
+        let input = vec![1; 10_000];
+        let mut u = Unstructured::new(&input);
+        let expr = MultExpr::arbitrary(&mut u);
+        let depth: usize = mcata(&Depth, &expr);
+        assert!(depth <= Unstructured::DEFAULT_MAX_DEPTH + 1);
+    }
+
+    #[test]
+    fn harnesses_accept_random_inputs() {
+        for input in inputs() {
+            check_print_parse(&input);
+            check_eval_twice(&input);
+            check_parse_print_parse(&input);
+            check_infix_round_trip::<Expr>(&input);
+            check_infix_round_trip::<MultExpr>(&input);
+            check_infix_round_trip::<NegateExpr>(&input);
+        }
+        check_parse_print_parse(b"(add (multiply 80 5) -4)");
+    }
+
+    // Every evaluator that can handle a MultExpr.  The other expression types get here by widening
+    // or desugaring.  The VM stops on overflow instead of wrapping, so it only gets a say when the
+    // result didn't overflow.
+    fn evaluate_everywhere(expr: &MultExpr) -> Vec<i64> {
+        let open: WrappingInt = Evaluate::evaluate(expr);
+        let mendler: WrappingInt = mcata(&Evaluator, expr);
+        let mut values = vec![open.0, mendler.0];
+        match Vm::new().run(&compile(expr)) {
+            Ok(metered) => values.push(metered.value),
+            Err(error) => assert!(matches!(error, VmError::Overflow { .. })),
+        }
+        values
+    }
+
+    fn assert_same_evaluations(expr: &MultExpr, parsed: &MultExpr) {
+        let expected = evaluate_everywhere(expr);
+        assert!(expected.iter().all(|value| *value == expected[0]));
+        assert_eq!(expected, evaluate_everywhere(parsed));
+    }
+
+    #[test]
+    fn infix_round_trips_preserve_evaluation() {
+        for input in inputs() {
+            let expr: Expr = arbitrary_expr(&input);
+            let parsed = infix_round_trip(&expr);
+            assert_eq!(EvaluateInt::evaluate(&expr), EvaluateInt::evaluate(&parsed));
+            let widened: MultExpr = widen(expr);
+            assert_same_evaluations(&widened, &widen(parsed));
+
+            let expr: MultExpr = arbitrary_expr(&input);
+            let parsed = infix_round_trip(&expr);
+            assert_same_evaluations(&expr, &parsed);
+
+            let expr: NegateExpr = arbitrary_expr(&input);
+            let parsed = infix_round_trip(&expr);
+            assert_same_evaluations(&expr.desugar(), &parsed.desugar());
+        }
+    }
+
+    #[test]
+    fn negated_literals_round_trip() {
+        let expr: NegateExpr = negate(integer_literal(0));
+        assert_eq!(infix_round_trip(&expr).to_string(), "-0");
+        let expr: NegateExpr = negate(negate(integer_literal(-3)));
+        assert_eq!(infix_round_trip(&expr).to_string(), "---3");
+    }
+}
@@ -0,0 +1,242 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Generates large, random expressions to use as benchmark workloads.  The `workload` function in
+//! the conformance module always builds the same perfectly balanced tree, which isn't much like the
+//! expressions that show up in practice.  This generator builds lopsided trees, with a configurable
+//! mix of terms, and a target size.
+//!
+//! The generator is seeded, and doesn't depend on anything outside of the standard library, so the
+//! same configuration always builds the same expression — in every `Language`.
+
+use crate::conformance::*;
+
+use std::convert::TryFrom;
+
+/// A small, fast pseudo-random number generator (SplitMix64).  It's nowhere near good enough for
+/// cryptography, but it's reproducible, which is what we want for benchmarks.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in the range `0..bound`.  `bound` must not be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Returns a number in the range `min..=max`.  Panics if `min` is greater than `max`.
+    pub fn between(&mut self, min: i64, max: i64) -> i64 {
+        assert!(min <= max, "Empty range {}..={}", min, max);
+        // The full range of i64 is one wider than a u64 can count, but every u64 is in it.
+        match u64::try_from(max as i128 - min as i128 + 1) {
+            Ok(width) => (min as i128 + self.below(width) as i128) as i64,
+            Err(_) => self.next_u64() as i64,
+        }
+    }
+}
+
+/// How often each kind of operator should appear, relative to the others.  A weight of zero means
+/// that the operator never appears.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermWeights {
+    pub add: u32,
+    pub multiply: u32,
+}
+
+impl Default for TermWeights {
+    fn default() -> TermWeights {
+        TermWeights {
+            add: 3,
+            multiply: 1,
+        }
+    }
+}
+
+/// Describes the expressions that the generator should build.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub weights: TermWeights,
+    /// The number of nodes (terms) in the generated expression.  Every operator is binary, so a
+    /// tree always has an odd number of nodes; an even target is rounded down.
+    pub target_nodes: usize,
+    /// The range of values for integer literals.  Keep this small if you're going to evaluate the
+    /// expression in a debug build, since large products will overflow.
+    pub literal_range: (i64, i64),
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> GeneratorConfig {
+        GeneratorConfig {
+            seed: 0,
+            weights: TermWeights::default(),
+            target_nodes: 1023,
+            literal_range: (0, 9),
+        }
+    }
+}
+
+/// Builds a random expression in any language.
+pub fn generate<L: Language>(config: &GeneratorConfig) -> L::Expr {
+    let total = config.weights.add + config.weights.multiply;
+    assert!(
+        total > 0,
+        "At least one operator must have a nonzero weight"
+    );
+    let mut rng = Rng::new(config.seed);
+    let nodes = config.target_nodes.max(1);
+    generate_nodes::<L>(config, total, &mut rng, nodes)
+}
+
+fn generate_nodes<L: Language>(
+    config: &GeneratorConfig,
+    total: u32,
+    rng: &mut Rng,
+    nodes: usize,
+) -> L::Expr {
+    if nodes < 3 {
+        let (min, max) = config.literal_range;
+        return L::integer_literal(rng.between(min, max));
+    }
+    // Split the remaining nodes between the two operands.  Both halves have to be odd, so that
+    // each of them can be a complete tree.
+    let children = (nodes - 1) & !1;
+    let lhs_nodes = 2 * rng.below((children / 2) as u64) as usize + 1;
+    let rhs_nodes = children - lhs_nodes;
+    let choice = rng.below(u64::from(total)) as u32;
+    let lhs = generate_nodes::<L>(config, total, rng, lhs_nodes);
+    let rhs = generate_nodes::<L>(config, total, rng, rhs_nodes);
+    if choice < config.weights.add {
+        L::add(lhs, rhs)
+    } else {
+        L::multiply(lhs, rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_nodes(printed: &str) -> usize {
+        // Every operator is printed with a pair of parentheses, and every literal is a run of
+        // digits.
+        let operators = printed.matches('(').count();
+        let literals = printed
+            .split(|ch: char| !ch.is_ascii_digit())
+            .filter(|token| !token.is_empty())
+            .count();
+        operators + literals
+    }
+
+    #[test]
+    fn builds_the_target_number_of_nodes() {
+        for &target_nodes in &[1, 2, 3, 31, 100, 1023] {
+            let config = GeneratorConfig {
+                target_nodes,
+                ..GeneratorConfig::default()
+            };
+            let expr = generate::<EvaluateIntLanguage>(&config);
+            let printed = EvaluateIntLanguage::print(&expr);
+            let expected = if target_nodes % 2 == 0 {
+                target_nodes - 1
+            } else {
+                target_nodes
+            };
+            assert_eq!(count_nodes(&printed), expected, "{}", printed);
+        }
+    }
+
+    #[test]
+    fn can_pick_from_any_range() {
+        let mut rng = Rng::new(7);
+        for _ in 0..100 {
+            let value = rng.between(-3, 3);
+            assert!((-3..=3).contains(&value));
+        }
+        assert_eq!(rng.between(5, 5), 5);
+        let values = (0..100)
+            .map(|_| rng.between(i64::MIN, i64::MAX))
+            .collect::<Vec<_>>();
+        assert!(values.iter().any(|value| *value < 0));
+        assert!(values.iter().any(|value| *value > 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Empty range")]
+    fn rejects_empty_ranges() {
+        Rng::new(0).between(1, 0);
+    }
+
+    #[test]
+    fn is_reproducible() {
+        let config = GeneratorConfig {
+            seed: 42,
+            target_nodes: 63,
+            ..GeneratorConfig::default()
+        };
+        let first = EvaluateIntLanguage::print(&generate::<EvaluateIntLanguage>(&config));
+        let second = EvaluateIntLanguage::print(&generate::<EvaluateIntLanguage>(&config));
+        assert_eq!(first, second);
+        let other = GeneratorConfig { seed: 43, ..config };
+        let third = EvaluateIntLanguage::print(&generate::<EvaluateIntLanguage>(&other));
+        assert_ne!(first, third);
+    }
+
+    #[test]
+    fn respects_weights() {
+        let config = GeneratorConfig {
+            weights: TermWeights {
+                add: 1,
+                multiply: 0,
+            },
+            target_nodes: 63,
+            ..GeneratorConfig::default()
+        };
+        let printed = EvaluateIntLanguage::print(&generate::<EvaluateIntLanguage>(&config));
+        assert!(printed.contains('+'));
+        assert!(!printed.contains('*'));
+    }
+
+    #[test]
+    fn every_language_builds_the_same_expression() {
+        let config = GeneratorConfig {
+            seed: 7,
+            target_nodes: 35,
+            ..GeneratorConfig::default()
+        };
+        let expected = EvaluateIntLanguage::print(&generate::<EvaluateIntLanguage>(&config));
+        let value = EvaluateIntLanguage::evaluate(&generate::<EvaluateIntLanguage>(&config));
+        let church = generate::<ChurchLanguage>(&config);
+        assert_eq!(ChurchLanguage::print(&church), expected);
+        assert_eq!(ChurchLanguage::evaluate(&church), value);
+        let tagless = generate::<TaglessFinalLanguage>(&config);
+        assert_eq!(TaglessFinalLanguage::print(&tagless), expected);
+        assert_eq!(TaglessFinalLanguage::evaluate(&tagless), value);
+    }
+}
@@ -0,0 +1,270 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Incremental type checking.  A REPL or an editor checks the same expression over and over, with
+//! a small edit in between each check.  Most of the expression hasn't changed, so most of the work
+//! of checking it again is wasted.  Hash-consing (see the interning module) tells us exactly which
+//! subtrees are unchanged: an edit only creates new nodes along the spine from the edited node up
+//! to the root, and every other subtree interns to the same handle as before.  So if we cache each
+//! subtree's type by its handle, rechecking only has to visit the new spine.
+//!
+//! The type system is for ch07's pairs.  ch07d found type errors at runtime, by evaluating into a
+//! value that might be a mismatched pair; here we find them statically instead.  Every subtree is
+//! either an int or a pair of two types, and arithmetic needs ints, while projections need pairs.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::interning::*;
+use crate::rewrite::*;
+use crate::telemetry::TermKind;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// The type of an expression in a language with pairs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PairType {
+    Int,
+    Pair(Box<PairType>, Box<PairType>),
+}
+
+impl fmt::Display for PairType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PairType::Int => write!(f, "int"),
+            PairType::Pair(first, second) => write!(f, "({}, {})", first, second),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TypeError {
+    /// Arithmetic on a pair.
+    NotAnInt { term: &'static str, found: PairType },
+    /// A projection of an int.
+    NotAPair { term: &'static str },
+    /// A kind of term that the type checker doesn't know about.
+    UnknownTerm(&'static str),
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeError::NotAnInt { term, found } => {
+                write!(f, "can't {} a value of type {}", term, found)
+            }
+            TypeError::NotAPair { term } => write!(f, "can't take the {} of an int", term),
+            TypeError::UnknownTerm(term) => write!(f, "can't type-check a {} term", term),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// Finds the type of a node, given the types of its children.
+fn type_of_node(kind: &'static str, mut children: Vec<PairType>) -> Result<PairType, TypeError> {
+    if kind == Add::<()>::NAME || kind == Multiply::<()>::NAME {
+        match children.iter().find(|child| **child != PairType::Int) {
+            Some(found) => Err(TypeError::NotAnInt {
+                term: kind,
+                found: found.clone(),
+            }),
+            None => Ok(PairType::Int),
+        }
+    } else if kind == Pair::<()>::NAME {
+        let second = children.pop().unwrap();
+        let first = children.pop().unwrap();
+        Ok(PairType::Pair(Box::new(first), Box::new(second)))
+    } else if kind == First::<()>::NAME || kind == Second::<()>::NAME {
+        match (children.pop().unwrap(), kind == First::<()>::NAME) {
+            (PairType::Pair(first, _), true) => Ok(*first),
+            (PairType::Pair(_, second), false) => Ok(*second),
+            (PairType::Int, _) => Err(TypeError::NotAPair { term: kind }),
+        }
+    } else {
+        Err(TypeError::UnknownTerm(kind))
+    }
+}
+
+/// The type of every subtree that has been checked so far, keyed by its interned handle.
+#[derive(Default)]
+pub struct TypeCache {
+    types: HashMap<Interned<Shape>, Result<PairType, TypeError>>,
+    hits: usize,
+    misses: usize,
+}
+
+impl TypeCache {
+    pub fn new() -> TypeCache {
+        TypeCache::default()
+    }
+
+    /// Finds the type of an interned subtree, only checking the parts of it that haven't been
+    /// checked before.
+    pub fn type_of(&mut self, shape: &Interned<Shape>) -> Result<PairType, TypeError> {
+        if let Some(result) = self.types.get(shape) {
+            self.hits += 1;
+            return result.clone();
+        }
+        self.misses += 1;
+        let result = match &**shape {
+            Shape::Literal(_) => Ok(PairType::Int),
+            Shape::Term { kind, children } => children
+                .iter()
+                .map(|child| self.type_of(child))
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|children| type_of_node(kind, children)),
+        };
+        self.types.insert(shape.clone(), result.clone());
+        result
+    }
+
+    /// How many times a subtree's type was found in the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// How many subtrees have actually been checked.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// The number of subtrees whose types are cached.
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Forgets every cached type.  The cache holds onto the handles of the subtrees that it has
+    /// checked, so clear it before collecting the interner's garbage.
+    pub fn clear(&mut self) {
+        self.types.clear();
+    }
+}
+
+/// An interner and a type cache that share handles, so that each new version of an expression can
+/// be checked incrementally.
+#[derive(Default)]
+pub struct IncrementalChecker {
+    pub interner: Interner<Shape>,
+    pub cache: TypeCache,
+}
+
+impl IncrementalChecker {
+    pub fn new() -> IncrementalChecker {
+        IncrementalChecker::default()
+    }
+
+    pub fn check_tree(&mut self, tree: &Node) -> Result<PairType, TypeError> {
+        let shape = self.interner.intern_tree(tree);
+        self.cache.type_of(&shape)
+    }
+
+    pub fn check<E>(&mut self, expr: &E) -> Result<PairType, TypeError>
+    where
+        E: Expression,
+        ToNode: Algebra<E::Signature, E, Node>,
+    {
+        self.check_tree(&to_node(expr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    // A balanced tree of additions of `depth` levels, with `leaf` as its leftmost leaf.
+    fn balanced(depth: u32, leaf: PairExpr) -> PairExpr {
+        match depth {
+            0 => leaf,
+            _ => add(
+                balanced(depth - 1, leaf),
+                balanced(depth - 1, integer_literal(1)),
+            ),
+        }
+    }
+
+    #[test]
+    fn can_check_pairs() {
+        let mut checker = IncrementalChecker::new();
+        let expr: PairExpr = pair(
+            integer_literal(1),
+            pair(integer_literal(2), integer_literal(3)),
+        );
+        assert_eq!(
+            checker.check(&expr).unwrap().to_string(),
+            "(int, (int, int))"
+        );
+        let expr: PairExpr = first(second(expr));
+        assert_eq!(checker.check(&expr), Ok(PairType::Int));
+        let expr: PairExpr = add(
+            pair(integer_literal(1), integer_literal(2)),
+            integer_literal(3),
+        );
+        assert_eq!(
+            checker.check(&expr).unwrap_err().to_string(),
+            "can't add a value of type (int, int)"
+        );
+        let expr: PairExpr = second(integer_literal(1));
+        assert_eq!(
+            checker.check(&expr),
+            Err(TypeError::NotAPair { term: "second" })
+        );
+    }
+
+    #[test]
+    fn rechecking_only_visits_the_edited_spine() {
+        let mut checker = IncrementalChecker::new();
+        let depth = 10;
+        assert_eq!(
+            checker.check(&balanced(depth, integer_literal(0))),
+            Ok(PairType::Int)
+        );
+        let first_misses = checker.cache.misses();
+
+        // Checking the same expression again is one cache hit.
+        checker.check(&balanced(depth, integer_literal(0))).unwrap();
+        assert_eq!(checker.cache.misses(), first_misses);
+
+        // Editing the leftmost leaf creates a new leaf and a new node at each level above it.
+        let edit = pair(integer_literal(0), integer_literal(0));
+        assert_eq!(
+            checker.check(&balanced(depth, edit)),
+            Err(TypeError::NotAnInt {
+                term: "add",
+                found: PairType::Pair(Box::new(PairType::Int), Box::new(PairType::Int)),
+            })
+        );
+        // The pair's children are both 0, which was already checked.
+        assert_eq!(checker.cache.misses() - first_misses, 1 + depth as usize);
+    }
+
+    #[test]
+    fn reports_unknown_terms() {
+        let mut checker = IncrementalChecker::new();
+        let tree = Node::term("divide", vec![Node::Literal(1), Node::Literal(2)]);
+        assert_eq!(
+            checker.check_tree(&tree),
+            Err(TypeError::UnknownTerm("divide"))
+        );
+    }
+}
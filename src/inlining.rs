@@ -0,0 +1,346 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! An inliner for programs, as a second example of a whole-program optimization.  It replaces
+//! references to small definitions, and to definitions that are only used once, with a copy of
+//! the definition's expression.  After inlining into a definition, it folds constants (ch09d),
+//! since inlining often leaves constant subexpressions behind.
+//!
+//! Definitions are processed in dependency order, so a definition has already been simplified by
+//! the time we decide whether to inline it, and the size threshold applies to that simplified
+//! expression.  A definition that gets inlined has had every one of its references replaced, so
+//! it's removed from the program, unless it's one of the program's exports, which are never
+//! inlined.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch09a_mendler::*;
+use crate::ch09d_owned_fold::*;
+use crate::program::*;
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+/// Which definitions the inliner is allowed to inline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InlineOptions {
+    /// Inline definitions with at most this many nodes, after simplifying them.
+    pub max_size: usize,
+    /// Inline definitions that are referred to exactly once, whatever their size.
+    pub single_use: bool,
+    /// Definitions that must stay in the program, and so are never inlined.
+    pub exports: BTreeSet<String>,
+}
+
+impl InlineOptions {
+    pub const DEFAULT_MAX_SIZE: usize = 3;
+
+    pub fn new(exports: &[&str]) -> InlineOptions {
+        InlineOptions {
+            max_size: InlineOptions::DEFAULT_MAX_SIZE,
+            single_use: true,
+            exports: exports.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    pub fn with_max_size(self, max_size: usize) -> InlineOptions {
+        InlineOptions { max_size, ..self }
+    }
+
+    pub fn with_single_use(self, single_use: bool) -> InlineOptions {
+        InlineOptions { single_use, ..self }
+    }
+}
+
+/// What the inliner did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InlineReport {
+    /// The definitions that were inlined and removed, in dependency order.
+    pub inlined: Vec<String>,
+    /// How many additions and multiplications were folded away afterwards.
+    pub folds: usize,
+}
+
+// Substitution is a fold that copies an expression, replacing each reference to an inlined
+// definition with a copy of that definition.  The copies don't need any further substitution,
+// since every inlined definition was processed before anything that refers to it.
+
+struct Substitute<'a> {
+    inlined: &'a HashMap<String, ProgramExpr>,
+}
+
+impl<'a, E> Algebra<Global, E, ProgramExpr> for Substitute<'a> {
+    fn apply<F>(&self, term: &Global, _recurse: F) -> ProgramExpr
+    where
+        F: FnMut(&E) -> ProgramExpr,
+    {
+        match self.inlined.get(&term.name) {
+            Some(expr) => mcata(self, expr),
+            None => global(&term.name),
+        }
+    }
+}
+
+impl<'a, E> Algebra<IntegerLiteral, E, ProgramExpr> for Substitute<'a> {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> ProgramExpr
+    where
+        F: FnMut(&E) -> ProgramExpr,
+    {
+        ProgramExpr::from(IntegerLiteral { value: term.value })
+    }
+}
+
+impl<'a, E> Algebra<Add<E>, E, ProgramExpr> for Substitute<'a> {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> ProgramExpr
+    where
+        F: FnMut(&E) -> ProgramExpr,
+    {
+        let (lhs, rhs) = (recurse(&term.lhs), recurse(&term.rhs));
+        ProgramExpr::from(Add { lhs, rhs })
+    }
+}
+
+impl<'a, E> Algebra<Multiply<E>, E, ProgramExpr> for Substitute<'a> {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> ProgramExpr
+    where
+        F: FnMut(&E) -> ProgramExpr,
+    {
+        let (lhs, rhs) = (recurse(&term.lhs), recurse(&term.rhs));
+        ProgramExpr::from(Multiply { lhs, rhs })
+    }
+}
+
+// References can't be folded into constants, so constant folding rebuilds them as-is.
+
+impl<E, T> OwnedAlgebra<Global, E, Folded<T>> for ConstantFold
+where
+    T: From<Global>,
+{
+    fn apply_owned<F>(&self, term: Global, _recurse: F) -> Folded<T>
+    where
+        F: FnMut(E) -> Folded<T>,
+    {
+        Folded::Expr(T::from(term))
+    }
+}
+
+// Deciding what to inline needs the size of each definition, and how many times each definition
+// is referred to.
+
+struct Size;
+
+impl<E> Algebra<Global, E, usize> for Size {
+    fn apply<F>(&self, _term: &Global, _recurse: F) -> usize
+    where
+        F: FnMut(&E) -> usize,
+    {
+        1
+    }
+}
+
+impl<E> Algebra<IntegerLiteral, E, usize> for Size {
+    fn apply<F>(&self, _term: &IntegerLiteral, _recurse: F) -> usize
+    where
+        F: FnMut(&E) -> usize,
+    {
+        1
+    }
+}
+
+struct Uses;
+
+impl<E> Algebra<Global, E, Vec<String>> for Uses {
+    fn apply<F>(&self, term: &Global, _recurse: F) -> Vec<String>
+    where
+        F: FnMut(&E) -> Vec<String>,
+    {
+        vec![term.name.clone()]
+    }
+}
+
+impl<E> Algebra<IntegerLiteral, E, Vec<String>> for Uses {
+    fn apply<F>(&self, _term: &IntegerLiteral, _recurse: F) -> Vec<String>
+    where
+        F: FnMut(&E) -> Vec<String>,
+    {
+        Vec::new()
+    }
+}
+
+macro_rules! binary_counts {
+    ($($term:ident),+ $(,)?) => {
+        $(
+            impl<E> Algebra<$term<E>, E, usize> for Size {
+                fn apply<F>(&self, term: &$term<E>, mut recurse: F) -> usize
+                where
+                    F: FnMut(&E) -> usize,
+                {
+                    1 + recurse(&term.lhs) + recurse(&term.rhs)
+                }
+            }
+
+            impl<E> Algebra<$term<E>, E, Vec<String>> for Uses {
+                fn apply<F>(&self, term: &$term<E>, mut recurse: F) -> Vec<String>
+                where
+                    F: FnMut(&E) -> Vec<String>,
+                {
+                    let mut result = recurse(&term.lhs);
+                    result.extend(recurse(&term.rhs));
+                    result
+                }
+            }
+        )+
+    };
+}
+
+binary_counts!(Add, Multiply);
+
+/// Inlines small and single-use definitions, and folds the constants that that exposes.  Fails if
+/// the program refers to undefined names or has a cycle.
+pub fn inline(
+    program: Program,
+    options: &InlineOptions,
+) -> Result<(Program, InlineReport), ProgramError> {
+    let order: Vec<String> = program
+        .dependency_order()?
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let mut uses: HashMap<String, usize> = HashMap::new();
+    for definition in program.definitions() {
+        for name in mcata(&Uses, &definition.expr) {
+            *uses.entry(name).or_default() += 1;
+        }
+    }
+
+    let written: Vec<String> = program
+        .definitions()
+        .iter()
+        .map(|definition| definition.name.clone())
+        .collect();
+    let mut exprs: HashMap<String, ProgramExpr> = program
+        .into_definitions()
+        .into_iter()
+        .map(|definition| (definition.name, definition.expr))
+        .collect();
+
+    let mut inlined = HashMap::new();
+    let mut report = InlineReport::default();
+    let fold = ConstantFold::new();
+    for name in order {
+        let expr = exprs.remove(&name).unwrap();
+        let substituted = mcata(&Substitute { inlined: &inlined }, &expr);
+        let simplified: ProgramExpr = into_fold(&fold, substituted).into_expr();
+        let small = mcata(&Size, &simplified) <= options.max_size;
+        let single_use = options.single_use && uses.get(&name) == Some(&1);
+        if !options.exports.contains(&name) && (small || single_use) {
+            report.inlined.push(name.clone());
+            inlined.insert(name, simplified);
+        } else {
+            exprs.insert(name, simplified);
+        }
+    }
+    report.folds = fold.folds();
+
+    let mut result = Program::new();
+    for name in written {
+        if let Some(expr) = exprs.remove(&name) {
+            result.define(&name, expr)?;
+        }
+    }
+    Ok((result, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn example() -> Program {
+        let mut program = Program::new();
+        program.define("rate", integer_literal(3)).unwrap();
+        program
+            .define("base", multiply(global("rate"), integer_literal(100)))
+            .unwrap();
+        program
+            .define(
+                "total",
+                add(
+                    multiply(global("base"), global("count")),
+                    multiply(global("count"), global("count")),
+                ),
+            )
+            .unwrap();
+        program
+            .define("count", add(global("input"), integer_literal(1)))
+            .unwrap();
+        program.define("input", integer_literal(6)).unwrap();
+        program
+    }
+
+    #[test]
+    fn can_inline_small_and_single_use_definitions() {
+        let expected = example().evaluate().unwrap()["total"];
+        let (program, report) = inline(example(), &InlineOptions::new(&["total"])).unwrap();
+        // rate and input are small, and base is only used once.  count is used three times, but
+        // once input is inlined, it folds down to a literal, which is small enough.
+        assert_eq!(report.inlined, vec!["rate", "base", "input", "count"]);
+        assert_eq!(report.folds, 5);
+        assert_eq!(program.to_string(), "total = 2149\n");
+        assert_eq!(program.evaluate().unwrap()["total"], expected);
+    }
+
+    #[test]
+    fn respects_thresholds_and_exports() {
+        let options = InlineOptions::new(&["total", "count"])
+            .with_max_size(1)
+            .with_single_use(false);
+        let (program, report) = inline(example(), &options).unwrap();
+        // base is only small once rate has been inlined into it and folded.
+        assert_eq!(report.inlined, vec!["rate", "base", "input"]);
+        assert_eq!(
+            program.to_string(),
+            "total = ((300 * count) + (count * count))\n\
+             count = 7\n"
+        );
+    }
+
+    #[test]
+    fn leaves_overflowing_definitions_unfolded() {
+        let mut program = Program::new();
+        program
+            .define("a", add(integer_literal(i64::MAX), integer_literal(1)))
+            .unwrap();
+        program.define("b", global("a")).unwrap();
+        let (program, report) = inline(program, &InlineOptions::new(&["b"])).unwrap();
+        assert_eq!(report.inlined, vec!["a"]);
+        assert_eq!(report.folds, 0);
+        assert_eq!(program.to_string(), format!("b = ({} + 1)\n", i64::MAX));
+        assert_eq!(
+            program.evaluate(),
+            Err(ProgramError::Overflow("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_bad_programs() {
+        let mut program = example();
+        program.define("broken", global("missing")).unwrap();
+        assert!(matches!(
+            inline(program, &InlineOptions::new(&[])),
+            Err(ProgramError::Undefined { .. })
+        ));
+    }
+}
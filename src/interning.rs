@@ -0,0 +1,251 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Hash-consing: keep exactly one copy of each distinct subtree, so that two subtrees are equal if
+//! and only if they're the same object.  The dag module does this for a whole tree at once, and
+//! hands back a table of indices.  Algorithms like e-graphs and common subexpression elimination
+//! want the same guarantee incrementally, as they build new subtrees, and want to pass subtrees
+//! around as values.  So here an `Interner` hands out `Interned` handles, whose equality and hash
+//! only look at the pointer: comparing two subtrees takes constant time, however big they are.
+//!
+//! Interning a new node only has to look at the node itself, and not its whole subtree, since its
+//! children are already interned handles, and compare in constant time too.
+//!
+//! Handles from different interners are never equal, even if their subtrees are.  Interners are
+//! scoped rather than global, so that each algorithm can throw its table away when it's done.
+
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::rewrite::*;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// A handle to an interned value, which is equal to another handle only if they point at the same
+/// value.
+pub struct Interned<T>(Rc<T>);
+
+impl<T> Clone for Interned<T> {
+    fn clone(&self) -> Self {
+        Interned(Rc::clone(&self.0))
+    }
+}
+
+impl<T> PartialEq for Interned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Eq for Interned<T> {}
+
+impl<T> Hash for Interned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state)
+    }
+}
+
+impl<T> Deref for Interned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Interned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A table with one copy of each distinct value.
+pub struct Interner<T> {
+    table: HashSet<Rc<T>>,
+}
+
+impl<T> Default for Interner<T> {
+    fn default() -> Self {
+        Interner {
+            table: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Eq + Hash> Interner<T> {
+    pub fn new() -> Interner<T> {
+        Interner::default()
+    }
+
+    /// Returns the handle for `value`, adding it to the table if it's not already there.
+    pub fn intern(&mut self, value: T) -> Interned<T> {
+        if let Some(existing) = self.table.get(&value) {
+            return Interned(Rc::clone(existing));
+        }
+        let value = Rc::new(value);
+        self.table.insert(Rc::clone(&value));
+        Interned(value)
+    }
+
+    /// The number of distinct values in the table.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Removes every value that no handle refers to anymore.  Returns how many were removed.
+    /// Removing a node can release the last handles to its children, so this keeps going until
+    /// there's nothing left to remove.
+    pub fn collect_garbage(&mut self) -> usize {
+        let before = self.table.len();
+        loop {
+            let len = self.table.len();
+            self.table.retain(|value| Rc::strong_count(value) > 1);
+            if self.table.len() == len {
+                return before - len;
+            }
+        }
+    }
+}
+
+// For expressions, we intern nodes that look like the rewrite module's `Node`, except that their
+// children are interned handles.
+
+/// One node of an interned expression.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub enum Shape {
+    Literal(i64),
+    Term {
+        kind: &'static str,
+        children: Vec<Interned<Shape>>,
+    },
+}
+
+impl Shape {
+    pub fn children(&self) -> &[Interned<Shape>] {
+        match self {
+            Shape::Literal(_) => &[],
+            Shape::Term { children, .. } => children,
+        }
+    }
+}
+
+impl Interned<Shape> {
+    /// Copies the interned subtree back out into a tree.
+    pub fn to_tree(&self) -> Node {
+        match &**self {
+            Shape::Literal(value) => Node::Literal(*value),
+            Shape::Term { kind, children } => {
+                Node::term(kind, children.iter().map(Interned::to_tree).collect())
+            }
+        }
+    }
+}
+
+impl Interner<Shape> {
+    /// Interns every subtree of `tree`, and returns the handle for the whole thing.
+    pub fn intern_tree(&mut self, tree: &Node) -> Interned<Shape> {
+        let shape = match tree {
+            Node::Literal(value) => Shape::Literal(*value),
+            Node::Term { kind, children } => Shape::Term {
+                kind,
+                children: children
+                    .iter()
+                    .map(|child| self.intern_tree(child))
+                    .collect(),
+            },
+        };
+        self.intern(shape)
+    }
+
+    /// Interns every subexpression of `expr`.
+    pub fn intern_expr<E>(&mut self, expr: &E) -> Interned<Shape>
+    where
+        E: Expression,
+        ToNode: Algebra<E::Signature, E, Node>,
+    {
+        self.intern_tree(&to_node(expr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+
+    fn one_plus_two() -> MultExpr {
+        add(integer_literal(1), integer_literal(2))
+    }
+
+    #[test]
+    fn equal_subtrees_are_the_same_handle() {
+        let mut interner = Interner::new();
+        // (1 + 2) * (1 + 2)
+        let expr: MultExpr = multiply(one_plus_two(), one_plus_two());
+        let product = interner.intern_expr(&expr);
+        let children = product.children();
+        assert_eq!(children[0], children[1]);
+        assert!(Rc::ptr_eq(&children[0].0, &children[1].0));
+        assert_eq!(interner.len(), 4);
+
+        let sum = interner.intern_expr(&one_plus_two());
+        assert_eq!(sum, children[0]);
+        assert_ne!(sum, product);
+        assert_eq!(interner.len(), 4);
+        assert_eq!(product.to_tree(), to_node(&expr));
+    }
+
+    #[test]
+    fn handles_from_different_interners_are_different() {
+        let mut first = Interner::new();
+        let mut second = Interner::new();
+        let a = first.intern_expr(&one_plus_two());
+        let b = second.intern_expr(&one_plus_two());
+        assert_ne!(a, b);
+        assert_eq!(
+            *a,
+            Shape::Term {
+                kind: "add",
+                children: a.children().to_vec()
+            }
+        );
+        assert_eq!(a.to_tree(), b.to_tree());
+    }
+
+    #[test]
+    fn can_collect_garbage() {
+        let mut interner = Interner::new();
+        let sum = interner.intern_expr(&one_plus_two());
+        let product = interner.intern_expr(&multiply::<MultExpr>(
+            integer_literal(1),
+            integer_literal(3),
+        ));
+        assert_eq!(interner.len(), 5);
+        drop(product);
+        // The product and the 3 are gone, but 1 is still part of the sum.
+        assert_eq!(interner.collect_garbage(), 2);
+        assert_eq!(interner.len(), 3);
+        drop(sum);
+        assert_eq!(interner.collect_garbage(), 3);
+        assert!(interner.is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A flat enum of every kind of term in the crate.  Finding out what kind of term you have in an
+//! open-sum expression means climbing through its nested Sums, which is fine for an algebra but
+//! awkward for code that just wants to `match` on the kind, or use it to index a table.  `kind()`
+//! does the climbing for you, and returns a `Kind`, which is a plain C-like enum that you can
+//! match on, hash, or turn into an index.
+//!
+//! (Telemetry's `TermKind` trait gives each kind of term a name at compile time; `Kind` is the
+//! runtime counterpart, and reports the same names.)
+//!
+//! An enum can't be extended from the outside, so this is the one place in the crate that has to
+//! know about every term.  When you add a new term, add a variant for it to `kinds!`, and the term
+//! to `kinded_terms!`.
+
+use crate::cells::CellRef;
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::Subtract;
+use crate::ch07a_pairs::*;
+use crate::ch07e_division::Divide;
+use crate::ch07f_modulo::{IntDiv, Modulo};
+use crate::ch08a_expressions::*;
+use crate::ch08c_units_of_measure::Measured;
+use crate::ch08e_sugar::Negate;
+use crate::ch08f_generic_literals::Lit;
+use crate::ch08h_error_terms::ErrorTerm;
+use crate::ch09i_power_expansion::Power;
+use crate::ch09k_variadic_terms::{AddN, MulN};
+use crate::ch12a_booleans::*;
+use crate::ch12c_numeric_coercion::IntToFloat;
+use crate::ch12d_extern_functions::Extern;
+use crate::ch12e_comparisons::{Equals, LessOrEqual, LessThan};
+use crate::ch12f_higher_order_functions::{Apply, Lambda};
+use crate::ch12g_interval_branches::IfThenElse;
+use crate::program::Global;
+use crate::telemetry::TermKind;
+
+use std::fmt;
+
+macro_rules! kinds {
+    ($($kind:ident => $name:expr),+ $(,)?) => {
+        /// Every kind of term.
+        #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+        pub enum Kind {
+            $($kind),+
+        }
+
+        impl Kind {
+            /// Every kind, in the same order as their indexes.
+            pub const ALL: &'static [Kind] = &[$(Kind::$kind),+];
+
+            /// The name of this kind of term, which is the same name that telemetry uses.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Kind::$kind => $name),+
+                }
+            }
+        }
+    };
+}
+
+kinds! {
+    IntegerLiteral => IntegerLiteral::NAME,
+    Add => Add::<()>::NAME,
+    Multiply => Multiply::<()>::NAME,
+    Subtract => Subtract::<()>::NAME,
+    Divide => Divide::<()>::NAME,
+    Modulo => Modulo::<()>::NAME,
+    IntDiv => IntDiv::<()>::NAME,
+    Pair => Pair::<()>::NAME,
+    First => First::<()>::NAME,
+    Second => Second::<()>::NAME,
+    Negate => Negate::<()>::NAME,
+    Power => "power",
+    CellRef => "cell_ref",
+    Measured => "measured",
+    BoolLiteral => "bool_literal",
+    Variable => "variable",
+    And => "and",
+    Or => "or",
+    Not => "not",
+    Lit => "lit",
+    IntToFloat => "int_to_float",
+    ErrorTerm => "error",
+    AddN => AddN::<()>::NAME,
+    MulN => MulN::<()>::NAME,
+    Global => "global",
+    Extern => "extern",
+    Lambda => "lambda",
+    Apply => "apply",
+    Equals => "equals",
+    LessThan => "less_than",
+    LessOrEqual => "less_or_equal",
+    IfThenElse => "if_then_else",
+}
+
+impl Kind {
+    /// How many kinds there are, which is the size of a jump table indexed by `index`.
+    pub const COUNT: usize = Kind::ALL.len();
+
+    /// A dense index for this kind, between 0 and `COUNT`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Anything that knows what kind of term it is: terms, sums of terms, and expressions.
+pub trait Kinded {
+    fn kind(&self) -> Kind;
+}
+
+macro_rules! kinded_terms {
+    ($($term:ident $(<$E:ident>)?),+ $(,)?) => {
+        $(
+            impl $(<$E>)? Kinded for $term $(<$E>)? {
+                fn kind(&self) -> Kind {
+                    Kind::$term
+                }
+            }
+        )+
+    };
+}
+
+kinded_terms!(
+    IntegerLiteral,
+    Add<E>,
+    Multiply<E>,
+    Subtract<E>,
+    Divide<E>,
+    Modulo<E>,
+    IntDiv<E>,
+    Pair<E>,
+    First<E>,
+    Second<E>,
+    Negate<E>,
+    Power<E>,
+    CellRef,
+    Measured,
+    BoolLiteral,
+    Variable,
+    And<E>,
+    Or<E>,
+    Not<E>,
+    Lit<T>,
+    IntToFloat<E>,
+    ErrorTerm,
+    AddN<E>,
+    MulN<E>,
+    Global,
+    Extern<E>,
+    Lambda<E>,
+    Apply<E>,
+    Equals<E>,
+    LessThan<E>,
+    LessOrEqual<E>,
+    IfThenElse<E>,
+);
+
+impl<L, R> Kinded for Sum<L, R>
+where
+    L: Kinded,
+    R: Kinded,
+{
+    fn kind(&self) -> Kind {
+        match self {
+            Sum::Left(left) => left.kind(),
+            Sum::Right(right) => right.kind(),
+        }
+    }
+}
+
+/// An expression's kind is the kind of its outermost term.
+impl<E> Kinded for E
+where
+    E: Expression,
+    E::Signature: Kinded,
+{
+    fn kind(&self) -> Kind {
+        self.unwrap().kind()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    #[test]
+    fn can_find_the_kind_of_a_term() {
+        let expr: PairExpr = first(pair(integer_literal(1), integer_literal(2)));
+        assert_eq!(expr.kind(), Kind::First);
+        let expr: MultExpr = multiply(integer_literal(80), integer_literal(5));
+        assert_eq!(expr.kind(), Kind::Multiply);
+        assert_eq!(expr.kind().to_string(), "multiply");
+        let expr: BoolExpr = not(variable("x"));
+        assert_eq!(expr.kind(), Kind::Not);
+    }
+
+    #[test]
+    fn later_terms_have_kinds_too() {
+        use crate::ch05d_subtraction::*;
+        use crate::ch08f_generic_literals::*;
+        use crate::ch12c_numeric_coercion::*;
+        use crate::ch12e_comparisons::*;
+
+        let expr: SubExpr = subtract(integer_literal(5), integer_literal(4));
+        assert_eq!(expr.kind(), Kind::Subtract);
+        let expr: NumExpr = lit(2.5);
+        assert_eq!(expr.kind(), Kind::Lit);
+        let expr: CmpExpr = less_than(integer_literal(1), integer_literal(2));
+        assert_eq!(expr.kind().to_string(), "less_than");
+    }
+
+    #[test]
+    fn indexes_are_dense() {
+        for (index, kind) in Kind::ALL.iter().enumerate() {
+            assert_eq!(kind.index(), index);
+        }
+    }
+
+    #[test]
+    fn can_dispatch_through_a_jump_table() {
+        let mut counts = [0; Kind::COUNT];
+        let exprs: Vec<MultExpr> = vec![
+            integer_literal(1),
+            add(integer_literal(1), integer_literal(2)),
+            multiply(integer_literal(3), integer_literal(4)),
+            integer_literal(5),
+        ];
+        for expr in &exprs {
+            counts[expr.kind().index()] += 1;
+        }
+        assert_eq!(counts[Kind::IntegerLiteral.index()], 2);
+        assert_eq!(counts[Kind::Add.index()], 1);
+        assert_eq!(counts[Kind::Multiply.index()], 1);
+    }
+}
@@ -0,0 +1,214 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Laws that every term should uphold, no matter who defines it.  A crate that adds its own terms
+//! (or its own signatures built out of ours) can call these from its tests to check that its new
+//! impls play nicely with the rest of the framework.  Each law is an assertion that panics, with a
+//! message describing the expression that broke it.
+//!
+//! Each law only asks for the impls that it needs, so a signature only has to support the laws
+//! that make sense for it: you can't check that a language round-trips through the printer unless
+//! each of its terms knows how to print and parse itself.
+
+use std::fmt::Debug;
+
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch08e_sugar::*;
+use crate::ch09a_mendler::*;
+use crate::ch10a_dynamic_terms::*;
+use crate::ch10b_plugin_registry::*;
+use crate::dump::*;
+use crate::fuzz::SExpr;
+
+/// Evaluating the same expression twice gives the same value.
+pub fn assert_evaluation_is_deterministic<V, E>(expr: &E)
+where
+    E: Eval<V, E>,
+    V: Debug + PartialEq,
+{
+    let first = expr.evaluate::<V>();
+    let second = expr.evaluate::<V>();
+    assert_eq!(
+        first, second,
+        "evaluation is not deterministic: got {:?}, then {:?}",
+        first, second
+    );
+}
+
+/// Desugaring an expression doesn't change its value.  This only applies to sugar terms that can
+/// also be evaluated directly; the law checks that the direct evaluation agrees with the
+/// definition.
+pub fn assert_desugar_preserves_evaluation<V, E, F>(expr: E)
+where
+    E: Expression + Eval<V, E>,
+    E::Signature: Desugar<E, F>,
+    F: Eval<V, F>,
+    V: Debug + PartialEq,
+{
+    let before = expr.evaluate::<V>();
+    let desugared: F = expr.desugar();
+    let after = desugared.evaluate::<V>();
+    assert_eq!(
+        before, after,
+        "desugaring changed the value from {:?} to {:?}",
+        before, after
+    );
+}
+
+/// Printing an expression as an s-expression, and parsing it back with `registry`, gives back the
+/// same expression.
+pub fn assert_printer_round_trips<E>(expr: &E, registry: &Registry)
+where
+    E: Expression,
+    E::Signature: FromDyn<E>,
+    SExpr: Algebra<E::Signature, E, String>,
+{
+    let printed = mcata(&SExpr, expr);
+    let parsed = registry
+        .parse(&printed)
+        .unwrap_or_else(|error| panic!("cannot parse {:?}: {}", printed, error));
+    let reparsed: E = from_dyn(&parsed)
+        .unwrap_or_else(|| panic!("{:?} parses into a term outside the signature", printed));
+    let reprinted = mcata(&SExpr, &reparsed);
+    assert_eq!(
+        printed, reprinted,
+        "printer does not round-trip: {:?} comes back as {:?}",
+        printed, reprinted
+    );
+}
+
+/// Canonicalizing an expression's dump a second time doesn't change it.
+pub fn assert_canonicalization_is_idempotent<E>(expr: &E)
+where
+    E: Expression,
+    Dump: Algebra<E::Signature, E, DumpNode>,
+{
+    let mut once = dump_tree(expr);
+    once.canonicalize();
+    let mut twice = once.clone();
+    twice.canonicalize();
+    assert_eq!(
+        once.render(DumpVersion::LATEST),
+        twice.render(DumpVersion::LATEST),
+        "canonicalization is not idempotent"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch02_open_sum::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+    use crate::define_sugar;
+
+    fn exprs() -> Vec<MultExpr> {
+        vec![
+            integer_literal(404),
+            add(integer_literal(1), integer_literal(2)),
+            multiply(
+                add(integer_literal(3), integer_literal(-4)),
+                multiply(integer_literal(5), integer_literal(6)),
+            ),
+        ]
+    }
+
+    #[test]
+    fn our_terms_uphold_the_laws() {
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        for expr in exprs() {
+            assert_evaluation_is_deterministic::<i64, _>(&expr);
+            assert_printer_round_trips(&expr, &registry);
+            assert_canonicalization_is_idempotent(&expr);
+        }
+    }
+
+    // A downstream crate would define its sugar the same way, along with a direct evaluation rule.
+
+    define_sugar!(
+        /// Squaring
+        Square { value }, square, "{value}²" => multiply(value, integer_literal(2))
+    );
+
+    define_sugar!(
+        /// Doubling
+        Double { value }, double, "2 * {value}" => multiply(integer_literal(2), value)
+    );
+
+    impl<V, E> Eval<V, E> for Square<E>
+    where
+        V: Clone + std::ops::Mul<Output = V>,
+    {
+        fn eval<F>(&self, mut eval_subexpr: F) -> V
+        where
+            F: FnMut(&E) -> V,
+        {
+            let value = eval_subexpr(&self.value);
+            value.clone() * value
+        }
+    }
+
+    impl<V, E> Eval<V, E> for Double<E>
+    where
+        V: Clone + std::ops::Add<Output = V>,
+    {
+        fn eval<F>(&self, mut eval_subexpr: F) -> V
+        where
+            F: FnMut(&E) -> V,
+        {
+            let value = eval_subexpr(&self.value);
+            value.clone() + value
+        }
+    }
+
+    type SugarSig<E> = Sum![Square<E>, Double<E>, MultSig<E>];
+    struct SugarExpr(Box<SugarSig<SugarExpr>>);
+
+    impl Expression for SugarExpr {
+        type Signature = SugarSig<SugarExpr>;
+        fn wrap(sig: Self::Signature) -> Self {
+            Self(Box::new(sig))
+        }
+        fn unwrap(&self) -> &Self::Signature {
+            &self.0
+        }
+        fn into_signature(self) -> Self::Signature {
+            *self.0
+        }
+    }
+
+    from_terms!(
+        SugarExpr: Square<SugarExpr>,
+        Double<SugarExpr>,
+        Multiply<SugarExpr>,
+        IntegerLiteral,
+        Add<SugarExpr>,
+    );
+
+    #[test]
+    fn correct_sugar_upholds_the_laws() {
+        let expr: SugarExpr = add(double(integer_literal(200)), integer_literal(4));
+        assert_desugar_preserves_evaluation::<i64, _, MultExpr>(expr);
+    }
+
+    #[test]
+    #[should_panic(expected = "desugaring changed the value from 9 to 6")]
+    fn broken_sugar_breaks_the_laws() {
+        // Square's definition multiplies by 2 instead of by itself.
+        let expr: SugarExpr = square(integer_literal(3));
+        assert_desugar_preserves_evaluation::<i64, _, MultExpr>(expr);
+    }
+}
@@ -13,18 +13,25 @@
 // limitations under the License.
 // ------------------------------------------------------------------------------------------------
 
-#![feature(optin_builtin_traits)]
+#![cfg_attr(feature = "nightly", feature(auto_traits, negative_impls))]
 
 pub mod ch01a_before;
 pub mod ch01b_new_method;
 pub mod ch01c_sad_face;
 
+#[macro_use]
 pub mod ch02_open_sum;
 pub mod ch03_evaluation;
+#[macro_use]
 pub mod ch04_smart_constructors;
+#[cfg(feature = "nightly")]
+pub mod ch04_not_eq;
 
 pub mod ch05a_multiplication;
 pub mod ch05b_display;
+pub mod ch05c_closed_enum_bridge;
+pub mod ch05d_subtraction;
+pub mod ch05e_distribute;
 
 pub mod ch06_calculator_monad;
 
@@ -32,8 +39,90 @@ pub mod ch07a_pairs;
 pub mod ch07b_generic_evaluation;
 pub mod ch07c_pair_evaluation;
 pub mod ch07d_safer_pair_evaluation;
+pub mod ch07e_division;
+pub mod ch07f_modulo;
 
 pub mod ch08a_expressions;
 pub mod ch08b_open_recursion_evaluation;
+pub mod ch08c_units_of_measure;
+pub mod ch08d_cross_family_conversion;
+pub mod ch08e_sugar;
+pub mod ch08f_generic_literals;
+pub mod ch08g_gradual_migration;
+pub mod ch08h_error_terms;
+pub mod ch08i_metrics;
+
+pub mod ch09a_mendler;
+pub mod ch09b_church_encoding;
+pub mod ch09c_tagless_final;
+pub mod ch09d_owned_fold;
+pub mod ch09e_functor;
+pub mod ch09f_simplify_on_construct;
+pub mod ch09g_signature_sets;
+pub mod ch09h_bounded_depth;
+pub mod ch09i_power_expansion;
+pub mod ch09j_small_expressions;
+pub mod ch09k_variadic_terms;
+pub mod ch09l_lowering_pipeline;
+
+pub mod ch10a_dynamic_terms;
+pub mod ch10b_plugin_registry;
+pub mod ch10c_dyn_evaluators;
+pub mod ch10d_value_kinds;
+pub mod ch10e_operator_tables;
+
+pub mod ch11a_format_options;
+#[cfg(feature = "ansi")]
+pub mod ch11b_ansi_colors;
+pub mod ch11c_tree_dump;
+pub mod ch11d_latex;
+pub mod ch11e_mathml;
+pub mod ch11f_html_export;
+pub mod ch11g_show_your_work;
+pub mod ch11h_pretty_documents;
+pub mod ch11i_summary;
+pub mod ch12a_booleans;
+pub mod ch12b_boolean_simplifier;
+pub mod ch12c_numeric_coercion;
+pub mod ch12d_extern_functions;
+pub mod ch12e_comparisons;
+pub mod ch12f_higher_order_functions;
+pub mod ch12g_interval_branches;
+
+pub mod allocations;
+pub mod arena;
+pub mod binary;
+pub mod cells;
+pub mod conformance;
+pub mod corpus;
+pub mod dag;
+pub mod dead_code;
+pub mod diagnostics;
+pub mod dump;
+pub mod effects;
+pub mod equivalence;
+pub mod fuzz;
+pub mod generator;
+pub mod incremental;
+pub mod inlining;
+pub mod interning;
+pub mod kinds;
+pub mod laws;
+pub mod limits;
+pub mod obfuscate;
+pub mod observable;
+pub mod parallel;
+pub mod parse;
+pub mod passes;
+pub mod program;
+pub mod reassociate;
+pub mod rewrite;
+#[cfg(feature = "serialization")]
+pub mod serialization;
+pub mod shared_interning;
+pub mod span;
+pub mod telemetry;
+pub mod trampoline;
+pub mod vm;
 
 pub mod old;
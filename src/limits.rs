@@ -0,0 +1,1016 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Nearly everything in this crate walks an expression recursively: evaluating it, printing it,
+//! even dropping it.  Each level of nesting uses up some stack, so an expression that's nested
+//! deeply enough will overflow the stack, no matter which encoding you use.  That's not a problem
+//! for expressions that you write by hand, but it is for expressions that you build from untrusted
+//! input.
+//!
+//! The registry parser in ch10b has a depth limit of its own.  This module provides the same kind
+//! of guard for expressions that you construct directly: a `DepthGuard` has a smart constructor
+//! for each term that refuses to build an expression deeper than its limit.
+//!
+//! Depth isn't the only thing you might want to check about untrusted input, though.  A
+//! `Validator` generalizes the depth guard: it can also reject literals outside of a configured
+//! range, and it runs any construction hooks that you give it, each of which sees every term just
+//! before it's built and can veto it.
+//!
+//! Finally, a service that evaluates expressions for other people will want to cap how much memory
+//! each request can use, no matter what shape its expression has.  A `Budget` is a handle that you
+//! thread through the constructors (and the ch10b parser) that you build a request's expressions
+//! with; it counts the nodes and bytes that they allocate, and refuses to go over its limits.
+//!
+//! Time is the other thing such a service will want to cap.  A `Deadline` wraps an algebra, checks
+//! the clock every so many nodes, and abandons the fold with `TimedOut` once the deadline passes.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::telemetry::TermKind;
+
+use std::cell::Cell;
+use std::fmt;
+use std::mem::size_of;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+use std::time::Instant;
+
+/// An expression would have been nested more deeply than a `DepthGuard` allows.
+#[derive(Debug, PartialEq)]
+pub struct TooDeep {
+    pub limit: usize,
+    /// How deep the expression would have been.
+    pub depth: usize,
+    /// Where the operand that couldn't be nested any deeper is, as a path of child indices from
+    /// the term that was being built or checked (`[0]` is the lhs, `[1]` the rhs).  Its own depth
+    /// is exactly the limit.  The path is only empty when a literal is too deep by itself, which
+    /// means that the limit is zero.
+    pub path: Vec<usize>,
+}
+
+impl TooDeep {
+    /// The error for a term whose operands have the given depths.  It blames the deepest operand
+    /// (the first one, if there's a tie).
+    fn for_operands(limit: usize, operands: &[usize]) -> TooDeep {
+        let deepest = (0..operands.len())
+            .rev()
+            .max_by_key(|index| operands[*index]);
+        TooDeep {
+            limit,
+            depth: 1 + operands.iter().max().unwrap_or(&0),
+            path: deepest.into_iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for TooDeep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expression would be nested {} levels deep", self.depth)?;
+        if !self.path.is_empty() {
+            write!(f, " (through operand {:?})", self.path)?;
+        }
+        write!(f, ", but the limit is {}", self.limit)
+    }
+}
+
+impl std::error::Error for TooDeep {}
+
+/// An expression whose depth has been checked.  The only way to create one is through a
+/// `DepthGuard`, so it can't be deeper than the guard's limit.
+pub struct Checked<E> {
+    expr: E,
+    depth: usize,
+}
+
+impl<E> Checked<E> {
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get(&self) -> &E {
+        &self.expr
+    }
+
+    pub fn into_inner(self) -> E {
+        self.expr
+    }
+}
+
+/// Builds expressions that are at most `max_depth` levels deep.  (A literal has depth 1, just like
+/// the `Depth` algebra from ch09a.)
+pub struct DepthGuard {
+    pub max_depth: usize,
+}
+
+impl DepthGuard {
+    pub fn new(max_depth: usize) -> DepthGuard {
+        DepthGuard { max_depth }
+    }
+
+    /// Checks the depth of a term with the given operands before building it.
+    fn wrap<E>(
+        &self,
+        operands: &[usize],
+        build: impl FnOnce() -> E,
+    ) -> Result<Checked<E>, TooDeep> {
+        let error = TooDeep::for_operands(self.max_depth, operands);
+        if error.depth > self.max_depth {
+            return Err(error);
+        }
+        Ok(Checked {
+            expr: build(),
+            depth: error.depth,
+        })
+    }
+
+    pub fn integer_literal<E>(&self, value: i64) -> Result<Checked<E>, TooDeep>
+    where
+        E: From<IntegerLiteral>,
+    {
+        self.wrap(&[], || E::from(IntegerLiteral { value }))
+    }
+
+    pub fn add<E>(&self, lhs: Checked<E>, rhs: Checked<E>) -> Result<Checked<E>, TooDeep>
+    where
+        E: From<Add<E>>,
+    {
+        self.wrap(&[lhs.depth, rhs.depth], || {
+            E::from(Add {
+                lhs: lhs.expr,
+                rhs: rhs.expr,
+            })
+        })
+    }
+
+    pub fn multiply<E>(&self, lhs: Checked<E>, rhs: Checked<E>) -> Result<Checked<E>, TooDeep>
+    where
+        E: From<Multiply<E>>,
+    {
+        self.wrap(&[lhs.depth, rhs.depth], || {
+            E::from(Multiply {
+                lhs: lhs.expr,
+                rhs: rhs.expr,
+            })
+        })
+    }
+
+    /// Checks an expression that was built some other way.  This has to walk the whole
+    /// expression, so it's only safe to call on expressions that you already trust not to overflow
+    /// the stack.
+    pub fn check<E>(&self, expr: E) -> Result<Checked<E>, TooDeep>
+    where
+        E: Expression,
+        DeepestPath: Algebra<E::Signature, E, Deepest>,
+    {
+        let Deepest { depth, mut path } = mcata(&DeepestPath, &expr);
+        if depth > self.max_depth {
+            // The path leads to a leaf, one level for each step, so the operand that's exactly at
+            // the limit is `depth - limit` steps down.
+            path.reverse();
+            path.truncate(depth - self.max_depth);
+            return Err(TooDeep {
+                limit: self.max_depth,
+                depth,
+                path,
+            });
+        }
+        Ok(Checked { expr, depth })
+    }
+}
+
+/// The depth of an expression, and the path to one of its deepest leaves, in reverse: the leaf's
+/// own index comes first, and the root's child comes last.
+pub struct Deepest {
+    pub depth: usize,
+    pub path: Vec<usize>,
+}
+
+/// Calculates the depth of an expression, like ch09a's `Depth`, and also where its deepest leaf
+/// is.  (It picks the first one, if there's a tie.)
+pub struct DeepestPath;
+
+impl<E> Algebra<IntegerLiteral, E, Deepest> for DeepestPath {
+    fn apply<F>(&self, _term: &IntegerLiteral, _recurse: F) -> Deepest
+    where
+        F: FnMut(&E) -> Deepest,
+    {
+        Deepest {
+            depth: 1,
+            path: Vec::new(),
+        }
+    }
+}
+
+macro_rules! deepest_path_terms {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<E> Algebra<$term<E>, E, Deepest> for DeepestPath {
+                fn apply<F>(&self, term: &$term<E>, mut recurse: F) -> Deepest
+                where
+                    F: FnMut(&E) -> Deepest,
+                {
+                    let children = vec![$(recurse(&term.$field)),+];
+                    let (index, deepest) = children
+                        .into_iter()
+                        .enumerate()
+                        .rev()
+                        .max_by_key(|(_, child)| child.depth)
+                        .unwrap();
+                    let mut path = deepest.path;
+                    path.push(index);
+                    Deepest {
+                        depth: 1 + deepest.depth,
+                        path,
+                    }
+                }
+            }
+        )+
+    };
+}
+
+deepest_path_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+);
+
+/// The reasons that a `Validator` might refuse to build a term.
+#[derive(Debug, PartialEq)]
+pub enum ConstructError {
+    TooDeep(TooDeep),
+    LiteralOutOfRange {
+        value: i64,
+        range: RangeInclusive<i64>,
+    },
+    /// A construction hook rejected the term.
+    Rejected(String),
+}
+
+impl fmt::Display for ConstructError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstructError::TooDeep(err) => write!(f, "{}", err),
+            ConstructError::LiteralOutOfRange { value, range } => write!(
+                f,
+                "literal {} is outside of the allowed range {}..={}",
+                value,
+                range.start(),
+                range.end()
+            ),
+            ConstructError::Rejected(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConstructError {}
+
+impl From<TooDeep> for ConstructError {
+    fn from(err: TooDeep) -> ConstructError {
+        ConstructError::TooDeep(err)
+    }
+}
+
+/// What a construction hook gets to see about the term that's about to be built.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Construction {
+    /// The kind of term, using the same names as telemetry.
+    pub kind: &'static str,
+    /// How deep the new expression will be.
+    pub depth: usize,
+    /// The value, if the term is a literal.
+    pub value: Option<i64>,
+}
+
+type Hook = Box<dyn Fn(&Construction) -> Result<(), String>>;
+
+/// Builds expressions from untrusted input, checking each term before building it.  By default it
+/// doesn't check anything; use the `with_` methods to turn on the checks you need.
+#[derive(Default)]
+pub struct Validator {
+    pub max_depth: Option<usize>,
+    pub literals: Option<RangeInclusive<i64>>,
+    hooks: Vec<Hook>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator::default()
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Validator {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn with_literal_range(mut self, literals: RangeInclusive<i64>) -> Validator {
+        self.literals = Some(literals);
+        self
+    }
+
+    /// Adds a hook that runs before each term is built, after the built-in checks have passed.
+    /// Hooks run in the order that they were added, and the first one to return an error stops
+    /// the term from being built.
+    pub fn with_hook<F>(mut self, hook: F) -> Validator
+    where
+        F: Fn(&Construction) -> Result<(), String> + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Checks a term with operands of the given depths before it's built.
+    fn validate(
+        &self,
+        construction: Construction,
+        operands: &[usize],
+    ) -> Result<(), ConstructError> {
+        if let Some(limit) = self.max_depth {
+            if construction.depth > limit {
+                return Err(ConstructError::TooDeep(TooDeep::for_operands(
+                    limit, operands,
+                )));
+            }
+        }
+        if let (Some(range), Some(value)) = (&self.literals, construction.value) {
+            if !range.contains(&value) {
+                return Err(ConstructError::LiteralOutOfRange {
+                    value,
+                    range: range.clone(),
+                });
+            }
+        }
+        for hook in &self.hooks {
+            hook(&construction).map_err(ConstructError::Rejected)?;
+        }
+        Ok(())
+    }
+
+    pub fn try_integer_literal<E>(&self, value: i64) -> Result<Checked<E>, ConstructError>
+    where
+        E: From<IntegerLiteral>,
+    {
+        self.validate(
+            Construction {
+                kind: IntegerLiteral::NAME,
+                depth: 1,
+                value: Some(value),
+            },
+            &[],
+        )?;
+        Ok(Checked {
+            expr: E::from(IntegerLiteral { value }),
+            depth: 1,
+        })
+    }
+
+    pub fn try_add<E>(&self, lhs: Checked<E>, rhs: Checked<E>) -> Result<Checked<E>, ConstructError>
+    where
+        E: From<Add<E>>,
+    {
+        let depth = 1 + lhs.depth.max(rhs.depth);
+        self.validate(
+            Construction {
+                kind: Add::<E>::NAME,
+                depth,
+                value: None,
+            },
+            &[lhs.depth, rhs.depth],
+        )?;
+        Ok(Checked {
+            expr: E::from(Add {
+                lhs: lhs.expr,
+                rhs: rhs.expr,
+            }),
+            depth,
+        })
+    }
+
+    pub fn try_multiply<E>(
+        &self,
+        lhs: Checked<E>,
+        rhs: Checked<E>,
+    ) -> Result<Checked<E>, ConstructError>
+    where
+        E: From<Multiply<E>>,
+    {
+        let depth = 1 + lhs.depth.max(rhs.depth);
+        self.validate(
+            Construction {
+                kind: Multiply::<E>::NAME,
+                depth,
+                value: None,
+            },
+            &[lhs.depth, rhs.depth],
+        )?;
+        Ok(Checked {
+            expr: E::from(Multiply {
+                lhs: lhs.expr,
+                rhs: rhs.expr,
+            }),
+            depth,
+        })
+    }
+}
+
+/// The resources that a `Budget` keeps track of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    Nodes,
+    Bytes,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resource::Nodes => write!(f, "nodes"),
+            Resource::Bytes => write!(f, "bytes"),
+        }
+    }
+}
+
+/// Building a term would have used more of a `Budget` than it allows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OverBudget {
+    pub resource: Resource,
+    pub limit: usize,
+    /// How much would have been used in total, including what was used before.
+    pub requested: usize,
+}
+
+impl fmt::Display for OverBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expression would use {} {}, but the budget is {}",
+            self.requested, self.resource, self.limit
+        )
+    }
+}
+
+impl std::error::Error for OverBudget {}
+
+/// Caps the total number of nodes and bytes that can be allocated while building expressions.
+/// Every node that's built with this budget is charged against it, and nothing is refunded when
+/// an expression is dropped, so a budget bounds the total amount of work that one request can
+/// make you do.  The constructors only need a shared reference, so you can hand the same budget to
+/// everything that works on a request.
+///
+/// A node costs `size_of` its expression's signature, which is how much it takes up on the heap.
+#[derive(Debug, Default)]
+pub struct Budget {
+    pub max_nodes: Option<usize>,
+    pub max_bytes: Option<usize>,
+    nodes: Cell<usize>,
+    bytes: Cell<usize>,
+}
+
+impl Budget {
+    /// Creates a budget with no limits, which just counts what gets allocated.
+    pub fn new() -> Budget {
+        Budget::default()
+    }
+
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Budget {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Budget {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn nodes_used(&self) -> usize {
+        self.nodes.get()
+    }
+
+    pub fn bytes_used(&self) -> usize {
+        self.bytes.get()
+    }
+
+    /// Charges some nodes and bytes against this budget.  If either would go over its limit,
+    /// nothing is charged.
+    pub fn charge(&self, nodes: usize, bytes: usize) -> Result<(), OverBudget> {
+        let nodes = Budget::check(Resource::Nodes, self.max_nodes, self.nodes.get(), nodes)?;
+        let bytes = Budget::check(Resource::Bytes, self.max_bytes, self.bytes.get(), bytes)?;
+        self.nodes.set(nodes);
+        self.bytes.set(bytes);
+        Ok(())
+    }
+
+    fn check(
+        resource: Resource,
+        limit: Option<usize>,
+        used: usize,
+        amount: usize,
+    ) -> Result<usize, OverBudget> {
+        let requested = used.saturating_add(amount);
+        match limit {
+            Some(limit) if requested > limit => Err(OverBudget {
+                resource,
+                limit,
+                requested,
+            }),
+            _ => Ok(requested),
+        }
+    }
+
+    fn charge_node<E>(&self) -> Result<(), OverBudget>
+    where
+        E: Expression,
+    {
+        self.charge(1, size_of::<E::Signature>())
+    }
+
+    pub fn integer_literal<E>(&self, value: i64) -> Result<E, OverBudget>
+    where
+        E: Expression + From<IntegerLiteral>,
+    {
+        self.charge_node::<E>()?;
+        Ok(E::from(IntegerLiteral { value }))
+    }
+
+    pub fn add<E>(&self, lhs: E, rhs: E) -> Result<E, OverBudget>
+    where
+        E: Expression + From<Add<E>>,
+    {
+        self.charge_node::<E>()?;
+        Ok(E::from(Add { lhs, rhs }))
+    }
+
+    pub fn multiply<E>(&self, lhs: E, rhs: E) -> Result<E, OverBudget>
+    where
+        E: Expression + From<Multiply<E>>,
+    {
+        self.charge_node::<E>()?;
+        Ok(E::from(Multiply { lhs, rhs }))
+    }
+}
+
+// Checking the clock is cheap, but not free, so a deadline only does it every so many nodes.  Once
+// the deadline passes, the fold has to stop for real: no term that's already been entered can be
+// allowed to run its algebra on a value that we made up, since the algebra might do anything with
+// it (like try to project a pair out of a number).  So the fold carries a Result, and each term
+// folds all of its children before it hands their values to the wrapped algebra.  The first
+// TimedOut short-circuits everything above it.
+
+/// A Mendler-style algebra that can fail.  Unlike ch09a's `Algebra`, the recursive call returns a
+/// `Result`, and the algebra can use `?` to give up as soon as any child fails.
+pub trait TryAlgebra<T, E, V, Err> {
+    fn try_apply<F>(&self, term: &T, recurse: F) -> Result<V, Err>
+    where
+        F: FnMut(&E) -> Result<V, Err>;
+}
+
+impl<A, E, V, Err, L, R> TryAlgebra<Sum<L, R>, E, V, Err> for A
+where
+    A: TryAlgebra<L, E, V, Err> + TryAlgebra<R, E, V, Err>,
+{
+    fn try_apply<F>(&self, term: &Sum<L, R>, recurse: F) -> Result<V, Err>
+    where
+        F: FnMut(&E) -> Result<V, Err>,
+    {
+        match term {
+            Sum::Left(lhs) => self.try_apply(lhs, recurse),
+            Sum::Right(rhs) => self.try_apply(rhs, recurse),
+        }
+    }
+}
+
+/// A fallible version of ch09a's `mcata`, which stops at the first error.
+pub fn try_mcata<A, E, V, Err>(algebra: &A, expr: &E) -> Result<V, Err>
+where
+    E: Expression,
+    A: TryAlgebra<E::Signature, E, V, Err>,
+{
+    algebra.try_apply(expr.unwrap(), |subexpr| try_mcata(algebra, subexpr))
+}
+
+/// A point in time after which a fold should give up.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    pub at: Instant,
+    check_every: usize,
+}
+
+impl Deadline {
+    pub const DEFAULT_CHECK_EVERY: usize = 1024;
+
+    pub fn at(at: Instant) -> Deadline {
+        Deadline {
+            at,
+            check_every: Deadline::DEFAULT_CHECK_EVERY,
+        }
+    }
+
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline::at(Instant::now() + timeout)
+    }
+
+    /// Checks the clock every `check_every` nodes.  (Zero would mean never checking it, so it's
+    /// treated as 1.)
+    pub fn with_check_every(self, check_every: usize) -> Deadline {
+        Deadline {
+            check_every: check_every.max(1),
+            ..self
+        }
+    }
+
+    /// How many nodes to fold between each check of the clock.
+    pub fn check_every(&self) -> usize {
+        self.check_every
+    }
+}
+
+/// A fold ran past its deadline.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TimedOut {
+    /// How many nodes were folded before giving up.
+    pub nodes: usize,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out after {} nodes", self.nodes)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Wraps an algebra so that it stops folding once a deadline passes.
+pub struct WithDeadline<A> {
+    pub algebra: A,
+    deadline: Deadline,
+    nodes: Cell<usize>,
+}
+
+impl<A> WithDeadline<A> {
+    /// Counts a node, and fails if the fold should stop before it.
+    fn enter(&self) -> Result<(), TimedOut> {
+        let nodes = self.nodes.get() + 1;
+        if nodes.is_multiple_of(self.deadline.check_every) && Instant::now() >= self.deadline.at {
+            return Err(TimedOut {
+                nodes: self.nodes.get(),
+            });
+        }
+        self.nodes.set(nodes);
+        Ok(())
+    }
+}
+
+impl<A, V, E> TryAlgebra<IntegerLiteral, E, V, TimedOut> for WithDeadline<A>
+where
+    A: Algebra<IntegerLiteral, E, V>,
+{
+    fn try_apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Result<V, TimedOut>
+    where
+        F: FnMut(&E) -> Result<V, TimedOut>,
+    {
+        self.enter()?;
+        Ok(self
+            .algebra
+            .apply(term, |_| unreachable!("literals don't have children")))
+    }
+}
+
+// Like telemetry's Instrumented, this needs one impl per term type, so that it doesn't overlap
+// with the impl for Sum.  It also needs to know each term's children, so that it can fold them
+// before calling the wrapped algebra.  The wrapped algebra then gets each child's value back by
+// asking for that child, which means it can only ask for each child once.
+
+macro_rules! deadline_terms {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<A, V, E> TryAlgebra<$term<E>, E, V, TimedOut> for WithDeadline<A>
+            where
+                A: Algebra<$term<E>, E, V>,
+            {
+                fn try_apply<F>(&self, term: &$term<E>, mut recurse: F) -> Result<V, TimedOut>
+                where
+                    F: FnMut(&E) -> Result<V, TimedOut>,
+                {
+                    self.enter()?;
+                    let mut children = [$((&term.$field, Some(recurse(&term.$field)?))),+];
+                    Ok(self.algebra.apply(term, |child| {
+                        children
+                            .iter_mut()
+                            .find(|(field, _)| std::ptr::eq(*field, child))
+                            .and_then(|(_, value)| value.take())
+                            .expect("algebra asked for a child's value more than once")
+                    }))
+                }
+            }
+        )+
+    };
+}
+
+deadline_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+);
+
+/// Folds an expression with an algebra, giving up if it runs past `deadline`.  Every child of a
+/// term is folded before the algebra sees the term, even if the algebra wouldn't have needed all
+/// of them.
+pub fn mcata_before<A, E, V>(algebra: A, expr: &E, deadline: Deadline) -> Result<V, TimedOut>
+where
+    E: Expression,
+    WithDeadline<A>: TryAlgebra<E::Signature, E, V, TimedOut>,
+{
+    let wrapped = WithDeadline {
+        algebra,
+        deadline,
+        nodes: Cell::new(0),
+    };
+    try_mcata(&wrapped, expr)
+}
+
+/// Evaluates an expression, giving up if it runs past `deadline`.
+pub fn evaluate_before<V, E>(expr: &E, deadline: Deadline) -> Result<V, TimedOut>
+where
+    E: Expression,
+    WithDeadline<Evaluator>: TryAlgebra<E::Signature, E, V, TimedOut>,
+{
+    mcata_before(Evaluator, expr, deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch03_evaluation::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch07c_pair_evaluation::*;
+
+    #[test]
+    fn can_build_shallow_expressions() -> Result<(), TooDeep> {
+        let guard = DepthGuard::new(3);
+        let product = guard.multiply(guard.integer_literal(80)?, guard.integer_literal(5)?)?;
+        let sum: Checked<MultExpr> = guard.add(product, guard.integer_literal(4)?)?;
+        assert_eq!(sum.depth(), 3);
+        assert_eq!(sum.get().evaluate(), 404);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_deep_expressions() -> Result<(), TooDeep> {
+        let guard = DepthGuard::new(2);
+        let product: Checked<MultExpr> =
+            guard.multiply(guard.integer_literal(80)?, guard.integer_literal(5)?)?;
+        assert_eq!(
+            guard.add(product, guard.integer_literal(4)?).err(),
+            Some(TooDeep {
+                limit: 2,
+                depth: 3,
+                path: vec![0],
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn blames_the_deeper_operand() -> Result<(), TooDeep> {
+        let guard = DepthGuard::new(2);
+        let product: Checked<MultExpr> =
+            guard.multiply(guard.integer_literal(80)?, guard.integer_literal(5)?)?;
+        assert_eq!(
+            guard.add(guard.integer_literal(4)?, product).err(),
+            Some(TooDeep {
+                limit: 2,
+                depth: 3,
+                path: vec![1],
+            })
+        );
+        assert_eq!(
+            DepthGuard::new(0).integer_literal::<MultExpr>(4).err(),
+            Some(TooDeep {
+                limit: 0,
+                depth: 1,
+                path: vec![],
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn can_check_existing_expressions() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        assert_eq!(
+            DepthGuard::new(3).check(expr).map(|c| c.depth()).ok(),
+            Some(3)
+        );
+        let expr: Expr = add(integer_literal(1), integer_literal(2));
+        assert_eq!(
+            DepthGuard::new(1).check(expr).err(),
+            Some(TooDeep {
+                limit: 1,
+                depth: 2,
+                path: vec![0],
+            })
+        );
+    }
+
+    #[test]
+    fn check_finds_the_operand_at_the_limit() {
+        // The deepest leaf is the 3 in ((1 + (2 * 3)) * 4), four levels down.
+        let expr: MultExpr = add(
+            integer_literal(0),
+            multiply(
+                add(
+                    integer_literal(1),
+                    multiply(integer_literal(2), integer_literal(3)),
+                ),
+                integer_literal(4),
+            ),
+        );
+        assert_eq!(
+            DepthGuard::new(2).check(expr).err(),
+            Some(TooDeep {
+                limit: 2,
+                depth: 5,
+                path: vec![1, 0, 1],
+            })
+        );
+    }
+
+    #[test]
+    fn can_validate_literals() -> Result<(), ConstructError> {
+        let validator = Validator::new().with_literal_range(-100..=100);
+        let sum: Checked<MultExpr> = validator.try_add(
+            validator.try_integer_literal(99)?,
+            validator.try_integer_literal(-100)?,
+        )?;
+        assert_eq!(sum.get().evaluate(), -1);
+        assert_eq!(
+            validator.try_integer_literal::<MultExpr>(101).err(),
+            Some(ConstructError::LiteralOutOfRange {
+                value: 101,
+                range: -100..=100
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn can_validate_depth() -> Result<(), ConstructError> {
+        let validator = Validator::new().with_max_depth(2);
+        let product: Checked<MultExpr> = validator.try_multiply(
+            validator.try_integer_literal(80)?,
+            validator.try_integer_literal(5)?,
+        )?;
+        let err = validator
+            .try_add(product, validator.try_integer_literal(4)?)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "expression would be nested 3 levels deep (through operand [0]), but the limit is 2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hooks_can_veto_terms() -> Result<(), ConstructError> {
+        let validator = Validator::new().with_hook(|construction| {
+            if construction.kind == Multiply::<MultExpr>::NAME {
+                return Err("multiplication isn't allowed here".to_string());
+            }
+            Ok(())
+        });
+        let lhs: Checked<MultExpr> = validator.try_integer_literal(80)?;
+        let rhs = validator.try_integer_literal(5)?;
+        assert_eq!(
+            validator.try_multiply(lhs, rhs).err(),
+            Some(ConstructError::Rejected(
+                "multiplication isn't allowed here".to_string()
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn budgets_count_nodes_and_bytes() -> Result<(), OverBudget> {
+        let budget = Budget::new();
+        let expr: MultExpr = budget.add(
+            budget.multiply(budget.integer_literal(80)?, budget.integer_literal(5)?)?,
+            budget.integer_literal(4)?,
+        )?;
+        assert_eq!(expr.evaluate(), 404);
+        assert_eq!(budget.nodes_used(), 5);
+        assert_eq!(
+            budget.bytes_used(),
+            5 * size_of::<<MultExpr as Expression>::Signature>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn budgets_cap_nodes() -> Result<(), OverBudget> {
+        let budget = Budget::new().with_max_nodes(2);
+        let lhs: Expr = budget.integer_literal(1)?;
+        let rhs: Expr = budget.integer_literal(2)?;
+        let err = budget.add(lhs, rhs).err().unwrap();
+        assert_eq!(
+            err,
+            OverBudget {
+                resource: Resource::Nodes,
+                limit: 2,
+                requested: 3
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "expression would use 3 nodes, but the budget is 2"
+        );
+        assert_eq!(budget.nodes_used(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn budgets_cap_bytes() {
+        let node = size_of::<<Expr as Expression>::Signature>();
+        let budget = Budget::new().with_max_bytes(node * 3 / 2);
+        assert!(budget.integer_literal::<Expr>(1).is_ok());
+        let err = budget.integer_literal::<Expr>(2).err().unwrap();
+        assert_eq!(err.resource, Resource::Bytes);
+        // A failed charge doesn't use up any of the budget.
+        assert_eq!(budget.nodes_used(), 1);
+        assert_eq!(budget.bytes_used(), node);
+    }
+
+    #[test]
+    fn can_evaluate_before_a_deadline() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let deadline = Deadline::after(Duration::from_secs(3600)).with_check_every(1);
+        assert_eq!(evaluate_before::<i64, _>(&expr, deadline), Ok(404));
+    }
+
+    #[test]
+    fn gives_up_after_the_deadline() {
+        // 1 + 1 + ... + 1, with 100 additions
+        let mut expr: MultExpr = integer_literal(1);
+        for _ in 0..100 {
+            expr = add(expr, integer_literal(1));
+        }
+        let past = Deadline::at(Instant::now()).with_check_every(16);
+        let error = evaluate_before::<i64, _>(&expr, past).unwrap_err();
+        assert_eq!(error, TimedOut { nodes: 15 });
+        assert_eq!(error.to_string(), "timed out after 15 nodes");
+
+        // Any algebra can have a deadline, not just evaluation.
+        let depth = mcata_before(Depth, &expr, past);
+        assert_eq!(depth, Err(TimedOut { nodes: 15 }));
+    }
+
+    #[test]
+    fn no_term_sees_a_value_after_the_deadline() {
+        // (1 + 1 + ... + 1, first (1, 2)), with 6 additions.  The deadline passes at the second
+        // pair, and the projection around it must not be handed a made-up value.
+        let mut sum: PairExpr = integer_literal(1);
+        for _ in 0..6 {
+            sum = add(sum, integer_literal(1));
+        }
+        let expr: PairExpr = pair(sum, first(pair(integer_literal(1), integer_literal(2))));
+        let past = Deadline::at(Instant::now()).with_check_every(16);
+        assert_eq!(
+            evaluate_before::<IntOrPair, _>(&expr, past),
+            Err(TimedOut { nodes: 15 })
+        );
+    }
+
+    #[test]
+    fn always_checks_the_clock_eventually() {
+        let deadline = Deadline::at(Instant::now()).with_check_every(0);
+        assert_eq!(deadline.check_every(), 1);
+        let expr: Expr = integer_literal(1);
+        assert_eq!(
+            evaluate_before::<i64, _>(&expr, deadline),
+            Err(TimedOut { nodes: 0 })
+        );
+    }
+}
@@ -0,0 +1,330 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Obfuscation: rewrites an expression into one that means the same thing, but looks different.
+//! The generator's expressions all share its habits (every chain grouped the way the generator
+//! happened to split it, and no wasted work), so a benchmark corpus built from it isn't very
+//! diverse.  And anything that claims two expressions are equivalent needs pairs of expressions
+//! that are equivalent without being identical.
+//!
+//! The obfuscator applies a number of random disguises to an expression.  It can regroup a chain of
+//! additions or multiplications, or it can insert an identity: a zero or a one that's been written
+//! out the long way, so that nothing folds it away by accident.  The inserted identities can be
+//! proven away again with the `eliminate_identities` rewriter, using the rewrite engine's proofs.
+//!
+//! Like the rewrite engine, the obfuscator works on `Node`s, since it needs to pick nodes at random
+//! and copy subtrees around.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::generator::Rng;
+use crate::rewrite::*;
+use crate::telemetry::TermKind;
+
+/// How often each disguise should be chosen, relative to the others.  A weight of zero means that
+/// the obfuscator never uses it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisguiseWeights {
+    /// Regrouping a chain: `(a + b) + c` becomes `a + (b + c)`, and vice versa.
+    pub reassociate: u32,
+    /// Adding a disguised zero: `x` becomes `x + (k + -k)`.
+    pub add_zero: u32,
+    /// Multiplying by a disguised one: `x` becomes `x * (1 + (k + -k))`.
+    pub multiply_one: u32,
+}
+
+impl Default for DisguiseWeights {
+    fn default() -> DisguiseWeights {
+        DisguiseWeights {
+            reassociate: 2,
+            add_zero: 1,
+            multiply_one: 1,
+        }
+    }
+}
+
+/// Describes how to obfuscate an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObfuscatorConfig {
+    pub seed: u64,
+    pub weights: DisguiseWeights,
+    /// How many disguises to apply.  A regrouping that's chosen when there's no chain to regroup
+    /// does nothing, so this is an upper bound.
+    pub disguises: usize,
+}
+
+impl Default for ObfuscatorConfig {
+    fn default() -> ObfuscatorConfig {
+        ObfuscatorConfig {
+            seed: 0,
+            weights: DisguiseWeights::default(),
+            disguises: 8,
+        }
+    }
+}
+
+fn operands<'a>(node: &'a Node, kind: &str) -> Option<(&'a Node, &'a Node)> {
+    match node {
+        Node::Term { kind: k, children } if *k == kind => match children.as_slice() {
+            [lhs, rhs] => Some((lhs, rhs)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn binary(kind: &'static str, lhs: Node, rhs: Node) -> Node {
+    Node::term(kind, vec![lhs, rhs])
+}
+
+/// Regroups a chain of two operations: `(a + b) + c` becomes `a + (b + c)`, and if the left
+/// operand isn't part of the chain, `a + (b + c)` becomes `(a + b) + c`.
+fn regroup(node: &Node) -> Option<Node> {
+    let kind = node.kind();
+    if kind != Add::<()>::NAME && kind != Multiply::<()>::NAME {
+        return None;
+    }
+    let (lhs, rhs) = operands(node, kind)?;
+    if let Some((a, b)) = operands(lhs, kind) {
+        return Some(binary(
+            kind,
+            a.clone(),
+            binary(kind, b.clone(), rhs.clone()),
+        ));
+    }
+    let (b, c) = operands(rhs, kind)?;
+    Some(binary(
+        kind,
+        binary(kind, lhs.clone(), b.clone()),
+        c.clone(),
+    ))
+}
+
+/// `k + -k`, for some small, nonzero `k`.
+fn disguised_zero(rng: &mut Rng) -> Node {
+    let k = rng.between(1, 9);
+    binary(Add::<()>::NAME, Node::Literal(k), Node::Literal(-k))
+}
+
+// The paths to every node, in preorder.
+fn paths(node: &Node, path: &mut Vec<usize>, result: &mut Vec<Vec<usize>>) {
+    result.push(path.clone());
+    for (index, child) in node.children().iter().enumerate() {
+        path.push(index);
+        paths(child, path, result);
+        path.pop();
+    }
+}
+
+/// Applies random disguises to a Node.  The same configuration always produces the same result.
+pub fn obfuscate_node(node: &Node, config: &ObfuscatorConfig) -> Node {
+    let weights = &config.weights;
+    let total = weights.reassociate + weights.add_zero + weights.multiply_one;
+    assert!(
+        total > 0,
+        "At least one disguise must have a nonzero weight"
+    );
+    let mut rng = Rng::new(config.seed);
+    let mut node = node.clone();
+    for _ in 0..config.disguises {
+        let mut all = Vec::new();
+        paths(&node, &mut Vec::new(), &mut all);
+        let choice = rng.below(u64::from(total)) as u32;
+        let (path, replacement) = if choice < weights.reassociate {
+            // Only some nodes can be regrouped, so pick one of those.
+            let mut candidates: Vec<(Vec<usize>, Node)> = (all.into_iter())
+                .filter_map(|path| {
+                    let replacement = regroup(node.at(&path)?)?;
+                    Some((path, replacement))
+                })
+                .collect();
+            if candidates.is_empty() {
+                continue;
+            }
+            let index = rng.below(candidates.len() as u64) as usize;
+            candidates.swap_remove(index)
+        } else {
+            let path = all.swap_remove(rng.below(all.len() as u64) as usize);
+            let target = node.at(&path).expect("path came from this tree").clone();
+            let replacement = if choice < weights.reassociate + weights.add_zero {
+                binary(Add::<()>::NAME, target, disguised_zero(&mut rng))
+            } else {
+                let one = binary(Add::<()>::NAME, Node::Literal(1), disguised_zero(&mut rng));
+                binary(Multiply::<()>::NAME, target, one)
+            };
+            (path, replacement)
+        };
+        node = node
+            .replace(&path, replacement)
+            .expect("path came from this tree");
+    }
+    node
+}
+
+/// Applies random disguises to an expression.  The disguises add additions and multiplications,
+/// so the expression type has to have them, even if the original expression doesn't use them.
+pub fn obfuscate<E>(expr: &E, config: &ObfuscatorConfig) -> E
+where
+    E: Expression + From<IntegerLiteral> + From<Add<E>> + From<Multiply<E>>,
+    E::Signature: FromNode<E>,
+    ToNode: Algebra<E::Signature, E, Node>,
+{
+    let node = obfuscate_node(&to_node(expr), config);
+    from_node(&node).expect("obfuscator produced a term that the expression has")
+}
+
+// Eliminating the identities is a job for the rewrite engine.  Cancelling a disguised zero turns
+// it back into a plain one, and then the identity rules can remove it; a disguised one goes the
+// same way, via `1 + 0`.  These rules also remove any identities that were in the original
+// expression, so to check that an obfuscation only inserted identities, eliminate them from both.
+
+/// Rewrites `k + -k` to `0`.
+pub const CANCEL_LITERALS: Rule = Rule {
+    name: "cancel literals",
+    rewrite: |node| match operands(node, Add::<()>::NAME)? {
+        (Node::Literal(lhs), Node::Literal(rhs)) if lhs.checked_add(*rhs) == Some(0) => {
+            Some(Node::Literal(0))
+        }
+        _ => None,
+    },
+};
+
+/// Rewrites `x + 0` and `0 + x` to `x`.
+pub const ADD_ZERO: Rule = Rule {
+    name: "add zero",
+    rewrite: |node| match operands(node, Add::<()>::NAME)? {
+        (x, Node::Literal(0)) | (Node::Literal(0), x) => Some(x.clone()),
+        _ => None,
+    },
+};
+
+/// Rewrites `x * 1` and `1 * x` to `x`.
+pub const MULTIPLY_ONE: Rule = Rule {
+    name: "multiply one",
+    rewrite: |node| match operands(node, Multiply::<()>::NAME)? {
+        (x, Node::Literal(1)) | (Node::Literal(1), x) => Some(x.clone()),
+        _ => None,
+    },
+};
+
+/// A rewriter that removes identities, including the ones that the obfuscator inserts.
+pub fn eliminate_identities() -> Rewriter {
+    Rewriter::new(vec![CANCEL_LITERALS, ADD_ZERO, MULTIPLY_ONE])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::EvaluateIntLanguage;
+    use crate::generator::*;
+
+    fn sample(seed: u64) -> Node {
+        let config = GeneratorConfig {
+            seed,
+            target_nodes: 31,
+            ..GeneratorConfig::default()
+        };
+        to_node(&generate::<EvaluateIntLanguage>(&config))
+    }
+
+    fn obfuscator(seed: u64, weights: DisguiseWeights) -> ObfuscatorConfig {
+        ObfuscatorConfig {
+            seed,
+            weights,
+            ..ObfuscatorConfig::default()
+        }
+    }
+
+    #[test]
+    fn obfuscation_preserves_meaning() {
+        for seed in 0..20 {
+            let original = sample(seed);
+            let obfuscated = obfuscate_node(&original, &obfuscator(seed, Default::default()));
+            assert_ne!(obfuscated, original);
+            assert_eq!(
+                small_step().normalize(&obfuscated),
+                small_step().normalize(&original)
+            );
+        }
+    }
+
+    #[test]
+    fn obfuscation_is_reproducible() {
+        let original = sample(7);
+        let config = obfuscator(3, Default::default());
+        assert_eq!(
+            obfuscate_node(&original, &config),
+            obfuscate_node(&original, &config)
+        );
+        assert_ne!(
+            obfuscate_node(&original, &config),
+            obfuscate_node(&original, &obfuscator(4, Default::default()))
+        );
+    }
+
+    #[test]
+    fn regrouping_keeps_the_same_operands() {
+        let weights = DisguiseWeights {
+            reassociate: 1,
+            add_zero: 0,
+            multiply_one: 0,
+        };
+        for seed in 0..20 {
+            let original = sample(seed);
+            let obfuscated = obfuscate_node(&original, &obfuscator(seed, weights.clone()));
+            assert_eq!(obfuscated.size(), original.size());
+            assert_eq!(
+                small_step().normalize(&obfuscated),
+                small_step().normalize(&original)
+            );
+        }
+        // A lone literal has nothing to regroup.
+        let literal = Node::Literal(5);
+        assert_eq!(obfuscate_node(&literal, &obfuscator(0, weights)), literal);
+    }
+
+    #[test]
+    fn inserted_identities_can_be_proven_away() {
+        let weights = DisguiseWeights {
+            reassociate: 0,
+            add_zero: 1,
+            multiply_one: 1,
+        };
+        let rewriter = eliminate_identities();
+        for seed in 0..20 {
+            let original = sample(seed);
+            let obfuscated = obfuscate_node(&original, &obfuscator(seed, weights.clone()));
+            assert!(obfuscated.size() > original.size());
+            let (simplified, proof) = rewriter.normalize_with_proof(&obfuscated);
+            assert_eq!(simplified, *rewriter.normalize(&original));
+            assert_eq!(proof.replay(&rewriter), Ok(simplified));
+        }
+    }
+
+    #[test]
+    fn can_obfuscate_typed_expressions() {
+        let config = GeneratorConfig {
+            target_nodes: 15,
+            ..GeneratorConfig::default()
+        };
+        let original = generate::<EvaluateIntLanguage>(&config);
+        let obfuscated: MultExpr = obfuscate(&original, &ObfuscatorConfig::default());
+        assert_ne!(obfuscated.to_string(), original.to_string());
+        let expected: i64 = mcata(&Evaluator, &original);
+        assert_eq!(mcata::<_, _, i64>(&Evaluator, &obfuscated), expected);
+    }
+}
@@ -0,0 +1,357 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! An expression that tells you when it changes.  Editors and spreadsheets don't rebuild a whole
+//! expression for every keystroke; they replace one subtree at a time, and then want to update
+//! whatever is showing the parts that changed.  An `ObservableExpr` lets each of those listeners
+//! subscribe to one subtree, named by its path from the root (as in `Node::at`), and pushes the
+//! subtree's new value, size, and depth to them whenever a replacement touches it.
+//!
+//! A replacement touches a subscribed subtree if it happens inside of it, or if it replaces the
+//! subtree (or one of its ancestors) outright.  Nothing is recomputed for any other subscription.
+//! The expression is stored as a `Node` from the rewrite module, since we need to walk down paths
+//! and patch the tree in place, and is converted back into its typed form on request.
+
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::dag::Dag;
+use crate::dag::DagError;
+use crate::rewrite::*;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// The values that we keep up to date for each subscribed subtree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metrics {
+    /// The subtree's value, or why it doesn't have one: it contains a term that doesn't evaluate
+    /// to a number, or it overflows.
+    pub value: Result<i64, DagError>,
+    pub size: usize,
+    pub depth: usize,
+}
+
+impl Metrics {
+    fn of(node: &Node) -> Metrics {
+        Metrics {
+            value: Dag::from_tree(node).evaluate(),
+            size: node.size(),
+            depth: depth(node),
+        }
+    }
+}
+
+fn depth(node: &Node) -> usize {
+    1 + node.children().iter().map(depth).max().unwrap_or(0)
+}
+
+/// What a listener hears about the subtree that it subscribed to.
+#[derive(Debug, PartialEq)]
+pub enum Change<'a> {
+    /// The subtree changed, and these are its new contents and metrics.
+    Updated {
+        path: &'a [usize],
+        node: &'a Node,
+        metrics: &'a Metrics,
+    },
+    /// The subtree no longer exists, because one of its ancestors was replaced with something
+    /// smaller.  The subscription stays active, in case a later replacement brings it back.
+    Removed { path: &'a [usize] },
+}
+
+/// Identifies a subscription, so that you can cancel it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SubscriptionId(usize);
+
+/// Returned when a replacement names a path that doesn't exist.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NoSuchPath {
+    pub path: Vec<usize>,
+}
+
+type Listener = Box<dyn FnMut(&Change)>;
+
+struct Subscription {
+    path: Vec<usize>,
+    listener: Listener,
+}
+
+/// An expression whose subtrees can be replaced, and which notifies subscribers when the subtrees
+/// that they care about change.
+pub struct ObservableExpr<E> {
+    root: Node,
+    subscriptions: BTreeMap<SubscriptionId, Subscription>,
+    // The metrics of every subscribed path that currently exists, shared between all of the
+    // subscriptions to that path.
+    metrics: HashMap<Vec<usize>, Metrics>,
+    next_id: usize,
+    expr: PhantomData<fn() -> E>,
+}
+
+impl<E> ObservableExpr<E>
+where
+    E: Expression,
+    E::Signature: FromNode<E>,
+    ToNode: Algebra<E::Signature, E, Node>,
+{
+    pub fn new(expr: &E) -> ObservableExpr<E> {
+        ObservableExpr {
+            root: to_node(expr),
+            subscriptions: BTreeMap::new(),
+            metrics: HashMap::new(),
+            next_id: 0,
+            expr: PhantomData,
+        }
+    }
+
+    /// Returns the current expression.  This is None if a replacement added a kind of term that
+    /// `E` doesn't have.
+    pub fn expr(&self) -> Option<E> {
+        from_node(&self.root)
+    }
+
+    /// Replaces the subtree at `path` with another expression, and notifies every subscriber whose
+    /// subtree changed as a result.
+    pub fn replace(&mut self, path: &[usize], replacement: &E) -> Result<(), NoSuchPath> {
+        self.replace_node(path, to_node(replacement))
+    }
+}
+
+impl<E> ObservableExpr<E> {
+    pub fn node(&self) -> &Node {
+        &self.root
+    }
+
+    /// The current metrics of a subscribed subtree.  This is None if no one is subscribed to
+    /// `path`, or if there is nothing there.
+    pub fn metrics(&self, path: &[usize]) -> Option<&Metrics> {
+        self.metrics.get(path)
+    }
+
+    /// Calls `listener` whenever the subtree at `path` changes.  The path doesn't have to exist
+    /// yet.
+    pub fn subscribe<F>(&mut self, path: &[usize], listener: F) -> SubscriptionId
+    where
+        F: FnMut(&Change) + 'static,
+    {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        if let Some(node) = self.root.at(path) {
+            self.metrics
+                .entry(path.to_vec())
+                .or_insert_with(|| Metrics::of(node));
+        }
+        let path = path.to_vec();
+        let listener = Box::new(listener);
+        self.subscriptions
+            .insert(id, Subscription { path, listener });
+        id
+    }
+
+    /// Cancels a subscription.  Returns false if it was already cancelled.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let subscription = match self.subscriptions.remove(&id) {
+            Some(subscription) => subscription,
+            None => return false,
+        };
+        let path = &subscription.path;
+        if !self.subscriptions.values().any(|other| &other.path == path) {
+            self.metrics.remove(path);
+        }
+        true
+    }
+
+    /// Like `replace`, but with a replacement that's already a Node.
+    pub fn replace_node(&mut self, path: &[usize], replacement: Node) -> Result<(), NoSuchPath> {
+        let slot = at_mut(&mut self.root, path).ok_or_else(|| NoSuchPath {
+            path: path.to_vec(),
+        })?;
+        *slot = replacement;
+
+        // Recompute each affected path once, no matter how many subscribers it has...
+        let mut affected: Vec<&[usize]> = self
+            .subscriptions
+            .values()
+            .map(|subscription| subscription.path.as_slice())
+            .filter(|subscribed| touches(subscribed, path))
+            .collect();
+        affected.sort_unstable();
+        affected.dedup();
+        for subscribed in affected {
+            match self.root.at(subscribed) {
+                Some(node) => {
+                    self.metrics.insert(subscribed.to_vec(), Metrics::of(node));
+                }
+                None => {
+                    self.metrics.remove(subscribed);
+                }
+            }
+        }
+
+        // ...and then tell each subscriber, in the order that they subscribed.
+        for subscription in self.subscriptions.values_mut() {
+            let subscribed = subscription.path.as_slice();
+            if !touches(subscribed, path) {
+                continue;
+            }
+            let change = match (self.root.at(subscribed), self.metrics.get(subscribed)) {
+                (Some(node), Some(metrics)) => Change::Updated {
+                    path: subscribed,
+                    node,
+                    metrics,
+                },
+                _ => Change::Removed { path: subscribed },
+            };
+            (subscription.listener)(&change);
+        }
+        Ok(())
+    }
+}
+
+// A replacement at one path touches a subscription to another if either path is a prefix of the
+// other: the replacement is either inside of the subscribed subtree, or replaces it wholesale.
+
+fn touches(subscribed: &[usize], replaced: &[usize]) -> bool {
+    subscribed.starts_with(replaced) || replaced.starts_with(subscribed)
+}
+
+fn at_mut<'a>(node: &'a mut Node, path: &[usize]) -> Option<&'a mut Node> {
+    match path.split_first() {
+        None => Some(node),
+        Some((index, rest)) => match node {
+            Node::Literal(_) => None,
+            Node::Term { children, .. } => at_mut(children.get_mut(*index)?, rest),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch03_evaluation::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // (1 + 2) * (3 + 4)
+    fn example() -> ObservableExpr<MultExpr> {
+        let lhs: MultExpr = add(integer_literal(1), integer_literal(2));
+        let rhs: MultExpr = add(integer_literal(3), integer_literal(4));
+        ObservableExpr::new(&multiply(lhs, rhs))
+    }
+
+    /// Subscribes to `path`, and returns a log of the values that the subscription hears about.
+    fn record(
+        observable: &mut ObservableExpr<MultExpr>,
+        path: &[usize],
+    ) -> Rc<RefCell<Vec<Option<i64>>>> {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&log);
+        observable.subscribe(path, move |change| {
+            let value = match change {
+                Change::Updated { metrics, .. } => Some(metrics.value.unwrap()),
+                Change::Removed { .. } => None,
+            };
+            sink.borrow_mut().push(value);
+        });
+        log
+    }
+
+    #[test]
+    fn subscriptions_start_with_current_metrics() {
+        let mut observable = example();
+        record(&mut observable, &[]);
+        record(&mut observable, &[1]);
+        let expected = Metrics {
+            value: Ok(21),
+            size: 7,
+            depth: 3,
+        };
+        assert_eq!(observable.metrics(&[]), Some(&expected));
+        assert_eq!(observable.metrics(&[1]).unwrap().value, Ok(7));
+        assert_eq!(observable.metrics(&[0]), None);
+    }
+
+    #[test]
+    fn reports_overflow_in_metrics() {
+        let mut observable = example();
+        observable.subscribe(&[], |_| {});
+        let replacement = add(integer_literal(i64::MAX), integer_literal(1));
+        observable.replace(&[], &replacement).unwrap();
+        assert_eq!(
+            observable.metrics(&[]).unwrap().value,
+            Err(DagError::Overflow { node: 2 })
+        );
+    }
+
+    #[test]
+    fn only_touched_subscriptions_are_notified() {
+        let mut observable = example();
+        let root = record(&mut observable, &[]);
+        let lhs = record(&mut observable, &[0]);
+        let rhs = record(&mut observable, &[1]);
+        let rhs_leaf = record(&mut observable, &[1, 0]);
+
+        // 1 + 2  →  1 + 5
+        observable.replace(&[0, 1], &integer_literal(5)).unwrap();
+        assert_eq!(*root.borrow(), vec![Some(42)]);
+        assert_eq!(*lhs.borrow(), vec![Some(6)]);
+        assert!(rhs.borrow().is_empty());
+        assert!(rhs_leaf.borrow().is_empty());
+
+        // 3 + 4  →  10
+        observable.replace(&[1], &integer_literal(10)).unwrap();
+        assert_eq!(*root.borrow(), vec![Some(42), Some(60)]);
+        assert_eq!(*rhs.borrow(), vec![Some(10)]);
+        assert_eq!(*rhs_leaf.borrow(), vec![None]);
+        assert_eq!(observable.metrics(&[1, 0]), None);
+
+        // 10  →  8 + 2, which brings [1, 0] back
+        let sum: MultExpr = add(integer_literal(8), integer_literal(2));
+        observable.replace(&[1], &sum).unwrap();
+        assert_eq!(*rhs_leaf.borrow(), vec![None, Some(8)]);
+        assert_eq!(observable.expr().unwrap().evaluate(), 60);
+    }
+
+    #[test]
+    fn can_unsubscribe() {
+        let mut observable = example();
+        let log = Rc::new(RefCell::new(0));
+        let sink = Rc::clone(&log);
+        let id = observable.subscribe(&[0], move |_| *sink.borrow_mut() += 1);
+        observable.replace(&[0], &integer_literal(1)).unwrap();
+        assert!(observable.unsubscribe(id));
+        assert!(!observable.unsubscribe(id));
+        observable.replace(&[0], &integer_literal(2)).unwrap();
+        assert_eq!(*log.borrow(), 1);
+        assert_eq!(observable.metrics(&[0]), None);
+    }
+
+    #[test]
+    fn cannot_replace_missing_paths() {
+        let mut observable = example();
+        let error = observable.replace(&[0, 0, 0], &integer_literal(1));
+        assert_eq!(
+            error,
+            Err(NoSuchPath {
+                path: vec![0, 0, 0]
+            })
+        );
+        assert_eq!(observable.expr().unwrap().evaluate(), 21);
+    }
+}
@@ -15,68 +15,11 @@
 
 // ------------------------------------------------------------------------------------------------
 // Data types
+//
+// The terms, the open sum, and the smart constructors are the ones from ch02 and ch04.
 
-pub auto trait NotEq {}
-impl<X> !NotEq for (X, X) {}
-
-pub struct IntegerLiteral {
-    pub value: i64,
-}
-
-pub fn integer_literal<E: From<IntegerLiteral>>(value: i64) -> E {
-    E::from(IntegerLiteral { value })
-}
-
-pub struct Add<E> {
-    pub lhs: Box<E>,
-    pub rhs: Box<E>,
-}
-
-pub fn add<E: From<Add<E>>>(lhs: E, rhs: E) -> E {
-    E::from(Add {
-        lhs: Box::new(lhs),
-        rhs: Box::new(rhs),
-    })
-}
-
-// ------------------------------------------------------------------------------------------------
-// Open sums
-
-pub enum CoproductPair<L, R> {
-    Left(L),
-    Right(R),
-}
-
-impl<L, R> CoproductPair<L, R> {
-    pub fn new_left(left: L) -> CoproductPair<L, R> {
-        CoproductPair::Left(left)
-    }
-    pub fn new_right(right: R) -> CoproductPair<L, R> {
-        CoproductPair::Right(right)
-    }
-}
-
-impl<L, R> From<L> for CoproductPair<L, R> {
-    fn from(left: L) -> CoproductPair<L, R> {
-        CoproductPair::Left(left)
-    }
-}
-
-impl<X, L, R> From<X> for CoproductPair<L, R>
-where
-    R: From<X>,
-    (X, L): NotEq,
-    (X, Self): NotEq,
-{
-    fn from(x: X) -> CoproductPair<L, R> {
-        CoproductPair::Right(R::from(x))
-    }
-}
-
-macro_rules! Coproduct {
-    { $A:ty, $B:ty } => { CoproductPair<$A, $B> };
-    { $A:ty, $($B:ty),+ } => { CoproductPair<$A, Coproduct![$($B),+]> };
-}
+use crate::ch02_open_sum::{Add, IntegerLiteral, Sum};
+pub use crate::ch04_smart_constructors::{add, integer_literal};
 
 // ------------------------------------------------------------------------------------------------
 // Evaluate
@@ -88,15 +31,15 @@ pub trait Evaluate {
     fn evaluate<V: Result>(&self) -> V;
 }
 
-impl<L, R> Evaluate for CoproductPair<L, R>
+impl<L, R> Evaluate for Sum<L, R>
 where
     L: Evaluate,
     R: Evaluate,
 {
     fn evaluate<V: Result>(&self) -> V {
         match self {
-            CoproductPair::Left(l) => l.evaluate(),
-            CoproductPair::Right(r) => r.evaluate(),
+            Sum::Left(l) => l.evaluate(),
+            Sum::Right(r) => r.evaluate(),
         }
     }
 }
@@ -119,17 +62,10 @@ where
 // ------------------------------------------------------------------------------------------------
 // Expr
 
-pub type Sig<E> = Coproduct![IntegerLiteral, Add<E>];
-pub struct Expr(Sig<Expr>);
+pub type Sig<E> = Sum![IntegerLiteral, Add<E>];
+pub struct Expr(Box<Sig<Expr>>);
 
-impl<X> From<X> for Expr
-where
-    Sig<Expr>: From<X>,
-{
-    fn from(x: X) -> Expr {
-        Expr(Sig::<Expr>::from(x))
-    }
-}
+from_terms!(Expr: IntegerLiteral, Add<Expr>);
 
 impl Evaluate for Expr {
     fn evaluate<V: Result>(&self) -> V {
@@ -177,10 +113,7 @@ mod eval_tests {
     fn can_evaluate_add() {
         let one = IntegerLiteral { value: 1 };
         let two = IntegerLiteral { value: 2 };
-        let add = Add {
-            lhs: Box::new(one),
-            rhs: Box::new(two),
-        };
+        let add = Add { lhs: one, rhs: two };
         assert_eq!(add.evaluate::<i64>(), 3);
     }
 
@@ -0,0 +1,204 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Batch workloads often have files containing thousands of independent expressions.  Parsing them
+//! one after another leaves most of the machine idle, so this module splits the input into its
+//! top-level expressions, and parses those chunks on a pool of threads.
+//!
+//! The registry from ch10b holds its parse rules in plain (non-`Send`) closures, and the `DynExpr`s
+//! that it produces aren't `Send` either.  So each worker thread builds its own registry, and hands
+//! each parsed expression to a `process` function right away, on the same thread.  Only the results
+//! of `process` cross back over to the caller, in the same order as the expressions in the input.
+
+use crate::ch10a_dynamic_terms::*;
+use crate::ch10b_plugin_registry::*;
+use crate::span::*;
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+/// Splits an input into its top-level s-expressions: parenthesized forms and bare atoms.  A close
+/// paren that doesn't match anything gets a chunk of its own, and an unclosed form runs to the end
+/// of the input, so that the parser can report those errors.
+pub fn split_top_level(input: &str) -> Vec<Span> {
+    let mut chunks = Vec::new();
+    let mut depth = 0;
+    let mut start: Option<usize> = None;
+    for (position, ch) in input.char_indices() {
+        if depth == 0 {
+            // A bare atom ends at whitespace or at the start of the next form.
+            if let Some(atom_start) = start {
+                if ch.is_whitespace() || ch == '(' || ch == ')' {
+                    chunks.push(Span::new(atom_start, position));
+                    start = None;
+                }
+            }
+            if ch.is_whitespace() {
+                continue;
+            }
+            match ch {
+                '(' => {
+                    start = Some(position);
+                    depth = 1;
+                }
+                ')' => chunks.push(Span::new(position, position + 1)),
+                _ => {
+                    start.get_or_insert(position);
+                }
+            }
+        } else {
+            match ch {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        chunks.push(Span::new(start.take().unwrap(), position + 1));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some(start) = start {
+        chunks.push(Span::new(start, input.len()));
+    }
+    chunks
+}
+
+/// Parses every top-level expression in `input` on `threads` worker threads (or one per CPU, if
+/// `threads` is 0).  Each worker calls `make_registry` once to get its own registry, and then calls
+/// `process` with the result of parsing each chunk that it claims.  Returns the span of each chunk,
+/// and the result of processing it, in input order.  Error positions inside of a chunk are relative
+/// to the start of that chunk.
+pub fn parse_parallel<T, M, P>(
+    input: &str,
+    threads: usize,
+    make_registry: M,
+    process: P,
+) -> Vec<(Span, T)>
+where
+    T: Send,
+    M: Fn() -> Registry + Sync,
+    P: Fn(&Registry, Result<DynExpr, ParseError>) -> T + Sync,
+{
+    let chunks = split_top_level(input);
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        threads => threads,
+    }
+    .min(chunks.len())
+    .max(1);
+
+    // Workers claim chunks one at a time, so a few big expressions don't leave one thread with all
+    // of the work.
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, T)> = thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let registry = make_registry();
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let span = match chunks.get(index) {
+                            Some(span) => span,
+                            None => return results,
+                        };
+                        let parsed = registry.parse(span.slice(input));
+                        results.push((index, process(&registry, parsed)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    chunks
+        .into_iter()
+        .zip(results)
+        .map(|(span, (_, value))| (span, value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arithmetic() -> Registry {
+        Registry::with_plugins(&[&ArithmeticPlugin])
+    }
+
+    #[test]
+    fn can_split_at_top_level() {
+        let input = " (add 1 (multiply 2 3))\n42 (add 4 5)-7) (add";
+        let chunks = split_top_level(input)
+            .into_iter()
+            .map(|span| span.slice(input))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chunks,
+            vec![
+                "(add 1 (multiply 2 3))",
+                "42",
+                "(add 4 5)",
+                "-7",
+                ")",
+                "(add"
+            ]
+        );
+    }
+
+    #[test]
+    fn can_parse_in_parallel() {
+        let input = (0..1000)
+            .map(|i| format!("(add {} (multiply {} 2))", i, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let results = parse_parallel(&input, 4, arithmetic, |registry, parsed| {
+            registry.evaluate(&parsed.unwrap()).unwrap()
+        });
+        let values = results.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+        assert_eq!(values, (0..1000).map(|i| 3 * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reports_errors_per_chunk() {
+        let input = "(add 1 2) (subtract 3 4) 5";
+        let results = parse_parallel(input, 0, arithmetic, |registry, parsed| {
+            parsed.map(|expr| registry.evaluate(&expr).unwrap())
+        });
+        assert_eq!(
+            results,
+            vec![
+                (Span::new(0, 9), Ok(3)),
+                (
+                    Span::new(10, 24),
+                    Err(ParseError::UnknownTerm("subtract".to_string()))
+                ),
+                (Span::new(25, 26), Ok(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_input_has_no_chunks() {
+        let results = parse_parallel("  \n", 4, arithmetic, |_, parsed| parsed.is_ok());
+        assert!(results.is_empty());
+    }
+}
@@ -0,0 +1,629 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A parser for the infix syntax that our Display impls print, such as `(80 * 5) + 4`, so that
+//! printed expressions can be read back in.  Operators follow the usual precedence: `*`, `/`, `%`,
+//! and `div` bind tighter than `+` and `-`, and operators at the same level associate to the left.
+//! The printers parenthesize every operator, so they never rely on precedence, but people do.
+//! A prefix `-` is ch08e's negation sugar.
+//!
+//! Integer literals can be in any radix that ch11a's `format_with` prints, such as `0xff`, and
+//! `parse_formatted` also accepts digits split into groups, so that anything printed with a set
+//! of `FormatOptions` can be read back in with the same options.
+//!
+//! The parser can produce any expression type.  It doesn't know which terms the target signature
+//! contains until it tries to build one, so it uses ch08d's `Narrow` instead of ch04's `From`
+//! injections, which lets it report a term that the signature doesn't have as an ordinary parse
+//! error, instead of refusing to compile.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::ch08a_expressions::*;
+use crate::ch08d_cross_family_conversion::*;
+use crate::ch08e_sugar::*;
+use crate::ch10b_plugin_registry::DEFAULT_MAX_DEPTH;
+use crate::ch11a_format_options::*;
+use crate::span::*;
+
+use std::fmt;
+
+/// Why an input couldn't be parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// The input ended in the middle of an expression.
+    UnexpectedEnd { span: Span },
+    /// The input contains something that doesn't belong where it is.
+    UnexpectedToken { span: Span, found: String },
+    /// An integer literal is too big to fit in an i64.
+    Overflow { span: Span },
+    /// The input uses a term that the target expression type doesn't have.
+    UnsupportedTerm { span: Span, term: &'static str },
+    /// The input is nested more deeply than the parser's limit.  `position` is the offset of the
+    /// operand that went over the limit.
+    TooDeep { position: usize, limit: usize },
+}
+
+impl ParseError {
+    /// The part of the input that the error refers to.
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedEnd { span }
+            | ParseError::UnexpectedToken { span, .. }
+            | ParseError::Overflow { span }
+            | ParseError::UnsupportedTerm { span, .. } => *span,
+            ParseError::TooDeep { position, .. } => Span::at(*position),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd { .. } => write!(f, "unexpected end of input"),
+            ParseError::UnexpectedToken { span, found } => {
+                write!(f, "unexpected `{}` at offset {}", found, span.start)
+            }
+            ParseError::Overflow { span } => {
+                write!(f, "integer literal at offset {} is too big", span.start)
+            }
+            ParseError::UnsupportedTerm { span, term } => write!(
+                f,
+                "expression type has no `{}` term (at offset {})",
+                term, span.start
+            ),
+            ParseError::TooDeep { position, limit } => write!(
+                f,
+                "expression at offset {} is nested more than {} levels deep",
+                position, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token {
+    Integer,
+    Negate,
+    Operator(Operator),
+    Open,
+    Close,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    IntDiv,
+}
+
+impl Operator {
+    fn binds_tightly(self) -> bool {
+        !matches!(self, Operator::Add | Operator::Subtract)
+    }
+}
+
+/// Finds the end of the integer literal that starts at `start`: an optional `-`, an optional radix
+/// prefix, and digits in that radix, which `separator` can split into groups.  A separator only
+/// belongs to the literal if it's between two digits.
+fn literal_end(input: &str, start: usize, separator: Option<char>) -> usize {
+    let zero = start + usize::from(input.as_bytes()[start] == b'-');
+    let (radix, digits_start) = match input.get(zero..zero + 2) {
+        Some("0b") => (2, zero + 2),
+        Some("0o") => (8, zero + 2),
+        Some("0x") => (16, zero + 2),
+        _ => (10, zero),
+    };
+    let mut end = digits_start;
+    let mut chars = input[digits_start..].chars().peekable();
+    while let Some(ch) = chars.next() {
+        let between_digits = end > digits_start && chars.peek().is_some_and(|c| c.is_digit(radix));
+        let belongs = ch.is_digit(radix) || (Some(ch) == separator && between_digits);
+        if !belongs {
+            break;
+        }
+        end += ch.len_utf8();
+    }
+    // A prefix with no digits after it isn't a prefix; the literal is just the `0`.
+    if end == digits_start {
+        return zero + 1;
+    }
+    end
+}
+
+/// Splits the input into tokens.  A `-` in a place where an operand is expected is part of an
+/// integer literal if it comes right before a digit, and a negation otherwise.
+fn tokenize(input: &str, separator: Option<char>) -> Result<Vec<(Token, Span)>, ParseError> {
+    let bytes = input.as_bytes();
+    let mut tokens: Vec<(Token, Span)> = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let byte = bytes[start];
+        if byte.is_ascii_whitespace() {
+            start += 1;
+            continue;
+        }
+        let expects_operand = match tokens.last() {
+            None
+            | Some((Token::Negate, _))
+            | Some((Token::Operator(_), _))
+            | Some((Token::Open, _)) => true,
+            Some((Token::Integer, _)) | Some((Token::Close, _)) => false,
+        };
+        let negative =
+            byte == b'-' && expects_operand && bytes.get(start + 1).is_some_and(u8::is_ascii_digit);
+        let (token, end) = if byte.is_ascii_digit() || negative {
+            (Token::Integer, literal_end(input, start, separator))
+        } else if byte.is_ascii_alphabetic() {
+            let word = bytes[start..]
+                .iter()
+                .take_while(|b| b.is_ascii_alphanumeric());
+            let end = start + word.count();
+            match &input[start..end] {
+                "div" => (Token::Operator(Operator::IntDiv), end),
+                found => {
+                    let span = Span::new(start, end);
+                    let found = found.to_string();
+                    return Err(ParseError::UnexpectedToken { span, found });
+                }
+            }
+        } else {
+            let token = match byte {
+                b'(' => Token::Open,
+                b')' => Token::Close,
+                b'+' => Token::Operator(Operator::Add),
+                b'-' if expects_operand => Token::Negate,
+                b'-' => Token::Operator(Operator::Subtract),
+                b'*' => Token::Operator(Operator::Multiply),
+                b'/' => Token::Operator(Operator::Divide),
+                b'%' => Token::Operator(Operator::Modulo),
+                _ => {
+                    let end = start + input[start..].chars().next().unwrap().len_utf8();
+                    let span = Span::new(start, end);
+                    let found = input[start..end].to_string();
+                    return Err(ParseError::UnexpectedToken { span, found });
+                }
+            };
+            (token, start + 1)
+        };
+        tokens.push((token, Span::new(start, end)));
+        start = end;
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<(Token, Span)>,
+    next: usize,
+    max_depth: Option<usize>,
+    grouping: Option<DigitGrouping>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<(Token, Span)> {
+        self.tokens.get(self.next).copied()
+    }
+
+    fn end(&self) -> Span {
+        Span::at(self.input.len())
+    }
+
+    fn unexpected(&self, span: Span) -> ParseError {
+        ParseError::UnexpectedToken {
+            span,
+            found: span.slice(self.input).to_string(),
+        }
+    }
+
+    // expr := term (('+' | '-') term)*
+    // term := operand (('*' | '/' | '%' | 'div') operand)*
+    //
+    // Chains of operators are parsed with a loop, so only parentheses and negations make the parser
+    // recurse.  `depth` counts those.
+    fn parse_expr<E>(&mut self, tightly: bool, depth: usize) -> Result<(E, Span), ParseError>
+    where
+        E: Expression,
+        E::Signature: ParseableSignature<E>,
+    {
+        let (mut lhs, mut span) = if tightly {
+            self.parse_operand(depth)?
+        } else {
+            self.parse_expr(true, depth)?
+        };
+        while let Some((Token::Operator(operator), operator_span)) = self.peek() {
+            if operator.binds_tightly() != tightly {
+                break;
+            }
+            self.next += 1;
+            let (rhs, rhs_span) = if tightly {
+                self.parse_operand(depth)?
+            } else {
+                self.parse_expr(true, depth)?
+            };
+            span = span.to(rhs_span);
+            lhs = build(operator, lhs, rhs, operator_span)?;
+        }
+        Ok((lhs, span))
+    }
+
+    // operand := integer | '-' operand | '(' expr ')'
+    fn parse_operand<E>(&mut self, depth: usize) -> Result<(E, Span), ParseError>
+    where
+        E: Expression,
+        E::Signature: ParseableSignature<E>,
+    {
+        let (token, span) = self
+            .peek()
+            .ok_or(ParseError::UnexpectedEnd { span: self.end() })?;
+        if let Some(limit) = self.max_depth {
+            if depth > limit {
+                let position = span.start;
+                return Err(ParseError::TooDeep { position, limit });
+            }
+        }
+        self.next += 1;
+        match token {
+            Token::Integer => Ok((self.literal(span)?, span)),
+            Token::Negate => {
+                let (value, value_span) = self.parse_operand(depth + 1)?;
+                Ok((
+                    narrow(Negate { value }, "negate", span)?,
+                    span.to(value_span),
+                ))
+            }
+            Token::Open => {
+                let (expr, _) = self.parse_expr(false, depth + 1)?;
+                match self.peek() {
+                    Some((Token::Close, close)) => {
+                        self.next += 1;
+                        Ok((expr, span.to(close)))
+                    }
+                    Some((_, other)) => Err(self.unexpected(other)),
+                    None => Err(ParseError::UnexpectedEnd { span: self.end() }),
+                }
+            }
+            Token::Operator(_) | Token::Close => Err(self.unexpected(span)),
+        }
+    }
+
+    // A negated literal prints the same way as a negative one, so `-6` could be either.  We build a
+    // negation if the expression type has one, so that negated literals (even `-0`) survive a round
+    // trip, and a negative literal otherwise.
+    fn literal<E>(&self, span: Span) -> Result<E, ParseError>
+    where
+        E: Expression,
+        E::Signature: ParseableSignature<E>,
+    {
+        let text = span.slice(self.input);
+        let magnitude = text.strip_prefix('-');
+        if let Some(Some(value)) = magnitude.map(|digits| parse_literal(digits, self.grouping)) {
+            let digits = Span::new(span.start + 1, span.end);
+            let value = narrow(IntegerLiteral { value }, "integer_literal", digits)?;
+            if let Ok(negation) = E::Signature::narrow(Negate { value }) {
+                return Ok(E::wrap(negation));
+            }
+        }
+        // The tokenizer only lets through well-formed literals, so this can only fail if the value
+        // is too big.
+        let value = parse_literal(text, self.grouping).ok_or(ParseError::Overflow { span })?;
+        narrow(IntegerLiteral { value }, "integer_literal", span)
+    }
+}
+
+/// The terms that the parser might try to build.  Every signature implements this, since every
+/// term implements `Narrow` for any type; a term that isn't in the signature just fails to narrow.
+pub trait ParseableSignature<E>:
+    Narrow<IntegerLiteral>
+    + Narrow<Negate<E>>
+    + Narrow<Add<E>>
+    + Narrow<Subtract<E>>
+    + Narrow<Multiply<E>>
+    + Narrow<Divide<E>>
+    + Narrow<Modulo<E>>
+    + Narrow<IntDiv<E>>
+{
+}
+
+impl<E, S> ParseableSignature<E> for S where
+    S: Narrow<IntegerLiteral>
+        + Narrow<Negate<E>>
+        + Narrow<Add<E>>
+        + Narrow<Subtract<E>>
+        + Narrow<Multiply<E>>
+        + Narrow<Divide<E>>
+        + Narrow<Modulo<E>>
+        + Narrow<IntDiv<E>>
+{
+}
+
+fn narrow<E, X>(term: X, name: &'static str, span: Span) -> Result<E, ParseError>
+where
+    E: Expression,
+    E::Signature: Narrow<X>,
+{
+    E::Signature::narrow(term)
+        .map(E::wrap)
+        .map_err(|_| ParseError::UnsupportedTerm { span, term: name })
+}
+
+fn build<E>(operator: Operator, lhs: E, rhs: E, span: Span) -> Result<E, ParseError>
+where
+    E: Expression,
+    E::Signature: ParseableSignature<E>,
+{
+    match operator {
+        Operator::Add => narrow(Add { lhs, rhs }, "add", span),
+        Operator::Subtract => narrow(Subtract { lhs, rhs }, "subtract", span),
+        Operator::Multiply => narrow(Multiply { lhs, rhs }, "multiply", span),
+        Operator::Divide => narrow(Divide { lhs, rhs }, "divide", span),
+        Operator::Modulo => narrow(Modulo { lhs, rhs }, "modulo", span),
+        Operator::IntDiv => narrow(IntDiv { lhs, rhs }, "int_div", span),
+    }
+}
+
+/// Parses an infix expression into any expression type whose signature has every term that the
+/// input uses.  Input nested more than `DEFAULT_MAX_DEPTH` levels deep is rejected.
+pub fn parse<E>(input: &str) -> Result<E, ParseError>
+where
+    E: Expression,
+    E::Signature: ParseableSignature<E>,
+{
+    parse_with_max_depth(input, Some(DEFAULT_MAX_DEPTH))
+}
+
+/// Parses an infix expression, rejecting input whose parentheses and negations are nested more
+/// than `max_depth` levels deep.  Parsing recurses once per level, so without a limit, a
+/// malicious input can overflow the stack.  `None` removes the limit.
+pub fn parse_with_max_depth<E>(input: &str, max_depth: Option<usize>) -> Result<E, ParseError>
+where
+    E: Expression,
+    E::Signature: ParseableSignature<E>,
+{
+    parse_with(input, max_depth, None)
+}
+
+/// Parses an infix expression that was printed by `format_with` using `options`, whose literals
+/// might have their digits grouped.  Like `parse`, it rejects input nested more than
+/// `DEFAULT_MAX_DEPTH` levels deep.
+pub fn parse_formatted<E>(input: &str, options: &FormatOptions) -> Result<E, ParseError>
+where
+    E: Expression,
+    E::Signature: ParseableSignature<E>,
+{
+    parse_with(input, Some(DEFAULT_MAX_DEPTH), options.grouping)
+}
+
+fn parse_with<E>(
+    input: &str,
+    max_depth: Option<usize>,
+    grouping: Option<DigitGrouping>,
+) -> Result<E, ParseError>
+where
+    E: Expression,
+    E::Signature: ParseableSignature<E>,
+{
+    let tokens = tokenize(input, grouping.map(|grouping| grouping.separator))?;
+    let mut parser = Parser {
+        input,
+        tokens,
+        next: 0,
+        max_depth,
+        grouping,
+    };
+    let (expr, _) = parser.parse_expr(false, 1)?;
+    match parser.peek() {
+        Some((_, span)) => Err(parser.unexpected(span)),
+        None => Ok(expr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch07c_pair_evaluation::*;
+    use crate::ch08b_open_recursion_evaluation::Evaluate;
+    use crate::conformance::EvaluateIntLanguage;
+    use crate::generator::*;
+
+    #[test]
+    fn can_parse_with_precedence() {
+        let expr: MultExpr = parse("(80 * 5) + 4").unwrap();
+        assert_eq!(expr.to_string(), "((80 * 5) + 4)");
+        let expr: MultExpr = parse("4 + 80 * 5").unwrap();
+        assert_eq!(expr.to_string(), "(4 + (80 * 5))");
+        assert_eq!(expr.evaluate::<i64>(), 404);
+        let expr: SubExpr = parse("1337 - 1000 - 30 - 7").unwrap();
+        assert_eq!(expr.to_string(), "(((1337 - 1000) - 30) - 7)");
+        assert_eq!(expr.evaluate::<i64>(), 300);
+    }
+
+    #[test]
+    fn can_parse_negative_literals() {
+        let expr: SubExpr = parse("-3 - -4").unwrap();
+        assert_eq!(expr.to_string(), "(-3 - -4)");
+        assert_eq!(expr.evaluate::<i64>(), 1);
+    }
+
+    #[test]
+    fn can_parse_negations() {
+        let expr: NegateExpr = parse("-(1 + 2) * --3").unwrap();
+        assert_eq!(expr.to_string(), "(-(1 + 2) * --3)");
+        assert_eq!(expr.desugar::<MultExpr>().evaluate::<i64>(), -9);
+        assert_eq!(
+            parse::<MultExpr>("-(1 + 2)").err(),
+            Some(ParseError::UnsupportedTerm {
+                span: Span::new(0, 1),
+                term: "negate",
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_deeply_nested_input() {
+        assert!(parse_with_max_depth::<Expr>("((1 + 2) + 3)", Some(3)).is_ok());
+        assert_eq!(
+            parse_with_max_depth::<Expr>("(((1 + 2) + 3) + 4)", Some(3)).err(),
+            Some(ParseError::TooDeep {
+                position: 3,
+                limit: 3
+            })
+        );
+        assert_eq!(
+            parse_with_max_depth::<NegateExpr>("---1", Some(2)).err(),
+            Some(ParseError::TooDeep {
+                position: 2,
+                limit: 2
+            })
+        );
+        // The default limit keeps a pathological input from overflowing the stack.
+        let input = format!("{}1{}", "(".repeat(200_000), ")".repeat(200_000));
+        assert_eq!(
+            parse::<Expr>(&input).err(),
+            Some(ParseError::TooDeep {
+                position: DEFAULT_MAX_DEPTH,
+                limit: DEFAULT_MAX_DEPTH
+            })
+        );
+    }
+
+    #[test]
+    fn can_parse_every_operator() {
+        let expr: ModExpr = parse("7 % 2 + 7 div 2 + 7 / 2").unwrap();
+        assert_eq!(expr.evaluate::<Checked>(), Checked(Ok(IntOrPair::Int(7))));
+    }
+
+    #[test]
+    fn printed_expressions_round_trip() {
+        for seed in 0..20 {
+            let config = GeneratorConfig {
+                seed,
+                target_nodes: 63,
+                literal_range: (-9, 9),
+                ..GeneratorConfig::default()
+            };
+            let expr = generate::<EvaluateIntLanguage>(&config);
+            let printed = expr.to_string();
+            let parsed: MultExpr = parse(&printed).unwrap();
+            assert_eq!(parsed.to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn can_parse_literals_in_any_radix() {
+        let expr: MultExpr = parse("0x10 * -0b11 + 0o7").unwrap();
+        assert_eq!(expr.to_string(), "((16 * -3) + 7)");
+        // Without a grouping, a separator isn't part of a literal.
+        assert_eq!(
+            parse::<MultExpr>("1,000").err(),
+            Some(ParseError::UnexpectedToken {
+                span: Span::new(1, 2),
+                found: ",".to_string(),
+            })
+        );
+        // And a prefix without any digits isn't a prefix.
+        assert_eq!(
+            parse::<MultExpr>("0xg").err(),
+            Some(ParseError::UnexpectedToken {
+                span: Span::new(1, 3),
+                found: "xg".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn formatted_expressions_round_trip() {
+        let mut options = Vec::new();
+        for parens in [ParenPolicy::Always, ParenPolicy::Minimal] {
+            for radix in [
+                Radix::Binary,
+                Radix::Octal,
+                Radix::Decimal,
+                Radix::Hexadecimal,
+            ] {
+                for grouping in [
+                    None,
+                    Some(DigitGrouping::thousands(',')),
+                    Some(DigitGrouping::thousands('.')),
+                    Some(DigitGrouping::nibbles()),
+                ] {
+                    for (spacing, max_width) in [(true, None), (false, Some(20))] {
+                        options.push(FormatOptions {
+                            spacing,
+                            parens,
+                            max_width,
+                            radix,
+                            grouping,
+                        });
+                    }
+                }
+            }
+        }
+        for seed in 0..5 {
+            let config = GeneratorConfig {
+                seed,
+                target_nodes: 31,
+                literal_range: (-100_000, 100_000),
+                ..GeneratorConfig::default()
+            };
+            let expr = generate::<EvaluateIntLanguage>(&config);
+            for options in &options {
+                let printed = format_with(&expr, options);
+                let parsed: MultExpr = parse_formatted(&printed, options).unwrap();
+                assert_eq!(parsed.to_string(), expr.to_string(), "{}", printed);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_unsupported_terms() {
+        assert_eq!(
+            parse::<Expr>("1 + 2 * 3").err(),
+            Some(ParseError::UnsupportedTerm {
+                span: Span::new(6, 7),
+                term: "multiply",
+            })
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert_eq!(
+            parse::<MultExpr>("(1 + 2").err().map(|e| e.to_string()),
+            Some("unexpected end of input".to_string())
+        );
+        assert_eq!(
+            parse::<MultExpr>("1 + x").err().map(|e| e.span()),
+            Some(Span::new(4, 5))
+        );
+        assert_eq!(
+            parse::<MultExpr>("1 2").err().map(|e| e.to_string()),
+            Some("unexpected `2` at offset 2".to_string())
+        );
+        assert_eq!(
+            parse::<MultExpr>("99999999999999999999").err(),
+            Some(ParseError::Overflow {
+                span: Span::new(0, 20)
+            })
+        );
+    }
+}
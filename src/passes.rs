@@ -0,0 +1,256 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Transformation passes often don't change anything — constant folding an expression with no
+//! constant subtrees, or normalizing something that's already in normal form.  Drivers that just
+//! return the new expression make the caller compare the result with the input to find out, which
+//! costs a walk over both trees (and isn't even possible for the open-sum types, which don't
+//! implement PartialEq).  So our pass drivers say whether they changed anything: passes that
+//! consume their input return an `Outcome`, which hands back the original expression if nothing
+//! changed, and passes that borrow their input return a `Cow`, which is only `Owned` if they had to
+//! build something new.
+//!
+//! A `Pipeline` uses that to run a sequence of passes over and over until none of them changes
+//! anything, without running any pass on an expression that it has already seen.  Each pass can
+//! also run inside of a telemetry span, so that embedders can see which passes the time goes to.
+
+use crate::telemetry::*;
+
+use std::borrow::Cow;
+
+/// What happened when a pass ran.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome<E> {
+    /// The pass built a new expression.
+    Changed(E),
+    /// The pass didn't change anything, and this is the expression that it was given.
+    Unchanged(E),
+}
+
+impl<E> Outcome<E> {
+    pub fn is_changed(&self) -> bool {
+        matches!(self, Outcome::Changed(_))
+    }
+
+    pub fn get(&self) -> &E {
+        match self {
+            Outcome::Changed(expr) | Outcome::Unchanged(expr) => expr,
+        }
+    }
+
+    pub fn into_inner(self) -> E {
+        match self {
+            Outcome::Changed(expr) | Outcome::Unchanged(expr) => expr,
+        }
+    }
+
+    /// Runs another pass on the result of this one.  The combined outcome is a change if either
+    /// pass changed anything.
+    pub fn and_then<F>(self, pass: F) -> Outcome<E>
+    where
+        F: FnOnce(E) -> Outcome<E>,
+    {
+        match self {
+            Outcome::Changed(expr) => Outcome::Changed(pass(expr).into_inner()),
+            Outcome::Unchanged(expr) => pass(expr),
+        }
+    }
+}
+
+impl<E: Clone> From<Cow<'_, E>> for Outcome<E> {
+    fn from(cow: Cow<'_, E>) -> Outcome<E> {
+        match cow {
+            Cow::Borrowed(expr) => Outcome::Unchanged(expr.clone()),
+            Cow::Owned(expr) => Outcome::Changed(expr),
+        }
+    }
+}
+
+struct Pass<E> {
+    name: &'static str,
+    run: Box<dyn Fn(E) -> Outcome<E>>,
+}
+
+impl<E> Pass<E> {
+    fn run_in_span(&self, subscriber: &dyn Subscriber, expr: E) -> Outcome<E> {
+        in_span(subscriber, self.name, 0, || (self.run)(expr))
+    }
+}
+
+/// The result of running a pipeline until it reaches a fixed point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixpoint<E> {
+    pub result: Outcome<E>,
+    /// The names of the passes that ran, in order, including the ones that didn't change anything.
+    pub ran: Vec<&'static str>,
+    /// Whether every pass has seen the final expression without changing it.  This is false if
+    /// the pipeline stopped because it hit its limit on the number of passes.
+    pub converged: bool,
+}
+
+/// A sequence of passes.
+pub struct Pipeline<E> {
+    passes: Vec<Pass<E>>,
+}
+
+impl<E> Default for Pipeline<E> {
+    fn default() -> Pipeline<E> {
+        Pipeline { passes: Vec::new() }
+    }
+}
+
+impl<E> Pipeline<E> {
+    pub fn new() -> Pipeline<E> {
+        Pipeline::default()
+    }
+
+    pub fn with_pass<F>(mut self, name: &'static str, run: F) -> Pipeline<E>
+    where
+        F: Fn(E) -> Outcome<E> + 'static,
+    {
+        self.passes.push(Pass {
+            name,
+            run: Box::new(run),
+        });
+        self
+    }
+
+    /// Runs each pass once, in order.
+    pub fn run(&self, expr: E) -> Outcome<E> {
+        self.instrumented_run(&NoSubscriber, expr)
+    }
+
+    /// Like `run`, but runs each pass inside of a span named after the pass.
+    pub fn instrumented_run(&self, subscriber: &dyn Subscriber, expr: E) -> Outcome<E> {
+        self.passes
+            .iter()
+            .fold(Outcome::Unchanged(expr), |outcome, pass| {
+                outcome.and_then(|expr| pass.run_in_span(subscriber, expr))
+            })
+    }
+
+    /// Runs the passes in a loop until none of them changes anything, or until `max_runs` passes
+    /// have run.  A pass that didn't change anything is only run again once some other pass has
+    /// changed the expression, so this stops as soon as every pass has seen the current expression.
+    pub fn fixpoint(&self, expr: E, max_runs: usize) -> Fixpoint<E> {
+        self.instrumented_fixpoint(&NoSubscriber, expr, max_runs)
+    }
+
+    /// Like `fixpoint`, but runs each pass inside of a span named after the pass.
+    pub fn instrumented_fixpoint(
+        &self,
+        subscriber: &dyn Subscriber,
+        expr: E,
+        max_runs: usize,
+    ) -> Fixpoint<E> {
+        let mut result = Outcome::Unchanged(expr);
+        let mut ran = Vec::new();
+        // How many passes in a row have seen the current expression without changing it.
+        let mut unchanged = 0;
+        for pass in self.passes.iter().cycle() {
+            if unchanged == self.passes.len() {
+                return Fixpoint {
+                    result,
+                    ran,
+                    converged: true,
+                };
+            }
+            if ran.len() == max_runs {
+                break;
+            }
+            ran.push(pass.name);
+            let changed = result.is_changed();
+            let outcome = pass.run_in_span(subscriber, result.into_inner());
+            unchanged = if outcome.is_changed() {
+                0
+            } else {
+                unchanged + 1
+            };
+            result = match outcome {
+                Outcome::Unchanged(expr) if changed => Outcome::Changed(expr),
+                outcome => outcome,
+            };
+        }
+        let converged = self.passes.is_empty();
+        Fixpoint {
+            result,
+            ran,
+            converged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn halve(value: i64) -> Outcome<i64> {
+        if value % 2 == 0 && value != 0 {
+            Outcome::Changed(value / 2)
+        } else {
+            Outcome::Unchanged(value)
+        }
+    }
+
+    fn shrink_by_three(value: i64) -> Outcome<i64> {
+        if value % 3 == 0 && value != 0 {
+            Outcome::Changed(value / 3)
+        } else {
+            Outcome::Unchanged(value)
+        }
+    }
+
+    #[test]
+    fn outcomes_remember_changes() {
+        assert_eq!(halve(12).and_then(halve), Outcome::Changed(3));
+        assert_eq!(halve(3).and_then(shrink_by_three), Outcome::Changed(1));
+        assert_eq!(halve(5).and_then(halve), Outcome::Unchanged(5));
+        let unchanged: Cow<i64> = Cow::Borrowed(&7);
+        assert_eq!(Outcome::from(unchanged), Outcome::Unchanged(7));
+    }
+
+    #[test]
+    fn pipelines_run_each_pass_once() {
+        let pipeline = Pipeline::new()
+            .with_pass("halve", halve)
+            .with_pass("shrink by three", shrink_by_three);
+        assert_eq!(pipeline.run(36), Outcome::Changed(6));
+        assert_eq!(pipeline.run(5), Outcome::Unchanged(5));
+    }
+
+    #[test]
+    fn fixpoints_stop_when_nothing_changes() {
+        let pipeline = Pipeline::new()
+            .with_pass("halve", halve)
+            .with_pass("shrink by three", shrink_by_three);
+        let fixpoint = pipeline.fixpoint(36, 100);
+        assert_eq!(fixpoint.result, Outcome::Changed(1));
+        assert!(fixpoint.converged);
+        // 36 -> 18 -> 6 -> 3 -> 1, and then a round to check that neither pass changes 1.
+        assert_eq!(fixpoint.ran.len(), 6);
+        let fixpoint = pipeline.fixpoint(5, 100);
+        assert_eq!(fixpoint.result, Outcome::Unchanged(5));
+        assert_eq!(fixpoint.ran, vec!["halve", "shrink by three"]);
+    }
+
+    #[test]
+    fn fixpoints_stop_at_their_limit() {
+        let pipeline = Pipeline::new().with_pass("negate", |value: i64| Outcome::Changed(-value));
+        let fixpoint = pipeline.fixpoint(1, 3);
+        assert_eq!(fixpoint.result, Outcome::Changed(-1));
+        assert!(!fixpoint.converged);
+        assert_eq!(fixpoint.ran.len(), 3);
+    }
+}
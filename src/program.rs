@@ -0,0 +1,408 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Small programs, rather than single expressions.  A program is a list of named top-level
+//! definitions, each an expression, which can refer to the values of other definitions with a new
+//! `Global` term.  Evaluating a program evaluates every definition, each one after the definitions
+//! it refers to.
+//!
+//! This looks a lot like the spreadsheet in the cells module, but a program is checked and
+//! evaluated as a whole, instead of being updated one cell at a time: a reference to a missing
+//! definition, or a cycle of definitions, is an error for the whole program.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A reference to the value of a top-level definition.
+pub struct Global {
+    pub name: String,
+}
+
+pub fn global<E: From<Global>>(name: &str) -> E {
+    E::from(Global {
+        name: name.to_string(),
+    })
+}
+
+impl fmt::Display for Global {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+pub type ProgramSig<E> = Sum![Global, Multiply<E>, Sig<E>];
+pub struct ProgramExpr(pub Box<ProgramSig<ProgramExpr>>);
+
+impl Expression for ProgramExpr {
+    type Signature = ProgramSig<ProgramExpr>;
+    fn wrap(sig: Self::Signature) -> Self {
+        Self(Box::new(sig))
+    }
+    fn unwrap(&self) -> &Self::Signature {
+        &self.0
+    }
+    fn into_signature(self) -> Self::Signature {
+        *self.0
+    }
+}
+
+from_terms!(
+    ProgramExpr: Global,
+    Multiply<ProgramExpr>,
+    IntegerLiteral,
+    Add<ProgramExpr>,
+);
+
+impl fmt::Display for ProgramExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The ways that a program can be wrong.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProgramError {
+    /// Two definitions have the same name.
+    Duplicate(String),
+    /// A definition refers to a name that isn't defined.
+    Undefined { definition: String, name: String },
+    /// These definitions refer to each other in a cycle.
+    Cycle(Vec<String>),
+    /// A definition's value doesn't fit in an i64.
+    Overflow(String),
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgramError::Duplicate(name) => write!(f, "{} is defined more than once", name),
+            ProgramError::Undefined { definition, name } => {
+                write!(f, "{} refers to {}, which isn't defined", definition, name)
+            }
+            ProgramError::Cycle(names) => write!(f, "cycle between {}", names.join(", ")),
+            ProgramError::Overflow(name) => write!(f, "{} overflows", name),
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+// Finding which definitions an expression refers to is a fold that only cares about Globals.
+
+/// An algebra that collects the names of every definition that an expression refers to.
+pub struct Globals;
+
+impl<E> Algebra<Global, E, BTreeSet<String>> for Globals {
+    fn apply<F>(&self, term: &Global, _recurse: F) -> BTreeSet<String>
+    where
+        F: FnMut(&E) -> BTreeSet<String>,
+    {
+        std::iter::once(term.name.clone()).collect()
+    }
+}
+
+impl<E> Algebra<IntegerLiteral, E, BTreeSet<String>> for Globals {
+    fn apply<F>(&self, _term: &IntegerLiteral, _recurse: F) -> BTreeSet<String>
+    where
+        F: FnMut(&E) -> BTreeSet<String>,
+    {
+        BTreeSet::new()
+    }
+}
+
+macro_rules! binary_globals {
+    ($($term:ident),+ $(,)?) => {
+        $(
+            impl<E> Algebra<$term<E>, E, BTreeSet<String>> for Globals {
+                fn apply<F>(&self, term: &$term<E>, mut recurse: F) -> BTreeSet<String>
+                where
+                    F: FnMut(&E) -> BTreeSet<String>,
+                {
+                    let mut result = recurse(&term.lhs);
+                    result.extend(recurse(&term.rhs));
+                    result
+                }
+            }
+        )+
+    };
+}
+
+binary_globals!(Add, Multiply);
+
+// Evaluating a definition looks up the values of the definitions it refers to, which the
+// dependency order guarantees have already been evaluated.
+
+struct GlobalEvaluator<'a> {
+    values: &'a HashMap<&'a str, i64>,
+}
+
+impl<'a, E> Algebra<Global, E, Option<i64>> for GlobalEvaluator<'a> {
+    fn apply<F>(&self, term: &Global, _recurse: F) -> Option<i64>
+    where
+        F: FnMut(&E) -> Option<i64>,
+    {
+        self.values.get(term.name.as_str()).copied()
+    }
+}
+
+impl<'a, E> Algebra<IntegerLiteral, E, Option<i64>> for GlobalEvaluator<'a> {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Option<i64>
+    where
+        F: FnMut(&E) -> Option<i64>,
+    {
+        Some(term.value)
+    }
+}
+
+impl<'a, E> Algebra<Add<E>, E, Option<i64>> for GlobalEvaluator<'a> {
+    fn apply<F>(&self, term: &Add<E>, mut recurse: F) -> Option<i64>
+    where
+        F: FnMut(&E) -> Option<i64>,
+    {
+        recurse(&term.lhs)?.checked_add(recurse(&term.rhs)?)
+    }
+}
+
+impl<'a, E> Algebra<Multiply<E>, E, Option<i64>> for GlobalEvaluator<'a> {
+    fn apply<F>(&self, term: &Multiply<E>, mut recurse: F) -> Option<i64>
+    where
+        F: FnMut(&E) -> Option<i64>,
+    {
+        recurse(&term.lhs)?.checked_mul(recurse(&term.rhs)?)
+    }
+}
+
+/// One top-level definition.
+pub struct Definition {
+    pub name: String,
+    pub expr: ProgramExpr,
+}
+
+/// A list of named definitions, in the order that they were written.
+#[derive(Default)]
+pub struct Program {
+    definitions: Vec<Definition>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    InProgress,
+    Done,
+}
+
+impl Program {
+    pub fn new() -> Program {
+        Program::default()
+    }
+
+    /// Adds a definition to the end of the program.  Its expression can refer to definitions that
+    /// haven't been added yet.
+    pub fn define(&mut self, name: &str, expr: ProgramExpr) -> Result<(), ProgramError> {
+        if self.get(name).is_some() {
+            return Err(ProgramError::Duplicate(name.to_string()));
+        }
+        self.definitions.push(Definition {
+            name: name.to_string(),
+            expr,
+        });
+        Ok(())
+    }
+
+    pub fn definitions(&self) -> &[Definition] {
+        &self.definitions
+    }
+
+    pub fn into_definitions(self) -> Vec<Definition> {
+        self.definitions
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ProgramExpr> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.name == name)
+            .map(|definition| &definition.expr)
+    }
+
+    /// The names of every definition, ordered so that each comes after every definition it refers
+    /// to.  Apart from that, definitions stay in the order they were written.
+    pub fn dependency_order(&self) -> Result<Vec<&str>, ProgramError> {
+        let references: HashMap<&str, BTreeSet<String>> = self
+            .definitions
+            .iter()
+            .map(|definition| (definition.name.as_str(), mcata(&Globals, &definition.expr)))
+            .collect();
+        let mut visits = HashMap::new();
+        let mut stack = Vec::new();
+        let mut order = Vec::new();
+        for definition in &self.definitions {
+            self.visit(
+                &definition.name,
+                &references,
+                &mut visits,
+                &mut stack,
+                &mut order,
+            )?;
+        }
+        Ok(order)
+    }
+
+    // A depth-first search over the definitions, following references.  Each definition is added
+    // to `order` after everything it refers to.  If we reach a definition that's still in
+    // progress, then everything on the stack from that definition up is a cycle.
+    fn visit<'a>(
+        &'a self,
+        name: &'a str,
+        references: &HashMap<&'a str, BTreeSet<String>>,
+        visits: &mut HashMap<&'a str, Visit>,
+        stack: &mut Vec<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) -> Result<(), ProgramError> {
+        match visits.get(name) {
+            Some(Visit::Done) => return Ok(()),
+            Some(Visit::InProgress) => {
+                let start = stack.iter().position(|n| *n == name).unwrap();
+                let cycle = stack[start..].iter().map(|n| n.to_string()).collect();
+                return Err(ProgramError::Cycle(cycle));
+            }
+            None => {}
+        }
+        visits.insert(name, Visit::InProgress);
+        stack.push(name);
+        for reference in &references[name] {
+            let (target, _) = references
+                .get_key_value(reference.as_str())
+                .ok_or_else(|| ProgramError::Undefined {
+                    definition: name.to_string(),
+                    name: reference.clone(),
+                })?;
+            self.visit(target, references, visits, stack, order)?;
+        }
+        stack.pop();
+        visits.insert(name, Visit::Done);
+        order.push(name);
+        Ok(())
+    }
+
+    /// Evaluates every definition.
+    pub fn evaluate(&self) -> Result<BTreeMap<String, i64>, ProgramError> {
+        let mut values = HashMap::new();
+        for name in self.dependency_order()? {
+            let expr = self.get(name).unwrap();
+            let value = mcata(&GlobalEvaluator { values: &values }, expr)
+                .ok_or_else(|| ProgramError::Overflow(name.to_string()))?;
+            values.insert(name, value);
+        }
+        Ok(values
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect())
+    }
+}
+
+/// Prints one definition per line, in the order they were written.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for definition in &self.definitions {
+            writeln!(f, "{} = {}", definition.name, definition.expr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn example() -> Program {
+        let mut program = Program::new();
+        program
+            .define("area", multiply(global("width"), global("height")))
+            .unwrap();
+        program.define("width", integer_literal(8)).unwrap();
+        program
+            .define("height", add(global("width"), integer_literal(2)))
+            .unwrap();
+        program
+    }
+
+    #[test]
+    fn can_order_definitions() {
+        assert_eq!(
+            example().dependency_order(),
+            Ok(vec!["width", "height", "area"])
+        );
+    }
+
+    #[test]
+    fn can_evaluate_programs() {
+        let values = example().evaluate().unwrap();
+        let values: Vec<(&str, i64)> = values.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        assert_eq!(values, vec![("area", 80), ("height", 10), ("width", 8)]);
+    }
+
+    #[test]
+    fn can_print_programs() {
+        assert_eq!(
+            example().to_string(),
+            "area = (width * height)\n\
+             width = 8\n\
+             height = (width + 2)\n"
+        );
+    }
+
+    #[test]
+    fn reports_bad_programs() {
+        let mut program = example();
+        assert_eq!(
+            program.define("width", integer_literal(1)),
+            Err(ProgramError::Duplicate("width".to_string()))
+        );
+        program.define("volume", global("depth")).unwrap();
+        assert_eq!(
+            program.evaluate().unwrap_err().to_string(),
+            "volume refers to depth, which isn't defined"
+        );
+
+        let mut program = Program::new();
+        program
+            .define("a", add(global("b"), integer_literal(1)))
+            .unwrap();
+        program
+            .define("b", add(global("a"), integer_literal(1)))
+            .unwrap();
+        assert_eq!(
+            program.dependency_order(),
+            Err(ProgramError::Cycle(vec!["a".to_string(), "b".to_string()]))
+        );
+
+        let mut program = Program::new();
+        program.define("big", integer_literal(i64::MAX)).unwrap();
+        program
+            .define("bigger", add(global("big"), integer_literal(1)))
+            .unwrap();
+        assert_eq!(
+            program.evaluate(),
+            Err(ProgramError::Overflow("bigger".to_string()))
+        );
+    }
+}
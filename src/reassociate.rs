@@ -0,0 +1,552 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Reassociation: constant folding that looks across a whole chain of additions or
+//! multiplications.  ch09d's constant folder can't do anything with `(1 + f()) + 2`, since neither
+//! addition has two constant operands.  But addition is associative and commutative, so we can
+//! treat the chain as a list of operands, `[1, f(), 2]`, and gather the constants together:
+//! `3 + f()`.
+//!
+//! Moving an operand changes when it's evaluated, though, so this is only safe for operands that
+//! are pure (see the effects module).  Constants always are, so they can move anywhere; every
+//! other operand stays in the order it was written.
+//!
+//! Moving constants can also change whether the chain overflows.  `(MAX + f()) + -1` overflows
+//! when `f()` is positive, but `f() + (MAX - 1)` doesn't.  So we only gather constants around at
+//! most one other operand, and only when every grouping of them overflows for exactly the same
+//! values of that operand.  We keep the gathered constant on the same side of that operand that
+//! the constants were, so that a type error (like adding an int to a float) is reported the same
+//! way, and we never drop an operand, even one multiplied by zero, since we don't know that it's
+//! an int that can't fail.
+//!
+//! A chain that has nothing to fold keeps its original grouping, since regrouping floating-point
+//! arithmetic can change its result.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+use crate::ch09d_owned_fold::*;
+use crate::ch12c_numeric_coercion::*;
+use crate::ch12d_extern_functions::*;
+use crate::effects::*;
+use crate::passes::*;
+use crate::program::*;
+
+use std::cell::Cell;
+
+/// The operators that we can reassociate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Chain {
+    Add,
+    Multiply,
+}
+
+impl Chain {
+    fn identity(self) -> i64 {
+        match self {
+            Chain::Add => 0,
+            Chain::Multiply => 1,
+        }
+    }
+
+    fn fold(self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            Chain::Add => lhs.checked_add(rhs),
+            Chain::Multiply => lhs.checked_mul(rhs),
+        }
+    }
+
+    /// Returns whether we can fold `constants` together and move them around `unknowns` other
+    /// operands without changing whether the chain overflows, however it was grouped.
+    fn can_regroup(self, constants: &[i64], unknowns: usize) -> bool {
+        // If the constants' magnitudes can be combined without overflowing, then so can any of
+        // their partial sums or products, in any order.  (Zeros don't count, since a product can
+        // overflow before it gets to one.)
+        let mut magnitudes = constants
+            .iter()
+            .map(|value| u128::from(value.unsigned_abs()));
+        let magnitude = match self {
+            Chain::Add => magnitudes.try_fold(0u128, u128::checked_add),
+            Chain::Multiply => magnitudes
+                .filter(|magnitude| *magnitude != 0)
+                .try_fold(1u128, u128::checked_mul),
+        };
+        if magnitude.is_none_or(|magnitude| magnitude > i64::MAX as u128) {
+            return false;
+        }
+        // With one other operand x, every partial result that includes x has to lie between x and
+        // the final result, which is only true if the constants all push in the same direction.
+        match (self, unknowns) {
+            (_, 0) => true,
+            (Chain::Add, 1) => {
+                constants.iter().all(|value| *value >= 0)
+                    || constants.iter().all(|value| *value <= 0)
+            }
+            (Chain::Multiply, 1) => constants.iter().all(|value| *value > 0),
+            _ => false,
+        }
+    }
+
+    fn combine<T>(self, lhs: Operand<T>, rhs: Operand<T>) -> Operand<T>
+    where
+        T: From<Add<T>> + From<Multiply<T>>,
+    {
+        let expr = match self {
+            Chain::Add => T::from(Add {
+                lhs: lhs.expr,
+                rhs: rhs.expr,
+            }),
+            Chain::Multiply => T::from(Multiply {
+                lhs: lhs.expr,
+                rhs: rhs.expr,
+            }),
+        };
+        Operand::new(expr, lhs.effects | rhs.effects)
+    }
+}
+
+/// A finished subexpression, along with what we need to know to move it around.
+pub struct Operand<T> {
+    pub expr: T,
+    pub effects: Effects,
+    /// The subexpression's value, if it's an integer literal.
+    pub constant: Option<i64>,
+}
+
+impl<T> Operand<T> {
+    fn new(expr: T, effects: Effects) -> Operand<T> {
+        Operand {
+            expr,
+            effects,
+            constant: None,
+        }
+    }
+
+    fn literal(value: i64) -> Operand<T>
+    where
+        T: From<IntegerLiteral>,
+    {
+        Operand {
+            expr: T::from(IntegerLiteral { value }),
+            effects: Effects::PURE,
+            constant: Some(value),
+        }
+    }
+}
+
+/// The result of reassociating a subexpression.  A chain stays unfinished until we reach the top
+/// of it, so that its parent can see all of its operands.
+pub enum Reassociated<T> {
+    Operand(Operand<T>),
+    Chain {
+        chain: Chain,
+        lhs: Box<Reassociated<T>>,
+        rhs: Box<Reassociated<T>>,
+    },
+}
+
+/// An algebra that reassociates chains of additions and multiplications.
+#[derive(Default)]
+pub struct Reassociate {
+    rewrites: Cell<usize>,
+}
+
+impl Reassociate {
+    pub fn new() -> Reassociate {
+        Reassociate::default()
+    }
+
+    /// How many constants have been folded or operands dropped so far.
+    pub fn rewrites(&self) -> usize {
+        self.rewrites.get()
+    }
+
+    // A chain's operands are its subtrees that aren't part of the same chain.
+    fn link<T>(&self, chain: Chain, lhs: Reassociated<T>, rhs: Reassociated<T>) -> Reassociated<T>
+    where
+        T: From<IntegerLiteral> + From<Add<T>> + From<Multiply<T>>,
+    {
+        let operand = |tree| match tree {
+            Reassociated::Chain { chain: inner, .. } if inner != chain => {
+                Reassociated::Operand(self.finish(tree))
+            }
+            tree => tree,
+        };
+        Reassociated::Chain {
+            chain,
+            lhs: Box::new(operand(lhs)),
+            rhs: Box::new(operand(rhs)),
+        }
+    }
+
+    /// Folds whatever can be folded in a chain, and builds the result.
+    pub fn finish<T>(&self, tree: Reassociated<T>) -> Operand<T>
+    where
+        T: From<IntegerLiteral> + From<Add<T>> + From<Multiply<T>>,
+    {
+        let chain = match &tree {
+            Reassociated::Operand(_) => return regroup(tree),
+            Reassociated::Chain { chain, .. } => *chain,
+        };
+        let mut leaves = Vec::new();
+        operands(&tree, &mut leaves);
+        let constants: Vec<i64> = leaves.iter().filter_map(|op| op.constant).collect();
+        let unknowns = leaves.len() - constants.len();
+        if constants.len() < 2 || !chain.can_regroup(&constants, unknowns) {
+            return regroup(tree);
+        }
+        let value = constants
+            .iter()
+            .try_fold(chain.identity(), |lhs, rhs| chain.fold(lhs, *rhs))
+            .expect("can_regroup checked for overflow");
+        self.rewrites.set(self.rewrites.get() + constants.len() - 1);
+
+        let constant_first = unknowns == 1 && !unknown_is_lhs(&tree);
+        let mut result = Vec::new();
+        flatten(tree, &mut result);
+        result.retain(|op| op.constant.is_none());
+        if constant_first {
+            result.insert(0, Operand::literal(value));
+        } else {
+            result.push(Operand::literal(value));
+        }
+        rebuild(chain, result)
+    }
+}
+
+/// Returns whether the chain's only non-constant operand is the left operand of its parent.
+fn unknown_is_lhs<T>(tree: &Reassociated<T>) -> bool {
+    let is_unknown = |tree: &Reassociated<T>| match tree {
+        Reassociated::Operand(operand) => operand.constant.is_none(),
+        Reassociated::Chain { .. } => false,
+    };
+    match tree {
+        Reassociated::Operand(_) => false,
+        Reassociated::Chain { lhs, rhs, .. } => {
+            is_unknown(lhs) || (!is_unknown(rhs) && (unknown_is_lhs(lhs) || unknown_is_lhs(rhs)))
+        }
+    }
+}
+
+fn operands<'a, T>(tree: &'a Reassociated<T>, leaves: &mut Vec<&'a Operand<T>>) {
+    match tree {
+        Reassociated::Operand(operand) => leaves.push(operand),
+        Reassociated::Chain { lhs, rhs, .. } => {
+            operands(lhs, leaves);
+            operands(rhs, leaves);
+        }
+    }
+}
+
+fn flatten<T>(tree: Reassociated<T>, operands: &mut Vec<Operand<T>>) {
+    match tree {
+        Reassociated::Operand(operand) => operands.push(operand),
+        Reassociated::Chain { lhs, rhs, .. } => {
+            flatten(*lhs, operands);
+            flatten(*rhs, operands);
+        }
+    }
+}
+
+// Rebuilds a chain with its original grouping.
+fn regroup<T>(tree: Reassociated<T>) -> Operand<T>
+where
+    T: From<Add<T>> + From<Multiply<T>>,
+{
+    match tree {
+        Reassociated::Operand(operand) => operand,
+        Reassociated::Chain { chain, lhs, rhs } => chain.combine(regroup(*lhs), regroup(*rhs)),
+    }
+}
+
+// Rebuilds a chain from a list of operands, grouping them to the left.
+fn rebuild<T>(chain: Chain, operands: Vec<Operand<T>>) -> Operand<T>
+where
+    T: From<Add<T>> + From<Multiply<T>>,
+{
+    let mut operands = operands.into_iter();
+    let first = operands.next().expect("chains have at least two operands");
+    operands.fold(first, |lhs, rhs| chain.combine(lhs, rhs))
+}
+
+impl<E, T> OwnedAlgebra<IntegerLiteral, E, Reassociated<T>> for Reassociate
+where
+    T: From<IntegerLiteral>,
+{
+    fn apply_owned<F>(&self, term: IntegerLiteral, _recurse: F) -> Reassociated<T>
+    where
+        F: FnMut(E) -> Reassociated<T>,
+    {
+        Reassociated::Operand(Operand::literal(term.value))
+    }
+}
+
+impl<E, T> OwnedAlgebra<Add<E>, E, Reassociated<T>> for Reassociate
+where
+    T: From<IntegerLiteral> + From<Add<T>> + From<Multiply<T>>,
+{
+    fn apply_owned<F>(&self, term: Add<E>, mut recurse: F) -> Reassociated<T>
+    where
+        F: FnMut(E) -> Reassociated<T>,
+    {
+        let (lhs, rhs) = (recurse(term.lhs), recurse(term.rhs));
+        self.link(Chain::Add, lhs, rhs)
+    }
+}
+
+impl<E, T> OwnedAlgebra<Multiply<E>, E, Reassociated<T>> for Reassociate
+where
+    T: From<IntegerLiteral> + From<Add<T>> + From<Multiply<T>>,
+{
+    fn apply_owned<F>(&self, term: Multiply<E>, mut recurse: F) -> Reassociated<T>
+    where
+        F: FnMut(E) -> Reassociated<T>,
+    {
+        let (lhs, rhs) = (recurse(term.lhs), recurse(term.rhs));
+        self.link(Chain::Multiply, lhs, rhs)
+    }
+}
+
+// Every other term is an operand that we can't see inside of, though we can still reassociate
+// the chains in its subexpressions.
+
+impl<E, T> OwnedAlgebra<FloatLiteral, E, Reassociated<T>> for Reassociate
+where
+    T: From<FloatLiteral>,
+{
+    fn apply_owned<F>(&self, term: FloatLiteral, _recurse: F) -> Reassociated<T>
+    where
+        F: FnMut(E) -> Reassociated<T>,
+    {
+        Reassociated::Operand(Operand::new(T::from(term), FloatLiteral::EFFECTS))
+    }
+}
+
+impl<E, T> OwnedAlgebra<Global, E, Reassociated<T>> for Reassociate
+where
+    T: From<Global>,
+{
+    fn apply_owned<F>(&self, term: Global, _recurse: F) -> Reassociated<T>
+    where
+        F: FnMut(E) -> Reassociated<T>,
+    {
+        Reassociated::Operand(Operand::new(T::from(term), Global::EFFECTS))
+    }
+}
+
+impl<E, T> OwnedAlgebra<IntToFloat<E>, E, Reassociated<T>> for Reassociate
+where
+    T: From<IntegerLiteral> + From<Add<T>> + From<Multiply<T>> + From<IntToFloat<T>>,
+{
+    fn apply_owned<F>(&self, term: IntToFloat<E>, mut recurse: F) -> Reassociated<T>
+    where
+        F: FnMut(E) -> Reassociated<T>,
+    {
+        let expr = self.finish(recurse(term.expr));
+        let effects = IntToFloat::<E>::EFFECTS | expr.effects;
+        Reassociated::Operand(Operand::new(
+            T::from(IntToFloat { expr: expr.expr }),
+            effects,
+        ))
+    }
+}
+
+impl<E, T> OwnedAlgebra<Extern<E>, E, Reassociated<T>> for Reassociate
+where
+    T: From<IntegerLiteral> + From<Add<T>> + From<Multiply<T>> + From<Extern<T>>,
+{
+    fn apply_owned<F>(&self, term: Extern<E>, recurse: F) -> Reassociated<T>
+    where
+        F: FnMut(E) -> Reassociated<T>,
+    {
+        let mut effects = Extern::<E>::EFFECTS;
+        let args = term.args.into_iter().map(recurse).map(|arg| {
+            let arg = self.finish(arg);
+            effects |= arg.effects;
+            arg.expr
+        });
+        let args = args.collect();
+        let name = term.name;
+        Reassociated::Operand(Operand::new(T::from(Extern { name, args }), effects))
+    }
+}
+
+/// Reassociates every chain of additions and multiplications in an expression, folding their
+/// constants together, without moving anything that has side effects.
+pub fn reassociate<E>(expr: E) -> Outcome<E>
+where
+    E: Expression + From<IntegerLiteral> + From<Add<E>> + From<Multiply<E>>,
+    Reassociate: OwnedAlgebra<E::Signature, E, Reassociated<E>>,
+{
+    let algebra = Reassociate::new();
+    let result = algebra.finish(into_fold(&algebra, expr)).expr;
+    if algebra.rewrites() > 0 {
+        Outcome::Changed(result)
+    } else {
+        Outcome::Unchanged(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Host functions that record each call, so we can check that the calls still happen, in the
+    // same order.
+    fn externs(calls: &Rc<RefCell<Vec<String>>>) -> Externs {
+        let mut externs = Externs::new();
+        for (name, result, value) in [
+            ("f", Type::Int, Number::Int(10)),
+            ("g", Type::Int, Number::Int(20)),
+            ("h", Type::Float, Number::Float(0.5)),
+        ] {
+            let calls = calls.clone();
+            externs.register(name, FunctionType::new(&[], result), move |_| {
+                calls.borrow_mut().push(name.to_string());
+                Ok(value)
+            });
+        }
+        externs
+    }
+
+    fn check(expr: fn() -> ExternExpr, expected: &str) {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let externs = externs(&calls);
+        let before = evaluate_with_externs(&expr(), &externs);
+        let calls_before = calls.replace(Vec::new());
+        let after = reassociate(expr()).into_inner();
+        assert_eq!(after.to_string(), expected);
+        assert_eq!(evaluate_with_externs(&after, &externs), before);
+        assert_eq!(*calls.borrow(), calls_before);
+    }
+
+    #[test]
+    fn gathers_constants_around_effects() {
+        // (1 + f()) + 2 ⇒ 3 + f()
+        check(
+            || {
+                add(
+                    add(integer_literal(1), call_extern("f", vec![])),
+                    integer_literal(2),
+                )
+            },
+            "(3 + f())",
+        );
+        // Chains of different operators are folded separately.
+        check(
+            || {
+                add(
+                    multiply(
+                        integer_literal(2),
+                        multiply(call_extern("f", vec![]), integer_literal(3)),
+                    ),
+                    add(integer_literal(4), integer_literal(5)),
+                )
+            },
+            "((f() * 6) + 9)",
+        );
+    }
+
+    #[test]
+    fn keeps_the_side_of_mixed_operands() {
+        // h() returns a float, so both of these are a type error that names the int first.
+        check(
+            || {
+                add(
+                    add(integer_literal(1), call_extern("h", vec![])),
+                    integer_literal(2),
+                )
+            },
+            "(3 + h())",
+        );
+        // Even when the constants fold to the identity, there's still an int to mix with.
+        check(
+            || {
+                multiply(
+                    multiply(call_extern("h", vec![]), integer_literal(1)),
+                    integer_literal(1),
+                )
+            },
+            "(h() * 1)",
+        );
+    }
+
+    #[test]
+    fn keeps_operands_of_zero_products() {
+        // f() * 0 would be 0, but then f wouldn't get called.
+        check(
+            || multiply(call_extern("f", vec![]), integer_literal(0)),
+            "(f() * 0)",
+        );
+        // And 2.5 * 0 is a type error, not 0.
+        check(
+            || multiply(float_literal(2.5), integer_literal(0)),
+            "(2.5 * 0)",
+        );
+    }
+
+    #[test]
+    fn keeps_chains_that_might_overflow() {
+        // MAX + 1 overflows, so (MAX + 1) * 0 does too.
+        let expr: ExternExpr = multiply(
+            add(integer_literal(i64::MAX), integer_literal(1)),
+            integer_literal(0),
+        );
+        assert!(!reassociate(expr).is_changed());
+        // This overflows when f() is positive, but f() + (MAX - 1) never does.
+        let expr: ExternExpr = add(
+            add(integer_literal(i64::MAX), call_extern("f", vec![])),
+            integer_literal(-1),
+        );
+        assert!(!reassociate(expr).is_changed());
+        // (f() * 2) * 0 overflows when f() is big enough, but f() * 0 never does.
+        let expr: ExternExpr = multiply(
+            multiply(call_extern("f", vec![]), integer_literal(2)),
+            integer_literal(0),
+        );
+        assert!(!reassociate(expr).is_changed());
+        // With two other operands, the constants could end up next to either one.
+        let expr: ExternExpr = add(
+            add(integer_literal(1), call_extern("f", vec![])),
+            add(integer_literal(2), call_extern("g", vec![])),
+        );
+        assert!(!reassociate(expr).is_changed());
+    }
+
+    #[test]
+    fn leaves_chains_without_constants_alone() {
+        let expr: ExternExpr = add(
+            float_literal(1.5),
+            add(call_extern("f", vec![]), float_literal(2.5)),
+        );
+        let result = reassociate(expr);
+        assert!(!result.is_changed());
+        assert_eq!(result.get().to_string(), "(1.5 + (f() + 2.5))");
+    }
+
+    #[test]
+    fn reads_are_operands_too() {
+        // (rate + 1) * (2 * 3) ⇒ (rate + 1) * 6
+        let expr: ProgramExpr = multiply(
+            add(global("rate"), integer_literal(1)),
+            multiply(integer_literal(2), integer_literal(3)),
+        );
+        assert_eq!(reassociate(expr).get().to_string(), "((rate + 1) * 6)");
+    }
+}
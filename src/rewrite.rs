@@ -0,0 +1,886 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A small term-rewriting engine.  Rewrite rules want to look a few levels deep into a tree, copy
+//! subtrees around, and say exactly where they fired — none of which is pleasant with the open-sum
+//! types, whose terms aren't Clone and can only be inspected by climbing through nested Sums.  So
+//! the engine works on `Node`, a uniform (and cloneable) view of an expression, and we convert to
+//! and from the open-sum types at the edges.
+//!
+//! Rules are plain functions that either rewrite a node or decline to.  The `Rewriter` applies them
+//! one step at a time, always to the leftmost-innermost node that some rule accepts, which is the
+//! order that you'd work through an expression by hand.  It can also record a `Proof` of which rule
+//! fired where, which you can replay later to audit the result.
+
+use crate::allocations::record_clone;
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::telemetry::TermKind;
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// A uniform view of an expression: either a literal, or a term with a name (the same names that
+/// telemetry uses) and a list of children.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub enum Node {
+    Literal(i64),
+    Term {
+        kind: &'static str,
+        children: Vec<Node>,
+    },
+}
+
+// Cloning a Node copies the whole tree, so we count each one, to see how much copying the rewriter
+// does.
+
+impl Clone for Node {
+    fn clone(&self) -> Node {
+        record_clone();
+        match self {
+            Node::Literal(value) => Node::Literal(*value),
+            Node::Term { kind, children } => Node::term(kind, children.clone()),
+        }
+    }
+}
+
+impl Node {
+    pub fn term(kind: &'static str, children: Vec<Node>) -> Node {
+        Node::Term { kind, children }
+    }
+
+    /// Returns the kind of term, or `"integer_literal"` for literals.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Node::Literal(_) => IntegerLiteral::NAME,
+            Node::Term { kind, .. } => kind,
+        }
+    }
+
+    pub fn children(&self) -> &[Node] {
+        match self {
+            Node::Literal(_) => &[],
+            Node::Term { children, .. } => children,
+        }
+    }
+
+    /// Returns the subtree at `path`, where each element is the index of a child.
+    pub fn at(&self, path: &[usize]) -> Option<&Node> {
+        match path.split_first() {
+            None => Some(self),
+            Some((index, rest)) => self.children().get(*index)?.at(rest),
+        }
+    }
+
+    /// Returns a copy of this tree with the subtree at `path` replaced.
+    pub fn replace(&self, path: &[usize], replacement: Node) -> Option<Node> {
+        let (index, rest) = match path.split_first() {
+            None => return Some(replacement),
+            Some(split) => split,
+        };
+        match self {
+            Node::Literal(_) => None,
+            Node::Term { kind, children } => {
+                let mut children = children.clone();
+                let child = children.get(*index)?.replace(rest, replacement)?;
+                children[*index] = child;
+                Some(Node::term(kind, children))
+            }
+        }
+    }
+
+    /// The number of nodes in this tree.
+    pub fn size(&self) -> usize {
+        1 + self.children().iter().map(Node::size).sum::<usize>()
+    }
+}
+
+// Converting an expression into a Node is a fold.
+
+/// An algebra that converts an expression into a Node.
+pub struct ToNode;
+
+impl<E> Algebra<IntegerLiteral, E, Node> for ToNode {
+    fn apply<F>(&self, term: &IntegerLiteral, _recurse: F) -> Node
+    where
+        F: FnMut(&E) -> Node,
+    {
+        Node::Literal(term.value)
+    }
+}
+
+macro_rules! to_node_terms {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<E> Algebra<$term<E>, E, Node> for ToNode {
+                fn apply<F>(&self, term: &$term<E>, mut recurse: F) -> Node
+                where
+                    F: FnMut(&E) -> Node,
+                {
+                    Node::term($term::<E>::NAME, vec![$(recurse(&term.$field)),+])
+                }
+            }
+        )+
+    };
+}
+
+to_node_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+);
+
+/// Converts an expression into a Node.
+pub fn to_node<E>(expr: &E) -> Node
+where
+    E: Expression,
+    ToNode: Algebra<E::Signature, E, Node>,
+{
+    mcata(&ToNode, expr)
+}
+
+// Going back can fail, since a rule might produce a kind of term that the expression type doesn't
+// have.  Like FromDyn in ch10a, each kind of term recognizes itself, and uses open recursion to
+// convert its children.
+
+/// Tries to create a term of this type from a Node.
+pub trait FromNode<E>: Sized {
+    fn from_node<F>(node: &Node, from_child: F) -> Option<Self>
+    where
+        F: FnMut(&Node) -> Option<E>;
+}
+
+impl<E> FromNode<E> for IntegerLiteral {
+    fn from_node<F>(node: &Node, _from_child: F) -> Option<Self>
+    where
+        F: FnMut(&Node) -> Option<E>,
+    {
+        match node {
+            Node::Literal(value) => Some(IntegerLiteral { value: *value }),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! from_node_terms {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<E> FromNode<E> for $term<E> {
+                fn from_node<F>(node: &Node, mut from_child: F) -> Option<Self>
+                where
+                    F: FnMut(&Node) -> Option<E>,
+                {
+                    if node.kind() != $term::<E>::NAME {
+                        return None;
+                    }
+                    let mut children = node.children().iter();
+                    let term = $term {
+                        $($field: from_child(children.next()?)?),+
+                    };
+                    match children.next() {
+                        Some(_) => None,
+                        None => Some(term),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+from_node_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+);
+
+impl<E, L, R> FromNode<E> for Sum<L, R>
+where
+    L: FromNode<E>,
+    R: FromNode<E>,
+{
+    fn from_node<F>(node: &Node, mut from_child: F) -> Option<Self>
+    where
+        F: FnMut(&Node) -> Option<E>,
+    {
+        if let Some(lhs) = L::from_node(node, &mut from_child) {
+            return Some(Sum::Left(lhs));
+        }
+        R::from_node(node, from_child).map(Sum::Right)
+    }
+}
+
+/// Converts a Node into an expression, if the expression's signature contains every kind of term
+/// that appears in it.
+pub fn from_node<E>(node: &Node) -> Option<E>
+where
+    E: Expression,
+    E::Signature: FromNode<E>,
+{
+    E::Signature::from_node(node, from_node).map(E::wrap)
+}
+
+/// A named rewrite rule.  The function returns the replacement for a node, or None if the rule
+/// doesn't apply to it.
+#[derive(Clone, Copy)]
+pub struct Rule {
+    pub name: &'static str,
+    pub rewrite: fn(&Node) -> Option<Node>,
+}
+
+/// One application of a rule.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rewrite {
+    pub rule: &'static str,
+    /// Where the rule fired.
+    pub path: Vec<usize>,
+    /// The whole tree, after the rewrite.
+    pub result: Node,
+}
+
+/// Applies a set of rules, one step at a time.
+pub struct Rewriter {
+    rules: Vec<Rule>,
+}
+
+impl Rewriter {
+    pub fn new(rules: Vec<Rule>) -> Rewriter {
+        Rewriter { rules }
+    }
+
+    /// Rewrites the leftmost-innermost node that some rule applies to.  If several rules apply to
+    /// that node, the first one wins.  Returns None if no rule applies anywhere.
+    pub fn step(&self, node: &Node) -> Option<Rewrite> {
+        let mut path = Vec::new();
+        let (rule, replacement) = self.find(node, &mut path)?;
+        let result = node.replace(&path, replacement)?;
+        Some(Rewrite { rule, path, result })
+    }
+
+    fn find(&self, node: &Node, path: &mut Vec<usize>) -> Option<(&'static str, Node)> {
+        for (index, child) in node.children().iter().enumerate() {
+            path.push(index);
+            if let Some(found) = self.find(child, path) {
+                return Some(found);
+            }
+            path.pop();
+        }
+        self.rules
+            .iter()
+            .find_map(|rule| Some((rule.name, (rule.rewrite)(node)?)))
+    }
+
+    /// Returns every step from `node` until no rule applies.  This won't return if the rules can
+    /// rewrite forever; use `normalize_within` if that's a possibility.
+    pub fn steps(&self, node: &Node) -> Vec<Rewrite> {
+        let mut steps: Vec<Rewrite> = Vec::new();
+        while let Some(rewrite) = self.step(steps.last().map_or(node, |last| &last.result)) {
+            steps.push(rewrite);
+        }
+        steps
+    }
+
+    /// Rewrites `node` until no rule applies.  If no rule applies to `node` itself, it's handed
+    /// back without copying it.  Unlike `steps`, this only keeps the current tree around.
+    pub fn normalize<'a>(&self, node: &'a Node) -> Cow<'a, Node> {
+        let mut current = Cow::Borrowed(node);
+        while let Some(rewrite) = self.step(&current) {
+            current = Cow::Owned(rewrite.result);
+        }
+        current
+    }
+}
+
+// A set of rules can easily rewrite forever (think of a commutativity rule), or keep making the
+// tree bigger.  When the rules come from somewhere you don't control, you can put limits on the
+// rewriting, and get back whatever the rewriter had managed so far if it hits one.
+
+/// Limits on how much work a rewriter will do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RewriteLimits {
+    /// The most steps to take.
+    pub max_steps: Option<usize>,
+    /// The largest the tree can get, as a multiple of its original size.
+    pub max_growth: Option<f64>,
+}
+
+impl Default for RewriteLimits {
+    fn default() -> RewriteLimits {
+        RewriteLimits {
+            max_steps: Some(10_000),
+            max_growth: Some(4.0),
+        }
+    }
+}
+
+/// Which limit a rewriter hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Steps(usize),
+    Growth(f64),
+}
+
+/// A rewriter stopped before reaching a normal form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    /// How many steps the rewriter took.
+    pub steps: usize,
+    /// The tree as of the last step that stayed within the limits.
+    pub partial: Node,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.limit {
+            Limit::Steps(max) => write!(f, "rewriting did not finish within {} steps", max),
+            Limit::Growth(max) => write!(
+                f,
+                "rewriting grew the tree by more than {}x after {} steps",
+                max, self.steps
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl Rewriter {
+    /// Rewrites `node` until no rule applies, or until it hits one of the limits.  Like
+    /// `normalize`, this only copies `node` if some rule applies to it.
+    pub fn normalize_within<'a>(
+        &self,
+        node: &'a Node,
+        limits: &RewriteLimits,
+    ) -> Result<Cow<'a, Node>, LimitExceeded> {
+        let original_size = node.size() as f64;
+        let mut current = Cow::Borrowed(node);
+        let mut steps = 0;
+        loop {
+            let next = match self.step(&current) {
+                Some(rewrite) => rewrite.result,
+                None => return Ok(current),
+            };
+            if let Some(max_steps) = limits.max_steps {
+                if steps >= max_steps {
+                    return Err(LimitExceeded {
+                        limit: Limit::Steps(max_steps),
+                        steps,
+                        partial: current.into_owned(),
+                    });
+                }
+            }
+            if let Some(max_growth) = limits.max_growth {
+                if next.size() as f64 > original_size * max_growth {
+                    return Err(LimitExceeded {
+                        limit: Limit::Growth(max_growth),
+                        steps,
+                        partial: current.into_owned(),
+                    });
+                }
+            }
+            current = Cow::Owned(next);
+            steps += 1;
+        }
+    }
+}
+
+// If you want to know *why* an expression was rewritten, you can ask for a proof: the sequence of
+// rules that fired, and where.  A proof is small (it doesn't keep the intermediate trees), and you
+// can replay it to check that each step really was a valid application of its rule.
+
+/// One step of a proof.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProofStep {
+    pub rule: &'static str,
+    pub path: Vec<usize>,
+}
+
+/// The rewrites that turn one tree into another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Proof {
+    pub start: Node,
+    pub steps: Vec<ProofStep>,
+}
+
+/// Why a proof couldn't be replayed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayError {
+    /// The rewriter doesn't have a rule with this name.
+    UnknownRule { step: usize, rule: &'static str },
+    /// There's no node at this step's path.
+    InvalidPath { step: usize },
+    /// The rule doesn't apply to the node at this step's path.
+    RuleDoesNotApply { step: usize, rule: &'static str },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::UnknownRule { step, rule } => {
+                write!(f, "step {}: unknown rule `{}`", step, rule)
+            }
+            ReplayError::InvalidPath { step } => write!(f, "step {}: no node at path", step),
+            ReplayError::RuleDoesNotApply { step, rule } => {
+                write!(f, "step {}: `{}` does not apply", step, rule)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl Proof {
+    /// Applies each step of the proof in turn, checking that every rule applies where the proof
+    /// says it did, and returns the final tree.
+    pub fn replay(&self, rewriter: &Rewriter) -> Result<Node, ReplayError> {
+        let mut node = self.start.clone();
+        for (step, proof_step) in self.steps.iter().enumerate() {
+            let rule = rewriter
+                .rules
+                .iter()
+                .find(|rule| rule.name == proof_step.rule)
+                .ok_or(ReplayError::UnknownRule {
+                    step,
+                    rule: proof_step.rule,
+                })?;
+            let target = node
+                .at(&proof_step.path)
+                .ok_or(ReplayError::InvalidPath { step })?;
+            let replacement = (rule.rewrite)(target).ok_or(ReplayError::RuleDoesNotApply {
+                step,
+                rule: proof_step.rule,
+            })?;
+            node = node
+                .replace(&proof_step.path, replacement)
+                .ok_or(ReplayError::InvalidPath { step })?;
+        }
+        Ok(node)
+    }
+}
+
+/// Prints one step per line, with the path written as a dotted list of child indices (or `root`).
+impl fmt::Display for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, step) in self.steps.iter().enumerate() {
+            let path: Vec<String> = step.path.iter().map(usize::to_string).collect();
+            let path = if path.is_empty() {
+                "root".to_string()
+            } else {
+                path.join(".")
+            };
+            writeln!(f, "{}. {} at {}", index + 1, step.rule, path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Rewriter {
+    /// Rewrites `node` until no rule applies, and returns a proof of how it got there.
+    pub fn normalize_with_proof(&self, node: &Node) -> (Node, Proof) {
+        let mut current = node.clone();
+        let mut steps = Vec::new();
+        while let Some(rewrite) = self.step(&current) {
+            steps.push(ProofStep {
+                rule: rewrite.rule,
+                path: rewrite.path,
+            });
+            current = rewrite.result;
+        }
+        let proof = Proof {
+            start: node.clone(),
+            steps,
+        };
+        (current, proof)
+    }
+}
+
+// The simplest useful set of rules evaluates arithmetic one operation at a time, giving us a
+// small-step evaluator.
+
+fn binary_literals(node: &Node, kind: &str) -> Option<(i64, i64)> {
+    match node {
+        Node::Term { kind: k, children } if *k == kind => match children.as_slice() {
+            [Node::Literal(lhs), Node::Literal(rhs)] => Some((*lhs, *rhs)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Adds two literals.
+pub const ADD_LITERALS: Rule = Rule {
+    name: "add literals",
+    rewrite: |node| {
+        let (lhs, rhs) = binary_literals(node, "add")?;
+        Some(Node::Literal(lhs.checked_add(rhs)?))
+    },
+};
+
+/// Multiplies two literals.
+pub const MULTIPLY_LITERALS: Rule = Rule {
+    name: "multiply literals",
+    rewrite: |node| {
+        let (lhs, rhs) = binary_literals(node, "multiply")?;
+        Some(Node::Literal(lhs.checked_mul(rhs)?))
+    },
+};
+
+/// A rewriter that evaluates arithmetic one operation at a time.
+pub fn small_step() -> Rewriter {
+    Rewriter::new(vec![ADD_LITERALS, MULTIPLY_LITERALS])
+}
+
+// Pairs from ch07 only fail at runtime, when you project out of something that isn't a pair.  Many
+// projections can be resolved statically, though: projecting out of a pair that you just built
+// gives you one of its halves without evaluating anything.  (That makes the result a bit lazier
+// than the original, since the other half is never evaluated, and can't fail.)  And projecting out
+// of a number is certain to fail, so we can report it before anything runs.
+
+// `Node` can hold any children at all, so a pair with the wrong number of them is left alone.
+fn project(node: &Node, kind: &str, index: usize) -> Option<Node> {
+    match node {
+        Node::Term { kind: k, children } if *k == kind => match children.as_slice() {
+            [Node::Term {
+                kind: "pair",
+                children,
+            }] => match children.as_slice() {
+                [first, second] => Some(if index == 0 { first } else { second }.clone()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Rewrites `first(pair(a, b))` to `a`.
+pub const FIRST_OF_PAIR: Rule = Rule {
+    name: "first of pair",
+    rewrite: |node| project(node, First::<()>::NAME, 0),
+};
+
+/// Rewrites `second(pair(a, b))` to `b`.
+pub const SECOND_OF_PAIR: Rule = Rule {
+    name: "second of pair",
+    rewrite: |node| project(node, Second::<()>::NAME, 1),
+};
+
+/// A rewriter that resolves every projection out of a pair literal.
+pub fn pair_fusion() -> Rewriter {
+    Rewriter::new(vec![FIRST_OF_PAIR, SECOND_OF_PAIR])
+}
+
+/// A projection that is certain to fail when evaluated, because its operand is a number.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProjectionError {
+    /// Where the projection is, in the fused tree.
+    pub path: Vec<usize>,
+    /// `"first"` or `"second"`.
+    pub projection: &'static str,
+    /// The kind of term that we'd be projecting out of.
+    pub operand: &'static str,
+}
+
+impl fmt::Display for ProjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` of `{}` can never succeed",
+            self.projection, self.operand
+        )
+    }
+}
+
+impl std::error::Error for ProjectionError {}
+
+fn is_number(node: &Node) -> bool {
+    let kind = node.kind();
+    kind == IntegerLiteral::NAME || kind == Add::<()>::NAME || kind == Multiply::<()>::NAME
+}
+
+fn find_projection_error(node: &Node, path: &mut Vec<usize>) -> Option<ProjectionError> {
+    for (index, child) in node.children().iter().enumerate() {
+        path.push(index);
+        if let Some(error) = find_projection_error(child, path) {
+            return Some(error);
+        }
+        path.pop();
+    }
+    let projection = node.kind();
+    match node.children() {
+        [operand]
+            if (projection == First::<()>::NAME || projection == Second::<()>::NAME)
+                && is_number(operand) =>
+        {
+            Some(ProjectionError {
+                path: path.clone(),
+                projection,
+                operand: operand.kind(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Resolves every projection out of a pair literal, and then checks that no remaining projection
+/// is certain to fail, reporting the leftmost-innermost one if there is.
+pub fn fuse_pairs(node: &Node) -> Result<Cow<'_, Node>, ProjectionError> {
+    let fused = pair_fusion().normalize(node);
+    match find_projection_error(&fused, &mut Vec::new()) {
+        Some(error) => Err(error),
+        None => Ok(fused),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    fn example() -> MultExpr {
+        // (80 * 5) + (2 * 2)
+        add(
+            multiply(integer_literal(80), integer_literal(5)),
+            multiply(integer_literal(2), integer_literal(2)),
+        )
+    }
+
+    #[test]
+    fn can_convert_to_and_from_nodes() {
+        let node = to_node(&example());
+        assert_eq!(node.size(), 7);
+        assert_eq!(node.at(&[1, 0]), Some(&Node::Literal(2)));
+        let expr: MultExpr = from_node(&node).unwrap();
+        assert_eq!(to_node(&expr), node);
+        assert!(from_node::<Expr>(&node).is_none());
+    }
+
+    #[test]
+    fn rewrites_leftmost_innermost_first() {
+        let steps = small_step().steps(&to_node(&example()));
+        let summary: Vec<(&str, Vec<usize>)> = steps
+            .iter()
+            .map(|step| (step.rule, step.path.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("multiply literals", vec![0]),
+                ("multiply literals", vec![1]),
+                ("add literals", vec![]),
+            ]
+        );
+        assert_eq!(steps.last().unwrap().result, Node::Literal(404));
+        let node = to_node(&example());
+        assert_eq!(*small_step().normalize(&node), Node::Literal(404));
+    }
+
+    #[test]
+    fn normalizing_a_normal_form_does_nothing() {
+        let node = Node::Literal(7);
+        assert_eq!(small_step().step(&node), None);
+        assert!(matches!(small_step().normalize(&node), Cow::Borrowed(_)));
+        assert!(matches!(
+            small_step().normalize_within(&node, &RewriteLimits::default()),
+            Ok(Cow::Borrowed(_))
+        ));
+    }
+
+    #[test]
+    fn does_not_rewrite_overflowing_arithmetic() {
+        let expr: Expr = add(integer_literal(i64::MAX), integer_literal(1));
+        let node = to_node(&expr);
+        assert!(matches!(small_step().normalize(&node), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn can_record_and_replay_proofs() {
+        let rewriter = small_step();
+        let start = to_node(&example());
+        let (result, proof) = rewriter.normalize_with_proof(&start);
+        assert_eq!(result, Node::Literal(404));
+        assert_eq!(
+            proof.to_string(),
+            "1. multiply literals at 0\n\
+             2. multiply literals at 1\n\
+             3. add literals at root\n"
+        );
+        assert_eq!(proof.replay(&rewriter), Ok(result));
+    }
+
+    #[test]
+    fn replay_checks_every_step() {
+        let rewriter = small_step();
+        let (_, mut proof) = rewriter.normalize_with_proof(&to_node(&example()));
+        proof.steps.swap(1, 2);
+        assert_eq!(
+            proof.replay(&rewriter),
+            Err(ReplayError::RuleDoesNotApply {
+                step: 1,
+                rule: "add literals"
+            })
+        );
+        let without_multiply = Rewriter::new(vec![ADD_LITERALS]);
+        let (_, proof) = rewriter.normalize_with_proof(&to_node(&example()));
+        assert_eq!(
+            proof.replay(&without_multiply),
+            Err(ReplayError::UnknownRule {
+                step: 0,
+                rule: "multiply literals"
+            })
+        );
+    }
+
+    const COMMUTE: Rule = Rule {
+        name: "commute",
+        rewrite: |node| match node {
+            Node::Term {
+                kind: "add",
+                children,
+            } => Some(Node::term(
+                "add",
+                vec![children[1].clone(), children[0].clone()],
+            )),
+            _ => None,
+        },
+    };
+
+    const ADD_ZERO: Rule = Rule {
+        name: "add zero",
+        rewrite: |node| match node {
+            Node::Literal(_) => Some(Node::term("add", vec![node.clone(), Node::Literal(0)])),
+            _ => None,
+        },
+    };
+
+    #[test]
+    fn stops_rules_that_loop_forever() {
+        let rewriter = Rewriter::new(vec![COMMUTE]);
+        let node = Node::term("add", vec![Node::Literal(1), Node::Literal(2)]);
+        let limits = RewriteLimits {
+            max_steps: Some(3),
+            max_growth: None,
+        };
+        let error = rewriter.normalize_within(&node, &limits).unwrap_err();
+        assert_eq!(error.limit, Limit::Steps(3));
+        assert_eq!(error.steps, 3);
+        assert_eq!(
+            error.partial,
+            Node::term("add", vec![Node::Literal(2), Node::Literal(1)])
+        );
+    }
+
+    #[test]
+    fn stops_rules_that_grow_the_tree() {
+        let rewriter = Rewriter::new(vec![ADD_ZERO]);
+        let error =
+            (rewriter.normalize_within(&Node::Literal(1), &RewriteLimits::default())).unwrap_err();
+        assert_eq!(error.limit, Limit::Growth(4.0));
+        assert!(error.partial.size() <= 4);
+    }
+
+    #[test]
+    fn limits_do_not_affect_terminating_rules() {
+        let node = to_node(&example());
+        assert_eq!(
+            small_step().normalize_within(&node, &RewriteLimits::default()),
+            Ok(Cow::Owned(Node::Literal(404)))
+        );
+        let limits = RewriteLimits {
+            max_steps: Some(3),
+            max_growth: Some(1.0),
+        };
+        assert_eq!(
+            small_step().normalize_within(&node, &limits),
+            Ok(Cow::Owned(Node::Literal(404)))
+        );
+    }
+
+    #[test]
+    fn can_fuse_projections_of_pairs() {
+        use crate::ch07a_pairs::*;
+        use crate::ch07b_generic_evaluation::*;
+        use crate::ch07c_pair_evaluation::*;
+        use crate::ch07d_safer_pair_evaluation::*;
+
+        // first(pair(second(pair(1, 2)) + 3, first(4)))
+        let expr: PairExpr = first(pair(
+            add(
+                second(pair(integer_literal(1), integer_literal(2))),
+                integer_literal(3),
+            ),
+            first(integer_literal(4)),
+        ));
+        assert_eq!(evaluate_any::<SafeIntOrPair, _>(&expr), SafeIntOrPair(None));
+
+        // The projection that fails at runtime is in the half that fusion throws away.
+        let node = to_node(&expr);
+        let fused: PairExpr = from_node(&fuse_pairs(&node).unwrap()).unwrap();
+        assert_eq!(
+            evaluate_any::<SafeIntOrPair, _>(&fused),
+            Some(IntOrPair::Int(5)).into()
+        );
+    }
+
+    #[test]
+    fn flags_projections_that_cannot_succeed() {
+        use crate::ch07a_pairs::*;
+
+        // pair(1, second(first(pair(2 + 3, 4))))
+        let expr: PairExpr = pair(
+            integer_literal(1),
+            second(first(pair(
+                add(integer_literal(2), integer_literal(3)),
+                integer_literal(4),
+            ))),
+        );
+        let error = fuse_pairs(&to_node(&expr)).unwrap_err();
+        assert_eq!(
+            error,
+            ProjectionError {
+                path: vec![1],
+                projection: "second",
+                operand: "add",
+            }
+        );
+        assert_eq!(error.to_string(), "`second` of `add` can never succeed");
+
+        // Nothing is known about projections whose operand isn't a pair or a number.
+        let expr: PairExpr = first(second(integer_literal(1)));
+        assert_eq!(fuse_pairs(&to_node(&expr)).unwrap_err().path, vec![0]);
+        let node = Node::term("first", vec![Node::term("cell_ref", vec![])]);
+        assert!(matches!(fuse_pairs(&node), Ok(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn leaves_malformed_pairs_alone() {
+        let one = Node::Literal(1);
+        for children in [
+            vec![],
+            vec![one.clone()],
+            vec![one.clone(), one.clone(), one],
+        ] {
+            let node = Node::term("second", vec![Node::term("pair", children)]);
+            assert!(matches!(fuse_pairs(&node), Ok(Cow::Borrowed(_))));
+        }
+    }
+}
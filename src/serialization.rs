@@ -0,0 +1,471 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Serialization for terms, signatures, and expressions.  The `Serialize` and `Deserialize` traits
+//! follow the shape of the ones in the `serde` crate, but without the dependency (like the fuzz
+//! module's `Arbitrary`), and with a much smaller data model: a `Value` is either an integer, or an
+//! object that maps names to values.  Values can be written and read as JSON.
+//!
+//! Each term is *externally tagged* with its telemetry name, the way serde represents an enum
+//! variant by default: `{"add": {"lhs": ..., "rhs": ...}}`.  A Sum doesn't add anything of its own,
+//! and deserializing a Sum looks at the tag to decide which side to build, so the order of the
+//! terms in a signature doesn't matter.  An expression serialized from one signature can be
+//! deserialized into any other signature that has all of the terms it uses.
+//!
+//! Only built with the `serialization` feature.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch07a_pairs::*;
+use crate::ch08a_expressions::*;
+use crate::telemetry::TermKind;
+
+use std::fmt;
+
+/// The data model: an integer, or an object with named fields, in order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Object(Vec<(String, Value)>),
+}
+
+/// Why a value couldn't be deserialized.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeserializeError {
+    /// The input isn't valid JSON (or uses parts of JSON that a Value can't hold).
+    Syntax { offset: usize },
+    /// The value is tagged with a term that the signature doesn't have.
+    UnknownTerm(String),
+    /// The value doesn't have the shape that the term expects.
+    Malformed { term: &'static str },
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeserializeError::Syntax { offset } => write!(f, "invalid JSON at offset {}", offset),
+            DeserializeError::UnknownTerm(tag) => write!(f, "signature has no `{}` term", tag),
+            DeserializeError::Malformed { term } => write!(f, "malformed `{}` term", term),
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+/// A type that can be converted into a Value.
+pub trait Serialize {
+    fn serialize(&self) -> Value;
+}
+
+/// A type that can be built from a Value.
+pub trait Deserialize: Sized {
+    /// Whether a value tagged with `tag` could be one of these.
+    fn accepts(tag: &str) -> bool;
+    fn deserialize(value: &Value) -> Result<Self, DeserializeError>;
+}
+
+fn tag(value: &Value) -> Option<(&str, &Value)> {
+    match value {
+        Value::Object(fields) => match fields.as_slice() {
+            [(tag, contents)] => Some((tag, contents)),
+            _ => None,
+        },
+        Value::Int(_) => None,
+    }
+}
+
+fn tagged(term: &'static str, fields: Vec<(&str, Value)>) -> Value {
+    let fields = (fields.into_iter())
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+    Value::Object(vec![(term.to_string(), Value::Object(fields))])
+}
+
+/// Returns the named field of a term, which must be tagged with `term`.
+fn field<'a>(
+    value: &'a Value,
+    term: &'static str,
+    name: &str,
+) -> Result<&'a Value, DeserializeError> {
+    let malformed = DeserializeError::Malformed { term };
+    match tag(value) {
+        Some((tag, Value::Object(fields))) if tag == term => (fields.iter())
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+            .ok_or(malformed),
+        Some((tag, _)) if tag == term => Err(malformed),
+        Some((tag, _)) => Err(DeserializeError::UnknownTerm(tag.to_string())),
+        None => Err(malformed),
+    }
+}
+
+impl Serialize for IntegerLiteral {
+    fn serialize(&self) -> Value {
+        tagged(Self::NAME, vec![("value", Value::Int(self.value))])
+    }
+}
+
+impl Deserialize for IntegerLiteral {
+    fn accepts(tag: &str) -> bool {
+        tag == Self::NAME
+    }
+
+    fn deserialize(value: &Value) -> Result<IntegerLiteral, DeserializeError> {
+        match field(value, Self::NAME, "value")? {
+            Value::Int(value) => Ok(IntegerLiteral { value: *value }),
+            Value::Object(_) => Err(DeserializeError::Malformed { term: Self::NAME }),
+        }
+    }
+}
+
+macro_rules! serialize_terms {
+    ($($term:ident { $($field:ident),+ }),+ $(,)?) => {
+        $(
+            impl<E: Serialize> Serialize for $term<E> {
+                fn serialize(&self) -> Value {
+                    tagged(Self::NAME, vec![$((stringify!($field), self.$field.serialize())),+])
+                }
+            }
+
+            impl<E: Deserialize> Deserialize for $term<E> {
+                fn accepts(tag: &str) -> bool {
+                    tag == Self::NAME
+                }
+
+                fn deserialize(value: &Value) -> Result<$term<E>, DeserializeError> {
+                    Ok($term {
+                        $($field: E::deserialize(field(value, Self::NAME, stringify!($field))?)?),+
+                    })
+                }
+            }
+        )+
+    };
+}
+
+serialize_terms!(
+    Add { lhs, rhs },
+    Multiply { lhs, rhs },
+    Pair { first, second },
+    First { pair },
+    Second { pair },
+);
+
+impl<L: Serialize, R: Serialize> Serialize for Sum<L, R> {
+    fn serialize(&self) -> Value {
+        match self {
+            Sum::Left(l) => l.serialize(),
+            Sum::Right(r) => r.serialize(),
+        }
+    }
+}
+
+impl<L: Deserialize, R: Deserialize> Deserialize for Sum<L, R> {
+    fn accepts(tag: &str) -> bool {
+        L::accepts(tag) || R::accepts(tag)
+    }
+
+    fn deserialize(value: &Value) -> Result<Sum<L, R>, DeserializeError> {
+        match tag(value) {
+            Some((tag, _)) if L::accepts(tag) => L::deserialize(value).map(Sum::Left),
+            Some((tag, _)) if R::accepts(tag) => R::deserialize(value).map(Sum::Right),
+            Some((tag, _)) => Err(DeserializeError::UnknownTerm(tag.to_string())),
+            None => Err(DeserializeError::Malformed { term: "expression" }),
+        }
+    }
+}
+
+// Like EvaluateInt, each expression type needs its own impl; a blanket impl for every Expression
+// would have recursive bounds that the compiler can never prove.
+
+macro_rules! serialize_expressions {
+    ($($expr:ident),+ $(,)?) => {
+        $(
+            impl Serialize for $expr {
+                fn serialize(&self) -> Value {
+                    self.unwrap().serialize()
+                }
+            }
+
+            impl Deserialize for $expr {
+                fn accepts(tag: &str) -> bool {
+                    <$expr as Expression>::Signature::accepts(tag)
+                }
+
+                fn deserialize(value: &Value) -> Result<$expr, DeserializeError> {
+                    Deserialize::deserialize(value).map($expr::wrap)
+                }
+            }
+        )+
+    };
+}
+
+serialize_expressions!(Expr, MultExpr, NoAddExpr, PairExpr);
+
+// Values are written as compact JSON.
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Object(fields) => {
+                f.write_str("{")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+// And read back in with a small recursive-descent parser, which only understands the parts of JSON
+// that a Value can hold: objects, string keys, and integers.
+
+struct JsonParser<'a> {
+    input: &'a [u8],
+    offset: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self) -> DeserializeError {
+        DeserializeError::Syntax {
+            offset: self.offset,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .input
+            .get(self.offset)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.offset += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.input.get(self.offset) == Some(&byte) {
+            self.offset += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), DeserializeError> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DeserializeError> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.input.get(self.offset) {
+                Some(b'"') => break,
+                Some(b'\\') => match self.input.get(self.offset + 1) {
+                    Some(escaped @ b'"') | Some(escaped @ b'\\') => {
+                        bytes.push(*escaped);
+                        self.offset += 2;
+                    }
+                    _ => return Err(self.error()),
+                },
+                Some(byte) => {
+                    bytes.push(*byte);
+                    self.offset += 1;
+                }
+                None => return Err(self.error()),
+            }
+        }
+        self.offset += 1;
+        String::from_utf8(bytes).map_err(|_| self.error())
+    }
+
+    fn value(&mut self) -> Result<Value, DeserializeError> {
+        self.skip_whitespace();
+        if self.eat(b'{') {
+            let mut fields = Vec::new();
+            if !self.eat(b'}') {
+                loop {
+                    let name = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((name, self.value()?));
+                    if self.eat(b'}') {
+                        break;
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            return Ok(Value::Object(fields));
+        }
+        let start = self.offset;
+        if self.input.get(self.offset) == Some(&b'-') {
+            self.offset += 1;
+        }
+        while self.input.get(self.offset).is_some_and(u8::is_ascii_digit) {
+            self.offset += 1;
+        }
+        let digits = std::str::from_utf8(&self.input[start..self.offset]).unwrap();
+        digits.parse().map(Value::Int).map_err(|_| {
+            self.offset = start;
+            self.error()
+        })
+    }
+}
+
+impl Value {
+    /// Parses a Value from JSON.
+    pub fn from_json(input: &str) -> Result<Value, DeserializeError> {
+        let mut parser = JsonParser {
+            input: input.as_bytes(),
+            offset: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.offset < input.len() {
+            return Err(parser.error());
+        }
+        Ok(value)
+    }
+}
+
+/// Serializes anything into JSON.
+pub fn to_json<T: Serialize>(value: &T) -> String {
+    value.serialize().to_string()
+}
+
+/// Deserializes anything from JSON.
+pub fn from_json<T: Deserialize>(input: &str) -> Result<T, DeserializeError> {
+    T::deserialize(&Value::from_json(input)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+
+    // The same terms as MultExpr, in the opposite order.
+    type FlippedSig<E> = Sum![Add<E>, Multiply<E>, IntegerLiteral];
+    struct FlippedExpr(Box<FlippedSig<FlippedExpr>>);
+
+    impl Expression for FlippedExpr {
+        type Signature = FlippedSig<FlippedExpr>;
+        fn wrap(sig: Self::Signature) -> Self {
+            Self(Box::new(sig))
+        }
+        fn unwrap(&self) -> &Self::Signature {
+            &self.0
+        }
+        fn into_signature(self) -> Self::Signature {
+            *self.0
+        }
+    }
+
+    serialize_expressions!(FlippedExpr);
+
+    #[test]
+    fn can_serialize_terms() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(-4),
+        );
+        assert_eq!(
+            to_json(&expr),
+            concat!(
+                r#"{"add":{"lhs":{"multiply":{"lhs":{"integer_literal":{"value":80}},"#,
+                r#""rhs":{"integer_literal":{"value":5}}}},"#,
+                r#""rhs":{"integer_literal":{"value":-4}}}}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn can_round_trip_expressions() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(-4),
+        );
+        let json = to_json(&expr);
+        let parsed: MultExpr = from_json(&json).unwrap();
+        assert_eq!(parsed.to_string(), expr.to_string());
+        assert_eq!(to_json(&parsed), json);
+
+        let expr: PairExpr = first(pair(integer_literal(1), integer_literal(2)));
+        let parsed: PairExpr = from_json(&to_json(&expr)).unwrap();
+        assert_eq!(to_json(&parsed), to_json(&expr));
+    }
+
+    #[test]
+    fn signature_order_does_not_matter() {
+        let expr: MultExpr = multiply(
+            integer_literal(6),
+            add(integer_literal(3), integer_literal(4)),
+        );
+        let flipped: FlippedExpr = from_json(&to_json(&expr)).unwrap();
+        assert_eq!(to_json(&flipped), to_json(&expr));
+        let back: MultExpr = from_json(&to_json(&flipped)).unwrap();
+        assert_eq!(back.to_string(), "(6 * (3 + 4))");
+        // Fields can come in any order, and whitespace is allowed.
+        let swapped = r#"{ "add": { "rhs": {"integer_literal": {"value": 2}},
+                                     "lhs": {"integer_literal": {"value": 1}} } }"#;
+        let expr: Expr = from_json(swapped).unwrap();
+        assert_eq!(expr.to_string(), "(1 + 2)");
+    }
+
+    #[test]
+    fn reports_errors() {
+        let expr: MultExpr = add(integer_literal(1), integer_literal(2));
+        assert_eq!(
+            from_json::<NoAddExpr>(&to_json(&expr)).err(),
+            Some(DeserializeError::UnknownTerm("add".to_string()))
+        );
+        assert_eq!(
+            from_json::<Expr>(r#"{"add":{"lhs":{"integer_literal":{"value":1}}}}"#).err(),
+            Some(DeserializeError::Malformed { term: "add" })
+        );
+        assert_eq!(
+            from_json::<Expr>(r#"{"add":{"lhs":1,"rhs":2}}"#).err(),
+            Some(DeserializeError::Malformed { term: "expression" })
+        );
+        assert_eq!(
+            from_json::<Expr>(r#"{"integer_literal":{"value":1}} x"#).err(),
+            Some(DeserializeError::Syntax { offset: 32 })
+        );
+        assert_eq!(
+            Value::from_json(r#"{"a\"b":1}"#),
+            Ok(Value::Object(vec![("a\"b".to_string(), Value::Int(1))]))
+        );
+    }
+}
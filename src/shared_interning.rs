@@ -0,0 +1,349 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! The interning module's `Interner` is scoped to one algorithm on one thread: its handles are
+//! `Rc`s, and interning takes `&mut self`.  When several threads are building expressions at once
+//! — like the workers in the parallel module — and want to share one hash-consing pool, we need
+//! handles that can cross threads, and a table that they can all add to at the same time.
+//!
+//! The obvious answer is to put the whole table behind one `Mutex`, but then every thread waits
+//! on every other thread's lookups.  Instead, a `ShardedInterner` splits its table into shards,
+//! each with its own lock, and picks the shard for a value from its hash.  Two threads only wait
+//! on each other when they happen to be interning values that land in the same shard.  Since the
+//! shard is a function of the value, equal values always meet in the same shard, and so there's
+//! still exactly one copy of each.
+
+use crate::ch08a_expressions::*;
+use crate::ch09a_mendler::*;
+use crate::rewrite::*;
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// A handle to a value in a `ShardedInterner`, which is equal to another handle only if they
+/// point at the same value.  Unlike `Interned`, these can be sent to and shared with other
+/// threads.
+pub struct SharedInterned<T>(Arc<T>);
+
+impl<T> Clone for SharedInterned<T> {
+    fn clone(&self) -> Self {
+        SharedInterned(Arc::clone(&self.0))
+    }
+}
+
+impl<T> PartialEq for SharedInterned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Eq for SharedInterned<T> {}
+
+impl<T> Hash for SharedInterned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).hash(state)
+    }
+}
+
+impl<T> Deref for SharedInterned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SharedInterned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// How many shards a `ShardedInterner` has by default.  This only needs to be comfortably more
+/// than the number of threads, so that they rarely collide.
+pub const DEFAULT_SHARDS: usize = 64;
+
+/// A table with one copy of each distinct value, which any number of threads can add to at once.
+pub struct ShardedInterner<T> {
+    shards: Vec<Mutex<HashSet<Arc<T>>>>,
+    hasher: RandomState,
+}
+
+impl<T> Default for ShardedInterner<T> {
+    fn default() -> Self {
+        ShardedInterner::with_shards(DEFAULT_SHARDS)
+    }
+}
+
+impl<T> ShardedInterner<T> {
+    /// Creates an interner whose table is split into `shards` pieces (at least one).
+    pub fn with_shards(shards: usize) -> ShardedInterner<T> {
+        ShardedInterner {
+            shards: (0..shards.max(1))
+                .map(|_| Mutex::new(HashSet::new()))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    // A thread that panics while holding a shard's lock can't leave the shard half-updated, since
+    // HashSet::insert either happens or it doesn't.  So there's no reason to let one panic poison
+    // the interner for every other thread.
+    fn lock(shard: &Mutex<HashSet<Arc<T>>>) -> MutexGuard<'_, HashSet<Arc<T>>> {
+        shard
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T: Eq + Hash> ShardedInterner<T> {
+    pub fn new() -> ShardedInterner<T> {
+        ShardedInterner::default()
+    }
+
+    fn shard_for(&self, value: &T) -> &Mutex<HashSet<Arc<T>>> {
+        let hash = self.hasher.hash_one(value);
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    /// Returns the handle for `value`, adding it to the table if it's not already there.  Only
+    /// locks the one shard that `value` belongs to.
+    pub fn intern(&self, value: T) -> SharedInterned<T> {
+        let mut shard = Self::lock(self.shard_for(&value));
+        if let Some(existing) = shard.get(&value) {
+            return SharedInterned(Arc::clone(existing));
+        }
+        let value = Arc::new(value);
+        shard.insert(Arc::clone(&value));
+        SharedInterned(value)
+    }
+
+    /// The number of distinct values in the table.  Other threads can add values while we're
+    /// counting, so this is only a snapshot.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| Self::lock(shard).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every value that no handle refers to anymore.  Returns how many were removed.  Like
+    /// `Interner::collect_garbage`, this keeps going until removing nodes doesn't release any more
+    /// children.  Each shard is locked separately, so other threads can keep interning while this
+    /// runs; a value that one of them interns concurrently is never removed out from under it,
+    /// since the returned handle keeps its count above one.
+    pub fn collect_garbage(&self) -> usize {
+        let mut removed = 0;
+        loop {
+            let mut removed_this_round = 0;
+            for shard in &self.shards {
+                let mut shard = Self::lock(shard);
+                let len = shard.len();
+                shard.retain(|value| Arc::strong_count(value) > 1);
+                removed_this_round += len - shard.len();
+            }
+            if removed_this_round == 0 {
+                return removed;
+            }
+            removed += removed_this_round;
+        }
+    }
+}
+
+// Interned expressions look just like the interning module's `Shape`, but with shared handles for
+// their children.
+
+/// One node of an expression in a `ShardedInterner`.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub enum SharedShape {
+    Literal(i64),
+    Term {
+        kind: &'static str,
+        children: Vec<SharedInterned<SharedShape>>,
+    },
+}
+
+impl SharedShape {
+    pub fn children(&self) -> &[SharedInterned<SharedShape>] {
+        match self {
+            SharedShape::Literal(_) => &[],
+            SharedShape::Term { children, .. } => children,
+        }
+    }
+}
+
+impl SharedInterned<SharedShape> {
+    /// Copies the interned subtree back out into a tree.
+    pub fn to_tree(&self) -> Node {
+        match &**self {
+            SharedShape::Literal(value) => Node::Literal(*value),
+            SharedShape::Term { kind, children } => {
+                Node::term(kind, children.iter().map(SharedInterned::to_tree).collect())
+            }
+        }
+    }
+}
+
+impl ShardedInterner<SharedShape> {
+    /// Interns every subtree of `tree`, and returns the handle for the whole thing.
+    pub fn intern_tree(&self, tree: &Node) -> SharedInterned<SharedShape> {
+        let shape = match tree {
+            Node::Literal(value) => SharedShape::Literal(*value),
+            Node::Term { kind, children } => SharedShape::Term {
+                kind,
+                children: children
+                    .iter()
+                    .map(|child| self.intern_tree(child))
+                    .collect(),
+            },
+        };
+        self.intern(shape)
+    }
+
+    /// Interns every subexpression of `expr`.
+    pub fn intern_expr<E>(&self, expr: &E) -> SharedInterned<SharedShape>
+    where
+        E: Expression,
+        ToNode: Algebra<E::Signature, E, Node>,
+    {
+        self.intern_tree(&to_node(expr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::ch05a_multiplication::*;
+    use crate::ch10a_dynamic_terms::*;
+    use crate::ch10b_plugin_registry::*;
+    use crate::parallel::*;
+
+    use std::thread;
+
+    fn one_plus_two() -> MultExpr {
+        add(integer_literal(1), integer_literal(2))
+    }
+
+    #[test]
+    fn equal_subtrees_are_the_same_handle() {
+        let interner = ShardedInterner::new();
+        // (1 + 2) * (1 + 2)
+        let expr: MultExpr = multiply(one_plus_two(), one_plus_two());
+        let product = interner.intern_expr(&expr);
+        let children = product.children();
+        assert_eq!(children[0], children[1]);
+        assert_eq!(interner.len(), 4);
+        assert_eq!(interner.intern_expr(&one_plus_two()), children[0]);
+        assert_eq!(interner.len(), 4);
+        assert_eq!(product.to_tree(), to_node(&expr));
+    }
+
+    #[test]
+    fn works_with_a_single_shard() {
+        let interner = ShardedInterner::with_shards(0);
+        assert_eq!(interner.shard_count(), 1);
+        let a = interner.intern_expr(&one_plus_two());
+        let b = interner.intern_expr(&one_plus_two());
+        assert_eq!(a, b);
+        assert_eq!(interner.len(), 3);
+    }
+
+    #[test]
+    fn threads_share_one_copy_of_each_subtree() {
+        let interner = ShardedInterner::new();
+        let handles = thread::scope(|scope| {
+            let workers = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..100)
+                            .map(|i| {
+                                let expr: MultExpr = multiply(
+                                    integer_literal(i),
+                                    add(integer_literal(i), integer_literal(1)),
+                                );
+                                interner.intern_expr(&expr)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        for other in &handles[1..] {
+            assert_eq!(&handles[0], other);
+        }
+        // Literals 0 through 99, plus a sum and a product for each i.
+        assert_eq!(interner.len(), 100 + 2 * 100);
+    }
+
+    #[test]
+    fn parallel_parser_can_share_the_pool() {
+        let input = (0..200)
+            .map(|i| format!("(add {} (multiply 2 3))", i % 10))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let interner = ShardedInterner::new();
+        let results = parse_parallel(
+            &input,
+            4,
+            || Registry::with_plugins(&[&ArithmeticPlugin]),
+            |_, parsed| {
+                let expr = from_dyn::<MultExpr>(&parsed.unwrap()).unwrap();
+                interner.intern_expr(&expr)
+            },
+        );
+        assert_eq!(results[3].1, results[13].1);
+        assert_ne!(results[3].1, results[4].1);
+        // Literals 0 through 9 (which include 2 and 3), the product, and ten sums.
+        assert_eq!(interner.len(), 10 + 1 + 10);
+    }
+
+    #[test]
+    fn can_collect_garbage() {
+        let interner = ShardedInterner::new();
+        let sum = interner.intern_expr(&one_plus_two());
+        let product = interner.intern_expr(&multiply::<MultExpr>(
+            integer_literal(1),
+            integer_literal(3),
+        ));
+        assert_eq!(interner.len(), 5);
+        drop(product);
+        // The product and the 3 are gone, but 1 is still part of the sum.
+        assert_eq!(interner.collect_garbage(), 2);
+        assert_eq!(interner.len(), 3);
+        drop(sum);
+        assert_eq!(interner.collect_garbage(), 3);
+        assert!(interner.is_empty());
+    }
+}
@@ -0,0 +1,324 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Structured telemetry for embedders who want to know where the time goes: a span around the
+//! evaluation of every node (with its kind of term, the size of its subtree, and how long it
+//! took), and around larger units of work like parsing or a whole pass over an expression.  (The
+//! pipelines in the `passes` module report a span for each pass that they run.)
+//!
+//! This follows the shape of the `tracing` crate — spans are entered and exited, and a
+//! `Subscriber` decides what to do with them — but only needs std.  A subscriber that forwards to
+//! `tracing` (or anything else) is a few lines of code.
+//!
+//! Instrumentation works with any Mendler-style algebra from ch09a.  `Instrumented` wraps the
+//! algebra, and reports a span for each node that it folds.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
+use crate::ch07a_pairs::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::ch08a_expressions::*;
+use crate::ch08e_sugar::Negate;
+use crate::ch09a_mendler::*;
+use crate::ch09k_variadic_terms::{AddN, MulN};
+use crate::ch10a_dynamic_terms::*;
+use crate::ch10b_plugin_registry::*;
+
+use std::time::Duration;
+use std::time::Instant;
+
+/// A finished span.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanData {
+    /// The kind of term, for evaluation spans; the name of the pass, for a `passes::Pipeline`;
+    /// otherwise the kind of work (like `"parse"`).
+    pub name: &'static str,
+    /// The number of nodes in the subtree, for evaluation spans; the number of bytes of input, for
+    /// parsing.  Pipeline passes don't know how big their expression is, and report zero.
+    pub size: usize,
+    pub duration: Duration,
+}
+
+/// Receives spans as they're entered and exited.  Spans nest: every span entered while another is
+/// open is exited before it.
+pub trait Subscriber {
+    fn enter(&self, name: &'static str);
+    fn exit(&self, span: &SpanData);
+}
+
+/// A subscriber that ignores everything.
+pub struct NoSubscriber;
+
+impl Subscriber for NoSubscriber {
+    fn enter(&self, _name: &'static str) {}
+    fn exit(&self, _span: &SpanData) {}
+}
+
+/// Runs `f` inside of a span.
+pub fn in_span<T, F>(subscriber: &dyn Subscriber, name: &'static str, size: usize, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let mut size = size;
+    in_span_with_size(subscriber, name, &mut size, |_| f())
+}
+
+/// The name that a kind of term is reported under.
+pub trait TermKind {
+    const NAME: &'static str;
+}
+
+impl TermKind for IntegerLiteral {
+    const NAME: &'static str = "integer_literal";
+}
+
+impl<E> TermKind for Add<E> {
+    const NAME: &'static str = "add";
+}
+
+impl<E> TermKind for Multiply<E> {
+    const NAME: &'static str = "multiply";
+}
+
+impl<E> TermKind for Subtract<E> {
+    const NAME: &'static str = "subtract";
+}
+
+impl<E> TermKind for Divide<E> {
+    const NAME: &'static str = "divide";
+}
+
+impl<E> TermKind for Modulo<E> {
+    const NAME: &'static str = "modulo";
+}
+
+impl<E> TermKind for IntDiv<E> {
+    const NAME: &'static str = "int_div";
+}
+
+impl<E> TermKind for Pair<E> {
+    const NAME: &'static str = "pair";
+}
+
+impl<E> TermKind for First<E> {
+    const NAME: &'static str = "first";
+}
+
+impl<E> TermKind for Second<E> {
+    const NAME: &'static str = "second";
+}
+
+impl<E> TermKind for Negate<E> {
+    const NAME: &'static str = "negate";
+}
+
+impl<E> TermKind for AddN<E> {
+    const NAME: &'static str = "add_n";
+}
+
+impl<E> TermKind for MulN<E> {
+    const NAME: &'static str = "mul_n";
+}
+
+/// The result of an instrumented algebra, along with the size of the subtree that produced it.
+pub struct Observed<V> {
+    pub value: V,
+    pub size: usize,
+}
+
+/// Wraps an algebra so that it reports a span for every node that it folds.
+pub struct Instrumented<'a, A> {
+    pub algebra: A,
+    pub subscriber: &'a dyn Subscriber,
+}
+
+// We can't write a single impl for every term type, because it would overlap with ch09a's impl for
+// Sum.  So we write one impl per term type, with a macro; adding a new term only takes a TermKind
+// impl and a line here.
+
+macro_rules! instrument_terms {
+    ($($term:ident $(<$E:ident>)?),+ $(,)?) => {
+        $(
+            impl<A, V, E> Algebra<$term $(<$E>)?, E, Observed<V>> for Instrumented<'_, A>
+            where
+                A: Algebra<$term $(<$E>)?, E, V>,
+            {
+                fn apply<F>(&self, term: &$term $(<$E>)?, mut recurse: F) -> Observed<V>
+                where
+                    F: FnMut(&E) -> Observed<V>,
+                {
+                    let name = <$term $(<$E>)? as TermKind>::NAME;
+                    let mut size = 1;
+                    let value = in_span_with_size(self.subscriber, name, &mut size, |size| {
+                        self.algebra.apply(term, |subexpr| {
+                            let observed = recurse(subexpr);
+                            *size += observed.size;
+                            observed.value
+                        })
+                    });
+                    Observed { value, size }
+                }
+            }
+        )+
+    };
+}
+
+/// Like `in_span`, but for work that only knows its size once it's done.
+fn in_span_with_size<T, F>(
+    subscriber: &dyn Subscriber,
+    name: &'static str,
+    size: &mut usize,
+    f: F,
+) -> T
+where
+    F: FnOnce(&mut usize) -> T,
+{
+    subscriber.enter(name);
+    let start = Instant::now();
+    let result = f(size);
+    subscriber.exit(&SpanData {
+        name,
+        size: *size,
+        duration: start.elapsed(),
+    });
+    result
+}
+
+instrument_terms!(
+    IntegerLiteral,
+    Add<E>,
+    Multiply<E>,
+    Pair<E>,
+    First<E>,
+    Second<E>
+);
+
+/// Folds an expression with an algebra, reporting a span for every node.
+pub fn instrumented_mcata<A, E, V>(algebra: A, subscriber: &dyn Subscriber, expr: &E) -> V
+where
+    E: Expression,
+    for<'a> Instrumented<'a, A>: Algebra<E::Signature, E, Observed<V>>,
+{
+    let instrumented = Instrumented {
+        algebra,
+        subscriber,
+    };
+    mcata(&instrumented, expr).value
+}
+
+/// Parses an expression with a plugin registry, inside of a `"parse"` span.
+pub fn instrumented_parse(
+    registry: &Registry,
+    subscriber: &dyn Subscriber,
+    input: &str,
+) -> Result<DynExpr, ParseError> {
+    in_span(subscriber, "parse", input.len(), || registry.parse(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::passes::*;
+
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: RefCell<Vec<String>>,
+    }
+
+    impl Subscriber for Recorder {
+        fn enter(&self, name: &'static str) {
+            self.events.borrow_mut().push(format!("enter {}", name));
+        }
+
+        fn exit(&self, span: &SpanData) {
+            (self.events.borrow_mut()).push(format!("exit {} (size {})", span.name, span.size));
+        }
+    }
+
+    #[test]
+    fn reports_a_span_for_every_node() {
+        let recorder = Recorder::default();
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let value: i64 = instrumented_mcata(Evaluator, &recorder, &expr);
+        assert_eq!(value, 404);
+        assert_eq!(
+            recorder.events.into_inner(),
+            vec![
+                "enter add",
+                "enter multiply",
+                "enter integer_literal",
+                "exit integer_literal (size 1)",
+                "enter integer_literal",
+                "exit integer_literal (size 1)",
+                "exit multiply (size 3)",
+                "enter integer_literal",
+                "exit integer_literal (size 1)",
+                "exit add (size 5)",
+            ]
+        );
+    }
+
+    #[test]
+    fn works_with_any_algebra() {
+        let expr: PairExpr = first(pair(integer_literal(7), integer_literal(6)));
+        let depth: usize = instrumented_mcata(Depth, &NoSubscriber, &expr);
+        assert_eq!(depth, 3);
+    }
+
+    #[test]
+    fn reports_a_span_for_parsing() {
+        let recorder = Recorder::default();
+        let registry = Registry::with_plugins(&[&ArithmeticPlugin]);
+        let input = "(add 1 2)";
+        assert!(instrumented_parse(&registry, &recorder, input).is_ok());
+        assert_eq!(
+            recorder.events.into_inner(),
+            vec!["enter parse", "exit parse (size 9)"]
+        );
+    }
+
+    #[test]
+    fn reports_a_span_for_every_pass() {
+        let recorder = Recorder::default();
+        let pipeline = Pipeline::new()
+            .with_pass("halve", |value: i64| {
+                if value % 2 == 0 {
+                    Outcome::Changed(value / 2)
+                } else {
+                    Outcome::Unchanged(value)
+                }
+            })
+            .with_pass("decrement", |value: i64| Outcome::Changed(value - 1));
+        let result = pipeline.instrumented_run(&recorder, 10);
+        assert_eq!(result, Outcome::Changed(4));
+        assert_eq!(
+            recorder.events.into_inner(),
+            vec![
+                "enter halve",
+                "exit halve (size 0)",
+                "enter decrement",
+                "exit decrement (size 0)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! Stack-safe recursion schemes.  Every operation on our open-sum expressions recurses once per
+//! level of nesting — folding them, building them, even dropping them — so a deep enough
+//! expression overflows the native stack no matter what the operation is.  The drivers in this
+//! module do the same jobs as ch09e's `cata` (and friends), but keep their work on the heap
+//! instead.
+//!
+//! They work in two phases.  First we take the expression apart one layer at a time, using
+//! ch09e's `Functor` to swap each layer's children for the *slots* where those children will be
+//! stored.  That gives us a flat table of layers, where every child has a larger slot than its
+//! parent.  Then we walk the table backwards, so that every layer's children are finished before
+//! we get to it, and use `fmap` again to swap each slot for the child's result.
+//!
+//! Any operation that can be written as an algebra over the functor can use these drivers, and
+//! so can anything that builds an expression from a seed, or rewrites one bottom-up.  Out of the
+//! box, that covers evaluation (with ch08b's `Eval` impls), metrics (ch08i), compiling for the
+//! stack machine (vm), and dropping: the expression types from ch02 through ch08a, and ch08e's
+//! `NegateExpr`, take themselves apart without recursing when they're dropped.
+//!
+//! Not everything fits, though.  An operation whose result for a term contains its children's
+//! results — printing with Display, or converting with rewrite's `to_node` or dump's `dump_tree` —
+//! would have to copy those results at every level, which is quadratic in the depth.  Those still
+//! recurse, so bound the depth of untrusted input (see the limits module) before using them.
+
+use crate::ch02_open_sum::*;
+use crate::ch04_smart_constructors::*;
+use crate::ch05a_multiplication::*;
+use crate::ch05d_subtraction::*;
+use crate::ch07a_pairs::*;
+use crate::ch07e_division::*;
+use crate::ch07f_modulo::*;
+use crate::ch08a_expressions::*;
+use crate::ch08b_open_recursion_evaluation::*;
+use crate::ch08e_sugar::*;
+use crate::ch09e_functor::*;
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::mem;
+
+/// Where a layer is stored in the table of layers.
+pub type Slot = usize;
+
+/// Takes some seeds apart one layer at a time, breadth-first, replacing each layer's children with
+/// the slots that they will occupy.  The first seed ends up in slot 0.
+fn unfold<Seed, L, M>(seed: Seed, mut split: impl FnMut(Seed) -> L) -> Vec<M>
+where
+    L: Functor<Seed, Slot, Output = M>,
+{
+    let mut layers = Vec::new();
+    let mut pending = VecDeque::new();
+    pending.push_back(seed);
+    while let Some(seed) = pending.pop_front() {
+        // The layer that we're splitting goes in slot `base`, and each seed that's still pending
+        // has already been given the next slot after that.
+        let base = layers.len();
+        let layer = split(seed).fmap(|child| {
+            pending.push_back(child);
+            base + pending.len()
+        });
+        layers.push(layer);
+    }
+    layers
+}
+
+/// Folds a table of layers from the last slot back to the first, returning the result for slot 0.
+fn refold<M, S, V, Err>(
+    layers: Vec<M>,
+    mut combine: impl FnMut(S) -> Result<V, Err>,
+) -> Result<V, Err>
+where
+    M: Functor<Slot, V, Output = S>,
+{
+    let mut values: Vec<Option<V>> = Vec::with_capacity(layers.len());
+    values.resize_with(layers.len(), || None);
+    for (slot, layer) in layers.into_iter().enumerate().rev() {
+        let term = layer.fmap(|child| {
+            values[child]
+                .take()
+                .expect("children are finished before their parents")
+        });
+        values[slot] = Some(combine(term)?);
+    }
+    Ok(values[0].take().expect("there is always a root layer"))
+}
+
+/// A stack-safe version of ch09e's `try_cata`.  Like that one, it stops at the first error, but
+/// since children are folded in reverse breadth-first order, it's not always the leftmost error
+/// that you'll get back.
+pub fn try_cata<E, V, Err, M, S>(
+    expr: E,
+    algebra: &mut dyn FnMut(S) -> Result<V, Err>,
+) -> Result<V, Err>
+where
+    E: Expression,
+    E::Signature: Functor<E, Slot, Output = M>,
+    M: Functor<Slot, V, Output = S>,
+{
+    let layers = unfold(expr, E::into_signature);
+    refold(layers, algebra)
+}
+
+/// A stack-safe version of ch09e's `cata`.
+pub fn cata<E, V, M, S>(expr: E, algebra: &mut dyn FnMut(S) -> V) -> V
+where
+    E: Expression,
+    E::Signature: Functor<E, Slot, Output = M>,
+    M: Functor<Slot, V, Output = S>,
+{
+    let layers = unfold(expr, E::into_signature);
+    match refold(layers, |term| Ok::<V, Infallible>(algebra(term))) {
+        Ok(value) => value,
+        Err(never) => match never {},
+    }
+}
+
+/// Builds an expression from a seed.  The coalgebra turns each seed into one term, whose children
+/// are the seeds for its subexpressions.
+pub fn ana<E, Seed, L, M>(seed: Seed, coalgebra: &mut dyn FnMut(Seed) -> L) -> E
+where
+    E: Expression,
+    L: Functor<Seed, Slot, Output = M>,
+    M: Functor<Slot, E, Output = E::Signature>,
+{
+    let layers = unfold(seed, coalgebra);
+    match refold(layers, |term| Ok::<E, Infallible>(E::wrap(term))) {
+        Ok(expr) => expr,
+        Err(never) => match never {},
+    }
+}
+
+/// Rewrites an expression from the bottom up, possibly into a different expression type.  Each
+/// term is rebuilt with its rewritten children, and then passed to `rewrite`.
+pub fn transform<E, F, M>(expr: E, rewrite: &mut dyn FnMut(F) -> F) -> F
+where
+    E: Expression,
+    F: Expression,
+    E::Signature: Functor<E, Slot, Output = M>,
+    M: Functor<Slot, F, Output = F::Signature>,
+{
+    cata(expr, &mut |term| rewrite(F::wrap(term)))
+}
+
+/// Drops an expression without recursing.  (Dropping it the normal way recurses once per level,
+/// just like everything else.)
+pub fn dismantle<E>(expr: E)
+where
+    E: Expression,
+    E::Signature: Functor<E, ()>,
+{
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        expr.into_signature().fmap(|child| pending.push(child));
+    }
+}
+
+/// Evaluates an expression without recursing.  ch08b's `Eval` impls work as they are: once a
+/// term's children have been replaced with their values, it can evaluate itself by cloning them.
+pub fn evaluate<V, E, M, S>(expr: E) -> V
+where
+    E: Expression,
+    E::Signature: Functor<E, Slot, Output = M>,
+    M: Functor<Slot, V, Output = S>,
+    S: Eval<V, V>,
+    V: Clone,
+{
+    cata(expr, &mut |term: S| term.eval(V::clone))
+}
+
+// The Drop impl that Rust would give a boxed expression recurses into each of its children.  We
+// can't call `dismantle` from a Drop impl, since it needs to own the expression, but we can swap
+// the signature for a literal, which has no children, and then dismantle the signature.  Each child
+// gets the same treatment when it's dropped in turn, and all that it has left by then is a
+// literal, so a drop never goes more than one level deep.
+//
+// That needs a stack on the heap, though, and most expressions are shallow enough that dropping
+// them shouldn't have to allocate.  So we let drops recurse the normal way until they're nested
+// `MAX_DROP_DEPTH` deep, and only then switch over.
+
+/// Takes an expression's signature out of its box, leaving a literal behind.
+pub fn take_signature<S, I>(sig: &mut S) -> S
+where
+    S: Inject<IntegerLiteral, I>,
+{
+    mem::replace(sig, S::inject(IntegerLiteral { value: 0 }))
+}
+
+const MAX_DROP_DEPTH: usize = 256;
+
+thread_local! {
+    static DROP_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+fn drop_signature<E, S, I>(sig: &mut S, signature: fn(&mut E) -> &mut S)
+where
+    S: Inject<IntegerLiteral, I> + Functor<E, ()>,
+{
+    // try_with fails while the thread is being torn down; we can still drop things without it.
+    let depth = DROP_DEPTH.try_with(Cell::get).unwrap_or(MAX_DROP_DEPTH);
+    if depth < MAX_DROP_DEPTH {
+        let _ = DROP_DEPTH.try_with(|current| current.set(depth + 1));
+        drop(take_signature(sig));
+        let _ = DROP_DEPTH.try_with(|current| current.set(depth));
+        return;
+    }
+    let mut pending = Vec::new();
+    take_signature(sig).fmap(|child| pending.push(child));
+    while let Some(mut expr) = pending.pop() {
+        take_signature(signature(&mut expr)).fmap(|child| pending.push(child));
+    }
+}
+
+macro_rules! stack_safe_drop {
+    ($($expr:ident),+ $(,)?) => {
+        $(
+            impl Drop for $expr {
+                fn drop(&mut self) {
+                    drop_signature(&mut *self.0, |expr: &mut $expr| &mut *expr.0);
+                }
+            }
+        )+
+    };
+}
+
+stack_safe_drop!(Expr, MultExpr, NoAddExpr, SubExpr, DivExpr, ModExpr, PairExpr, NegateExpr);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deep enough to overflow the stack of a test thread if any of these recursed.
+    const DEEP: usize = 200_000;
+
+    /// `1 + (1 + (1 + ...))`, nested `depth` levels deep.
+    fn deep(depth: usize) -> Expr {
+        let mut expr = integer_literal(1);
+        for _ in 1..depth {
+            expr = add(integer_literal(1), expr);
+        }
+        expr
+    }
+
+    #[test]
+    fn can_fold_deep_expressions() {
+        let sum = cata(deep(DEEP), &mut |term: Sig<i64>| match term {
+            Sum::Left(IntegerLiteral { value }) => value,
+            Sum::Right(Add { lhs, rhs }) => lhs + rhs,
+        });
+        assert_eq!(sum, DEEP as i64);
+    }
+
+    #[test]
+    fn can_evaluate_deep_expressions() {
+        assert_eq!(evaluate::<i64, _, _, _>(deep(DEEP)), DEEP as i64);
+        let mut expr: NoAddExpr = integer_literal(1);
+        for _ in 1..DEEP {
+            expr = multiply(integer_literal(1), expr);
+        }
+        assert_eq!(evaluate::<i64, _, _, _>(expr), 1);
+    }
+
+    #[test]
+    fn folds_match_the_recursive_version() {
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let rendered = cata(expr, &mut |term: MultSig<String>| match term {
+            Sum::Left(Multiply { lhs, rhs }) => format!("({} * {})", lhs, rhs),
+            Sum::Right(Sum::Left(IntegerLiteral { value })) => value.to_string(),
+            Sum::Right(Sum::Right(Add { lhs, rhs })) => format!("({} + {})", lhs, rhs),
+        });
+        assert_eq!(rendered, "((80 * 5) + 4)");
+    }
+
+    #[test]
+    fn fallible_folds_stop_at_errors() {
+        let mut calls = 0;
+        let result = try_cata(deep(DEEP), &mut |term: Sig<i64>| {
+            calls += 1;
+            match term {
+                Sum::Left(IntegerLiteral { value }) => Ok(value),
+                Sum::Right(Add { lhs, rhs }) if lhs + rhs > 10 => Err("too big"),
+                Sum::Right(Add { lhs, rhs }) => Ok(lhs + rhs),
+            }
+        });
+        assert_eq!(result, Err("too big"));
+        assert!(calls < 2 * DEEP);
+    }
+
+    #[test]
+    fn can_unfold_deep_expressions() {
+        // Each seed says how many literals are left to build.
+        let expr: Expr = ana(DEEP, &mut |remaining: usize| -> Sig<usize> {
+            if remaining == 1 {
+                Sum::Left(IntegerLiteral { value: 1 })
+            } else {
+                Sum::Right(Add {
+                    lhs: 1,
+                    rhs: remaining - 1,
+                })
+            }
+        });
+        assert_eq!(evaluate::<i64, _, _, _>(expr), DEEP as i64);
+    }
+
+    #[test]
+    fn can_transform_deep_expressions() {
+        let doubled: Expr = transform(deep(DEEP), &mut |expr: Expr| {
+            if let Sum::Left(IntegerLiteral { value }) = expr.unwrap() {
+                return integer_literal(value * 2);
+            }
+            expr
+        });
+        assert_eq!(evaluate::<i64, _, _, _>(doubled), 2 * DEEP as i64);
+    }
+
+    #[test]
+    fn can_drop_deep_expressions() {
+        dismantle(deep(DEEP));
+    }
+
+    /// `-(1 + -(1 + ...))`, or as close as `E` can get, nested `depth` levels deep.
+    fn deep_as<E>(depth: usize, wrap: fn(E) -> E) -> E
+    where
+        E: From<IntegerLiteral> + From<Add<E>>,
+    {
+        let mut expr = integer_literal(1);
+        for _ in 1..depth {
+            expr = wrap(add(integer_literal(1), expr));
+        }
+        expr
+    }
+
+    #[test]
+    fn expressions_drop_without_recursing() {
+        drop(deep_as::<Expr>(DEEP, |expr| expr));
+        drop(deep_as::<MultExpr>(DEEP, |expr| {
+            multiply(expr, integer_literal(1))
+        }));
+        drop(deep_as::<SubExpr>(DEEP, |expr| {
+            subtract(expr, integer_literal(1))
+        }));
+        drop(deep_as::<DivExpr>(DEEP, |expr| {
+            divide(expr, integer_literal(1))
+        }));
+        drop(deep_as::<ModExpr>(DEEP, |expr| {
+            modulo(expr, integer_literal(1))
+        }));
+        drop(deep_as::<PairExpr>(DEEP, |expr| {
+            first(pair(expr, integer_literal(1)))
+        }));
+        drop(deep_as::<NegateExpr>(DEEP, negate));
+        let mut expr: NoAddExpr = integer_literal(1);
+        for _ in 1..DEEP {
+            expr = multiply(integer_literal(1), expr);
+        }
+        drop(expr);
+    }
+}
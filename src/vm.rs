@@ -0,0 +1,402 @@
+// -*- coding: utf-8 -*-
+// ------------------------------------------------------------------------------------------------
+// Copyright © 2018-2019, Douglas Creager.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied.  See the License for the specific language governing permissions and
+// limitations under the License.
+// ------------------------------------------------------------------------------------------------
+
+//! A stack machine.  Compiling an expression flattens it into postfix code: each operand's code,
+//! followed by the operator's instruction, which pops its operands and pushes its result.  Neither
+//! compiling nor running recurses — compiling keeps its own stack of subexpressions still to
+//! visit, and running is a loop over a flat array — so neither can overflow the native stack no
+//! matter how deeply the expression was nested.
+//!
+//! That makes the machine a good place to run expressions from untrusted sources, as long as we
+//! also bound how much work they can do.  Every instruction costs some gas, according to a
+//! `GasSchedule`, and a run stops with `OutOfGas` as soon as it would spend more than its limit.
+//! The machine also uses checked arithmetic, and a run stops with `Overflow` instead of panicking
+//! or wrapping around.
+
+use crate::ch02_open_sum::*;
+use crate::ch05a_multiplication::*;
+use crate::ch08a_expressions::*;
+
+use std::fmt;
+
+/// One instruction of the stack machine.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Instr {
+    /// Pushes a constant.
+    Push(i64),
+    /// Pops two values, and pushes their sum.
+    Add,
+    /// Pops two values, and pushes their product.
+    Multiply,
+}
+
+/// Compiled code.  The only way to get some is to compile an expression, so it's always
+/// well-formed: no instruction pops more values than there are on the stack, and exactly one value
+/// is left at the end.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Code {
+    instrs: Vec<Instr>,
+}
+
+impl Code {
+    pub fn instrs(&self) -> &[Instr] {
+        &self.instrs
+    }
+
+    pub fn len(&self) -> usize {
+        self.instrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+}
+
+/// Compiles one layer of an expression: adds the subexpressions whose code comes first to
+/// `operands`, and returns the instruction that comes after them.
+pub trait Postfix<E> {
+    fn postfix<'a>(&'a self, operands: &mut Vec<&'a E>) -> Instr;
+}
+
+impl<E> Postfix<E> for IntegerLiteral {
+    fn postfix<'a>(&'a self, _operands: &mut Vec<&'a E>) -> Instr {
+        Instr::Push(self.value)
+    }
+}
+
+impl<E> Postfix<E> for Add<E> {
+    fn postfix<'a>(&'a self, operands: &mut Vec<&'a E>) -> Instr {
+        operands.extend([&self.lhs, &self.rhs]);
+        Instr::Add
+    }
+}
+
+impl<E> Postfix<E> for Multiply<E> {
+    fn postfix<'a>(&'a self, operands: &mut Vec<&'a E>) -> Instr {
+        operands.extend([&self.lhs, &self.rhs]);
+        Instr::Multiply
+    }
+}
+
+impl<E, L, R> Postfix<E> for Sum<L, R>
+where
+    L: Postfix<E>,
+    R: Postfix<E>,
+{
+    fn postfix<'a>(&'a self, operands: &mut Vec<&'a E>) -> Instr {
+        match self {
+            Sum::Left(lhs) => lhs.postfix(operands),
+            Sum::Right(rhs) => rhs.postfix(operands),
+        }
+    }
+}
+
+/// Compiles an expression into stack machine code.
+pub fn compile<E>(expr: &E) -> Code
+where
+    E: Expression,
+    E::Signature: Postfix<E>,
+{
+    enum Work<'a, E> {
+        Compile(&'a E),
+        Emit(Instr),
+    }
+
+    let mut instrs = Vec::new();
+    let mut operands = Vec::new();
+    let mut pending = vec![Work::Compile(expr)];
+    while let Some(work) = pending.pop() {
+        match work {
+            Work::Emit(instr) => instrs.push(instr),
+            Work::Compile(expr) => {
+                // The instruction runs after all of its operands, which run in order, so they go
+                // onto the stack the other way around.
+                let instr = expr.unwrap().postfix(&mut operands);
+                pending.push(Work::Emit(instr));
+                pending.extend(operands.drain(..).rev().map(Work::Compile));
+            }
+        }
+    }
+    Code { instrs }
+}
+
+/// How much gas each kind of instruction costs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GasSchedule {
+    pub push: u64,
+    pub add: u64,
+    pub multiply: u64,
+}
+
+impl Default for GasSchedule {
+    fn default() -> GasSchedule {
+        GasSchedule {
+            push: 1,
+            add: 1,
+            multiply: 2,
+        }
+    }
+}
+
+impl GasSchedule {
+    pub fn cost(&self, instr: Instr) -> u64 {
+        match instr {
+            Instr::Push(_) => self.push,
+            Instr::Add => self.add,
+            Instr::Multiply => self.multiply,
+        }
+    }
+}
+
+/// A run stopped because its next instruction would have cost more gas than it had left.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OutOfGas {
+    pub limit: u64,
+    /// The gas spent by the instructions that did run.
+    pub used: u64,
+    /// The index of the instruction that couldn't run.
+    pub pc: usize,
+    pub instr: Instr,
+}
+
+impl fmt::Display for OutOfGas {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "out of gas at instruction {} ({:?}) after using {} of {}",
+            self.pc, self.instr, self.used, self.limit
+        )
+    }
+}
+
+impl std::error::Error for OutOfGas {}
+
+/// Why a run stopped before it finished.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VmError {
+    OutOfGas(OutOfGas),
+    /// An instruction's result doesn't fit in an i64.
+    Overflow {
+        pc: usize,
+        instr: Instr,
+    },
+}
+
+impl From<OutOfGas> for VmError {
+    fn from(error: OutOfGas) -> VmError {
+        VmError::OutOfGas(error)
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::OutOfGas(error) => error.fmt(f),
+            VmError::Overflow { pc, instr } => {
+                write!(f, "overflow at instruction {} ({:?})", pc, instr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// The result of a run that finished, and how much gas it used.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Metered {
+    pub value: i64,
+    pub gas_used: u64,
+}
+
+/// Runs stack machine code, charging for each instruction before running it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Vm {
+    schedule: GasSchedule,
+    limit: u64,
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm {
+            schedule: GasSchedule::default(),
+            limit: u64::MAX,
+        }
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm::default()
+    }
+
+    pub fn with_schedule(mut self, schedule: GasSchedule) -> Vm {
+        self.schedule = schedule;
+        self
+    }
+
+    pub fn with_gas_limit(mut self, limit: u64) -> Vm {
+        self.limit = limit;
+        self
+    }
+
+    pub fn run(&self, code: &Code) -> Result<Metered, VmError> {
+        let mut stack: Vec<i64> = Vec::new();
+        let mut used: u64 = 0;
+        for (pc, instr) in code.instrs.iter().enumerate() {
+            let cost = self.schedule.cost(*instr);
+            match used.checked_add(cost) {
+                Some(total) if total <= self.limit => used = total,
+                _ => {
+                    return Err(VmError::OutOfGas(OutOfGas {
+                        limit: self.limit,
+                        used,
+                        pc,
+                        instr: *instr,
+                    }))
+                }
+            }
+            let value = match instr {
+                Instr::Push(value) => Some(*value),
+                Instr::Add | Instr::Multiply => {
+                    let rhs = stack.pop().unwrap();
+                    let lhs = stack.pop().unwrap();
+                    if *instr == Instr::Add {
+                        lhs.checked_add(rhs)
+                    } else {
+                        lhs.checked_mul(rhs)
+                    }
+                }
+            };
+            match value {
+                Some(value) => stack.push(value),
+                None => return Err(VmError::Overflow { pc, instr: *instr }),
+            }
+        }
+        Ok(Metered {
+            value: stack.pop().unwrap(),
+            gas_used: used,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ch04_smart_constructors::*;
+    use crate::trampoline::dismantle;
+
+    #[test]
+    fn can_compile_to_postfix() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let code = compile(&expr);
+        assert_eq!(
+            code.instrs(),
+            &[
+                Instr::Push(80),
+                Instr::Push(5),
+                Instr::Multiply,
+                Instr::Push(4),
+                Instr::Add,
+            ]
+        );
+    }
+
+    #[test]
+    fn charges_for_each_instruction() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let code = compile(&expr);
+        assert_eq!(
+            Vm::new().run(&code),
+            Ok(Metered {
+                value: 404,
+                gas_used: 3 + 2 + 1,
+            })
+        );
+        let schedule = GasSchedule {
+            push: 0,
+            add: 10,
+            multiply: 100,
+        };
+        let metered = Vm::new().with_schedule(schedule).run(&code).unwrap();
+        assert_eq!(metered.gas_used, 110);
+    }
+
+    #[test]
+    fn stops_when_out_of_gas() {
+        // (80 * 5) + 4
+        let expr: MultExpr = add(
+            multiply(integer_literal(80), integer_literal(5)),
+            integer_literal(4),
+        );
+        let code = compile(&expr);
+        let error = Vm::new().with_gas_limit(3).run(&code).unwrap_err();
+        assert_eq!(
+            error,
+            VmError::OutOfGas(OutOfGas {
+                limit: 3,
+                used: 2,
+                pc: 2,
+                instr: Instr::Multiply,
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "out of gas at instruction 2 (Multiply) after using 2 of 3"
+        );
+        // Exactly enough gas is enough.
+        assert!(Vm::new().with_gas_limit(6).run(&code).is_ok());
+    }
+
+    #[test]
+    fn stops_on_overflow() {
+        // 1 + (MAX * 2)
+        let expr: MultExpr = add(
+            integer_literal(1),
+            multiply(integer_literal(i64::MAX), integer_literal(2)),
+        );
+        let error = Vm::new().run(&compile(&expr)).unwrap_err();
+        assert_eq!(
+            error,
+            VmError::Overflow {
+                pc: 3,
+                instr: Instr::Multiply,
+            }
+        );
+        assert_eq!(error.to_string(), "overflow at instruction 3 (Multiply)");
+    }
+
+    #[test]
+    fn can_compile_deep_expressions() {
+        // 1 + (1 + (1 + ...)), deep enough to overflow the stack of a test thread if compiling
+        // recursed.
+        const DEEP: usize = 200_000;
+        let mut expr: MultExpr = integer_literal(1);
+        for _ in 1..DEEP {
+            expr = add(integer_literal(1), expr);
+        }
+        let code = compile(&expr);
+        assert_eq!(code.len(), 2 * DEEP - 1);
+        assert_eq!(Vm::new().run(&code).unwrap().value, DEEP as i64);
+        dismantle(expr);
+    }
+}